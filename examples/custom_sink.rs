@@ -0,0 +1,85 @@
+//! Custom sinks: forward engine metrics to an application's own counters & write accounts to any writer
+//! Run with `cargo run --example custom_sink`
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+use toypaymentengine::cli_io::{parse_txns_reader, write_accounts_csv, AccountColumns, CsvDialect};
+use toypaymentengine::metrics::MetricsSink;
+use toypaymentengine::payments_engine::PaymentsEngine;
+
+const RECORDS: &str = "type,client,tx,amount\n\
+                       deposit,1,1,10.0\n\
+                       deposit,2,2,5.0\n\
+                       withdrawal,2,3,9.0\n";
+
+/// Keeps running counts where the application can read them, e.g. to serve on its own metrics endpoint
+#[derive(Debug, Default, Clone)]
+struct CountingSink {
+    counters: Arc<Mutex<BTreeMap<String, u64>>>,
+}
+
+impl MetricsSink for CountingSink {
+    fn counter(&self, name: &str, value: u64) {
+        *self
+            .counters
+            .lock()
+            .unwrap()
+            .entry(name.to_string())
+            .or_default() += value;
+    }
+
+    fn gauge(&self, _name: &str, _value: f64) {}
+
+    fn histogram(&self, _name: &str, _value: f64) {}
+}
+
+/// Processes the records, returning the counts the sink saw & the accounts as semicolon separated csv
+fn run() -> Result<(BTreeMap<String, u64>, String), Box<dyn Error>> {
+    let sink = CountingSink::default();
+    let mut payments_engine = PaymentsEngine::new();
+    payments_engine.set_metrics_sink(Box::new(sink.clone()));
+    for txn in parse_txns_reader(RECORDS.as_bytes(), true)? {
+        let _ = payments_engine.process_txn(&txn);
+    }
+
+    let accounts: Vec<_> = payments_engine
+        .iter_accounts()
+        .map(|view| view.to_account())
+        .collect();
+    let dialect = CsvDialect {
+        delimiter: b';',
+        decimal_separator: ',',
+        ..CsvDialect::default()
+    };
+    let mut out = vec![];
+    write_accounts_csv(&mut out, &accounts, &AccountColumns::default(), &dialect)?;
+
+    let counters = sink.counters.lock().unwrap().clone();
+    Ok((counters, String::from_utf8(out)?))
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let (counters, accounts) = run()?;
+    for (name, count) in counters {
+        println!("{} {}", name, count);
+    }
+    print!("{}", accounts);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn tst_custom_sink() {
+        let (counters, accounts) = super::run().unwrap();
+        assert_eq!(counters["txns.accepted"], 2);
+        assert_eq!(counters["txns.rejected"], 1);
+        assert_eq!(
+            accounts,
+            "client;available;held;total;locked\n\
+             1;10,0000;0,0000;10,0000;false\n\
+             2;5,0000;0,0000;5,0000;false\n"
+        );
+    }
+}
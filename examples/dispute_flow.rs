@@ -0,0 +1,106 @@
+//! A dispute's lifecycle, watched by an observer stage added to the engine's pipeline
+//! Run with `cargo run --example dispute_flow`
+
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+use toypaymentengine::amount::TxnAmount;
+use toypaymentengine::payments_engine::pipeline::{Stage, TxnOutcome};
+use toypaymentengine::payments_engine::PaymentsEngine;
+use toypaymentengine::transaction::{DisputeHistory, PureTxn, RefTxn, SequencedTxn, Transaction};
+
+/// Records the outcome of every txn without affecting it, stages only seeing outcomes leave `before` alone
+#[derive(Debug, Default)]
+struct OutcomeObserver {
+    outcomes: Arc<Mutex<Vec<String>>>,
+}
+
+impl Stage for OutcomeObserver {
+    fn name(&self) -> &str {
+        "outcome_observer"
+    }
+
+    fn after(&mut self, _engine: &mut PaymentsEngine, s_txn: &SequencedTxn, outcome: &TxnOutcome) {
+        self.outcomes.lock().unwrap().push(format!(
+            "seq {} tx {} {:?}",
+            s_txn.seq,
+            s_txn.txn.id(),
+            outcome
+        ));
+    }
+}
+
+fn deposit(txn_id: u32, acnt_id: u16, amount: f64) -> Transaction {
+    Transaction::Deposit(PureTxn {
+        txn_id,
+        acnt_id,
+        amount: TxnAmount::from_f64(amount),
+        dispute: DisputeHistory::default(),
+        memo: None,
+        channel: None,
+    })
+}
+
+fn ref_txn(ref_id: u32, acnt_id: u16) -> RefTxn {
+    RefTxn { ref_id, acnt_id }
+}
+
+/// Disputes & resolves one deposit, then disputes & charges back another, returning what the observer saw
+fn run() -> Result<Vec<String>, Box<dyn Error>> {
+    let outcomes = Arc::new(Mutex::new(vec![]));
+    let mut payments_engine = PaymentsEngine::new();
+    payments_engine
+        .pipeline_mut()
+        .push(Box::new(OutcomeObserver {
+            outcomes: outcomes.clone(),
+        }));
+
+    let txns = [
+        deposit(1, 1, 10.0),
+        deposit(2, 1, 4.0),
+        Transaction::Dispute(ref_txn(1, 1)),
+        Transaction::Resolve(ref_txn(1, 1)),
+        Transaction::Dispute(ref_txn(2, 1)),
+        Transaction::Chargeback(ref_txn(2, 1)),
+        // The chargeback locked the account
+        deposit(3, 1, 1.0),
+    ];
+    for txn in &txns {
+        let _ = payments_engine.process_txn(txn);
+        let view = payments_engine
+            .account(1)
+            .ok_or("client 1 has no account")?;
+        println!(
+            "{:?} -> available {:.4} held {:.4} locked {}",
+            txn,
+            view.available(),
+            view.held(),
+            view.locked()
+        );
+    }
+
+    for s_txn in payments_engine.account_history(1, 0, usize::MAX) {
+        if let Transaction::Deposit(p_txn) = &s_txn.txn {
+            println!("deposit {} {}", p_txn.txn_id, p_txn.dispute.to_export_str());
+        }
+    }
+    let outcomes = outcomes.lock().unwrap().clone();
+    Ok(outcomes)
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    for outcome in run()? {
+        println!("{}", outcome);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn tst_dispute_flow() {
+        let outcomes = super::run().unwrap();
+        assert_eq!(outcomes.len(), 7);
+        assert_eq!(outcomes[5], "seq 6 tx 2 Applied");
+        assert_eq!(outcomes[6], "seq 7 tx 3 Rejected(AccountFrozen)");
+    }
+}
@@ -0,0 +1,66 @@
+//! Embedding the engine: parse records from memory, process them, & read back the accounts
+//! Run with `cargo run --example embed_basic`
+
+use std::error::Error;
+use toypaymentengine::amount::{Amount, PrecisionPolicy, TxnAmount};
+use toypaymentengine::cli_io::parse_txns_reader;
+use toypaymentengine::payments_engine::PaymentsEngine;
+use toypaymentengine::transaction::{DisputeHistory, PureTxn, Transaction};
+
+const RECORDS: &str = "type,client,tx,amount\n\
+                       deposit,1,1,10.0\n\
+                       deposit,2,2,5.0\n\
+                       withdrawal,1,3,2.5\n\
+                       withdrawal,2,4,9.0\n";
+
+/// Processes the records & a txn built in code, returning each client's available funds
+fn run() -> Result<Vec<(u16, f64)>, Box<dyn Error>> {
+    let mut payments_engine = PaymentsEngine::new();
+    for txn in parse_txns_reader(RECORDS.as_bytes(), true)? {
+        // Rejections are values, an embedder decides what to do with them
+        if let Err(e) = payments_engine.process_txn(&txn) {
+            println!("tx {} rejected: {:?}", txn.id(), e);
+        }
+    }
+
+    // Amounts are held in minor units, parsed under the same precision policies records are
+    let amount = Amount::parse("1.25", PrecisionPolicy::Reject).map_err(|e| format!("{:?}", e))?;
+    let deposit = Transaction::Deposit(PureTxn {
+        txn_id: 5,
+        acnt_id: 3,
+        amount: TxnAmount::new(amount),
+        dispute: DisputeHistory::default(),
+        memo: Some("INV-0042".to_string()),
+        channel: None,
+    });
+    payments_engine
+        .process_txn(&deposit)
+        .map_err(|e| format!("{:?}", e))?;
+
+    for view in payments_engine.iter_accounts() {
+        println!(
+            "client {} available {:.4} held {:.4} locked {}",
+            view.client(),
+            view.available(),
+            view.held(),
+            view.locked()
+        );
+    }
+    Ok(payments_engine
+        .iter_accounts()
+        .map(|view| (view.client(), view.available()))
+        .collect())
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    run()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn tst_embed_basic() {
+        assert_eq!(super::run().unwrap(), [(1, 7.5), (2, 5.0), (3, 1.25)]);
+    }
+}
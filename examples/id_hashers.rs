@@ -0,0 +1,77 @@
+//! Times a run of deposits under each `--id-hasher`, the lookups by txn id dominate very large runs
+//! Run with `cargo run --release --example id_hashers -- 50000000` for a 50M record run, 1M records when no count is given
+//! A 50M record run keeps every txn in memory, allow several GB
+
+use std::error::Error;
+use std::time::{Duration, Instant};
+use toypaymentengine::amount::TxnAmount;
+use toypaymentengine::payments_engine::config::{EngineConfig, IdHasher};
+use toypaymentengine::payments_engine::PaymentsEngine;
+use toypaymentengine::transaction::{DisputeHistory, PureTxn, Transaction};
+
+const DEFAULT_RECORDS: u32 = 1_000_000;
+
+/// Deposits with sequential txn ids spread over every client, as a partner file numbers them
+fn deposit(txn_id: u32) -> Transaction {
+    Transaction::Deposit(PureTxn {
+        txn_id,
+        acnt_id: (txn_id % u16::MAX as u32) as u16,
+        amount: TxnAmount::from_f64(1.0),
+        dispute: DisputeHistory::default(),
+        memo: None,
+        channel: None,
+    })
+}
+
+/// Processes `records` deposits, returning the time taken & the sum of available funds
+fn run(id_hasher: IdHasher, records: u32) -> Result<(Duration, f64), Box<dyn Error>> {
+    let mut payments_engine = PaymentsEngine::with_config(EngineConfig {
+        id_hasher,
+        expected_records: Some(records as usize),
+        expected_accounts: Some(u16::MAX as usize),
+        ..EngineConfig::default()
+    })?;
+    let start = Instant::now();
+    for txn_id in 1..=records {
+        payments_engine
+            .process_txn(&deposit(txn_id))
+            .map_err(|e| format!("{:?}", e))?;
+    }
+    let elapsed = start.elapsed();
+    let available = payments_engine
+        .iter_accounts()
+        .map(|view| view.available())
+        .sum();
+    Ok((elapsed, available))
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let records = match std::env::args().nth(1) {
+        Some(count) => count.parse()?,
+        None => DEFAULT_RECORDS,
+    };
+    for (name, id_hasher) in [("sip", IdHasher::Sip), ("fx", IdHasher::Fx)] {
+        let (elapsed, _) = run(id_hasher, records)?;
+        println!(
+            "{} {} records in {:.2?}, {:.0} records/s",
+            name,
+            records,
+            elapsed,
+            records as f64 / elapsed.as_secs_f64()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use toypaymentengine::payments_engine::config::IdHasher;
+
+    #[test]
+    fn tst_id_hashers() {
+        // Hashers only change how fast ids are looked up, never the balances
+        let (_, sip) = super::run(IdHasher::Sip, 1000).unwrap();
+        let (_, fx) = super::run(IdHasher::Fx, 1000).unwrap();
+        assert_eq!((sip, fx), (1000.0, 1000.0));
+    }
+}
@@ -0,0 +1,123 @@
+//! Dispute storm over a hot set of deposits, timed whole & against the txn id lookups its records make
+//! Run with `cargo run --release --example ref_cache_bench`
+//! Kept as the evidence behind not caching referenced txns: a 1024 entry LRU of them answered 95% of the
+//! storm's lookups yet ran within run to run noise of the engine without it, as the lookups take a few
+//! percent of a record's processing time. Rerun it before reconsidering a cache
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::HashMap;
+use std::error::Error;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+use toypaymentengine::amount::TxnAmount;
+use toypaymentengine::payments_engine::config::EngineConfig;
+use toypaymentengine::payments_engine::PaymentsEngine;
+use toypaymentengine::transaction::{DisputeHistory, PureTxn, RefTxn, Transaction};
+
+const CLIENTS: u16 = 1000;
+/// Deposits most disputes of the storm refer to
+const HOT_TXNS: u32 = 512;
+/// Share of disputes referring to a hot deposit, the rest refer to any deposit
+const HOT_SHARE: f64 = 0.9;
+
+/// Deposits followed by dispute & resolve pairs, mostly on the first `HOT_TXNS` deposits
+fn dispute_storm(deposits: u32, disputes: usize, seed: u64) -> Vec<Transaction> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let owner = |txn_id: u32| (txn_id % CLIENTS as u32) as u16 + 1;
+    let mut txns: Vec<Transaction> = (1..=deposits)
+        .map(|txn_id| {
+            Transaction::Deposit(PureTxn {
+                txn_id,
+                acnt_id: owner(txn_id),
+                amount: TxnAmount::from_f64(10.0),
+                dispute: DisputeHistory::default(),
+                memo: None,
+                channel: None,
+            })
+        })
+        .collect();
+    for _ in 0..disputes {
+        let ref_id = match rng.gen_bool(HOT_SHARE) {
+            true => rng.gen_range(1..=HOT_TXNS.min(deposits)),
+            false => rng.gen_range(1..=deposits),
+        };
+        let ref_txn = RefTxn {
+            ref_id,
+            acnt_id: owner(ref_id),
+        };
+        txns.push(Transaction::Dispute(ref_txn.clone()));
+        txns.push(Transaction::Resolve(ref_txn));
+    }
+    txns
+}
+
+/// Processes the storm, returning the time taken & the held funds left, which every resolve releases
+fn run(txns: &[Transaction]) -> Result<(Duration, f64), Box<dyn Error>> {
+    let mut payments_engine = PaymentsEngine::with_config(EngineConfig {
+        expected_records: Some(txns.len()),
+        ..EngineConfig::default()
+    })?;
+    let start = Instant::now();
+    for txn in txns {
+        let _ = payments_engine.process_txn(txn);
+    }
+    let elapsed = start.elapsed();
+    let held = payments_engine
+        .iter_accounts()
+        .map(|view| view.held())
+        .sum();
+    Ok((elapsed, held))
+}
+
+/// Looks up every id the storm's disputes & resolves refer to in a map of its deposits, as the engine does
+/// Returns the time taken & how many were found
+fn lookups(txns: &[Transaction]) -> (Duration, usize) {
+    let deposits: HashMap<u32, usize> = txns
+        .iter()
+        .enumerate()
+        .filter_map(|(indx, txn)| match txn {
+            Transaction::Deposit(p_txn) => Some((p_txn.txn_id, indx)),
+            _ => None,
+        })
+        .collect();
+    let start = Instant::now();
+    let found = txns
+        .iter()
+        .filter_map(|txn| match txn {
+            Transaction::Dispute(ref_txn) | Transaction::Resolve(ref_txn) => {
+                black_box(deposits.get(&ref_txn.ref_id))
+            }
+            _ => None,
+        })
+        .count();
+    (start.elapsed(), found)
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let txns = dispute_storm(1_000_000, 2_000_000, 7);
+    let (elapsed, _) = run(&txns)?;
+    let (looked_up, found) = lookups(&txns);
+    println!(
+        "{} records, {} hot deposits: {:.0} records/s, {} txn id lookups take {:.1}% of the run",
+        txns.len(),
+        HOT_TXNS,
+        txns.len() as f64 / elapsed.as_secs_f64(),
+        found,
+        looked_up.as_secs_f64() / elapsed.as_secs_f64() * 100.0
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn tst_ref_cache_bench() {
+        // Every dispute is resolved, & each refers to a deposit of the storm
+        let txns = super::dispute_storm(2000, 5000, 7);
+        let (_, held) = super::run(&txns).unwrap();
+        assert_eq!(held, 0.0);
+        let (_, found) = super::lookups(&txns);
+        assert_eq!(found, 10_000);
+    }
+}
@@ -0,0 +1,48 @@
+//! Streaming records from any reader, e.g. a socket or a request body a server hands the engine
+//! Run with `cargo run --example stream_reader`
+
+use std::error::Error;
+use std::io::Read;
+use toypaymentengine::payments_engine::{PaymentsEngine, StreamOptions};
+
+/// Records arriving in two chunks, split mid record as network reads can be
+const FIRST_CHUNK: &[u8] = b"type,client,tx,amount\n\
+                             deposit,1,1,10.0\n\
+                             deposit,2,2,5.0\n\
+                             withdrawal,1,3,2";
+const SECOND_CHUNK: &[u8] = b".5\n\
+                              dispute,2,2,\n\
+                              withdrawal,2,4,1.0\n";
+
+/// Client, available, & held funds
+type Balances = (u16, f64, f64);
+
+/// Streams the chunks as one reader, returning each client's balances
+fn run() -> Result<Vec<Balances>, Box<dyn Error>> {
+    let mut payments_engine = PaymentsEngine::new();
+    let reader = FIRST_CHUNK.chain(SECOND_CHUNK);
+    payments_engine.stream_process_reader(reader, true, &StreamOptions::default())?;
+    Ok(payments_engine
+        .iter_accounts()
+        .map(|view| (view.client(), view.available(), view.held()))
+        .collect())
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    for (client, available, held) in run()? {
+        println!(
+            "client {} available {:.4} held {:.4}",
+            client, available, held
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn tst_stream_reader() {
+        // The dispute held client 2's funds, so its withdrawal was rejected
+        assert_eq!(super::run().unwrap(), [(1, 7.5, 0.0), (2, 0.0, 5.0)]);
+    }
+}
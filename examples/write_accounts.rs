@@ -0,0 +1,145 @@
+//! Times writing account records, through `write_accounts_csv` & through a `String` per field as accounts were
+//! once written, with the v1 columns & the extended ones
+//! Run with `cargo run --release --example write_accounts` for 10M accounts, or give another count e.g. `-- 1000000`
+
+use std::error::Error;
+use std::io;
+use std::time::{Duration, Instant};
+use toypaymentengine::account::Account;
+use toypaymentengine::amount::{format_minor_units, format_wide_minor_units, Available, Held};
+use toypaymentengine::cli_io::{write_accounts_csv, AccountColumns, CsvDialect};
+use toypaymentengine::payments_engine::stats::AccountActivity;
+
+const DEFAULT_ACCOUNTS: usize = 10_000_000;
+
+/// Accounts with varied balances, ids repeat once past the client id range
+fn accounts(count: usize) -> Vec<Account> {
+    (0..count)
+        .map(|indx| {
+            let available = (indx % 100_000) as f64 * 1.2345;
+            let held = (indx % 7) as f64 * 0.5;
+            let mut acnt = Account::with_balances(
+                (indx % u16::MAX as usize) as u16,
+                Available::from_f64(available),
+                Held::from_f64(held),
+            );
+            acnt.admin_hold = indx % 11 == 0;
+            acnt
+        })
+        .collect()
+}
+
+/// Columns `--extended-output` writes, with activity & a chargeback txn for some clients
+fn extended_columns() -> AccountColumns {
+    let activity = (0..u16::MAX)
+        .step_by(3)
+        .map(|client| {
+            let activity = AccountActivity {
+                disputes: client as u64 % 5,
+                chargebacks: client as u64 % 2,
+                last_txn_id: Some(client as u32 * 1000),
+            };
+            (client, activity)
+        })
+        .collect();
+    AccountColumns {
+        lock_reasons: true,
+        activity: Some(activity),
+        chargeback_txns: Some((0..u16::MAX).step_by(7).map(|c| (c, c as u32)).collect()),
+    }
+}
+
+/// Fields of an account formatted a `String` each
+fn format_record(acnt: &Account, columns: &AccountColumns) -> Vec<String> {
+    let mut record = vec![
+        acnt.id.to_string(),
+        format_minor_units(acnt.available().minor_units()),
+        format_minor_units(acnt.held().minor_units()),
+        format_wide_minor_units(acnt.total_minor_units()),
+        acnt.is_locked().to_string(),
+    ];
+    if columns.lock_reasons {
+        record.push(acnt.locked_by_chargeback.to_string());
+        record.push(acnt.admin_hold.to_string());
+    }
+    if let Some(activity) = &columns.activity {
+        let activity = activity.get(&acnt.id).cloned().unwrap_or_default();
+        record.push(acnt.status().as_str().to_string());
+        record.push(activity.disputes.to_string());
+        record.push(activity.chargebacks.to_string());
+        record.push(
+            activity
+                .last_txn_id
+                .map_or(String::new(), |id| id.to_string()),
+        );
+    }
+    if let Some(chargeback_txns) = &columns.chargeback_txns {
+        let chargeback_txn = chargeback_txns.get(&acnt.id);
+        record.push(chargeback_txn.map_or(String::new(), |id| id.to_string()));
+    }
+    record
+}
+
+/// Writes the accounts a `String` per field, into `out`
+fn write_formatted<W: io::Write>(
+    out: W,
+    accounts: &[Account],
+    columns: &AccountColumns,
+) -> Result<(), Box<dyn Error>> {
+    let mut wtr = csv::WriterBuilder::new()
+        .terminator(csv::Terminator::Any(b'\n'))
+        .buffer_capacity(1 << 16)
+        .from_writer(out);
+    for acnt in accounts {
+        wtr.write_record(format_record(acnt, columns))?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+fn time(write: impl FnOnce() -> Result<(), Box<dyn Error>>) -> Result<Duration, Box<dyn Error>> {
+    let start = Instant::now();
+    write()?;
+    Ok(start.elapsed())
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let count = match std::env::args().nth(1) {
+        Some(count) => count.parse()?,
+        None => DEFAULT_ACCOUNTS,
+    };
+    let accounts = accounts(count);
+    let dialect = CsvDialect::default();
+    for (name, columns) in [
+        ("v1", AccountColumns::default()),
+        ("extended", extended_columns()),
+    ] {
+        let buffered = time(|| write_accounts_csv(io::sink(), &accounts, &columns, &dialect))?;
+        let formatted = time(|| write_formatted(io::sink(), &accounts, &columns))?;
+        println!(
+            "{} columns, {} accounts: record buffer {:.2?}, String per field {:.2?}",
+            name, count, buffered, formatted
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use toypaymentengine::cli_io::{write_accounts_csv, AccountColumns, CsvDialect};
+
+    #[test]
+    fn tst_write_accounts() {
+        // Both ways of writing give the same records, only the header is left to write_accounts_csv
+        let accounts = super::accounts(1000);
+        for columns in [AccountColumns::default(), super::extended_columns()] {
+            let mut buffered = Vec::new();
+            write_accounts_csv(&mut buffered, &accounts, &columns, &CsvDialect::default()).unwrap();
+            let mut formatted = Vec::new();
+            super::write_formatted(&mut formatted, &accounts, &columns).unwrap();
+            let records = String::from_utf8(buffered).unwrap();
+            let (_, records) = records.split_once('\n').unwrap();
+            assert_eq!(records, String::from_utf8(formatted).unwrap());
+        }
+    }
+}
@@ -1,68 +1,256 @@
-use crate::constants::PRECISION;
+use crate::amount::{
+    format_minor_units, format_wide_minor_units, Amount, AmountError, Available, Held, TxnAmount,
+    MINOR_UNITS,
+};
+
+/// Why an account accepts txns or not, a chargeback lock is reported over a hold as it can't be cleared
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccountStatus {
+    Active,
+    OnHold,
+    ChargedBack,
+}
+
+impl AccountStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AccountStatus::Active => "active",
+            AccountStatus::OnHold => "on_hold",
+            AccountStatus::ChargedBack => "charged_back",
+        }
+    }
+}
 
 /// Struct to hold data and methods for an account
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Account {
     /// Assuming 1 account per client for simplicity
     pub id: u16,
 
-    /// Funds which are available for withdrawal by client
-    pub available: f64,
+    /// Funds which are available for withdrawal by client, only moved through the movement functions
+    available: Available,
+
+    /// Amount held due to disputes, only moved through the movement functions
+    held: Held,
 
-    /// Amount held due to disputes
-    pub held: f64,
+    /// Locked because a chargeback was processed against the account
+    pub locked_by_chargeback: bool,
 
-    /// Status of account, determined by txn behavior
-    pub frozen: bool,
+    /// Manual hold placed & cleared by admin instructions
+    pub admin_hold: bool,
 }
 
 impl Account {
+    /// Empty, unlocked account, as a client's first deposit opens it
+    pub fn new(id: u16) -> Self {
+        Self::with_balances(id, Available::default(), Held::default())
+    }
+
+    /// Unlocked account with balances from elsewhere, e.g. a snapshot or an initial state file
+    pub fn with_balances(id: u16, available: Available, held: Held) -> Self {
+        Account {
+            id,
+            available,
+            held,
+            locked_by_chargeback: false,
+            admin_hold: false,
+        }
+    }
+
+    /// Sets the reasons the account is locked, e.g. restored alongside its balances
+    pub fn with_locks(mut self, locked_by_chargeback: bool, admin_hold: bool) -> Self {
+        self.locked_by_chargeback = locked_by_chargeback;
+        self.admin_hold = admin_hold;
+        self
+    }
+
+    pub fn available(&self) -> Available {
+        self.available
+    }
+
+    pub fn held(&self) -> Held {
+        self.held
+    }
+
+    /// Total is summed wider than minor units, so it holds even when both balances are near their limits
+    pub fn total_minor_units(&self) -> i128 {
+        self.available.minor_units() as i128 + self.held.minor_units() as i128
+    }
+
+    /// Total as a float, e.g. for json, output is formatted from `total_minor_units`
     pub fn get_total(&self) -> f64 {
-        self.available + self.held
+        self.total_minor_units() as f64 / MINOR_UNITS as f64
+    }
+
+    /// Adds a txn's funds to available, e.g. a deposit
+    pub fn credit(&mut self, amount: TxnAmount) -> Result<(), AmountError> {
+        let available = self.available.amount().checked_add(amount.amount())?;
+        self.set_balances(available, self.held.amount())
+    }
+
+    /// Takes a txn's funds out of available, e.g. a withdrawal, fee, or refund
+    pub fn debit(&mut self, amount: TxnAmount) -> Result<(), AmountError> {
+        let available = self.available.amount().checked_sub(amount.amount())?;
+        self.set_balances(available, self.held.amount())
+    }
+
+    /// Moves funds from available to held, e.g. for a dispute or a withdrawal waiting on approval
+    pub fn hold(&mut self, amount: TxnAmount) -> Result<(), AmountError> {
+        let available = self.available.amount().checked_sub(amount.amount())?;
+        let held = self.held.amount().checked_add(amount.amount())?;
+        self.set_balances(available, held)
+    }
+
+    /// Moves held funds back to available, e.g. when a dispute is resolved or partly released
+    pub fn release(&mut self, amount: TxnAmount) -> Result<(), AmountError> {
+        let available = self.available.amount().checked_add(amount.amount())?;
+        let held = self.held.amount().checked_sub(amount.amount())?;
+        self.set_balances(available, held)
+    }
+
+    /// Takes held funds out of the account, e.g. a chargeback or an approved withdrawal
+    pub fn settle_held(&mut self, amount: TxnAmount) -> Result<(), AmountError> {
+        let held = self.held.amount().checked_sub(amount.amount())?;
+        self.set_balances(self.available.amount(), held)
+    }
+
+    /// Combines another record of this account into it, e.g. when merging initial states
+    pub fn merge(&mut self, other: &Account) -> Result<(), AmountError> {
+        let available = self
+            .available
+            .amount()
+            .checked_add(other.available.amount())?;
+        let held = self.held.amount().checked_add(other.held.amount())?;
+        self.locked_by_chargeback |= other.locked_by_chargeback;
+        self.admin_hold |= other.admin_hold;
+        self.set_balances(available, held)
+    }
+
+    /// Balances are only set once every sum succeeded, an overflowing movement leaves them untouched
+    fn set_balances(&mut self, available: Amount, held: Amount) -> Result<(), AmountError> {
+        self.available = Available::new(available);
+        self.held = Held::new(held);
+        Ok(())
+    }
+
+    /// Locked accounts reject all transactions, this is the legacy `locked` output column
+    pub fn is_locked(&self) -> bool {
+        self.locked_by_chargeback || self.admin_hold
+    }
+
+    pub fn status(&self) -> AccountStatus {
+        match (self.locked_by_chargeback, self.admin_hold) {
+            (true, _) => AccountStatus::ChargedBack,
+            (false, true) => AccountStatus::OnHold,
+            (false, false) => AccountStatus::Active,
+        }
     }
 
     pub fn get_display_str(&self) -> String {
         format!(
-            "{:?},{:.*},{:.*},{:.*},{:?}",
+            "{:?},{},{},{},{:?}",
             self.id,
-            PRECISION,
-            self.available,
-            PRECISION,
-            self.held,
-            PRECISION,
-            self.get_total(),
-            self.frozen
+            format_minor_units(self.available.minor_units()),
+            format_minor_units(self.held.minor_units()),
+            format_wide_minor_units(self.total_minor_units()),
+            self.is_locked()
         )
     }
 
-    pub fn print_std_out(&self) {
-        println!("{}", self.get_display_str())
+    /// Display string with the legacy columns followed by the reasons an account is locked
+    pub fn get_extended_display_str(&self) -> String {
+        format!(
+            "{},{:?},{:?}",
+            self.get_display_str(),
+            self.locked_by_chargeback,
+            self.admin_hold
+        )
+    }
+
+    pub fn print_std_out(&self, extended: bool) {
+        match extended {
+            true => println!("{}", self.get_extended_display_str()),
+            false => println!("{}", self.get_display_str()),
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
     use crate::account::Account;
+    use crate::amount::{Amount, AmountError, Available, Held, TxnAmount};
 
     #[test]
     fn tst_get_total() {
-        let accnt = Account {
-            id: 1,
-            available: 10.0,
-            held: 5.0,
-            frozen: false,
-        };
+        let accnt = Account::with_balances(1, Available::from_f64(10.0), Held::from_f64(5.0));
         assert_eq!(accnt.get_total(), 15.0);
     }
 
+    #[test]
+    fn tst_fund_movements() {
+        let mut accnt = Account::new(1);
+        let amount = TxnAmount::from_f64;
+        accnt.credit(amount(10.0)).unwrap();
+        accnt.debit(amount(0.1)).unwrap();
+        accnt.hold(amount(4.2)).unwrap();
+        accnt.release(amount(1.2)).unwrap();
+        accnt.settle_held(amount(2.0)).unwrap();
+        assert_eq!(accnt.available(), Available::from_f64(6.9));
+        assert_eq!(accnt.held(), Held::from_f64(1.0));
+        assert_eq!(accnt.get_total(), 7.9);
+
+        // Amounts round to the nearest minor unit, repeated small credits don't drift
+        let mut accnt = Account::new(2);
+        for _ in 0..10 {
+            accnt.credit(amount(0.1)).unwrap();
+        }
+        accnt.credit(amount(0.00006)).unwrap();
+        assert_eq!(accnt.available().minor_units(), 10_001);
+    }
+
+    #[test]
+    fn tst_fund_movement_overflow() {
+        let max = Amount::from_minor_units(i64::MAX);
+        let mut accnt = Account::with_balances(1, Available::new(max), Held::new(max));
+        let one = TxnAmount::new(Amount::from_minor_units(1));
+        let before = accnt.clone();
+        // Overflowing movements error & leave both balances as they were
+        assert_eq!(accnt.credit(one), Err(AmountError::Overflow));
+        assert_eq!(
+            accnt.release(TxnAmount::new(max)),
+            Err(AmountError::Overflow)
+        );
+        accnt.debit(one).unwrap();
+        assert_eq!(accnt.hold(one), Err(AmountError::Overflow));
+        assert_eq!(accnt.available().minor_units(), i64::MAX - 1);
+        assert_eq!(accnt.held(), before.held());
+        // The total is summed wide enough to hold both balances, & formatted exactly
+        assert!(accnt.get_total() > max.to_f64());
+        assert_eq!(
+            accnt.get_display_str(),
+            "1,922337203685477.5806,922337203685477.5807,1844674407370955.1613,false"
+        );
+
+        let mut merged = Account::new(1);
+        assert_eq!(merged.merge(&before), Ok(()));
+        assert_eq!(merged.merge(&before), Err(AmountError::Overflow));
+        assert_eq!(merged, before);
+    }
+
     #[test]
     fn tst_print_std_out() {
-        let accnt = Account {
-            id: 1,
-            available: 10.0,
-            held: 5.0,
-            frozen: false,
-        };
+        let accnt = Account::with_balances(1, Available::from_f64(10.0), Held::from_f64(5.0));
         assert_eq!(accnt.get_display_str(), "1,10.0000,5.0000,15.0000,false");
     }
+
+    #[test]
+    fn tst_get_extended_display_str() {
+        let accnt = Account::with_balances(1, Available::from_f64(10.0), Held::from_f64(5.0))
+            .with_locks(false, true);
+        assert!(accnt.is_locked());
+        assert_eq!(
+            accnt.get_extended_display_str(),
+            "1,10.0000,5.0000,15.0000,true,false,true"
+        );
+    }
 }
@@ -0,0 +1,262 @@
+use crate::account::Account;
+use crate::transaction::SequencedTxn;
+use crate::webhook::{WebhookConfig, WebhookSink};
+use serde::Serialize;
+use std::collections::HashSet;
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+/// Account balance a rule watches
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BalanceField {
+    Available,
+    Held,
+    Total,
+}
+
+/// Direction a balance must cross its threshold in to trigger
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Comparison {
+    Above,
+    Below,
+}
+
+/// Condition on an account balance, written like `held>100` or `total<0`
+#[derive(Debug, Clone, PartialEq)]
+pub struct AlertRule {
+    pub field: BalanceField,
+    pub comparison: Comparison,
+    pub threshold: f64,
+}
+
+impl AlertRule {
+    pub fn matches(&self, available: f64, held: f64) -> bool {
+        let value = match self.field {
+            BalanceField::Available => available,
+            BalanceField::Held => held,
+            BalanceField::Total => available + held,
+        };
+        match self.comparison {
+            Comparison::Above => value > self.threshold,
+            Comparison::Below => value < self.threshold,
+        }
+    }
+}
+
+impl FromStr for AlertRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid alert rule '{}', expected e.g. held>100", s);
+        let (op_indx, comparison) = match (s.find('>'), s.find('<')) {
+            (Some(indx), None) => (indx, Comparison::Above),
+            (None, Some(indx)) => (indx, Comparison::Below),
+            _ => return Err(invalid()),
+        };
+        let field = match s[..op_indx].trim() {
+            "available" => BalanceField::Available,
+            "held" => BalanceField::Held,
+            "total" => BalanceField::Total,
+            _ => return Err(invalid()),
+        };
+        let threshold = s[op_indx + 1..].trim().parse().map_err(|_| invalid())?;
+        Ok(AlertRule {
+            field,
+            comparison,
+            threshold,
+        })
+    }
+}
+
+impl fmt::Display for AlertRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let field = match self.field {
+            BalanceField::Available => "available",
+            BalanceField::Held => "held",
+            BalanceField::Total => "total",
+        };
+        let op = match self.comparison {
+            Comparison::Above => ">",
+            Comparison::Below => "<",
+        };
+        write!(f, "{}{}{}", field, op, self.threshold)
+    }
+}
+
+/// Emitted when an account starts matching a rule
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BalanceAlert {
+    pub client: u16,
+    pub rule: String,
+    /// Id of the transaction which triggered the alert
+    pub tx: u32,
+    /// Sequence number of the triggering transaction
+    pub seq: u64,
+    pub available: f64,
+    pub held: f64,
+    pub total: f64,
+}
+
+/// Rules to evaluate & where alerts go, alerts are disabled without rules
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct AlertConfig {
+    pub rules: Vec<AlertRule>,
+    /// Alerts are appended here as json lines
+    pub file: Option<String>,
+    /// Alerts are posted here, retried per the webhook settings
+    pub webhook_urls: Vec<String>,
+    /// Alerts are written to stderr as json lines
+    pub stderr: bool,
+}
+
+/// Delivers balance alerts to every configured destination
+#[derive(Debug)]
+pub struct AlertSink {
+    rules: Vec<AlertRule>,
+    file: Option<String>,
+    webhook: Option<WebhookSink>,
+    stderr: bool,
+}
+
+impl AlertSink {
+    /// Webhook settings other than the urls are shared with high severity notifications
+    pub fn new(config: &AlertConfig, webhook: &WebhookConfig) -> Self {
+        let webhook = match config.webhook_urls.is_empty() {
+            true => None,
+            false => Some(WebhookSink::new(WebhookConfig {
+                urls: config.webhook_urls.clone(),
+                ..webhook.clone()
+            })),
+        };
+        Self {
+            rules: config.rules.clone(),
+            file: config.file.clone(),
+            webhook,
+            stderr: config.stderr,
+        }
+    }
+
+    pub fn rules(&self) -> &[AlertRule] {
+        &self.rules
+    }
+
+    pub fn emit(&self, alert: &BalanceAlert) -> Result<(), io::Error> {
+        let line = serde_json::to_string(alert)?;
+        if let Some(file_path) = &self.file {
+            let mut file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(file_path)?;
+            writeln!(file, "{}", line)?;
+        }
+        if self.stderr {
+            eprintln!("{}", line);
+        }
+        if let Some(webhook) = &self.webhook {
+            webhook.notify(alert)?;
+        }
+        Ok(())
+    }
+}
+
+/// Alerts for rules an account started matching after a txn was applied
+/// Alerts are edge triggered, `active` tracks (client, rule index) pairs currently matching
+/// so a rule fires again only after the account stops matching it in between
+pub fn evaluate_rules(
+    rules: &[AlertRule],
+    active: &mut HashSet<(u16, usize)>,
+    acnt: &Account,
+    s_txn: &SequencedTxn,
+) -> Vec<BalanceAlert> {
+    let mut alerts = vec![];
+    for (rule_indx, rule) in rules.iter().enumerate() {
+        let key = (acnt.id, rule_indx);
+        if !rule.matches(acnt.available().to_f64(), acnt.held().to_f64()) {
+            active.remove(&key);
+            continue;
+        }
+        if active.insert(key) {
+            alerts.push(BalanceAlert {
+                client: acnt.id,
+                rule: rule.to_string(),
+                tx: s_txn.txn.id(),
+                seq: s_txn.seq,
+                available: acnt.available().to_f64(),
+                held: acnt.held().to_f64(),
+                total: acnt.get_total(),
+            });
+        }
+    }
+    alerts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{evaluate_rules, AlertRule, BalanceField, Comparison};
+    use crate::account::Account;
+    use crate::amount::{Available, Held};
+    use crate::test_support::deposit;
+    use crate::transaction::SequencedTxn;
+    use std::collections::HashSet;
+
+    #[test]
+    fn tst_alert_rule_from_str() {
+        let rule: AlertRule = "held>100".parse().unwrap();
+        assert_eq!(
+            rule,
+            AlertRule {
+                field: BalanceField::Held,
+                comparison: Comparison::Above,
+                threshold: 100.0
+            }
+        );
+        assert!(rule.matches(0.0, 100.5));
+        assert!(!rule.matches(500.0, 100.0));
+
+        let rule: AlertRule = "total < -2.5".parse().unwrap();
+        assert_eq!(rule.to_string(), "total<-2.5");
+        assert!(rule.matches(-3.0, 0.0));
+
+        assert!("held>".parse::<AlertRule>().is_err());
+        assert!("balance>1".parse::<AlertRule>().is_err());
+        assert!("held<>1".parse::<AlertRule>().is_err());
+    }
+
+    #[test]
+    fn tst_evaluate_rules() {
+        let rules: Vec<AlertRule> = vec!["held>5".parse().unwrap(), "total<0".parse().unwrap()];
+        let mut active = HashSet::new();
+        let mut acnt = Account::new(1);
+        let s_txn = SequencedTxn {
+            seq: 7,
+            txn: deposit(1, 3, 1.0),
+        };
+        assert!(evaluate_rules(&rules, &mut active, &acnt, &s_txn).is_empty());
+
+        acnt = Account::with_balances(1, Available::default(), Held::from_f64(10.0));
+        let alerts = evaluate_rules(&rules, &mut active, &acnt, &s_txn);
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(
+            (
+                alerts[0].client,
+                alerts[0].rule.as_str(),
+                alerts[0].tx,
+                alerts[0].seq
+            ),
+            (1, "held>5", 3, 7)
+        );
+        assert!(
+            evaluate_rules(&rules, &mut active, &acnt, &s_txn).is_empty(),
+            "Rules still matching should not fire again"
+        );
+
+        acnt = Account::new(1);
+        assert!(evaluate_rules(&rules, &mut active, &acnt, &s_txn).is_empty());
+        acnt = Account::with_balances(1, Available::from_f64(-7.0), Held::from_f64(6.0));
+        let alerts = evaluate_rules(&rules, &mut active, &acnt, &s_txn);
+        assert_eq!(alerts.len(), 2, "Cleared rules fire again once matched");
+        assert_eq!(alerts[1].total, -1.0);
+    }
+}
@@ -0,0 +1,447 @@
+//! Decimal formatting of amounts through integer minor units, so output never shows float artifacts
+//! Input amounts are parsed into `Amount`s, which decide what happens to digits past `PRECISION` places
+//! under the run's `PrecisionPolicy`. The rounding `from_f64` of the newtypes is for configured amounts,
+//! e.g. fees & thresholds, & for tests, never for amounts read
+
+use crate::constants::PRECISION;
+use std::fmt;
+use std::str::FromStr;
+
+/// Minor units in one whole unit of currency
+pub const MINOR_UNITS: i64 = 10_i64.pow(PRECISION as u32);
+
+/// Magnitude below which every minor unit amount survives a round trip through f64
+pub const MAX_EXACT_MINOR_UNITS: i64 = 10_i64.pow(15);
+
+/// Rounds an amount to the nearest minor unit, ties away from zero
+/// Exact for amounts under `MAX_EXACT_MINOR_UNITS` minor units
+pub fn to_minor_units(amount: f64) -> i64 {
+    (amount * MINOR_UNITS as f64).round() as i64
+}
+
+/// Formats minor units as a decimal with `PRECISION` places, e.g. `-12345` as `-1.2345`
+pub fn format_minor_units(minor: i64) -> String {
+    format_wide_minor_units(minor.into())
+}
+
+/// Formats minor units summed wider than an `i64` as `format_minor_units` formats them, e.g. an account's total
+pub fn format_wide_minor_units(minor: i128) -> String {
+    let sign = if minor < 0 { "-" } else { "" };
+    let abs = minor.unsigned_abs();
+    let unit = MINOR_UNITS as u128;
+    format!(
+        "{}{}.{:0width$}",
+        sign,
+        abs / unit,
+        abs % unit,
+        width = PRECISION
+    )
+}
+
+/// Appends the decimal digits of a number, without the allocation `to_string` makes
+pub fn push_digits(out: &mut Vec<u8>, mut value: u64) {
+    let mut digits = [0u8; 20];
+    let mut start = digits.len();
+    loop {
+        start -= 1;
+        digits[start] = b'0' + (value % 10) as u8;
+        value /= 10;
+        if value == 0 {
+            break;
+        }
+    }
+    out.extend_from_slice(&digits[start..]);
+}
+
+/// Appends minor units as `format_wide_minor_units` formats them, with `separator` between whole & fractional parts
+/// Whole units of the sum of two `i64`s of minor units always fit a `u64`
+pub fn push_minor_units(out: &mut Vec<u8>, minor: i128, separator: char) {
+    if minor < 0 {
+        out.push(b'-');
+    }
+    let abs = minor.unsigned_abs();
+    let unit = MINOR_UNITS as u128;
+    push_digits(out, (abs / unit) as u64);
+    out.extend_from_slice(separator.encode_utf8(&mut [0; 4]).as_bytes());
+    let frac = abs % unit;
+    let mut scale = unit / 10;
+    while scale > 0 {
+        out.push(b'0' + (frac / scale % 10) as u8);
+        scale /= 10;
+    }
+}
+
+/// Formats an amount for output, amounts which round to zero never print as `-0.0000`
+pub fn format_amount(amount: f64) -> String {
+    format_minor_units(to_minor_units(amount))
+}
+
+/// Parses a decimal with at most `PRECISION` places into minor units
+pub fn parse_minor_units(s: &str) -> Option<i64> {
+    let (negative, digits) = match s.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, s),
+    };
+    let (whole, frac) = digits.split_once('.').unwrap_or((digits, ""));
+    let all_digits = |part: &str| part.bytes().all(|b| b.is_ascii_digit());
+    if whole.is_empty() || frac.len() > PRECISION || !all_digits(whole) || !all_digits(frac) {
+        return None;
+    }
+    let frac_minor = format!("{:0<width$}", frac, width = PRECISION)
+        .parse::<i64>()
+        .ok()?;
+    let minor = whole
+        .parse::<i64>()
+        .ok()?
+        .checked_mul(MINOR_UNITS)?
+        .checked_add(frac_minor)?;
+    Some(if negative { -minor } else { minor })
+}
+
+/// What parsing does with digits past `PRECISION` places
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PrecisionPolicy {
+    /// Drops them, truncating toward zero, as amounts were always read
+    #[default]
+    Truncate,
+    /// Rounds to the nearest minor unit, ties away from zero, the same as output formatting
+    Round,
+    /// Rejects the amount
+    Reject,
+}
+
+impl FromStr for PrecisionPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "truncate" => Ok(PrecisionPolicy::Truncate),
+            "round" => Ok(PrecisionPolicy::Round),
+            "reject" => Ok(PrecisionPolicy::Reject),
+            _ => Err(format!(
+                "Unknown precision policy '{}', expected truncate, round, or reject",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AmountError {
+    /// Not a finite number, or too large to hold in minor units
+    Malformed,
+    /// Has digits past `PRECISION` places & the policy rejects them
+    ExcessPrecision,
+    /// Sum or difference past the range of minor units
+    Overflow,
+}
+
+/// Amount held as whole minor units, so it always has exactly `PRECISION` places
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Amount(i64);
+
+impl Amount {
+    pub fn from_minor_units(minor: i64) -> Self {
+        Amount(minor)
+    }
+
+    pub fn minor_units(&self) -> i64 {
+        self.0
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        self.0 as f64 / MINOR_UNITS as f64
+    }
+
+    /// Nearest minor unit of a float, saturating for floats outside the range of minor units
+    pub fn round_f64(value: f64) -> Self {
+        Amount(to_minor_units(value))
+    }
+
+    pub fn checked_add(self, other: Amount) -> Result<Self, AmountError> {
+        self.0
+            .checked_add(other.0)
+            .map(Amount)
+            .ok_or(AmountError::Overflow)
+    }
+
+    pub fn checked_sub(self, other: Amount) -> Result<Self, AmountError> {
+        self.0
+            .checked_sub(other.0)
+            .map(Amount)
+            .ok_or(AmountError::Overflow)
+    }
+
+    /// Parses a decimal, applying the policy to digits past `PRECISION` places
+    /// Forms only a float parser reads, e.g. `1e3`, go through f64 with the same policy
+    pub fn parse(s: &str, policy: PrecisionPolicy) -> Result<Self, AmountError> {
+        match parse_decimal(s, policy) {
+            Some(result) => result,
+            None => Self::from_f64(
+                s.parse::<f64>().map_err(|_| AmountError::Malformed)?,
+                policy,
+            ),
+        }
+    }
+
+    /// Applies the policy to a float's digits past `PRECISION` places
+    /// Float artifacts a hair off a minor unit, e.g. `0.29` as `2899.9999..`, count as that minor unit
+    pub fn from_f64(value: f64, policy: PrecisionPolicy) -> Result<Self, AmountError> {
+        let scaled = value * MINOR_UNITS as f64;
+        if !scaled.is_finite() || scaled.abs() >= i64::MAX as f64 {
+            return Err(AmountError::Malformed);
+        }
+        let nearest = scaled.round();
+        if (scaled - nearest).abs() < 1e-6 {
+            return Ok(Amount(nearest as i64));
+        }
+        match policy {
+            PrecisionPolicy::Truncate => Ok(Amount(scaled.trunc() as i64)),
+            PrecisionPolicy::Round => Ok(Amount(nearest as i64)),
+            PrecisionPolicy::Reject => Err(AmountError::ExcessPrecision),
+        }
+    }
+}
+
+/// Funds of an account which are available for withdrawal
+/// Only `Account`'s movement functions change an account's funds
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Available(Amount);
+
+/// Funds of an account held by disputes & withdrawals waiting on approval
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Held(Amount);
+
+/// Funds a txn moves, e.g. a deposit's amount or what a dispute holds
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct TxnAmount(Amount);
+
+impl Available {
+    pub fn new(amount: Amount) -> Self {
+        Available(amount)
+    }
+
+    /// Rounds a float to the nearest minor unit
+    pub fn from_f64(value: f64) -> Self {
+        Available(Amount::round_f64(value))
+    }
+
+    pub fn amount(&self) -> Amount {
+        self.0
+    }
+
+    pub fn minor_units(&self) -> i64 {
+        self.0.minor_units()
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        self.0.to_f64()
+    }
+}
+
+impl Held {
+    pub fn new(amount: Amount) -> Self {
+        Held(amount)
+    }
+
+    /// Rounds a float to the nearest minor unit
+    pub fn from_f64(value: f64) -> Self {
+        Held(Amount::round_f64(value))
+    }
+
+    pub fn amount(&self) -> Amount {
+        self.0
+    }
+
+    pub fn minor_units(&self) -> i64 {
+        self.0.minor_units()
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        self.0.to_f64()
+    }
+}
+
+impl TxnAmount {
+    pub fn new(amount: Amount) -> Self {
+        TxnAmount(amount)
+    }
+
+    /// Rounds a float to the nearest minor unit, e.g. a configured fee
+    /// Amounts read go through `Amount::parse` & the run's policy instead
+    pub fn from_f64(value: f64) -> Self {
+        TxnAmount(Amount::round_f64(value))
+    }
+
+    pub fn amount(&self) -> Amount {
+        self.0
+    }
+
+    /// Sums amounts of txns referring to the same one, e.g. the parts a deposit was refunded in
+    pub fn checked_add(self, other: TxnAmount) -> Result<Self, AmountError> {
+        self.0.checked_add(other.0).map(TxnAmount)
+    }
+
+    /// What's left of an amount once part of it has moved, e.g. a hold after a partial release
+    pub fn checked_sub(self, other: TxnAmount) -> Result<Self, AmountError> {
+        self.0.checked_sub(other.0).map(TxnAmount)
+    }
+
+    pub fn minor_units(&self) -> i64 {
+        self.0.minor_units()
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        self.0.to_f64()
+    }
+}
+
+impl fmt::Display for Amount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&format_minor_units(self.0))
+    }
+}
+
+/// Parses a plain decimal exactly, None for text which isn't one so it can be tried as a float
+fn parse_decimal(s: &str, policy: PrecisionPolicy) -> Option<Result<Amount, AmountError>> {
+    let (negative, digits) = match s.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, s.strip_prefix('+').unwrap_or(s)),
+    };
+    let (whole, frac) = digits.split_once('.').unwrap_or((digits, ""));
+    let all_digits = |part: &str| part.bytes().all(|b| b.is_ascii_digit());
+    if (whole.is_empty() && frac.is_empty()) || !all_digits(whole) || !all_digits(frac) {
+        return None;
+    }
+    let (kept, extra) = frac.split_at(frac.len().min(PRECISION));
+    let round_up = match policy {
+        _ if extra.bytes().all(|b| b == b'0') => false,
+        PrecisionPolicy::Truncate => false,
+        PrecisionPolicy::Round => extra.as_bytes()[0] >= b'5',
+        PrecisionPolicy::Reject => return Some(Err(AmountError::ExcessPrecision)),
+    };
+    let minor = format!("{:0>1}{:0<width$}", whole, kept, width = PRECISION)
+        .parse::<i64>()
+        .ok()
+        .and_then(|minor| minor.checked_add(round_up as i64));
+    Some(match minor {
+        Some(minor) => Ok(Amount(if negative { -minor } else { minor })),
+        None => Err(AmountError::Malformed),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        format_amount, format_minor_units, format_wide_minor_units, parse_minor_units,
+        push_minor_units, to_minor_units, Amount, AmountError, PrecisionPolicy,
+        MAX_EXACT_MINOR_UNITS, MINOR_UNITS,
+    };
+
+    #[test]
+    fn tst_format_amount() {
+        assert_eq!(format_amount(0.1 + 0.2), "0.3000");
+        assert_eq!(format_amount(1.00005), "1.0001");
+        assert_eq!(format_amount(-0.00001), "0.0000");
+        assert_eq!(format_amount(-2.5), "-2.5000");
+        assert_eq!(format_minor_units(i64::MIN), "-922337203685477.5808");
+        let widest = 2 * i64::MAX as i128;
+        assert_eq!(format_wide_minor_units(widest), "1844674407370955.1614");
+        let mut pushed = vec![];
+        push_minor_units(&mut pushed, -widest, ',');
+        assert_eq!(pushed, b"-1844674407370955,1614");
+        assert_eq!(parse_minor_units("12.3"), Some(123_000));
+        assert_eq!(parse_minor_units("1.23456"), None);
+        assert_eq!(parse_minor_units("-.5"), None);
+        assert_eq!(parse_minor_units("1e3"), None);
+    }
+
+    #[test]
+    fn tst_amount_round_trip() {
+        // Every amount up to 100 in either direction, plus amounts near the largest exact one
+        let far = MAX_EXACT_MINOR_UNITS - MINOR_UNITS;
+        let mut pushed = vec![];
+        for minor in (-1_000_000..=1_000_000)
+            .chain(far..far + MINOR_UNITS)
+            .chain([i64::MIN, i64::MAX])
+        {
+            let formatted = format_minor_units(minor);
+            pushed.clear();
+            push_minor_units(&mut pushed, minor.into(), '.');
+            assert_eq!(pushed, formatted.as_bytes());
+            if minor.unsigned_abs() > MAX_EXACT_MINOR_UNITS as u64 {
+                continue;
+            }
+            assert_eq!(parse_minor_units(&formatted), Some(minor), "{}", formatted);
+            let amount = minor as f64 / MINOR_UNITS as f64;
+            assert_eq!(to_minor_units(amount), minor, "{}", formatted);
+            assert_eq!(format_amount(amount), formatted);
+        }
+    }
+
+    #[test]
+    fn tst_amount_precision_policy() {
+        let parse = |s: &str, policy| Amount::parse(s, policy).map(|amount| amount.to_string());
+        for policy in [
+            PrecisionPolicy::Truncate,
+            PrecisionPolicy::Round,
+            PrecisionPolicy::Reject,
+        ] {
+            assert_eq!(parse("0.29", policy), Ok("0.2900".to_string()));
+            assert_eq!(parse("-2", policy), Ok("-2.0000".to_string()));
+            assert_eq!(parse(".5", policy), Ok("0.5000".to_string()));
+            assert_eq!(parse("1.500000", policy), Ok("1.5000".to_string()));
+            assert_eq!(parse("1e3", policy), Ok("1000.0000".to_string()));
+            assert_eq!(parse("12.3.4", policy), Err(AmountError::Malformed));
+            assert_eq!(parse("NaN", policy), Err(AmountError::Malformed));
+        }
+        // Truncation keeps the same magnitude either side of zero
+        assert_eq!(
+            parse("0.12345", PrecisionPolicy::Truncate),
+            Ok("0.1234".to_string())
+        );
+        assert_eq!(
+            parse("-0.12345", PrecisionPolicy::Truncate),
+            Ok("-0.1234".to_string())
+        );
+        assert_eq!(
+            parse("-0.00009", PrecisionPolicy::Truncate),
+            Ok("0.0000".to_string())
+        );
+        assert_eq!(
+            parse("0.12345", PrecisionPolicy::Round),
+            Ok("0.1235".to_string())
+        );
+        assert_eq!(
+            parse("-0.12345", PrecisionPolicy::Round),
+            Ok("-0.1235".to_string())
+        );
+        assert_eq!(
+            parse("0.12344", PrecisionPolicy::Round),
+            Ok("0.1234".to_string())
+        );
+        assert_eq!(
+            parse("9.99995", PrecisionPolicy::Round),
+            Ok("10.0000".to_string())
+        );
+        assert_eq!(
+            parse("0.12345", PrecisionPolicy::Reject),
+            Err(AmountError::ExcessPrecision)
+        );
+        assert_eq!(
+            parse("1.5e-5", PrecisionPolicy::Reject),
+            Err(AmountError::ExcessPrecision)
+        );
+
+        assert_eq!(
+            Amount::from_f64(0.1 + 0.2, PrecisionPolicy::Reject),
+            Ok(Amount::from_minor_units(3000))
+        );
+        assert_eq!(
+            Amount::parse("-1.25", PrecisionPolicy::Reject)
+                .unwrap()
+                .to_f64(),
+            -1.25
+        );
+    }
+}
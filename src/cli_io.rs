@@ -1,65 +1,1573 @@
 use crate::account::Account;
-use crate::constants::PRECISION;
-use crate::transaction::{PureTxn, RefTxn, Transaction};
+use crate::alerts::AlertConfig;
+use crate::amount::{
+    format_wide_minor_units, parse_minor_units, push_digits, push_minor_units, Amount, AmountError,
+    PrecisionPolicy, TxnAmount,
+};
+use crate::clock::ClockSource;
+use crate::encoding::InputEncoding;
+use crate::generator::Scenario;
+use crate::payments_engine::config::{DuplicateCheck, EngineConfig, GcPolicy};
+use crate::payments_engine::flow_report::FlowBucket;
+use crate::payments_engine::initial_state::DuplicateClientPolicy;
+#[cfg(feature = "rules")]
+use crate::payments_engine::rules::RulesFile;
+use crate::payments_engine::stats::AccountActivity;
+use crate::shadow::ShadowConfig;
+use crate::timestamp::parse_timestamp;
+use crate::transaction::{
+    AdminAction, AdminTxn, Channel, DisputeHistory, PureTxn, RefTxn, RefundTxn, ReleaseTxn,
+    SequencedTxn, Transaction,
+};
+use crate::transform::IngestTransform;
+use crate::webhook::WebhookConfig;
 use csv::Writer;
-use csv::{ReaderBuilder, Trim};
+use csv::{QuoteStyle, ReaderBuilder, StringRecord, Terminator, Trim, WriterBuilder};
 use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
-use std::io::{self, ErrorKind};
+use std::io::{self, ErrorKind, Write};
+use std::str::FromStr;
+use std::time::Duration;
 
-fn get_specified_precision(val: &f64, decimal_precision: &i32) -> f64 {
-    (val * (10.0_f64).powi(*decimal_precision)).floor() / (10.0_f64).powi(*decimal_precision)
-}
+mod process_args;
+
+use process_args::parse_process_args;
 
 /// Options and data to export results
+#[derive(Debug, Clone, PartialEq)]
 pub enum OutputMethod {
-    /// Output to csv file.  Used for integration testing.
-    _Csv(String),
-    /// Output to console
+    /// Output to csv file
+    Csv(String),
+    /// Output to console, nothing else is written to stdout so the output can be piped
     StdOutput,
 }
 
-/// Output a collection of accounts
-pub fn output_accounts(accounts: &Vec<Account>, output: &OutputMethod) {
-    match output {
-        OutputMethod::_Csv(file_path) => {
-            let _ = output_accounts_csv(accounts, file_path);
+impl OutputMethod {
+    /// Parses an `--output` value, `-` is stdout & anything else a file path
+    pub fn from_arg(value: String) -> Self {
+        match value.as_str() {
+            "-" => OutputMethod::StdOutput,
+            _ => OutputMethod::Csv(value),
         }
-        OutputMethod::StdOutput => {
-            println!("client,available,held,total,locked");
-            for acnt in accounts.iter() {
-                acnt.print_std_out();
+    }
+}
+
+/// Format accounts are written in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Csv in the configured schema & dialect
+    Csv,
+    /// A json object per account & line, keyed by the columns of the configured schema
+    JsonLines,
+}
+
+/// One of the destinations a run's accounts are written to
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutputSink {
+    pub method: OutputMethod,
+    pub format: OutputFormat,
+}
+
+impl OutputSink {
+    pub fn csv(method: OutputMethod) -> Self {
+        Self {
+            method,
+            format: OutputFormat::Csv,
+        }
+    }
+
+    pub fn json_lines(method: OutputMethod) -> Self {
+        Self {
+            method,
+            format: OutputFormat::JsonLines,
+        }
+    }
+
+    pub fn is_stdout(&self) -> bool {
+        self.method == OutputMethod::StdOutput
+    }
+
+    /// Path of a csv file sink, checksums are only written for these
+    pub fn csv_file(&self) -> Option<&str> {
+        match (&self.method, self.format) {
+            (OutputMethod::Csv(file_path), OutputFormat::Csv) => Some(file_path),
+            _ => None,
+        }
+    }
+}
+
+/// Version of the account output format, each version only appends columns to the one before
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputSchema {
+    /// `client, available, held, total, locked`
+    #[default]
+    V1,
+    /// v1 followed by `status, disputes, chargebacks, last_txn_id`
+    V2,
+}
+
+impl FromStr for OutputSchema {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "v1" => Ok(OutputSchema::V1),
+            "v2" => Ok(OutputSchema::V2),
+            _ => Err(format!("Unknown output schema '{}'", s)),
+        }
+    }
+}
+
+/// Values written for the boolean lock columns of account outputs, for readers expecting e.g. `LOCKED` & `ACTIVE`
+#[derive(Debug, Clone, PartialEq)]
+pub struct StatusValues {
+    pub locked: String,
+    pub unlocked: String,
+}
+
+impl Default for StatusValues {
+    fn default() -> Self {
+        Self {
+            locked: "true".to_string(),
+            unlocked: "false".to_string(),
+        }
+    }
+}
+
+impl FromStr for StatusValues {
+    type Err = String;
+
+    /// Parses `<locked>,<unlocked>`, e.g. `LOCKED,ACTIVE` or `1,0`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(',') {
+            Some((locked, unlocked)) => Ok(Self {
+                locked: locked.trim().to_string(),
+                unlocked: unlocked.trim().to_string(),
+            }),
+            None => Err(format!(
+                "Invalid status values '{}', expected <locked>,<unlocked>",
+                s
+            )),
+        }
+    }
+}
+
+impl StatusValues {
+    fn as_str(&self, locked: bool) -> &str {
+        match locked {
+            true => &self.locked,
+            false => &self.unlocked,
+        }
+    }
+
+    /// Errors unless each value maps back to one state, so readers can tell locked & unlocked apart
+    fn validate(&self) -> Result<(), io::Error> {
+        if self.locked.is_empty() || self.unlocked.is_empty() {
+            return Err(invalid_input(
+                "--status-values can't be empty, an empty field reads as a missing value"
+                    .to_string(),
+            ));
+        }
+        if self.locked == self.unlocked {
+            return Err(invalid_input(format!(
+                "--status-values must differ, '{}' would be written for locked & unlocked accounts alike",
+                self.locked
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Formatting of account outputs, for spreadsheets of locales which don't read plain csv
+#[derive(Debug, Clone, PartialEq)]
+pub struct CsvDialect {
+    pub delimiter: u8,
+    /// Separates whole & fractional parts of amounts, `.` or `,`
+    pub decimal_separator: char,
+    /// End lines with `\r\n` instead of `\n`
+    pub crlf: bool,
+    /// Start the output with a UTF-8 byte order mark, which some spreadsheets need to detect the encoding
+    pub bom: bool,
+    /// Quote every field & prefix ones Excel would read as a formula with `'`, so opening the file in
+    /// Excel doesn't evaluate them, numeric looking fields are still read as numbers
+    pub excel_safe: bool,
+    /// Written for the `locked`, `locked_by_chargeback`, & `admin_hold` columns
+    pub status_values: StatusValues,
+}
+
+impl Default for CsvDialect {
+    fn default() -> Self {
+        Self {
+            delimiter: b',',
+            decimal_separator: '.',
+            crlf: false,
+            bom: false,
+            excel_safe: false,
+            status_values: StatusValues::default(),
+        }
+    }
+}
+
+impl CsvDialect {
+    /// Csv writer over `out` using the dialect, writing the byte order mark first if asked for
+    fn writer<W: io::Write>(&self, mut out: W) -> Result<Writer<W>, io::Error> {
+        if self.bom {
+            out.write_all("\u{feff}".as_bytes())?;
+        }
+        let terminator = match self.crlf {
+            true => Terminator::CRLF,
+            false => Terminator::Any(b'\n'),
+        };
+        let quote_style = match self.excel_safe {
+            true => QuoteStyle::Always,
+            false => QuoteStyle::Necessary,
+        };
+        Ok(WriterBuilder::new()
+            .delimiter(self.delimiter)
+            .terminator(terminator)
+            .quote_style(quote_style)
+            // Files are written unbuffered, a larger buffer means fewer writes for big outputs
+            .buffer_capacity(1 << 16)
+            .from_writer(out))
+    }
+
+    /// Field as written in the dialect, excel safe output prefixes fields Excel would read as a formula
+    /// Negative amounts start like a formula but are left as numbers
+    fn field<'a>(&self, field: &'a [u8]) -> Cow<'a, [u8]> {
+        let formula_like = matches!(
+            field.first(),
+            Some(b'=' | b'+' | b'-' | b'@' | b'\t' | b'\r')
+        );
+        if !self.excel_safe || !formula_like || self.is_number(field) {
+            return Cow::Borrowed(field);
+        }
+        let mut prefixed = Vec::with_capacity(field.len() + 1);
+        prefixed.push(b'\'');
+        prefixed.extend_from_slice(field);
+        Cow::Owned(prefixed)
+    }
+
+    fn is_number(&self, field: &[u8]) -> bool {
+        std::str::from_utf8(field).is_ok_and(|text| {
+            parse_minor_units(&text.replace(self.decimal_separator, ".")).is_some()
+        })
+    }
+
+    /// Errors if amounts or fields can't be told apart in the dialect
+    fn validate(&self) -> Result<(), io::Error> {
+        if !self.delimiter.is_ascii() || matches!(self.delimiter, b'"' | b'\r' | b'\n') {
+            return Err(invalid_input(
+                "--delimiter must be an ascii character other than a quote or line break"
+                    .to_string(),
+            ));
+        }
+        if !matches!(self.decimal_separator, '.' | ',') {
+            return Err(invalid_input(
+                "--decimal-separator must be '.' or ','".to_string(),
+            ));
+        }
+        if self.delimiter == self.decimal_separator as u8 {
+            return Err(invalid_input(
+                "--delimiter & --decimal-separator must differ".to_string(),
+            ));
+        }
+        self.status_values.validate()
+    }
+}
+
+/// Columns of an account output beyond the v1 ones
+#[derive(Debug, Default)]
+pub struct AccountColumns {
+    /// Append the reasons an account is locked
+    pub lock_reasons: bool,
+    /// Activity per client for the v2 columns, written as v1 when unset
+    pub activity: Option<HashMap<u16, AccountActivity>>,
+    /// Append the id of the txn whose chargeback locked each account, for the compliance feed
+    pub chargeback_txns: Option<HashMap<u16, u32>>,
+}
+
+/// Column names of account outputs
+/// Later columns are appended so readers of the legacy columns keep working
+pub(crate) fn account_header(columns: &AccountColumns) -> Vec<&'static str> {
+    let mut header = vec!["client", "available", "held", "total", "locked"];
+    if columns.lock_reasons {
+        header.extend(["locked_by_chargeback", "admin_hold"]);
+    }
+    if columns.activity.is_some() {
+        header.extend(["status", "disputes", "chargebacks", "last_txn_id"]);
+    }
+    if columns.chargeback_txns.is_some() {
+        header.push("chargeback_tx");
+    }
+    header
+}
+
+/// Output a collection of accounts to each sink, a sink failing doesn't stop the others
+pub fn output_accounts(
+    accounts: &[Account],
+    outputs: &[OutputSink],
+    columns: &AccountColumns,
+    dialect: &CsvDialect,
+) {
+    for sink in outputs {
+        match (&sink.method, sink.format) {
+            (OutputMethod::Csv(file_path), OutputFormat::Csv) => {
+                let _ = output_accounts_csv(accounts, file_path, columns, dialect);
+            }
+            (OutputMethod::StdOutput, OutputFormat::Csv) => {
+                let _ = write_accounts_csv(io::stdout().lock(), accounts, columns, dialect);
+            }
+            (OutputMethod::Csv(file_path), OutputFormat::JsonLines) => {
+                if let Ok(file) = std::fs::File::create(file_path) {
+                    let _ = write_accounts_json(file, accounts, columns);
+                }
+            }
+            (OutputMethod::StdOutput, OutputFormat::JsonLines) => {
+                let _ = write_accounts_json(io::stdout().lock(), accounts, columns);
             }
         }
     }
 }
 
-fn output_accounts_csv(accounts: &Vec<Account>, file_path: &str) -> Result<(), Box<dyn Error>> {
-    let mut wtr = Writer::from_path(file_path)?;
-    wtr.write_record(&["client", "available", "held", "total", "locked"])?;
+/// Writes a checksum sidecar next to each csv file the accounts were output to
+pub fn output_accounts_checksums(
+    accounts: &[Account],
+    outputs: &[OutputSink],
+) -> Result<(), Box<dyn Error>> {
+    for file_path in outputs.iter().filter_map(OutputSink::csv_file) {
+        output_accounts_checksum(accounts, file_path)?;
+    }
+    Ok(())
+}
+
+/// Fields of one record in a single buffer, reused across records so writing accounts allocates nothing per account
+#[derive(Debug, Default)]
+struct RecordBuf {
+    bytes: Vec<u8>,
+    /// End offset of each field in `bytes`
+    ends: Vec<usize>,
+}
+
+impl RecordBuf {
+    fn clear(&mut self) {
+        self.bytes.clear();
+        self.ends.clear();
+    }
+
+    fn end_field(&mut self) {
+        self.ends.push(self.bytes.len());
+    }
+
+    fn push_number(&mut self, value: u64) {
+        push_digits(&mut self.bytes, value);
+        self.end_field();
+    }
+
+    fn push_amount(&mut self, minor: i128, decimal_separator: char) {
+        push_minor_units(&mut self.bytes, minor, decimal_separator);
+        self.end_field();
+    }
+
+    fn push_str(&mut self, text: &str) {
+        self.bytes.extend_from_slice(text.as_bytes());
+        self.end_field();
+    }
+
+    fn push_bool(&mut self, flag: bool, status_values: &StatusValues) {
+        self.push_str(status_values.as_str(flag));
+    }
+
+    fn push_optional(&mut self, value: Option<u64>) {
+        match value {
+            Some(value) => self.push_number(value),
+            None => self.end_field(),
+        }
+    }
+
+    /// Fields of an account as they appear in output files, amounts & lock flags as the dialect writes them
+    fn push_account(&mut self, acnt: &Account, columns: &AccountColumns, dialect: &CsvDialect) {
+        let (decimal_separator, status_values) =
+            (dialect.decimal_separator, &dialect.status_values);
+        self.push_number(u64::from(acnt.id));
+        self.push_amount(acnt.available().minor_units().into(), decimal_separator);
+        self.push_amount(acnt.held().minor_units().into(), decimal_separator);
+        self.push_amount(acnt.total_minor_units(), decimal_separator);
+        self.push_bool(acnt.is_locked(), status_values);
+        if columns.lock_reasons {
+            self.push_bool(acnt.locked_by_chargeback, status_values);
+            self.push_bool(acnt.admin_hold, status_values);
+        }
+        if let Some(activity) = &columns.activity {
+            let activity = activity.get(&acnt.id).cloned().unwrap_or_default();
+            self.push_str(acnt.status().as_str());
+            self.push_number(activity.disputes);
+            self.push_number(activity.chargebacks);
+            self.push_optional(activity.last_txn_id.map(u64::from));
+        }
+        if let Some(chargeback_txns) = &columns.chargeback_txns {
+            self.push_optional(chargeback_txns.get(&acnt.id).map(|id| u64::from(*id)));
+        }
+    }
+
+    fn fields(&self) -> impl Iterator<Item = &[u8]> {
+        let starts = std::iter::once(0).chain(self.ends.iter().copied());
+        starts
+            .zip(self.ends.iter().copied())
+            .map(|(start, end)| &self.bytes[start..end])
+    }
+}
+
+/// Fields of an account as they appear in output files
+pub(crate) fn account_record(acnt: &Account, columns: &AccountColumns) -> Vec<String> {
+    let mut record = RecordBuf::default();
+    record.push_account(acnt, columns, &CsvDialect::default());
+    record
+        .fields()
+        .map(|field| String::from_utf8_lossy(field).into_owned())
+        .collect()
+}
+
+/// Writes the account header & one record per account, nothing else
+fn write_accounts<W: io::Write>(
+    wtr: &mut Writer<W>,
+    accounts: &[Account],
+    columns: &AccountColumns,
+    dialect: &CsvDialect,
+) -> Result<(), Box<dyn Error>> {
+    wtr.write_record(account_header(columns))?;
+    let mut record = RecordBuf::default();
     for acnt in accounts {
-        wtr.write_record(&[
-            format!("{}", acnt.id),
-            format!("{:.*}", PRECISION, acnt.available),
-            format!("{:.*}", PRECISION, acnt.held),
-            format!("{:.*}", PRECISION, acnt.get_total()),
-            format!("{}", acnt.frozen),
+        record.clear();
+        record.push_account(acnt, columns, dialect);
+        wtr.write_record(record.fields().map(|field| dialect.field(field)))?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+/// Writes accounts as csv in the dialect to any writer, e.g. a sink of an application embedding the engine
+pub fn write_accounts_csv<W: io::Write>(
+    out: W,
+    accounts: &[Account],
+    columns: &AccountColumns,
+    dialect: &CsvDialect,
+) -> Result<(), Box<dyn Error>> {
+    let mut wtr = dialect.writer(out)?;
+    write_accounts(&mut wtr, accounts, columns, dialect)
+}
+
+/// Value of an account field in json output
+/// Amounts stay strings so they keep their output precision, empty fields are null
+fn json_field(name: &str, field: String) -> serde_json::Value {
+    if field.is_empty() {
+        return serde_json::Value::Null;
+    }
+    if matches!(name, "available" | "held" | "total") {
+        return serde_json::Value::String(field);
+    }
+    if let Ok(flag) = field.parse::<bool>() {
+        return serde_json::Value::Bool(flag);
+    }
+    match field.parse::<u64>() {
+        Ok(number) => serde_json::Value::from(number),
+        Err(_) => serde_json::Value::String(field),
+    }
+}
+
+/// Writes a json object per account & line, with keys in column order
+fn write_accounts_json<W: io::Write>(
+    out: W,
+    accounts: &[Account],
+    columns: &AccountColumns,
+) -> Result<(), Box<dyn Error>> {
+    let mut out = io::BufWriter::new(out);
+    let header = account_header(columns);
+    for acnt in accounts {
+        let fields: Vec<String> = header
+            .iter()
+            .zip(account_record(acnt, columns))
+            .map(|(name, field)| {
+                format!(
+                    "{}:{}",
+                    serde_json::Value::from(*name),
+                    json_field(name, field)
+                )
+            })
+            .collect();
+        writeln!(out, "{{{}}}", fields.join(","))?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+fn output_accounts_csv(
+    accounts: &[Account],
+    file_path: &str,
+    columns: &AccountColumns,
+    dialect: &CsvDialect,
+) -> Result<(), Box<dyn Error>> {
+    write_accounts_csv(
+        std::fs::File::create(file_path)?,
+        accounts,
+        columns,
+        dialect,
+    )
+}
+
+/// Writes a `<file_path>.checksum` sidecar next to an accounts csv
+/// Holds the record count, column sums, & a sha256 of the file so recipients can verify transfers
+pub fn output_accounts_checksum(
+    accounts: &[Account],
+    file_path: &str,
+) -> Result<(), Box<dyn Error>> {
+    let digest = Sha256::digest(std::fs::read(file_path)?);
+    let sha256: String = digest.iter().map(|b| format!("{:02x}", b)).collect();
+    // Summed in minor units so the sums match the columns exactly
+    let sum = |minor: fn(&Account) -> i128| -> i128 { accounts.iter().map(minor).sum() };
+
+    let mut wtr = Writer::from_path(format!("{}.checksum", file_path))?;
+    wtr.write_record(["records", "available", "held", "total", "sha256"])?;
+    wtr.write_record([
+        format!("{}", accounts.len()),
+        format_wide_minor_units(sum(|a| a.available().minor_units().into())),
+        format_wide_minor_units(sum(|a| a.held().minor_units().into())),
+        format_wide_minor_units(sum(Account::total_minor_units)),
+        sha256,
+    ])?;
+    wtr.flush()?;
+    Ok(())
+}
+
+/// Appends accounts to a csv file, writing the header only if the file is new or empty
+pub fn append_accounts_csv(accounts: &[Account], file_path: &str) -> Result<(), Box<dyn Error>> {
+    let file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(file_path)?;
+    let is_empty = file.metadata()?.len() == 0;
+    let mut wtr = Writer::from_writer(file);
+    if is_empty {
+        wtr.write_record(account_header(&AccountColumns::default()))?;
+    }
+    for acnt in accounts {
+        wtr.write_record(account_record(acnt, &AccountColumns::default()))?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+/// Fields of a transaction as they appear in input files
+pub(crate) fn txn_record(txn: &Transaction) -> [String; 4] {
+    let (type_str, acnt_id, txn_id, amount) = match txn {
+        Transaction::Deposit(p_txn) => ("deposit", p_txn.acnt_id, p_txn.txn_id, Some(p_txn.amount)),
+        Transaction::Withdrawal(p_txn) => (
+            "withdrawal",
+            p_txn.acnt_id,
+            p_txn.txn_id,
+            Some(p_txn.amount),
+        ),
+        Transaction::Dispute(ref_txn) => ("dispute", ref_txn.acnt_id, ref_txn.ref_id, None),
+        Transaction::Resolve(ref_txn) => ("resolve", ref_txn.acnt_id, ref_txn.ref_id, None),
+        Transaction::Chargeback(ref_txn) => ("chargeback", ref_txn.acnt_id, ref_txn.ref_id, None),
+        Transaction::Admin(admin_txn) => {
+            let type_str = match admin_txn.action {
+                AdminAction::SetHold => "hold",
+                AdminAction::ClearHold => "unhold",
+                AdminAction::Approve => "approve",
+                AdminAction::Deny => "deny",
+            };
+            (type_str, admin_txn.acnt_id, admin_txn.instr_id, None)
+        }
+        Transaction::Refund(refund_txn) => (
+            "refund",
+            refund_txn.acnt_id,
+            refund_txn.ref_id,
+            Some(refund_txn.amount),
+        ),
+        Transaction::Release(release_txn) => (
+            "release",
+            release_txn.acnt_id,
+            release_txn.ref_id,
+            Some(release_txn.amount),
+        ),
+    };
+    [
+        type_str.to_string(),
+        format!("{}", acnt_id),
+        format!("{}", txn_id),
+        amount.map_or(String::new(), |amount| amount.amount().to_string()),
+    ]
+}
+
+/// Csv writer to a file or stdout
+pub fn csv_writer(output: &OutputMethod) -> Result<Writer<Box<dyn io::Write>>, io::Error> {
+    let wtr = match output {
+        OutputMethod::Csv(file_path) => {
+            Writer::from_writer(Box::new(std::fs::File::create(file_path)?) as Box<dyn io::Write>)
+        }
+        OutputMethod::StdOutput => {
+            Writer::from_writer(Box::new(io::stdout()) as Box<dyn io::Write>)
+        }
+    };
+    Ok(wtr)
+}
+
+/// Output a collection of transactions in the same format as input files
+pub fn output_txns_csv(txns: &[Transaction], output: &OutputMethod) -> Result<(), Box<dyn Error>> {
+    let mut wtr = csv_writer(output)?;
+    wtr.write_record(["type", "client", "tx", "amount"])?;
+    for txn in txns {
+        wtr.write_record(txn_record(txn))?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+/// Output processed transactions prefixed by the sequence number they were ingested with
+/// Deposits & withdrawals also get their dispute state, its transitions as `state@seq`, their memo,
+/// & their channel, every txn ends with its account sequence number when it has one
+pub fn output_txn_log_csv<'a>(
+    txns: impl IntoIterator<Item = (&'a SequencedTxn, Option<u64>)>,
+    file_path: &str,
+) -> Result<(), Box<dyn Error>> {
+    let mut wtr = Writer::from_path(file_path)?;
+    wtr.write_record([
+        "seq",
+        "type",
+        "client",
+        "tx",
+        "amount",
+        "dispute_state",
+        "dispute_history",
+        "memo",
+        "channel",
+        "acnt_seq",
+    ])?;
+    for (s_txn, acnt_seq) in txns {
+        let [type_str, client, tx, amount] = txn_record(&s_txn.txn);
+        let (dispute_state, dispute_history, memo, channel) = match &s_txn.txn {
+            Transaction::Deposit(p_txn) | Transaction::Withdrawal(p_txn) => (
+                p_txn.dispute.state().as_str().to_string(),
+                p_txn.dispute.to_export_str(),
+                p_txn.memo.clone().unwrap_or_default(),
+                p_txn
+                    .channel
+                    .map_or("", |channel| channel.as_str())
+                    .to_string(),
+            ),
+            _ => (String::new(), String::new(), String::new(), String::new()),
+        };
+        wtr.write_record([
+            format!("{}", s_txn.seq),
+            type_str,
+            client,
+            tx,
+            amount,
+            dispute_state,
+            dispute_history,
+            memo,
+            channel,
+            acnt_seq.map_or(String::new(), |acnt_seq| acnt_seq.to_string()),
         ])?;
     }
+    wtr.flush()?;
     Ok(())
 }
 
 pub struct CliOptions {
     pub input_file: String,
+    /// Every sink accounts are written to, stdout csv unless outputs are given
+    pub output: Vec<OutputSink>,
+    pub engine_config: EngineConfig,
+    /// Urls notified of high severity account events, disabled when empty
+    pub webhook: WebhookConfig,
+    /// File processed transactions & their sequence numbers are exported to
+    pub txn_log: Option<String>,
+    /// Inactive zero balance accounts are collected while streaming when set
+    pub gc_policy: Option<GcPolicy>,
+    /// Show a progress bar on interactive runs writing output to a file
+    pub progress: bool,
+    /// Append columns explaining why accounts are locked to the account output
+    pub extended_output: bool,
+    /// Version of the account output format
+    pub output_schema: OutputSchema,
+    /// Only output locked accounts & those under review, with the chargeback which locked them
+    pub only_locked: bool,
+    /// Delimiter, decimal separator, line endings, & byte order mark of the account output
+    pub csv_dialect: CsvDialect,
+    /// File per client processing counters & timers are written to
+    pub client_stats: Option<String>,
+    /// Write a checksum sidecar next to the accounts output file
+    pub checksum: bool,
+    /// File activity per payment channel is written to
+    pub channel_report: Option<String>,
+    /// File withdrawals still waiting on approval are reported to
+    pub pending_report: Option<String>,
+    /// Balance alert rules & their destinations, disabled without rules
+    pub alerts: AlertConfig,
+    /// Input is read as headerless fixed width records laid out by this spec instead of csv
+    pub fixed_width: Option<FixedWidthSpec>,
+    /// Read the input as an ISO 20022 camt document, needs the `iso20022` feature
+    pub iso20022: bool,
+    /// Encoding the input is in, detected from its first bytes when unset
+    pub encoding: Option<InputEncoding>,
+    /// Rewrites applied to every record before it is converted, in order
+    pub transforms: Vec<IngestTransform>,
+    /// File pseudonyms of `--anonymize` are written to with the client ids they stand for
+    pub anonymize_map: Option<String>,
+    /// Column aliases & schema checks of the csv header row
+    pub header: HeaderOptions,
+    /// What happens to amounts with more places than `PRECISION`
+    pub precision: PrecisionPolicy,
+    /// `host:port` of a statsd agent processing metrics are sent to
+    pub statsd: Option<String>,
+    /// Prepended to the name of every statsd metric
+    pub statsd_prefix: String,
+    /// Records taking longer than this to apply are logged with their type & client
+    pub latency_budget: Option<Duration>,
+    /// File txns held back from accounts under review are written to
+    pub quarantine: Option<String>,
+    /// File rejected references to other clients' txns are written to
+    pub audit_log: Option<String>,
+    /// File txns still under dispute at the end of the run are written to with their age
+    pub dispute_aging: Option<String>,
+    /// File each account's held funds are written to broken down into the txns holding them
+    pub held_breakdown: Option<String>,
+    /// File every dispute case is written to with its status & notes
+    pub dispute_cases: Option<String>,
+    /// File counts & funds flow per bucket of record timestamps are written to
+    pub flow_report: Option<String>,
+    /// Span of time each flow report row covers
+    pub flow_bucket: FlowBucket,
+    /// File reject rates per partner & error kind are written to
+    pub partner_report: Option<String>,
+    /// Reject rate above which the partner report escalates a partner
+    pub partner_reject_threshold: f64,
+    /// Unix socket urgent operator records are taken over, applied ahead of the bulk input
+    pub priority_unix: Option<String>,
+    /// Clock time based features go by, e.g. the dedupe window's max age
+    pub clock: ClockSource,
+    /// Ledger final balances of a sample of clients are compared against after the run
+    pub shadow: Option<ShadowConfig>,
+    /// Jsonl file records which weren't applied are appended to
+    pub dead_letter: Option<String>,
+    /// Directory account state is saved to as a sharded snapshot at the end of the run
+    pub save_snapshot: Option<String>,
+    /// Files the saved snapshot is split into
+    pub snapshot_shards: usize,
+    /// Save only accounts changed since the snapshot was last saved or restored, as a delta under it
+    pub snapshot_deltas: bool,
+    /// Directory of a sharded snapshot accounts are restored from before processing
+    pub restore_snapshot: Option<String>,
+    /// Csv of accounts, in the output format, loaded before processing
+    pub initial_state: Option<String>,
+    /// How clients appearing on more than one initial state row are consolidated
+    pub on_duplicate_client: DuplicateClientPolicy,
+    /// Csv of account open records, opening accounts & keeping client metadata before processing
+    pub onboarding: Option<String>,
+    /// Run the oracle next to the engine & fail the run if they ever disagree
+    pub oracle_check: bool,
+    /// Fail the run if account totals don't add up to the money which entered less what left
+    pub assert_conservation: bool,
+    /// Rhai script of custom validation rules, run on every txn before it's applied
+    pub script: Option<String>,
+    /// File the balances of clients active in each interval are written to
+    pub balances_series: Option<String>,
+    /// Sequence numbers per balance series interval
+    pub series_interval: u64,
+}
+
+/// Options for generating synthetic input files
+pub struct GenOptions {
+    pub scenario: Scenario,
+    /// Number of records to generate
+    pub records: usize,
+    /// Number of distinct clients records are spread over
+    pub clients: u16,
+    /// Seed of the generator, a random one is logged when unset
+    pub seed: Option<u64>,
     pub output: OutputMethod,
 }
 
-pub fn parse_cli() -> Result<CliOptions, io::Error> {
-    let input_file = std::env::args().nth(1).expect("Missing Input File");
-    let output = OutputMethod::StdOutput;
+/// Options for continuously generating & processing records while reporting resource use
+pub struct SoakOptions {
+    pub scenario: Scenario,
+    /// Number of distinct clients records are spread over
+    pub clients: u16,
+    /// How long to keep going for
+    pub duration: Duration,
+    /// Time between report rows
+    pub report_every: Duration,
+    /// Seed of the generator, a random one is logged when unset
+    pub seed: Option<u64>,
+    pub output: OutputMethod,
+}
 
-    let cli_options = CliOptions { input_file, output };
-    Ok(cli_options)
+/// Actions the binary can be asked to perform
+pub enum CliCommand {
+    /// Process an input file & output account states
+    Process(CliOptions),
+    /// Generate a synthetic input file
+    Gen(GenOptions),
+    /// Follow an append only input file, applying records as they are written
+    Tail(TailOptions),
+    /// Report differences between two account outputs
+    Diff(DiffOptions),
+    /// Summarize an input file without applying it
+    Inspect(InspectOptions),
+    /// Re-attempt dead lettered records against the state of their input file
+    ReplayDlq(ReplayOptions),
+    /// Generate & process synthetic records for a while, reporting throughput & memory
+    Soak(SoakOptions),
+    /// Process an input file on several engine workers, each owning a share of the clients
+    Cluster(ClusterOptions),
+    /// Apply records sent over a unix domain socket as they arrive
+    Listen(ListenOptions),
+    /// Answer read-only queries about a past run from its txn log
+    Explore(ExploreOptions),
+    /// Fold the deltas saved under a snapshot into a new full snapshot
+    CompactSnapshots(CompactOptions),
+    /// Replay a txn log under altered policies & report which account end states change
+    PolicyReplay(PolicyReplayOptions),
+    /// Fold a txn log's records before a sequence number into an opening state ahead of the rest
+    TrimLog(TrimLogOptions),
+    /// Match a txn log's deposits & withdrawals against an external bank statement by reference
+    Reconcile(ReconcileOptions),
+}
+
+/// Options for reconciling a txn log against a bank statement
+pub struct ReconcileOptions {
+    /// Txn log written by an earlier run with `--txn-log`, its memos are the references
+    pub txn_log: String,
+    /// Bank statement csv with a reference & a signed amount column
+    pub bank_statement: String,
+    pub reference_column: String,
+    pub amount_column: String,
+    pub output: OutputMethod,
+}
+
+/// Options for trimming a txn log
+pub struct TrimLogOptions {
+    /// Txn log written by an earlier run with `--txn-log`, possibly trimmed before
+    pub txn_log: String,
+    /// Records with a lower sequence number are folded into the opening state
+    pub before: u64,
+    /// Rules of the run which wrote the log, so trimmed records replay the way they were processed
+    pub engine_config: EngineConfig,
+    /// Directory the opening accounts are saved to as a sharded snapshot, embedded in the log when unset
+    pub snapshot_dir: Option<String>,
+    pub snapshot_shards: usize,
+    /// File the trimmed log is written to, the log is replaced when unset
+    pub output: Option<String>,
+}
+
+/// Options for replaying a txn log under policy overrides
+pub struct PolicyReplayOptions {
+    /// Txn log written by an earlier run with `--txn-log`
+    pub txn_log: String,
+    /// Rules of the run which wrote the log
+    pub engine_config: EngineConfig,
+    /// The run's rules with every `--override` applied in order
+    pub override_config: EngineConfig,
+    pub output: OutputMethod,
+}
+
+/// Options for compacting a snapshot & the deltas saved under it
+pub struct CompactOptions {
+    /// Directory of the snapshot, written by `--save-snapshot`
+    pub dir: String,
+    /// Files the compacted snapshot is split into, as many as the old snapshot if not given
+    pub shards: Option<usize>,
+}
+
+/// Options for exploring a past run through the txn log it wrote
+pub struct ExploreOptions {
+    /// Txn log written by an earlier run with `--txn-log`
+    pub txn_log: String,
+    /// Rules of the run which wrote the log, so its records replay the way they were processed
+    pub engine_config: EngineConfig,
+}
+
+/// Options for replaying a dead letter file after a fix
+pub struct ReplayOptions {
+    /// Dead letters written by an earlier run with `--dead-letter`
+    pub dead_letters: String,
+    /// Options of the rerun, its input file is the one the dead letters came from
+    pub cli_options: CliOptions,
+    /// Csv listing whether each dead letter now succeeds
+    pub report: String,
+}
+
+/// Options for summarizing an input file before a run
+pub struct InspectOptions {
+    pub input_file: String,
+    pub fixed_width: Option<FixedWidthSpec>,
+    /// Encoding the input is in, detected from its first bytes when unset
+    pub encoding: Option<InputEncoding>,
+    pub output: OutputMethod,
+}
+
+/// Options for comparing the account outputs of two runs
+pub struct DiffOptions {
+    /// Accounts csv of the baseline run
+    pub before: String,
+    /// Accounts csv of the run compared against the baseline
+    pub after: String,
+    pub output: OutputMethod,
+}
+
+/// Options for following a transaction file as it is appended to
+pub struct TailOptions {
+    pub cli_options: CliOptions,
+    /// Stop following once no new records have arrived for this long, follows forever when unset
+    pub idle_exit: Option<Duration>,
+}
+
+/// Options for applying records sent over a unix domain socket
+pub struct ListenOptions {
+    /// `input_file` is the socket path, there is no file to read
+    pub cli_options: CliOptions,
+    /// Path the socket is bound to, removed once listening stops
+    pub socket_path: String,
+}
+
+/// Options for processing an input file on several engine workers
+pub struct ClusterOptions {
+    pub cli_options: CliOptions,
+    /// Number of engine workers clients are spread over
+    pub workers: usize,
+    /// Directory each worker saves snapshots under, restarted workers only replay txns since their last one
+    pub snapshot_dir: Option<String>,
+    /// Txns routed to a worker between its snapshots
+    pub snapshot_every: u64,
+}
+
+fn invalid_input(msg: String) -> io::Error {
+    io::Error::new(ErrorKind::InvalidInput, msg)
+}
+
+/// Parses the value following a flag
+fn parse_flag_value<T: FromStr>(flag: &str, value: Option<&String>) -> Result<T, io::Error> {
+    let value = value.ok_or_else(|| invalid_input(format!("Missing value for {}", flag)))?;
+    value
+        .parse()
+        .map_err(|_| invalid_input(format!("Invalid value '{}' for {}", value, flag)))
+}
+
+/// Parses a `channel=value` flag value, e.g. `card=500`
+fn parse_channel_value<T: FromStr>(
+    flag: &str,
+    value: Option<&String>,
+) -> Result<(Channel, T), io::Error> {
+    let value: String = parse_flag_value(flag, value)?;
+    let invalid = || invalid_input(format!("Invalid value '{}' for {}", value, flag));
+    let (channel, setting) = value.split_once('=').ok_or_else(invalid)?;
+    Ok((
+        channel.parse().map_err(|_| invalid())?,
+        setting.parse().map_err(|_| invalid())?,
+    ))
+}
+
+/// Applies the rules a `--rules` file sets over the config
+#[cfg(feature = "rules")]
+fn apply_rules_file(file_path: &str, config: &mut EngineConfig) -> Result<(), io::Error> {
+    RulesFile::from_file(file_path)?.apply(config);
+    Ok(())
+}
+
+#[cfg(not(feature = "rules"))]
+fn apply_rules_file(_file_path: &str, _config: &mut EngineConfig) -> Result<(), io::Error> {
+    Err(invalid_input(
+        "--rules needs a build with the rules feature".to_string(),
+    ))
+}
+
+fn parse_gen_args(args: &[String]) -> Result<GenOptions, io::Error> {
+    let scenario = args
+        .first()
+        .ok_or_else(|| invalid_input("Missing scenario for gen".to_string()))?;
+    let scenario = scenario.parse::<Scenario>().map_err(invalid_input)?;
+    let mut gen_options = GenOptions {
+        scenario,
+        records: 1000,
+        clients: 100,
+        seed: None,
+        output: OutputMethod::StdOutput,
+    };
+
+    let mut args_iter = args[1..].iter();
+    while let Some(flag) = args_iter.next() {
+        match flag.as_str() {
+            "--records" => gen_options.records = parse_flag_value(flag, args_iter.next())?,
+            "--clients" => gen_options.clients = parse_flag_value(flag, args_iter.next())?,
+            "--seed" => gen_options.seed = Some(parse_flag_value(flag, args_iter.next())?),
+            "--output" => {
+                gen_options.output =
+                    OutputMethod::from_arg(parse_flag_value(flag, args_iter.next())?)
+            }
+            _ => return Err(invalid_input(format!("Unknown gen argument '{}'", flag))),
+        }
+    }
+    Ok(gen_options)
+}
+
+fn parse_soak_args(args: &[String]) -> Result<SoakOptions, io::Error> {
+    let scenario = args
+        .first()
+        .ok_or_else(|| invalid_input("Missing scenario for soak".to_string()))?;
+    let scenario = scenario.parse::<Scenario>().map_err(invalid_input)?;
+    let mut soak_options = SoakOptions {
+        scenario,
+        clients: 1000,
+        duration: Duration::from_secs(60),
+        report_every: Duration::from_secs(5),
+        seed: None,
+        output: OutputMethod::StdOutput,
+    };
+
+    let mut args_iter = args[1..].iter();
+    while let Some(flag) = args_iter.next() {
+        match flag.as_str() {
+            "--clients" => soak_options.clients = parse_flag_value(flag, args_iter.next())?,
+            "--seed" => soak_options.seed = Some(parse_flag_value(flag, args_iter.next())?),
+            "--duration" => {
+                soak_options.duration =
+                    Duration::from_secs(parse_flag_value(flag, args_iter.next())?)
+            }
+            "--report-every" => {
+                soak_options.report_every =
+                    Duration::from_secs(parse_flag_value(flag, args_iter.next())?)
+            }
+            "--output" => {
+                soak_options.output =
+                    OutputMethod::from_arg(parse_flag_value(flag, args_iter.next())?)
+            }
+            _ => return Err(invalid_input(format!("Unknown soak argument '{}'", flag))),
+        }
+    }
+    if soak_options.report_every.is_zero() {
+        return Err(invalid_input(
+            "--report-every must be at least 1".to_string(),
+        ));
+    }
+    Ok(soak_options)
+}
+
+/// Reads client ids from a file, one per line
+/// Blank lines & a leading `client` header are ignored so account outputs can be reused
+pub fn read_client_ids(file_path: &str) -> Result<HashSet<u16>, io::Error> {
+    let contents = std::fs::read_to_string(file_path)?;
+    let mut client_ids = HashSet::new();
+    for (indx, line) in contents.lines().enumerate() {
+        let id = line.split(',').next().unwrap_or("").trim();
+        if id.is_empty() || (indx == 0 && id == "client") {
+            continue;
+        }
+        let id = id
+            .parse()
+            .map_err(|_| invalid_input(format!("Invalid client id '{}' in {}", id, file_path)))?;
+        client_ids.insert(id);
+    }
+    Ok(client_ids)
+}
+
+fn parse_tail_args(args: &[String]) -> Result<TailOptions, io::Error> {
+    let input_file = args
+        .first()
+        .ok_or_else(|| invalid_input("Missing input file for tail".to_string()))?;
+    let mut idle_exit = None;
+    let mut process_args = vec![];
+    let mut args_iter = args[1..].iter();
+    while let Some(arg) = args_iter.next() {
+        match arg.as_str() {
+            "--idle-exit" => {
+                idle_exit = Some(Duration::from_secs(parse_flag_value(
+                    arg,
+                    args_iter.next(),
+                )?))
+            }
+            _ => process_args.push(arg.clone()),
+        }
+    }
+    let cli_options = parse_process_args(input_file, &process_args)?;
+    if cli_options.iso20022 {
+        return Err(invalid_input(
+            "tail doesn't support --iso20022, documents are only read once complete".to_string(),
+        ));
+    }
+    if cli_options.oracle_check {
+        return Err(invalid_input(
+            "tail doesn't support --oracle-check".to_string(),
+        ));
+    }
+    if cli_options.encoding.is_some() {
+        return Err(invalid_input(
+            "tail doesn't support --encoding, appended lines are read as utf-8".to_string(),
+        ));
+    }
+    if cli_options.header != HeaderOptions::default() {
+        return Err(invalid_input(
+            "tail doesn't support --validate-header or --header-alias, columns are read in the standard order"
+                .to_string(),
+        ));
+    }
+    Ok(TailOptions {
+        cli_options,
+        idle_exit,
+    })
+}
+
+fn parse_listen_args(args: &[String]) -> Result<ListenOptions, io::Error> {
+    let socket_path: String = parse_flag_value("--listen-unix", args.first())?;
+    let cli_options = parse_process_args(&socket_path, &args[1..])?;
+    // Records arrive a line at a time as utf-8 text
+    let unsupported = [
+        (cli_options.iso20022, "--iso20022"),
+        (cli_options.encoding.is_some(), "--encoding"),
+        (cli_options.oracle_check, "--oracle-check"),
+        (cli_options.progress, "--progress"),
+        (
+            cli_options.header != HeaderOptions::default(),
+            "--validate-header or --header-alias",
+        ),
+    ];
+    if let Some((_, flag)) = unsupported.iter().find(|(given, _)| *given) {
+        return Err(invalid_input(format!(
+            "--listen-unix doesn't support {}",
+            flag
+        )));
+    }
+    if cli_options.priority_unix.as_ref() == Some(&socket_path) {
+        return Err(invalid_input(
+            "--priority-unix must be another socket than --listen-unix".to_string(),
+        ));
+    }
+    Ok(ListenOptions {
+        cli_options,
+        socket_path,
+    })
+}
+
+fn parse_cluster_args(args: &[String]) -> Result<ClusterOptions, io::Error> {
+    let input_file = args
+        .first()
+        .ok_or_else(|| invalid_input("Missing input file for cluster".to_string()))?;
+    let mut workers = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let mut snapshot_dir = None;
+    let mut snapshot_every = None;
+    let mut process_args = vec![];
+    let mut args_iter = args[1..].iter();
+    while let Some(arg) = args_iter.next() {
+        match arg.as_str() {
+            "--workers" => workers = parse_flag_value(arg, args_iter.next())?,
+            "--cluster-snapshots" => snapshot_dir = Some(parse_flag_value(arg, args_iter.next())?),
+            "--snapshot-every" => snapshot_every = Some(parse_flag_value(arg, args_iter.next())?),
+            _ => process_args.push(arg.clone()),
+        }
+    }
+    if workers == 0 {
+        return Err(invalid_input("--workers must be at least 1".to_string()));
+    }
+    let snapshot_every = match (snapshot_every, &snapshot_dir) {
+        (Some(0), _) => {
+            return Err(invalid_input(
+                "--snapshot-every must be at least 1".to_string(),
+            ))
+        }
+        (Some(_), None) => {
+            return Err(invalid_input(
+                "--snapshot-every requires --cluster-snapshots".to_string(),
+            ))
+        }
+        (Some(every), Some(_)) => every,
+        (None, _) => 100_000,
+    };
+    let cli_options = parse_process_args(input_file, &process_args)?;
+    // Workers share nothing, so files written as records are processed would be clobbered
+    let unsupported = [
+        (cli_options.fixed_width.is_some(), "--fixed-width"),
+        (cli_options.iso20022, "--iso20022"),
+        (cli_options.dead_letter.is_some(), "--dead-letter"),
+        (cli_options.txn_log.is_some(), "--txn-log"),
+        (cli_options.gc_policy.is_some(), "--gc-inactive"),
+        (cli_options.client_stats.is_some(), "--client-stats"),
+        (cli_options.channel_report.is_some(), "--channel-report"),
+        (cli_options.pending_report.is_some(), "--pending-report"),
+        (cli_options.quarantine.is_some(), "--quarantine"),
+        (cli_options.audit_log.is_some(), "--audit-log"),
+        (cli_options.dispute_aging.is_some(), "--dispute-aging"),
+        (cli_options.held_breakdown.is_some(), "--held-breakdown"),
+        (cli_options.dispute_cases.is_some(), "--dispute-cases"),
+        (cli_options.flow_report.is_some(), "--flow-report"),
+        (
+            cli_options.engine_config.timestamp_sanity.is_some(),
+            "--max-future-skew & --max-timestamp-age",
+        ),
+        (cli_options.partner_report.is_some(), "--partner-report"),
+        (cli_options.priority_unix.is_some(), "--priority-unix"),
+        (cli_options.anonymize_map.is_some(), "--anonymize-map"),
+        (cli_options.only_locked, "--only-locked"),
+        (cli_options.clock != ClockSource::Wall, "--clock"),
+        (cli_options.shadow.is_some(), "--shadow-url"),
+        (cli_options.save_snapshot.is_some(), "--save-snapshot"),
+        (cli_options.restore_snapshot.is_some(), "--restore-snapshot"),
+        (cli_options.initial_state.is_some(), "--initial-state"),
+        (cli_options.onboarding.is_some(), "--onboarding"),
+        (
+            cli_options.engine_config.require_onboarding,
+            "--require-onboarding",
+        ),
+        (cli_options.balances_series.is_some(), "--balances-series"),
+        (cli_options.oracle_check, "--oracle-check"),
+        (cli_options.assert_conservation, "--assert-conservation"),
+        (cli_options.script.is_some(), "--script"),
+        (!cli_options.webhook.urls.is_empty(), "--webhook"),
+        (!cli_options.alerts.rules.is_empty(), "--alert"),
+        (cli_options.statsd.is_some(), "--statsd"),
+        (cli_options.latency_budget.is_some(), "--latency-budget-us"),
+        (
+            cli_options.engine_config.txn_registry.is_some(),
+            "--txn-registry",
+        ),
+        (
+            cli_options.engine_config.duplicate_check != DuplicateCheck::Exact,
+            "--dedup-store",
+        ),
+        // The coordinator's txn ids are never recycled
+        (cli_options.engine_config.id_epoch.is_some(), "--id-epoch"),
+    ];
+    if let Some((_, flag)) = unsupported.iter().find(|(set, _)| *set) {
+        return Err(invalid_input(format!("cluster doesn't support {}", flag)));
+    }
+    Ok(ClusterOptions {
+        cli_options,
+        workers,
+        snapshot_dir,
+        snapshot_every,
+    })
+}
+
+fn parse_replay_args(args: &[String]) -> Result<ReplayOptions, io::Error> {
+    if args.len() < 2 {
+        return Err(invalid_input(
+            "replay-dlq requires a dead letter file & the input file it came from".to_string(),
+        ));
+    }
+    let mut report = "dlq_replay_report.csv".to_string();
+    let mut process_args = vec![];
+    let mut args_iter = args[2..].iter();
+    while let Some(arg) = args_iter.next() {
+        match arg.as_str() {
+            "--replay-report" => report = parse_flag_value(arg, args_iter.next())?,
+            _ => process_args.push(arg.clone()),
+        }
+    }
+    let cli_options = parse_process_args(&args[1], &process_args)?;
+    if cli_options.iso20022 {
+        return Err(invalid_input(
+            "replay-dlq doesn't support --iso20022, entries can't be read apart from their statement"
+                .to_string(),
+        ));
+    }
+    if cli_options.oracle_check {
+        return Err(invalid_input(
+            "replay-dlq doesn't support --oracle-check".to_string(),
+        ));
+    }
+    if cli_options.dead_letter.as_ref() == Some(&args[0]) {
+        return Err(invalid_input(
+            "--dead-letter must differ from the dead letter file being replayed".to_string(),
+        ));
+    }
+    // The input is processed again, the registry already holds its ids so every record would be a duplicate
+    if cli_options.engine_config.txn_registry.is_some() {
+        return Err(invalid_input(
+            "replay-dlq doesn't support --txn-registry".to_string(),
+        ));
+    }
+    Ok(ReplayOptions {
+        dead_letters: args[0].clone(),
+        cli_options,
+        report,
+    })
+}
+
+fn parse_diff_args(args: &[String]) -> Result<DiffOptions, io::Error> {
+    if args.len() < 2 {
+        return Err(invalid_input(
+            "diff requires a before & after accounts file".to_string(),
+        ));
+    }
+    let mut diff_options = DiffOptions {
+        before: args[0].clone(),
+        after: args[1].clone(),
+        output: OutputMethod::StdOutput,
+    };
+    let mut args_iter = args[2..].iter();
+    while let Some(flag) = args_iter.next() {
+        match flag.as_str() {
+            "--output" => {
+                diff_options.output =
+                    OutputMethod::from_arg(parse_flag_value(flag, args_iter.next())?)
+            }
+            _ => return Err(invalid_input(format!("Unknown diff argument '{}'", flag))),
+        }
+    }
+    Ok(diff_options)
+}
+
+fn parse_explore_args(args: &[String]) -> Result<ExploreOptions, io::Error> {
+    let mut explore_options = ExploreOptions {
+        txn_log: args
+            .first()
+            .ok_or_else(|| invalid_input("explore requires a txn log".to_string()))?
+            .clone(),
+        engine_config: EngineConfig::default(),
+    };
+    let mut args_iter = args[1..].iter();
+    while let Some(flag) = args_iter.next() {
+        match flag.as_str() {
+            "--rules" => {
+                let file_path: String = parse_flag_value(flag, args_iter.next())?;
+                apply_rules_file(&file_path, &mut explore_options.engine_config)?;
+            }
+            _ => {
+                return Err(invalid_input(format!(
+                    "Unknown explore argument '{}'",
+                    flag
+                )))
+            }
+        }
+    }
+    Ok(explore_options)
+}
+
+fn parse_policy_replay_args(args: &[String]) -> Result<PolicyReplayOptions, io::Error> {
+    let txn_log = args
+        .first()
+        .ok_or_else(|| invalid_input("replay requires a txn log".to_string()))?
+        .clone();
+    let mut engine_config = EngineConfig::default();
+    let mut overrides: Vec<String> = vec![];
+    let mut output = OutputMethod::StdOutput;
+    let mut args_iter = args[1..].iter();
+    while let Some(flag) = args_iter.next() {
+        match flag.as_str() {
+            "--rules" => {
+                let file_path: String = parse_flag_value(flag, args_iter.next())?;
+                apply_rules_file(&file_path, &mut engine_config)?;
+            }
+            "--override" => overrides.push(parse_flag_value(flag, args_iter.next())?),
+            "--output" => {
+                output = OutputMethod::from_arg(parse_flag_value(flag, args_iter.next())?)
+            }
+            _ => return Err(invalid_input(format!("Unknown replay argument '{}'", flag))),
+        }
+    }
+    if overrides.is_empty() {
+        return Err(invalid_input(
+            "replay requires at least one --override".to_string(),
+        ));
+    }
+    // Overrides apply over the rules file whichever order they were given in
+    let mut override_config = engine_config.clone();
+    for text in &overrides {
+        override_config
+            .apply_override(text)
+            .map_err(invalid_input)?;
+    }
+    Ok(PolicyReplayOptions {
+        txn_log,
+        engine_config,
+        override_config,
+        output,
+    })
+}
+
+fn parse_reconcile_args(args: &[String]) -> Result<ReconcileOptions, io::Error> {
+    if args.len() < 2 {
+        return Err(invalid_input(
+            "reconcile requires a txn log & a bank statement".to_string(),
+        ));
+    }
+    let mut reconcile_options = ReconcileOptions {
+        txn_log: args[0].clone(),
+        bank_statement: args[1].clone(),
+        reference_column: "reference".to_string(),
+        amount_column: "amount".to_string(),
+        output: OutputMethod::StdOutput,
+    };
+    let mut args_iter = args[2..].iter();
+    while let Some(flag) = args_iter.next() {
+        match flag.as_str() {
+            "--reference-column" => {
+                reconcile_options.reference_column = parse_flag_value(flag, args_iter.next())?
+            }
+            "--amount-column" => {
+                reconcile_options.amount_column = parse_flag_value(flag, args_iter.next())?
+            }
+            "--output" => {
+                reconcile_options.output =
+                    OutputMethod::from_arg(parse_flag_value(flag, args_iter.next())?)
+            }
+            _ => {
+                return Err(invalid_input(format!(
+                    "Unknown reconcile argument '{}'",
+                    flag
+                )))
+            }
+        }
+    }
+    Ok(reconcile_options)
+}
+
+fn parse_trim_log_args(args: &[String]) -> Result<TrimLogOptions, io::Error> {
+    let txn_log = args
+        .first()
+        .ok_or_else(|| invalid_input("trim-log requires a txn log".to_string()))?
+        .clone();
+    let mut before: Option<u64> = None;
+    let mut trim_options = TrimLogOptions {
+        txn_log,
+        before: 0,
+        engine_config: EngineConfig::default(),
+        snapshot_dir: None,
+        snapshot_shards: 4,
+        output: None,
+    };
+    let mut args_iter = args[1..].iter();
+    while let Some(flag) = args_iter.next() {
+        match flag.as_str() {
+            "--before" => before = Some(parse_flag_value(flag, args_iter.next())?),
+            "--rules" => {
+                let file_path: String = parse_flag_value(flag, args_iter.next())?;
+                apply_rules_file(&file_path, &mut trim_options.engine_config)?;
+            }
+            "--snapshot-dir" => {
+                trim_options.snapshot_dir = Some(parse_flag_value(flag, args_iter.next())?)
+            }
+            "--snapshot-shards" => {
+                trim_options.snapshot_shards = parse_flag_value(flag, args_iter.next())?;
+                if trim_options.snapshot_shards == 0 {
+                    return Err(invalid_input(
+                        "--snapshot-shards must be at least 1".to_string(),
+                    ));
+                }
+            }
+            "--output" => trim_options.output = Some(parse_flag_value(flag, args_iter.next())?),
+            _ => {
+                return Err(invalid_input(format!(
+                    "Unknown trim-log argument '{}'",
+                    flag
+                )))
+            }
+        }
+    }
+    trim_options.before =
+        before.ok_or_else(|| invalid_input("trim-log requires --before <seq>".to_string()))?;
+    Ok(trim_options)
+}
+
+fn parse_compact_args(args: &[String]) -> Result<CompactOptions, io::Error> {
+    let mut compact_options = CompactOptions {
+        dir: args
+            .first()
+            .ok_or_else(|| invalid_input("compact-snapshots requires a snapshot dir".to_string()))?
+            .clone(),
+        shards: None,
+    };
+    let mut args_iter = args[1..].iter();
+    while let Some(flag) = args_iter.next() {
+        match flag.as_str() {
+            "--snapshot-shards" => {
+                let shards: usize = parse_flag_value(flag, args_iter.next())?;
+                if shards == 0 {
+                    return Err(invalid_input(
+                        "--snapshot-shards must be at least 1".to_string(),
+                    ));
+                }
+                compact_options.shards = Some(shards);
+            }
+            _ => {
+                return Err(invalid_input(format!(
+                    "Unknown compact-snapshots argument '{}'",
+                    flag
+                )))
+            }
+        }
+    }
+    Ok(compact_options)
+}
+
+fn parse_inspect_args(args: &[String]) -> Result<InspectOptions, io::Error> {
+    let mut inspect_options = InspectOptions {
+        input_file: args
+            .first()
+            .ok_or_else(|| invalid_input("inspect requires an input file".to_string()))?
+            .clone(),
+        fixed_width: None,
+        encoding: None,
+        output: OutputMethod::StdOutput,
+    };
+    let mut args_iter = args[1..].iter();
+    while let Some(flag) = args_iter.next() {
+        match flag.as_str() {
+            "--fixed-width" => {
+                let file_path: String = parse_flag_value(flag, args_iter.next())?;
+                inspect_options.fixed_width = Some(FixedWidthSpec::from_file(&file_path)?);
+            }
+            "--encoding" => {
+                inspect_options.encoding = Some(parse_flag_value(flag, args_iter.next())?)
+            }
+            "--output" => {
+                inspect_options.output =
+                    OutputMethod::from_arg(parse_flag_value(flag, args_iter.next())?)
+            }
+            _ => {
+                return Err(invalid_input(format!(
+                    "Unknown inspect argument '{}'",
+                    flag
+                )))
+            }
+        }
+    }
+    Ok(inspect_options)
+}
+
+/// Parses cli arguments, not including the binary name
+pub fn parse_cli_args(args: &[String]) -> Result<CliCommand, io::Error> {
+    match args.first().map(String::as_str) {
+        Some("gen") => Ok(CliCommand::Gen(parse_gen_args(&args[1..])?)),
+        Some("tail") => Ok(CliCommand::Tail(parse_tail_args(&args[1..])?)),
+        Some("diff") => Ok(CliCommand::Diff(parse_diff_args(&args[1..])?)),
+        Some("inspect") => Ok(CliCommand::Inspect(parse_inspect_args(&args[1..])?)),
+        Some("replay-dlq") => Ok(CliCommand::ReplayDlq(parse_replay_args(&args[1..])?)),
+        Some("soak") => Ok(CliCommand::Soak(parse_soak_args(&args[1..])?)),
+        Some("cluster") => Ok(CliCommand::Cluster(parse_cluster_args(&args[1..])?)),
+        Some("--listen-unix") => Ok(CliCommand::Listen(parse_listen_args(&args[1..])?)),
+        Some("explore") => Ok(CliCommand::Explore(parse_explore_args(&args[1..])?)),
+        Some("replay") => Ok(CliCommand::PolicyReplay(parse_policy_replay_args(
+            &args[1..],
+        )?)),
+        Some("trim-log") => Ok(CliCommand::TrimLog(parse_trim_log_args(&args[1..])?)),
+        Some("reconcile") => Ok(CliCommand::Reconcile(parse_reconcile_args(&args[1..])?)),
+        Some("compact-snapshots") => Ok(CliCommand::CompactSnapshots(parse_compact_args(
+            &args[1..],
+        )?)),
+        Some(input_file) => Ok(CliCommand::Process(parse_process_args(
+            input_file,
+            &args[1..],
+        )?)),
+        None => Err(invalid_input("Missing Input File".to_string())),
+    }
+}
+
+pub fn parse_cli() -> Result<CliCommand, io::Error> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    parse_cli_args(&args)
 }
 
 /// A transaction which adds or removes an amount
@@ -71,22 +1579,135 @@ pub struct RawInputTxn {
     acnt_id: u16,
     #[serde(rename = "tx")]
     txn_id: u32,
-    #[serde(deserialize_with = "csv::invalid_option")]
-    amount: Option<f64>,
+    /// Kept as text so a malformed amount can be told apart from a missing one
+    amount: Option<String>,
+    /// Optional trailing column, kept on deposits & withdrawals & noted on dispute cases
+    #[serde(default)]
+    memo: Option<String>,
+    /// Optional `card`, `ach`, or `wire` column, kept on deposits & withdrawals
+    #[serde(default)]
+    channel: Option<String>,
+    /// Optional unix seconds or RFC 3339 column, only read by reports bucketing records by time
+    #[serde(default)]
+    timestamp: Option<String>,
+    /// Optional column naming the partner who sent the record, only read by the partner report
+    #[serde(default)]
+    partner: Option<String>,
 }
 
 impl RawInputTxn {
+    pub(crate) fn txn_type(&self) -> &str {
+        &self.txn_type
+    }
+
+    pub(crate) fn memo(&self) -> Option<&str> {
+        self.memo.as_deref()
+    }
+
+    /// Partner column, None when the record has none or it's blank
+    pub(crate) fn partner(&self) -> Option<&str> {
+        self.partner
+            .as_deref()
+            .filter(|partner| !partner.is_empty())
+    }
+
+    /// Unix seconds of the timestamp column, None when the record has none
+    pub(crate) fn timestamp(&self) -> Result<Option<i64>, InputTxnErr> {
+        match self.timestamp.as_deref().map(str::trim) {
+            None | Some("") => Ok(None),
+            Some(text) => parse_timestamp(text)
+                .map(Some)
+                .ok_or_else(|| InputTxnErr::MalformedTimestamp(text.to_string())),
+        }
+    }
+
+    #[cfg(feature = "iso20022")]
+    pub(crate) fn new(
+        txn_type: &str,
+        acnt_id: u16,
+        txn_id: u32,
+        amount: Option<String>,
+        memo: Option<String>,
+    ) -> Self {
+        Self {
+            txn_type: txn_type.to_string(),
+            acnt_id,
+            txn_id,
+            amount,
+            memo,
+            channel: None,
+            timestamp: None,
+            partner: None,
+        }
+    }
+
+    /// Rewrites the record as each transform says, in order
+    /// Amounts which aren't numbers are left for `convert_to_txn` to reject
+    pub fn apply_transforms(&mut self, transforms: &[IngestTransform]) -> Result<(), InputTxnErr> {
+        for transform in transforms {
+            match transform {
+                IngestTransform::ScaleAmount(factor) => {
+                    if let Some(amount) = &mut self.amount {
+                        if let Ok(value) = parse_amount(amount) {
+                            *amount = scaled_amount_text(value * factor);
+                        }
+                    }
+                }
+                IngestTransform::RemapClients(lookup) => {
+                    self.acnt_id = *lookup
+                        .get(&self.acnt_id)
+                        .ok_or(InputTxnErr::UnmappedClient(self.acnt_id))?;
+                }
+                IngestTransform::Pseudonymize(pseudonyms) => {
+                    self.acnt_id = pseudonyms.pseudonym(self.acnt_id);
+                }
+                IngestTransform::TruncateAmount(places) => {
+                    if let Some(amount) = &mut self.amount {
+                        if let Some(truncated) = truncated_amount_text(amount, *places) {
+                            *amount = truncated;
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
     pub fn convert_to_txn(self) -> Result<Transaction, InputTxnErr> {
+        self.convert_with_precision(PrecisionPolicy::default())
+    }
+
+    /// Converts the record, applying the policy to amount digits past `PRECISION` places
+    pub fn convert_with_precision(
+        self,
+        policy: PrecisionPolicy,
+    ) -> Result<Transaction, InputTxnErr> {
         let type_str = self.txn_type.as_str();
+        let parse_amount = |amount: &Option<String>| match amount {
+            Some(amount) => Amount::parse(amount, policy).map_err(|e| match e {
+                AmountError::Malformed | AmountError::Overflow => {
+                    InputTxnErr::MalformedAmount(amount.to_string())
+                }
+                AmountError::ExcessPrecision => InputTxnErr::ExcessPrecision(amount.to_string()),
+            }),
+            None => Err(InputTxnErr::MissingAmount),
+        };
         if type_str == "deposit" || type_str == "withdrawal" {
-            if self.amount.is_none() {
-                return Err(InputTxnErr::MissingAmount);
-            }
+            let amount = parse_amount(&self.amount)?;
             let pure_txn = PureTxn {
                 txn_id: self.txn_id,
                 acnt_id: self.acnt_id,
-                amount: get_specified_precision(&self.amount.unwrap(), &(PRECISION as i32)),
-                disputed: false,
+                amount: TxnAmount::new(amount),
+                dispute: DisputeHistory::default(),
+                memo: self.memo,
+                channel: match self.channel {
+                    Some(channel) => Some(
+                        channel
+                            .parse()
+                            .map_err(|_| InputTxnErr::UnknownChannel(channel))?,
+                    ),
+                    None => None,
+                },
             };
             if type_str == "deposit" {
                 return Ok(Transaction::Deposit(pure_txn));
@@ -106,26 +1727,281 @@ impl RawInputTxn {
                 return Ok(Transaction::Resolve(ref_txn));
             }
             return Ok(Transaction::Chargeback(ref_txn));
+        } else if type_str == "refund" {
+            return Ok(Transaction::Refund(RefundTxn {
+                ref_id: self.txn_id,
+                acnt_id: self.acnt_id,
+                amount: TxnAmount::new(parse_amount(&self.amount)?),
+            }));
+        } else if type_str == "release" {
+            return Ok(Transaction::Release(ReleaseTxn {
+                ref_id: self.txn_id,
+                acnt_id: self.acnt_id,
+                amount: TxnAmount::new(parse_amount(&self.amount)?),
+            }));
+        } else if ["hold", "unhold", "approve", "deny"].contains(&type_str) {
+            if self.amount.is_some() {
+                return Err(InputTxnErr::ShouldHaveNoAmount);
+            }
+            let action = match type_str {
+                "hold" => AdminAction::SetHold,
+                "unhold" => AdminAction::ClearHold,
+                "approve" => AdminAction::Approve,
+                _ => AdminAction::Deny,
+            };
+            return Ok(Transaction::Admin(AdminTxn {
+                instr_id: self.txn_id,
+                acnt_id: self.acnt_id,
+                action,
+            }));
         }
         Err(InputTxnErr::UnsupportedType)
     }
 }
 
+/// Parses the amount column, anything other than a finite number is malformed
+fn parse_amount(amount: &str) -> Result<f64, InputTxnErr> {
+    match amount.parse::<f64>() {
+        Ok(value) if value.is_finite() => Ok(value),
+        _ => Err(InputTxnErr::MalformedAmount(amount.to_string())),
+    }
+}
+
+/// Decimal text of a scaled amount, cut short of float artifacts so they can't trip `PrecisionPolicy::Reject`
+fn scaled_amount_text(value: f64) -> String {
+    let text = format!("{:.10}", value);
+    text.trim_end_matches('0').trim_end_matches('.').to_string()
+}
+
+/// Amount text without the places past `places`, cut from the text itself so no float rounding creeps in
+/// Anything but a plain decimal, e.g. `1e-3`, is left for the run's precision policy & `convert_to_txn`
+fn truncated_amount_text(amount: &str, places: usize) -> Option<String> {
+    let digits = amount.strip_prefix('-').unwrap_or(amount);
+    let (whole, frac) = digits.split_once('.').unwrap_or((digits, ""));
+    let all_digits = |part: &str| part.bytes().all(|b| b.is_ascii_digit());
+    if whole.is_empty() || !all_digits(whole) || !all_digits(frac) {
+        return None;
+    }
+    let end = amount.len() - frac.len() + places.min(frac.len());
+    Some(amount[..end].trim_end_matches('.').to_string())
+}
+
 #[derive(PartialEq, Debug)]
 pub enum InputTxnErr {
+    /// A fixed width record's client or tx field isn't a valid id
+    MalformedRecord,
     MissingAmount,
+    /// Amount was given but isn't a number, holds the offending text
+    MalformedAmount(String),
+    /// Amount has more places than `PRECISION` & the run rejects such amounts, holds the offending text
+    ExcessPrecision(String),
     UnsupportedType,
     ShouldHaveNoAmount,
+    /// Channel column holds something other than a known payment rail
+    UnknownChannel(String),
+    /// Client id missing from the lookup of a client remapping transform
+    UnmappedClient(u16),
+    /// Timestamp column is neither unix seconds nor RFC 3339, holds the offending text
+    MalformedTimestamp(String),
+}
+
+/// Position of one field in a fixed width record, in bytes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FixedWidthField {
+    pub start: usize,
+    pub width: usize,
+}
+
+impl FixedWidthField {
+    /// Field text with padding removed, records cut short of the field read as empty
+    fn extract<'a>(&self, line: &'a str) -> &'a str {
+        let end = (self.start + self.width).min(line.len());
+        line.get(self.start..end).unwrap_or("").trim()
+    }
+}
+
+/// Columns every csv input needs
+const REQUIRED_COLUMNS: [&str; 4] = ["type", "client", "tx", "amount"];
+/// Columns csv input may add
+const OPTIONAL_COLUMNS: [&str; 4] = ["memo", "channel", "timestamp", "partner"];
+
+/// How the header row of csv input is read
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HeaderOptions {
+    /// Partner column names & the column each stands for
+    pub aliases: HashMap<String, String>,
+    /// Fail on columns outside the schema & on missing required ones instead of leaving it to deserializing
+    pub validate: bool,
+}
+
+impl HeaderOptions {
+    /// Parses an `<alias>=<column>` pair into the alias map
+    fn add_alias(&mut self, pair: &str) -> Result<(), io::Error> {
+        let (alias, column) = pair.split_once('=').ok_or_else(|| {
+            invalid_input(format!(
+                "Header alias '{}' should be written as <alias>=<column>",
+                pair
+            ))
+        })?;
+        let (alias, column) = (alias.trim(), column.trim());
+        if !REQUIRED_COLUMNS.contains(&column) && !OPTIONAL_COLUMNS.contains(&column) {
+            return Err(invalid_input(format!(
+                "Header alias '{}' names unknown column '{}'",
+                pair, column
+            )));
+        }
+        self.aliases.insert(alias.to_string(), column.to_string());
+        Ok(())
+    }
+
+    /// Header with aliases replaced by the columns they stand for, checked against the schema if asked to
+    /// Errors name every unexpected & missing column, with the position of unexpected ones
+    pub fn apply(&self, headers: &StringRecord) -> Result<StringRecord, io::Error> {
+        let columns: StringRecord = headers
+            .iter()
+            .map(|name| self.aliases.get(name).map_or(name, String::as_str))
+            .collect();
+        if !self.validate {
+            return Ok(columns);
+        }
+        let mut problems = vec![];
+        for (indx, name) in columns.iter().enumerate() {
+            if !REQUIRED_COLUMNS.contains(&name) && !OPTIONAL_COLUMNS.contains(&name) {
+                problems.push(format!("unexpected column '{}' at {}", name, indx + 1));
+            } else if columns.iter().take(indx).any(|earlier| earlier == name) {
+                problems.push(format!("column '{}' repeated at {}", name, indx + 1));
+            }
+        }
+        for required in REQUIRED_COLUMNS {
+            if !columns.iter().any(|name| name == required) {
+                problems.push(format!("missing column '{}'", required));
+            }
+        }
+        if problems.is_empty() {
+            return Ok(columns);
+        }
+        Err(io::Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "Header {:?} doesn't match the schema: {}.  Expected {} with optional {}",
+                headers.iter().collect::<Vec<_>>(),
+                problems.join(", "),
+                REQUIRED_COLUMNS.join(","),
+                OPTIONAL_COLUMNS.join(" & "),
+            ),
+        ))
+    }
+}
+
+/// Layout of fixed width records, read from a column spec file
+/// The spec is a csv of `field,start,width` rows naming `type`, `client`, `tx`, & `amount`,
+/// plus `memo` & `channel` when records carry them
+#[derive(Debug, Clone, PartialEq)]
+pub struct FixedWidthSpec {
+    pub txn_type: FixedWidthField,
+    pub client: FixedWidthField,
+    pub tx: FixedWidthField,
+    pub amount: FixedWidthField,
+    pub memo: Option<FixedWidthField>,
+    pub channel: Option<FixedWidthField>,
+}
+
+impl FixedWidthSpec {
+    pub fn from_file(file_path: &str) -> Result<Self, io::Error> {
+        let mut rdr = ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_path(file_path)
+            .map_err(io::Error::other)?;
+        let (mut txn_type, mut client, mut tx, mut amount) = (None, None, None, None);
+        let (mut memo, mut channel) = (None, None);
+        for result in rdr.records() {
+            let record = result.map_err(io::Error::other)?;
+            let invalid = || invalid_input(format!("Invalid column spec row {:?}", record));
+            let field = FixedWidthField {
+                start: record
+                    .get(1)
+                    .ok_or_else(invalid)?
+                    .parse()
+                    .map_err(|_| invalid())?,
+                width: record
+                    .get(2)
+                    .ok_or_else(invalid)?
+                    .parse()
+                    .map_err(|_| invalid())?,
+            };
+            match record.get(0) {
+                Some("type") => txn_type = Some(field),
+                Some("client") => client = Some(field),
+                Some("tx") => tx = Some(field),
+                Some("amount") => amount = Some(field),
+                Some("memo") => memo = Some(field),
+                Some("channel") => channel = Some(field),
+                _ => return Err(invalid()),
+            }
+        }
+        match (txn_type, client, tx, amount) {
+            (Some(txn_type), Some(client), Some(tx), Some(amount)) => Ok(Self {
+                txn_type,
+                client,
+                tx,
+                amount,
+                memo,
+                channel,
+            }),
+            _ => Err(invalid_input(format!(
+                "Column spec {} must give type, client, tx, & amount",
+                file_path
+            ))),
+        }
+    }
+
+    /// Cuts a record into the same raw form csv records are read into
+    /// Numeric fields may be zero or space padded, a blank amount is a missing one
+    pub fn decode(&self, line: &str) -> Result<RawInputTxn, InputTxnErr> {
+        let amount = self.amount.extract(line);
+        let memo = self.memo.as_ref().map_or("", |memo| memo.extract(line));
+        let channel = self
+            .channel
+            .as_ref()
+            .map_or("", |channel| channel.extract(line));
+        Ok(RawInputTxn {
+            txn_type: self.txn_type.extract(line).to_lowercase(),
+            acnt_id: self
+                .client
+                .extract(line)
+                .parse()
+                .map_err(|_| InputTxnErr::MalformedRecord)?,
+            txn_id: self
+                .tx
+                .extract(line)
+                .parse()
+                .map_err(|_| InputTxnErr::MalformedRecord)?,
+            amount: (!amount.is_empty()).then(|| amount.to_string()),
+            memo: (!memo.is_empty()).then(|| memo.to_string()),
+            channel: (!channel.is_empty()).then(|| channel.to_string()),
+            timestamp: None,
+            partner: None,
+        })
+    }
 }
 
 pub fn _parse_txns_csv(
     in_file_path: &str,
     has_header: bool,
+) -> Result<Vec<Transaction>, io::Error> {
+    parse_txns_reader(std::fs::File::open(in_file_path)?, has_header)
+}
+
+/// Parses every record from any reader, e.g. an in memory buffer or a network stream
+/// Errors on the first malformed record, amounts are read under the default `PrecisionPolicy`
+pub fn parse_txns_reader<R: io::Read>(
+    reader: R,
+    has_header: bool,
 ) -> Result<Vec<Transaction>, io::Error> {
     let mut rdr = ReaderBuilder::new()
         .trim(Trim::All)
         .has_headers(has_header)
-        .from_path(in_file_path)?;
+        .from_reader(reader);
 
     let mut txn_vec = vec![];
     for result in rdr.deserialize() {
@@ -142,33 +2018,48 @@ pub fn _parse_txns_csv(
 #[cfg(test)]
 mod tests {
     use super::{
-        get_specified_precision, output_accounts_csv, InputTxnErr, RawInputTxn, _parse_txns_csv,
+        _parse_txns_csv, account_header, account_record, output_accounts, output_accounts_checksum,
+        output_accounts_csv, output_txn_log_csv, output_txns_csv, parse_cli_args,
+        parse_txns_reader, write_accounts, write_accounts_csv, AccountColumns, CliCommand,
+        CsvDialect, FixedWidthField, FixedWidthSpec, HeaderOptions, InputTxnErr, OutputMethod,
+        OutputSchema, OutputSink, RawInputTxn, StatusValues,
+    };
+    use crate::amount::{format_amount, Amount, PrecisionPolicy};
+    use crate::amount::{Available, Held, TxnAmount};
+    use crate::generator::Scenario;
+    use crate::payments_engine::config::{
+        AnomalyConfig, ChannelRules, ClientFilter, DedupeWindowConfig, DisputableTxns,
+        DuplicateCheck, EngineConfig, GcPolicy, IdHasher, IdIndex, RiskConfig, SafetyLimits,
+        TimestampSanity, WithdrawnFundsDispute,
     };
+    use crate::payments_engine::stats::AccountActivity;
+    use crate::payments_engine::PaymentsEngine;
     use crate::test::utils::_get_test_output_file;
+    use crate::test_support::{deposit, withdrawal};
     use crate::{
         account::Account,
         test::utils::_get_test_input_file,
-        transaction::{PureTxn, RefTxn, Transaction},
+        transaction::{
+            AdminAction, AdminTxn, Channel, DisputeHistory, DisputeState, PureTxn, RefTxn,
+            SequencedTxn, Transaction,
+        },
     };
-    use csv::ReaderBuilder;
+    use csv::{ReaderBuilder, StringRecord, Trim};
+    use std::collections::{BTreeMap, HashMap, HashSet};
+    use std::time::Duration;
 
     #[test]
     fn tst_parse_txns_csv() {
         let f = _get_test_input_file("no_header.csv");
         let txns = _parse_txns_csv(f.as_str(), false).unwrap();
         assert_eq!(txns.len(), 1);
-        let deposit = Transaction::Deposit(PureTxn {
-            txn_id: 1,
-            acnt_id: 1,
-            amount: 10.0,
-            disputed: false,
-        });
-        assert_eq!(txns[0], deposit);
+        let first = deposit(1, 1, 10.0);
+        assert_eq!(txns[0], first);
 
         let f = _get_test_input_file("simple.csv");
         let txns = _parse_txns_csv(f.as_str(), true).unwrap();
         assert_eq!(txns.len(), 1);
-        assert_eq!(txns[0], deposit);
+        assert_eq!(txns[0], first);
 
         let f = _get_test_input_file("dep_disp_res.csv");
         let txns = _parse_txns_csv(f.as_str(), true).unwrap();
@@ -181,26 +2072,220 @@ mod tests {
             ref_id: 1,
             acnt_id: 1,
         });
-        assert_eq!(txns[0], deposit);
+        assert_eq!(txns[0], first);
         assert_eq!(txns[1], dispute);
         assert_eq!(txns[2], resolve);
 
-        let deposit = Transaction::Deposit(PureTxn {
-            txn_id: 1,
-            acnt_id: 1,
-            amount: 0.1234,
-            disputed: false,
-        });
-
         let f = _get_test_input_file("decimal_precision.csv");
         let txns = _parse_txns_csv(f.as_str(), true).unwrap();
-        assert_eq!(txns[0], deposit, "Should have dropped to 4 decimal places");
+        assert_eq!(
+            txns[0],
+            deposit(1, 1, 0.1234),
+            "Should have dropped to 4 decimal places"
+        );
+    }
+
+    #[test]
+    fn tst_parse_txns_reader() {
+        let data = "type, client, tx, amount\ndeposit, 1, 1, 10.0\nwithdrawal, 1, 2, 2.5\n";
+        let txns = parse_txns_reader(data.as_bytes(), true).unwrap();
+        assert_eq!(txns.len(), 2);
+        assert_eq!(txns[1], withdrawal(1, 2, 2.5));
+        assert!(parse_txns_reader("deposit, 1, 1,\n".as_bytes(), false).is_err());
+
+        let data = "type,client,tx,amount,memo\ndeposit,1,1,10.0,INV-0042\ndispute,1,1,,\n";
+        let txns = parse_txns_reader(data.as_bytes(), true).unwrap();
+        assert!(
+            matches!(&txns[0], Transaction::Deposit(p_txn) if p_txn.memo.as_deref() == Some("INV-0042"))
+        );
+
+        let data = "type,client,tx,amount,channel\ndeposit,1,1,10.0,wire\ndeposit,1,2,1.0,\n";
+        let txns = parse_txns_reader(data.as_bytes(), true).unwrap();
+        assert!(
+            matches!(&txns[0], Transaction::Deposit(p_txn) if p_txn.channel == Some(Channel::Wire))
+        );
+        assert!(matches!(&txns[1], Transaction::Deposit(p_txn) if p_txn.channel.is_none()));
+        assert!(parse_txns_reader(
+            "type,client,tx,amount,channel\ndeposit,1,1,1.0,fax\n".as_bytes(),
+            true
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn tst_header_options() {
+        let header = |names: &[&str]| names.iter().collect::<StringRecord>();
+        let mut options = HeaderOptions::default();
+        let partner = header(&["kind", "client", "txn_id", "amount", "note"]);
+        // Without validation unknown columns are left for deserializing to ignore
+        assert!(options.apply(&partner).is_ok());
+
+        options.validate = true;
+        assert!(options
+            .apply(&header(&["type", "client", "tx", "amount", "channel"]))
+            .is_ok());
+        let e = options.apply(&partner).unwrap_err().to_string();
+        assert!(e.contains("unexpected column 'kind' at 1"), "{}", e);
+        assert!(e.contains("unexpected column 'note' at 5"), "{}", e);
+        assert!(e.contains("missing column 'type'"), "{}", e);
+        assert!(e.contains("missing column 'tx'"), "{}", e);
+        let e = options
+            .apply(&header(&["type", "client", "tx", "tx", "amount"]))
+            .unwrap_err()
+            .to_string();
+        assert!(e.contains("column 'tx' repeated at 4"), "{}", e);
+
+        for pair in ["kind=type", "txn_id=tx", "note=memo"] {
+            assert!(options.add_alias(pair).is_ok());
+        }
+        assert!(options.add_alias("ref=reference").is_err());
+        assert!(options.add_alias("ref").is_err());
+        assert_eq!(
+            options.apply(&partner).unwrap(),
+            header(&["type", "client", "tx", "amount", "memo"])
+        );
+
+        let f_input = _get_test_output_file("tst_header_options_input.csv");
+        std::fs::write(
+            &f_input,
+            "kind,client,txn_id,amount
+deposit,1,1,2.5
+",
+        )
+        .unwrap();
+        let f_output = _get_test_output_file("tst_header_options.csv");
+        let run = |extra: &[&str]| {
+            let mut args = vec![f_input.as_str(), "--output", f_output.as_str()];
+            args.extend(extra);
+            match parse_cli_args(&to_args(&args)) {
+                Ok(CliCommand::Process(cli_options)) => {
+                    PaymentsEngine::new().streaming_execute(&cli_options)
+                }
+                _ => panic!("Should parse as process command"),
+            }
+        };
+        assert!(run(&["--validate-header"]).is_err());
+        assert!(run(&[
+            "--validate-header",
+            "--header-alias",
+            "kind=type",
+            "--header-alias",
+            "txn_id=tx"
+        ])
+        .is_ok());
+        assert_eq!(
+            std::fs::read_to_string(&f_output).unwrap(),
+            "client,available,held,total,locked
+1,2.5000,0.0000,2.5000,false
+"
+        );
+        assert!(parse_cli_args(&to_args(&["tail", "t.csv", "--validate-header"])).is_err());
+    }
+
+    #[test]
+    fn tst_convert_with_precision() {
+        let deposit = |amount: &str| RawInputTxn {
+            txn_type: "deposit".to_string(),
+            acnt_id: 1,
+            txn_id: 1,
+            amount: Some(amount.to_string()),
+            memo: None,
+            channel: None,
+            timestamp: None,
+            partner: None,
+        };
+        let amount_of = |result: Result<Transaction, InputTxnErr>| match result {
+            Ok(Transaction::Deposit(p_txn)) => Ok(p_txn.amount.to_f64()),
+            Ok(_) => panic!("Should convert to a deposit"),
+            Err(e) => Err(e),
+        };
+        assert_eq!(amount_of(deposit("0.12345").convert_to_txn()), Ok(0.1234));
+        assert_eq!(amount_of(deposit("0.29").convert_to_txn()), Ok(0.29));
+        assert_eq!(
+            amount_of(deposit("0.12345").convert_with_precision(PrecisionPolicy::Round)),
+            Ok(0.1235)
+        );
+        assert_eq!(
+            amount_of(deposit("0.12345").convert_with_precision(PrecisionPolicy::Reject)),
+            Err(InputTxnErr::ExcessPrecision("0.12345".to_string()))
+        );
+        assert_eq!(
+            amount_of(deposit("0.1230").convert_with_precision(PrecisionPolicy::Reject)),
+            Ok(0.123)
+        );
+
+        let args: Vec<String> = ["in.csv", "--precision", "reject"]
+            .iter()
+            .map(|a| a.to_string())
+            .collect();
+        match parse_cli_args(&args) {
+            Ok(CliCommand::Process(cli_options)) => {
+                assert_eq!(cli_options.precision, PrecisionPolicy::Reject)
+            }
+            _ => panic!("Should parse as process command"),
+        }
+        let args: Vec<String> = ["in.csv", "--precision", "floor"]
+            .iter()
+            .map(|a| a.to_string())
+            .collect();
+        assert!(parse_cli_args(&args).is_err());
     }
 
     #[test]
-    fn tst_get_specified_precision() {
-        let val = 0.12345;
-        assert_eq!(0.1234, get_specified_precision(&val, &4));
+    fn tst_negative_precision_and_output() {
+        // Truncation keeps the same magnitude either side of zero
+        let truncated = |s: &str| {
+            Amount::parse(s, PrecisionPolicy::Truncate)
+                .unwrap()
+                .to_f64()
+        };
+        assert_eq!(-0.1234, truncated("-0.12345"));
+        assert_eq!(-0.29, truncated("-0.29"));
+        assert_eq!(-2.0, truncated("-2.0"));
+        assert_eq!(0.0, truncated("-0.00009"));
+
+        let account = |id, available, held| {
+            Account::with_balances(id, Available::from_f64(available), Held::from_f64(held))
+        };
+        let accounts = vec![
+            account(1, -2.00005, 0.0),
+            account(2, -0.00004, 0.00001),
+            account(3, -10.0, 4.5),
+        ];
+        let mut wtr = csv::Writer::from_writer(vec![]);
+        write_accounts(
+            &mut wtr,
+            &accounts,
+            &AccountColumns::default(),
+            &CsvDialect::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(wtr.into_inner().unwrap()).unwrap(),
+            "client,available,held,total,locked\n\
+             1,-2.0001,0.0000,-2.0001,false\n\
+             2,0.0000,0.0000,0.0000,false\n\
+             3,-10.0000,4.5000,-5.5000,false\n"
+        );
+
+        let f = _get_test_output_file("tst_negative_output.csv");
+        output_accounts_csv(
+            &accounts,
+            &f,
+            &AccountColumns::default(),
+            &CsvDialect::default(),
+        )
+        .unwrap();
+        assert!(output_accounts_checksum(&accounts, &f).is_ok());
+        let checksum = std::fs::read_to_string(format!("{}.checksum", f)).unwrap();
+        assert!(
+            checksum
+                .lines()
+                .nth(1)
+                .unwrap()
+                .starts_with("3,-12.0001,4.5000,-7.5001,"),
+            "Sums should match the rounded negative columns"
+        );
     }
 
     #[test]
@@ -209,7 +2294,11 @@ mod tests {
             txn_type: "unsupportedtype".to_string(),
             acnt_id: 1,
             txn_id: 1,
-            amount: Some(10.0),
+            amount: Some("10.0".to_string()),
+            memo: None,
+            channel: None,
+            timestamp: None,
+            partner: None,
         };
         match in_txn.convert_to_txn() {
             Ok(_) => panic!("Should error"),
@@ -220,7 +2309,11 @@ mod tests {
             txn_type: "dispute".to_string(),
             acnt_id: 1,
             txn_id: 1,
-            amount: Some(10.0),
+            amount: Some("10.0".to_string()),
+            memo: None,
+            channel: None,
+            timestamp: None,
+            partner: None,
         };
         match in_txn.convert_to_txn() {
             Ok(_) => panic!("Should error"),
@@ -232,17 +2325,42 @@ mod tests {
             acnt_id: 1,
             txn_id: 1,
             amount: None,
+            memo: None,
+            channel: None,
+            timestamp: None,
+            partner: None,
         };
         match in_txn.convert_to_txn() {
             Ok(_) => panic!("Should error"),
             Err(e) => assert_eq!(e, InputTxnErr::MissingAmount),
         }
 
+        for malformed in ["12.3.4", "abc", "NaN"] {
+            let in_txn = RawInputTxn {
+                txn_type: "withdrawal".to_string(),
+                acnt_id: 1,
+                txn_id: 1,
+                amount: Some(malformed.to_string()),
+                memo: None,
+                channel: None,
+                timestamp: None,
+                partner: None,
+            };
+            assert_eq!(
+                in_txn.convert_to_txn(),
+                Err(InputTxnErr::MalformedAmount(malformed.to_string()))
+            );
+        }
+
         let in_txn = RawInputTxn {
             txn_type: "dispute".to_string(),
             acnt_id: 1,
             txn_id: 1,
             amount: None,
+            memo: None,
+            channel: None,
+            timestamp: None,
+            partner: None,
         };
         match in_txn.convert_to_txn() {
             Ok(txn) => assert_eq!(
@@ -256,17 +2374,96 @@ mod tests {
         }
     }
 
+    #[test]
+    fn tst_deserialize_amount() {
+        let data =
+            "type, client, tx, amount\ndeposit, 1, 1, 12.3.4\ndeposit, 1, 2,\ndispute, 1, 1,\n";
+        let mut rdr = ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(data.as_bytes());
+        let results: Vec<_> = rdr
+            .deserialize::<RawInputTxn>()
+            .map(|record| record.unwrap().convert_to_txn())
+            .collect();
+        assert_eq!(
+            results[0],
+            Err(InputTxnErr::MalformedAmount("12.3.4".to_string()))
+        );
+        assert_eq!(results[1], Err(InputTxnErr::MissingAmount));
+        assert!(results[2].is_ok());
+    }
+
+    #[test]
+    fn tst_to_admin_transaction() {
+        let in_txn = RawInputTxn {
+            txn_type: "hold".to_string(),
+            acnt_id: 2,
+            txn_id: 7,
+            amount: None,
+            memo: None,
+            channel: None,
+            timestamp: None,
+            partner: None,
+        };
+        assert_eq!(
+            in_txn.convert_to_txn(),
+            Ok(Transaction::Admin(AdminTxn {
+                instr_id: 7,
+                acnt_id: 2,
+                action: AdminAction::SetHold
+            }))
+        );
+
+        let in_txn = RawInputTxn {
+            txn_type: "deny".to_string(),
+            acnt_id: 2,
+            txn_id: 7,
+            amount: None,
+            memo: None,
+            channel: None,
+            timestamp: None,
+            partner: None,
+        };
+        assert_eq!(
+            in_txn.convert_to_txn(),
+            Ok(Transaction::Admin(AdminTxn {
+                instr_id: 7,
+                acnt_id: 2,
+                action: AdminAction::Deny
+            }))
+        );
+
+        let in_txn = RawInputTxn {
+            txn_type: "unhold".to_string(),
+            acnt_id: 2,
+            txn_id: 7,
+            amount: Some("1.0".to_string()),
+            memo: None,
+            channel: None,
+            timestamp: None,
+            partner: None,
+        };
+        assert_eq!(
+            in_txn.convert_to_txn(),
+            Err(InputTxnErr::ShouldHaveNoAmount)
+        );
+    }
+
     #[test]
     fn tst_output_accounts_csv() {
-        let accounts = vec![Account {
-            id: 1,
-            available: 3.0,
-            held: 7.0,
-            frozen: false,
-        }];
+        let accounts = vec![Account::with_balances(
+            1,
+            Available::from_f64(3.0),
+            Held::from_f64(7.0),
+        )];
 
         let f = _get_test_output_file("tst_file_output.csv");
-        let res = output_accounts_csv(&accounts, f.as_str());
+        let res = output_accounts_csv(
+            &accounts,
+            f.as_str(),
+            &AccountColumns::default(),
+            &CsvDialect::default(),
+        );
         assert!(res.is_ok());
 
         let mut rdr = ReaderBuilder::new()
@@ -280,5 +2477,1105 @@ mod tests {
         } else {
             panic!("File should be readable")
         }
+
+        let accounts =
+            vec![
+                Account::with_balances(1, Available::from_f64(3.0), Held::from_f64(7.0))
+                    .with_locks(false, true),
+            ];
+        let f = _get_test_output_file("tst_file_output_extended.csv");
+        assert!(output_accounts_csv(
+            &accounts,
+            f.as_str(),
+            &AccountColumns {
+                lock_reasons: true,
+                activity: None,
+                chargeback_txns: None,
+            },
+            &CsvDialect::default(),
+        )
+        .is_ok());
+        let mut rdr = ReaderBuilder::new().from_path(f.as_str()).unwrap();
+        assert_eq!(
+            rdr.headers().unwrap(),
+            vec![
+                "client",
+                "available",
+                "held",
+                "total",
+                "locked",
+                "locked_by_chargeback",
+                "admin_hold"
+            ]
+        );
+        let record = rdr.records().next().unwrap().unwrap();
+        assert_eq!(
+            record,
+            vec!["1", "3.0000", "7.0000", "10.0000", "true", "false", "true"]
+        );
+    }
+
+    #[test]
+    fn tst_output_schema_v2() {
+        let f_input = _get_test_output_file("tst_output_schema_v2_input.csv");
+        std::fs::write(
+            &f_input,
+            "type, client, tx, amount\n\
+             deposit, 1, 1, 5.0\n\
+             deposit, 1, 2, 3.0\n\
+             dispute, 1, 1,\n\
+             chargeback, 1, 1,\n\
+             deposit, 2, 3, 4.0\n\
+             hold, 2, 50,\n",
+        )
+        .unwrap();
+        let f_output = _get_test_output_file("tst_output_schema_v2.csv");
+        let args: Vec<String> = [&f_input, "--output-schema", "v2", "--output", &f_output]
+            .iter()
+            .map(|a| a.to_string())
+            .collect();
+        let cli_options = match parse_cli_args(&args) {
+            Ok(CliCommand::Process(cli_options)) => cli_options,
+            _ => panic!("Should parse as process command"),
+        };
+        assert_eq!(cli_options.output_schema, OutputSchema::V2);
+        let mut payments_engine = PaymentsEngine::new();
+        assert!(payments_engine.streaming_execute(&cli_options).is_ok());
+        assert_eq!(
+            std::fs::read_to_string(&f_output).unwrap(),
+            "client,available,held,total,locked,status,disputes,chargebacks,last_txn_id\n\
+             1,3.0000,0.0000,3.0000,true,charged_back,1,1,2\n\
+             2,4.0000,0.0000,4.0000,true,on_hold,0,0,3\n"
+        );
+        assert!(parse_cli_args(&to_args(&["in.csv", "--output-schema", "v3"])).is_err());
+    }
+
+    #[test]
+    fn tst_only_locked() {
+        let f_input = _get_test_output_file("tst_only_locked_input.csv");
+        std::fs::write(
+            &f_input,
+            "type, client, tx, amount\n\
+             deposit, 1, 1, 5.0\n\
+             deposit, 1, 2, 3.0\n\
+             dispute, 1, 2,\n\
+             chargeback, 1, 2,\n\
+             deposit, 2, 3, 4.0\n\
+             hold, 2, 50,\n\
+             deposit, 3, 4, 1.0\n",
+        )
+        .unwrap();
+        let f_output = _get_test_output_file("tst_only_locked.csv");
+        let args = to_args(&[&f_input, "--only-locked", "--output", &f_output]);
+        let cli_options = match parse_cli_args(&args) {
+            Ok(CliCommand::Process(cli_options)) => cli_options,
+            _ => panic!("Should parse as process command"),
+        };
+        let mut payments_engine = PaymentsEngine::new();
+        assert!(payments_engine.streaming_execute(&cli_options).is_ok());
+        assert_eq!(
+            std::fs::read_to_string(&f_output).unwrap(),
+            "client,available,held,total,locked,chargeback_tx\n\
+             1,5.0000,0.0000,5.0000,true,2\n\
+             2,4.0000,0.0000,4.0000,true,\n"
+        );
+        assert!(!payments_engine.is_restricted(3));
+    }
+
+    #[test]
+    fn tst_output_accounts_stdout_purity() {
+        let accounts =
+            vec![
+                Account::with_balances(2, Available::from_f64(-1.5), Held::from_f64(0.25))
+                    .with_locks(true, false),
+            ];
+        // Stdout gets exactly what a file output would, a header & one line per account
+        let mut wtr = csv::Writer::from_writer(vec![]);
+        write_accounts(
+            &mut wtr,
+            &accounts,
+            &AccountColumns::default(),
+            &CsvDialect::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(wtr.into_inner().unwrap()).unwrap(),
+            "client,available,held,total,locked\n2,-1.5000,0.2500,-1.2500,true\n"
+        );
+
+        let args = to_args(&["transactions.csv", "--output", "-"]);
+        match parse_cli_args(&args) {
+            Ok(CliCommand::Process(cli_options)) => assert_eq!(
+                cli_options.output,
+                [OutputSink::csv(OutputMethod::StdOutput)]
+            ),
+            _ => panic!("Should parse as process command"),
+        }
+    }
+
+    #[test]
+    fn tst_output_sinks() {
+        let f_csv = _get_test_output_file("tst_output_sinks.csv");
+        let f_json = _get_test_output_file("tst_output_sinks.jsonl");
+        let args = to_args(&[
+            "transactions.csv",
+            "--output",
+            &f_csv,
+            "--output-json",
+            &f_json,
+            "--output-json",
+            "-",
+            "--checksum",
+        ]);
+        let cli_options = match parse_cli_args(&args) {
+            Ok(CliCommand::Process(cli_options)) => cli_options,
+            _ => panic!("Should parse as process command"),
+        };
+        assert_eq!(
+            cli_options.output,
+            [
+                OutputSink::csv(OutputMethod::Csv(f_csv.clone())),
+                OutputSink::json_lines(OutputMethod::Csv(f_json.clone())),
+                OutputSink::json_lines(OutputMethod::StdOutput),
+            ]
+        );
+
+        let accounts =
+            vec![
+                Account::with_balances(2, Available::from_f64(-1.5), Held::from_f64(0.25))
+                    .with_locks(true, false),
+            ];
+        let columns = AccountColumns {
+            lock_reasons: false,
+            activity: Some(HashMap::new()),
+            chargeback_txns: None,
+        };
+        output_accounts(
+            &accounts,
+            &cli_options.output[..2],
+            &columns,
+            &CsvDialect::default(),
+        );
+        assert_eq!(
+            std::fs::read_to_string(&f_csv).unwrap(),
+            "client,available,held,total,locked,status,disputes,chargebacks,last_txn_id\n\
+             2,-1.5000,0.2500,-1.2500,true,charged_back,0,0,\n"
+        );
+        assert_eq!(
+            std::fs::read_to_string(&f_json).unwrap(),
+            "{\"client\":2,\"available\":\"-1.5000\",\"held\":\"0.2500\",\"total\":\"-1.2500\",\
+             \"locked\":true,\"status\":\"charged_back\",\"disputes\":0,\"chargebacks\":0,\
+             \"last_txn_id\":null}\n"
+        );
+
+        // Two sinks can't share stdout & checksums need a csv file
+        assert!(
+            parse_cli_args(&to_args(&["in.csv", "--output", "-", "--output-json", "-"])).is_err()
+        );
+        assert!(parse_cli_args(&to_args(&[
+            "in.csv",
+            "--output-json",
+            "a.jsonl",
+            "--checksum"
+        ]))
+        .is_err());
+    }
+
+    #[test]
+    fn tst_csv_dialect() {
+        let accounts = vec![Account::with_balances(
+            1,
+            Available::from_f64(1234.5),
+            Held::from_f64(0.25),
+        )];
+        let args = to_args(&[
+            "transactions.csv",
+            "--delimiter",
+            ";",
+            "--decimal-separator",
+            ",",
+            "--crlf",
+            "--bom",
+        ]);
+        let dialect = match parse_cli_args(&args) {
+            Ok(CliCommand::Process(cli_options)) => cli_options.csv_dialect,
+            _ => panic!("Should parse as process command"),
+        };
+        let f = _get_test_output_file("tst_csv_dialect.csv");
+        assert!(output_accounts_csv(&accounts, &f, &AccountColumns::default(), &dialect).is_ok());
+        assert_eq!(
+            std::fs::read_to_string(&f).unwrap(),
+            "\u{feff}client;available;held;total;locked\r\n1;1234,5000;0,2500;1234,7500;false\r\n"
+        );
+
+        // Tab delimited output with a decimal comma needs no quoting
+        let args = to_args(&["in.csv", "--delimiter", "tab", "--decimal-separator", ","]);
+        assert!(parse_cli_args(&args).is_ok());
+        for args in [
+            vec!["in.csv", "--decimal-separator", ","],
+            vec!["in.csv", "--decimal-separator", ";"],
+            vec!["in.csv", "--delimiter", "||"],
+            vec!["in.csv", "--delimiter", "\""],
+        ] {
+            assert!(parse_cli_args(&to_args(&args)).is_err(), "{:?}", args);
+        }
+    }
+
+    /// Fields of an account formatted a `String` each, as accounts were written before the reused record buffer
+    fn format_record(
+        acnt: &Account,
+        columns: &AccountColumns,
+        dialect: &CsvDialect,
+    ) -> Vec<String> {
+        let amount = |amount: f64| {
+            format_amount(amount).replace('.', &dialect.decimal_separator.to_string())
+        };
+        let mut record = vec![
+            format!("{}", acnt.id),
+            amount(acnt.available().to_f64()),
+            amount(acnt.held().to_f64()),
+            amount(acnt.get_total()),
+            format!("{}", acnt.is_locked()),
+        ];
+        if columns.lock_reasons {
+            record.push(format!("{}", acnt.locked_by_chargeback));
+            record.push(format!("{}", acnt.admin_hold));
+        }
+        if let Some(activity) = &columns.activity {
+            let activity = activity.get(&acnt.id).cloned().unwrap_or_default();
+            record.push(acnt.status().as_str().to_string());
+            record.push(activity.disputes.to_string());
+            record.push(activity.chargebacks.to_string());
+            record.push(
+                activity
+                    .last_txn_id
+                    .map_or(String::new(), |id| id.to_string()),
+            );
+        }
+        if let Some(chargeback_txns) = &columns.chargeback_txns {
+            record.push(
+                chargeback_txns
+                    .get(&acnt.id)
+                    .map_or(String::new(), |id| id.to_string()),
+            );
+        }
+        record
+    }
+
+    #[test]
+    fn tst_write_accounts_matches_format_path() {
+        let mut accounts = vec![
+            Account::with_balances(1, Available::from_f64(1234.5), Held::from_f64(0.25)),
+            Account::with_balances(2, Available::from_f64(-3.0001), Held::from_f64(0.0)),
+            Account::with_balances(u16::MAX, Available::from_f64(0.0), Held::from_f64(0.0)),
+            Account::with_balances(
+                40,
+                Available::from_f64(987654321.9999),
+                Held::from_f64(12.0),
+            ),
+        ];
+        accounts[1].locked_by_chargeback = true;
+        accounts[2].admin_hold = true;
+        let activity = HashMap::from([
+            (
+                1,
+                AccountActivity {
+                    disputes: 3,
+                    chargebacks: 0,
+                    last_txn_id: Some(u32::MAX),
+                },
+            ),
+            (
+                2,
+                AccountActivity {
+                    disputes: 1,
+                    chargebacks: 1,
+                    last_txn_id: None,
+                },
+            ),
+        ]);
+        let extended = AccountColumns {
+            lock_reasons: true,
+            activity: Some(activity),
+            chargeback_txns: Some(HashMap::from([(2, 17)])),
+        };
+        let decimal_comma = CsvDialect {
+            delimiter: b';',
+            decimal_separator: ',',
+            ..CsvDialect::default()
+        };
+        for columns in [AccountColumns::default(), extended] {
+            for dialect in [CsvDialect::default(), decimal_comma.clone()] {
+                let mut formatted = dialect.writer(Vec::new()).unwrap();
+                formatted.write_record(account_header(&columns)).unwrap();
+                for acnt in &accounts {
+                    formatted
+                        .write_record(format_record(acnt, &columns, &dialect))
+                        .unwrap();
+                }
+                let formatted = formatted.into_inner().unwrap();
+
+                let mut written = Vec::new();
+                assert!(write_accounts_csv(&mut written, &accounts, &columns, &dialect).is_ok());
+                assert_eq!(
+                    String::from_utf8(written).unwrap(),
+                    String::from_utf8(formatted).unwrap()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn tst_excel_safe_output() {
+        let accounts = [Account::with_balances(
+            1,
+            Available::from_f64(-1.0),
+            Held::from_f64(0.25),
+        )];
+        let args = to_args(&["transactions.csv", "--excel-safe"]);
+        let dialect = match parse_cli_args(&args) {
+            Ok(CliCommand::Process(cli_options)) => cli_options.csv_dialect,
+            _ => panic!("Should parse as process command"),
+        };
+        let mut out = vec![];
+        write_accounts_csv(&mut out, &accounts, &AccountColumns::default(), &dialect).unwrap();
+        // Negative amounts look like formulas but stay numbers, without a `'` prefix
+        assert_eq!(
+            out,
+            b"\"client\",\"available\",\"held\",\"total\",\"locked\"\r\n\
+              \"1\",\"-1.0000\",\"0.2500\",\"-0.7500\",\"false\"\r\n"
+        );
+        assert_eq!(&*dialect.field(b"=SUM(A1)"), b"'=SUM(A1)");
+        assert_eq!(&*dialect.field(b"+1+1"), b"'+1+1");
+        assert_eq!(&*dialect.field(b"@cmd"), b"'@cmd");
+        assert_eq!(&*dialect.field(b"-A1"), b"'-A1");
+        assert_eq!(&*dialect.field(b"-1.5"), b"-1.5");
+        assert_eq!(&*dialect.field(b"client"), b"client");
+
+        // A decimal comma negative amount is still a number
+        let dialect = CsvDialect {
+            delimiter: b';',
+            decimal_separator: ',',
+            ..dialect
+        };
+        let mut out = vec![];
+        write_accounts_csv(&mut out, &accounts, &AccountColumns::default(), &dialect).unwrap();
+        assert_eq!(
+            out,
+            b"\"client\";\"available\";\"held\";\"total\";\"locked\"\r\n\
+              \"1\";\"-1,0000\";\"0,2500\";\"-0,7500\";\"false\"\r\n"
+        );
+    }
+
+    #[test]
+    fn tst_status_values_output() {
+        let account = |id, locked_by_chargeback, admin_hold| {
+            Account::with_balances(id, Available::from_f64(1.0), Held::default())
+                .with_locks(locked_by_chargeback, admin_hold)
+        };
+        let accounts = [
+            account(1, false, false),
+            account(2, true, false),
+            account(3, false, true),
+        ];
+        let columns = AccountColumns {
+            lock_reasons: true,
+            ..AccountColumns::default()
+        };
+        let written = |status_values: &str| {
+            let args = to_args(&["in.csv", "--status-values", status_values]);
+            let dialect = match parse_cli_args(&args) {
+                Ok(CliCommand::Process(cli_options)) => cli_options.csv_dialect,
+                _ => panic!("Should parse as process command"),
+            };
+            let mut out = vec![];
+            write_accounts_csv(&mut out, &accounts, &columns, &dialect).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        assert_eq!(
+            written("LOCKED, ACTIVE"),
+            "client,available,held,total,locked,locked_by_chargeback,admin_hold\n\
+             1,1.0000,0.0000,1.0000,ACTIVE,ACTIVE,ACTIVE\n\
+             2,1.0000,0.0000,1.0000,LOCKED,LOCKED,ACTIVE\n\
+             3,1.0000,0.0000,1.0000,LOCKED,ACTIVE,LOCKED\n"
+        );
+        assert_eq!(
+            written("1,0"),
+            "client,available,held,total,locked,locked_by_chargeback,admin_hold\n\
+             1,1.0000,0.0000,1.0000,0,0,0\n\
+             2,1.0000,0.0000,1.0000,1,1,0\n\
+             3,1.0000,0.0000,1.0000,1,0,1\n"
+        );
+        // Json lines keep booleans whatever the csv writes
+        assert_eq!(account_record(&accounts[1], &columns)[4], "true");
+
+        assert!("LOCKED".parse::<StatusValues>().is_err());
+        for (locked, unlocked) in [("1", "1"), ("", "0"), ("LOCKED", "")] {
+            let status_values = StatusValues {
+                locked: locked.to_string(),
+                unlocked: unlocked.to_string(),
+            };
+            assert!(status_values.validate().is_err(), "{:?}", status_values);
+            let args = to_args(&[
+                "in.csv",
+                "--status-values",
+                &format!("{},{}", locked, unlocked),
+            ]);
+            assert!(parse_cli_args(&args).is_err(), "{:?}", args);
+        }
+        assert!(StatusValues::default().validate().is_ok());
+    }
+
+    fn to_args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn tst_parse_cli_args() {
+        match parse_cli_args(&to_args(&["transactions.csv"])) {
+            Ok(CliCommand::Process(cli_options)) => {
+                assert_eq!(cli_options.input_file, "transactions.csv");
+                assert_eq!(cli_options.engine_config, EngineConfig::default());
+            }
+            _ => panic!("Should parse as process command"),
+        }
+
+        match parse_cli_args(&to_args(&[
+            "transactions.csv",
+            "--dedup-store",
+            "ids.bin",
+            "--dedup-expected",
+            "5000",
+            "--withdrawn-dispute",
+            "cap",
+            "--disputable",
+            "deposits",
+            "--max-open-disputes",
+            "4",
+            "--approval-threshold",
+            "1000",
+            "--txn-registry",
+            "ids.reg",
+            "--expected-records",
+            "250000",
+            "--expected-accounts",
+            "5000",
+            "--id-hasher",
+            "fx",
+            "--id-index",
+            "direct",
+            "--risk-threshold",
+            "8",
+            "--risk-weight",
+            "dispute=3.5",
+            "--anomaly-deviations",
+            "3",
+            "--anomaly-window",
+            "20",
+            "--channel-limit",
+            "card=500",
+            "--channel-fee",
+            "card=0.25",
+            "--channel-dispute-window",
+            "ach=1000",
+            "--max-accounts",
+            "100",
+            "--max-memory-mb",
+            "2",
+            "--reserve-floor",
+            "7=1000",
+            "--reserve-floor",
+            "9=250.5",
+            "--max-future-skew",
+            "300",
+            "--quarantine-bad-timestamps",
+        ])) {
+            Ok(CliCommand::Process(cli_options)) => {
+                assert_eq!(
+                    cli_options.engine_config.duplicate_check,
+                    DuplicateCheck::Probabilistic {
+                        expected_txns: 5000,
+                        false_positive_rate: 0.01,
+                        store_path: "ids.bin".to_string()
+                    }
+                );
+                assert_eq!(
+                    cli_options.engine_config.withdrawn_funds_dispute,
+                    WithdrawnFundsDispute::CapAtAvailable
+                );
+                assert_eq!(
+                    cli_options.engine_config.disputable_txns,
+                    DisputableTxns::Deposits
+                );
+                assert_eq!(cli_options.engine_config.max_open_disputes, Some(4));
+                assert_eq!(
+                    cli_options.engine_config.withdrawal_approval_threshold,
+                    Some(1000.0)
+                );
+                assert_eq!(
+                    cli_options.engine_config.txn_registry,
+                    Some("ids.reg".to_string())
+                );
+                assert_eq!(cli_options.engine_config.expected_records, Some(250000));
+                assert_eq!(cli_options.engine_config.expected_accounts, Some(5000));
+                assert_eq!(cli_options.engine_config.id_hasher, IdHasher::Fx);
+                assert_eq!(cli_options.engine_config.id_index, IdIndex::Direct);
+                assert_eq!(
+                    cli_options.engine_config.risk,
+                    Some(RiskConfig {
+                        dispute: 3.5,
+                        review_threshold: 8.0,
+                        ..RiskConfig::default()
+                    })
+                );
+                assert_eq!(
+                    cli_options.engine_config.anomaly,
+                    Some(AnomalyConfig {
+                        max_deviations: 3.0,
+                        window: 20,
+                        ..AnomalyConfig::default()
+                    })
+                );
+                assert_eq!(
+                    cli_options.engine_config.limits,
+                    SafetyLimits {
+                        max_accounts: Some(100),
+                        max_processed_txns: None,
+                        max_memory_bytes: Some(2 << 20),
+                    }
+                );
+                assert_eq!(
+                    cli_options.engine_config.channel_rules[&Channel::Card],
+                    ChannelRules {
+                        max_amount: Some(500.0),
+                        fee: 0.25,
+                        dispute_window: None,
+                    }
+                );
+                assert_eq!(
+                    cli_options.engine_config.channel_rules[&Channel::Ach].dispute_window,
+                    Some(1000)
+                );
+                assert_eq!(
+                    cli_options.engine_config.reserve_floors,
+                    BTreeMap::from([(7, 1000.0), (9, 250.5)])
+                );
+                assert_eq!(
+                    cli_options.engine_config.timestamp_sanity,
+                    Some(TimestampSanity {
+                        max_future_secs: Some(300),
+                        max_age_secs: None,
+                        quarantine: true,
+                    })
+                );
+            }
+            _ => panic!("Should parse as process command"),
+        }
+
+        match parse_cli_args(&to_args(&[
+            "gen",
+            "chargeback-wave",
+            "--records",
+            "50",
+            "--seed",
+            "42",
+            "--output",
+            "out.csv",
+        ])) {
+            Ok(CliCommand::Gen(gen_options)) => {
+                assert_eq!(gen_options.scenario, Scenario::ChargebackWave);
+                assert_eq!(gen_options.records, 50);
+                assert_eq!(gen_options.seed, Some(42));
+                assert_eq!(gen_options.clients, 100, "Should default clients");
+                assert!(matches!(gen_options.output, OutputMethod::Csv(f) if f == "out.csv"));
+            }
+            _ => panic!("Should parse as gen command"),
+        }
+
+        match parse_cli_args(&to_args(&[
+            "transactions.csv",
+            "--gc-inactive",
+            "1000",
+            "--gc-archive",
+            "archive.csv",
+        ])) {
+            Ok(CliCommand::Process(cli_options)) => assert_eq!(
+                cli_options.gc_policy,
+                Some(GcPolicy {
+                    inactive_for: 1000,
+                    archive_path: Some("archive.csv".to_string())
+                })
+            ),
+            _ => panic!("Should parse as process command"),
+        }
+        assert!(parse_cli_args(&to_args(&["transactions.csv", "--gc-archive", "a.csv"])).is_err());
+
+        match parse_cli_args(&to_args(&[
+            "transactions.csv",
+            "--dedupe-window",
+            "64",
+            "--dedupe-window-secs",
+            "300",
+        ])) {
+            Ok(CliCommand::Process(cli_options)) => assert_eq!(
+                cli_options.engine_config.dedupe_window,
+                Some(DedupeWindowConfig {
+                    max_ids_per_client: 64,
+                    max_age: Some(Duration::from_secs(300))
+                })
+            ),
+            _ => panic!("Should parse as process command"),
+        }
+        assert!(
+            parse_cli_args(&to_args(&["transactions.csv", "--dedupe-window-secs", "1"])).is_err()
+        );
+
+        assert_eq!(
+            parse_cli_args(&to_args(&["transactions.csv", "--progress"])).is_ok(),
+            cfg!(feature = "progress")
+        );
+        match parse_cli_args(&to_args(&[
+            "transactions.csv",
+            "--output",
+            "accounts.csv",
+            "--client-stats",
+            "stats.csv",
+            "--statsd",
+            "127.0.0.1:8125",
+        ])) {
+            Ok(CliCommand::Process(cli_options)) => {
+                assert_eq!(cli_options.statsd, Some("127.0.0.1:8125".to_string()));
+                assert_eq!(cli_options.client_stats, Some("stats.csv".to_string()));
+                assert_eq!(
+                    cli_options.output,
+                    [OutputSink::csv(OutputMethod::Csv(
+                        "accounts.csv".to_string()
+                    ))]
+                );
+            }
+            _ => panic!("Should parse as process command"),
+        }
+
+        match parse_cli_args(&to_args(&[
+            "tail",
+            "live.csv",
+            "--idle-exit",
+            "30",
+            "--extended-output",
+        ])) {
+            Ok(CliCommand::Tail(tail_options)) => {
+                assert_eq!(tail_options.cli_options.input_file, "live.csv");
+                assert_eq!(tail_options.idle_exit, Some(Duration::from_secs(30)));
+                assert!(tail_options.cli_options.extended_output);
+            }
+            _ => panic!("Should parse as tail command"),
+        }
+
+        match parse_cli_args(&to_args(&[
+            "transactions.csv",
+            "--alert",
+            "held>100",
+            "--alert",
+            "total<0",
+        ])) {
+            Ok(CliCommand::Process(cli_options)) => {
+                assert_eq!(cli_options.alerts.rules.len(), 2);
+                assert!(cli_options.alerts.stderr, "Alerts should default to stderr");
+            }
+            _ => panic!("Should parse as process command"),
+        }
+        assert!(parse_cli_args(&to_args(&["transactions.csv", "--alert", "held=1"])).is_err());
+        assert!(parse_cli_args(&to_args(&[
+            "transactions.csv",
+            "--risk-weight",
+            "dispute=1"
+        ]))
+        .is_err());
+        for args in [
+            ["--quarantine", "q.csv"],
+            ["--anomaly-window", "5"],
+            ["--anomaly-deviations", "0"],
+            ["--flow-bucket", "day"],
+            ["--partner-reject-threshold", "0.1"],
+            ["--max-timestamp-age", "-5"],
+            ["--reserve-floor", "1=-3"],
+        ] {
+            let mut args = args.to_vec();
+            args.insert(0, "transactions.csv");
+            assert!(parse_cli_args(&to_args(&args)).is_err());
+        }
+        assert!(
+            parse_cli_args(&to_args(&["transactions.csv", "--alert-file", "a.jsonl"])).is_err()
+        );
+        assert!(parse_cli_args(&to_args(&[
+            "transactions.csv",
+            "--quarantine-bad-timestamps"
+        ]))
+        .is_err());
+        assert!(parse_cli_args(&to_args(&["transactions.csv", "--snapshot-shards", "8"])).is_err());
+        match parse_cli_args(&to_args(&[
+            "transactions.csv",
+            "--save-snapshot",
+            "snap",
+            "--snapshot-shards",
+            "8",
+        ])) {
+            Ok(CliCommand::Process(cli_options)) => assert_eq!(
+                (
+                    cli_options.save_snapshot.as_deref(),
+                    cli_options.snapshot_shards
+                ),
+                (Some("snap"), 8)
+            ),
+            _ => panic!("Should parse as process command"),
+        }
+        assert!(parse_cli_args(&to_args(&["transactions.csv", "--snapshot-deltas"])).is_err());
+        match parse_cli_args(&to_args(&[
+            "compact-snapshots",
+            "snap",
+            "--snapshot-shards",
+            "2",
+        ])) {
+            Ok(CliCommand::CompactSnapshots(compact_options)) => {
+                assert_eq!(
+                    (compact_options.dir.as_str(), compact_options.shards),
+                    ("snap", Some(2))
+                )
+            }
+            _ => panic!("Should parse as compact-snapshots command"),
+        }
+        assert!(parse_cli_args(&to_args(&["transactions.csv", "--series-interval", "8"])).is_err());
+        assert!(parse_cli_args(&to_args(&[
+            "transactions.csv",
+            "--on-duplicate-client",
+            "sum"
+        ]))
+        .is_err());
+        assert!(parse_cli_args(&to_args(&[
+            "transactions.csv",
+            "--oracle-check",
+            "--withdrawn-dispute",
+            "cap"
+        ]))
+        .is_err());
+        assert!(parse_cli_args(&to_args(&[
+            "transactions.csv",
+            "--oracle-check",
+            "--id-hasher",
+            "fx"
+        ]))
+        .is_ok());
+        // Collected accounts leave the oracle's model too
+        assert!(parse_cli_args(&to_args(&[
+            "transactions.csv",
+            "--oracle-check",
+            "--gc-inactive",
+            "100"
+        ]))
+        .is_err());
+        assert!(parse_cli_args(&to_args(&[
+            "transactions.csv",
+            "--initial-state",
+            "accounts.csv",
+            "--on-duplicate-client",
+            "keep"
+        ]))
+        .is_err());
+        match parse_cli_args(&to_args(&[
+            "transactions.csv",
+            "--balances-series",
+            "series.csv",
+            "--series-interval",
+            "50",
+        ])) {
+            Ok(CliCommand::Process(cli_options)) => assert_eq!(
+                (
+                    cli_options.balances_series.as_deref(),
+                    cli_options.series_interval
+                ),
+                (Some("series.csv"), 50)
+            ),
+            _ => panic!("Should parse as process command"),
+        }
+
+        assert!(parse_cli_args(&to_args(&[])).is_err());
+        match parse_cli_args(&to_args(&["diff", "a.csv", "b.csv", "--output", "d.csv"])) {
+            Ok(CliCommand::Diff(diff_options)) => {
+                assert_eq!(diff_options.before, "a.csv");
+                assert_eq!(diff_options.after, "b.csv");
+                assert!(matches!(diff_options.output, OutputMethod::Csv(f) if f == "d.csv"));
+            }
+            _ => panic!("Should parse as diff command"),
+        }
+        match parse_cli_args(&to_args(&[
+            "reconcile",
+            "txn_log.csv",
+            "bank.csv",
+            "--reference-column",
+            "Ref",
+        ])) {
+            Ok(CliCommand::Reconcile(reconcile_options)) => {
+                assert_eq!(reconcile_options.bank_statement, "bank.csv");
+                assert_eq!(reconcile_options.reference_column, "Ref");
+                assert_eq!(reconcile_options.amount_column, "amount");
+                assert!(matches!(reconcile_options.output, OutputMethod::StdOutput));
+            }
+            _ => panic!("Should parse as reconcile command"),
+        }
+        assert!(parse_cli_args(&to_args(&["reconcile", "txn_log.csv"])).is_err());
+        match parse_cli_args(&to_args(&["inspect", "partner.csv"])) {
+            Ok(CliCommand::Inspect(inspect_options)) => {
+                assert_eq!(inspect_options.input_file, "partner.csv");
+                assert!(inspect_options.fixed_width.is_none());
+            }
+            _ => panic!("Should parse as inspect command"),
+        }
+        assert!(parse_cli_args(&to_args(&["inspect"])).is_err());
+
+        let f_only = _get_test_output_file("tst_parse_cli_args_only_clients.txt");
+        std::fs::write(&f_only, "client\n1\n\n3,5.0000\n").unwrap();
+        let f_skip = _get_test_output_file("tst_parse_cli_args_skip_clients.txt");
+        std::fs::write(&f_skip, "3\n").unwrap();
+        match parse_cli_args(&to_args(&[
+            "transactions.csv",
+            "--only-clients",
+            &f_only,
+            "--skip-clients",
+            &f_skip,
+        ])) {
+            Ok(CliCommand::Process(cli_options)) => assert_eq!(
+                cli_options.engine_config.client_filter,
+                Some(ClientFilter {
+                    only: Some(HashSet::from([1, 3])),
+                    skip: HashSet::from([3]),
+                })
+            ),
+            _ => panic!("Should parse as process command"),
+        }
+        std::fs::write(&f_skip, "abc\n").unwrap();
+        assert!(
+            parse_cli_args(&to_args(&["transactions.csv", "--skip-clients", &f_skip])).is_err()
+        );
+
+        assert!(parse_cli_args(&to_args(&["transactions.csv", "--checksum"])).is_err());
+        assert!(parse_cli_args(&to_args(&["tail"])).is_err());
+        assert!(parse_cli_args(&to_args(&["diff", "a.csv"])).is_err());
+        assert!(parse_cli_args(&to_args(&["gen"])).is_err());
+        assert!(
+            parse_cli_args(&to_args(&["transactions.csv", "--withdrawn-dispute", "x"])).is_err()
+        );
+        assert!(parse_cli_args(&to_args(&["gen", "mixed", "--records", "x"])).is_err());
+        assert!(parse_cli_args(&to_args(&["gen", "mixed", "--bogus"])).is_err());
+
+        match parse_cli_args(&to_args(&[
+            "cluster",
+            "transactions.csv",
+            "--workers",
+            "4",
+            "--cluster-snapshots",
+            "snapshots",
+            "--snapshot-every",
+            "500",
+            "--precision",
+            "round",
+        ])) {
+            Ok(CliCommand::Cluster(cluster_options)) => {
+                assert_eq!(cluster_options.workers, 4);
+                assert_eq!(cluster_options.snapshot_dir.as_deref(), Some("snapshots"));
+                assert_eq!(cluster_options.snapshot_every, 500);
+                assert_eq!(
+                    cluster_options.cli_options.precision,
+                    PrecisionPolicy::Round
+                );
+            }
+            _ => panic!("Should parse as cluster command"),
+        }
+        assert!(parse_cli_args(&to_args(&["cluster"])).is_err());
+        assert!(parse_cli_args(&to_args(&["cluster", "t.csv", "--workers", "0"])).is_err());
+        assert!(parse_cli_args(&to_args(&["cluster", "t.csv", "--snapshot-every", "5"])).is_err());
+        assert!(parse_cli_args(&to_args(&["cluster", "t.csv", "--txn-log", "log.csv"])).is_err());
+        assert!(parse_cli_args(&to_args(&["cluster", "t.csv", "--assert-conservation"])).is_err());
+    }
+
+    #[test]
+    fn tst_output_accounts_checksum() {
+        let mut accounts = vec![
+            Account::with_balances(1, Available::from_f64(3.0), Held::from_f64(7.0)),
+            Account::with_balances(2, Available::from_f64(-1.5), Held::default())
+                .with_locks(true, false),
+        ];
+        let f = _get_test_output_file("tst_output_accounts_checksum.csv");
+        assert!(output_accounts_csv(
+            &accounts,
+            &f,
+            &AccountColumns::default(),
+            &CsvDialect::default()
+        )
+        .is_ok());
+        assert!(output_accounts_checksum(&accounts, &f).is_ok());
+
+        let mut rdr = ReaderBuilder::new()
+            .from_path(format!("{}.checksum", f))
+            .unwrap();
+        let record = rdr.records().next().unwrap().unwrap();
+        assert_eq!(&record[0], "2");
+        assert_eq!(&record[1], "1.5000");
+        assert_eq!(&record[2], "7.0000");
+        assert_eq!(&record[3], "8.5000");
+        assert_eq!(
+            std::fs::read_to_string(&f).unwrap(),
+            "client,available,held,total,locked\n\
+             1,3.0000,7.0000,10.0000,false\n\
+             2,-1.5000,0.0000,-1.5000,true\n"
+        );
+        assert_eq!(
+            &record[4],
+            "212dd4fed9f4134a572f404e78befcc0dfce9a62135866bb781c0084cdf7b312"
+        );
+
+        accounts.truncate(1);
+        assert!(output_accounts_csv(
+            &accounts,
+            &f,
+            &AccountColumns::default(),
+            &CsvDialect::default()
+        )
+        .is_ok());
+        assert!(output_accounts_checksum(&accounts, &f).is_ok());
+        let mut rdr = ReaderBuilder::new()
+            .from_path(format!("{}.checksum", f))
+            .unwrap();
+        let record = rdr.records().next().unwrap().unwrap();
+        assert_eq!(
+            &record[4], "1d01d310333c0404ad92fc20e999b0f615bf3bca491c8d766f56eaa9c61379b2",
+            "Digest should follow file contents"
+        );
+
+        // A checksum of a file that can't be read is an error, not an empty sidecar
+        assert!(output_accounts_checksum(&accounts, &format!("{}.missing", f)).is_err());
+    }
+
+    #[test]
+    fn tst_output_txns_csv() {
+        let txns = vec![
+            deposit(1, 1, 10.0),
+            Transaction::Dispute(RefTxn {
+                ref_id: 1,
+                acnt_id: 1,
+            }),
+        ];
+        let f = _get_test_output_file("tst_txns_output.csv");
+        let res = output_txns_csv(&txns, &OutputMethod::Csv(f.clone()));
+        assert!(res.is_ok());
+
+        let parsed = _parse_txns_csv(f.as_str(), true).unwrap();
+        assert_eq!(parsed, txns, "Generated files should round trip");
+    }
+
+    #[test]
+    fn tst_fixed_width_decode() {
+        let spec =
+            FixedWidthSpec::from_file(&_get_test_input_file("fixed_width_spec.csv")).unwrap();
+        assert_eq!(
+            spec.tx,
+            FixedWidthField {
+                start: 15,
+                width: 8
+            }
+        );
+
+        let txn = spec
+            .decode("WITHDRAWAL00001000000030000004.2500")
+            .unwrap()
+            .convert_to_txn();
+        assert!(
+            matches!(txn, Ok(Transaction::Withdrawal(p_txn)) if p_txn.amount == TxnAmount::from_f64(4.25))
+        );
+        let txn = spec
+            .decode("resolve   0000700000009")
+            .unwrap()
+            .convert_to_txn();
+        assert_eq!(
+            txn,
+            Ok(Transaction::Resolve(RefTxn {
+                ref_id: 9,
+                acnt_id: 7
+            })),
+            "Short records should read missing fields as blank"
+        );
+        assert_eq!(
+            spec.decode("deposit   abcde00000001000001.0000").err(),
+            Some(InputTxnErr::MalformedRecord)
+        );
+
+        let with_memo = FixedWidthSpec {
+            memo: Some(FixedWidthField {
+                start: 35,
+                width: 10,
+            }),
+            ..spec.clone()
+        };
+        let txn = with_memo
+            .decode("DEPOSIT   00001000000010000010.5000INV-0042")
+            .unwrap()
+            .convert_to_txn();
+        assert!(
+            matches!(txn, Ok(Transaction::Deposit(p_txn)) if p_txn.memo.as_deref() == Some("INV-0042"))
+        );
+
+        let f_spec = _get_test_output_file("tst_fixed_width_spec_incomplete.csv");
+        std::fs::write(&f_spec, "field,start,width\ntype,0,10\n").unwrap();
+        assert!(FixedWidthSpec::from_file(&f_spec).is_err());
+    }
+
+    #[test]
+    fn tst_output_txn_log_csv() {
+        let mut disputed = DisputeHistory::default();
+        disputed.record(DisputeState::Disputed, 3);
+        let txns = [
+            SequencedTxn {
+                seq: 1,
+                txn: Transaction::Deposit(PureTxn {
+                    txn_id: 1,
+                    acnt_id: 1,
+                    amount: TxnAmount::from_f64(10.0),
+                    dispute: disputed,
+                    memo: Some("INV-0042".to_string()),
+                    channel: Some(Channel::Card),
+                }),
+            },
+            SequencedTxn {
+                seq: 3,
+                txn: Transaction::Dispute(RefTxn {
+                    ref_id: 1,
+                    acnt_id: 1,
+                }),
+            },
+        ];
+        let f = _get_test_output_file("tst_txn_log_output.csv");
+        let acnt_seqs = [Some(1), None];
+        assert!(output_txn_log_csv(txns.iter().zip(acnt_seqs), f.as_str()).is_ok());
+
+        let mut rdr = ReaderBuilder::new().from_path(f.as_str()).unwrap();
+        let records: Vec<csv::StringRecord> = rdr.records().map(|r| r.unwrap()).collect();
+        assert_eq!(
+            records[0],
+            vec![
+                "1",
+                "deposit",
+                "1",
+                "1",
+                "10.0000",
+                "disputed",
+                "disputed@3",
+                "INV-0042",
+                "card",
+                "1"
+            ]
+        );
+        assert_eq!(
+            records[1],
+            vec!["3", "dispute", "1", "1", "", "", "", "", "", ""]
+        );
     }
 }
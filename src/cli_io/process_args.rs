@@ -0,0 +1,886 @@
+//! Flags of the process command, read by groups of related options
+//! Each group takes the flags it knows & applies the ones needing another flag once every flag is read,
+//! checks spanning groups come last
+
+use super::{
+    apply_rules_file, invalid_input, parse_channel_value, parse_flag_value, read_client_ids,
+    CliOptions, CsvDialect, FixedWidthSpec, HeaderOptions, OutputMethod, OutputSchema, OutputSink,
+};
+use crate::alerts::AlertConfig;
+use crate::amount::PrecisionPolicy;
+use crate::clock::ClockSource;
+use crate::payments_engine::config::{
+    AnomalyConfig, ClientFilter, DedupeWindowConfig, DisputableTxns, DuplicateCheck, EngineConfig,
+    GcPolicy, RiskConfig, TimestampSanity,
+};
+use crate::payments_engine::flow_report::FlowBucket;
+use crate::payments_engine::initial_state::DuplicateClientPolicy;
+use crate::payments_engine::partner_report::DEFAULT_REJECT_THRESHOLD;
+use crate::shadow::ShadowConfig;
+use crate::transform::{ClientPseudonyms, IngestTransform};
+use crate::webhook::WebhookConfig;
+use std::io;
+use std::slice::Iter;
+use std::time::Duration;
+
+pub(super) fn parse_process_args(
+    input_file: &str,
+    args: &[String],
+) -> Result<CliOptions, io::Error> {
+    let mut cli_options = default_options(input_file);
+    let mut input = InputFlags::default();
+    let mut rules = RulesFlags::default();
+    let mut output = OutputFlags::default();
+    let mut reports = ReportFlags::default();
+    let mut state = StateFlags::default();
+
+    let mut args_iter = args.iter();
+    while let Some(flag) = args_iter.next() {
+        let taken = input.take(flag, &mut args_iter, &mut cli_options)?
+            || rules.take(flag, &mut args_iter, &mut cli_options)?
+            || output.take(flag, &mut args_iter, &mut cli_options)?
+            || reports.take(flag, &mut args_iter, &mut cli_options)?
+            || state.take(flag, &mut args_iter, &mut cli_options)?
+            || take_notify_flag(flag, &mut args_iter, &mut cli_options)?;
+        if !taken {
+            return Err(invalid_input(format!("Unknown argument '{}'", flag)));
+        }
+    }
+
+    input.apply(&mut cli_options)?;
+    rules.apply(&mut cli_options)?;
+    output.apply(&mut cli_options)?;
+    reports.apply(&mut cli_options)?;
+    state.apply(&mut cli_options)?;
+    apply_notify(&mut cli_options)?;
+    check_combinations(&cli_options)?;
+    Ok(cli_options)
+}
+
+/// Options of a run given only its input file
+fn default_options(input_file: &str) -> CliOptions {
+    CliOptions {
+        input_file: input_file.to_string(),
+        output: vec![OutputSink::csv(OutputMethod::StdOutput)],
+        engine_config: EngineConfig::default(),
+        webhook: WebhookConfig::default(),
+        txn_log: None,
+        gc_policy: None,
+        progress: false,
+        extended_output: false,
+        output_schema: OutputSchema::V1,
+        only_locked: false,
+        csv_dialect: CsvDialect::default(),
+        client_stats: None,
+        checksum: false,
+        channel_report: None,
+        pending_report: None,
+        alerts: AlertConfig::default(),
+        fixed_width: None,
+        iso20022: false,
+        encoding: None,
+        transforms: vec![],
+        anonymize_map: None,
+        header: HeaderOptions::default(),
+        precision: PrecisionPolicy::default(),
+        statsd: None,
+        statsd_prefix: "payments_engine".to_string(),
+        latency_budget: None,
+        quarantine: None,
+        audit_log: None,
+        dispute_aging: None,
+        held_breakdown: None,
+        dispute_cases: None,
+        flow_report: None,
+        flow_bucket: FlowBucket::Hour,
+        partner_report: None,
+        partner_reject_threshold: DEFAULT_REJECT_THRESHOLD,
+        priority_unix: None,
+        clock: ClockSource::Wall,
+        shadow: None,
+        dead_letter: None,
+        save_snapshot: None,
+        snapshot_shards: 4,
+        snapshot_deltas: false,
+        restore_snapshot: None,
+        initial_state: None,
+        on_duplicate_client: DuplicateClientPolicy::Error,
+        onboarding: None,
+        oracle_check: false,
+        assert_conservation: false,
+        script: None,
+        balances_series: None,
+        series_interval: 1000,
+    }
+}
+
+/// How records are read & rewritten before they become txns
+#[derive(Default)]
+struct InputFlags {
+    anonymize: bool,
+    anonymize_key_file: Option<String>,
+    anonymize_decimals: Option<usize>,
+}
+
+impl InputFlags {
+    /// Reads the flag & any value it takes, false when the flag belongs to another group
+    fn take(
+        &mut self,
+        flag: &str,
+        args_iter: &mut Iter<'_, String>,
+        cli_options: &mut CliOptions,
+    ) -> Result<bool, io::Error> {
+        match flag {
+            "--fixed-width" => {
+                let file_path: String = parse_flag_value(flag, args_iter.next())?;
+                cli_options.fixed_width = Some(FixedWidthSpec::from_file(&file_path)?);
+            }
+            "--iso20022" => match cfg!(feature = "iso20022") {
+                true => cli_options.iso20022 = true,
+                false => {
+                    return Err(invalid_input(
+                        "--iso20022 needs a build with the iso20022 feature".to_string(),
+                    ))
+                }
+            },
+            "--encoding" => cli_options.encoding = Some(parse_flag_value(flag, args_iter.next())?),
+            "--transform" => {
+                let expr: String = parse_flag_value(flag, args_iter.next())?;
+                cli_options.transforms.push(IngestTransform::parse(&expr)?);
+            }
+            "--clock" => cli_options.clock = parse_flag_value(flag, args_iter.next())?,
+            "--anonymize" => self.anonymize = true,
+            "--anonymize-key-file" => {
+                self.anonymize_key_file = Some(parse_flag_value(flag, args_iter.next())?)
+            }
+            "--anonymize-decimals" => {
+                self.anonymize_decimals = Some(parse_flag_value(flag, args_iter.next())?)
+            }
+            "--anonymize-map" => {
+                cli_options.anonymize_map = Some(parse_flag_value(flag, args_iter.next())?)
+            }
+            "--validate-header" => cli_options.header.validate = true,
+            "--header-alias" => {
+                let pair: String = parse_flag_value(flag, args_iter.next())?;
+                cli_options.header.add_alias(&pair)?;
+            }
+            "--precision" => cli_options.precision = parse_flag_value(flag, args_iter.next())?,
+            "--dead-letter" => {
+                cli_options.dead_letter = Some(parse_flag_value(flag, args_iter.next())?)
+            }
+            "--priority-unix" => match cfg!(all(unix, feature = "listen")) {
+                true => cli_options.priority_unix = Some(parse_flag_value(flag, args_iter.next())?),
+                false => {
+                    return Err(invalid_input(
+                        "--priority-unix needs a unix build with the listen feature".to_string(),
+                    ))
+                }
+            },
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    /// Adds the anonymize transforms & checks input flags fit the input format
+    fn apply(self, cli_options: &mut CliOptions) -> Result<(), io::Error> {
+        match self.anonymize {
+            true => {
+                // Pseudonyms stand for the engine's client ids, so they come after any remapping
+                cli_options.transforms.push(IngestTransform::Pseudonymize(
+                    ClientPseudonyms::from_key_source(self.anonymize_key_file.as_deref())?,
+                ));
+                if let Some(places) = self.anonymize_decimals {
+                    cli_options
+                        .transforms
+                        .push(IngestTransform::TruncateAmount(places));
+                }
+                if cli_options.dead_letter.is_some() {
+                    return Err(invalid_input(
+                        "--dead-letter keeps records as read, it can't be combined with --anonymize"
+                            .to_string(),
+                    ));
+                }
+            }
+            false
+                if self.anonymize_decimals.is_some()
+                    || self.anonymize_key_file.is_some()
+                    || cli_options.anonymize_map.is_some() =>
+            {
+                return Err(invalid_input(
+                    "--anonymize-decimals, --anonymize-key-file & --anonymize-map require --anonymize"
+                        .to_string(),
+                ))
+            }
+            false => {}
+        }
+        if cli_options.iso20022 && cli_options.fixed_width.is_some() {
+            return Err(invalid_input(
+                "--iso20022 & --fixed-width can't be combined".to_string(),
+            ));
+        }
+        if cli_options.header != HeaderOptions::default()
+            && (cli_options.fixed_width.is_some() || cli_options.iso20022)
+        {
+            return Err(invalid_input(
+                "--validate-header & --header-alias only apply to csv input".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Rules the engine applies to txns, its capacity, & how it tells duplicates apart
+#[derive(Default)]
+struct RulesFlags {
+    dedup_store: Option<String>,
+    dedup_expected: Option<usize>,
+    dedup_fp_rate: Option<f64>,
+    dedupe_window: Option<usize>,
+    dedupe_window_secs: Option<u64>,
+    client_filter: ClientFilter,
+    risk_threshold: Option<f64>,
+    risk: RiskConfig,
+    anomaly_deviations: Option<f64>,
+    anomaly: AnomalyConfig,
+    timestamp_sanity: TimestampSanity,
+    gc_inactive: Option<u64>,
+    gc_archive: Option<String>,
+}
+
+impl RulesFlags {
+    /// Reads the flag & any value it takes, false when the flag belongs to another group
+    fn take(
+        &mut self,
+        flag: &str,
+        args_iter: &mut Iter<'_, String>,
+        cli_options: &mut CliOptions,
+    ) -> Result<bool, io::Error> {
+        let config = &mut cli_options.engine_config;
+        match flag {
+            "--dedup-store" => self.dedup_store = Some(parse_flag_value(flag, args_iter.next())?),
+            "--dedup-expected" => {
+                self.dedup_expected = Some(parse_flag_value(flag, args_iter.next())?)
+            }
+            "--dedup-fp-rate" => {
+                self.dedup_fp_rate = Some(parse_flag_value(flag, args_iter.next())?)
+            }
+            "--dedupe-window" => {
+                self.dedupe_window = Some(parse_flag_value(flag, args_iter.next())?)
+            }
+            "--dedupe-window-secs" => {
+                self.dedupe_window_secs = Some(parse_flag_value(flag, args_iter.next())?)
+            }
+            "--txn-registry" => {
+                config.txn_registry = Some(parse_flag_value(flag, args_iter.next())?)
+            }
+            "--only-clients" => {
+                let file_path: String = parse_flag_value(flag, args_iter.next())?;
+                self.client_filter.only = Some(read_client_ids(&file_path)?);
+            }
+            "--skip-clients" => {
+                let file_path: String = parse_flag_value(flag, args_iter.next())?;
+                self.client_filter.skip = read_client_ids(&file_path)?;
+            }
+            "--approval-threshold" => {
+                config.withdrawal_approval_threshold =
+                    Some(parse_flag_value(flag, args_iter.next())?)
+            }
+            "--expected-records" => {
+                config.expected_records = Some(parse_flag_value(flag, args_iter.next())?)
+            }
+            "--expected-accounts" => {
+                config.expected_accounts = Some(parse_flag_value(flag, args_iter.next())?)
+            }
+            "--id-hasher" => config.id_hasher = parse_flag_value(flag, args_iter.next())?,
+            "--id-index" => config.id_index = parse_flag_value(flag, args_iter.next())?,
+            "--max-accounts" => {
+                config.limits.max_accounts = Some(parse_flag_value(flag, args_iter.next())?)
+            }
+            "--max-txns" => {
+                config.limits.max_processed_txns = Some(parse_flag_value(flag, args_iter.next())?)
+            }
+            "--max-memory-mb" => {
+                let megabytes: usize = parse_flag_value(flag, args_iter.next())?;
+                config.limits.max_memory_bytes = Some(megabytes << 20);
+            }
+            "--risk-threshold" => {
+                self.risk_threshold = Some(parse_flag_value(flag, args_iter.next())?)
+            }
+            "--risk-weight" => {
+                let value: String = parse_flag_value(flag, args_iter.next())?;
+                let invalid = || invalid_input(format!("Invalid value '{}' for {}", value, flag));
+                let (event, weight) = value.split_once('=').ok_or_else(invalid)?;
+                let weight = weight.parse().map_err(|_| invalid())?;
+                match event {
+                    "failed-withdrawal" => self.risk.failed_withdrawal = weight,
+                    "dispute" => self.risk.dispute = weight,
+                    "chargeback" => self.risk.chargeback = weight,
+                    "anomaly" => self.risk.anomaly = weight,
+                    _ => return Err(invalid()),
+                }
+            }
+            "--anomaly-deviations" => {
+                self.anomaly_deviations = Some(parse_flag_value(flag, args_iter.next())?)
+            }
+            "--anomaly-min-history" => {
+                self.anomaly.min_history = parse_flag_value(flag, args_iter.next())?
+            }
+            "--anomaly-window" => self.anomaly.window = parse_flag_value(flag, args_iter.next())?,
+            "--channel-limit" => {
+                let (channel, max_amount) = parse_channel_value(flag, args_iter.next())?;
+                let rules = config.channel_rules.entry(channel);
+                rules.or_default().max_amount = Some(max_amount);
+            }
+            "--channel-fee" => {
+                let (channel, fee) = parse_channel_value(flag, args_iter.next())?;
+                config.channel_rules.entry(channel).or_default().fee = fee;
+            }
+            "--channel-dispute-window" => {
+                let (channel, window) = parse_channel_value(flag, args_iter.next())?;
+                let rules = config.channel_rules.entry(channel);
+                rules.or_default().dispute_window = Some(window);
+            }
+            "--max-future-skew" => {
+                self.timestamp_sanity.max_future_secs =
+                    Some(parse_flag_value(flag, args_iter.next())?)
+            }
+            "--max-timestamp-age" => {
+                self.timestamp_sanity.max_age_secs = Some(parse_flag_value(flag, args_iter.next())?)
+            }
+            "--quarantine-bad-timestamps" => self.timestamp_sanity.quarantine = true,
+            "--require-onboarding" => config.require_onboarding = true,
+            "--reserve-floor" => {
+                let value: String = parse_flag_value(flag, args_iter.next())?;
+                let invalid = || invalid_input(format!("Invalid value '{}' for {}", value, flag));
+                let (client, floor) = value.split_once('=').ok_or_else(invalid)?;
+                let client: u16 = client.trim().parse().map_err(|_| invalid())?;
+                let floor: f64 = floor.trim().parse().map_err(|_| invalid())?;
+                if !floor.is_finite() || floor < 0.0 {
+                    return Err(invalid());
+                }
+                config.reserve_floors.insert(client, floor);
+            }
+            "--withdrawn-dispute" => {
+                config.withdrawn_funds_dispute = parse_flag_value(flag, args_iter.next())?
+            }
+            "--disputable" => config.disputable_txns = parse_flag_value(flag, args_iter.next())?,
+            "--rules" => {
+                let file_path: String = parse_flag_value(flag, args_iter.next())?;
+                apply_rules_file(&file_path, config)?;
+            }
+            "--compact-withdrawals" => config.compact_withdrawals = true,
+            "--id-epoch" => config.id_epoch = Some(parse_flag_value(flag, args_iter.next())?),
+            "--max-open-disputes" => {
+                config.max_open_disputes = Some(parse_flag_value(flag, args_iter.next())?)
+            }
+            "--flag-dispute-floods" => config.flag_dispute_floods = true,
+            "--gc-inactive" => self.gc_inactive = Some(parse_flag_value(flag, args_iter.next())?),
+            "--gc-archive" => self.gc_archive = Some(parse_flag_value(flag, args_iter.next())?),
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    /// Sets the rules needing several flags, each only when the flag enabling it was given
+    fn apply(self, cli_options: &mut CliOptions) -> Result<(), io::Error> {
+        let config = &mut cli_options.engine_config;
+        if let Some(store_path) = self.dedup_store {
+            config.duplicate_check = DuplicateCheck::Probabilistic {
+                expected_txns: self.dedup_expected.unwrap_or(1_000_000),
+                false_positive_rate: self.dedup_fp_rate.unwrap_or(0.01),
+                store_path,
+            };
+        }
+        if let Some(review_threshold) = self.risk_threshold {
+            config.risk = Some(RiskConfig {
+                review_threshold,
+                ..self.risk
+            });
+        } else if self.risk != RiskConfig::default() {
+            return Err(invalid_input(
+                "--risk-weight requires --risk-threshold".to_string(),
+            ));
+        }
+        match self.anomaly_deviations {
+            Some(max_deviations) if max_deviations > 0.0 && self.anomaly.window > 0 => {
+                config.anomaly = Some(AnomalyConfig {
+                    max_deviations,
+                    ..self.anomaly
+                });
+            }
+            Some(_) => {
+                return Err(invalid_input(
+                    "--anomaly-deviations & --anomaly-window must be more than 0".to_string(),
+                ))
+            }
+            None if self.anomaly != AnomalyConfig::default() => {
+                return Err(invalid_input(
+                    "--anomaly-min-history & --anomaly-window require --anomaly-deviations"
+                        .to_string(),
+                ))
+            }
+            None => {}
+        }
+        let timestamp_sanity = self.timestamp_sanity;
+        let bounds = [
+            timestamp_sanity.max_future_secs,
+            timestamp_sanity.max_age_secs,
+        ];
+        if bounds
+            .iter()
+            .any(|bound| bound.is_some_and(|secs| secs < 0))
+        {
+            return Err(invalid_input(
+                "--max-future-skew & --max-timestamp-age must be 0 or more seconds".to_string(),
+            ));
+        }
+        if bounds.iter().any(Option::is_some) {
+            config.timestamp_sanity = Some(timestamp_sanity);
+        } else if timestamp_sanity.quarantine {
+            return Err(invalid_input(
+                "--quarantine-bad-timestamps requires --max-future-skew or --max-timestamp-age"
+                    .to_string(),
+            ));
+        }
+        if self.client_filter != ClientFilter::default() {
+            config.client_filter = Some(self.client_filter);
+        }
+        if let Some(max_ids_per_client) = self.dedupe_window {
+            config.dedupe_window = Some(DedupeWindowConfig {
+                max_ids_per_client,
+                max_age: self.dedupe_window_secs.map(Duration::from_secs),
+            });
+        } else if self.dedupe_window_secs.is_some() {
+            return Err(invalid_input(
+                "--dedupe-window-secs requires --dedupe-window".to_string(),
+            ));
+        }
+        if config.compact_withdrawals && config.disputable_txns != DisputableTxns::Deposits {
+            return Err(invalid_input(
+                "--compact-withdrawals requires --disputable deposits".to_string(),
+            ));
+        }
+        if config.id_epoch == Some(0) {
+            return Err(invalid_input("--id-epoch must be at least 1".to_string()));
+        }
+        if config.id_epoch.is_some()
+            && (config.compact_withdrawals
+                || config.txn_registry.is_some()
+                || config.duplicate_check != DuplicateCheck::Exact)
+        {
+            return Err(invalid_input(
+                "--id-epoch can't be combined with --compact-withdrawals, --txn-registry, or --dedup-store"
+                    .to_string(),
+            ));
+        }
+        if let Some(inactive_for) = self.gc_inactive {
+            cli_options.gc_policy = Some(GcPolicy {
+                inactive_for,
+                archive_path: self.gc_archive,
+            });
+        } else if self.gc_archive.is_some() {
+            return Err(invalid_input(
+                "--gc-archive requires --gc-inactive".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Where & how final balances are written
+#[derive(Default)]
+struct OutputFlags {
+    outputs: Vec<OutputSink>,
+}
+
+impl OutputFlags {
+    /// Reads the flag & any value it takes, false when the flag belongs to another group
+    fn take(
+        &mut self,
+        flag: &str,
+        args_iter: &mut Iter<'_, String>,
+        cli_options: &mut CliOptions,
+    ) -> Result<bool, io::Error> {
+        match flag {
+            "--output" => {
+                self.outputs
+                    .push(OutputSink::csv(OutputMethod::from_arg(parse_flag_value(
+                        flag,
+                        args_iter.next(),
+                    )?)))
+            }
+            "--output-json" => self
+                .outputs
+                .push(OutputSink::json_lines(OutputMethod::from_arg(
+                    parse_flag_value(flag, args_iter.next())?,
+                ))),
+            "--progress" => match cfg!(feature = "progress") {
+                true => cli_options.progress = true,
+                false => {
+                    return Err(invalid_input(
+                        "--progress needs a build with the progress feature".to_string(),
+                    ))
+                }
+            },
+            "--extended-output" => cli_options.extended_output = true,
+            "--only-locked" => cli_options.only_locked = true,
+            "--output-schema" => {
+                cli_options.output_schema = parse_flag_value(flag, args_iter.next())?
+            }
+            "--delimiter" => {
+                let delimiter: String = parse_flag_value(flag, args_iter.next())?;
+                cli_options.csv_dialect.delimiter = match delimiter.as_str() {
+                    "tab" => b'\t',
+                    _ if delimiter.len() == 1 => delimiter.as_bytes()[0],
+                    _ => {
+                        return Err(invalid_input(format!(
+                            "Invalid value '{}' for {}, expected a single character or tab",
+                            delimiter, flag
+                        )))
+                    }
+                }
+            }
+            "--decimal-separator" => {
+                cli_options.csv_dialect.decimal_separator =
+                    parse_flag_value(flag, args_iter.next())?
+            }
+            "--crlf" => cli_options.csv_dialect.crlf = true,
+            "--bom" => cli_options.csv_dialect.bom = true,
+            "--status-values" => {
+                cli_options.csv_dialect.status_values = parse_flag_value(flag, args_iter.next())?
+            }
+            "--excel-safe" => {
+                cli_options.csv_dialect.excel_safe = true;
+                cli_options.csv_dialect.crlf = true;
+            }
+            "--checksum" => cli_options.checksum = true,
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    /// Replaces the stdout default by the outputs given, if any
+    fn apply(self, cli_options: &mut CliOptions) -> Result<(), io::Error> {
+        cli_options.csv_dialect.validate()?;
+        if self.outputs.iter().filter(|sink| sink.is_stdout()).count() > 1 {
+            return Err(invalid_input(
+                "Only one of --output & --output-json may be stdout".to_string(),
+            ));
+        }
+        if !self.outputs.is_empty() {
+            cli_options.output = self.outputs;
+        }
+        if cli_options.checksum
+            && !cli_options
+                .output
+                .iter()
+                .any(|sink| sink.csv_file().is_some())
+        {
+            return Err(invalid_input(
+                "--checksum requires --output to a file".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Reports, logs, & checks written alongside final balances
+#[derive(Default)]
+struct ReportFlags {
+    flow_bucket: Option<FlowBucket>,
+    partner_reject_threshold: Option<f64>,
+    series_interval: Option<u64>,
+    shadow_url: Option<String>,
+    shadow_sample: Option<usize>,
+    shadow_report: Option<String>,
+}
+
+impl ReportFlags {
+    /// Reads the flag & any value it takes, false when the flag belongs to another group
+    fn take(
+        &mut self,
+        flag: &str,
+        args_iter: &mut Iter<'_, String>,
+        cli_options: &mut CliOptions,
+    ) -> Result<bool, io::Error> {
+        match flag {
+            "--pending-report" => {
+                cli_options.pending_report = Some(parse_flag_value(flag, args_iter.next())?)
+            }
+            "--channel-report" => {
+                cli_options.channel_report = Some(parse_flag_value(flag, args_iter.next())?)
+            }
+            "--client-stats" => {
+                cli_options.client_stats = Some(parse_flag_value(flag, args_iter.next())?)
+            }
+            "--quarantine" => {
+                cli_options.quarantine = Some(parse_flag_value(flag, args_iter.next())?)
+            }
+            "--audit-log" => {
+                cli_options.audit_log = Some(parse_flag_value(flag, args_iter.next())?)
+            }
+            "--txn-log" => cli_options.txn_log = Some(parse_flag_value(flag, args_iter.next())?),
+            "--dispute-aging" => {
+                cli_options.dispute_aging = Some(parse_flag_value(flag, args_iter.next())?)
+            }
+            "--held-breakdown" => {
+                cli_options.held_breakdown = Some(parse_flag_value(flag, args_iter.next())?)
+            }
+            "--dispute-cases" => {
+                cli_options.dispute_cases = Some(parse_flag_value(flag, args_iter.next())?)
+            }
+            "--flow-report" => {
+                cli_options.flow_report = Some(parse_flag_value(flag, args_iter.next())?)
+            }
+            "--flow-bucket" => self.flow_bucket = Some(parse_flag_value(flag, args_iter.next())?),
+            "--partner-report" => {
+                cli_options.partner_report = Some(parse_flag_value(flag, args_iter.next())?)
+            }
+            "--partner-reject-threshold" => {
+                self.partner_reject_threshold = Some(parse_flag_value(flag, args_iter.next())?)
+            }
+            "--balances-series" => {
+                cli_options.balances_series = Some(parse_flag_value(flag, args_iter.next())?)
+            }
+            "--series-interval" => {
+                self.series_interval = Some(parse_flag_value(flag, args_iter.next())?)
+            }
+            "--shadow-url" => self.shadow_url = Some(parse_flag_value(flag, args_iter.next())?),
+            "--shadow-sample" => {
+                self.shadow_sample = Some(parse_flag_value(flag, args_iter.next())?)
+            }
+            "--shadow-report" => {
+                self.shadow_report = Some(parse_flag_value(flag, args_iter.next())?)
+            }
+            "--oracle-check" => cli_options.oracle_check = true,
+            "--assert-conservation" => cli_options.assert_conservation = true,
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    /// Sets report settings, each only when the report it belongs to was asked for
+    fn apply(self, cli_options: &mut CliOptions) -> Result<(), io::Error> {
+        match (self.flow_bucket, &cli_options.flow_report) {
+            (Some(_), None) => {
+                return Err(invalid_input(
+                    "--flow-bucket requires --flow-report".to_string(),
+                ))
+            }
+            (Some(bucket), Some(_)) => cli_options.flow_bucket = bucket,
+            (None, _) => {}
+        }
+        match (self.partner_reject_threshold, &cli_options.partner_report) {
+            (Some(_), None) => {
+                return Err(invalid_input(
+                    "--partner-reject-threshold requires --partner-report".to_string(),
+                ))
+            }
+            (Some(threshold), Some(_)) if !(0.0..=1.0).contains(&threshold) => {
+                return Err(invalid_input(
+                    "--partner-reject-threshold must be between 0 & 1".to_string(),
+                ))
+            }
+            (Some(threshold), Some(_)) => cli_options.partner_reject_threshold = threshold,
+            (None, _) => {}
+        }
+        match (self.series_interval, &cli_options.balances_series) {
+            (Some(0), _) => {
+                return Err(invalid_input(
+                    "--series-interval must be at least 1".to_string(),
+                ))
+            }
+            (Some(interval), Some(_)) => cli_options.series_interval = interval,
+            (Some(_), None) => {
+                return Err(invalid_input(
+                    "--series-interval requires --balances-series".to_string(),
+                ))
+            }
+            (None, _) => {}
+        }
+        if let Some(url) = self.shadow_url {
+            if !url.contains("{client}") {
+                return Err(invalid_input(
+                    "--shadow-url needs a {client} placeholder for the client id".to_string(),
+                ));
+            }
+            let default = ShadowConfig::default();
+            cli_options.shadow = Some(ShadowConfig {
+                url,
+                sample: self.shadow_sample.unwrap_or(default.sample),
+                report_path: self.shadow_report,
+            });
+        } else if self.shadow_sample.is_some() || self.shadow_report.is_some() {
+            return Err(invalid_input(
+                "--shadow-sample & --shadow-report require --shadow-url".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// State a run starts from & the snapshots it saves
+#[derive(Default)]
+struct StateFlags {
+    snapshot_shards: Option<usize>,
+    on_duplicate_client: Option<DuplicateClientPolicy>,
+}
+
+impl StateFlags {
+    /// Reads the flag & any value it takes, false when the flag belongs to another group
+    fn take(
+        &mut self,
+        flag: &str,
+        args_iter: &mut Iter<'_, String>,
+        cli_options: &mut CliOptions,
+    ) -> Result<bool, io::Error> {
+        match flag {
+            "--save-snapshot" => {
+                cli_options.save_snapshot = Some(parse_flag_value(flag, args_iter.next())?)
+            }
+            "--snapshot-shards" => {
+                self.snapshot_shards = Some(parse_flag_value(flag, args_iter.next())?)
+            }
+            "--snapshot-deltas" => cli_options.snapshot_deltas = true,
+            "--restore-snapshot" => {
+                cli_options.restore_snapshot = Some(parse_flag_value(flag, args_iter.next())?)
+            }
+            "--initial-state" => {
+                cli_options.initial_state = Some(parse_flag_value(flag, args_iter.next())?)
+            }
+            "--on-duplicate-client" => {
+                self.on_duplicate_client = Some(parse_flag_value(flag, args_iter.next())?)
+            }
+            "--onboarding" => {
+                cli_options.onboarding = Some(parse_flag_value(flag, args_iter.next())?)
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    /// Sets snapshot & initial state settings, each only when the flag it belongs to was given
+    fn apply(self, cli_options: &mut CliOptions) -> Result<(), io::Error> {
+        match (self.snapshot_shards, &cli_options.save_snapshot) {
+            (Some(0), _) => {
+                return Err(invalid_input(
+                    "--snapshot-shards must be at least 1".to_string(),
+                ))
+            }
+            (Some(shards), Some(_)) => cli_options.snapshot_shards = shards,
+            (Some(_), None) => {
+                return Err(invalid_input(
+                    "--snapshot-shards requires --save-snapshot".to_string(),
+                ))
+            }
+            (None, _) => {}
+        }
+        if cli_options.snapshot_deltas && cli_options.save_snapshot.is_none() {
+            return Err(invalid_input(
+                "--snapshot-deltas requires --save-snapshot".to_string(),
+            ));
+        }
+        if cli_options.initial_state.is_some() && cli_options.restore_snapshot.is_some() {
+            return Err(invalid_input(
+                "--initial-state & --restore-snapshot can't be combined".to_string(),
+            ));
+        }
+        match (self.on_duplicate_client, &cli_options.initial_state) {
+            (Some(policy), Some(_)) => cli_options.on_duplicate_client = policy,
+            (Some(_), None) => {
+                return Err(invalid_input(
+                    "--on-duplicate-client requires --initial-state".to_string(),
+                ))
+            }
+            (None, _) => {}
+        }
+        Ok(())
+    }
+}
+
+/// Reads an alerting, metrics, webhook, or script flag & any value it takes, false for other flags
+fn take_notify_flag(
+    flag: &str,
+    args_iter: &mut Iter<'_, String>,
+    cli_options: &mut CliOptions,
+) -> Result<bool, io::Error> {
+    match flag {
+        "--alert" => cli_options
+            .alerts
+            .rules
+            .push(parse_flag_value(flag, args_iter.next())?),
+        "--alert-file" => cli_options.alerts.file = Some(parse_flag_value(flag, args_iter.next())?),
+        "--alert-webhook" => cli_options
+            .alerts
+            .webhook_urls
+            .push(parse_flag_value(flag, args_iter.next())?),
+        "--alert-stderr" => cli_options.alerts.stderr = true,
+        "--statsd" => cli_options.statsd = Some(parse_flag_value(flag, args_iter.next())?),
+        "--statsd-prefix" => cli_options.statsd_prefix = parse_flag_value(flag, args_iter.next())?,
+        "--latency-budget-us" => {
+            cli_options.latency_budget = Some(Duration::from_micros(parse_flag_value(
+                flag,
+                args_iter.next(),
+            )?))
+        }
+        "--webhook" => cli_options
+            .webhook
+            .urls
+            .push(parse_flag_value(flag, args_iter.next())?),
+        "--webhook-retries" => {
+            cli_options.webhook.max_retries = parse_flag_value(flag, args_iter.next())?
+        }
+        "--webhook-backoff-ms" => {
+            cli_options.webhook.backoff_ms = parse_flag_value(flag, args_iter.next())?
+        }
+        "--webhook-dead-letter" => {
+            cli_options.webhook.dead_letter_path = parse_flag_value(flag, args_iter.next())?
+        }
+        "--script" => match cfg!(feature = "scripting") {
+            true => cli_options.script = Some(parse_flag_value(flag, args_iter.next())?),
+            false => {
+                return Err(invalid_input(
+                    "--script needs a build with the scripting feature".to_string(),
+                ))
+            }
+        },
+        _ => return Ok(false),
+    }
+    Ok(true)
+}
+
+/// Checks alert destinations have rules to send, alerts go to stderr unless somewhere else was asked for
+fn apply_notify(cli_options: &mut CliOptions) -> Result<(), io::Error> {
+    let alerts = &mut cli_options.alerts;
+    if alerts.rules.is_empty() && (alerts.file.is_some() || !alerts.webhook_urls.is_empty()) {
+        return Err(invalid_input(
+            "--alert-file & --alert-webhook require --alert".to_string(),
+        ));
+    }
+    if !alerts.rules.is_empty() && alerts.file.is_none() && alerts.webhook_urls.is_empty() {
+        alerts.stderr = true;
+    }
+    Ok(())
+}
+
+/// Checks flags of one group against the settings another group applied
+fn check_combinations(cli_options: &CliOptions) -> Result<(), io::Error> {
+    let config = &cli_options.engine_config;
+    if cli_options.quarantine.is_some() && config.risk.is_none() && config.anomaly.is_none() {
+        return Err(invalid_input(
+            "--quarantine requires --risk-threshold or --anomaly-deviations".to_string(),
+        ));
+    }
+    if cli_options.oracle_check
+        && (!config.uses_standard_rules()
+            || cli_options.gc_policy.is_some()
+            || cli_options.script.is_some()
+            || cli_options.onboarding.is_some())
+    {
+        return Err(invalid_input(
+            "--oracle-check only models the standard rules, it can't be combined with options changing them"
+                .to_string(),
+        ));
+    }
+    Ok(())
+}
@@ -0,0 +1,158 @@
+//! Sources of the current time for time based engine features, e.g. the dedupe window's max age
+//! Times are unix seconds in UTC, as record timestamps are
+
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Tells the engine what time it is, implementations should be cheap as they are read per transaction
+pub trait Clock: fmt::Debug + Send {
+    /// Current time in unix seconds
+    fn now(&self) -> i64;
+
+    /// True if the clock is driven by the timestamps of streamed records
+    fn follows_records(&self) -> bool {
+        false
+    }
+
+    /// Sees the timestamp of a streamed record before it is processed
+    fn observe(&mut self, _timestamp: i64) {}
+}
+
+/// The system clock, used unless another clock is set, e.g. by servers processing records as they arrive
+#[derive(Debug, Default)]
+pub struct WallClock;
+
+impl Clock for WallClock {
+    fn now(&self) -> i64 {
+        match SystemTime::now().duration_since(UNIX_EPOCH) {
+            Ok(elapsed) => elapsed.as_secs() as i64,
+            Err(e) => -(e.duration().as_secs() as i64),
+        }
+    }
+}
+
+/// Time of the latest record timestamp seen, so replaying an input gives the same results every time
+/// Never goes back, records older than one already seen leave it where it is, 0 before any timestamp
+#[derive(Debug, Default)]
+pub struct RecordClock {
+    latest: Option<i64>,
+}
+
+impl Clock for RecordClock {
+    fn now(&self) -> i64 {
+        self.latest.unwrap_or(0)
+    }
+
+    fn follows_records(&self) -> bool {
+        true
+    }
+
+    fn observe(&mut self, timestamp: i64) {
+        self.latest = Some(
+            self.latest
+                .map_or(timestamp, |latest| latest.max(timestamp)),
+        );
+    }
+}
+
+/// Clock only moving when told to, clones share the time so a test can keep one & give the engine another
+#[derive(Debug, Clone, Default)]
+pub struct ManualClock {
+    now: Arc<AtomicI64>,
+}
+
+impl ManualClock {
+    pub fn new(now: i64) -> Self {
+        Self {
+            now: Arc::new(AtomicI64::new(now)),
+        }
+    }
+
+    pub fn set(&self, now: i64) {
+        self.now.store(now, Ordering::Relaxed);
+    }
+
+    pub fn advance(&self, secs: i64) {
+        self.now.fetch_add(secs, Ordering::Relaxed);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> i64 {
+        self.now.load(Ordering::Relaxed)
+    }
+}
+
+/// Clock a run is driven by
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ClockSource {
+    #[default]
+    Wall,
+    Records,
+}
+
+impl ClockSource {
+    pub fn clock(&self) -> Box<dyn Clock> {
+        match self {
+            ClockSource::Wall => Box::new(WallClock),
+            ClockSource::Records => Box::new(RecordClock::default()),
+        }
+    }
+}
+
+impl FromStr for ClockSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "wall" => Ok(ClockSource::Wall),
+            "records" => Ok(ClockSource::Records),
+            _ => Err(format!("Unknown clock '{}'", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Clock, ClockSource, ManualClock, RecordClock, WallClock};
+    use crate::payments_engine::config::{DedupeWindowConfig, EngineConfig};
+    use crate::payments_engine::PaymentsEngine;
+    use crate::test_support::deposit;
+
+    use std::time::Duration;
+
+    #[test]
+    fn tst_clocks() {
+        assert!(WallClock.now() > 1_700_000_000);
+        let mut records = RecordClock::default();
+        assert_eq!(records.now(), 0);
+        records.observe(1714571100);
+        records.observe(1714571000);
+        assert_eq!(records.now(), 1714571100);
+        assert_eq!("records".parse(), Ok(ClockSource::Records));
+        assert!("sundial".parse::<ClockSource>().is_err());
+
+        let config = EngineConfig {
+            dedupe_window: Some(DedupeWindowConfig {
+                max_ids_per_client: 10,
+                max_age: Some(Duration::from_secs(60)),
+            }),
+            ..EngineConfig::default()
+        };
+        let mut payments_engine = PaymentsEngine::with_config(config).unwrap();
+        let clock = ManualClock::new(1714571100);
+        payments_engine.set_clock(Box::new(clock.clone()));
+        let deposit = deposit(1, 1, 1.0);
+        assert!(payments_engine.process_txn(&deposit).is_ok());
+        clock.advance(60);
+        assert_eq!(payments_engine.now(), 1714571160);
+        // A retry inside the window is absorbed, once it's older than the window it's a duplicate
+        assert!(payments_engine.process_txn(&deposit).is_ok());
+        clock.advance(1);
+        assert!(payments_engine.process_txn(&deposit).is_err());
+        assert_eq!(payments_engine.duplicates_absorbed(), 1);
+    }
+}
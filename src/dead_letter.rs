@@ -0,0 +1,118 @@
+//! Records the engine could not apply, kept with why so they can be replayed after a fix
+
+use crate::cli_io::InputTxnErr;
+use crate::payments_engine::TxnErrors;
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+
+/// Why an input record wasn't applied
+#[derive(Debug, PartialEq)]
+pub enum RecordError {
+    /// The row couldn't be read as a record, holds the reader's message
+    Malformed(String),
+    /// The record was read but isn't a valid transaction
+    Input(InputTxnErr),
+    /// The engine rejected the transaction, with the sequence number it was given
+    Rejected(TxnErrors, u64),
+}
+
+/// One poison record as written to the dead letter file, a json object per line
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DeadLetter {
+    /// Line of the input file the record was read from
+    pub line: u64,
+    /// Record text as it appeared in the input
+    pub record: String,
+    /// `malformed`, `input`, or `rejected`
+    pub kind: String,
+    pub error: String,
+    /// Sequence number of a rejected record
+    pub seq: Option<u64>,
+}
+
+impl DeadLetter {
+    pub fn new(line: u64, record: &str, error: &RecordError) -> Self {
+        let (kind, error, seq) = match error {
+            RecordError::Malformed(msg) => ("malformed", msg.clone(), None),
+            RecordError::Input(e) => ("input", format!("{:?}", e), None),
+            RecordError::Rejected(e, seq) => ("rejected", format!("{:?}", e), Some(*seq)),
+        };
+        Self {
+            line,
+            record: record.to_string(),
+            kind: kind.to_string(),
+            error,
+            seq,
+        }
+    }
+}
+
+/// Appends dead letters to a file, records from earlier runs are kept
+#[derive(Debug)]
+pub struct DeadLetterSink {
+    file: File,
+}
+
+impl DeadLetterSink {
+    pub fn new(file_path: &str) -> Result<Self, io::Error> {
+        Ok(Self {
+            file: OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(file_path)?,
+        })
+    }
+
+    pub fn append(&mut self, dead_letter: &DeadLetter) -> Result<(), io::Error> {
+        writeln!(self.file, "{}", serde_json::to_string(dead_letter)?)
+    }
+}
+
+/// Reads every dead letter in a file, in the order they were written
+pub fn read_dead_letters(file_path: &str) -> Result<Vec<DeadLetter>, io::Error> {
+    let mut dead_letters = vec![];
+    for line in BufReader::new(File::open(file_path)?).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        dead_letters.push(serde_json::from_str(&line)?);
+    }
+    Ok(dead_letters)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_dead_letters, DeadLetter, DeadLetterSink, RecordError};
+    use crate::payments_engine::TxnErrors;
+    use crate::test::utils::_get_test_output_file;
+
+    #[test]
+    fn tst_dead_letter_round_trip() {
+        let f = _get_test_output_file("tst_dead_letter_round_trip.jsonl");
+        let _ = std::fs::remove_file(&f);
+        let rejected = DeadLetter::new(
+            3,
+            "withdrawal,1,2,50.0",
+            &RecordError::Rejected(TxnErrors::AccountLacksFunds, 2),
+        );
+        let malformed = DeadLetter::new(4, "deposit,x", &RecordError::Malformed("bad".to_string()));
+        let mut sink = DeadLetterSink::new(&f).unwrap();
+        sink.append(&rejected).unwrap();
+        sink.append(&malformed).unwrap();
+
+        assert_eq!(
+            read_dead_letters(&f).unwrap(),
+            vec![rejected.clone(), malformed]
+        );
+        assert_eq!(
+            (
+                rejected.kind.as_str(),
+                rejected.error.as_str(),
+                rejected.seq
+            ),
+            ("rejected", "AccountLacksFunds", Some(2))
+        );
+    }
+}
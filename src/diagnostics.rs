@@ -0,0 +1,49 @@
+//! Diagnostics for people watching a run, always written to stderr so stdout only ever holds outputs
+
+use std::fmt;
+
+/// How serious a diagnostic is, shown as its line's prefix
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let level = match self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+        };
+        write!(f, "{}", level)
+    }
+}
+
+/// A diagnostic as it appears on stderr, e.g. `[ERROR] Failed to diff accounts: ...`
+pub fn format_line(level: Level, msg: impl fmt::Display) -> String {
+    format!("[{}] {}", level, msg)
+}
+
+/// Writes a diagnostic to stderr
+pub fn log(level: Level, msg: impl fmt::Display) {
+    eprintln!("{}", format_line(level, msg));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_line, Level};
+
+    #[test]
+    fn tst_format_line() {
+        assert_eq!(
+            format_line(
+                Level::Error,
+                format_args!("Processing stopped early: {}", 3)
+            ),
+            "[ERROR] Processing stopped early: 3"
+        );
+        assert_eq!(format_line(Level::Warn, "slow"), "[WARN] slow");
+    }
+}
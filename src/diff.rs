@@ -0,0 +1,227 @@
+use crate::amount::format_amount;
+use crate::cli_io::{csv_writer, DiffOptions};
+use csv::{ReaderBuilder, Trim, Writer};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::io::{self, ErrorKind};
+
+/// Fields of one account row keyed by column name, the client column excluded
+pub type AccountFields = BTreeMap<String, String>;
+
+/// Difference in a single field of an account present in both outputs
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldChange {
+    pub field: String,
+    pub before: String,
+    pub after: String,
+    /// After minus before, only set when both values are numeric
+    pub delta: Option<f64>,
+}
+
+/// How an account differs between two outputs
+#[derive(Debug, Clone, PartialEq)]
+pub enum AccountDiff {
+    Added(u16),
+    Removed(u16),
+    Changed {
+        client: u16,
+        changes: Vec<FieldChange>,
+    },
+}
+
+/// Reads an accounts csv as written by the engine, extended columns included if present
+pub fn read_accounts_csv(file_path: &str) -> Result<BTreeMap<u16, AccountFields>, Box<dyn Error>> {
+    let mut rdr = ReaderBuilder::new().trim(Trim::All).from_path(file_path)?;
+    let headers = rdr.headers()?.clone();
+    let client_indx = headers.iter().position(|h| h == "client").ok_or_else(|| {
+        io::Error::new(
+            ErrorKind::InvalidData,
+            format!("Missing client column in {}", file_path),
+        )
+    })?;
+
+    let mut accounts = BTreeMap::new();
+    for result in rdr.records() {
+        let record = result?;
+        let client: u16 = record[client_indx].parse()?;
+        let fields = headers
+            .iter()
+            .zip(record.iter())
+            .filter(|(header, _)| *header != "client")
+            .map(|(header, value)| (header.to_string(), value.to_string()))
+            .collect();
+        accounts.insert(client, fields);
+    }
+    Ok(accounts)
+}
+
+/// Compares the fields of an account, a column missing on one side counts as empty
+fn field_changes(before: &AccountFields, after: &AccountFields) -> Vec<FieldChange> {
+    let mut fields: Vec<&String> = before.keys().chain(after.keys()).collect();
+    fields.sort();
+    fields.dedup();
+
+    let empty = String::new();
+    fields
+        .into_iter()
+        .filter_map(|field| {
+            let before_value = before.get(field).unwrap_or(&empty);
+            let after_value = after.get(field).unwrap_or(&empty);
+            if before_value == after_value {
+                return None;
+            }
+            let delta = match (before_value.parse::<f64>(), after_value.parse::<f64>()) {
+                (Ok(b), Ok(a)) => Some(a - b),
+                _ => None,
+            };
+            Some(FieldChange {
+                field: field.clone(),
+                before: before_value.clone(),
+                after: after_value.clone(),
+                delta,
+            })
+        })
+        .collect()
+}
+
+/// Accounts added, removed, & changed going from before to after, ordered by client
+pub fn diff_accounts(
+    before: &BTreeMap<u16, AccountFields>,
+    after: &BTreeMap<u16, AccountFields>,
+) -> Vec<AccountDiff> {
+    let mut clients: Vec<&u16> = before.keys().chain(after.keys()).collect();
+    clients.sort();
+    clients.dedup();
+
+    clients
+        .into_iter()
+        .filter_map(|client| match (before.get(client), after.get(client)) {
+            (None, Some(_)) => Some(AccountDiff::Added(*client)),
+            (Some(_), None) => Some(AccountDiff::Removed(*client)),
+            (Some(b), Some(a)) => {
+                let changes = field_changes(b, a);
+                match changes.is_empty() {
+                    true => None,
+                    false => Some(AccountDiff::Changed {
+                        client: *client,
+                        changes,
+                    }),
+                }
+            }
+            (None, None) => None,
+        })
+        .collect()
+}
+
+/// Writes a diff report of the two account outputs
+pub fn diff_execute(options: &DiffOptions) -> Result<(), Box<dyn Error>> {
+    let before = read_accounts_csv(&options.before)?;
+    let after = read_accounts_csv(&options.after)?;
+    let diffs = diff_accounts(&before, &after);
+    write_diff_report(csv_writer(&options.output)?, &diffs)
+}
+
+/// Writes one row per added or removed account & per changed field
+pub fn write_diff_report<W: io::Write>(
+    mut wtr: Writer<W>,
+    diffs: &[AccountDiff],
+) -> Result<(), Box<dyn Error>> {
+    wtr.write_record(["change", "client", "field", "before", "after", "delta"])?;
+    for diff in diffs.iter() {
+        match diff {
+            AccountDiff::Added(client) => {
+                wtr.write_record(["added", &client.to_string(), "", "", "", ""])?
+            }
+            AccountDiff::Removed(client) => {
+                wtr.write_record(["removed", &client.to_string(), "", "", "", ""])?
+            }
+            AccountDiff::Changed { client, changes } => {
+                for change in changes {
+                    let delta = change.delta.map_or(String::new(), format_amount);
+                    wtr.write_record([
+                        "changed",
+                        &client.to_string(),
+                        &change.field,
+                        &change.before,
+                        &change.after,
+                        &delta,
+                    ])?;
+                }
+            }
+        }
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{diff_accounts, diff_execute, read_accounts_csv, AccountDiff, FieldChange};
+    use crate::cli_io::{DiffOptions, OutputMethod};
+    use crate::test::utils::_get_test_output_file;
+
+    #[test]
+    fn tst_diff_accounts() {
+        let f_before = _get_test_output_file("tst_diff_accounts_before.csv");
+        let f_after = _get_test_output_file("tst_diff_accounts_after.csv");
+        std::fs::write(
+            &f_before,
+            "client,available,held,total,locked\n\
+             1,1.0000,0.0000,1.0000,false\n\
+             2,2.0000,0.0000,2.0000,false\n\
+             3,3.0000,0.0000,3.0000,false\n",
+        )
+        .unwrap();
+        std::fs::write(
+            &f_after,
+            "client,available,held,total,locked\n\
+             1,1.0000,0.0000,1.0000,false\n\
+             3,1.5000,1.5000,3.0000,true\n\
+             4,4.0000,0.0000,4.0000,false\n",
+        )
+        .unwrap();
+
+        let before = read_accounts_csv(&f_before).unwrap();
+        let after = read_accounts_csv(&f_after).unwrap();
+        let diffs = diff_accounts(&before, &after);
+        assert_eq!(diffs.len(), 3);
+        assert_eq!(diffs[0], AccountDiff::Removed(2));
+        assert_eq!(diffs[2], AccountDiff::Added(4));
+        match &diffs[1] {
+            AccountDiff::Changed { client, changes } => {
+                assert_eq!(*client, 3);
+                assert_eq!(
+                    changes[0],
+                    FieldChange {
+                        field: "available".to_string(),
+                        before: "3.0000".to_string(),
+                        after: "1.5000".to_string(),
+                        delta: Some(-1.5),
+                    }
+                );
+                assert_eq!(changes[2].field, "locked");
+                assert_eq!(changes[2].delta, None, "Non numeric fields have no delta");
+                assert_eq!(changes.len(), 3, "Unchanged total should not be reported");
+            }
+            _ => panic!("Client 3 should have changed"),
+        }
+
+        let f_report = _get_test_output_file("tst_diff_accounts_report.csv");
+        let options = DiffOptions {
+            before: f_before,
+            after: f_after,
+            output: OutputMethod::Csv(f_report.clone()),
+        };
+        assert!(diff_execute(&options).is_ok());
+        let report = std::fs::read_to_string(&f_report).unwrap();
+        assert_eq!(
+            report,
+            "change,client,field,before,after,delta\n\
+             removed,2,,,,\n\
+             changed,3,available,3.0000,1.5000,-1.5000\n\
+             changed,3,held,0.0000,1.5000,1.5000\n\
+             changed,3,locked,false,true,\n\
+             added,4,,,,\n"
+        );
+    }
+}
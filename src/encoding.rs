@@ -0,0 +1,252 @@
+//! Text encodings of partner input files, transcoded to UTF-8 in front of the csv reader
+//! Files starting with a byte order mark are read in the encoding it marks, UTF-16 without one is
+//! recognized by the zero bytes of its ascii characters, anything else is read as UTF-8
+
+use std::io::{self, BufRead, BufReader, ErrorKind, Read};
+use std::str::FromStr;
+
+/// Bytes sniffed for zero bytes when a file has no byte order mark
+const SNIFF_LEN: usize = 64;
+const UTF8_BOM: [u8; 3] = [0xef, 0xbb, 0xbf];
+const UTF16LE_BOM: [u8; 2] = [0xff, 0xfe];
+const UTF16BE_BOM: [u8; 2] = [0xfe, 0xff];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputEncoding {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    /// ISO 8859-1, every byte is the code point of the same value
+    Latin1,
+}
+
+impl FromStr for InputEncoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "utf-8" | "utf8" => Ok(InputEncoding::Utf8),
+            "utf-16le" | "utf16le" => Ok(InputEncoding::Utf16Le),
+            "utf-16be" | "utf16be" => Ok(InputEncoding::Utf16Be),
+            "latin1" | "iso-8859-1" => Ok(InputEncoding::Latin1),
+            _ => Err(format!("Unknown encoding '{}'", s)),
+        }
+    }
+}
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, msg)
+}
+
+/// Encoding & length of the byte order mark the input starts with, if any
+fn sniff_bom(start: &[u8]) -> Option<(InputEncoding, usize)> {
+    if start.starts_with(&UTF8_BOM) {
+        Some((InputEncoding::Utf8, UTF8_BOM.len()))
+    } else if start.starts_with(&UTF16LE_BOM) {
+        Some((InputEncoding::Utf16Le, UTF16LE_BOM.len()))
+    } else if start.starts_with(&UTF16BE_BOM) {
+        Some((InputEncoding::Utf16Be, UTF16BE_BOM.len()))
+    } else {
+        None
+    }
+}
+
+/// Guesses the encoding of input without a byte order mark from where its zero bytes are
+/// Errors on zero bytes which don't look like UTF-16, such input isn't text in any supported encoding
+fn sniff_zero_bytes(start: &[u8]) -> Result<InputEncoding, io::Error> {
+    let pairs = start.len() / 2;
+    let zeros_at = |parity: usize| {
+        start
+            .chunks_exact(2)
+            .filter(|pair| pair[parity] == 0)
+            .count()
+    };
+    match (zeros_at(0), zeros_at(1)) {
+        (0, 0) => Ok(InputEncoding::Utf8),
+        (0, odd) if odd * 2 >= pairs => Ok(InputEncoding::Utf16Le),
+        (even, 0) if even * 2 >= pairs => Ok(InputEncoding::Utf16Be),
+        _ => Err(invalid_data(
+            "Input holds zero bytes but isn't UTF-16, pass --encoding if it is text".to_string(),
+        )),
+    }
+}
+
+/// Reads input as UTF-8 whatever encoding it was written in
+pub struct DecodingReader<R> {
+    inner: BufReader<R>,
+    encoding: InputEncoding,
+    /// Decoded bytes not handed out yet
+    decoded: Vec<u8>,
+    decoded_pos: usize,
+    /// High surrogate waiting for the low one which completes it
+    high_surrogate: Option<u16>,
+    /// Odd byte of a UTF-16 unit split across reads
+    odd_byte: Option<u8>,
+    /// Bytes of input consumed, to point at undecodable input
+    offset: u64,
+}
+
+impl<R: Read> DecodingReader<R> {
+    /// Detects the encoding unless `encoding` is given, a byte order mark is skipped either way
+    /// Errors if the input is in neither a detectable nor the given encoding
+    pub fn new(reader: R, encoding: Option<InputEncoding>) -> Result<Self, io::Error> {
+        let mut inner = BufReader::new(reader);
+        let start = inner.fill_buf()?;
+        let bom = sniff_bom(start);
+        let encoding = match (encoding, bom) {
+            (Some(encoding), Some((marked, _))) if encoding != marked => {
+                return Err(invalid_data(format!(
+                    "Input marks itself as {:?} but {:?} was given",
+                    marked, encoding
+                )))
+            }
+            (Some(encoding), _) => encoding,
+            (None, Some((marked, _))) => marked,
+            (None, None) => sniff_zero_bytes(&start[..start.len().min(SNIFF_LEN)])?,
+        };
+        let bom_len = bom.map_or(0, |(_, len)| len);
+        inner.consume(bom_len);
+        Ok(Self {
+            inner,
+            encoding,
+            decoded: vec![],
+            decoded_pos: 0,
+            high_surrogate: None,
+            odd_byte: None,
+            offset: bom_len as u64,
+        })
+    }
+
+    pub fn encoding(&self) -> InputEncoding {
+        self.encoding
+    }
+
+    fn push_char(&mut self, c: char) {
+        let mut buf = [0u8; 4];
+        self.decoded
+            .extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+    }
+
+    fn push_utf16_unit(&mut self, unit: u16) -> Result<(), io::Error> {
+        let undecodable =
+            |offset: u64| invalid_data(format!("Input isn't valid UTF-16 at byte {}", offset));
+        match (self.high_surrogate.take(), unit) {
+            (None, 0xd800..=0xdbff) => self.high_surrogate = Some(unit),
+            (Some(high), 0xdc00..=0xdfff) => {
+                let code = 0x10000 + (((high as u32) - 0xd800) << 10) + (unit as u32 - 0xdc00);
+                let c = char::from_u32(code).ok_or_else(|| undecodable(self.offset))?;
+                self.push_char(c);
+            }
+            (None, _) => {
+                let c = char::from_u32(unit as u32).ok_or_else(|| undecodable(self.offset))?;
+                self.push_char(c);
+            }
+            (Some(_), _) => return Err(undecodable(self.offset)),
+        }
+        Ok(())
+    }
+
+    /// Decodes the next chunk of input, returns false once the input is exhausted
+    fn decode_chunk(&mut self) -> Result<bool, io::Error> {
+        self.decoded.clear();
+        self.decoded_pos = 0;
+        let chunk = self.inner.fill_buf()?.to_vec();
+        if chunk.is_empty() {
+            if self.odd_byte.is_some() || self.high_surrogate.is_some() {
+                return Err(invalid_data(format!(
+                    "Input ends in the middle of a UTF-16 character at byte {}",
+                    self.offset
+                )));
+            }
+            return Ok(false);
+        }
+        self.inner.consume(chunk.len());
+        match self.encoding {
+            InputEncoding::Utf8 => self.decoded = chunk,
+            InputEncoding::Latin1 => {
+                for byte in chunk {
+                    self.push_char(byte as char);
+                }
+            }
+            InputEncoding::Utf16Le | InputEncoding::Utf16Be => {
+                for byte in chunk {
+                    let first = match self.odd_byte.take() {
+                        Some(first) => first,
+                        None => {
+                            self.odd_byte = Some(byte);
+                            continue;
+                        }
+                    };
+                    let unit = match self.encoding {
+                        InputEncoding::Utf16Le => u16::from_le_bytes([first, byte]),
+                        _ => u16::from_be_bytes([first, byte]),
+                    };
+                    self.push_utf16_unit(unit)?;
+                    self.offset += 2;
+                }
+                return Ok(true);
+            }
+        }
+        self.offset += self.decoded.len() as u64;
+        Ok(true)
+    }
+}
+
+impl<R: Read> Read for DecodingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // A chunk may decode to nothing, e.g. a lone high surrogate, so keep going until it doesn't
+        while self.decoded_pos == self.decoded.len() {
+            if !self.decode_chunk()? {
+                return Ok(0);
+            }
+        }
+        let n = buf.len().min(self.decoded.len() - self.decoded_pos);
+        buf[..n].copy_from_slice(&self.decoded[self.decoded_pos..self.decoded_pos + n]);
+        self.decoded_pos += n;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DecodingReader, InputEncoding};
+    use std::io::Read;
+
+    fn decode(bytes: &[u8], encoding: Option<InputEncoding>) -> Result<String, std::io::Error> {
+        let mut decoded = String::new();
+        DecodingReader::new(bytes, encoding)?.read_to_string(&mut decoded)?;
+        Ok(decoded)
+    }
+
+    fn utf16(text: &str, little_endian: bool) -> Vec<u8> {
+        text.encode_utf16()
+            .flat_map(|unit| match little_endian {
+                true => unit.to_le_bytes(),
+                false => unit.to_be_bytes(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn tst_decode_encodings() {
+        let text = "type,client,tx,amount,memo\ndeposit,1,1,1.5,Zürich 𝄞\n";
+        let mut le = vec![0xff, 0xfe];
+        le.extend(utf16(text, true));
+        assert_eq!(decode(&le, None).unwrap(), text);
+        // Without a byte order mark the zero bytes give UTF-16 away
+        assert_eq!(decode(&utf16(text, false), None).unwrap(), text);
+
+        let mut utf8 = vec![0xef, 0xbb, 0xbf];
+        utf8.extend(text.as_bytes());
+        assert_eq!(decode(&utf8, None).unwrap(), text);
+        assert_eq!(
+            decode(b"memo\n\xfcber\n", Some(InputEncoding::Latin1)).unwrap(),
+            "memo\nüber\n"
+        );
+
+        assert!(decode(&le, Some(InputEncoding::Utf8)).is_err());
+        assert!(decode(b"type\0\0\0,client", None).is_err());
+        let truncated = &utf16(text, true)[..5];
+        assert!(decode(truncated, Some(InputEncoding::Utf16Le)).is_err());
+    }
+}
@@ -0,0 +1,54 @@
+//! Synthetic workloads for load tests & soaks
+//! Generating them needs the `gen` feature, which pulls in rand, scenarios are always known so options parse
+
+use std::str::FromStr;
+
+#[cfg(any(test, feature = "gen"))]
+mod workload;
+#[cfg(any(test, feature = "gen"))]
+pub use workload::{gen_execute, generate_scenario, seeded_rng, ScenarioStream};
+
+/// Shapes of synthetic workloads the generator can produce
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Scenario {
+    /// Mostly deposits & withdrawals with the occasional dispute, resolve, or chargeback
+    Mixed,
+    /// A large share of deposits get disputed, with only some of them resolved
+    DisputeStorm,
+    /// Disputes followed by chargebacks freezing most accounts, then continued traffic on frozen accounts
+    ChargebackWave,
+    /// Upstream retries, records are re-sent with the same txn id shortly after the original
+    DuplicateRetries,
+    /// A valid stream whose records are shuffled locally so references can arrive before their targets
+    OutOfOrder,
+    /// Mixed traffic without chargebacks, accounts never freeze so it can run indefinitely
+    Steady,
+}
+
+impl FromStr for Scenario {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mixed" => Ok(Scenario::Mixed),
+            "dispute-storm" => Ok(Scenario::DisputeStorm),
+            "chargeback-wave" => Ok(Scenario::ChargebackWave),
+            "duplicate-retries" => Ok(Scenario::DuplicateRetries),
+            "out-of-order" => Ok(Scenario::OutOfOrder),
+            "steady" => Ok(Scenario::Steady),
+            _ => Err(format!("Unknown scenario '{}'", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Scenario;
+
+    #[test]
+    fn tst_scenario_from_str() {
+        assert_eq!("dispute-storm".parse(), Ok(Scenario::DisputeStorm));
+        assert_eq!("out-of-order".parse(), Ok(Scenario::OutOfOrder));
+        assert!("storm".parse::<Scenario>().is_err());
+    }
+}
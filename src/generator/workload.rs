@@ -0,0 +1,323 @@
+use super::Scenario;
+use crate::amount::{Amount, TxnAmount};
+use crate::cli_io::{output_txns_csv, GenOptions};
+use crate::diagnostics::{log, Level};
+use crate::transaction::{DisputeHistory, PureTxn, RefTxn, Transaction};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use std::error::Error;
+
+/// Deposits & disputes a stream remembers as targets for later records
+const MAX_TRACKED_TXNS: usize = 100_000;
+
+/// Keeps track of generated ids so referential records can point at real deposits
+struct GenState {
+    next_txn_id: u32,
+    clients: u16,
+    /// Deposits which could still be disputed
+    deposits: Vec<(u32, u16)>,
+    /// Deposits which have been disputed but not yet resolved or charged back
+    disputed: Vec<(u32, u16)>,
+}
+
+impl GenState {
+    fn new(clients: u16) -> Self {
+        Self {
+            next_txn_id: 1,
+            clients: clients.max(1),
+            deposits: vec![],
+            disputed: vec![],
+        }
+    }
+
+    /// Random amount of minor units divided by `divisor`, rounded half away from zero
+    fn random_amount(rng: &mut impl Rng, divisor: i64) -> TxnAmount {
+        let minor: i64 = rng.gen_range(1..=1_000_000);
+        TxnAmount::new(Amount::from_minor_units((minor + divisor / 2) / divisor))
+    }
+
+    fn deposit(&mut self, rng: &mut impl Rng) -> Transaction {
+        let acnt_id = rng.gen_range(1..=self.clients);
+        let txn_id = self.next_txn_id;
+        self.next_txn_id += 1;
+        self.deposits.push((txn_id, acnt_id));
+        Transaction::Deposit(PureTxn {
+            txn_id,
+            acnt_id,
+            amount: Self::random_amount(rng, 1),
+            dispute: DisputeHistory::default(),
+            memo: None,
+            channel: None,
+        })
+    }
+
+    fn withdrawal(&mut self, rng: &mut impl Rng) -> Transaction {
+        let acnt_id = rng.gen_range(1..=self.clients);
+        let txn_id = self.next_txn_id;
+        self.next_txn_id += 1;
+        Transaction::Withdrawal(PureTxn {
+            txn_id,
+            acnt_id,
+            amount: Self::random_amount(rng, 10),
+            dispute: DisputeHistory::default(),
+            memo: None,
+            channel: None,
+        })
+    }
+
+    /// Disputes a random undisputed deposit, falls back to a deposit if none are left
+    fn dispute(&mut self, rng: &mut impl Rng) -> Transaction {
+        if self.deposits.is_empty() {
+            return self.deposit(rng);
+        }
+        let indx = rng.gen_range(0..self.deposits.len());
+        let (ref_id, acnt_id) = self.deposits.swap_remove(indx);
+        self.disputed.push((ref_id, acnt_id));
+        Transaction::Dispute(RefTxn { ref_id, acnt_id })
+    }
+
+    /// Settles a random open dispute, falls back to a dispute if none are open
+    fn settle(&mut self, rng: &mut impl Rng, chargeback: bool) -> Transaction {
+        if self.disputed.is_empty() {
+            return self.dispute(rng);
+        }
+        let indx = rng.gen_range(0..self.disputed.len());
+        let (ref_id, acnt_id) = self.disputed.swap_remove(indx);
+        let ref_txn = RefTxn { ref_id, acnt_id };
+        if chargeback {
+            Transaction::Chargeback(ref_txn)
+        } else {
+            self.deposits.push((ref_id, acnt_id));
+            Transaction::Resolve(ref_txn)
+        }
+    }
+
+    /// Like `mixed` with every dispute resolved
+    fn steady(&mut self, rng: &mut impl Rng) -> Transaction {
+        match rng.gen_range(0..100) {
+            0..=59 => self.deposit(rng),
+            60..=89 => self.withdrawal(rng),
+            90..=95 => self.dispute(rng),
+            _ => self.settle(rng, false),
+        }
+    }
+
+    fn mixed(&mut self, rng: &mut impl Rng) -> Transaction {
+        match rng.gen_range(0..100) {
+            0..=59 => self.deposit(rng),
+            60..=89 => self.withdrawal(rng),
+            90..=95 => self.dispute(rng),
+            96..=98 => self.settle(rng, false),
+            _ => self.settle(rng, true),
+        }
+    }
+}
+
+/// Endless supply of a scenario's records in batches, txn ids keep counting up across batches
+pub struct ScenarioStream {
+    scenario: Scenario,
+    state: GenState,
+}
+
+impl ScenarioStream {
+    pub fn new(scenario: Scenario, clients: u16) -> Self {
+        Self {
+            scenario,
+            state: GenState::new(clients),
+        }
+    }
+
+    /// Txn ids not handed out yet, a batch uses at most one per record
+    pub fn txn_ids_left(&self) -> u32 {
+        u32::MAX - self.state.next_txn_id
+    }
+
+    /// Generates the next `records` records, each batch has the full shape of the scenario
+    /// Only the latest deposits are kept as dispute targets so a long running stream doesn't grow
+    pub fn next_batch(&mut self, records: usize, rng: &mut impl Rng) -> Vec<Transaction> {
+        let txns = scenario_batch(&mut self.state, self.scenario, records, rng);
+        for tracked in [&mut self.state.deposits, &mut self.state.disputed] {
+            let excess = tracked.len().saturating_sub(MAX_TRACKED_TXNS);
+            tracked.drain(..excess);
+        }
+        txns
+    }
+}
+
+/// Rng of a run, seeded with `seed` or a random seed which is logged so the run can be repeated with `--seed`
+/// A seed reproduces its records on builds with the same rand version
+pub fn seeded_rng(seed: Option<u64>) -> StdRng {
+    let seed = seed.unwrap_or_else(|| {
+        let seed = rand::thread_rng().gen();
+        log(Level::Info, format_args!("Generating with seed {}", seed));
+        seed
+    });
+    StdRng::seed_from_u64(seed)
+}
+
+/// Generates the transactions for a scenario
+/// Output is a sequence of records as they would appear in an input file, which may not all be valid
+pub fn generate_scenario(options: &GenOptions, rng: &mut impl Rng) -> Vec<Transaction> {
+    scenario_batch(
+        &mut GenState::new(options.clients),
+        options.scenario,
+        options.records,
+        rng,
+    )
+}
+
+fn scenario_batch(
+    state: &mut GenState,
+    scenario: Scenario,
+    records: usize,
+    rng: &mut impl Rng,
+) -> Vec<Transaction> {
+    let mut txns = Vec::with_capacity(records);
+    match scenario {
+        Scenario::Mixed => {
+            while txns.len() < records {
+                txns.push(state.mixed(rng));
+            }
+        }
+        Scenario::DisputeStorm => {
+            // Seed with deposits so the storm has something to dispute
+            let seed_count = records / 2;
+            while txns.len() < seed_count {
+                txns.push(state.deposit(rng));
+            }
+            while txns.len() < records {
+                let txn = match rng.gen_range(0..10) {
+                    0..=6 => state.dispute(rng),
+                    7..=8 => state.settle(rng, false),
+                    _ => state.deposit(rng),
+                };
+                txns.push(txn);
+            }
+        }
+        Scenario::ChargebackWave => {
+            let seed_count = records * 2 / 5;
+            while txns.len() < seed_count {
+                txns.push(state.deposit(rng));
+            }
+            let dispute_count = records * 3 / 5;
+            while txns.len() < dispute_count {
+                txns.push(state.dispute(rng));
+            }
+            let chargeback_count = records * 4 / 5;
+            while txns.len() < chargeback_count {
+                txns.push(state.settle(rng, true));
+            }
+            // Traffic after the wave lands mostly on frozen accounts
+            while txns.len() < records {
+                txns.push(state.mixed(rng));
+            }
+        }
+        Scenario::DuplicateRetries => {
+            while txns.len() < records {
+                let txn = state.mixed(rng);
+                let is_pure = matches!(txn, Transaction::Deposit(_) | Transaction::Withdrawal(_));
+                txns.push(txn.clone());
+                // Roughly a quarter of pure records get retried with the same txn id
+                if is_pure && txns.len() < records && rng.gen_bool(0.25) {
+                    txns.push(txn);
+                }
+            }
+        }
+        Scenario::Steady => {
+            while txns.len() < records {
+                txns.push(state.steady(rng));
+            }
+        }
+        Scenario::OutOfOrder => {
+            while txns.len() < records {
+                txns.push(state.mixed(rng));
+            }
+            for window in txns.chunks_mut(8) {
+                window.shuffle(rng);
+            }
+        }
+    }
+    txns
+}
+
+/// Generates a scenario and writes it to the configured output
+pub fn gen_execute(options: &GenOptions) -> Result<(), Box<dyn Error>> {
+    let mut rng = seeded_rng(options.seed);
+    let txns = generate_scenario(options, &mut rng);
+    output_txns_csv(&txns, &options.output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{generate_scenario, seeded_rng};
+    use crate::cli_io::{GenOptions, OutputMethod};
+    use crate::generator::Scenario;
+    use crate::payments_engine::PaymentsEngine;
+    use crate::transaction::Transaction;
+    use std::collections::HashSet;
+
+    fn gen_options(scenario: Scenario) -> GenOptions {
+        GenOptions {
+            scenario,
+            records: 200,
+            clients: 10,
+            seed: None,
+            output: OutputMethod::StdOutput,
+        }
+    }
+
+    #[test]
+    fn tst_generate_scenario_sizes() {
+        let mut rng = seeded_rng(Some(1));
+        for scenario in [
+            Scenario::Mixed,
+            Scenario::DisputeStorm,
+            Scenario::ChargebackWave,
+            Scenario::DuplicateRetries,
+            Scenario::OutOfOrder,
+            Scenario::Steady,
+        ] {
+            let txns = generate_scenario(&gen_options(scenario), &mut rng);
+            assert_eq!(txns.len(), 200, "{:?} should honor record count", scenario);
+        }
+    }
+
+    #[test]
+    fn tst_generate_scenario_shapes() {
+        let mut rng = seeded_rng(Some(2));
+        let txns = generate_scenario(&gen_options(Scenario::ChargebackWave), &mut rng);
+        let mut payments_engine = PaymentsEngine::new();
+        for txn in txns.iter() {
+            let _ = payments_engine.process_txn(txn);
+        }
+        assert!(
+            payments_engine
+                .accounts
+                .iter()
+                .any(|a| a.locked_by_chargeback),
+            "Chargeback wave should freeze accounts"
+        );
+
+        let txns = generate_scenario(&gen_options(Scenario::DuplicateRetries), &mut rng);
+        let mut seen = HashSet::new();
+        let duplicates = txns
+            .iter()
+            .filter(|txn| match txn {
+                Transaction::Deposit(p_txn) | Transaction::Withdrawal(p_txn) => {
+                    !seen.insert(p_txn.txn_id)
+                }
+                _ => false,
+            })
+            .count();
+        assert!(duplicates > 0, "Duplicate retries should repeat txn ids");
+    }
+
+    #[test]
+    fn tst_seeded_scenarios_repeat() {
+        let options = gen_options(Scenario::OutOfOrder);
+        let txns = generate_scenario(&options, &mut seeded_rng(Some(42)));
+        assert_eq!(generate_scenario(&options, &mut seeded_rng(Some(42))), txns);
+        assert_ne!(generate_scenario(&options, &mut seeded_rng(Some(43))), txns);
+    }
+}
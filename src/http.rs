@@ -0,0 +1,367 @@
+//! Minimal blocking HTTP/1.1 client, only plain `http://` urls are supported
+//! Enough for talking to internal services without pulling in a TLS stack
+
+use std::io::{self, BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+/// Largest response body read, a bigger one fails the request rather than growing without bound
+pub const MAX_BODY_BYTES: u64 = 1 << 20;
+/// Longest status or header line read
+const MAX_LINE_BYTES: u64 = 8 * 1024;
+
+/// Pieces of an http url needed to make a request
+#[derive(Debug, PartialEq)]
+struct HttpUrl {
+    host: String,
+    port: u16,
+    path: String,
+}
+
+fn parse_url(url: &str) -> Result<HttpUrl, io::Error> {
+    let rest = url.strip_prefix("http://").ok_or_else(|| {
+        io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("Only http:// urls are supported, got '{}'", url),
+        )
+    })?;
+    let (authority, path) = match rest.find('/') {
+        Some(indx) => (&rest[..indx], &rest[indx..]),
+        None => (rest, "/"),
+    };
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) => (
+            host,
+            port.parse()
+                .map_err(|_| io::Error::new(ErrorKind::InvalidInput, "Invalid port in url"))?,
+        ),
+        None => (authority, 80),
+    };
+    Ok(HttpUrl {
+        host: host.to_string(),
+        port,
+        path: path.to_string(),
+    })
+}
+
+impl HttpUrl {
+    /// Value of the `Host` header, the port is left out when it's the default one
+    fn host_header(&self) -> String {
+        match self.port {
+            80 => self.host.clone(),
+            port => format!("{}:{}", self.host, port),
+        }
+    }
+}
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, msg)
+}
+
+/// Connects to the first address of the host which accepts within the timeout
+fn connect(url: &HttpUrl, timeout: Duration) -> Result<TcpStream, io::Error> {
+    let mut last_err = None;
+    for addr in (url.host.as_str(), url.port).to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_err = Some(e),
+        }
+    }
+    Err(last_err.unwrap_or_else(|| {
+        io::Error::new(
+            ErrorKind::NotFound,
+            format!("No address found for {}", url.host),
+        )
+    }))
+}
+
+/// Reads a status, header, or chunk size line, erroring if it's longer than `MAX_LINE_BYTES`
+fn read_line<R: BufRead>(rdr: &mut R, line: &mut String) -> Result<usize, io::Error> {
+    line.clear();
+    let read = rdr.take(MAX_LINE_BYTES).read_line(line)?;
+    if read as u64 == MAX_LINE_BYTES && !line.ends_with('\n') {
+        return Err(invalid_data(format!(
+            "Http line longer than {} bytes",
+            MAX_LINE_BYTES
+        )));
+    }
+    Ok(read)
+}
+
+/// Reads up to `len` bytes of body, erroring if that passes `MAX_BODY_BYTES` or the body ends early
+fn read_exact_body<R: Read>(rdr: &mut R, len: u64, body: &mut Vec<u8>) -> Result<(), io::Error> {
+    if body.len() as u64 + len > MAX_BODY_BYTES {
+        return Err(body_too_large());
+    }
+    let read = rdr.take(len).read_to_end(body)?;
+    if (read as u64) < len {
+        return Err(io::Error::new(
+            ErrorKind::UnexpectedEof,
+            "Http body ended early",
+        ));
+    }
+    Ok(())
+}
+
+fn body_too_large() -> io::Error {
+    invalid_data(format!("Http body larger than {} bytes", MAX_BODY_BYTES))
+}
+
+/// Reads a `Transfer-Encoding: chunked` body, trailers after the last chunk are skipped
+fn read_chunked_body<R: BufRead>(rdr: &mut R) -> Result<Vec<u8>, io::Error> {
+    let mut body = vec![];
+    let mut line = String::new();
+    loop {
+        read_line(rdr, &mut line)?;
+        let size = line.split(';').next().unwrap_or("").trim();
+        let size = u64::from_str_radix(size, 16)
+            .map_err(|_| invalid_data(format!("Malformed http chunk size '{}'", size)))?;
+        if size == 0 {
+            break;
+        }
+        read_exact_body(rdr, size, &mut body)?;
+        read_line(rdr, &mut line)?;
+    }
+    while read_line(rdr, &mut line)? > 0 && line != "\r\n" {}
+    Ok(body)
+}
+
+/// Status & body of a response
+#[derive(Debug, Clone, PartialEq)]
+pub struct HttpResponse {
+    pub status: u16,
+    pub body: String,
+}
+
+/// Sends a request & reads the status line, returns the status & the reader positioned at the headers
+fn send_request(
+    method: &str,
+    url: &str,
+    body: Option<&str>,
+    timeout: Duration,
+) -> Result<(u16, BufReader<TcpStream>), io::Error> {
+    let url = parse_url(url)?;
+    let mut stream = connect(&url, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+
+    let mut request = format!(
+        "{} {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n",
+        method,
+        url.path,
+        url.host_header()
+    );
+    if let Some(body) = body {
+        request.push_str(&format!(
+            "Content-Type: application/json\r\nContent-Length: {}\r\n",
+            body.len()
+        ));
+    }
+    request.push_str("\r\n");
+    request.push_str(body.unwrap_or(""));
+    stream.write_all(request.as_bytes())?;
+
+    let mut rdr = BufReader::new(stream);
+    let mut status_line = String::new();
+    read_line(&mut rdr, &mut status_line)?;
+    let status = status_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse().ok())
+        .ok_or_else(|| io::Error::new(ErrorKind::InvalidData, "Malformed http status line"))?;
+    Ok((status, rdr))
+}
+
+/// Posts a json body to a url, returning the response status
+pub fn post_json(url: &str, body: &str, timeout: Duration) -> Result<u16, io::Error> {
+    send_request("POST", url, Some(body), timeout).map(|(status, _)| status)
+}
+
+/// Gets a url, returning the response status & body
+/// The body is read up to its content length, chunk by chunk when chunked, or until the server closes the
+/// connection without either.  Errors if the body is larger than `MAX_BODY_BYTES`
+pub fn get(url: &str, timeout: Duration) -> Result<HttpResponse, io::Error> {
+    let (status, mut rdr) = send_request("GET", url, None, timeout)?;
+    let mut content_length = None;
+    let mut chunked = false;
+    let mut line = String::new();
+    loop {
+        if read_line(&mut rdr, &mut line)? == 0 || line == "\r\n" {
+            break;
+        }
+        let header = line.to_lowercase();
+        if let Some(len) = header.strip_prefix("content-length:") {
+            content_length = len.trim().parse::<u64>().ok();
+        }
+        if let Some(encoding) = header.strip_prefix("transfer-encoding:") {
+            chunked = encoding.trim().ends_with("chunked");
+        }
+    }
+    let body = match (chunked, content_length) {
+        (true, _) => read_chunked_body(&mut rdr)?,
+        (false, Some(len)) => {
+            let mut body = vec![];
+            read_exact_body(&mut rdr, len, &mut body)?;
+            body
+        }
+        (false, None) => {
+            let mut body = vec![];
+            rdr.take(MAX_BODY_BYTES + 1).read_to_end(&mut body)?;
+            if body.len() as u64 > MAX_BODY_BYTES {
+                return Err(body_too_large());
+            }
+            body
+        }
+    };
+    let body = String::from_utf8(body)
+        .map_err(|_| invalid_data("Http body isn't valid utf-8".to_string()))?;
+    Ok(HttpResponse { status, body })
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::{get, parse_url, post_json, HttpResponse, HttpUrl, MAX_BODY_BYTES};
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::thread::{self, JoinHandle};
+    use std::time::{Duration, Instant};
+
+    /// Serves one canned response per status given, returns the url & the received request bodies
+    pub fn serve_responses(statuses: Vec<u16>) -> (String, JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let handle = thread::spawn(move || {
+            let mut bodies = vec![];
+            for status in statuses {
+                let (stream, _) = listener.accept().unwrap();
+                let mut rdr = BufReader::new(stream);
+                let mut content_length = 0;
+                let mut line = String::new();
+                loop {
+                    line.clear();
+                    rdr.read_line(&mut line).unwrap();
+                    if line == "\r\n" {
+                        break;
+                    }
+                    if let Some(len) = line.to_lowercase().strip_prefix("content-length: ") {
+                        content_length = len.trim().parse().unwrap();
+                    }
+                }
+                let mut body = vec![0; content_length];
+                rdr.read_exact(&mut body).unwrap();
+                bodies.push(String::from_utf8(body).unwrap());
+                let response = format!("HTTP/1.1 {} X\r\nContent-Length: 2\r\n\r\nok", status);
+                rdr.get_mut().write_all(response.as_bytes()).unwrap();
+            }
+            bodies
+        });
+        (url, handle)
+    }
+
+    #[test]
+    fn tst_parse_url() {
+        assert_eq!(
+            parse_url("http://localhost:8080/a/b").unwrap(),
+            HttpUrl {
+                host: "localhost".to_string(),
+                port: 8080,
+                path: "/a/b".to_string()
+            }
+        );
+        assert_eq!(
+            parse_url("http://example.com").unwrap(),
+            HttpUrl {
+                host: "example.com".to_string(),
+                port: 80,
+                path: "/".to_string()
+            }
+        );
+        assert!(parse_url("https://example.com").is_err());
+    }
+
+    #[test]
+    fn tst_post_json() {
+        let (url, handle) = serve_responses(vec![201]);
+        let status = post_json(&url, "{\"a\":1}", Duration::from_secs(5)).unwrap();
+        assert_eq!(status, 201);
+        assert_eq!(handle.join().unwrap(), vec!["{\"a\":1}".to_string()]);
+    }
+
+    #[test]
+    fn tst_get() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/balances/1", listener.local_addr().unwrap());
+        let handle = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut rdr = BufReader::new(stream);
+            let mut request_line = String::new();
+            rdr.read_line(&mut request_line).unwrap();
+            let response = "HTTP/1.1 200 OK\r\nContent-Length: 7\r\n\r\n{\"a\":1}trailing";
+            rdr.get_mut().write_all(response.as_bytes()).unwrap();
+            request_line
+        });
+        assert_eq!(
+            get(&url, Duration::from_secs(5)).unwrap(),
+            HttpResponse {
+                status: 200,
+                body: "{\"a\":1}".to_string()
+            }
+        );
+        assert_eq!(handle.join().unwrap(), "GET /balances/1 HTTP/1.1\r\n");
+    }
+
+    /// Answers one request with a raw response, returns the url & the request's head
+    fn serve_raw(response: String) -> (String, JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/balances/1", listener.local_addr().unwrap());
+        let handle = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut rdr = BufReader::new(stream);
+            let mut head = String::new();
+            while rdr.read_line(&mut head).unwrap() > 2 {}
+            let _ = rdr.get_mut().write_all(response.as_bytes());
+            head
+        });
+        (url, handle)
+    }
+
+    #[test]
+    fn tst_get_chunked() {
+        let (url, handle) = serve_raw(
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+             4\r\n{\"a\"\r\n3;ext=1\r\n:1}\r\n0\r\nX-Trailer: 1\r\n\r\n"
+                .to_string(),
+        );
+        assert_eq!(get(&url, Duration::from_secs(5)).unwrap().body, "{\"a\":1}");
+        // The port is part of the host header whenever it isn't 80
+        let port = url.split(':').nth(2).unwrap().split('/').next().unwrap();
+        assert!(handle
+            .join()
+            .unwrap()
+            .contains(&format!("Host: 127.0.0.1:{}\r\n", port)));
+    }
+
+    #[test]
+    fn tst_get_body_cap() {
+        let oversized = MAX_BODY_BYTES + 1;
+        let (url, _handle) = serve_raw(format!(
+            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n",
+            oversized
+        ));
+        assert!(get(&url, Duration::from_secs(5)).is_err());
+        // Without a length the body is still only read up to the cap
+        let (url, _handle) = serve_raw(format!(
+            "HTTP/1.1 200 OK\r\n\r\n{}",
+            "x".repeat(oversized as usize)
+        ));
+        assert!(get(&url, Duration::from_secs(5)).is_err());
+    }
+
+    #[test]
+    fn tst_connect_timeout() {
+        // A documentation only address, nothing answers it
+        let start = Instant::now();
+        assert!(post_json("http://192.0.2.1:81/hook", "{}", Duration::from_millis(200)).is_err());
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+}
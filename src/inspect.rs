@@ -0,0 +1,166 @@
+use crate::amount::TxnAmount;
+use crate::cli_io::{csv_writer, txn_record, FixedWidthSpec, InspectOptions, RawInputTxn};
+use crate::encoding::DecodingReader;
+use crate::transaction::Transaction;
+use csv::{ReaderBuilder, Trim};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+
+/// Summary of an input file, gathered without applying any of its records
+#[derive(Debug, Default, PartialEq)]
+pub struct FileStats {
+    /// Readable records by their type
+    pub by_type: BTreeMap<String, u64>,
+    /// Rows which couldn't be read or aren't a valid transaction
+    pub malformed: u64,
+    pub clients: HashSet<u16>,
+    /// Smallest & largest deposit or withdrawal amount
+    pub min_amount: Option<TxnAmount>,
+    pub max_amount: Option<TxnAmount>,
+    /// Deposits & withdrawals reusing a txn id an earlier one already had
+    pub duplicate_txn_ids: u64,
+    /// Times each deposit or withdrawal txn id was seen
+    txn_ids: HashMap<u32, u32>,
+}
+
+impl FileStats {
+    /// Number of readable records
+    pub fn records(&self) -> u64 {
+        self.by_type.values().sum()
+    }
+
+    fn add_record(&mut self, record: RawInputTxn) {
+        let txn = match record.convert_to_txn() {
+            Ok(txn) => txn,
+            Err(_) => {
+                self.malformed += 1;
+                return;
+            }
+        };
+        let [type_str, ..] = txn_record(&txn);
+        *self.by_type.entry(type_str).or_default() += 1;
+        self.clients.insert(txn.acnt_id());
+        if let Transaction::Deposit(p_txn) | Transaction::Withdrawal(p_txn) = &txn {
+            self.min_amount = Some(
+                self.min_amount
+                    .map_or(p_txn.amount, |m| m.min(p_txn.amount)),
+            );
+            self.max_amount = Some(
+                self.max_amount
+                    .map_or(p_txn.amount, |m| m.max(p_txn.amount)),
+            );
+            let seen = self.txn_ids.entry(p_txn.txn_id).or_default();
+            if *seen > 0 {
+                self.duplicate_txn_ids += 1;
+            }
+            *seen += 1;
+        }
+    }
+}
+
+/// Gathers stats from a csv input file, or fixed width records when a spec is given
+pub fn inspect_reader<R: io::Read>(
+    reader: R,
+    fixed_width: Option<&FixedWidthSpec>,
+) -> Result<FileStats, io::Error> {
+    let mut stats = FileStats::default();
+    match fixed_width {
+        Some(spec) => {
+            for line in BufReader::new(reader).lines() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                match spec.decode(line.trim_end_matches('\r')) {
+                    Ok(record) => stats.add_record(record),
+                    Err(_) => stats.malformed += 1,
+                }
+            }
+        }
+        None => {
+            let mut rdr = ReaderBuilder::new().trim(Trim::All).from_reader(reader);
+            for result in rdr.deserialize() {
+                match result {
+                    Ok(record) => stats.add_record(record),
+                    Err(e) if e.is_io_error() => return Err(io::Error::other(e)),
+                    Err(_) => stats.malformed += 1,
+                }
+            }
+        }
+    }
+    Ok(stats)
+}
+
+/// Writes a `stat,value` report of an input file
+pub fn inspect_execute(options: &InspectOptions) -> Result<(), Box<dyn Error>> {
+    let stats = inspect_reader(
+        DecodingReader::new(File::open(&options.input_file)?, options.encoding)?,
+        options.fixed_width.as_ref(),
+    )?;
+
+    let mut wtr = csv_writer(&options.output)?;
+    wtr.write_record(["stat", "value"])?;
+    wtr.write_record(["records", &stats.records().to_string()])?;
+    for (type_str, count) in stats.by_type.iter() {
+        wtr.write_record([format!("type.{}", type_str), count.to_string()])?;
+    }
+    wtr.write_record(["malformed", &stats.malformed.to_string()])?;
+    wtr.write_record(["clients", &stats.clients.len().to_string()])?;
+    wtr.write_record([
+        "min_amount",
+        &stats
+            .min_amount
+            .map_or(String::new(), |amount| amount.amount().to_string()),
+    ])?;
+    wtr.write_record([
+        "max_amount",
+        &stats
+            .max_amount
+            .map_or(String::new(), |amount| amount.amount().to_string()),
+    ])?;
+    wtr.write_record(["duplicate_txn_ids", &stats.duplicate_txn_ids.to_string()])?;
+    wtr.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::inspect_reader;
+    use crate::amount::TxnAmount;
+    use crate::cli_io::FixedWidthSpec;
+    use crate::test::utils::_get_test_input_file;
+
+    #[test]
+    fn tst_inspect_reader() {
+        let data = "type,client,tx,amount\n\
+                    deposit,1,1,10.0\n\
+                    deposit,2,2,2.5\n\
+                    withdrawal,1,1,1.0\n\
+                    dispute,3,2,\n\
+                    deposit,1,3,abc\n\
+                    transfer,1,4,1.0\n\
+                    deposit,x,5,1.0\n";
+        let stats = inspect_reader(data.as_bytes(), None).unwrap();
+        assert_eq!(stats.records(), 4);
+        assert_eq!(stats.by_type["deposit"], 2);
+        assert_eq!(stats.by_type["dispute"], 1);
+        assert_eq!(stats.malformed, 3);
+        assert_eq!(stats.clients.len(), 3);
+        assert_eq!(
+            (stats.min_amount, stats.max_amount),
+            (
+                Some(TxnAmount::from_f64(1.0)),
+                Some(TxnAmount::from_f64(10.0))
+            )
+        );
+        assert_eq!(stats.duplicate_txn_ids, 1);
+
+        let spec =
+            FixedWidthSpec::from_file(&_get_test_input_file("fixed_width_spec.csv")).unwrap();
+        let file = std::fs::File::open(_get_test_input_file("fixed_width.txt")).unwrap();
+        let stats = inspect_reader(file, Some(&spec)).unwrap();
+        assert_eq!((stats.records(), stats.malformed), (4, 1));
+    }
+}
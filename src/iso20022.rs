@@ -0,0 +1,163 @@
+//! Reads the entries of ISO 20022 camt statements, reports, & notifications as input records
+//! Only the subset needed to move funds is mapped, per entry:
+//! - `CdtDbtInd` `CRDT` is a deposit & `DBIT` a withdrawal
+//! - `NtryRef` is the txn id & `Amt` the amount
+//! - `AddtlNtryInf` is the memo
+//! - The client is the numeric `Acct/Id/Othr/Id` of the statement the entry belongs to
+//!
+//! Entries which aren't booked yet are skipped, reversals aren't supported & are rejected
+
+use crate::cli_io::{InputTxnErr, RawInputTxn};
+use roxmltree::{Document, Node};
+use std::io::{self, ErrorKind};
+
+/// Elements holding the entries of one account in camt.052, camt.053, & camt.054 documents
+const ENTRY_CONTAINERS: [&str; 3] = ["Stmt", "Rpt", "Ntfctn"];
+
+/// One entry as read from the document
+#[derive(Debug)]
+pub struct CamtEntry {
+    /// Line of the document the entry starts on
+    pub line: u64,
+    /// Entry element as it appears in the document
+    pub source: String,
+    pub record: Result<RawInputTxn, InputTxnErr>,
+}
+
+fn child<'a, 'input>(node: Node<'a, 'input>, name: &str) -> Option<Node<'a, 'input>> {
+    node.children()
+        .find(|c| c.is_element() && c.tag_name().name() == name)
+}
+
+/// Text of the element at `path` below `node`, trimmed
+fn text_at<'a>(node: Node<'a, '_>, path: &[&str]) -> Option<&'a str> {
+    let mut node = node;
+    for name in path {
+        node = child(node, name)?;
+    }
+    node.text().map(str::trim)
+}
+
+/// Entries are booked unless their status says otherwise, older versions put the code directly in `Sts`
+fn is_booked(entry: Node) -> bool {
+    text_at(entry, &["Sts", "Cd"])
+        .or_else(|| text_at(entry, &["Sts"]))
+        .is_none_or(|status| status.is_empty() || status == "BOOK")
+}
+
+fn decode_entry(entry: Node, acnt_id: Option<u16>) -> Result<RawInputTxn, InputTxnErr> {
+    if text_at(entry, &["RvslInd"]) == Some("true") {
+        return Err(InputTxnErr::UnsupportedType);
+    }
+    let txn_type = match text_at(entry, &["CdtDbtInd"]) {
+        Some("CRDT") => "deposit",
+        Some("DBIT") => "withdrawal",
+        _ => return Err(InputTxnErr::UnsupportedType),
+    };
+    let txn_id = text_at(entry, &["NtryRef"])
+        .and_then(|txn_id| txn_id.parse().ok())
+        .ok_or(InputTxnErr::MalformedRecord)?;
+    Ok(RawInputTxn::new(
+        txn_type,
+        acnt_id.ok_or(InputTxnErr::MalformedRecord)?,
+        txn_id,
+        text_at(entry, &["Amt"]).map(str::to_string),
+        text_at(entry, &["AddtlNtryInf"]).map(str::to_string),
+    ))
+}
+
+/// Reads every booked entry of a camt document in document order
+/// Errors if the document isn't well formed xml, entries which can't be mapped hold why
+pub fn decode_entries(xml: &str) -> Result<Vec<CamtEntry>, io::Error> {
+    let doc = Document::parse(xml).map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
+    let mut entries = vec![];
+    let containers = doc
+        .descendants()
+        .filter(|n| n.is_element() && ENTRY_CONTAINERS.contains(&n.tag_name().name()));
+    for container in containers {
+        let acnt_id = text_at(container, &["Acct", "Id", "Othr", "Id"])
+            .and_then(|acnt_id| acnt_id.parse().ok());
+        let booked = container
+            .children()
+            .filter(|n| n.is_element() && n.tag_name().name() == "Ntry" && is_booked(*n));
+        for entry in booked {
+            entries.push(CamtEntry {
+                line: doc.text_pos_at(entry.range().start).row as u64,
+                source: xml[entry.range()].to_string(),
+                record: decode_entry(entry, acnt_id),
+            });
+        }
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::decode_entries;
+    use crate::amount::TxnAmount;
+    use crate::cli_io::{parse_cli_args, CliCommand, InputTxnErr};
+    use crate::dead_letter::read_dead_letters;
+    use crate::payments_engine::PaymentsEngine;
+    use crate::test::utils::{_get_test_input_file, _get_test_output_file};
+    use crate::test_support::pure_txn;
+    use crate::transaction::{PureTxn, Transaction};
+
+    #[test]
+    fn tst_decode_camt_entries() {
+        let xml = std::fs::read_to_string(_get_test_input_file("camt053.xml")).unwrap();
+        let entries = decode_entries(&xml).unwrap();
+        assert_eq!(entries.len(), 4, "Pending entries should be skipped");
+        assert_eq!(entries[0].line, 12);
+        assert!(entries[0].source.starts_with("<Ntry>"));
+
+        let txns: Vec<Result<Transaction, InputTxnErr>> = entries
+            .into_iter()
+            .map(|entry| entry.record.and_then(|record| record.convert_to_txn()))
+            .collect();
+        assert_eq!(
+            txns[0],
+            Ok(Transaction::Deposit(PureTxn {
+                memo: Some("Invoice 42".to_string()),
+                ..pure_txn(7, 1001, 250.5)
+            }))
+        );
+        assert!(
+            matches!(&txns[1], Ok(Transaction::Withdrawal(p_txn)) if p_txn.amount == TxnAmount::from_f64(20.0))
+        );
+        assert_eq!(txns[2], Err(InputTxnErr::UnsupportedType));
+        assert_eq!(txns[3], Err(InputTxnErr::MalformedRecord));
+
+        assert!(decode_entries("<Document><Stmt>").is_err());
+    }
+
+    #[test]
+    fn tst_process_camt_file() {
+        let f_output = _get_test_output_file("tst_process_camt_file.csv");
+        let f_dlq = _get_test_output_file("tst_process_camt_file.jsonl");
+        let _ = std::fs::remove_file(&f_dlq);
+        let args: Vec<String> = [
+            _get_test_input_file("camt053.xml").as_str(),
+            "--iso20022",
+            "--output",
+            &f_output,
+            "--dead-letter",
+            &f_dlq,
+        ]
+        .iter()
+        .map(|a| a.to_string())
+        .collect();
+        let cli_options = match parse_cli_args(&args) {
+            Ok(CliCommand::Process(cli_options)) => cli_options,
+            _ => panic!("Should parse as process command"),
+        };
+        let mut payments_engine = PaymentsEngine::new();
+        assert!(payments_engine.streaming_execute(&cli_options).is_ok());
+        assert_eq!(payments_engine.accounts.len(), 1);
+        assert_eq!(payments_engine.accounts[0].available().to_f64(), 230.5);
+        let dead_letters = read_dead_letters(&f_dlq).unwrap();
+        assert_eq!(
+            dead_letters.iter().map(|d| d.line).collect::<Vec<_>>(),
+            vec![31, 42]
+        );
+    }
+}
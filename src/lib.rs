@@ -0,0 +1,27 @@
+pub mod account;
+pub mod alerts;
+pub mod amount;
+pub mod cli_io;
+pub mod clock;
+pub mod constants;
+pub mod dead_letter;
+pub mod diagnostics;
+pub mod diff;
+pub mod encoding;
+pub mod generator;
+mod http;
+pub mod inspect;
+#[cfg(feature = "iso20022")]
+pub mod iso20022;
+pub mod metrics;
+pub mod payments_engine;
+pub mod reconcile;
+pub mod shadow;
+pub mod soak;
+mod test;
+#[cfg(any(test, feature = "test_support"))]
+pub mod test_support;
+pub mod timestamp;
+pub mod transaction;
+pub mod transform;
+pub mod webhook;
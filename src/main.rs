@@ -1,11 +1,129 @@
-mod account;
-mod cli_io;
-mod constants;
-mod payments_engine;
-mod test;
-mod transaction;
+use std::fmt::Display;
+use toypaymentengine::cli_io::{parse_cli, CliCommand};
+use toypaymentengine::diagnostics::{log, Level};
+use toypaymentengine::diff;
+#[cfg(feature = "gen")]
+use toypaymentengine::generator;
+use toypaymentengine::inspect;
+use toypaymentengine::payments_engine::cluster;
+use toypaymentengine::payments_engine::config::EngineConfig;
+use toypaymentengine::payments_engine::policy_replay::policy_replay_execute;
+use toypaymentengine::payments_engine::snapshot_store::compact_snapshots;
+use toypaymentengine::payments_engine::trim_log::trim_txn_log;
+use toypaymentengine::payments_engine::PaymentsEngine;
+use toypaymentengine::reconcile;
+#[cfg(feature = "gen")]
+use toypaymentengine::soak;
+#[cfg(feature = "gen")]
+use toypaymentengine::soak::CountingAllocator;
 
+/// Counts allocations so `soak` can report them, the cost is a few relaxed atomic adds per allocation
+/// Only builds with `soak` pay it
+#[cfg(feature = "gen")]
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+/// Turns a subcommand's error into the message reported for it
+fn failed<E: Display>(context: &'static str) -> impl FnOnce(E) -> String {
+    move |e| format!("{}: {}", context, e)
+}
+
+fn engine(config: EngineConfig) -> Result<PaymentsEngine, String> {
+    PaymentsEngine::with_config(config).map_err(failed("Failed to create payments engine"))
+}
+
+/// Every failure exits non-zero, so scripts driving the engine can tell a run failed
 fn main() {
-    let mut payment_engine = payments_engine::PaymentsEngine::new();
-    payment_engine.streaming_execute_cli();
+    if let Err(e) = run() {
+        log(Level::Error, e);
+        std::process::exit(1);
+    }
+}
+
+/// Runs the subcommand given on the command line, errors with the message to report
+fn run() -> Result<(), String> {
+    match parse_cli().map_err(|e| e.to_string())? {
+        CliCommand::Process(cli_options) => engine(cli_options.engine_config.clone())?
+            .streaming_execute(&cli_options)
+            .map_err(failed("Processing stopped early")),
+        #[cfg(feature = "tail")]
+        CliCommand::Tail(tail_options) => engine(tail_options.cli_options.engine_config.clone())?
+            .tail_execute(&tail_options)
+            .map_err(failed("Failed to tail input file")),
+        #[cfg(feature = "gen")]
+        CliCommand::Gen(gen_options) => {
+            generator::gen_execute(&gen_options).map_err(failed("Failed to generate scenario"))
+        }
+        CliCommand::Diff(diff_options) => {
+            diff::diff_execute(&diff_options).map_err(failed("Failed to diff accounts"))
+        }
+        CliCommand::ReplayDlq(replay_options) => {
+            engine(replay_options.cli_options.engine_config.clone())?
+                .replay_dlq_execute(&replay_options)
+                .map_err(failed("Failed to replay dead letters"))
+        }
+        CliCommand::Inspect(inspect_options) => inspect::inspect_execute(&inspect_options)
+            .map_err(failed("Failed to inspect input file")),
+        #[cfg(feature = "gen")]
+        CliCommand::Soak(soak_options) => {
+            soak::soak_execute(&soak_options).map_err(failed("Soak stopped early"))
+        }
+        CliCommand::Cluster(cluster_options) => {
+            cluster::cluster_execute(&cluster_options).map_err(failed("Cluster run stopped early"))
+        }
+        #[cfg(all(unix, feature = "listen"))]
+        CliCommand::Listen(listen_options) => {
+            engine(listen_options.cli_options.engine_config.clone())?
+                .listen_execute(&listen_options)
+                .map_err(failed("Listening stopped early"))
+        }
+        CliCommand::Explore(explore_options) => engine(explore_options.engine_config.clone())?
+            .explore_execute(&explore_options)
+            .map_err(failed("Failed to explore txn log")),
+        CliCommand::CompactSnapshots(compact_options) => {
+            let manifest = compact_snapshots(&compact_options.dir, compact_options.shards)
+                .map_err(failed("Failed to compact snapshots"))?;
+            log(
+                Level::Info,
+                format_args!(
+                    "Compacted {} to seq {} with {} accounts",
+                    compact_options.dir, manifest.seq, manifest.accounts
+                ),
+            );
+            Ok(())
+        }
+        CliCommand::PolicyReplay(policy_replay_options) => {
+            policy_replay_execute(&policy_replay_options)
+                .map_err(failed("Failed to replay txn log"))
+        }
+        CliCommand::TrimLog(trim_options) => {
+            let summary = trim_txn_log(&trim_options).map_err(failed("Failed to trim txn log"))?;
+            log(
+                Level::Info,
+                format_args!(
+                    "Trimmed {} records into an opening state of {} accounts, kept {}",
+                    summary.trimmed, summary.accounts, summary.kept
+                ),
+            );
+            Ok(())
+        }
+        CliCommand::Reconcile(reconcile_options) => {
+            reconcile::reconcile_execute(&reconcile_options)
+                .map_err(failed("Failed to reconcile txn log"))
+        }
+        #[cfg(not(unix))]
+        CliCommand::Listen(_) => {
+            Err("--listen-unix needs a platform with unix domain sockets".to_string())
+        }
+        #[cfg(all(unix, not(feature = "listen")))]
+        CliCommand::Listen(_) => {
+            Err("--listen-unix needs a build with the listen feature".to_string())
+        }
+        #[cfg(not(feature = "tail"))]
+        CliCommand::Tail(_) => Err("tail needs a build with the tail feature".to_string()),
+        #[cfg(not(feature = "gen"))]
+        CliCommand::Gen(_) | CliCommand::Soak(_) => {
+            Err("gen & soak need a build with the gen feature".to_string())
+        }
+    }
 }
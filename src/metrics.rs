@@ -0,0 +1,185 @@
+//! Telemetry hooks the engine reports through, so embedders can forward to their own stack
+
+use std::cell::RefCell;
+use std::fmt;
+use std::io;
+use std::net::UdpSocket;
+use std::time::{Duration, Instant};
+
+/// Largest datagram sent, lines are batched up to it so a packet fits an ethernet frame unfragmented
+const MAX_PAYLOAD: usize = 1432;
+/// Buffered lines are sent at least this often while metrics keep arriving
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Receives engine metrics, implementations should be cheap as they are called per transaction
+pub trait MetricsSink: fmt::Debug + Send {
+    /// Adds to a running count
+    fn counter(&self, name: &str, value: u64);
+    /// Sets the current value of a level
+    fn gauge(&self, name: &str, value: f64);
+    /// Records one observation of a distribution, e.g. a timing
+    fn histogram(&self, name: &str, value: f64);
+}
+
+/// Discards every metric, used when no sink is configured
+#[derive(Debug, Default)]
+pub struct NoopMetrics;
+
+impl MetricsSink for NoopMetrics {
+    fn counter(&self, _name: &str, _value: u64) {}
+    fn gauge(&self, _name: &str, _value: f64) {}
+    fn histogram(&self, _name: &str, _value: f64) {}
+}
+
+/// Lines waiting to be sent in one datagram
+#[derive(Debug)]
+struct Batch {
+    lines: String,
+    since: Instant,
+}
+
+/// Sends metrics as statsd lines over udp, delivery is fire & forget
+/// Lines are batched newline separated into datagrams of up to `MAX_PAYLOAD` bytes, a batch is sent
+/// once the next line wouldn't fit, once it's `FLUSH_INTERVAL` old, or when the sink is dropped
+#[derive(Debug)]
+pub struct StatsdMetrics {
+    socket: UdpSocket,
+    /// Prepended to every metric name, joined with a `.`
+    prefix: String,
+    batch: RefCell<Batch>,
+}
+
+impl StatsdMetrics {
+    /// `addr` is the `host:port` of the statsd agent
+    /// Errors if the address can't be resolved or connected to
+    pub fn new(addr: &str, prefix: &str) -> Result<Self, io::Error> {
+        let socket = UdpSocket::bind("0.0.0.0:0")?;
+        socket.connect(addr).map_err(|e| {
+            io::Error::new(e.kind(), format!("Can't reach statsd at {}: {}", addr, e))
+        })?;
+        Ok(Self {
+            socket,
+            prefix: prefix.to_string(),
+            batch: RefCell::new(Batch {
+                lines: String::with_capacity(MAX_PAYLOAD),
+                since: Instant::now(),
+            }),
+        })
+    }
+
+    /// Sends the buffered lines now
+    pub fn flush(&self) {
+        let mut batch = self.batch.borrow_mut();
+        self.send_batch(&mut batch);
+    }
+
+    fn send_batch(&self, batch: &mut Batch) {
+        if !batch.lines.is_empty() {
+            // Dropped packets are accepted by statsd's design, there is nothing to retry
+            let _ = self.socket.send(batch.lines.as_bytes());
+            batch.lines.clear();
+        }
+        batch.since = Instant::now();
+    }
+
+    fn line(&self, name: &str, value: &str, kind: &str) -> String {
+        match self.prefix.is_empty() {
+            true => format!("{}:{}|{}", name, value, kind),
+            false => format!("{}.{}:{}|{}", self.prefix, name, value, kind),
+        }
+    }
+
+    fn send(&self, line: String) {
+        let mut batch = self.batch.borrow_mut();
+        if !batch.lines.is_empty() && batch.lines.len() + 1 + line.len() > MAX_PAYLOAD {
+            self.send_batch(&mut batch);
+        }
+        if !batch.lines.is_empty() {
+            batch.lines.push('\n');
+        }
+        batch.lines.push_str(&line);
+        if batch.lines.len() >= MAX_PAYLOAD || batch.since.elapsed() >= FLUSH_INTERVAL {
+            self.send_batch(&mut batch);
+        }
+    }
+}
+
+impl Drop for StatsdMetrics {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+impl MetricsSink for StatsdMetrics {
+    fn counter(&self, name: &str, value: u64) {
+        self.send(self.line(name, &value.to_string(), "c"));
+    }
+
+    fn gauge(&self, name: &str, value: f64) {
+        self.send(self.line(name, &value.to_string(), "g"));
+    }
+
+    fn histogram(&self, name: &str, value: f64) {
+        self.send(self.line(name, &value.to_string(), "h"));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MetricsSink, StatsdMetrics, MAX_PAYLOAD};
+    use std::net::UdpSocket;
+    use std::time::Duration;
+
+    #[test]
+    fn tst_statsd_metrics() {
+        let agent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        agent
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let addr = agent.local_addr().unwrap().to_string();
+        let metrics = StatsdMetrics::new(&addr, "payments").unwrap();
+        metrics.counter("txns.accepted", 2);
+        metrics.gauge("accounts", 3.0);
+        metrics.histogram("txn.apply_us", 1.5);
+        drop(metrics);
+
+        // Lines are batched into one datagram, sent at the latest when the sink is dropped
+        let mut buf = [0_u8; MAX_PAYLOAD];
+        let len = agent.recv(&mut buf).unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&buf[..len]),
+            "payments.txns.accepted:2|c\npayments.accounts:3|g\npayments.txn.apply_us:1.5|h"
+        );
+    }
+
+    #[test]
+    fn tst_statsd_batches_fit_payload() {
+        let agent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        agent
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let addr = agent.local_addr().unwrap().to_string();
+        let metrics = StatsdMetrics::new(&addr, "").unwrap();
+        for value in 0..500 {
+            metrics.counter("txns.accepted", value);
+        }
+        drop(metrics);
+
+        let mut buf = [0_u8; 2 * MAX_PAYLOAD];
+        let mut lines = 0;
+        while lines < 500 {
+            let len = agent.recv(&mut buf).unwrap();
+            assert!(len <= MAX_PAYLOAD, "Datagram of {} bytes", len);
+            let batch = String::from_utf8_lossy(&buf[..len]).to_string();
+            for line in batch.split('\n') {
+                assert_eq!(line, format!("txns.accepted:{}|c", lines));
+                lines += 1;
+            }
+        }
+    }
+
+    #[test]
+    fn tst_statsd_bad_address() {
+        assert!(StatsdMetrics::new("not an address", "payments").is_err());
+    }
+}
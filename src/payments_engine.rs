@@ -1,36 +1,382 @@
-use crate::account::Account;
-use crate::transaction::Transaction;
-use std::collections::HashMap;
+use crate::alerts::AlertSink;
+use crate::amount::TxnAmount;
+use crate::clock::{Clock, WallClock};
+use crate::dead_letter::DeadLetterSink;
+use crate::metrics::{MetricsSink, NoopMetrics};
+use crate::transaction::Channel;
+use crate::transaction::{SequencedTxn, Sequencer};
+use crate::webhook::WebhookSink;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io;
+use std::sync::mpsc::Sender;
+use std::time::Duration;
+pub mod account_store;
+pub mod account_view;
+mod anomaly;
+mod approvals;
+pub mod audit;
+mod balance_series;
 mod batch_execute;
+pub mod channels;
+pub mod cluster;
+pub mod config;
+pub mod conservation;
+mod dedup;
+pub mod dedupe_window;
+pub mod dispute_aging;
+pub mod dispute_cases;
+pub mod events;
+pub mod explore;
+pub mod flow_report;
+mod gc;
+mod history;
+mod id_hash;
+mod id_lookup;
+pub mod initial_state;
+pub mod limits;
+#[cfg(all(unix, feature = "listen"))]
+mod listen;
+pub mod live_snapshot;
+mod notify;
+pub mod onboarding;
+pub mod oracle;
+pub mod partner_report;
+pub mod pipeline;
+pub mod policy_replay;
+pub mod priority_lane;
+mod progress;
+mod replay;
+mod risk;
+#[cfg(feature = "rules")]
+pub mod rules;
+pub mod savepoint;
+#[cfg(feature = "scripting")]
+pub mod script;
+pub mod session;
+// Only tailing & listening act on signals, a build with neither has nothing to register them for
+#[cfg(all(unix, feature = "signals", any(feature = "tail", feature = "listen")))]
+mod signals;
+pub mod simulate;
+pub mod snapshot_store;
+pub mod state_summary;
+pub mod stats;
 mod stream_process;
+#[cfg(feature = "tail")]
+mod tail;
+pub mod timestamp_sanity;
 mod transactions;
+pub mod trim_log;
+mod txn_arena;
+pub mod txn_registry;
+
+use account_store::AccountStore;
+use anomaly::AmountHistory;
+use approvals::PendingWithdrawal;
+use audit::CrossClientRef;
+use balance_series::BalanceSeries;
+use channels::ChannelStats;
+use config::{DisputableTxns, DuplicateCheck, EngineConfig};
+use conservation::FundsLedger;
+use dedup::DuplicateFilter;
+use dedupe_window::DedupeWindow;
+use events::EngineEvent;
+use flow_report::FlowReport;
+use id_hash::{IdBuildHasher, IdMap, IdSet};
+use id_lookup::IdLookup;
+use live_snapshot::SnapshotPublisher;
+use onboarding::ClientProfile;
+use partner_report::PartnerReport;
+use pipeline::Pipeline;
+use priority_lane::PriorityLane;
+use savepoint::Savepoints;
+use snapshot_store::SnapshotCheckpoint;
+use stats::ClientStats;
+use timestamp_sanity::TimestampRejects;
+use txn_arena::{TxnArena, TxnKey};
+use txn_registry::TxnIdRegistry;
+
+pub use stream_process::StreamOptions;
+pub use transactions::TxnErrors;
 
 #[derive(Debug)]
 pub struct PaymentsEngine {
-    /// List of accounts in order of their creation
-    pub accounts: Vec<Account>,
-    /// Utility to provide O(1) lookup speed for account Id's
-    /// In real scenario would want to check on DB or REDIS client
-    acnt_map: HashMap<u16, usize>,
+    /// List of accounts in order of their creation, looked up by client id through the store
+    pub accounts: AccountStore,
 
     /// List of accepted transactions in order of their creation
     /// Assignment does not require tracking RefTxn's,
     /// but cool because you can confirm account state from transaction history ¯\_(ツ)_/¯
     /// For a payment engine would want an ACID DB
-    processed_txns: Vec<Transaction>,
+    /// Each is stored with the sequence number it was ingested with
+    processed_txns: TxnArena,
     /// Utility to provide O(1) lookup speed for account Id's
     /// Will only point to pure transactions as ref txn's aren't given identifiers
     /// In real scenario would want to check on DB or REDIS client
-    txn_map: HashMap<u32, usize>,
+    txn_map: IdLookup<u32, TxnKey>,
+    /// Ids of accepted txns kept without their txn, withdrawals when they're compacted & the txns of
+    /// collected accounts, so they're still rejected as duplicates
+    withdrawal_ids: IdSet<u32>,
+    /// Keys of each client's processed txns in order, to page through an account's history
+    account_txns: IdMap<u16, Vec<TxnKey>>,
+    /// Sequence number of each client's latest processed txn, to find inactive accounts to collect
+    last_activity: IdMap<u16, u64>,
+    /// Account sequence numbers of each client used before its history, e.g. by records of a trimmed log
+    account_seq_base: IdMap<u16, u64>,
+
+    /// Assigns sequence numbers to transactions which arrive without one
+    sequencer: Sequencer,
+    /// Sequence number of the last transaction given to the engine, later ones must be greater
+    last_seq: u64,
+    /// `last_seq` when inactive accounts were last collected while streaming
+    last_gc_seq: u64,
+
+    /// Set when duplicate txn ids are checked probabilistically instead of through txn_map
+    dup_filter: Option<DuplicateFilter>,
+    /// Receives high severity events while streaming when webhooks are configured
+    webhook_sink: Option<WebhookSink>,
+    /// Clients with a negative balance, so webhooks only hear of one as it goes negative
+    negative_clients: HashSet<u16>,
+    /// Receives balance alerts while streaming when alert rules are configured
+    alert_sink: Option<AlertSink>,
+    /// Subscriber to engine events, none are built unless an embedder subscribed
+    event_sender: Option<Sender<EngineEvent>>,
+    /// (client, rule index) pairs currently matching an alert rule
+    active_alerts: HashSet<(u16, usize)>,
+    /// Receives processing counters, gauges, & timings, discards them unless a sink is set
+    metrics: Box<dyn MetricsSink>,
+    /// Time time based features go by, the system clock unless another is set
+    clock: Box<dyn Clock>,
+    /// Processing counters & timers per client, to find accounts dominating processing time
+    client_stats: HashMap<u16, ClientStats>,
+    /// Records taking longer than this to apply are logged, unchecked when unset
+    latency_budget: Option<Duration>,
+    /// Records which took longer than the latency budget
+    slow_records: u64,
+    /// Activity per payment rail, keyed None for txns without a channel
+    channel_stats: BTreeMap<Option<Channel>, ChannelStats>,
+
+    config: EngineConfig,
+    /// Amount held for disputed txns when less than the txn amount was held
+    held_amounts: IdMap<u32, TxnAmount>,
+    /// Memos of applied dispute, resolve, & chargeback records by disputed txn id, with the record's sequence number
+    case_notes: IdMap<u32, Vec<(u64, String)>>,
+    /// Bytes the case notes hold, kept as notes are taken & rolled back
+    case_note_bytes: usize,
+    /// Amount refunded so far per deposit, by txn id
+    refunded: IdMap<u32, TxnAmount>,
+    /// Ids of withdrawals denied approval, their funds never left so they can't be disputed
+    denied_withdrawals: IdSet<u32>,
+    /// Accounts disputes flagged for manual review
+    flagged_for_review: HashSet<u16>,
+    /// Number of currently disputed txns per account
+    open_disputes: IdMap<u16, usize>,
+    /// Recently ingested records, set when retries within a window are dropped quietly
+    dedupe_window: Option<DedupeWindow>,
+    /// Set once snapshots are enabled, shares account copies with reader threads
+    snapshot_publisher: Option<SnapshotPublisher>,
+    /// Money entering & leaving accounts, only kept when the conservation check is enabled
+    funds_ledger: Option<FundsLedger>,
+    /// Last snapshot saved or restored, kept while deltas are saved so only changes since are written
+    snapshot_checkpoint: Option<SnapshotCheckpoint>,
+    /// Metadata of clients read from an onboarding file, by client id
+    client_profiles: HashMap<u16, ClientProfile>,
+    /// Records skipped at ingest because the client filter excluded their client
+    filtered_records: u64,
+    /// Txn ids accepted again in a later id epoch
+    recycled_txn_ids: u64,
+    /// Rejected references to other clients' txns, kept as an audit trail
+    cross_client_refs: Vec<CrossClientRef>,
+    /// Withdrawals above the approval threshold waiting on a decision, by txn id
+    pending_withdrawals: HashMap<u32, PendingWithdrawal>,
+    /// Txn ids accepted by this & previous runs, set when a registry file is configured
+    txn_registry: Option<TxnIdRegistry>,
+    /// Accumulated risk scores by account, only kept when risk scoring is configured
+    risk_scores: HashMap<u16, f64>,
+    /// Accounts whose risk score reached the review threshold
+    under_review: HashSet<u16>,
+    /// Rolling statistics of accepted amounts by account, only kept when anomaly detection is configured
+    amount_history: HashMap<u16, AmountHistory>,
+    /// Txns of accounts under review or of anomalous amounts, held back instead of applied
+    quarantined: Vec<SequencedTxn>,
+    /// Streamed records rejected for timestamps outside the sanity bounds
+    timestamp_rejects: TimestampRejects,
+    /// Stages every txn passes through, the standard ones unless library users change them
+    pipeline: Pipeline,
+    /// Receives records which weren't applied when a dead letter file is configured
+    dead_letter_sink: Option<DeadLetterSink>,
+    /// Open savepoints & the undo log of txns processed since the oldest
+    savepoints: Savepoints,
+    /// Set when balances of active clients are written per interval of sequence numbers
+    balance_series: Option<BalanceSeries>,
+    /// Set when streamed records are counted per hour or day of their timestamps
+    flow_report: Option<FlowReport>,
+    /// Set when streamed records & their rejects are counted per partner
+    partner_report: Option<PartnerReport>,
+    /// Urgent operator records applied ahead of the bulk input, set when a priority lane is open
+    priority_lane: Option<PriorityLane>,
+}
+
+impl Default for PaymentsEngine {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl PaymentsEngine {
     pub fn new() -> Self {
         Self {
-            accounts: vec![],
-            acnt_map: HashMap::new(),
-            processed_txns: vec![],
-            txn_map: HashMap::new(),
+            accounts: AccountStore::default(),
+            processed_txns: TxnArena::default(),
+            txn_map: IdLookup::default(),
+            withdrawal_ids: IdSet::default(),
+            account_txns: IdMap::default(),
+            last_activity: IdMap::default(),
+            account_seq_base: IdMap::default(),
+            sequencer: Sequencer::default(),
+            last_seq: 0,
+            last_gc_seq: 0,
+            dup_filter: None,
+            webhook_sink: None,
+            alert_sink: None,
+            event_sender: None,
+            active_alerts: HashSet::new(),
+            negative_clients: HashSet::new(),
+            metrics: Box::new(NoopMetrics),
+            clock: Box::new(WallClock),
+            client_stats: HashMap::new(),
+            latency_budget: None,
+            slow_records: 0,
+            channel_stats: BTreeMap::new(),
+            config: EngineConfig::default(),
+            held_amounts: IdMap::default(),
+            case_notes: IdMap::default(),
+            case_note_bytes: 0,
+            refunded: IdMap::default(),
+            denied_withdrawals: IdSet::default(),
+            flagged_for_review: HashSet::new(),
+            open_disputes: IdMap::default(),
+            dedupe_window: None,
+            snapshot_publisher: None,
+            snapshot_checkpoint: None,
+            funds_ledger: None,
+            client_profiles: HashMap::new(),
+            filtered_records: 0,
+            recycled_txn_ids: 0,
+            cross_client_refs: vec![],
+            pending_withdrawals: HashMap::new(),
+            txn_registry: None,
+            risk_scores: HashMap::new(),
+            under_review: HashSet::new(),
+            amount_history: HashMap::new(),
+            quarantined: vec![],
+            timestamp_rejects: TimestampRejects::default(),
+            pipeline: Pipeline::standard(),
+            dead_letter_sink: None,
+            savepoints: Savepoints::default(),
+            balance_series: None,
+            flow_report: None,
+            partner_report: None,
+            priority_lane: None,
+        }
+    }
+
+    /// Creates an engine with non default behavior
+    /// Errors if resources the config points at, like a duplicate store, can't be created
+    /// or if withdrawals are compacted while they may be disputed
+    /// or if txn id epochs are combined with id stores which don't know when an id was seen
+    pub fn with_config(config: EngineConfig) -> Result<Self, io::Error> {
+        if config.compact_withdrawals && config.disputable_txns != DisputableTxns::Deposits {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Compacted withdrawals can't be disputed, only deposits may be disputable",
+            ));
+        }
+        if config.id_epoch.is_some()
+            && (config.compact_withdrawals
+                || config.txn_registry.is_some()
+                || config.duplicate_check != DuplicateCheck::Exact)
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Txn id epochs need every id stored with its txn, \
+                 compacted withdrawals, registries, & duplicate stores don't keep one",
+            ));
         }
+        let dup_filter = match &config.duplicate_check {
+            DuplicateCheck::Exact => None,
+            DuplicateCheck::Probabilistic {
+                expected_txns,
+                false_positive_rate,
+                store_path,
+            } => Some(DuplicateFilter::new(
+                *expected_txns,
+                *false_positive_rate,
+                store_path,
+            )?),
+        };
+        let dedupe_window = config.dedupe_window.clone().map(DedupeWindow::new);
+        let txn_registry = match &config.txn_registry {
+            Some(file_path) => Some(TxnIdRegistry::load(file_path)?),
+            None => None,
+        };
+        let expected_records = config.expected_records.unwrap_or(0);
+        let expected_accounts = config.expected_accounts.unwrap_or(0);
+        let hasher = IdBuildHasher::new(config.id_hasher);
+        Ok(Self {
+            accounts: AccountStore::with_capacity(
+                config.id_index,
+                expected_accounts,
+                hasher.clone(),
+            ),
+            processed_txns: TxnArena::with_capacity(expected_records),
+            txn_map: IdLookup::with_capacity(config.id_index, expected_records, hasher.clone()),
+            account_txns: IdMap::with_capacity_and_hasher(expected_accounts, hasher.clone()),
+            last_activity: IdMap::with_capacity_and_hasher(expected_accounts, hasher.clone()),
+            withdrawal_ids: IdSet::with_hasher(hasher.clone()),
+            held_amounts: IdMap::with_hasher(hasher.clone()),
+            refunded: IdMap::with_hasher(hasher.clone()),
+            denied_withdrawals: IdSet::with_hasher(hasher.clone()),
+            open_disputes: IdMap::with_capacity_and_hasher(expected_accounts, hasher),
+            dup_filter,
+            dedupe_window,
+            txn_registry,
+            config,
+            ..Self::new()
+        })
+    }
+
+    /// Reports processing metrics to `sink` from now on, e.g. an embedder's telemetry stack
+    pub fn set_metrics_sink(&mut self, sink: Box<dyn MetricsSink>) {
+        self.metrics = sink;
+    }
+
+    /// Drives time based features by `clock` from now on, e.g. record timestamps or a clock a test controls
+    pub fn set_clock(&mut self, clock: Box<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    /// Current time in unix seconds by the engine's clock
+    pub fn now(&self) -> i64 {
+        self.clock.now()
+    }
+
+    /// Logs records taking longer than `budget` to apply from now on, with their type & client
+    pub fn set_latency_budget(&mut self, budget: Duration) {
+        self.latency_budget = Some(budget);
+    }
+
+    /// Number of records skipped because the client filter excluded their client
+    pub fn filtered_records(&self) -> u64 {
+        self.filtered_records
+    }
+
+    /// Number of txn ids reused by upstream after rolling over, accepted because they were stored in an earlier epoch
+    pub fn recycled_txn_ids(&self) -> u64 {
+        self.recycled_txn_ids
+    }
+
+    /// True if a dispute the account couldn't cover flagged it for manual review
+    pub fn is_flagged_for_review(&self, acnt_id: u16) -> bool {
+        self.flagged_for_review.contains(&acnt_id)
     }
 }
@@ -0,0 +1,212 @@
+use super::config::IdIndex;
+use super::id_hash::IdBuildHasher;
+use super::id_lookup::IdLookup;
+use crate::account::Account;
+use std::ops::{Deref, Index, IndexMut};
+
+/// Handle to an account in an `AccountStore`, only valid until the store is next compacted
+/// Handles from before a compaction panic on use instead of reaching whichever account moved into their slot
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AcntKey {
+    indx: usize,
+    generation: u32,
+}
+
+/// Accounts in order of their creation, owning the lookup from client id to their position
+/// Reordering only happens through the store, which rebuilds the lookup & bumps its generation
+#[derive(Debug, Clone, Default)]
+pub struct AccountStore {
+    accounts: Vec<Account>,
+    /// Utility to provide O(1) lookup speed for account Id's
+    /// In real scenario would want to check on DB or REDIS client
+    acnt_map: IdLookup<u16, usize>,
+    generation: u32,
+}
+
+impl AccountStore {
+    /// Builds a store from accounts in creation order, errors with the id of a client appearing twice
+    pub fn from_accounts(accounts: Vec<Account>) -> Result<Self, u16> {
+        let mut acnt_map =
+            IdLookup::with_capacity(IdIndex::default(), accounts.len(), IdBuildHasher::default());
+        for (indx, acnt) in accounts.iter().enumerate() {
+            if acnt_map.insert(acnt.id, indx).is_some() {
+                return Err(acnt.id);
+            }
+        }
+        Ok(Self {
+            accounts,
+            acnt_map,
+            generation: 0,
+        })
+    }
+
+    /// Empty store with room for `capacity` accounts, looked up through an `index` hashing with `hasher`
+    pub(super) fn with_capacity(index: IdIndex, capacity: usize, hasher: IdBuildHasher) -> Self {
+        Self {
+            accounts: Vec::with_capacity(capacity),
+            acnt_map: IdLookup::with_capacity(index, capacity, hasher),
+            generation: 0,
+        }
+    }
+
+    /// Points each client at its position again, keeping the store's kind of lookup & hasher
+    fn rebuild_map(&mut self) {
+        let mut acnt_map = self.acnt_map.empty_like(self.accounts.len());
+        acnt_map.extend(
+            self.accounts
+                .iter()
+                .enumerate()
+                .map(|(indx, acnt)| (acnt.id, indx)),
+        );
+        self.acnt_map = acnt_map;
+    }
+
+    /// Handle to the client's account if it exists
+    pub fn key(&self, acnt_id: u16) -> Option<AcntKey> {
+        self.acnt_map.get(&acnt_id).map(|indx| AcntKey {
+            indx: *indx,
+            generation: self.generation,
+        })
+    }
+
+    pub fn get(&self, acnt_id: u16) -> Option<&Account> {
+        self.key(acnt_id).map(|key| &self[key])
+    }
+
+    /// Adds a new account, the caller checks the client doesn't have one already
+    /// Panics rather than let a client appear twice in output
+    pub(super) fn insert(&mut self, acnt: Account) -> AcntKey {
+        assert!(
+            !self.acnt_map.contains_key(&acnt.id),
+            "Client {} already has an account",
+            acnt.id
+        );
+        let indx = self.accounts.len();
+        self.acnt_map.insert(acnt.id, indx);
+        self.accounts.push(acnt);
+        AcntKey {
+            indx,
+            generation: self.generation,
+        }
+    }
+
+    /// Removes accounts matching `remove` & returns them, survivors keep their relative order
+    /// Every handle taken before this is invalidated, as survivors may have moved
+    pub(super) fn extract_if(&mut self, mut remove: impl FnMut(&Account) -> bool) -> Vec<Account> {
+        let (removed, kept): (Vec<Account>, Vec<Account>) =
+            self.accounts.drain(..).partition(|acnt| remove(acnt));
+        self.accounts = kept;
+        self.rebuild_map();
+        self.generation = self.generation.wrapping_add(1);
+        removed
+    }
+
+    /// Replaces every account with those of `other`, invalidating handles taken before
+    /// The store keeps its own hasher
+    pub(super) fn replace(&mut self, other: AccountStore) {
+        self.accounts = other.accounts;
+        self.rebuild_map();
+        self.generation = self.generation.wrapping_add(1);
+    }
+
+    /// Puts a client's account back to an earlier state, None removes an account created since
+    pub(super) fn restore(&mut self, acnt_id: u16, acnt: Option<Account>) {
+        match (self.key(acnt_id), acnt) {
+            (Some(key), Some(acnt)) => self[key] = acnt,
+            (None, Some(acnt)) => {
+                self.insert(acnt);
+            }
+            (Some(_), None) => {
+                self.extract_if(|acnt| acnt.id == acnt_id);
+            }
+            (None, None) => {}
+        }
+    }
+
+    fn check_key(&self, key: AcntKey) -> usize {
+        assert_eq!(
+            key.generation, self.generation,
+            "Account key used after the store was compacted"
+        );
+        key.indx
+    }
+}
+
+impl Deref for AccountStore {
+    type Target = Vec<Account>;
+
+    fn deref(&self) -> &Vec<Account> {
+        &self.accounts
+    }
+}
+
+impl Index<usize> for AccountStore {
+    type Output = Account;
+
+    fn index(&self, indx: usize) -> &Account {
+        &self.accounts[indx]
+    }
+}
+
+impl IndexMut<usize> for AccountStore {
+    fn index_mut(&mut self, indx: usize) -> &mut Account {
+        &mut self.accounts[indx]
+    }
+}
+
+impl Index<AcntKey> for AccountStore {
+    type Output = Account;
+
+    fn index(&self, key: AcntKey) -> &Account {
+        &self.accounts[self.check_key(key)]
+    }
+}
+
+impl IndexMut<AcntKey> for AccountStore {
+    fn index_mut(&mut self, key: AcntKey) -> &mut Account {
+        let indx = self.check_key(key);
+        &mut self.accounts[indx]
+    }
+}
+
+impl PartialEq<AccountStore> for Vec<Account> {
+    fn eq(&self, other: &AccountStore) -> bool {
+        *self == other.accounts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AccountStore;
+    use crate::account::Account;
+    use crate::amount::{Available, Held};
+
+    fn account(id: u16, available: f64) -> Account {
+        Account::with_balances(id, Available::from_f64(available), Held::default())
+    }
+
+    #[test]
+    fn tst_account_store_stale_key() {
+        let mut store = AccountStore::default();
+        let first = store.insert(account(1, 0.0));
+        store.insert(account(2, 5.0));
+        assert_eq!(store.key(1), Some(first));
+
+        let removed = store.extract_if(|acnt| acnt.id == 1);
+        assert_eq!(removed, vec![account(1, 0.0)]);
+        assert_eq!(store.get(2), Some(&account(2, 5.0)));
+        assert_eq!(store.key(1), None);
+
+        // The old handle's slot now holds client 2, using it must not alias that account
+        let stale = std::panic::catch_unwind(|| store[first].id);
+        assert!(
+            stale.is_err(),
+            "Stale keys should never reach another account"
+        );
+
+        assert_eq!(
+            AccountStore::from_accounts(vec![account(3, 0.0), account(3, 1.0)]).err(),
+            Some(3)
+        );
+    }
+}
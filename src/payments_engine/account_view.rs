@@ -0,0 +1,98 @@
+//! Read-only access to accounts for embedders, without cloning them or relying on the store's layout
+
+use super::PaymentsEngine;
+use crate::account::{Account, AccountStatus};
+
+/// Borrowed view of one client's account, valid until the engine processes its next record
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AccountView<'a> {
+    acnt: &'a Account,
+}
+
+impl<'a> AccountView<'a> {
+    pub fn client(&self) -> u16 {
+        self.acnt.id
+    }
+
+    pub fn available(&self) -> f64 {
+        self.acnt.available().to_f64()
+    }
+
+    pub fn held(&self) -> f64 {
+        self.acnt.held().to_f64()
+    }
+
+    pub fn total(&self) -> f64 {
+        self.acnt.get_total()
+    }
+
+    /// True if the account rejects all txns, by a chargeback or an admin hold
+    pub fn locked(&self) -> bool {
+        self.acnt.is_locked()
+    }
+
+    pub fn locked_by_chargeback(&self) -> bool {
+        self.acnt.locked_by_chargeback
+    }
+
+    pub fn admin_hold(&self) -> bool {
+        self.acnt.admin_hold
+    }
+
+    pub fn status(&self) -> AccountStatus {
+        self.acnt.status()
+    }
+
+    /// Owned copy of the account, e.g. to keep past the next record
+    pub fn to_account(&self) -> Account {
+        self.acnt.clone()
+    }
+}
+
+impl PaymentsEngine {
+    /// Every account in ascending client order, whatever order the clients were first seen in
+    pub fn iter_accounts(&self) -> impl Iterator<Item = AccountView<'_>> {
+        let mut accounts: Vec<&Account> = self.accounts.iter().collect();
+        accounts.sort_unstable_by_key(|acnt| acnt.id);
+        accounts.into_iter().map(|acnt| AccountView { acnt })
+    }
+
+    /// The client's account, None if the engine hasn't seen the client
+    pub fn account(&self, acnt_id: u16) -> Option<AccountView<'_>> {
+        self.accounts.get(acnt_id).map(|acnt| AccountView { acnt })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::account::AccountStatus;
+    use crate::test_support::EngineFixture;
+    use crate::transaction::AdminAction;
+
+    #[test]
+    fn tst_iter_accounts() {
+        let fixture = EngineFixture::new()
+            .deposit(3, 1, 10.0)
+            .deposit(1, 2, 5.0)
+            .deposit(2, 3, 2.0)
+            .dispute(3, 1)
+            .admin(2, 4, AdminAction::SetHold)
+            .build();
+        let engine = &fixture.engine;
+        let clients: Vec<u16> = engine.iter_accounts().map(|view| view.client()).collect();
+        assert_eq!(clients, [1, 2, 3]);
+
+        let view = engine.account(3).unwrap();
+        assert_eq!(
+            (view.available(), view.held(), view.total()),
+            (0.0, 10.0, 10.0)
+        );
+        assert!(!view.locked());
+        assert_eq!(view.to_account(), *engine.accounts.get(3).unwrap());
+
+        let held = engine.account(2).unwrap();
+        assert!(held.locked() && held.admin_hold() && !held.locked_by_chargeback());
+        assert_eq!(held.status(), AccountStatus::OnHold);
+        assert!(engine.account(4).is_none());
+    }
+}
@@ -0,0 +1,160 @@
+//! Ceilings on deposit & withdrawal amounts inferred from each client's accepted history
+//! Amounts far above what a client usually moves are quarantined for review instead of applied
+
+use super::pipeline::TxnOutcome;
+use super::PaymentsEngine;
+use crate::transaction::Transaction;
+
+/// Least spread assumed as a fraction of the mean, so clients always moving the same amount
+/// aren't flagged for the smallest increase
+const MIN_RELATIVE_SPREAD: f64 = 0.05;
+
+/// Rolling mean & variance of accepted amounts
+/// Plain running statistics until `window` amounts were seen, exponentially weighted afterwards
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct RollingStats {
+    count: u64,
+    mean: f64,
+    variance: f64,
+}
+
+impl RollingStats {
+    fn add(&mut self, amount: f64, window: u64) {
+        self.count += 1;
+        let weight = 1.0 / self.count.min(window.max(1)) as f64;
+        let diff = amount - self.mean;
+        self.mean += weight * diff;
+        self.variance = (1.0 - weight) * (self.variance + weight * diff * diff);
+    }
+
+    /// Largest normal amount, None until enough amounts were seen to judge
+    fn ceiling(&self, max_deviations: f64, min_history: u64) -> Option<f64> {
+        if self.count < min_history.max(1) {
+            return None;
+        }
+        let spread = self
+            .variance
+            .sqrt()
+            .max(self.mean.abs() * MIN_RELATIVE_SPREAD);
+        Some(self.mean + max_deviations * spread)
+    }
+}
+
+/// Accepted deposit & withdrawal amounts of a client
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub(super) struct AmountHistory {
+    deposits: RollingStats,
+    withdrawals: RollingStats,
+}
+
+impl AmountHistory {
+    fn stats_of(&self, txn: &Transaction) -> Option<(&RollingStats, f64)> {
+        match txn {
+            Transaction::Deposit(p_txn) => Some((&self.deposits, p_txn.amount.to_f64())),
+            Transaction::Withdrawal(p_txn) => Some((&self.withdrawals, p_txn.amount.to_f64())),
+            _ => None,
+        }
+    }
+}
+
+impl PaymentsEngine {
+    /// Highest deposit & withdrawal amounts the client's history deems normal
+    /// None while anomaly detection is off or the client has too little history
+    pub fn amount_ceilings(&self, acnt_id: u16) -> (Option<f64>, Option<f64>) {
+        let (anomaly, history) = match (&self.config.anomaly, self.amount_history.get(&acnt_id)) {
+            (Some(anomaly), Some(history)) => (anomaly, history),
+            _ => return (None, None),
+        };
+        let ceiling =
+            |stats: &RollingStats| stats.ceiling(anomaly.max_deviations, anomaly.min_history);
+        (ceiling(&history.deposits), ceiling(&history.withdrawals))
+    }
+
+    /// True if a deposit or withdrawal is larger than its client's history deems normal
+    pub(super) fn is_anomalous(&self, txn: &Transaction) -> bool {
+        let (anomaly, history) = match (
+            &self.config.anomaly,
+            self.amount_history.get(&txn.acnt_id()),
+        ) {
+            (Some(anomaly), Some(history)) => (anomaly, history),
+            _ => return false,
+        };
+        history.stats_of(txn).is_some_and(|(stats, amount)| {
+            stats
+                .ceiling(anomaly.max_deviations, anomaly.min_history)
+                .is_some_and(|ceiling| amount > ceiling)
+        })
+    }
+
+    /// Adds the amount of an applied deposit or withdrawal to its client's history
+    pub(super) fn record_amount(&mut self, txn: &Transaction, outcome: &TxnOutcome) {
+        let window = match (&self.config.anomaly, outcome) {
+            (Some(anomaly), TxnOutcome::Applied) => anomaly.window,
+            _ => return,
+        };
+        let history = self.amount_history.entry(txn.acnt_id()).or_default();
+        match txn {
+            Transaction::Deposit(p_txn) => history.deposits.add(p_txn.amount.to_f64(), window),
+            Transaction::Withdrawal(p_txn) => {
+                history.withdrawals.add(p_txn.amount.to_f64(), window)
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RollingStats;
+    use crate::payments_engine::config::{AnomalyConfig, EngineConfig, RiskConfig};
+    use crate::payments_engine::transactions::TxnErrors;
+    use crate::test_support::EngineFixture;
+
+    #[test]
+    fn tst_anomalous_amounts() {
+        let mut stats = RollingStats::default();
+        for amount in [2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0] {
+            stats.add(amount, 100);
+        }
+        assert_eq!(stats.mean, 5.0);
+        assert!((stats.variance - 4.0).abs() < 1e-9);
+        assert_eq!(stats.ceiling(3.0, 8), Some(11.0));
+        assert_eq!(stats.ceiling(3.0, 9), None);
+
+        let config = EngineConfig {
+            anomaly: Some(AnomalyConfig {
+                min_history: 3,
+                ..AnomalyConfig::default()
+            }),
+            risk: Some(RiskConfig::default()),
+            ..EngineConfig::default()
+        };
+        let fixture = EngineFixture::with_config(config)
+            .deposit(1, 1, 10.0)
+            .deposit(1, 2, 10.0)
+            .withdrawal(1, 3, 1.0)
+            // Too little history to judge yet
+            .deposit(1, 4, 50.0)
+            .deposit(1, 5, 1000.0)
+            .deposit(1, 6, 30.0)
+            // Withdrawals have their own history
+            .withdrawal(1, 7, 20.0)
+            .deposit(2, 8, 1000.0)
+            .build();
+        assert!(fixture.results[3].is_ok());
+        assert_eq!(fixture.results[4], Err(TxnErrors::AnomalousAmount));
+        assert!(fixture.results[5].is_ok());
+        assert!(fixture.results[6].is_ok());
+        assert!(fixture.results[7].is_ok());
+
+        let engine = &fixture.engine;
+        let quarantined: Vec<u64> = engine.quarantined_txns().iter().map(|s| s.seq).collect();
+        assert_eq!(quarantined, [5]);
+        assert_eq!(engine.risk_score(1), RiskConfig::default().anomaly);
+        assert_eq!(engine.accounts.get(1).unwrap().available().to_f64(), 79.0);
+        let (deposit_ceiling, withdrawal_ceiling) = engine.amount_ceilings(1);
+        assert!(deposit_ceiling.unwrap() > 50.0);
+        assert_eq!(withdrawal_ceiling, None);
+        assert_eq!(engine.amount_ceilings(2), (None, None));
+    }
+}
@@ -0,0 +1,218 @@
+use super::account_store::AcntKey;
+use super::conservation::FundsFlow;
+use super::transactions::{overflowed, TxnErrors};
+use super::PaymentsEngine;
+use crate::amount::TxnAmount;
+use crate::transaction::{AdminAction, AdminTxn, PureTxn};
+use csv::Writer;
+use std::error::Error;
+
+/// A withdrawal above the approval threshold, its funds are held until it is decided
+#[derive(Debug, Clone, PartialEq)]
+pub struct PendingWithdrawal {
+    pub txn_id: u32,
+    pub acnt_id: u16,
+    pub amount: TxnAmount,
+    /// Sequence number the withdrawal was processed under
+    pub seq: u64,
+}
+
+impl PaymentsEngine {
+    /// True if a withdrawal of this amount must wait for approval
+    pub(super) fn needs_approval(&self, amount: TxnAmount) -> bool {
+        self.config
+            .withdrawal_approval_threshold
+            .is_some_and(|threshold| amount > TxnAmount::from_f64(threshold))
+    }
+
+    /// Records an accepted withdrawal, whose funds were moved to held, as waiting to be approved or denied
+    pub(super) fn park_withdrawal(&mut self, p_txn: &PureTxn) {
+        self.pending_withdrawals.insert(
+            p_txn.txn_id,
+            PendingWithdrawal {
+                txn_id: p_txn.txn_id,
+                acnt_id: p_txn.acnt_id,
+                amount: p_txn.amount,
+                seq: self.last_seq,
+            },
+        );
+    }
+
+    /// Pending withdrawal an approve or deny instruction refers to
+    pub(super) fn pending_for(
+        &self,
+        admin_txn: &AdminTxn,
+    ) -> Result<&PendingWithdrawal, TxnErrors> {
+        match self.pending_withdrawals.get(&admin_txn.instr_id) {
+            Some(pending) if pending.acnt_id == admin_txn.acnt_id => Ok(pending),
+            _ => Err(TxnErrors::TxnNotPendingApproval),
+        }
+    }
+
+    /// Settles a pending withdrawal, approvals release the held funds & denials return them
+    /// A denied withdrawal is kept so its id stays taken, but marked so it can't be disputed
+    pub(super) fn decide_withdrawal(
+        &mut self,
+        acnt_key: AcntKey,
+        admin_txn: &AdminTxn,
+    ) -> Result<(), TxnErrors> {
+        let amount = self.pending_for(admin_txn)?.amount;
+        let acnt = &mut self.accounts[acnt_key];
+        match admin_txn.action {
+            AdminAction::Deny => {
+                acnt.release(amount).map_err(overflowed)?;
+                self.denied_withdrawals.insert(admin_txn.instr_id);
+            }
+            _ => {
+                acnt.settle_held(amount).map_err(overflowed)?;
+                self.record_funds_flow(FundsFlow::Withdrawal, amount);
+            }
+        }
+        self.pending_withdrawals.remove(&admin_txn.instr_id);
+        Ok(())
+    }
+
+    /// True if the txn is a withdrawal waiting on approval
+    pub(super) fn is_pending_approval(&self, txn_id: u32) -> bool {
+        self.pending_withdrawals.contains_key(&txn_id)
+    }
+
+    /// Withdrawals waiting on approval in the order they were processed
+    pub fn pending_withdrawals(&self) -> Vec<&PendingWithdrawal> {
+        let mut pending: Vec<&PendingWithdrawal> = self.pending_withdrawals.values().collect();
+        pending.sort_by_key(|p| p.seq);
+        pending
+    }
+
+    /// Writes withdrawals waiting on approval to a csv report
+    pub fn output_pending_withdrawals_csv(&self, file_path: &str) -> Result<(), Box<dyn Error>> {
+        let mut wtr = Writer::from_path(file_path)?;
+        wtr.write_record(["client", "tx", "amount", "seq"])?;
+        for pending in self.pending_withdrawals() {
+            wtr.write_record([
+                pending.acnt_id.to_string(),
+                pending.txn_id.to_string(),
+                pending.amount.amount().to_string(),
+                pending.seq.to_string(),
+            ])?;
+        }
+        wtr.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::payments_engine::config::EngineConfig;
+    use crate::payments_engine::transactions::TxnErrors;
+    use crate::payments_engine::PaymentsEngine;
+    use crate::test::utils::_get_test_output_file;
+    use crate::test_support::{deposit, withdrawal};
+    use crate::transaction::{AdminAction, AdminTxn, RefTxn, Transaction};
+
+    fn decision(txn_id: u32, action: AdminAction) -> Transaction {
+        Transaction::Admin(AdminTxn {
+            instr_id: txn_id,
+            acnt_id: 1,
+            action,
+        })
+    }
+
+    #[test]
+    fn tst_withdrawal_approval() {
+        let config = EngineConfig {
+            withdrawal_approval_threshold: Some(100.0),
+            ..EngineConfig::default()
+        };
+        let mut payments_engine = PaymentsEngine::with_config(config).unwrap();
+        let _ = payments_engine.process_txn(&deposit(1, 1, 500.0));
+        assert!(payments_engine.process_txn(&withdrawal(1, 2, 50.0)).is_ok());
+        assert!(payments_engine
+            .process_txn(&withdrawal(1, 3, 150.0))
+            .is_ok());
+        assert!(payments_engine
+            .process_txn(&withdrawal(1, 4, 200.0))
+            .is_ok());
+        assert_eq!(payments_engine.accounts[0].available().to_f64(), 100.0);
+        assert_eq!(payments_engine.accounts[0].held().to_f64(), 350.0);
+        let pending = payments_engine.pending_withdrawals();
+        assert_eq!(pending.len(), 2);
+        assert_eq!((pending[0].txn_id, pending[0].seq), (3, 3));
+
+        assert_eq!(
+            payments_engine.process_txn(&Transaction::Dispute(RefTxn {
+                ref_id: 3,
+                acnt_id: 1
+            })),
+            Err(TxnErrors::TxnPendingApproval)
+        );
+
+        assert!(payments_engine
+            .process_txn(&decision(3, AdminAction::Approve))
+            .is_ok());
+        assert_eq!(payments_engine.accounts[0].available().to_f64(), 100.0);
+        assert_eq!(payments_engine.accounts[0].held().to_f64(), 200.0);
+        assert!(payments_engine
+            .process_txn(&decision(4, AdminAction::Deny))
+            .is_ok());
+        assert_eq!(payments_engine.accounts[0].available().to_f64(), 300.0);
+        assert_eq!(payments_engine.accounts[0].held().to_f64(), 0.0);
+        assert_eq!(
+            payments_engine.process_txn(&decision(4, AdminAction::Approve)),
+            Err(TxnErrors::TxnNotPendingApproval),
+            "Decided withdrawals can't be decided again"
+        );
+        assert!(payments_engine.pending_withdrawals().is_empty());
+    }
+
+    #[test]
+    fn tst_denied_withdrawal_not_disputable() {
+        let config = EngineConfig {
+            withdrawal_approval_threshold: Some(100.0),
+            ..EngineConfig::default()
+        };
+        let mut payments_engine = PaymentsEngine::with_config(config).unwrap();
+        let _ = payments_engine.process_txn(&deposit(1, 1, 500.0));
+        let _ = payments_engine.process_txn(&withdrawal(1, 2, 150.0));
+        let _ = payments_engine.process_txn(&withdrawal(1, 3, 200.0));
+        assert!(payments_engine
+            .process_txn(&decision(2, AdminAction::Approve))
+            .is_ok());
+        assert!(payments_engine
+            .process_txn(&decision(3, AdminAction::Deny))
+            .is_ok());
+        let dispute = |ref_id| Transaction::Dispute(RefTxn { ref_id, acnt_id: 1 });
+
+        // Denied funds never left, holding them again would take them from the client twice
+        assert_eq!(
+            payments_engine.process_txn(&dispute(3)),
+            Err(TxnErrors::RefTxnNotDisputable)
+        );
+        assert_eq!(payments_engine.accounts[0].available().to_f64(), 350.0);
+        assert_eq!(payments_engine.accounts[0].held().to_f64(), 0.0);
+        assert_eq!(
+            payments_engine.process_txn(&withdrawal(1, 3, 1.0)),
+            Err(TxnErrors::TxnIdAlreadyExists)
+        );
+        assert!(payments_engine.process_txn(&dispute(2)).is_ok());
+        assert_eq!(payments_engine.accounts[0].held().to_f64(), 150.0);
+    }
+
+    #[test]
+    fn tst_output_pending_withdrawals_csv() {
+        let config = EngineConfig {
+            withdrawal_approval_threshold: Some(1.0),
+            ..EngineConfig::default()
+        };
+        let mut payments_engine = PaymentsEngine::with_config(config).unwrap();
+        let _ = payments_engine.process_txn(&deposit(1, 1, 10.0));
+        let _ = payments_engine.process_txn(&withdrawal(1, 2, 5.0));
+
+        let f = _get_test_output_file("tst_output_pending_withdrawals.csv");
+        assert!(payments_engine.output_pending_withdrawals_csv(&f).is_ok());
+        assert_eq!(
+            std::fs::read_to_string(&f).unwrap(),
+            "client,tx,amount,seq\n1,2,5.0000,2\n"
+        );
+    }
+}
@@ -0,0 +1,145 @@
+use super::PaymentsEngine;
+use crate::diagnostics::{log, Level};
+use crate::transaction::{SequencedTxn, Transaction};
+use crate::webhook::HighSeverityEvent;
+use csv::Writer;
+use serde::Serialize;
+use std::error::Error;
+
+/// Dispute, resolve, or chargeback naming a txn of another client, a sign of feed corruption or abuse
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CrossClientRef {
+    pub seq: u64,
+    /// `dispute`, `resolve`, or `chargeback`
+    pub kind: &'static str,
+    /// Client the record came in for
+    pub client: u16,
+    pub tx: u32,
+    /// Client the referenced txn belongs to
+    pub owner: u16,
+}
+
+impl PaymentsEngine {
+    /// Records a rejected reference to another client's txn in the audit trail & reports it
+    pub(super) fn audit_cross_client_ref(&mut self, s_txn: &SequencedTxn) {
+        let (kind, ref_txn) = match &s_txn.txn {
+            Transaction::Dispute(ref_txn) => ("dispute", ref_txn),
+            Transaction::Resolve(ref_txn) => ("resolve", ref_txn),
+            Transaction::Chargeback(ref_txn) => ("chargeback", ref_txn),
+            _ => return,
+        };
+        let owner = match self.stored_txn_key(ref_txn.ref_id) {
+            Some(txn_key) => self.processed_txns[txn_key].txn.acnt_id(),
+            None => return,
+        };
+        let entry = CrossClientRef {
+            seq: s_txn.seq,
+            kind,
+            client: ref_txn.acnt_id,
+            tx: ref_txn.ref_id,
+            owner,
+        };
+        self.metrics.counter("txns.cross_client_refs", 1);
+        if let Some(sink) = &self.webhook_sink {
+            // Undeliverable events are dead lettered by the sink
+            let _ = sink.notify(&HighSeverityEvent::CrossClientReference {
+                client: entry.client,
+                tx: entry.tx,
+                owner: entry.owner,
+            });
+        }
+        self.cross_client_refs.push(entry);
+    }
+
+    /// Rejected references to other clients' txns in the order they arrived
+    /// Entries outlive rollbacks, the attempt was made either way
+    pub fn cross_client_refs(&self) -> &[CrossClientRef] {
+        &self.cross_client_refs
+    }
+
+    /// Writes the audit trail of references to other clients' txns to a csv
+    pub fn output_audit_log_csv(&self, file_path: &str) -> Result<(), Box<dyn Error>> {
+        let mut wtr = Writer::from_path(file_path)?;
+        for entry in &self.cross_client_refs {
+            wtr.serialize(entry)?;
+        }
+        // Serializing nothing leaves the file without a header
+        if self.cross_client_refs.is_empty() {
+            wtr.write_record(["seq", "kind", "client", "tx", "owner"])?;
+        }
+        wtr.flush()?;
+        Ok(())
+    }
+
+    /// Warns about references to other clients' txns at the end of a run, they shouldn't go unnoticed
+    pub(super) fn summarize_audit(&self) {
+        if !self.cross_client_refs.is_empty() {
+            log(
+                Level::Warn,
+                format_args!(
+                    "{} records referenced txns of other clients, see --audit-log",
+                    self.cross_client_refs.len()
+                ),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CrossClientRef;
+    use crate::payments_engine::TxnErrors;
+    use crate::test::utils::_get_test_output_file;
+    use crate::test_support::EngineFixture;
+    use crate::transaction::{RefTxn, Transaction};
+
+    #[test]
+    fn tst_cross_client_refs() {
+        let mut payments_engine = EngineFixture::new()
+            .deposit(1, 1, 10.0)
+            .deposit(2, 2, 10.0)
+            .build()
+            .engine;
+        let dispute = |ref_id, acnt_id| Transaction::Dispute(RefTxn { ref_id, acnt_id });
+        assert_eq!(
+            payments_engine.process_txn(&dispute(1, 2)),
+            Err(TxnErrors::RefTxnOfOtherClient)
+        );
+        // Neither client's funds moved
+        assert_eq!(
+            payments_engine.accounts.get(1).unwrap().held().to_f64(),
+            0.0
+        );
+        assert_eq!(
+            payments_engine.accounts.get(2).unwrap().held().to_f64(),
+            0.0
+        );
+        // Unknown txns & clients are ordinary failures, not audited
+        assert_eq!(
+            payments_engine.process_txn(&dispute(9, 2)),
+            Err(TxnErrors::TxnIdDoesNotExist)
+        );
+        assert_eq!(
+            payments_engine.process_txn(&dispute(1, 7)),
+            Err(TxnErrors::AccountDoesNotExist)
+        );
+        assert!(payments_engine.process_txn(&dispute(1, 1)).is_ok());
+
+        assert_eq!(
+            payments_engine.cross_client_refs(),
+            [CrossClientRef {
+                seq: 3,
+                kind: "dispute",
+                client: 2,
+                tx: 1,
+                owner: 1,
+            }]
+        );
+        let f_audit = _get_test_output_file("tst_cross_client_refs.csv");
+        assert!(payments_engine.output_audit_log_csv(&f_audit).is_ok());
+        assert_eq!(
+            std::fs::read_to_string(&f_audit).unwrap(),
+            "seq,kind,client,tx,owner\n3,dispute,2,1,1\n"
+        );
+    }
+}
@@ -0,0 +1,141 @@
+use super::PaymentsEngine;
+use crate::cli_io::{account_header, account_record, AccountColumns};
+use csv::Writer;
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::io;
+
+/// Writes the balances of clients active in each interval of sequence numbers as it closes
+/// Rows are `seq,client,available,held,total,locked` with `seq` the last one of the interval
+/// Clients without activity in an interval get no row, their last row still holds
+#[derive(Debug)]
+pub(super) struct BalanceSeries {
+    wtr: Writer<File>,
+    /// Sequence numbers per interval
+    interval: u64,
+    /// Interval the latest txn fell in, intervals are numbered from 0
+    period: u64,
+    /// Clients a txn was applied to in the current interval
+    touched: BTreeSet<u16>,
+}
+
+impl BalanceSeries {
+    pub(super) fn new(file_path: &str, interval: u64) -> Result<Self, io::Error> {
+        let mut wtr = Writer::from_path(file_path)?;
+        let mut header = vec!["seq"];
+        header.extend(account_header(&AccountColumns::default()));
+        wtr.write_record(header)?;
+        Ok(Self {
+            wtr,
+            interval: interval.max(1),
+            period: 0,
+            touched: BTreeSet::new(),
+        })
+    }
+
+    fn period_of(&self, seq: u64) -> u64 {
+        seq.saturating_sub(1) / self.interval
+    }
+}
+
+impl PaymentsEngine {
+    /// Starts writing a balance row per active client for every `interval` sequence numbers
+    pub fn enable_balance_series(
+        &mut self,
+        file_path: &str,
+        interval: u64,
+    ) -> Result<(), io::Error> {
+        let mut series = BalanceSeries::new(file_path, interval)?;
+        series.period = series.period_of(self.last_seq + 1);
+        self.balance_series = Some(series);
+        Ok(())
+    }
+
+    /// Closes the interval in progress if a txn about to run has moved past it
+    pub(super) fn close_balance_interval(&mut self, seq: u64) {
+        let (period, closed_at) = match &self.balance_series {
+            Some(series) => {
+                let period = series.period_of(seq);
+                let closed_at =
+                    (period > series.period).then(|| (series.period + 1) * series.interval);
+                (period, closed_at)
+            }
+            None => return,
+        };
+        if let Some(closed_at) = closed_at {
+            if self.write_balance_rows(closed_at).is_err() {
+                // Error logging and follow up
+            }
+        }
+        if let Some(series) = &mut self.balance_series {
+            series.period = series.period.max(period);
+        }
+    }
+
+    /// Notes a client a txn was just applied to
+    pub(super) fn touch_balance_series(&mut self, acnt_id: u16) {
+        if let Some(series) = &mut self.balance_series {
+            series.touched.insert(acnt_id);
+        }
+    }
+
+    /// Closes the interval in progress at the last processed sequence number & flushes the series
+    pub(super) fn finish_balance_series(&mut self) -> Result<(), io::Error> {
+        self.write_balance_rows(self.last_seq)?;
+        if let Some(series) = &mut self.balance_series {
+            series.wtr.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Writes a row for each client touched since the last rows & clears them
+    fn write_balance_rows(&mut self, seq: u64) -> Result<(), io::Error> {
+        let series = match &mut self.balance_series {
+            Some(series) => series,
+            None => return Ok(()),
+        };
+        for acnt_id in std::mem::take(&mut series.touched) {
+            // Accounts collected since they were touched have nothing left to report
+            if let Some(acnt) = self.accounts.get(acnt_id) {
+                let mut record = vec![seq.to_string()];
+                record.extend(account_record(acnt, &AccountColumns::default()));
+                series.wtr.write_record(record)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::payments_engine::PaymentsEngine;
+    use crate::test::utils::_get_test_output_file;
+    use crate::test_support::deposit;
+
+    #[test]
+    fn tst_balance_series() {
+        let f = _get_test_output_file("tst_balance_series.csv");
+        let mut payments_engine = PaymentsEngine::new();
+        payments_engine.enable_balance_series(&f, 2).unwrap();
+        let txns = [
+            deposit(1, 1, 1.0),
+            deposit(2, 2, 2.0),
+            deposit(1, 3, 1.5),
+            // Rejected as a duplicate, client 2 isn't active in this interval
+            deposit(2, 3, 5.0),
+            deposit(2, 5, 0.5),
+        ];
+        for txn in txns.iter() {
+            let _ = payments_engine.process_txn(txn);
+        }
+        payments_engine.finish_balance_series().unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&f).unwrap(),
+            "seq,client,available,held,total,locked\n\
+             2,1,1.0000,0.0000,1.0000,false\n\
+             2,2,2.0000,0.0000,2.0000,false\n\
+             4,1,2.5000,0.0000,2.5000,false\n\
+             5,2,2.5000,0.0000,2.5000,false\n"
+        );
+    }
+}
@@ -1,5 +1,5 @@
 use super::PaymentsEngine;
-use crate::cli_io::{output_accounts, parse_cli, CliOptions, _parse_txns_csv};
+use crate::cli_io::{_parse_txns_csv, output_accounts, parse_cli, CliCommand, CliOptions};
 use std::io;
 
 impl PaymentsEngine {
@@ -12,7 +12,10 @@ impl PaymentsEngine {
             // TODO custom parsing error message
             return;
         }
-        let cli_options = cli_res.unwrap();
+        let cli_options = match cli_res.unwrap() {
+            CliCommand::Process(cli_options) => cli_options,
+            _ => return,
+        };
 
         match self._batch_execute(&cli_options) {
             Ok(_) => {
@@ -41,7 +44,13 @@ impl PaymentsEngine {
             }
         }
 
-        output_accounts(&self.accounts, &cli_input.output);
+        let columns = self.account_columns(cli_input);
+        output_accounts(
+            &self.output_account_list(cli_input),
+            &cli_input.output,
+            &columns,
+            &cli_input.csv_dialect,
+        );
 
         Ok(())
     }
@@ -50,9 +59,18 @@ impl PaymentsEngine {
 #[cfg(test)]
 mod test {
     use crate::account::Account;
-    use crate::cli_io::{CliOptions, OutputMethod};
+    use crate::alerts::AlertConfig;
+    use crate::amount::{Available, Held, PrecisionPolicy};
+    use crate::cli_io::{
+        CliOptions, CsvDialect, HeaderOptions, OutputMethod, OutputSchema, OutputSink,
+    };
+    use crate::clock::ClockSource;
+    use crate::payments_engine::config::EngineConfig;
+    use crate::payments_engine::flow_report::FlowBucket;
+    use crate::payments_engine::initial_state::DuplicateClientPolicy;
     use crate::payments_engine::PaymentsEngine;
     use crate::test::utils::{_get_test_input_file, _get_test_output_file};
+    use crate::webhook::WebhookConfig;
     use std::io;
 
     pub fn batch_execute_on_tst_file(file_root: &str) -> Result<PaymentsEngine, io::Error> {
@@ -62,7 +80,56 @@ mod test {
         let mut payments_engine = PaymentsEngine::new();
         let cli_input = CliOptions {
             input_file: f_input,
-            output: OutputMethod::_Csv(f_output),
+            output: vec![OutputSink::csv(OutputMethod::Csv(f_output))],
+            engine_config: EngineConfig::default(),
+            webhook: WebhookConfig::default(),
+            txn_log: None,
+            gc_policy: None,
+            progress: false,
+            extended_output: false,
+            output_schema: OutputSchema::V1,
+            only_locked: false,
+            csv_dialect: CsvDialect::default(),
+            client_stats: None,
+            checksum: false,
+            channel_report: None,
+            pending_report: None,
+            alerts: AlertConfig::default(),
+            fixed_width: None,
+            iso20022: false,
+            encoding: None,
+            transforms: vec![],
+            anonymize_map: None,
+            header: HeaderOptions::default(),
+            audit_log: None,
+            dispute_aging: None,
+            held_breakdown: None,
+            dispute_cases: None,
+            flow_report: None,
+            flow_bucket: FlowBucket::Hour,
+            partner_report: None,
+            partner_reject_threshold: 0.05,
+            priority_unix: None,
+            clock: ClockSource::Wall,
+            shadow: None,
+            precision: PrecisionPolicy::Truncate,
+            statsd: None,
+            statsd_prefix: String::new(),
+            latency_budget: None,
+            quarantine: None,
+            dead_letter: None,
+            save_snapshot: None,
+            snapshot_shards: 4,
+            snapshot_deltas: false,
+            restore_snapshot: None,
+            initial_state: None,
+            on_duplicate_client: DuplicateClientPolicy::Error,
+            onboarding: None,
+            oracle_check: false,
+            assert_conservation: false,
+            script: None,
+            balances_series: None,
+            series_interval: 1000,
         };
         let _ = payments_engine._batch_execute(&cli_input);
         Ok(payments_engine)
@@ -72,12 +139,11 @@ mod test {
     fn tst_batch_execute() {
         let res = batch_execute_on_tst_file("simple");
         assert!(res.is_ok(), "Error free is the way to be");
-        let expected = vec![Account {
-            id: 1,
-            available: 10.0,
-            held: 0.0,
-            frozen: false,
-        }];
+        let expected = vec![Account::with_balances(
+            1,
+            Available::from_f64(10.0),
+            Held::default(),
+        )];
         assert_eq!(expected, res.unwrap().accounts);
     }
 }
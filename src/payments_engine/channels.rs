@@ -0,0 +1,316 @@
+use super::transactions::TxnErrors;
+use super::txn_arena::TxnKey;
+use super::PaymentsEngine;
+use crate::amount::{format_amount, TxnAmount};
+use crate::transaction::{Channel, PureTxn, SequencedTxn, Transaction};
+use csv::Writer;
+use std::error::Error;
+
+/// Activity of one payment rail, txns without a channel are counted together
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ChannelStats {
+    pub deposits: u64,
+    pub deposit_amount: f64,
+    pub withdrawals: u64,
+    pub withdrawal_amount: f64,
+    /// Deposits & withdrawals over the rail, & txns referring to them, which were rejected
+    pub rejects: u64,
+    pub disputes: u64,
+    pub chargebacks: u64,
+    /// Fees taken from accounts for accepted txns
+    pub fees: f64,
+    /// Refunds of deposits made over the rail
+    pub refunds: u64,
+    pub refund_amount: f64,
+}
+
+impl PaymentsEngine {
+    /// Rejects deposits & withdrawals over their channel's amount limit
+    pub(super) fn check_channel_limit(&self, p_txn: &PureTxn) -> Result<(), TxnErrors> {
+        let max_amount = p_txn
+            .channel
+            .and_then(|channel| self.config.channel_rules.get(&channel))
+            .and_then(|rules| rules.max_amount);
+        match max_amount {
+            Some(max_amount) if p_txn.amount > TxnAmount::from_f64(max_amount) => {
+                Err(TxnErrors::ChannelLimitExceeded)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Fee charged for a deposit or withdrawal, 0 unless its channel has one
+    pub(super) fn channel_fee(&self, p_txn: &PureTxn) -> TxnAmount {
+        p_txn
+            .channel
+            .and_then(|channel| self.config.channel_rules.get(&channel))
+            .map_or(TxnAmount::default(), |rules| TxnAmount::from_f64(rules.fee))
+    }
+
+    /// Rejects disputes arriving later than the disputed txn's channel allows
+    pub(super) fn check_dispute_window(&self, txn_key: TxnKey) -> Result<(), TxnErrors> {
+        let s_txn = &self.processed_txns[txn_key];
+        let window = match &s_txn.txn {
+            Transaction::Deposit(p_txn) | Transaction::Withdrawal(p_txn) => p_txn
+                .channel
+                .and_then(|channel| self.config.channel_rules.get(&channel))
+                .and_then(|rules| rules.dispute_window),
+            _ => None,
+        };
+        match window {
+            Some(window) if self.last_seq.saturating_sub(s_txn.seq) > window => {
+                Err(TxnErrors::DisputeWindowClosed)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Channel of the deposit or withdrawal a txn is or refers to, None when it has none
+    fn channel_of(&self, txn: &Transaction) -> Option<Channel> {
+        let ref_id = match txn {
+            Transaction::Deposit(p_txn) | Transaction::Withdrawal(p_txn) => return p_txn.channel,
+            Transaction::Dispute(ref_txn)
+            | Transaction::Resolve(ref_txn)
+            | Transaction::Chargeback(ref_txn) => ref_txn.ref_id,
+            Transaction::Refund(refund_txn) => refund_txn.ref_id,
+            Transaction::Release(release_txn) => release_txn.ref_id,
+            Transaction::Admin(_) => return None,
+        };
+        let txn_key = self.stored_txn_key(ref_id)?;
+        match &self.processed_txns[txn_key].txn {
+            Transaction::Deposit(p_txn) | Transaction::Withdrawal(p_txn) => p_txn.channel,
+            _ => None,
+        }
+    }
+
+    pub(super) fn record_channel_stats(
+        &mut self,
+        s_txn: &SequencedTxn,
+        result: &Result<(), TxnErrors>,
+    ) {
+        if let Transaction::Admin(_) = s_txn.txn {
+            return;
+        }
+        let channel = self.channel_of(&s_txn.txn);
+        let fee = match &s_txn.txn {
+            Transaction::Deposit(p_txn) | Transaction::Withdrawal(p_txn) => {
+                self.channel_fee(p_txn).to_f64()
+            }
+            _ => 0.0,
+        };
+        let stats = self.channel_stats.entry(channel).or_default();
+        if result.is_err() {
+            stats.rejects += 1;
+            return;
+        }
+        match &s_txn.txn {
+            Transaction::Deposit(p_txn) => {
+                stats.deposits += 1;
+                stats.deposit_amount += p_txn.amount.to_f64();
+                stats.fees += fee;
+            }
+            Transaction::Withdrawal(p_txn) => {
+                stats.withdrawals += 1;
+                stats.withdrawal_amount += p_txn.amount.to_f64();
+                stats.fees += fee;
+            }
+            Transaction::Dispute(_) => stats.disputes += 1,
+            Transaction::Chargeback(_) => stats.chargebacks += 1,
+            Transaction::Refund(refund_txn) => {
+                stats.refunds += 1;
+                stats.refund_amount += refund_txn.amount.to_f64();
+            }
+            _ => {}
+        }
+    }
+
+    /// Activity of a channel, None for txns without one, unset if the channel saw no txns
+    pub fn channel_stats(&self, channel: Option<Channel>) -> Option<&ChannelStats> {
+        self.channel_stats.get(&channel)
+    }
+
+    /// Writes a row of activity per channel, txns without a channel are reported as `none`
+    pub fn output_channel_report_csv(&self, file_path: &str) -> Result<(), Box<dyn Error>> {
+        let mut wtr = Writer::from_path(file_path)?;
+        wtr.write_record([
+            "channel",
+            "deposits",
+            "deposit_amount",
+            "withdrawals",
+            "withdrawal_amount",
+            "rejects",
+            "disputes",
+            "chargebacks",
+            "fees",
+            "refunds",
+            "refund_amount",
+        ])?;
+        for (channel, stats) in self.channel_stats.iter() {
+            wtr.write_record([
+                channel
+                    .map_or("none", |channel| channel.as_str())
+                    .to_string(),
+                stats.deposits.to_string(),
+                format_amount(stats.deposit_amount),
+                stats.withdrawals.to_string(),
+                format_amount(stats.withdrawal_amount),
+                stats.rejects.to_string(),
+                stats.disputes.to_string(),
+                stats.chargebacks.to_string(),
+                format_amount(stats.fees),
+                stats.refunds.to_string(),
+                format_amount(stats.refund_amount),
+            ])?;
+        }
+        wtr.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::account::Account;
+    use crate::amount::{Amount, Available, Held, TxnAmount};
+    use crate::payments_engine::config::{ChannelRules, EngineConfig};
+    use crate::payments_engine::{PaymentsEngine, TxnErrors};
+    use crate::test::utils::_get_test_output_file;
+    use crate::test_support::{deposit, pure_txn};
+    use crate::transaction::{Channel, PureTxn, RefTxn, RefundTxn, Transaction};
+
+    fn dispute(ref_id: u32) -> Transaction {
+        Transaction::Dispute(RefTxn { ref_id, acnt_id: 1 })
+    }
+
+    #[test]
+    fn tst_fee_taken_with_amount() {
+        let mut config = EngineConfig {
+            overdraft_limit: 1e15,
+            ..EngineConfig::default()
+        };
+        config.channel_rules.insert(
+            Channel::Wire,
+            ChannelRules {
+                fee: 0.0002,
+                ..ChannelRules::default()
+            },
+        );
+        let mut payments_engine = PaymentsEngine::with_config(config).unwrap();
+        let floor = Available::new(Amount::from_minor_units(i64::MIN + 5));
+        payments_engine
+            .accounts
+            .insert(Account::with_balances(1, floor, Held::default()));
+        let wire = Some(Channel::Wire);
+
+        // The fee fits but the amount overflows, so neither is taken & the id stays free
+        assert_eq!(
+            payments_engine.process_txn(&Transaction::Withdrawal(PureTxn {
+                channel: wire,
+                ..pure_txn(1, 1, 1.0)
+            })),
+            Err(TxnErrors::BalanceOverflow)
+        );
+        assert_eq!(payments_engine.accounts[0].available(), floor);
+        assert!(payments_engine
+            .process_txn(&Transaction::Deposit(PureTxn {
+                channel: wire,
+                ..pure_txn(1, 1, 1.0)
+            }))
+            .is_ok());
+        assert_eq!(
+            payments_engine.accounts[0].available().minor_units(),
+            i64::MIN + 5 + 10_000 - 2
+        );
+    }
+
+    #[test]
+    fn tst_channel_rules() {
+        let mut config = EngineConfig::default();
+        config.channel_rules.insert(
+            Channel::Card,
+            ChannelRules {
+                max_amount: Some(100.0),
+                fee: 0.5,
+                dispute_window: Some(2),
+            },
+        );
+        config.channel_rules.insert(
+            Channel::Wire,
+            ChannelRules {
+                fee: 10.0,
+                ..ChannelRules::default()
+            },
+        );
+        let mut payments_engine = PaymentsEngine::with_config(config).unwrap();
+        let card = Some(Channel::Card);
+        let wire = Some(Channel::Wire);
+
+        assert_eq!(
+            payments_engine.process_txn(&Transaction::Deposit(PureTxn {
+                channel: card,
+                ..pure_txn(1, 1, 150.0)
+            })),
+            Err(TxnErrors::ChannelLimitExceeded)
+        );
+        assert!(payments_engine
+            .process_txn(&Transaction::Deposit(PureTxn {
+                channel: card,
+                ..pure_txn(1, 2, 50.0)
+            }))
+            .is_ok());
+        assert!(payments_engine
+            .process_txn(&Transaction::Deposit(PureTxn {
+                channel: wire,
+                ..pure_txn(1, 3, 500.0)
+            }))
+            .is_ok());
+        assert_eq!(payments_engine.accounts[0].available().to_f64(), 539.5);
+        assert_eq!(
+            payments_engine.process_txn(&Transaction::Withdrawal(PureTxn {
+                channel: wire,
+                ..pure_txn(1, 4, 535.0)
+            })),
+            Err(TxnErrors::AccountLacksFunds),
+            "The fee must be covered too"
+        );
+        assert!(payments_engine.process_txn(&deposit(1, 5, 20.0)).is_ok());
+        assert_eq!(payments_engine.accounts[0].available().to_f64(), 559.5);
+
+        // Seq 2 is 4 records back, outside the card window of 2
+        assert_eq!(
+            payments_engine.process_txn(&dispute(2)),
+            Err(TxnErrors::DisputeWindowClosed)
+        );
+        assert!(payments_engine.process_txn(&dispute(3)).is_ok());
+        // Refunds count towards the channel of their deposit
+        assert!(payments_engine
+            .process_txn(&Transaction::Refund(RefundTxn {
+                ref_id: 5,
+                acnt_id: 1,
+                amount: TxnAmount::from_f64(5.0),
+            }))
+            .is_ok());
+
+        let wire_stats = payments_engine.channel_stats(wire).unwrap();
+        assert_eq!(
+            (
+                wire_stats.deposits,
+                wire_stats.rejects,
+                wire_stats.disputes,
+                wire_stats.fees
+            ),
+            (1, 1, 1, 10.0)
+        );
+        let card_stats = payments_engine.channel_stats(card).unwrap();
+        assert_eq!((card_stats.deposits, card_stats.rejects), (1, 2));
+
+        let f = _get_test_output_file("tst_channel_rules.csv");
+        payments_engine.output_channel_report_csv(&f).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&f).unwrap(),
+            "channel,deposits,deposit_amount,withdrawals,withdrawal_amount,rejects,disputes,chargebacks,fees,refunds,refund_amount\n\
+             none,1,20.0000,0,0.0000,0,0,0,0.0000,1,5.0000\n\
+             card,1,50.0000,0,0.0000,2,0,0,0.5000,0,0.0000\n\
+             wire,1,500.0000,0,0.0000,1,1,0,10.0000,0,0.0000\n"
+        );
+    }
+}
@@ -0,0 +1,812 @@
+//! Runs several engine workers on their own threads, each owning the clients a consistent hash ring routes to it
+//! Every txn only touches its own client's account, so workers never need to coordinate
+//! Txn ids are claimed by the first worker to accept a txn with them, the coordinator rejects their reuse by another
+
+use super::config::EngineConfig;
+use super::id_hash::{IdBuildHasher, IdMap};
+use super::limits::{map_bytes, LimitExceeded};
+use super::live_snapshot::AccountSnapshot;
+use super::PaymentsEngine;
+use crate::account::Account;
+use crate::cli_io::{
+    output_accounts, output_accounts_checksums, AccountColumns, ClusterOptions, RawInputTxn,
+};
+use crate::diagnostics::{log, Level};
+use crate::encoding::DecodingReader;
+use crate::transaction::{SequencedTxn, Sequencer, Transaction};
+use csv::{ReaderBuilder, Trim};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::File;
+use std::io;
+use std::mem::size_of;
+use std::sync::mpsc::{self, Receiver, SendError, SyncSender};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// Points each worker places on the ring, more points spread clients more evenly
+const VIRTUAL_NODES: usize = 64;
+/// Txns queued for a worker before routing to it blocks
+const WORKER_QUEUE: usize = 1024;
+/// Restarts of one worker before the run is given up on, a txn which crashes it would crash it again
+const MAX_RESTARTS: u32 = 3;
+/// How long the coordinator waits on another worker's claim before checking the worker is still running
+const CLAIM_POLL: Duration = Duration::from_millis(50);
+
+/// Mixes an id into a well spread ring position
+fn ring_hash(value: u64) -> u64 {
+    let mut z = value.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// Consistent hash ring of workers, adding a worker only moves the clients it takes over
+#[derive(Debug, Clone)]
+pub struct HashRing {
+    /// Ring positions & the worker at each, sorted by position
+    points: Vec<(u64, usize)>,
+}
+
+impl HashRing {
+    pub fn new(workers: usize) -> Self {
+        let mut points: Vec<(u64, usize)> = (0..workers)
+            .flat_map(|worker| {
+                (0..VIRTUAL_NODES)
+                    .map(move |vnode| (ring_hash(((worker as u64) << 32) | vnode as u64), worker))
+            })
+            .collect();
+        points.sort_unstable();
+        Self { points }
+    }
+
+    /// Worker owning the client, the first point at or after the client's position
+    pub fn route(&self, acnt_id: u16) -> usize {
+        let position = ring_hash(u64::MAX - acnt_id as u64);
+        let indx = self.points.partition_point(|(point, _)| *point < position);
+        self.points[indx % self.points.len()].1
+    }
+}
+
+enum WorkerMsg {
+    Txn(SequencedTxn),
+    /// Save the worker's accounts as its latest snapshot
+    Snapshot,
+    /// Stop & hand back the engine
+    Finish,
+    /// Fail as if the worker had crashed
+    #[cfg(test)]
+    Crash,
+}
+
+/// Sequence number & directory of a worker's latest completed snapshot
+type LatestSnapshot = Arc<Mutex<Option<(u64, String)>>>;
+
+/// Worker a deposit or withdrawal id was routed to, & whether it accepted a txn with the id
+#[derive(Debug, Clone, Copy)]
+struct TxnClaim {
+    worker: usize,
+    /// Txns with the id routed to the worker which it hasn't applied yet
+    in_flight: u32,
+    /// Sequence number of the latest txn with the id the worker accepted
+    accepted_seq: Option<u64>,
+}
+
+/// Deposit & withdrawal ids claimed by workers, settled by the workers as they apply txns
+/// A claim no txn was accepted under is dropped once settled, so a rejected txn doesn't keep its id
+struct TxnClaims {
+    claims: Mutex<IdMap<u32, TxnClaim>>,
+    settled: Condvar,
+}
+
+impl TxnClaims {
+    fn lock(&self) -> Result<MutexGuard<'_, IdMap<u32, TxnClaim>>, io::Error> {
+        self.claims
+            .lock()
+            .map_err(|_| io::Error::other("Txn claims lock poisoned"))
+    }
+
+    /// Settles the claim of a deposit or withdrawal the worker owning it has applied
+    fn settle(&self, s_txn: &SequencedTxn, accepted: bool) -> Result<(), io::Error> {
+        let (Transaction::Deposit(p_txn) | Transaction::Withdrawal(p_txn)) = &s_txn.txn else {
+            return Ok(());
+        };
+        let mut claims = self.lock()?;
+        if let Some(claim) = claims.get_mut(&p_txn.txn_id) {
+            claim.in_flight = claim.in_flight.saturating_sub(1);
+            if accepted {
+                claim.accepted_seq = Some(s_txn.seq);
+            }
+            if claim.in_flight == 0 && claim.accepted_seq.is_none() {
+                claims.remove(&p_txn.txn_id);
+            }
+        }
+        drop(claims);
+        self.settled.notify_all();
+        Ok(())
+    }
+
+    /// Settles every claim of a worker rebuilt by replay, from the deposits & withdrawals the replay accepted
+    /// Nothing is in flight to the rebuilt worker, it starts from everything routed to it
+    fn resettle(&self, worker: usize, accepted: &[(u32, u64)]) -> Result<(), io::Error> {
+        let mut claims = self.lock()?;
+        for (txn_id, seq) in accepted {
+            if let Some(claim) = claims.get_mut(txn_id) {
+                claim.accepted_seq = Some(*seq);
+            }
+        }
+        claims.retain(|_, claim| {
+            if claim.worker == worker {
+                claim.in_flight = 0;
+            }
+            claim.worker != worker || claim.accepted_seq.is_some()
+        });
+        Ok(())
+    }
+}
+
+struct Worker {
+    tx: SyncSender<WorkerMsg>,
+    handle: Option<JoinHandle<Result<PaymentsEngine, io::Error>>>,
+    /// Txns routed to the worker since its latest snapshot, or from the start without one, replayed if it restarts
+    replay_log: Vec<SequencedTxn>,
+    latest_snapshot: LatestSnapshot,
+    /// Txns routed to the worker since it was last asked for a snapshot
+    since_snapshot: u64,
+    restarts: u32,
+}
+
+/// Applies messages to a worker's engine until told to finish
+fn run_worker(
+    mut engine: PaymentsEngine,
+    rx: Receiver<WorkerMsg>,
+    snapshot_dir: Option<String>,
+    latest_snapshot: LatestSnapshot,
+    claims: Arc<TxnClaims>,
+) -> Result<PaymentsEngine, io::Error> {
+    for msg in rx {
+        match msg {
+            WorkerMsg::Txn(s_txn) => {
+                // Rejections are part of normal processing, as in a single engine run
+                let accepted = engine.process_sequenced_txn(&s_txn).is_ok();
+                claims.settle(&s_txn, accepted)?;
+            }
+            WorkerMsg::Snapshot => {
+                let Some(dir) = &snapshot_dir else { continue };
+                // Snapshots alternate between two slots so the latest complete one is never overwritten
+                let previous = latest_snapshot
+                    .lock()
+                    .map_err(|_| io::Error::other("Snapshot lock poisoned"))?
+                    .clone();
+                let slot = match previous {
+                    Some((_, slot)) if slot.ends_with("slot-0") => format!("{}/slot-1", dir),
+                    _ => format!("{}/slot-0", dir),
+                };
+                engine.snapshot().save_sharded(&slot, 1)?;
+                *latest_snapshot
+                    .lock()
+                    .map_err(|_| io::Error::other("Snapshot lock poisoned"))? =
+                    Some((engine.last_seq, slot));
+            }
+            WorkerMsg::Finish => return Ok(engine),
+            #[cfg(test)]
+            WorkerMsg::Crash => return Err(io::Error::other("Crashed on request")),
+        }
+    }
+    Err(io::Error::other("Coordinator hung up"))
+}
+
+/// Routes txns to engine workers by client & restarts workers which fail
+/// Restarted workers restore their latest snapshot & replay the txns routed to them since
+pub struct Cluster {
+    ring: HashRing,
+    workers: Vec<Worker>,
+    config: EngineConfig,
+    snapshot_dir: Option<String>,
+    snapshot_every: u64,
+    sequencer: Sequencer,
+    /// Worker each deposit & withdrawal id was routed to & accepted by
+    claims: Arc<TxnClaims>,
+    /// Txns rejected for reusing an id first routed to another worker
+    duplicates: u64,
+    /// Bytes of the memos in the replay logs, kept as txns are logged
+    replay_memo_bytes: usize,
+}
+
+impl Cluster {
+    /// Starts the workers, snapshots are only taken when a directory is given
+    /// Errors if an engine can't be created from the config
+    pub fn new(
+        config: EngineConfig,
+        workers: usize,
+        snapshot_dir: Option<&str>,
+        snapshot_every: u64,
+    ) -> Result<Self, io::Error> {
+        let claims = Arc::new(TxnClaims {
+            claims: Mutex::new(IdMap::with_hasher(IdBuildHasher::new(config.id_hasher))),
+            settled: Condvar::new(),
+        });
+        let mut cluster = Self {
+            ring: HashRing::new(workers.max(1)),
+            workers: vec![],
+            config,
+            snapshot_dir: snapshot_dir.map(str::to_string),
+            snapshot_every: snapshot_every.max(1),
+            sequencer: Sequencer::default(),
+            claims,
+            duplicates: 0,
+            replay_memo_bytes: 0,
+        };
+        for indx in 0..workers.max(1) {
+            let latest_snapshot = LatestSnapshot::default();
+            let engine = PaymentsEngine::with_config(cluster.config.clone())?;
+            let (tx, handle) = cluster.spawn_worker(indx, engine, latest_snapshot.clone());
+            cluster.workers.push(Worker {
+                tx,
+                handle: Some(handle),
+                replay_log: vec![],
+                latest_snapshot,
+                since_snapshot: 0,
+                restarts: 0,
+            });
+        }
+        Ok(cluster)
+    }
+
+    fn worker_snapshot_dir(&self, indx: usize) -> Option<String> {
+        self.snapshot_dir
+            .as_ref()
+            .map(|dir| format!("{}/worker-{:02}", dir, indx))
+    }
+
+    fn spawn_worker(
+        &self,
+        indx: usize,
+        engine: PaymentsEngine,
+        latest_snapshot: LatestSnapshot,
+    ) -> (
+        SyncSender<WorkerMsg>,
+        JoinHandle<Result<PaymentsEngine, io::Error>>,
+    ) {
+        let (tx, rx) = mpsc::sync_channel(WORKER_QUEUE);
+        let snapshot_dir = self.worker_snapshot_dir(indx);
+        let claims = self.claims.clone();
+        let handle =
+            thread::spawn(move || run_worker(engine, rx, snapshot_dir, latest_snapshot, claims));
+        (tx, handle)
+    }
+
+    /// Number of worker restarts so far
+    pub fn restarts(&self) -> u32 {
+        self.workers.iter().map(|worker| worker.restarts).sum()
+    }
+
+    /// Number of txns rejected for reusing an id first routed to another worker
+    pub fn duplicates(&self) -> u64 {
+        self.duplicates
+    }
+
+    /// Rough size of the coordinator's own state, the txn claims & replay logs, workers' engines aren't counted
+    pub fn estimated_memory_bytes(&self) -> usize {
+        let logged: usize = self
+            .workers
+            .iter()
+            .map(|worker| worker.replay_log.capacity())
+            .sum();
+        let claimed = self.claims.lock().map_or(0, |claims| claims.len());
+        map_bytes::<u32, TxnClaim>(claimed)
+            + logged * size_of::<SequencedTxn>()
+            + self.replay_memo_bytes
+    }
+
+    /// Sequences a txn & routes it to the worker owning its client
+    /// Deposits & withdrawals reusing an id another worker accepted are rejected, a worker
+    /// only checks the ids of its own clients
+    /// Errors if that worker keeps failing, or once the coordinator's state passes the configured memory limit
+    pub fn submit(&mut self, txn: Transaction) -> Result<(), io::Error> {
+        let indx = self.ring.route(txn.acnt_id());
+        if let Transaction::Deposit(p_txn) | Transaction::Withdrawal(p_txn) = &txn {
+            if !self.claim(p_txn.txn_id, indx)? {
+                // Still sequenced, as a rejected txn is by a single engine
+                self.sequencer.assign(txn);
+                self.duplicates += 1;
+                return Ok(());
+            }
+            self.replay_memo_bytes += p_txn.memo.as_ref().map_or(0, String::capacity);
+        }
+        let s_txn = self.sequencer.assign(txn);
+        let worker = &mut self.workers[indx];
+        worker.replay_log.push(s_txn.clone());
+        worker.since_snapshot += 1;
+        let snapshot_due =
+            self.snapshot_dir.is_some() && worker.since_snapshot >= self.snapshot_every;
+        if worker.tx.send(WorkerMsg::Txn(s_txn)).is_err() {
+            // The txn is in the replay log, so the restarted worker applies it
+            self.restart_worker(indx)?;
+        }
+        if snapshot_due {
+            self.request_snapshot(indx)?;
+        }
+        if let Some(max) = self.config.limits.max_memory_bytes {
+            let bytes = self.estimated_memory_bytes();
+            if bytes > max {
+                return Err(io::Error::other(LimitExceeded::MemoryBytes(bytes)));
+            }
+        }
+        Ok(())
+    }
+
+    /// Claims a txn id for a worker, false if another worker has accepted a txn with it
+    /// Waits for another worker's txns with the id to be applied first, so the id is only taken if one was accepted
+    fn claim(&mut self, txn_id: u32, indx: usize) -> Result<bool, io::Error> {
+        let txn_claims = self.claims.clone();
+        loop {
+            let mut claims = txn_claims.lock()?;
+            let owner = match claims.get_mut(&txn_id) {
+                None => {
+                    claims.insert(
+                        txn_id,
+                        TxnClaim {
+                            worker: indx,
+                            in_flight: 1,
+                            accepted_seq: None,
+                        },
+                    );
+                    return Ok(true);
+                }
+                Some(claim) if claim.worker == indx => {
+                    claim.in_flight += 1;
+                    return Ok(true);
+                }
+                Some(claim) if claim.accepted_seq.is_some() => return Ok(false),
+                Some(claim) => claim.worker,
+            };
+            let (claims, waited) = txn_claims
+                .settled
+                .wait_timeout(claims, CLAIM_POLL)
+                .map_err(|_| io::Error::other("Txn claims lock poisoned"))?;
+            drop(claims);
+            let stopped = self.workers[owner]
+                .handle
+                .as_ref()
+                .is_none_or(JoinHandle::is_finished);
+            if waited.timed_out() && stopped {
+                // The claim's txns are in the replay log, the restarted worker settles it
+                self.restart_worker(owner)?;
+            }
+        }
+    }
+
+    fn request_snapshot(&mut self, indx: usize) -> Result<(), io::Error> {
+        self.truncate_replay_log(indx)?;
+        self.workers[indx].since_snapshot = 0;
+        self.send(indx, WorkerMsg::Snapshot)
+    }
+
+    /// Drops the logged txns the worker's latest completed snapshot covers
+    fn truncate_replay_log(&mut self, indx: usize) -> Result<(), io::Error> {
+        let worker = &mut self.workers[indx];
+        let covered_seq = match &*worker
+            .latest_snapshot
+            .lock()
+            .map_err(|_| io::Error::other("Snapshot lock poisoned"))?
+        {
+            Some((seq, _)) => *seq,
+            None => return Ok(()),
+        };
+        let covered = worker
+            .replay_log
+            .partition_point(|s_txn| s_txn.seq <= covered_seq);
+        for s_txn in worker.replay_log.drain(..covered) {
+            if let Transaction::Deposit(p_txn) | Transaction::Withdrawal(p_txn) = &s_txn.txn {
+                self.replay_memo_bytes -= p_txn.memo.as_ref().map_or(0, String::capacity);
+            }
+        }
+        Ok(())
+    }
+
+    /// Sends a message, restarting the worker & resending if it has stopped
+    fn send(&mut self, indx: usize, mut msg: WorkerMsg) -> Result<(), io::Error> {
+        loop {
+            match self.workers[indx].tx.send(msg) {
+                Ok(()) => return Ok(()),
+                Err(SendError(unsent)) => {
+                    msg = unsent;
+                    self.restart_worker(indx)?;
+                }
+            }
+        }
+    }
+
+    /// Replaces a stopped worker with one restored from its latest snapshot, replaying the txns routed to it since
+    /// Snapshots only hold accounts, so the txns accepted before one are restored as ids, still duplicates but no
+    /// longer disputable, refundable, or releasable
+    /// Errors if the snapshot can't be loaded
+    fn restart_worker(&mut self, indx: usize) -> Result<(), io::Error> {
+        let worker = &mut self.workers[indx];
+        if let Some(handle) = worker.handle.take() {
+            match handle.join() {
+                Ok(Err(e)) => log(Level::Warn, format_args!("Worker {} failed: {}", indx, e)),
+                Err(_) => log(Level::Warn, format_args!("Worker {} panicked", indx)),
+                Ok(Ok(_)) => {}
+            }
+        }
+        worker.restarts += 1;
+        if worker.restarts > MAX_RESTARTS {
+            return Err(io::Error::other(format!(
+                "Worker {} failed {} times, giving up",
+                indx, worker.restarts
+            )));
+        }
+
+        let mut engine = PaymentsEngine::with_config(self.config.clone())?;
+        let latest = worker
+            .latest_snapshot
+            .lock()
+            .map_err(|_| io::Error::other("Snapshot lock poisoned"))?
+            .clone();
+        let covered_seq = match latest {
+            Some((seq, slot)) => {
+                engine.restore_accounts(AccountSnapshot::load_sharded(&slot)?);
+                // Kept as the ids of compacted withdrawals are, so they're still rejected as duplicates
+                let claims = self.claims.lock()?;
+                engine
+                    .withdrawal_ids
+                    .extend(claims.iter().filter_map(|(txn_id, claim)| {
+                        (claim.worker == indx
+                            && claim.accepted_seq.is_some_and(|accepted| accepted <= seq))
+                        .then_some(*txn_id)
+                    }));
+                seq
+            }
+            None => 0,
+        };
+        self.truncate_replay_log(indx)?;
+        let worker = &self.workers[indx];
+        let mut accepted = vec![];
+        replay(&mut engine, &worker.replay_log, &mut accepted);
+        self.claims.resettle(indx, &accepted)?;
+        log(
+            Level::Info,
+            format_args!(
+                "Worker {} restarted from seq {}, replaying {} txns",
+                indx,
+                covered_seq,
+                worker.replay_log.len()
+            ),
+        );
+        let latest_snapshot = worker.latest_snapshot.clone();
+        let (tx, handle) = self.spawn_worker(indx, engine, latest_snapshot);
+        let worker = &mut self.workers[indx];
+        worker.tx = tx;
+        worker.handle = Some(handle);
+        Ok(())
+    }
+
+    /// Stops every worker & hands back their engines, restarting any which failed on the way
+    pub fn finish(mut self) -> Result<Vec<PaymentsEngine>, io::Error> {
+        let mut engines = vec![];
+        for indx in 0..self.workers.len() {
+            loop {
+                self.send(indx, WorkerMsg::Finish)?;
+                let handle = self.workers[indx]
+                    .handle
+                    .take()
+                    .ok_or_else(|| io::Error::other(format!("Worker {} has no thread", indx)))?;
+                match handle.join() {
+                    Ok(Ok(engine)) => {
+                        engines.push(engine);
+                        break;
+                    }
+                    Ok(Err(e)) => log(Level::Warn, format_args!("Worker {} failed: {}", indx, e)),
+                    Err(_) => log(Level::Warn, format_args!("Worker {} panicked", indx)),
+                }
+                self.restart_worker(indx)?;
+            }
+        }
+        Ok(engines)
+    }
+
+    #[cfg(test)]
+    fn crash_worker(&mut self, indx: usize) {
+        let _ = self.workers[indx].tx.send(WorkerMsg::Crash);
+        if let Some(handle) = &self.workers[indx].handle {
+            while !handle.is_finished() {
+                thread::yield_now();
+            }
+        }
+    }
+}
+
+/// Applies logged txns to a restarted worker's engine, noting the ids & seqs of the deposits & withdrawals accepted
+fn replay(engine: &mut PaymentsEngine, txns: &[SequencedTxn], accepted: &mut Vec<(u32, u64)>) {
+    for s_txn in txns {
+        let applied = engine.process_sequenced_txn(s_txn).is_ok();
+        if let (true, Transaction::Deposit(p_txn) | Transaction::Withdrawal(p_txn)) =
+            (applied, &s_txn.txn)
+        {
+            accepted.push((p_txn.txn_id, s_txn.seq));
+        }
+    }
+}
+
+/// Accounts of every worker ordered by client id
+pub fn merge_accounts(engines: &[PaymentsEngine]) -> Vec<Account> {
+    let mut accounts: Vec<Account> = engines
+        .iter()
+        .flat_map(|engine| engine.accounts.iter().cloned())
+        .collect();
+    accounts.sort_by_key(|acnt| acnt.id);
+    accounts
+}
+
+/// Reads the input on the calling thread & processes it on the cluster's workers
+/// Writes the accounts of every worker as one output, ordered by client id
+pub fn cluster_execute(options: &ClusterOptions) -> Result<(), Box<dyn Error>> {
+    let cli_input = &options.cli_options;
+    let mut cluster = Cluster::new(
+        cli_input.engine_config.clone(),
+        options.workers,
+        options.snapshot_dir.as_deref(),
+        options.snapshot_every,
+    )?;
+    let file = File::open(&cli_input.input_file)?;
+    let reader = DecodingReader::new(file, cli_input.encoding).map_err(|e| {
+        io::Error::new(
+            e.kind(),
+            format!("Can't decode {}: {}", cli_input.input_file, e),
+        )
+    })?;
+    let mut rdr = ReaderBuilder::new().trim(Trim::All).from_reader(reader);
+    let headers = cli_input.header.apply(rdr.headers()?)?;
+    rdr.set_headers(headers);
+    let mut unreadable: u64 = 0;
+    for result in rdr.deserialize::<RawInputTxn>() {
+        let mut record = match result {
+            Ok(record) => record,
+            Err(e) if e.is_io_error() => return Err(e.into()),
+            Err(_) => {
+                unreadable += 1;
+                continue;
+            }
+        };
+        let txn = record
+            .apply_transforms(&cli_input.transforms)
+            .and_then(|_| record.convert_with_precision(cli_input.precision));
+        match txn {
+            Ok(txn) => cluster.submit(txn)?,
+            Err(_) => unreadable += 1,
+        }
+    }
+    if unreadable > 0 {
+        log(
+            Level::Warn,
+            format_args!("Skipped {} unreadable records", unreadable),
+        );
+    }
+    if cluster.duplicates() > 0 {
+        log(
+            Level::Warn,
+            format_args!(
+                "Rejected {} txns reusing the id of another worker's client",
+                cluster.duplicates()
+            ),
+        );
+    }
+    let restarts = cluster.restarts();
+    let engines = cluster.finish()?;
+    if restarts > 0 {
+        log(
+            Level::Warn,
+            format_args!("Workers were restarted {} times", restarts),
+        );
+    }
+
+    let accounts = merge_accounts(&engines);
+    let mut columns = AccountColumns {
+        lock_reasons: cli_input.extended_output,
+        activity: None,
+        chargeback_txns: None,
+    };
+    for engine in &engines {
+        if let Some(activity) = engine.account_columns(cli_input).activity {
+            columns
+                .activity
+                .get_or_insert_with(HashMap::new)
+                .extend(activity);
+        }
+    }
+    output_accounts(
+        &accounts,
+        &cli_input.output,
+        &columns,
+        &cli_input.csv_dialect,
+    );
+    if cli_input.checksum {
+        output_accounts_checksums(&accounts, &cli_input.output)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{merge_accounts, Cluster, HashRing};
+    use crate::generator::{seeded_rng, Scenario, ScenarioStream};
+    use crate::payments_engine::config::{EngineConfig, SafetyLimits};
+    use crate::payments_engine::PaymentsEngine;
+    use crate::test::utils::_get_test_output_file;
+    use crate::test_support::{deposit, pure_txn, withdrawal};
+    use crate::transaction::{PureTxn, RefTxn, Transaction};
+
+    #[test]
+    fn tst_hash_ring() {
+        let ring = HashRing::new(4);
+        let owners: Vec<usize> = (0..1000).map(|acnt_id| ring.route(acnt_id)).collect();
+        for worker in 0..4 {
+            let owned = owners.iter().filter(|owner| **owner == worker).count();
+            assert!(owned > 100, "Worker {} only owns {} clients", worker, owned);
+        }
+        // A fifth worker only takes clients over, the rest stay where they were
+        let grown = HashRing::new(5);
+        let moved = (0..1000)
+            .filter(|acnt_id| grown.route(*acnt_id) != owners[*acnt_id as usize])
+            .count();
+        assert!(moved < 400, "{} clients moved", moved);
+        assert!((0..1000).all(|acnt_id| {
+            let owner = grown.route(acnt_id);
+            owner == 4 || owner == owners[acnt_id as usize]
+        }));
+    }
+
+    #[test]
+    fn tst_cluster_matches_single_engine() {
+        let mut rng = seeded_rng(Some(11));
+        let mut stream = ScenarioStream::new(Scenario::Steady, 200);
+        let txns = stream.next_batch(20_000, &mut rng);
+
+        let mut single = PaymentsEngine::new();
+        let mut cluster = Cluster::new(EngineConfig::default(), 3, None, 1).unwrap();
+        for txn in &txns {
+            let _ = single.process_txn(txn);
+            assert!(cluster.submit(txn.clone()).is_ok());
+        }
+        let engines = cluster.finish().unwrap();
+        assert_eq!(engines.len(), 3);
+        let mut expected = single.accounts.to_vec();
+        expected.sort_by_key(|acnt| acnt.id);
+        assert_eq!(merge_accounts(&engines), expected);
+    }
+
+    #[test]
+    fn tst_cluster_worker_restart() {
+        let dispute = |ref_id: u32| {
+            Transaction::Dispute(RefTxn {
+                ref_id,
+                acnt_id: (ref_id % 20) as u16,
+            })
+        };
+        // Disputes after the restart refer to deposits from before the worker's latest snapshot
+        let txns: Vec<Transaction> = (1..=4000)
+            .map(|txn_id| match txn_id % 3 {
+                0 => withdrawal((txn_id % 20) as u16, txn_id, 1.5),
+                _ => deposit((txn_id % 20) as u16, txn_id, 2.0),
+            })
+            .chain((1..=60).filter(|ref_id| ref_id % 3 != 0).map(dispute))
+            .collect();
+        let expected = |txns: &[Transaction]| {
+            let mut single = PaymentsEngine::new();
+            for txn in txns {
+                let _ = single.process_txn(txn);
+            }
+            let mut expected = single.accounts.to_vec();
+            expected.sort_by_key(|acnt| acnt.id);
+            expected
+        };
+        // Restored from a snapshot the restarted worker only knows the ids of the txns before it, so
+        // its clients' disputes of those are rejected
+        let ring = HashRing::new(3);
+        let restored: Vec<Transaction> = txns
+            .iter()
+            .filter(|txn| !matches!(txn, Transaction::Dispute(_)) || ring.route(txn.acnt_id()) != 1)
+            .cloned()
+            .collect();
+        assert!(restored.len() < txns.len());
+
+        let snapshot_dir = _get_test_output_file("tst_cluster_worker_restart");
+        let _ = std::fs::remove_dir_all(&snapshot_dir);
+        for (snapshot_dir, expected) in [
+            (None, expected(&txns)),
+            (Some(snapshot_dir.as_str()), expected(&restored)),
+        ] {
+            assert!(expected.iter().any(|acnt| acnt.held().to_f64() > 0.0));
+            let mut cluster = Cluster::new(EngineConfig::default(), 3, snapshot_dir, 100).unwrap();
+            for (indx, txn) in txns.iter().enumerate() {
+                if indx == 2500 {
+                    cluster.crash_worker(1);
+                }
+                assert!(cluster.submit(txn.clone()).is_ok());
+            }
+            assert_eq!(cluster.restarts(), 1);
+            if snapshot_dir.is_some() {
+                // The log was truncated at the snapshot the worker restarted from, or a later one
+                assert!(cluster.workers[1].replay_log[0].seq > 100);
+            }
+            assert_eq!(merge_accounts(&cluster.finish().unwrap()), expected);
+        }
+        assert!(std::path::Path::new(&snapshot_dir)
+            .join("worker-01/slot-0/manifest.json")
+            .exists());
+    }
+
+    #[test]
+    fn tst_cluster_duplicate_ids() {
+        let ring = HashRing::new(2);
+        let other = (2..100)
+            .find(|acnt_id| ring.route(*acnt_id) != ring.route(1))
+            .unwrap();
+        let txns = [
+            deposit(1, 1, 1.0),
+            deposit(other, 1, 1.0),
+            deposit(other, 2, 1.0),
+            deposit(1, 2, 1.0),
+        ];
+
+        let mut single = PaymentsEngine::new();
+        let mut cluster = Cluster::new(EngineConfig::default(), 2, None, 1).unwrap();
+        for txn in &txns {
+            let _ = single.process_txn(txn);
+            assert!(cluster.submit(txn.clone()).is_ok());
+        }
+        assert_eq!(cluster.duplicates(), 2);
+        let mut expected = single.accounts.to_vec();
+        expected.sort_by_key(|acnt| acnt.id);
+        assert_eq!(merge_accounts(&cluster.finish().unwrap()), expected);
+    }
+
+    #[test]
+    fn tst_cluster_rejected_id_reused() {
+        let ring = HashRing::new(2);
+        let other = (2..100)
+            .find(|acnt_id| ring.route(*acnt_id) != ring.route(1))
+            .unwrap();
+        // A withdrawal from a client without an account is rejected & leaves its id free for another worker,
+        // once a worker accepts an id it stays taken
+        let txns = [
+            withdrawal(1, 1, 1.0),
+            deposit(other, 1, 1.0),
+            deposit(1, 2, 1.0),
+            deposit(other, 2, 1.0),
+        ];
+
+        let mut single = PaymentsEngine::new();
+        let mut cluster = Cluster::new(EngineConfig::default(), 2, None, 1).unwrap();
+        for txn in &txns {
+            let _ = single.process_txn(txn);
+            assert!(cluster.submit(txn.clone()).is_ok());
+        }
+        assert_eq!(cluster.duplicates(), 1);
+        let mut expected = single.accounts.to_vec();
+        expected.sort_by_key(|acnt| acnt.id);
+        assert_eq!(merge_accounts(&cluster.finish().unwrap()), expected);
+        assert_eq!(expected.len(), 2);
+    }
+
+    #[test]
+    fn tst_cluster_memory_limit() {
+        let config = EngineConfig {
+            limits: SafetyLimits {
+                max_memory_bytes: Some(1024),
+                ..SafetyLimits::default()
+            },
+            ..EngineConfig::default()
+        };
+        let mut cluster = Cluster::new(config, 2, None, 1).unwrap();
+        let deposit = Transaction::Deposit(PureTxn {
+            memo: Some("x".repeat(2048)),
+            ..pure_txn(1, 1, 1.0)
+        });
+        let err = cluster.submit(deposit).unwrap_err();
+        assert!(err.to_string().starts_with("Memory limit exceeded"));
+    }
+}
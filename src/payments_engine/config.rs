@@ -0,0 +1,389 @@
+use crate::transaction::{Channel, Transaction};
+use std::collections::{BTreeMap, HashSet};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// How the engine decides whether a txn id has been seen before
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum DuplicateCheck {
+    /// Look ids up in the in memory txn map
+    #[default]
+    Exact,
+    /// Bloom filter front end with an on disk confirmation store
+    /// Meant for very large id spaces where an in memory set is too costly
+    Probabilistic {
+        /// Number of txn ids the filter is sized for
+        expected_txns: usize,
+        /// Target false positive rate of the filter, positives fall back to the store
+        false_positive_rate: f64,
+        /// File accepted txn ids are kept in, hashed to the txns they refer to
+        store_path: String,
+    },
+}
+
+/// How a dispute is applied when the account's available funds can't cover the disputed amount
+/// Happens when a deposit is disputed after its funds were withdrawn
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum WithdrawnFundsDispute {
+    /// Hold the full amount, driving available negative
+    #[default]
+    AllowNegative,
+    /// Hold at most the available funds, resolves & chargebacks release only what was held
+    CapAtAvailable,
+    /// Hold the full amount & flag the account for manual review
+    FlagForReview,
+}
+
+impl FromStr for WithdrawnFundsDispute {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "allow-negative" => Ok(WithdrawnFundsDispute::AllowNegative),
+            "cap" => Ok(WithdrawnFundsDispute::CapAtAvailable),
+            "flag" => Ok(WithdrawnFundsDispute::FlagForReview),
+            _ => Err(format!("Unknown withdrawn funds dispute policy '{}'", s)),
+        }
+    }
+}
+
+/// Which accepted txns a dispute may refer to, other references are rejected as not disputable
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DisputableTxns {
+    #[default]
+    DepositsAndWithdrawals,
+    Deposits,
+    Withdrawals,
+}
+
+impl DisputableTxns {
+    /// True if a dispute of `txn` is allowed, never for disputes, resolves, chargebacks, or admin txns
+    pub fn allows(&self, txn: &Transaction) -> bool {
+        matches!(
+            (self, txn),
+            (
+                DisputableTxns::DepositsAndWithdrawals,
+                Transaction::Deposit(_)
+            ) | (
+                DisputableTxns::DepositsAndWithdrawals,
+                Transaction::Withdrawal(_)
+            ) | (DisputableTxns::Deposits, Transaction::Deposit(_))
+                | (DisputableTxns::Withdrawals, Transaction::Withdrawal(_))
+        )
+    }
+}
+
+impl FromStr for DisputableTxns {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "all" => Ok(DisputableTxns::DepositsAndWithdrawals),
+            "deposits" => Ok(DisputableTxns::Deposits),
+            "withdrawals" => Ok(DisputableTxns::Withdrawals),
+            _ => Err(format!("Unknown disputable txn types '{}'", s)),
+        }
+    }
+}
+
+/// What a chargeback does to the account besides removing the held funds
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ChargebackPolicy {
+    /// Lock the account, rejecting its later txns
+    #[default]
+    Freeze,
+    /// Leave the account open, for markets where chargebacks are routine
+    KeepOpen,
+}
+
+/// Hash function of the lookups keyed by client & txn id
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IdHasher {
+    /// Std's randomly seeded SipHash, resists inputs crafted to collide
+    #[default]
+    Sip,
+    /// Fx hash, much cheaper for integer ids but predictable, only for trusted inputs
+    Fx,
+}
+
+impl FromStr for IdHasher {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sip" => Ok(IdHasher::Sip),
+            "fx" => Ok(IdHasher::Fx),
+            _ => Err(format!("Unknown id hasher '{}'", s)),
+        }
+    }
+}
+
+/// How the lookups keyed by client & txn id find an id's entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IdIndex {
+    /// Hash map, suits any ids
+    #[default]
+    Hashed,
+    /// Vec indexed by the id, skips hashing while ids are dense & falls back to hashing once they aren't
+    Direct,
+}
+
+impl FromStr for IdIndex {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "hashed" => Ok(IdIndex::Hashed),
+            "direct" => Ok(IdIndex::Direct),
+            _ => Err(format!("Unknown id index '{}'", s)),
+        }
+    }
+}
+
+/// Rules of one payment rail, applied to deposits & withdrawals arriving over it
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ChannelRules {
+    /// Largest amount a single deposit or withdrawal may move, unlimited when unset
+    pub max_amount: Option<f64>,
+    /// Flat fee taken from available funds on each accepted deposit or withdrawal
+    pub fee: f64,
+    /// Disputes must arrive within this many sequence numbers of the txn, unlimited when unset
+    pub dispute_window: Option<u64>,
+}
+
+/// Bounds of the window retried records are dropped within
+#[derive(Debug, Clone, PartialEq)]
+pub struct DedupeWindowConfig {
+    /// Most recent records remembered per client
+    pub max_ids_per_client: usize,
+    /// Records are forgotten once this old, kept until pushed out by newer records when unset
+    pub max_age: Option<Duration>,
+}
+
+/// Client ids records are processed for, records of other clients are skipped at ingest
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ClientFilter {
+    /// Only these clients are processed when set
+    pub only: Option<HashSet<u16>>,
+    /// These clients are never processed
+    pub skip: HashSet<u16>,
+}
+
+impl ClientFilter {
+    pub fn allows(&self, acnt_id: u16) -> bool {
+        let listed = self
+            .only
+            .as_ref()
+            .is_none_or(|only| only.contains(&acnt_id));
+        listed && !self.skip.contains(&acnt_id)
+    }
+}
+
+/// Weights added to an account's risk score per event & the score which puts it under review
+#[derive(Debug, Clone, PartialEq)]
+pub struct RiskConfig {
+    /// Added when a withdrawal is rejected
+    pub failed_withdrawal: f64,
+    /// Added when a dispute is accepted
+    pub dispute: f64,
+    /// Added when a chargeback is accepted
+    pub chargeback: f64,
+    /// Added when a deposit or withdrawal is quarantined as an anomalous amount
+    pub anomaly: f64,
+    /// Accounts reaching this score are placed under review
+    pub review_threshold: f64,
+}
+
+impl Default for RiskConfig {
+    fn default() -> Self {
+        Self {
+            failed_withdrawal: 1.0,
+            dispute: 2.0,
+            chargeback: 5.0,
+            anomaly: 3.0,
+            review_threshold: 10.0,
+        }
+    }
+}
+
+/// Ceilings on deposit & withdrawal amounts inferred from each client's accepted amounts
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnomalyConfig {
+    /// Amounts more than this many standard deviations above the client's mean are quarantined
+    pub max_deviations: f64,
+    /// Amounts of a kind a client must have had accepted before its ceiling is enforced
+    pub min_history: u64,
+    /// Number of recent amounts the rolling mean & deviation mostly reflect
+    pub window: u64,
+}
+
+impl Default for AnomalyConfig {
+    fn default() -> Self {
+        Self {
+            max_deviations: 4.0,
+            min_history: 10,
+            window: 50,
+        }
+    }
+}
+
+/// Bounds on how far a streamed record's timestamp may be from the stream's current time
+/// Records outside them are rejected, e.g. rows of a partner whose clock is skewed
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct TimestampSanity {
+    /// Seconds a timestamp may be ahead of the current time, unchecked when unset
+    pub max_future_secs: Option<i64>,
+    /// Seconds a timestamp may be behind the current time, unchecked when unset
+    pub max_age_secs: Option<i64>,
+    /// Keep rejected records with the quarantined txns instead of only rejecting them
+    pub quarantine: bool,
+}
+
+/// Caps guarding the host against untrusted inputs, each is unlimited when unset
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct SafetyLimits {
+    pub max_accounts: Option<usize>,
+    pub max_processed_txns: Option<usize>,
+    /// Ceiling on `PaymentsEngine::estimated_memory_bytes`
+    pub max_memory_bytes: Option<usize>,
+}
+
+/// Tunable engine behavior, defaults match the original processing rules
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct EngineConfig {
+    pub duplicate_check: DuplicateCheck,
+    pub withdrawn_funds_dispute: WithdrawnFundsDispute,
+    /// Types of txn disputes may refer to
+    pub disputable_txns: DisputableTxns,
+    pub chargeback_policy: ChargebackPolicy,
+    /// Funds withdrawals may take beyond available ones, withdrawals available funds can't cover are rejected at 0
+    pub overdraft_limit: f64,
+    /// Keep accepted withdrawals only as ids for the duplicate check, needs only deposits to be disputable
+    /// Compacted withdrawals are left out of account histories & txn logs
+    pub compact_withdrawals: bool,
+    /// Records per txn id epoch, ids stored in an earlier epoch may be reused once upstream rolls its ids over
+    /// Ids stay duplicates within their epoch, ids of txns under dispute or pending approval are never reused
+    pub id_epoch: Option<u64>,
+    /// Most txns an account may have disputed at once, unlimited when unset
+    pub max_open_disputes: Option<usize>,
+    /// Flag accounts for review when a dispute is rejected for exceeding the open dispute cap
+    pub flag_dispute_floods: bool,
+    /// Drop retried records quietly while they are in a short window, the txn map still rejects older ones
+    pub dedupe_window: Option<DedupeWindowConfig>,
+    /// Restricts streamed records to some clients, all are processed when unset
+    pub client_filter: Option<ClientFilter>,
+    /// Withdrawals above this amount are held until an approve or deny admin record arrives
+    pub withdrawal_approval_threshold: Option<f64>,
+    /// File txn ids are registered in across runs, ids a previous run accepted are rejected
+    pub txn_registry: Option<String>,
+    /// Number of records the run is expected to see, used to pre-size transaction storage
+    pub expected_records: Option<usize>,
+    /// Number of clients the run is expected to see, used to pre-size account storage
+    pub expected_accounts: Option<usize>,
+    /// Hash function of the client & txn id lookups
+    pub id_hasher: IdHasher,
+    /// Whether the client & txn id lookups are hashed or indexed by id
+    pub id_index: IdIndex,
+    /// Scores accounts on risky events, quarantining transactions of high scorers, disabled when unset
+    pub risk: Option<RiskConfig>,
+    /// Quarantines deposits & withdrawals far above their client's usual amounts, disabled when unset
+    pub anomaly: Option<AnomalyConfig>,
+    /// Caps on engine growth, streaming stops once one is passed
+    pub limits: SafetyLimits,
+    /// Rules per payment rail, txns without a channel or of rails without rules are unaffected
+    pub channel_rules: BTreeMap<Channel, ChannelRules>,
+    /// Reject deposits which would open an account, so only onboarded or restored clients transact
+    pub require_onboarding: bool,
+    /// Minimum available funds of each regulatory reserve account by client
+    /// Withdrawals & disputes which would take a reserve account below its floor are rejected
+    pub reserve_floors: BTreeMap<u16, f64>,
+    /// Rejects streamed records dated too far in the future or past, timestamps aren't checked when unset
+    pub timestamp_sanity: Option<TimestampSanity>,
+}
+
+impl EngineConfig {
+    /// True if txns are accepted or rejected only by the original processing rules
+    /// Storage sizing, hashing, & safety limits don't change how a txn is decided
+    pub fn uses_standard_rules(&self) -> bool {
+        let standard = EngineConfig::default();
+        self.duplicate_check == standard.duplicate_check
+            && self.withdrawn_funds_dispute == standard.withdrawn_funds_dispute
+            && self.disputable_txns == standard.disputable_txns
+            && self.chargeback_policy == standard.chargeback_policy
+            && self.overdraft_limit == standard.overdraft_limit
+            && self.max_open_disputes.is_none()
+            && self.id_epoch.is_none()
+            && self.dedupe_window.is_none()
+            && self.client_filter.is_none()
+            && self.withdrawal_approval_threshold.is_none()
+            && self.txn_registry.is_none()
+            && self.risk.is_none()
+            && self.anomaly.is_none()
+            && self.channel_rules.is_empty()
+            && !self.require_onboarding
+            && self.reserve_floors.is_empty()
+            && self.timestamp_sanity.is_none()
+    }
+
+    /// Sets the policy a `key=value` override names, for what-if replays of a txn log
+    /// Values take the names the matching flag or rules file key takes, `dispute_window` sets every rail's window
+    pub fn apply_override(&mut self, text: &str) -> Result<(), String> {
+        let (key, value) = text
+            .split_once('=')
+            .ok_or_else(|| format!("Override '{}' should be written as <key>=<value>", text))?;
+        let (key, value) = (key.trim(), value.trim());
+        let invalid = || format!("Invalid value '{}' for override {}", value, key);
+        match key {
+            "freeze_policy" => {
+                self.chargeback_policy = match value {
+                    "always" => ChargebackPolicy::Freeze,
+                    "never" => ChargebackPolicy::KeepOpen,
+                    _ => return Err(invalid()),
+                }
+            }
+            "overdraft_limit" => {
+                let limit: f64 = value.parse().map_err(|_| invalid())?;
+                if !limit.is_finite() || limit < 0.0 {
+                    return Err(invalid());
+                }
+                self.overdraft_limit = limit;
+            }
+            "disputable" => self.disputable_txns = value.parse()?,
+            "withdrawn_funds" => self.withdrawn_funds_dispute = value.parse()?,
+            "max_open_disputes" => match value.parse() {
+                Ok(0) | Err(_) => return Err(invalid()),
+                Ok(max_open) => self.max_open_disputes = Some(max_open),
+            },
+            "approval_threshold" => {
+                self.withdrawal_approval_threshold = Some(value.parse().map_err(|_| invalid())?)
+            }
+            "dispute_window" => {
+                // Windows are counted in sequence numbers as inputs needn't carry timestamps
+                let window: u64 = value.parse().map_err(|_| {
+                    format!(
+                        "Invalid value '{}' for override dispute_window, it's counted in records",
+                        value
+                    )
+                })?;
+                for channel in [Channel::Card, Channel::Ach, Channel::Wire] {
+                    self.channel_rules
+                        .entry(channel)
+                        .or_default()
+                        .dispute_window = Some(window);
+                }
+            }
+            _ => return Err(format!("Unknown override '{}'", key)),
+        }
+        Ok(())
+    }
+}
+
+/// Which accounts `PaymentsEngine::gc_accounts` may collect & what happens to them
+#[derive(Debug, Clone, PartialEq)]
+pub struct GcPolicy {
+    /// Accounts must have had no accepted txn within this many sequence numbers of the latest one
+    /// Their transactions are assumed to be too old to be disputed
+    pub inactive_for: u64,
+    /// Collected accounts are appended to this csv, else they are dropped
+    pub archive_path: Option<String>,
+}
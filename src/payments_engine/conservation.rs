@@ -0,0 +1,215 @@
+//! Funds conservation check, a cheap end to end detector of balances corrupted by a bug
+//! Money enters by deposits & leaves by withdrawals, fees, refunds, & chargebacks, disputes & holds only move it
+//! within an account, so account totals must always add up to what entered less what left
+
+use super::PaymentsEngine;
+use crate::account::Account;
+use crate::amount::{format_minor_units, TxnAmount};
+use std::fmt;
+
+/// Ways money enters or leaves the engine's accounts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum FundsFlow {
+    Deposit,
+    Withdrawal,
+    Fee,
+    Refund,
+    Chargeback,
+}
+
+/// Money which entered & left accounts since the check was enabled, in minor units
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FundsLedger {
+    /// Account totals when the check was enabled, e.g. restored from a snapshot
+    pub opening: i64,
+    pub deposits: i64,
+    pub withdrawals: i64,
+    pub fees: i64,
+    pub refunds: i64,
+    pub chargebacks: i64,
+}
+
+impl FundsLedger {
+    /// What account totals should add up to
+    pub fn expected(&self) -> i64 {
+        self.opening + self.deposits
+            - self.withdrawals
+            - self.fees
+            - self.refunds
+            - self.chargebacks
+    }
+}
+
+/// Account totals which don't add up to the ledger
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConservationError {
+    pub ledger: FundsLedger,
+    /// Sum of account totals, in minor units
+    pub actual: i64,
+}
+
+impl fmt::Display for ConservationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ledger = &self.ledger;
+        write!(
+            f,
+            "Funds not conserved, accounts total {} but should total {}: opening {} + deposits {} - withdrawals {} - fees {} - refunds {} - chargebacks {}",
+            format_minor_units(self.actual),
+            format_minor_units(ledger.expected()),
+            format_minor_units(ledger.opening),
+            format_minor_units(ledger.deposits),
+            format_minor_units(ledger.withdrawals),
+            format_minor_units(ledger.fees),
+            format_minor_units(ledger.refunds),
+            format_minor_units(ledger.chargebacks),
+        )
+    }
+}
+
+impl std::error::Error for ConservationError {}
+
+impl PaymentsEngine {
+    /// Starts tracking money entering & leaving, taking current account totals as the opening balance
+    pub fn enable_conservation_check(&mut self) {
+        self.funds_ledger = Some(FundsLedger {
+            opening: self.accounts_total(),
+            ..FundsLedger::default()
+        });
+    }
+
+    /// Sum of account totals in minor units, saturating past the range the ledger holds
+    fn accounts_total(&self) -> i64 {
+        let total: i128 = self.accounts.iter().map(Account::total_minor_units).sum();
+        i64::try_from(total).unwrap_or(if total < 0 { i64::MIN } else { i64::MAX })
+    }
+
+    pub fn funds_ledger(&self) -> Option<&FundsLedger> {
+        self.funds_ledger.as_ref()
+    }
+
+    /// Notes money entering or leaving accounts as a txn moves it
+    pub(super) fn record_funds_flow(&mut self, flow: FundsFlow, amount: TxnAmount) {
+        let ledger = match &mut self.funds_ledger {
+            Some(ledger) => ledger,
+            None => return,
+        };
+        let amount = amount.minor_units();
+        match flow {
+            FundsFlow::Deposit => ledger.deposits += amount,
+            FundsFlow::Withdrawal => ledger.withdrawals += amount,
+            FundsFlow::Fee => ledger.fees += amount,
+            FundsFlow::Refund => ledger.refunds += amount,
+            FundsFlow::Chargeback => ledger.chargebacks += amount,
+        }
+    }
+
+    /// Errors if account totals don't add up to the money which entered less what left, Ok if the check isn't enabled
+    pub fn check_conservation(&self) -> Result<(), ConservationError> {
+        let ledger = match &self.funds_ledger {
+            Some(ledger) => ledger,
+            None => return Ok(()),
+        };
+        let actual = self.accounts_total();
+        match actual == ledger.expected() {
+            true => Ok(()),
+            false => Err(ConservationError {
+                ledger: *ledger,
+                actual,
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::account::Account;
+    use crate::amount::{Held, TxnAmount};
+    use crate::payments_engine::config::{ChannelRules, EngineConfig};
+    use crate::test_support::{deposit, pure_txn, withdrawal, EngineFixture};
+    use crate::transaction::{
+        AdminAction, AdminTxn, Channel, PureTxn, RefTxn, RefundTxn, Transaction,
+    };
+
+    #[test]
+    fn tst_conservation_check() {
+        let mut config = EngineConfig {
+            withdrawal_approval_threshold: Some(50.0),
+            ..EngineConfig::default()
+        };
+        config.channel_rules.insert(
+            Channel::Card,
+            ChannelRules {
+                fee: 1.0,
+                ..ChannelRules::default()
+            },
+        );
+        let mut fixture = EngineFixture::with_config(config)
+            .deposit(1, 1, 100.0)
+            .build();
+        let engine = &mut fixture.engine;
+        engine.enable_conservation_check();
+        assert_eq!(engine.funds_ledger().unwrap().opening, 1_000_000);
+
+        let txns = [
+            Transaction::Deposit(PureTxn {
+                channel: Some(Channel::Card),
+                ..pure_txn(2, 2, 30.0)
+            }),
+            // Parked until approved, so it only leaves on approval
+            withdrawal(1, 3, 60.0),
+            Transaction::Admin(AdminTxn {
+                instr_id: 3,
+                acnt_id: 1,
+                action: AdminAction::Approve,
+            }),
+            withdrawal(1, 4, 10.0),
+            Transaction::Refund(RefundTxn {
+                ref_id: 1,
+                acnt_id: 1,
+                amount: TxnAmount::from_f64(5.0),
+            }),
+            Transaction::Dispute(RefTxn {
+                ref_id: 2,
+                acnt_id: 2,
+            }),
+            Transaction::Chargeback(RefTxn {
+                ref_id: 2,
+                acnt_id: 2,
+            }),
+        ];
+        for txn in &txns {
+            assert!(engine.process_txn(txn).is_ok(), "{:?}", txn);
+        }
+        let ledger = *engine.funds_ledger().unwrap();
+        assert_eq!(
+            (
+                ledger.deposits,
+                ledger.withdrawals,
+                ledger.fees,
+                ledger.refunds,
+                ledger.chargebacks
+            ),
+            (300_000, 700_000, 10_000, 50_000, 300_000)
+        );
+        assert_eq!(ledger.expected(), 240_000);
+        assert!(engine.check_conservation().is_ok());
+
+        // Rolled back txns leave the ledger as they found it
+        let savepoint = engine.savepoint();
+        let deposit = deposit(1, 5, 7.0);
+        assert!(engine.process_txn(&deposit).is_ok());
+        assert!(engine.rollback_to(savepoint).is_ok());
+        assert_eq!(*engine.funds_ledger().unwrap(), ledger);
+        assert!(engine.check_conservation().is_ok());
+
+        let key = engine.accounts.key(1).unwrap();
+        let acnt = &engine.accounts[key];
+        let held = Held::from_f64(acnt.held().to_f64() + 0.5);
+        engine.accounts[key] = Account::with_balances(1, acnt.available(), held);
+        let err = engine.check_conservation().unwrap_err();
+        assert_eq!(err.actual - err.ledger.expected(), 5_000);
+        assert!(err
+            .to_string()
+            .starts_with("Funds not conserved, accounts total 24.5000 but should total 24.0000"));
+    }
+}
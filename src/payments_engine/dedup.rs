@@ -0,0 +1,339 @@
+use super::txn_arena::TxnKey;
+use std::collections::hash_map::DefaultHasher;
+use std::fs::{self, File, OpenOptions};
+use std::hash::{Hash, Hasher};
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+
+/// Fixed size bit set answering "definitely new" or "maybe seen" for txn ids
+#[derive(Debug)]
+pub struct BloomFilter {
+    bits: Vec<u64>,
+    num_bits: usize,
+    num_hashes: u32,
+}
+
+impl BloomFilter {
+    /// Sizes the filter to hold `expected_items` with roughly the given false positive rate
+    pub fn new(expected_items: usize, false_positive_rate: f64) -> Self {
+        let n = expected_items.max(1) as f64;
+        let p = false_positive_rate.clamp(f64::MIN_POSITIVE, 0.5);
+        let ln2 = std::f64::consts::LN_2;
+        let num_bits = (-(n * p.ln()) / (ln2 * ln2)).ceil().max(64.0) as usize;
+        let num_hashes = ((num_bits as f64 / n) * ln2).round().max(1.0) as u32;
+        Self {
+            bits: vec![0; num_bits.div_ceil(64)],
+            num_bits,
+            num_hashes,
+        }
+    }
+
+    /// Double hashing, positions are h1 + i * h2
+    fn positions(&self, item: u32) -> impl Iterator<Item = usize> + '_ {
+        let mut hasher = DefaultHasher::new();
+        item.hash(&mut hasher);
+        let h1 = hasher.finish();
+        let mut hasher = DefaultHasher::new();
+        (item, 0x9e37_79b9_u32).hash(&mut hasher);
+        let h2 = hasher.finish() | 1;
+        (0..self.num_hashes as u64)
+            .map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % self.num_bits as u64) as usize)
+    }
+
+    pub fn insert(&mut self, item: u32) {
+        let positions: Vec<usize> = self.positions(item).collect();
+        for pos in positions {
+            self.bits[pos / 64] |= 1 << (pos % 64);
+        }
+    }
+
+    /// False means the item was never inserted, true means it may have been
+    pub fn may_contain(&self, item: u32) -> bool {
+        self.positions(item)
+            .all(|pos| self.bits[pos / 64] & (1 << (pos % 64)) != 0)
+    }
+}
+
+/// Bytes of a store slot, a txn id, its txn key's generation, & a tag holding its txn key's index
+const SLOT_LEN: u64 = 16;
+/// Tag of a slot never used, a fresh store is all empty slots
+const EMPTY: u64 = 0;
+/// Tag of a slot whose id was removed by a rollback, probes carry on past it
+const REMOVED: u64 = 1;
+/// Tag of an id whose txn isn't kept, e.g. a compacted withdrawal
+const UNKEYED: u64 = 2;
+/// Tags from here on are a txn key's index offset by this
+const FIRST_INDX: u64 = 3;
+
+/// Where probing for an id ended
+struct Probe {
+    /// Slot holding the id, else the slot it would be inserted in
+    slot: u64,
+    /// Generation & tag of the id's slot, None if the id isn't stored
+    found: Option<(u32, u64)>,
+    /// Tag the slot had, inserting into an empty slot uses up another slot
+    tag: u64,
+}
+
+/// Open addressing hash table of txn ids kept in a file, probed linearly from an id's hash
+#[derive(Debug)]
+struct HashedStore {
+    file: File,
+    /// Slots in the table, a power of two
+    slots: u64,
+    /// Slots holding an id or a removed id, the table grows before they fill three quarters of it
+    used: u64,
+}
+
+impl HashedStore {
+    fn create(path: &str, slots: u64) -> Result<Self, io::Error> {
+        let file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .write(true)
+            .truncate(true)
+            .open(path)?;
+        file.set_len(slots * SLOT_LEN)?;
+        Ok(Self {
+            file,
+            slots,
+            used: 0,
+        })
+    }
+
+    fn is_full(&self) -> bool {
+        (self.used + 1) * 4 > self.slots * 3
+    }
+
+    /// Fibonacci hashing spreads sequential ids across the table
+    fn home(&self, txn_id: u32) -> u64 {
+        (txn_id as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15) >> (64 - self.slots.trailing_zeros())
+    }
+
+    fn read_slot(&self, slot: u64) -> Result<(u32, u32, u64), io::Error> {
+        let mut buf = [0_u8; SLOT_LEN as usize];
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(slot * SLOT_LEN))?;
+        file.read_exact(&mut buf)?;
+        Ok(decode_slot(&buf))
+    }
+
+    fn write_slot(
+        &self,
+        slot: u64,
+        txn_id: u32,
+        generation: u32,
+        tag: u64,
+    ) -> Result<(), io::Error> {
+        let mut buf = [0_u8; SLOT_LEN as usize];
+        buf[..4].copy_from_slice(&txn_id.to_le_bytes());
+        buf[4..8].copy_from_slice(&generation.to_le_bytes());
+        buf[8..].copy_from_slice(&tag.to_le_bytes());
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(slot * SLOT_LEN))?;
+        file.write_all(&buf)
+    }
+
+    fn probe(&self, txn_id: u32) -> Result<Probe, io::Error> {
+        let mut slot = self.home(txn_id);
+        let mut insert_at = None;
+        loop {
+            let (id, generation, tag) = self.read_slot(slot)?;
+            match tag {
+                EMPTY => {
+                    let (slot, tag) = insert_at.unwrap_or((slot, EMPTY));
+                    return Ok(Probe {
+                        slot,
+                        found: None,
+                        tag,
+                    });
+                }
+                REMOVED => {
+                    insert_at.get_or_insert((slot, REMOVED));
+                }
+                _ if id == txn_id => {
+                    return Ok(Probe {
+                        slot,
+                        found: Some((generation, tag)),
+                        tag,
+                    })
+                }
+                _ => {}
+            }
+            slot = (slot + 1) & (self.slots - 1);
+        }
+    }
+
+    /// Stores the id with its tag, replacing what was stored for it
+    fn put(&mut self, txn_id: u32, generation: u32, tag: u64) -> Result<(), io::Error> {
+        let probe = self.probe(txn_id)?;
+        self.write_slot(probe.slot, txn_id, generation, tag)?;
+        self.used += (probe.tag == EMPTY) as u64;
+        Ok(())
+    }
+}
+
+fn decode_slot(buf: &[u8; SLOT_LEN as usize]) -> (u32, u32, u64) {
+    let txn_id = u32::from_le_bytes(buf[..4].try_into().unwrap());
+    let generation = u32::from_le_bytes(buf[4..8].try_into().unwrap());
+    let tag = u64::from_le_bytes(buf[8..].try_into().unwrap());
+    (txn_id, generation, tag)
+}
+
+/// Two tier duplicate check, a bloom filter front end backed by an on disk store of accepted ids
+/// The store maps ids to their txn keys, so disputes find their txn without ids being held in memory
+/// The store is only read when the filter reports a possible match
+#[derive(Debug)]
+pub struct DuplicateFilter {
+    bloom: BloomFilter,
+    store_path: String,
+    store: HashedStore,
+}
+
+impl DuplicateFilter {
+    /// Creates a filter with a fresh confirmation store at `store_path`
+    pub fn new(
+        expected_txns: usize,
+        false_positive_rate: f64,
+        store_path: &str,
+    ) -> Result<Self, io::Error> {
+        let slots = (expected_txns as u64 * 4 / 3 + 1)
+            .next_power_of_two()
+            .max(64);
+        Ok(Self {
+            bloom: BloomFilter::new(expected_txns, false_positive_rate),
+            store_path: store_path.to_string(),
+            store: HashedStore::create(store_path, slots)?,
+        })
+    }
+
+    /// Generation & tag stored for the id, None if it isn't stored
+    fn find(&self, txn_id: u32) -> Result<Option<(u32, u64)>, io::Error> {
+        if !self.bloom.may_contain(txn_id) {
+            return Ok(None);
+        }
+        Ok(self.store.probe(txn_id)?.found)
+    }
+
+    /// Returns true if the txn id has already been recorded
+    pub fn contains(&self, txn_id: u32) -> Result<bool, io::Error> {
+        Ok(self.find(txn_id)?.is_some())
+    }
+
+    /// Key of the recorded txn with this id, None if the id wasn't recorded or its txn isn't kept
+    pub(super) fn txn_key(&self, txn_id: u32) -> Result<Option<TxnKey>, io::Error> {
+        Ok(match self.find(txn_id)? {
+            Some((generation, tag)) if tag >= FIRST_INDX => {
+                Some(TxnKey::from_parts((tag - FIRST_INDX) as usize, generation))
+            }
+            _ => None,
+        })
+    }
+
+    /// Records an accepted txn id with the key of its txn, if the txn is kept
+    pub(super) fn insert(&mut self, txn_id: u32, txn_key: Option<TxnKey>) -> Result<(), io::Error> {
+        if self.store.is_full() {
+            self.grow()?;
+        }
+        let (generation, tag) = match txn_key.map(TxnKey::into_parts) {
+            Some((indx, generation)) => (generation, indx as u64 + FIRST_INDX),
+            None => (0, UNKEYED),
+        };
+        self.store.put(txn_id, generation, tag)?;
+        self.bloom.insert(txn_id);
+        Ok(())
+    }
+
+    /// Forgets an id, e.g. of a rolled back txn, the bloom filter still reports it as a possible match
+    pub fn remove(&mut self, txn_id: u32) -> Result<(), io::Error> {
+        if !self.bloom.may_contain(txn_id) {
+            return Ok(());
+        }
+        let probe = self.store.probe(txn_id)?;
+        match probe.found {
+            Some(_) => self.store.write_slot(probe.slot, 0, 0, REMOVED),
+            None => Ok(()),
+        }
+    }
+
+    /// Rehashes the store into one twice the size, which replaces it once complete
+    fn grow(&mut self) -> Result<(), io::Error> {
+        let grow_path = format!("{}.grow", self.store_path);
+        let mut grown = HashedStore::create(&grow_path, self.store.slots * 2)?;
+        let mut file = &self.store.file;
+        file.seek(SeekFrom::Start(0))?;
+        let mut rdr = BufReader::new(file);
+        let mut buf = [0_u8; SLOT_LEN as usize];
+        for _ in 0..self.store.slots {
+            rdr.read_exact(&mut buf)?;
+            let (txn_id, generation, tag) = decode_slot(&buf);
+            if tag >= UNKEYED {
+                grown.put(txn_id, generation, tag)?;
+            }
+        }
+        fs::rename(&grow_path, &self.store_path)?;
+        self.store = grown;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BloomFilter, DuplicateFilter, SLOT_LEN};
+    use crate::payments_engine::txn_arena::TxnKey;
+    use crate::test::utils::_get_test_output_file;
+
+    #[test]
+    fn tst_bloom_filter() {
+        let mut bloom = BloomFilter::new(1000, 0.01);
+        for ii in 0..1000 {
+            bloom.insert(ii);
+        }
+        assert!(
+            (0..1000).all(|ii| bloom.may_contain(ii)),
+            "Should never have false negatives"
+        );
+        let false_positives = (1000..11000).filter(|ii| bloom.may_contain(*ii)).count();
+        assert!(
+            false_positives < 500,
+            "False positive rate should be near configured rate"
+        );
+    }
+
+    #[test]
+    fn tst_duplicate_filter() {
+        let f = _get_test_output_file("tst_duplicate_filter.bin");
+        let mut filter = DuplicateFilter::new(10, 0.01, f.as_str()).unwrap();
+        let size = || std::fs::metadata(&f).unwrap().len();
+        assert_eq!(size(), 64 * SLOT_LEN, "Should start with the minimum table");
+        assert!(!filter.contains(1).unwrap());
+        filter.insert(1, Some(TxnKey::from_parts(0, 0))).unwrap();
+        filter.insert(2, None).unwrap();
+        assert!(filter.contains(1).unwrap());
+        assert!(filter.contains(2).unwrap());
+        assert!(!filter.contains(3).unwrap());
+        assert_eq!(filter.txn_key(1).unwrap(), Some(TxnKey::from_parts(0, 0)));
+        assert_eq!(
+            filter.txn_key(2).unwrap(),
+            None,
+            "Compacted ids have no txn"
+        );
+
+        // Rolled back ids are forgotten, ids probed past them are still found
+        filter.remove(1).unwrap();
+        assert!(!filter.contains(1).unwrap());
+        assert!(filter.contains(2).unwrap());
+
+        // Sequential & colliding ids grow the table past its minimum & keep their keys
+        for ii in 3..200 {
+            filter
+                .insert(ii << 16, Some(TxnKey::from_parts(ii as usize, 1)))
+                .unwrap();
+        }
+        assert_eq!(size(), 512 * SLOT_LEN);
+        assert!((3..200).all(
+            |ii| filter.txn_key(ii << 16).unwrap() == Some(TxnKey::from_parts(ii as usize, 1))
+        ));
+        assert!(filter.contains(2).unwrap());
+        assert!(!filter.contains(1).unwrap());
+    }
+}
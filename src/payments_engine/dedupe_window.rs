@@ -0,0 +1,278 @@
+use super::config::DedupeWindowConfig;
+use super::limits::MAP_ENTRY_OVERHEAD;
+use super::PaymentsEngine;
+use crate::transaction::Transaction;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, VecDeque};
+use std::mem::size_of;
+
+/// Bytes a held id costs in a client's queue & lookup map
+const HELD_ID_BYTES: usize = 2 * size_of::<(u32, i64)>() + MAP_ENTRY_OVERHEAD;
+
+/// Deposits & withdrawals recently applied for a single client, oldest first
+#[derive(Debug, Default)]
+struct ClientWindow {
+    /// Txn ids with the clock time they were applied at
+    order: VecDeque<(u32, i64)>,
+    /// The same ids by the time they were applied at
+    ids: HashMap<u32, i64>,
+}
+
+impl ClientWindow {
+    /// Forgets ids applied longer than `max_age` seconds ago, returns how many were forgotten
+    fn expire(&mut self, now: i64, max_age: i64) -> usize {
+        let mut expired = 0;
+        while let Some((id, seen)) = self.order.front() {
+            if now.saturating_sub(*seen) <= max_age {
+                break;
+            }
+            self.ids.remove(id);
+            self.order.pop_front();
+            expired += 1;
+        }
+        expired
+    }
+}
+
+/// Deposits & withdrawals are the only records the window holds, their ids are unique so a repeat is a retry
+/// Disputes, resolves, & the rest reuse the id of the txn they refer to, e.g. a dispute after a resolve
+fn is_windowed(txn: &Transaction) -> bool {
+    matches!(txn, Transaction::Deposit(_) | Transaction::Withdrawal(_))
+}
+
+/// Sliding window of recently applied deposits & withdrawals per client
+/// Retries of them inside the window are dropped quietly instead of being rejected as duplicates,
+/// records which were rejected aren't held so retrying them, e.g. once funds arrive, processes them again
+#[derive(Debug)]
+pub struct DedupeWindow {
+    config: DedupeWindowConfig,
+    clients: HashMap<u16, ClientWindow>,
+    /// Ids held over every client's window
+    held: usize,
+    /// Records dropped because they were already in the window
+    absorbed: u64,
+}
+
+impl DedupeWindow {
+    pub fn new(config: DedupeWindowConfig) -> Self {
+        Self {
+            config,
+            clients: HashMap::new(),
+            held: 0,
+            absorbed: 0,
+        }
+    }
+
+    /// True if the record retries a deposit or withdrawal applied recently, without counting or expiring anything
+    pub fn holds(&self, txn: &Transaction, now: i64) -> bool {
+        if !is_windowed(txn) {
+            return false;
+        }
+        let seen = match self
+            .clients
+            .get(&txn.acnt_id())
+            .and_then(|w| w.ids.get(&txn.id()))
+        {
+            Some(seen) => *seen,
+            None => return false,
+        };
+        self.config
+            .max_age
+            .is_none_or(|max_age| now.saturating_sub(seen) <= max_age.as_secs() as i64)
+    }
+
+    /// Returns true, counting it as absorbed, if the record retries a deposit or withdrawal applied recently
+    /// `now` is in unix seconds, as engine clocks tell it
+    pub fn check(&mut self, txn: &Transaction, now: i64) -> bool {
+        if !is_windowed(txn) {
+            return false;
+        }
+        let window = match self.clients.get_mut(&txn.acnt_id()) {
+            Some(window) => window,
+            None => return false,
+        };
+        if let Some(max_age) = self.config.max_age {
+            self.held -= window.expire(now, max_age.as_secs() as i64);
+        }
+        let retried = window.ids.contains_key(&txn.id());
+        self.absorbed += retried as u64;
+        retried
+    }
+
+    /// Adds an applied deposit or withdrawal to its client's window, other records are ignored
+    pub fn insert(&mut self, txn: &Transaction, now: i64) {
+        if !is_windowed(txn) {
+            return;
+        }
+        let window = self.clients.entry(txn.acnt_id()).or_default();
+        if let Entry::Vacant(seen) = window.ids.entry(txn.id()) {
+            seen.insert(now);
+            window.order.push_back((txn.id(), now));
+            self.held += 1;
+        }
+        if window.order.len() > self.config.max_ids_per_client {
+            if let Some((oldest, _)) = window.order.pop_front() {
+                window.ids.remove(&oldest);
+                self.held -= 1;
+            }
+        }
+    }
+
+    /// Rough size of the held ids & the clients' windows
+    pub fn memory_bytes(&self) -> usize {
+        self.clients.len() * (size_of::<u16>() + size_of::<ClientWindow>() + MAP_ENTRY_OVERHEAD)
+            + self.held * HELD_ID_BYTES
+    }
+}
+
+impl PaymentsEngine {
+    /// True if a dedupe window is configured & the record is a retry of one inside it
+    pub(super) fn is_windowed_duplicate(&mut self, txn: &Transaction) -> bool {
+        match &mut self.dedupe_window {
+            Some(window) => window.check(txn, self.clock.now()),
+            None => false,
+        }
+    }
+
+    /// True if a dedupe window is configured & holds the record, without counting it as absorbed
+    pub(super) fn is_windowed_retry(&self, txn: &Transaction) -> bool {
+        self.dedupe_window
+            .as_ref()
+            .is_some_and(|window| window.holds(txn, self.clock.now()))
+    }
+
+    /// Adds an applied record to the dedupe window if one is configured
+    pub(super) fn window_applied(&mut self, txn: &Transaction) {
+        if let Some(window) = &mut self.dedupe_window {
+            window.insert(txn, self.clock.now());
+        }
+    }
+
+    /// Number of retried records the dedupe window dropped
+    pub fn duplicates_absorbed(&self) -> u64 {
+        self.dedupe_window.as_ref().map_or(0, |w| w.absorbed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DedupeWindow;
+    use crate::clock::ClockSource;
+    use crate::payments_engine::config::{DedupeWindowConfig, EngineConfig};
+    use crate::payments_engine::stream_process::StreamOptions;
+    use crate::payments_engine::PaymentsEngine;
+    use crate::test_support::deposit;
+    use crate::transaction::{RefTxn, Transaction};
+    use std::time::Duration;
+
+    #[test]
+    fn tst_dedupe_window() {
+        let mut window = DedupeWindow::new(DedupeWindowConfig {
+            max_ids_per_client: 2,
+            max_age: Some(Duration::from_secs(60)),
+        });
+        let now = 1714571100;
+        assert!(
+            !window.check(&deposit(1, 1, 1.0), now),
+            "Records are only held once applied"
+        );
+        window.insert(&deposit(1, 1, 1.0), now);
+        assert!(window.check(&deposit(1, 1, 1.0), now));
+        assert!(
+            !window.check(&deposit(2, 1, 1.0), now),
+            "Windows are per client"
+        );
+        let dispute = Transaction::Dispute(RefTxn {
+            ref_id: 1,
+            acnt_id: 1,
+        });
+        window.insert(&dispute, now);
+        assert!(
+            !window.check(&dispute, now),
+            "Disputes share ids with the txns they reference & aren't windowed"
+        );
+
+        // Client 1 window holds 2 records so the first deposit gets pushed out
+        window.insert(&deposit(1, 2, 1.0), now);
+        window.insert(&deposit(1, 3, 1.0), now);
+        assert!(!window.check(&deposit(1, 1, 1.0), now));
+        assert!(window.check(&deposit(1, 2, 1.0), now));
+        assert_eq!(window.held, 2);
+
+        let later = now + 61;
+        assert!(
+            !window.check(&deposit(1, 2, 1.0), later),
+            "Records older than max age should expire"
+        );
+        assert_eq!(window.absorbed, 2);
+        assert_eq!(window.held, 0, "Expired ids stop counting toward memory");
+    }
+
+    #[test]
+    fn tst_dedupe_window_record_clock() {
+        let config = EngineConfig {
+            dedupe_window: Some(DedupeWindowConfig {
+                max_ids_per_client: 10,
+                max_age: Some(Duration::from_secs(60)),
+            }),
+            ..EngineConfig::default()
+        };
+        // Replays go by record time, however fast they are read
+        let records = "type,client,tx,amount,timestamp\n\
+                       deposit,1,1,1.0,2024-05-01T13:45:00Z\n\
+                       deposit,1,1,1.0,2024-05-01T13:46:00Z\n\
+                       deposit,1,1,1.0,2024-05-01T13:47:01Z\n";
+        let mut payments_engine = PaymentsEngine::with_config(config).unwrap();
+        payments_engine.set_clock(ClockSource::Records.clock());
+        assert!(payments_engine
+            .stream_process_reader(records.as_bytes(), true, &StreamOptions::default())
+            .is_ok());
+        assert_eq!(payments_engine.now(), 1714571221);
+        assert_eq!(payments_engine.duplicates_absorbed(), 1);
+    }
+
+    fn windowed_engine() -> PaymentsEngine {
+        PaymentsEngine::with_config(EngineConfig {
+            dedupe_window: Some(DedupeWindowConfig {
+                max_ids_per_client: 10,
+                max_age: None,
+            }),
+            ..EngineConfig::default()
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn tst_dedupe_window_retry_after_reject() {
+        // The first withdrawal lacks funds, its retry once the deposit lands goes through
+        let records = "type,client,tx,amount\n\
+                       withdrawal,1,2,4.0\n\
+                       deposit,1,1,5.0\n\
+                       withdrawal,1,2,4.0\n\
+                       withdrawal,1,2,4.0\n";
+        let mut payments_engine = windowed_engine();
+        assert!(payments_engine
+            .stream_process_reader(records.as_bytes(), true, &StreamOptions::default())
+            .is_ok());
+        let acnt = payments_engine.iter_accounts().next().unwrap();
+        assert_eq!((acnt.available(), acnt.held()), (1.0, 0.0));
+        assert_eq!(payments_engine.duplicates_absorbed(), 1);
+    }
+
+    #[test]
+    fn tst_dedupe_window_redispute() {
+        // Disputes reuse the id of their txn, a dispute after a resolve isn't a retry
+        let records = "type,client,tx,amount\n\
+                       deposit,1,1,5.0\n\
+                       dispute,1,1,\n\
+                       resolve,1,1,\n\
+                       dispute,1,1,\n";
+        let mut payments_engine = windowed_engine();
+        assert!(payments_engine
+            .stream_process_reader(records.as_bytes(), true, &StreamOptions::default())
+            .is_ok());
+        let acnt = payments_engine.iter_accounts().next().unwrap();
+        assert_eq!((acnt.available(), acnt.held()), (0.0, 5.0));
+        assert_eq!(payments_engine.duplicates_absorbed(), 0);
+    }
+}
@@ -0,0 +1,291 @@
+use super::PaymentsEngine;
+use crate::amount::{format_amount, Amount, TxnAmount};
+use crate::transaction::Transaction;
+use csv::Writer;
+use std::collections::BTreeMap;
+use std::error::Error;
+
+/// A txn still under dispute & how long it has been, so ops can chase it before chargeback deadlines
+#[derive(Debug, Clone, PartialEq)]
+pub struct DisputeAge {
+    pub acnt_id: u16,
+    pub txn_id: u32,
+    /// Funds the dispute holds, less than the txn amount when withdrawn funds were only partly held
+    pub held: TxnAmount,
+    /// Sequence number of the dispute record
+    pub disputed_seq: u64,
+    /// Records processed since the dispute, the engine has no clock so age is counted in records
+    pub age: u64,
+}
+
+/// What holds part of an account's funds
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HeldSource {
+    Dispute,
+    PendingApproval,
+    /// Held funds no open dispute or pending withdrawal accounts for, e.g. restored with `--initial-state`
+    Unattributed,
+}
+
+impl HeldSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HeldSource::Dispute => "dispute",
+            HeldSource::PendingApproval => "pending_approval",
+            HeldSource::Unattributed => "unattributed",
+        }
+    }
+}
+
+/// A txn contributing to an account's held funds
+/// `txn_id`, `seq`, & `age` aren't set for unattributed funds
+#[derive(Debug, Clone, PartialEq)]
+pub struct HeldPart {
+    pub acnt_id: u16,
+    pub source: HeldSource,
+    pub txn_id: Option<u32>,
+    pub amount: TxnAmount,
+    /// Sequence number of the dispute or of the withdrawal parked for approval
+    pub seq: Option<u64>,
+    /// Records processed since `seq`
+    pub age: Option<u64>,
+}
+
+impl PaymentsEngine {
+    /// Txns currently under dispute, oldest dispute first
+    pub fn dispute_aging(&self) -> Vec<DisputeAge> {
+        let mut aging: Vec<DisputeAge> = self
+            .processed_txns
+            .iter()
+            .filter_map(|s_txn| match &s_txn.txn {
+                Transaction::Deposit(p_txn) | Transaction::Withdrawal(p_txn)
+                    if p_txn.dispute.is_disputed() =>
+                {
+                    let disputed_seq = p_txn.dispute.transitions().last()?.seq;
+                    Some(DisputeAge {
+                        acnt_id: p_txn.acnt_id,
+                        txn_id: p_txn.txn_id,
+                        held: self
+                            .held_amounts
+                            .get(&p_txn.txn_id)
+                            .copied()
+                            .unwrap_or(p_txn.amount),
+                        disputed_seq,
+                        age: self.last_seq.saturating_sub(disputed_seq),
+                    })
+                }
+                _ => None,
+            })
+            .collect();
+        aging.sort_by_key(|entry| entry.disputed_seq);
+        aging
+    }
+
+    /// Breaks each account's held funds down into the txns holding them, by client then oldest first
+    /// Whatever the txns don't account for ends the client's parts as unattributed
+    pub fn held_breakdown(&self) -> Vec<HeldPart> {
+        let mut parts: BTreeMap<u16, Vec<HeldPart>> = BTreeMap::new();
+        for entry in self.dispute_aging() {
+            parts.entry(entry.acnt_id).or_default().push(HeldPart {
+                acnt_id: entry.acnt_id,
+                source: HeldSource::Dispute,
+                txn_id: Some(entry.txn_id),
+                amount: entry.held,
+                seq: Some(entry.disputed_seq),
+                age: Some(entry.age),
+            });
+        }
+        for pending in self.pending_withdrawals() {
+            parts.entry(pending.acnt_id).or_default().push(HeldPart {
+                acnt_id: pending.acnt_id,
+                source: HeldSource::PendingApproval,
+                txn_id: Some(pending.txn_id),
+                amount: pending.amount,
+                seq: Some(pending.seq),
+                age: Some(self.last_seq.saturating_sub(pending.seq)),
+            });
+        }
+        for acnt in self.accounts.iter() {
+            if acnt.held().minor_units() != 0 {
+                parts.entry(acnt.id).or_default();
+            }
+        }
+
+        let mut breakdown = vec![];
+        for (acnt_id, mut acnt_parts) in parts {
+            acnt_parts.sort_by_key(|part| part.seq);
+            let held = self
+                .accounts
+                .get(acnt_id)
+                .map_or(0, |acnt| acnt.held().minor_units());
+            let attributed: i64 = acnt_parts
+                .iter()
+                .map(|part| part.amount.minor_units())
+                .sum();
+            let rest = held - attributed;
+            breakdown.extend(acnt_parts);
+            if rest != 0 {
+                breakdown.push(HeldPart {
+                    acnt_id,
+                    source: HeldSource::Unattributed,
+                    txn_id: None,
+                    amount: TxnAmount::new(Amount::from_minor_units(rest)),
+                    seq: None,
+                    age: None,
+                });
+            }
+        }
+        breakdown
+    }
+
+    /// Writes a row per txn holding funds with the account's held total, so each row shows its share
+    pub fn output_held_breakdown_csv(&self, file_path: &str) -> Result<(), Box<dyn Error>> {
+        let optional = |value: Option<u64>| value.map_or(String::new(), |value| value.to_string());
+        let mut wtr = Writer::from_path(file_path)?;
+        wtr.write_record(["client", "held", "source", "tx", "amount", "seq", "age"])?;
+        for part in self.held_breakdown() {
+            let held = self
+                .accounts
+                .get(part.acnt_id)
+                .map_or(0.0, |acnt| acnt.held().to_f64());
+            wtr.write_record([
+                part.acnt_id.to_string(),
+                format_amount(held),
+                part.source.as_str().to_string(),
+                part.txn_id
+                    .map_or(String::new(), |txn_id| txn_id.to_string()),
+                part.amount.amount().to_string(),
+                optional(part.seq),
+                optional(part.age),
+            ])?;
+        }
+        wtr.flush()?;
+        Ok(())
+    }
+
+    /// Writes txns currently under dispute with their age to a csv report, oldest first
+    pub fn output_dispute_aging_csv(&self, file_path: &str) -> Result<(), Box<dyn Error>> {
+        let mut wtr = Writer::from_path(file_path)?;
+        wtr.write_record(["client", "tx", "held", "disputed_seq", "age"])?;
+        for entry in self.dispute_aging() {
+            wtr.write_record([
+                entry.acnt_id.to_string(),
+                entry.txn_id.to_string(),
+                entry.held.amount().to_string(),
+                entry.disputed_seq.to_string(),
+                entry.age.to_string(),
+            ])?;
+        }
+        wtr.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DisputeAge, HeldPart, HeldSource};
+    use crate::account::Account;
+    use crate::amount::{Held, TxnAmount};
+    use crate::payments_engine::config::{EngineConfig, WithdrawnFundsDispute};
+    use crate::test::utils::_get_test_output_file;
+    use crate::test_support::EngineFixture;
+
+    #[test]
+    fn tst_dispute_aging() {
+        let config = EngineConfig {
+            withdrawn_funds_dispute: WithdrawnFundsDispute::CapAtAvailable,
+            ..EngineConfig::default()
+        };
+        let payments_engine = EngineFixture::with_config(config)
+            .deposit(1, 1, 10.0)
+            .deposit(2, 2, 5.0)
+            .deposit(1, 3, 4.0)
+            .withdrawal(2, 4, 3.0)
+            .dispute(2, 2)
+            .dispute(1, 3)
+            .resolve(1, 3)
+            .dispute(1, 1)
+            .deposit(1, 5, 1.0)
+            .build()
+            .engine;
+        assert_eq!(
+            payments_engine.dispute_aging(),
+            [
+                // Only the 2.0 left after the withdrawal could be held
+                DisputeAge {
+                    acnt_id: 2,
+                    txn_id: 2,
+                    held: TxnAmount::from_f64(2.0),
+                    disputed_seq: 5,
+                    age: 4,
+                },
+                DisputeAge {
+                    acnt_id: 1,
+                    txn_id: 1,
+                    held: TxnAmount::from_f64(10.0),
+                    disputed_seq: 8,
+                    age: 1,
+                },
+            ]
+        );
+
+        let f_aging = _get_test_output_file("tst_dispute_aging.csv");
+        assert!(payments_engine.output_dispute_aging_csv(&f_aging).is_ok());
+        assert_eq!(
+            std::fs::read_to_string(&f_aging).unwrap(),
+            "client,tx,held,disputed_seq,age\n2,2,2.0000,5,4\n1,1,10.0000,8,1\n"
+        );
+    }
+
+    #[test]
+    fn tst_held_breakdown() {
+        let config = EngineConfig {
+            withdrawal_approval_threshold: Some(50.0),
+            ..EngineConfig::default()
+        };
+        let mut payments_engine = EngineFixture::with_config(config)
+            .deposit(1, 1, 100.0)
+            .deposit(1, 2, 20.0)
+            .deposit(2, 3, 5.0)
+            .withdrawal(1, 4, 60.0)
+            .dispute(1, 2)
+            .dispute(2, 3)
+            .resolve(2, 3)
+            .build()
+            .engine;
+        // Held restored from elsewhere has no txn behind it
+        let acnt_key = payments_engine.accounts.key(1).unwrap();
+        let acnt = &payments_engine.accounts[acnt_key];
+        let held = Held::from_f64(acnt.held().to_f64() + 0.5);
+        payments_engine.accounts[acnt_key] = Account::with_balances(1, acnt.available(), held);
+
+        let part = |source, txn_id: Option<u32>, amount: f64, seq: Option<u64>| HeldPart {
+            acnt_id: 1,
+            source,
+            txn_id,
+            amount: TxnAmount::from_f64(amount),
+            seq,
+            age: seq.map(|seq| 7 - seq),
+        };
+        assert_eq!(
+            payments_engine.held_breakdown(),
+            [
+                part(HeldSource::PendingApproval, Some(4), 60.0, Some(4)),
+                part(HeldSource::Dispute, Some(2), 20.0, Some(5)),
+                part(HeldSource::Unattributed, None, 0.5, None),
+            ]
+        );
+
+        let f_breakdown = _get_test_output_file("tst_held_breakdown.csv");
+        assert!(payments_engine
+            .output_held_breakdown_csv(&f_breakdown)
+            .is_ok());
+        assert_eq!(
+            std::fs::read_to_string(&f_breakdown).unwrap(),
+            "client,held,source,tx,amount,seq,age\n\
+             1,80.5000,pending_approval,4,60.0000,4,3\n\
+             1,80.5000,dispute,2,20.0000,5,2\n\
+             1,80.5000,unattributed,,0.5000,,\n"
+        );
+    }
+}
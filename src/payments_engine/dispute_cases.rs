@@ -0,0 +1,200 @@
+use super::PaymentsEngine;
+use crate::transaction::{DisputeState, PureTxn, Transaction};
+use csv::Writer;
+use std::error::Error;
+use std::mem::size_of;
+
+/// One dispute of a txn, from the record opening it to the one closing it, with the notes support left on it
+/// A txn disputed again after being resolved has a case per dispute
+#[derive(Debug, Clone, PartialEq)]
+pub struct DisputeCase {
+    pub acnt_id: u16,
+    pub txn_id: u32,
+    /// `Disputed` while the case is open, else how it was closed
+    pub status: DisputeState,
+    /// Sequence number of the dispute record
+    pub opened_seq: u64,
+    /// Sequence number of the resolve or chargeback record, None while open
+    pub closed_seq: Option<u64>,
+    /// Memos of the case's dispute, resolve, & chargeback records, oldest first
+    pub notes: Vec<String>,
+}
+
+/// Bytes a case note holds, its text & its place in the txn's list of notes
+pub(super) fn note_bytes(note: &str) -> usize {
+    size_of::<(u64, String)>() + note.len()
+}
+
+impl PaymentsEngine {
+    /// Remembers the memo of an applied dispute, resolve, or chargeback record as a note on its case
+    pub(super) fn note_dispute_case(&mut self, ref_id: u32, note: String) {
+        self.case_note_bytes += note_bytes(&note);
+        self.case_notes
+            .entry(ref_id)
+            .or_default()
+            .push((self.last_seq, note));
+    }
+
+    /// Cases of a txn built from its dispute history, notes are given to the case open when they were made
+    fn cases_of(&self, p_txn: &PureTxn) -> Vec<DisputeCase> {
+        let mut cases: Vec<DisputeCase> = vec![];
+        for transition in p_txn.dispute.transitions() {
+            match transition.state {
+                DisputeState::Disputed => cases.push(DisputeCase {
+                    acnt_id: p_txn.acnt_id,
+                    txn_id: p_txn.txn_id,
+                    status: DisputeState::Disputed,
+                    opened_seq: transition.seq,
+                    closed_seq: None,
+                    notes: vec![],
+                }),
+                DisputeState::Resolved | DisputeState::ChargedBack => {
+                    if let Some(case) = cases.last_mut() {
+                        case.status = transition.state;
+                        case.closed_seq = Some(transition.seq);
+                    }
+                }
+                DisputeState::Undisputed => {}
+            }
+        }
+        let notes = self
+            .case_notes
+            .get(&p_txn.txn_id)
+            .map_or(&[][..], Vec::as_slice);
+        for (seq, note) in notes {
+            // Notes from before the first case belong to an earlier txn which had the same id
+            if let Some(case) = cases.iter_mut().rev().find(|case| case.opened_seq <= *seq) {
+                case.notes.push(note.clone());
+            }
+        }
+        cases
+    }
+
+    /// Dispute cases of a client's txns, in the order the txns were processed
+    pub fn dispute_cases(&self, acnt_id: u16) -> Vec<DisputeCase> {
+        self.account_txns
+            .get(&acnt_id)
+            .map_or(&[][..], Vec::as_slice)
+            .iter()
+            .flat_map(|txn_key| match &self.processed_txns[*txn_key].txn {
+                Transaction::Deposit(p_txn) | Transaction::Withdrawal(p_txn) => {
+                    self.cases_of(p_txn)
+                }
+                _ => vec![],
+            })
+            .collect()
+    }
+
+    /// Txn id of the first chargeback against a client, the one which locked the account
+    pub fn locking_chargeback(&self, acnt_id: u16) -> Option<u32> {
+        self.dispute_cases(acnt_id)
+            .into_iter()
+            .filter(|case| case.status == DisputeState::ChargedBack)
+            .min_by_key(|case| case.closed_seq)
+            .map(|case| case.txn_id)
+    }
+
+    /// Writes every dispute case to a csv report, in the order cases were opened
+    /// Notes are joined with ` | `
+    pub fn output_dispute_cases_csv(&self, file_path: &str) -> Result<(), Box<dyn Error>> {
+        let mut cases: Vec<DisputeCase> = self
+            .processed_txns
+            .iter()
+            .flat_map(|s_txn| match &s_txn.txn {
+                Transaction::Deposit(p_txn) | Transaction::Withdrawal(p_txn) => {
+                    self.cases_of(p_txn)
+                }
+                _ => vec![],
+            })
+            .collect();
+        cases.sort_by_key(|case| case.opened_seq);
+
+        let mut wtr = Writer::from_path(file_path)?;
+        wtr.write_record([
+            "client",
+            "tx",
+            "status",
+            "opened_seq",
+            "closed_seq",
+            "notes",
+        ])?;
+        for case in cases {
+            let status = match case.status {
+                DisputeState::Disputed => "open",
+                status => status.as_str(),
+            };
+            wtr.write_record([
+                case.acnt_id.to_string(),
+                case.txn_id.to_string(),
+                status.to_string(),
+                case.opened_seq.to_string(),
+                case.closed_seq.map_or(String::new(), |seq| seq.to_string()),
+                case.notes.join(" | "),
+            ])?;
+        }
+        wtr.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DisputeCase;
+    use crate::payments_engine::stream_process::StreamOptions;
+    use crate::payments_engine::PaymentsEngine;
+    use crate::test::utils::_get_test_output_file;
+    use crate::transaction::DisputeState;
+
+    #[test]
+    fn tst_dispute_cases() {
+        let mut payments_engine = PaymentsEngine::new();
+        let records = "type,client,tx,amount,memo\n\
+                       deposit,1,1,10.0,\n\
+                       deposit,1,2,5.0,\n\
+                       dispute,1,1,,customer says goods never arrived\n\
+                       resolve,1,1,,tracking shows delivery\n\
+                       dispute,1,1,,\n\
+                       dispute,1,2,,second claim\n\
+                       chargeback,1,2,,\n\
+                       dispute,1,9,,ignored as the dispute is rejected\n";
+        assert!(payments_engine
+            .stream_process_reader(records.as_bytes(), true, &StreamOptions::default())
+            .is_ok());
+        let case = |txn_id, status, opened_seq, closed_seq, notes: &[&str]| DisputeCase {
+            acnt_id: 1,
+            txn_id,
+            status,
+            opened_seq,
+            closed_seq,
+            notes: notes.iter().map(|note| note.to_string()).collect(),
+        };
+        assert_eq!(
+            payments_engine.dispute_cases(1),
+            [
+                case(
+                    1,
+                    DisputeState::Resolved,
+                    3,
+                    Some(4),
+                    &[
+                        "customer says goods never arrived",
+                        "tracking shows delivery"
+                    ]
+                ),
+                case(1, DisputeState::Disputed, 5, None, &[]),
+                case(2, DisputeState::ChargedBack, 6, Some(7), &["second claim"]),
+            ]
+        );
+        assert!(payments_engine.dispute_cases(2).is_empty());
+
+        let f_cases = _get_test_output_file("tst_dispute_cases.csv");
+        assert!(payments_engine.output_dispute_cases_csv(&f_cases).is_ok());
+        assert_eq!(
+            std::fs::read_to_string(&f_cases).unwrap(),
+            "client,tx,status,opened_seq,closed_seq,notes\n\
+             1,1,resolved,3,4,customer says goods never arrived | tracking shows delivery\n\
+             1,1,open,5,,\n\
+             1,2,chargedback,6,7,second claim\n"
+        );
+    }
+}
@@ -0,0 +1,302 @@
+//! Typed events of what processing did, sent over a channel so embedders can build projections without
+//! polling engine state
+//! Events are sent as txns are decided & aren't taken back when a savepoint is rolled back
+
+use super::transactions::TxnErrors;
+use super::PaymentsEngine;
+use crate::account::Account;
+use crate::amount::TxnAmount;
+use crate::transaction::{AdminAction, SequencedTxn, Transaction};
+use std::sync::mpsc::{self, Receiver};
+
+/// What happened to an account
+#[derive(Debug, Clone, PartialEq)]
+pub enum EventKind {
+    /// A txn opened the client's account, sent ahead of the txn's own event
+    AccountCreated,
+    Deposited {
+        tx: u32,
+        amount: TxnAmount,
+    },
+    Withdrew {
+        tx: u32,
+        amount: TxnAmount,
+    },
+    /// A withdrawal above the approval threshold is holding its funds until approved or denied
+    WithdrawalPendingApproval {
+        tx: u32,
+        amount: TxnAmount,
+    },
+    /// `held` is what the dispute moved to held funds, less than the txn's amount when capped
+    DisputeOpened {
+        tx: u32,
+        held: TxnAmount,
+    },
+    DisputeResolved {
+        tx: u32,
+    },
+    ChargedBack {
+        tx: u32,
+    },
+    Refunded {
+        tx: u32,
+        amount: TxnAmount,
+    },
+    Released {
+        tx: u32,
+        amount: TxnAmount,
+    },
+    /// An admin instruction was applied, `instr` is the id of the withdrawal decided for approvals & denials
+    Admin {
+        instr: u32,
+        action: AdminAction,
+    },
+    /// The account was locked by a chargeback or an admin hold, sent after the txn's own event
+    AccountFrozen,
+    /// The account's last lock was cleared, sent after the txn's own event
+    AccountUnfrozen,
+    /// A txn wasn't applied & changed nothing
+    Rejected {
+        error: TxnErrors,
+    },
+}
+
+/// An event of the txn processed under `seq` for `client`
+#[derive(Debug, Clone, PartialEq)]
+pub struct EngineEvent {
+    pub seq: u64,
+    pub client: u16,
+    pub kind: EventKind,
+}
+
+impl PaymentsEngine {
+    /// Sends an event for every txn processed from now on to the returned receiver, in processing order
+    /// Replaces any earlier subscriber, the channel is unbounded so the receiver should be read as events arrive
+    pub fn subscribe_events(&mut self) -> Receiver<EngineEvent> {
+        let (sender, receiver) = mpsc::channel();
+        self.event_sender = Some(sender);
+        receiver
+    }
+
+    /// True if the client's account is locked, read before a txn so its events can tell a lock changed
+    /// Skipped when nobody is subscribed
+    pub(super) fn locked_before_events(&self, acnt_id: u16) -> bool {
+        self.event_sender.is_some() && self.accounts.get(acnt_id).is_some_and(Account::is_locked)
+    }
+
+    /// Event of an applied txn's own change to the account
+    fn txn_event_kind(&self, txn: &Transaction) -> EventKind {
+        match txn {
+            Transaction::Deposit(p_txn) => EventKind::Deposited {
+                tx: p_txn.txn_id,
+                amount: p_txn.amount,
+            },
+            Transaction::Withdrawal(p_txn) if self.is_pending_approval(p_txn.txn_id) => {
+                EventKind::WithdrawalPendingApproval {
+                    tx: p_txn.txn_id,
+                    amount: p_txn.amount,
+                }
+            }
+            Transaction::Withdrawal(p_txn) => EventKind::Withdrew {
+                tx: p_txn.txn_id,
+                amount: p_txn.amount,
+            },
+            Transaction::Dispute(ref_txn) => EventKind::DisputeOpened {
+                tx: ref_txn.ref_id,
+                held: self.disputed_hold(ref_txn.ref_id),
+            },
+            Transaction::Resolve(ref_txn) => EventKind::DisputeResolved { tx: ref_txn.ref_id },
+            Transaction::Chargeback(ref_txn) => EventKind::ChargedBack { tx: ref_txn.ref_id },
+            Transaction::Refund(refund_txn) => EventKind::Refunded {
+                tx: refund_txn.ref_id,
+                amount: refund_txn.amount,
+            },
+            Transaction::Release(release_txn) => EventKind::Released {
+                tx: release_txn.ref_id,
+                amount: release_txn.amount,
+            },
+            Transaction::Admin(admin_txn) => EventKind::Admin {
+                instr: admin_txn.instr_id,
+                action: admin_txn.action,
+            },
+        }
+    }
+
+    /// Funds a dispute of the txn holds, its whole amount unless the dispute was capped or partly released
+    fn disputed_hold(&self, ref_id: u32) -> TxnAmount {
+        if let Some(hold) = self.held_amounts.get(&ref_id) {
+            return *hold;
+        }
+        match self
+            .stored_txn_key(ref_id)
+            .map(|txn_key| &self.processed_txns[txn_key].txn)
+        {
+            Some(Transaction::Deposit(p_txn) | Transaction::Withdrawal(p_txn)) => p_txn.amount,
+            _ => TxnAmount::default(),
+        }
+    }
+
+    /// Sends the events of a processed txn, dropping the subscription once its receiver is gone
+    pub(super) fn send_events(
+        &mut self,
+        s_txn: &SequencedTxn,
+        result: &Result<(), TxnErrors>,
+        accounts_before: usize,
+        locked_before: bool,
+    ) {
+        if self.event_sender.is_none() {
+            return;
+        }
+        let client = s_txn.txn.acnt_id();
+        let mut kinds = vec![];
+        match result {
+            Ok(()) => {
+                if self.accounts.len() > accounts_before {
+                    kinds.push(EventKind::AccountCreated);
+                }
+                kinds.push(self.txn_event_kind(&s_txn.txn));
+                let locked = self.accounts.get(client).is_some_and(Account::is_locked);
+                match (locked_before, locked) {
+                    (false, true) => kinds.push(EventKind::AccountFrozen),
+                    (true, false) => kinds.push(EventKind::AccountUnfrozen),
+                    _ => {}
+                }
+            }
+            Err(e) => kinds.push(EventKind::Rejected { error: e.clone() }),
+        }
+        let sent = self.event_sender.as_ref().is_some_and(|sender| {
+            kinds.into_iter().all(|kind| {
+                sender
+                    .send(EngineEvent {
+                        seq: s_txn.seq,
+                        client,
+                        kind,
+                    })
+                    .is_ok()
+            })
+        });
+        if !sent {
+            self.event_sender = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EngineEvent, EventKind};
+    use crate::amount::TxnAmount;
+    use crate::payments_engine::config::EngineConfig;
+    use crate::payments_engine::transactions::TxnErrors;
+    use crate::test_support::{deposit, withdrawal, EngineFixture};
+    use crate::transaction::{AdminAction, AdminTxn, RefTxn, Transaction};
+
+    fn admin(instr_id: u32, action: AdminAction) -> Transaction {
+        Transaction::Admin(AdminTxn {
+            instr_id,
+            acnt_id: 1,
+            action,
+        })
+    }
+
+    #[test]
+    fn tst_engine_events() {
+        let config = EngineConfig {
+            withdrawal_approval_threshold: Some(50.0),
+            ..EngineConfig::default()
+        };
+        let mut engine = EngineFixture::with_config(config)
+            .deposit(1, 1, 10.0)
+            .build()
+            .engine;
+        let events = engine.subscribe_events();
+        let ref_txn = RefTxn {
+            ref_id: 1,
+            acnt_id: 1,
+        };
+        let txns = [
+            deposit(1, 2, 100.0),
+            deposit(2, 3, 5.0),
+            withdrawal(1, 4, 60.0),
+            withdrawal(2, 5, 9.0),
+            admin(6, AdminAction::SetHold),
+            admin(7, AdminAction::ClearHold),
+            Transaction::Dispute(ref_txn.clone()),
+            Transaction::Chargeback(ref_txn),
+            deposit(1, 8, 1.0),
+        ];
+        for txn in &txns {
+            let _ = engine.process_txn(txn);
+        }
+
+        let rejected = |error| EventKind::Rejected { error };
+        let received: Vec<(u64, u16, EventKind)> = events
+            .try_iter()
+            .map(|EngineEvent { seq, client, kind }| (seq, client, kind))
+            .collect();
+        assert_eq!(
+            received,
+            [
+                (
+                    2,
+                    1,
+                    EventKind::Deposited {
+                        tx: 2,
+                        amount: TxnAmount::from_f64(100.0)
+                    }
+                ),
+                (3, 2, EventKind::AccountCreated),
+                (
+                    3,
+                    2,
+                    EventKind::Deposited {
+                        tx: 3,
+                        amount: TxnAmount::from_f64(5.0)
+                    }
+                ),
+                (
+                    4,
+                    1,
+                    EventKind::WithdrawalPendingApproval {
+                        tx: 4,
+                        amount: TxnAmount::from_f64(60.0)
+                    }
+                ),
+                (5, 2, rejected(TxnErrors::AccountLacksFunds)),
+                (
+                    6,
+                    1,
+                    EventKind::Admin {
+                        instr: 6,
+                        action: AdminAction::SetHold
+                    }
+                ),
+                (6, 1, EventKind::AccountFrozen),
+                (
+                    7,
+                    1,
+                    EventKind::Admin {
+                        instr: 7,
+                        action: AdminAction::ClearHold
+                    }
+                ),
+                (7, 1, EventKind::AccountUnfrozen),
+                (
+                    8,
+                    1,
+                    EventKind::DisputeOpened {
+                        tx: 1,
+                        held: TxnAmount::from_f64(10.0)
+                    }
+                ),
+                (9, 1, EventKind::ChargedBack { tx: 1 }),
+                (9, 1, EventKind::AccountFrozen),
+                (10, 1, rejected(TxnErrors::AccountFrozen)),
+            ]
+        );
+
+        // The subscription ends once its receiver is gone
+        drop(events);
+        assert!(engine.process_txn(&txns[0]).is_err());
+        assert!(engine.event_sender.is_none());
+    }
+}
@@ -0,0 +1,333 @@
+//! Read-only exploration of a past run, rebuilt from the `--txn-log` it wrote
+//! Queries are read a line at a time & answered from the rebuilt state, records are never applied
+
+use super::trim_log::{logged_txn, read_opening_state, seq_column, txn_log_reader};
+use super::PaymentsEngine;
+use crate::cli_io::{account_header, account_record, txn_record, AccountColumns, ExploreOptions};
+use crate::diagnostics::{log, Level};
+use crate::transaction::{AdminAction, DisputeState, Transaction};
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::{self, BufRead, IsTerminal, Write};
+
+const HELP: &str = "\
+account <client>  balances, lock reasons, & activity of a client
+why <client>      what locked a client's account
+history <client>  the client's logged txns
+txn <tx>          a deposit or withdrawal & its dispute history
+summary           state summary of the whole log
+quit              stop exploring
+";
+
+/// Types of records, which explore refuses to apply
+const RECORD_TYPES: [&str; 11] = [
+    "deposit",
+    "withdrawal",
+    "dispute",
+    "resolve",
+    "chargeback",
+    "refund",
+    "release",
+    "hold",
+    "unhold",
+    "approve",
+    "deny",
+];
+
+/// Answer about a client, or why the client id couldn't be read
+fn with_client(client: &str, answer: impl FnOnce(u16) -> String) -> String {
+    match client.parse() {
+        Ok(acnt_id) => answer(acnt_id),
+        Err(_) => format!("Invalid client id '{}'\n", client),
+    }
+}
+
+impl PaymentsEngine {
+    /// Rebuilds state by applying a txn log's records under their logged sequence numbers
+    /// A trimmed log's opening state is restored first & logged account sequence numbers carry on from the log
+    /// Returns how many logged records were rejected, which happens when the log was written under other rules
+    pub fn load_txn_log(&mut self, file_path: &str) -> Result<usize, Box<dyn Error>> {
+        if let Some(opening) = read_opening_state(file_path)? {
+            self.restore_opening_state(&opening)?;
+        }
+        let mut rdr = txn_log_reader(file_path)?;
+        let headers = rdr.headers()?.clone();
+        let seq_indx = seq_column(file_path, &headers)?;
+        let acnt_seq_indx = headers.iter().position(|h| h == "acnt_seq");
+        let mut rejected = 0;
+        for record in rdr.records() {
+            let record = record?;
+            let s_txn = logged_txn(file_path, &headers, seq_indx, &record)?;
+            // Logs written before account sequence numbers, & quarantined records, leave them empty
+            if let Some(acnt_seq) = acnt_seq_indx.and_then(|indx| record[indx].parse().ok()) {
+                self.observe_account_seq(s_txn.txn.acnt_id(), acnt_seq);
+            }
+            if self.process_sequenced_txn(&s_txn).is_err() {
+                rejected += 1;
+            }
+        }
+        Ok(rejected)
+    }
+
+    /// Loads the txn log & answers queries from stdin on stdout until stdin ends or a quit query
+    pub fn explore_execute(
+        &mut self,
+        explore_options: &ExploreOptions,
+    ) -> Result<(), Box<dyn Error>> {
+        let rejected = self.load_txn_log(&explore_options.txn_log)?;
+        log(
+            Level::Info,
+            format_args!(
+                "Loaded {} txns of {} clients up to seq {}, ask help for queries",
+                self.processed_txns.len(),
+                self.accounts.len(),
+                self.last_seq
+            ),
+        );
+        if rejected > 0 {
+            log(
+                Level::Warn,
+                format_args!(
+                    "{} logged records were rejected on replay, the log may have been written under other --rules",
+                    rejected
+                ),
+            );
+        }
+        let stdin = io::stdin();
+        let prompt = stdin.is_terminal();
+        self.explore(stdin.lock(), io::stdout().lock(), prompt)?;
+        Ok(())
+    }
+
+    /// Answers queries read a line at a time until the input ends or a quit query
+    pub fn explore<R: BufRead, W: Write>(
+        &self,
+        mut input: R,
+        mut output: W,
+        prompt: bool,
+    ) -> Result<(), io::Error> {
+        loop {
+            if prompt {
+                write!(output, "> ")?;
+                output.flush()?;
+            }
+            let mut line = String::new();
+            if input.read_line(&mut line)? == 0 {
+                return Ok(());
+            }
+            match line.trim() {
+                "quit" | "exit" => return Ok(()),
+                "" => {}
+                query => {
+                    output.write_all(self.answer_query(query).as_bytes())?;
+                    output.flush()?;
+                }
+            }
+        }
+    }
+
+    /// Answers one query, every answer ends with a newline
+    pub fn answer_query(&self, query: &str) -> String {
+        let words: Vec<&str> = query
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|word| !word.is_empty())
+            .collect();
+        match words.as_slice() {
+            ["help"] => HELP.to_string(),
+            ["summary"] => format!("{}\n", self.state_summary()),
+            ["account", client] => with_client(client, |acnt_id| self.describe_account(acnt_id)),
+            ["why", client] => with_client(client, |acnt_id| self.explain_lock(acnt_id)),
+            ["history", client] => with_client(client, |acnt_id| self.describe_history(acnt_id)),
+            ["txn", tx] => match tx.parse() {
+                Ok(txn_id) => self.describe_txn(txn_id),
+                Err(_) => format!("Invalid txn id '{}'\n", tx),
+            },
+            [first, ..] if RECORD_TYPES.contains(first) => {
+                "Exploring is read-only, records aren't applied\n".to_string()
+            }
+            _ => format!("Unknown query '{}', try help\n", query),
+        }
+    }
+
+    fn describe_account(&self, acnt_id: u16) -> String {
+        let acnt = match self.accounts.get(acnt_id) {
+            Some(acnt) => acnt,
+            None => return format!("No account for client {}\n", acnt_id),
+        };
+        let columns = AccountColumns {
+            lock_reasons: true,
+            activity: Some(HashMap::from([(acnt_id, self.account_activity(acnt_id))])),
+            chargeback_txns: None,
+        };
+        format!(
+            "{}\n{}\n",
+            account_header(&columns).join(","),
+            account_record(acnt, &columns).join(",")
+        )
+    }
+
+    /// Chargebacks & admin holds behind a locked account, with the sequence numbers of their records
+    fn explain_lock(&self, acnt_id: u16) -> String {
+        let acnt = match self.accounts.get(acnt_id) {
+            Some(acnt) => acnt,
+            None => return format!("No account for client {}\n", acnt_id),
+        };
+        if !acnt.is_locked() {
+            return format!("Client {} isn't locked\n", acnt_id);
+        }
+        let mut reasons = vec![];
+        if acnt.locked_by_chargeback {
+            let chargebacks: Vec<String> = self
+                .dispute_cases(acnt_id)
+                .into_iter()
+                .filter(|case| case.status == DisputeState::ChargedBack)
+                .map(|case| {
+                    let mut reason = format!(
+                        "Chargeback of tx {} at seq {}, disputed at seq {}",
+                        case.txn_id,
+                        case.closed_seq.unwrap_or_default(),
+                        case.opened_seq
+                    );
+                    if !case.notes.is_empty() {
+                        reason += &format!(", notes: {}", case.notes.join(" | "));
+                    }
+                    reason
+                })
+                .collect();
+            match chargebacks.is_empty() {
+                true => reasons.push("Chargeback from before the log started".to_string()),
+                false => reasons.extend(chargebacks),
+            }
+        }
+        if acnt.admin_hold {
+            let hold_seq = self
+                .account_history(acnt_id, 0, usize::MAX)
+                .into_iter()
+                .rev()
+                .find_map(|s_txn| match &s_txn.txn {
+                    Transaction::Admin(admin_txn) if admin_txn.action == AdminAction::SetHold => {
+                        Some(s_txn.seq)
+                    }
+                    _ => None,
+                });
+            reasons.push(match hold_seq {
+                Some(seq) => format!("Admin hold placed at seq {}", seq),
+                None => "Admin hold from before the log started".to_string(),
+            });
+        }
+        reasons
+            .iter()
+            .map(|reason| format!("{}\n", reason))
+            .collect()
+    }
+
+    fn describe_history(&self, acnt_id: u16) -> String {
+        let history = self.account_history(acnt_id, 0, usize::MAX);
+        if history.is_empty() {
+            return format!("No txns for client {}\n", acnt_id);
+        }
+        let mut answer = "seq,type,client,tx,amount,acnt_seq\n".to_string();
+        for (offset, s_txn) in history.into_iter().enumerate() {
+            answer += &format!(
+                "{},{},{}\n",
+                s_txn.seq,
+                txn_record(&s_txn.txn).join(","),
+                self.account_seq_at(acnt_id, offset)
+            );
+        }
+        answer
+    }
+
+    fn describe_txn(&self, txn_id: u32) -> String {
+        let s_txn = match self.stored_txn_key(txn_id) {
+            Some(txn_key) => &self.processed_txns[txn_key],
+            None => return format!("No deposit or withdrawal with tx {}\n", txn_id),
+        };
+        let dispute = match &s_txn.txn {
+            Transaction::Deposit(p_txn) | Transaction::Withdrawal(p_txn) => &p_txn.dispute,
+            _ => return format!("No deposit or withdrawal with tx {}\n", txn_id),
+        };
+        format!(
+            "seq,type,client,tx,amount,dispute_state,dispute_history\n{},{},{},{}\n",
+            s_txn.seq,
+            txn_record(&s_txn.txn).join(","),
+            dispute.state().as_str(),
+            dispute.to_export_str()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cli_io::{output_txn_log_csv, parse_cli_args, CliCommand};
+    use crate::payments_engine::PaymentsEngine;
+    use crate::test::utils::_get_test_output_file;
+    use crate::test_support::EngineFixture;
+    use crate::transaction::AdminAction;
+
+    #[test]
+    fn tst_explore_txn_log() {
+        let fixture = EngineFixture::new()
+            .deposit(1, 1, 10.0)
+            .deposit(1, 2, 5.0)
+            .dispute(1, 1)
+            .chargeback(1, 1)
+            .deposit(2, 3, 1.0)
+            .admin(2, 4, AdminAction::SetHold)
+            .build();
+        let f_log = _get_test_output_file("tst_explore_txn_log.csv");
+        assert!(output_txn_log_csv(fixture.engine.numbered_txns(), &f_log).is_ok());
+
+        let explore_options = match parse_cli_args(&["explore".to_string(), f_log.clone()]) {
+            Ok(CliCommand::Explore(explore_options)) => explore_options,
+            _ => panic!("Should parse as explore command"),
+        };
+        let mut payments_engine = PaymentsEngine::new();
+        assert_eq!(
+            payments_engine
+                .load_txn_log(&explore_options.txn_log)
+                .unwrap(),
+            0
+        );
+        assert_eq!(
+            payments_engine.state_summary(),
+            fixture.engine.state_summary()
+        );
+
+        let queries = "why 1\nwhy 2\n\naccount 2\nhistory 1\ntxn 1\ndeposit, 1, 9, 1.0\nwhy x\nquit\nsummary\n";
+        let mut answers = vec![];
+        assert!(payments_engine
+            .explore(queries.as_bytes(), &mut answers, false)
+            .is_ok());
+        assert_eq!(
+            String::from_utf8(answers).unwrap(),
+            "Chargeback of tx 1 at seq 4, disputed at seq 3\n\
+             Admin hold placed at seq 6\n\
+             client,available,held,total,locked,locked_by_chargeback,admin_hold,status,disputes,chargebacks,last_txn_id\n\
+             2,1.0000,0.0000,1.0000,true,false,true,on_hold,0,0,3\n\
+             seq,type,client,tx,amount,acnt_seq\n\
+             1,deposit,1,1,10.0000,1\n\
+             2,deposit,1,2,5.0000,2\n\
+             3,dispute,1,1,,3\n\
+             4,chargeback,1,1,,4\n\
+             seq,type,client,tx,amount,dispute_state,dispute_history\n\
+             1,deposit,1,1,10.0000,chargedback,disputed@3 chargedback@4\n\
+             Exploring is read-only, records aren't applied\n\
+             Invalid client id 'x'\n"
+        );
+        // Nothing was applied
+        assert_eq!(payments_engine.last_seq, 6);
+        assert_eq!(
+            payments_engine
+                .accounts
+                .get(1)
+                .unwrap()
+                .available()
+                .to_f64(),
+            5.0
+        );
+
+        std::fs::write(&f_log, "type,client,tx,amount\ndeposit,1,1,1.0\n").unwrap();
+        assert!(PaymentsEngine::new().load_txn_log(&f_log).is_err());
+    }
+}
@@ -0,0 +1,228 @@
+//! Throughput & funds flow per hour or day of record timestamps, for finance's after batch report
+//! Only streamed records are counted, as only they carry a `timestamp` column
+
+use super::PaymentsEngine;
+use crate::amount::format_amount;
+use crate::timestamp::{format_date, format_timestamp};
+use crate::transaction::Transaction;
+use csv::Writer;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::str::FromStr;
+
+/// Span of time a flow report row covers, in UTC
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FlowBucket {
+    #[default]
+    Hour,
+    Day,
+}
+
+impl FlowBucket {
+    fn secs(&self) -> i64 {
+        match self {
+            FlowBucket::Hour => 3600,
+            FlowBucket::Day => 86_400,
+        }
+    }
+
+    fn label(&self, start: i64) -> String {
+        match self {
+            FlowBucket::Hour => format_timestamp(start),
+            FlowBucket::Day => format_date(start),
+        }
+    }
+}
+
+impl FromStr for FlowBucket {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "hour" => Ok(FlowBucket::Hour),
+            "day" => Ok(FlowBucket::Day),
+            _ => Err(format!("Unknown flow bucket '{}'", s)),
+        }
+    }
+}
+
+/// Records of one bucket, counts by type are of applied records
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FlowStats {
+    pub deposits: u64,
+    pub withdrawals: u64,
+    pub disputes: u64,
+    pub resolves: u64,
+    pub chargebacks: u64,
+    pub refunds: u64,
+    /// Holds, unholds, approvals, & denials
+    pub admin: u64,
+    pub rejected: u64,
+    pub gross_deposits: f64,
+    pub gross_withdrawals: f64,
+}
+
+impl FlowStats {
+    /// Gross deposits less gross withdrawals
+    pub fn net_flow(&self) -> f64 {
+        self.gross_deposits - self.gross_withdrawals
+    }
+}
+
+/// Flow stats by bucket start, records without a timestamp are kept under None
+#[derive(Debug, Default)]
+pub(super) struct FlowReport {
+    bucket: FlowBucket,
+    buckets: BTreeMap<Option<i64>, FlowStats>,
+}
+
+impl PaymentsEngine {
+    /// Starts counting streamed records per bucket of their timestamps
+    pub fn enable_flow_report(&mut self, bucket: FlowBucket) {
+        self.flow_report = Some(FlowReport {
+            bucket,
+            buckets: BTreeMap::new(),
+        });
+    }
+
+    /// True if streamed records' timestamps are read, for the flow report, a clock following them, or
+    /// their sanity check
+    pub(super) fn wants_timestamps(&self) -> bool {
+        self.flow_report.is_some()
+            || self.clock.follows_records()
+            || self.config.timestamp_sanity.is_some()
+    }
+
+    /// Counts a streamed record in the bucket of its timestamp
+    pub(super) fn record_flow(&mut self, timestamp: Option<i64>, txn: &Transaction, applied: bool) {
+        let report = match &mut self.flow_report {
+            Some(report) => report,
+            None => return,
+        };
+        let secs = report.bucket.secs();
+        let start = timestamp.map(|timestamp| timestamp.div_euclid(secs) * secs);
+        let stats = report.buckets.entry(start).or_default();
+        if !applied {
+            stats.rejected += 1;
+            return;
+        }
+        match txn {
+            Transaction::Deposit(p_txn) => {
+                stats.deposits += 1;
+                stats.gross_deposits += p_txn.amount.to_f64();
+            }
+            Transaction::Withdrawal(p_txn) => {
+                stats.withdrawals += 1;
+                stats.gross_withdrawals += p_txn.amount.to_f64();
+            }
+            Transaction::Dispute(_) => stats.disputes += 1,
+            // Releases are partial resolves
+            Transaction::Resolve(_) | Transaction::Release(_) => stats.resolves += 1,
+            Transaction::Chargeback(_) => stats.chargebacks += 1,
+            Transaction::Refund(_) => stats.refunds += 1,
+            Transaction::Admin(_) => stats.admin += 1,
+        }
+    }
+
+    /// Flow stats of the bucket starting at `start`, None for records without a timestamp
+    pub fn flow_stats(&self, start: Option<i64>) -> Option<&FlowStats> {
+        self.flow_report.as_ref()?.buckets.get(&start)
+    }
+
+    /// Writes a row per bucket in time order, records without a timestamp come first with an empty bucket
+    pub fn output_flow_report_csv(&self, file_path: &str) -> Result<(), Box<dyn Error>> {
+        let mut wtr = Writer::from_path(file_path)?;
+        wtr.write_record([
+            "bucket",
+            "deposits",
+            "withdrawals",
+            "disputes",
+            "resolves",
+            "chargebacks",
+            "refunds",
+            "admin",
+            "rejected",
+            "gross_deposits",
+            "gross_withdrawals",
+            "net_flow",
+        ])?;
+        if let Some(report) = &self.flow_report {
+            for (start, stats) in &report.buckets {
+                wtr.write_record([
+                    start.map_or(String::new(), |start| report.bucket.label(start)),
+                    stats.deposits.to_string(),
+                    stats.withdrawals.to_string(),
+                    stats.disputes.to_string(),
+                    stats.resolves.to_string(),
+                    stats.chargebacks.to_string(),
+                    stats.refunds.to_string(),
+                    stats.admin.to_string(),
+                    stats.rejected.to_string(),
+                    format_amount(stats.gross_deposits),
+                    format_amount(stats.gross_withdrawals),
+                    format_amount(stats.net_flow()),
+                ])?;
+            }
+        }
+        wtr.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FlowBucket;
+    use crate::payments_engine::stream_process::StreamOptions;
+    use crate::payments_engine::PaymentsEngine;
+    use crate::test::utils::_get_test_output_file;
+
+    #[test]
+    fn tst_flow_report() {
+        let records = "type,client,tx,amount,timestamp\n\
+                       deposit,1,1,10.0,2024-05-01T13:05:00Z\n\
+                       deposit,2,2,5.5,1714572000\n\
+                       withdrawal,1,3,2.0,2024-05-01T15:59:59+02:00\n\
+                       withdrawal,2,4,50.0,2024-05-01T14:10:00Z\n\
+                       dispute,1,1,,2024-05-01T14:20:00Z\n\
+                       resolve,1,1,,2024-05-02T00:00:00Z\n\
+                       deposit,3,5,1.0,\n\
+                       deposit,3,6,1.0,last tuesday\n";
+        let mut payments_engine = PaymentsEngine::new();
+        payments_engine.enable_flow_report(FlowBucket::Hour);
+        assert!(payments_engine
+            .stream_process_reader(records.as_bytes(), true, &StreamOptions::default())
+            .is_ok());
+        // A timestamp which can't be read keeps the record out of the engine
+        assert!(
+            payments_engine
+                .accounts
+                .get(3)
+                .unwrap()
+                .available()
+                .to_f64()
+                == 1.0
+        );
+
+        let f_report = _get_test_output_file("tst_flow_report.csv");
+        assert!(payments_engine.output_flow_report_csv(&f_report).is_ok());
+        assert_eq!(
+            std::fs::read_to_string(&f_report).unwrap(),
+            "bucket,deposits,withdrawals,disputes,resolves,chargebacks,refunds,admin,rejected,gross_deposits,gross_withdrawals,net_flow\n\
+             ,1,0,0,0,0,0,0,0,1.0000,0.0000,1.0000\n\
+             2024-05-01T13:00:00Z,1,1,0,0,0,0,0,0,10.0000,2.0000,8.0000\n\
+             2024-05-01T14:00:00Z,1,0,1,0,0,0,0,1,5.5000,0.0000,5.5000\n\
+             2024-05-02T00:00:00Z,0,0,0,1,0,0,0,0,0.0000,0.0000,0.0000\n"
+        );
+
+        let mut payments_engine = PaymentsEngine::new();
+        payments_engine.enable_flow_report(FlowBucket::Day);
+        assert!(payments_engine
+            .stream_process_reader(records.as_bytes(), true, &StreamOptions::default())
+            .is_ok());
+        let day = payments_engine.flow_stats(Some(1714521600)).unwrap();
+        assert_eq!((day.deposits, day.withdrawals, day.rejected), (2, 1, 1));
+        assert_eq!(day.net_flow(), 13.5);
+        assert_eq!("day".parse(), Ok(FlowBucket::Day));
+        assert!("week".parse::<FlowBucket>().is_err());
+    }
+}
@@ -0,0 +1,276 @@
+use super::config::GcPolicy;
+use super::txn_arena::TxnKey;
+use super::PaymentsEngine;
+use crate::account::Account;
+use crate::cli_io::append_accounts_csv;
+use crate::transaction::{DisputeState, Transaction};
+use std::collections::HashSet;
+use std::error::Error;
+use std::io;
+
+impl PaymentsEngine {
+    /// True if a stored txn is under dispute, pending approval, or may still be disputed
+    /// Only a txn whose rail has a dispute window which is still open may be disputed, without one
+    /// the txns of an inactive account are assumed too old to dispute
+    fn is_open_txn(&self, txn_key: TxnKey) -> bool {
+        let txn = &self.processed_txns[txn_key].txn;
+        let p_txn = match txn {
+            Transaction::Deposit(p_txn) | Transaction::Withdrawal(p_txn) => p_txn,
+            _ => return false,
+        };
+        if p_txn.dispute.is_disputed() || self.is_pending_approval(p_txn.txn_id) {
+            return true;
+        }
+        let has_window = p_txn
+            .channel
+            .and_then(|channel| self.config.channel_rules.get(&channel))
+            .is_some_and(|rules| rules.dispute_window.is_some());
+        has_window
+            && self.check_dispute_window(txn_key).is_ok()
+            && self.config.disputable_txns.allows(txn)
+            && !self.denied_withdrawals.contains(&p_txn.txn_id)
+            && p_txn.dispute.state().can_transition(DisputeState::Disputed)
+    }
+
+    /// Drops a collected account's txns from the txn map & its activity
+    /// Their ids are kept without their txns, so they're still rejected as duplicates but can't be disputed
+    fn forget_txns(&mut self, acnt_id: u16) -> Result<(), io::Error> {
+        self.last_activity.remove(&acnt_id);
+        for txn_key in self.account_txns.remove(&acnt_id).unwrap_or_default() {
+            let txn_id = match &self.processed_txns[txn_key].txn {
+                Transaction::Deposit(p_txn) | Transaction::Withdrawal(p_txn) => p_txn.txn_id,
+                _ => continue,
+            };
+            match &mut self.dup_filter {
+                Some(filter) => filter.insert(txn_id, None)?,
+                None if self.txn_map.get(&txn_id) == Some(&txn_key) => {
+                    self.txn_map.remove(&txn_id);
+                    self.withdrawal_ids.insert(txn_id);
+                }
+                None => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Zero balance, unfrozen accounts can be collected
+    /// Frozen accounts are kept as they are of interest to compliance regardless of balance
+    fn is_collectable(acnt: &Account) -> bool {
+        !acnt.is_locked() && acnt.available().minor_units() == 0 && acnt.held().minor_units() == 0
+    }
+
+    /// Removes zero balance accounts which have been inactive long enough that their
+    /// transactions can no longer be disputed, compacting the account lookup
+    /// Accounts with a txn still open or disputable are kept, see `is_open_txn`
+    /// Removed accounts are archived if the policy has an archive path
+    /// Returns the number of accounts removed
+    pub fn gc_accounts(&mut self, policy: &GcPolicy) -> Result<usize, Box<dyn Error>> {
+        let collectable: HashSet<u16> = self
+            .accounts
+            .iter()
+            .filter(|acnt| {
+                let last_active = self.last_activity.get(&acnt.id).copied().unwrap_or(0);
+                Self::is_collectable(acnt)
+                    && last_active + policy.inactive_for <= self.last_seq
+                    && !self
+                        .account_txns
+                        .get(&acnt.id)
+                        .is_some_and(|txn_keys| txn_keys.iter().any(|k| self.is_open_txn(*k)))
+            })
+            .map(|acnt| acnt.id)
+            .collect();
+        if collectable.is_empty() {
+            return Ok(0);
+        }
+        let collected = self
+            .accounts
+            .extract_if(|acnt| collectable.contains(&acnt.id));
+        for acnt in &collected {
+            self.forget_txns(acnt.id)?;
+        }
+
+        if let Some(archive_path) = &policy.archive_path {
+            if !collected.is_empty() {
+                append_accounts_csv(&collected, archive_path)?;
+            }
+        }
+        Ok(collected.len())
+    }
+
+    /// Collects inactive accounts once an inactivity period of seqs has passed since the last collection
+    /// Collecting once per period amortizes the scan over processed txns, counting the seqs that passed
+    /// rather than waiting on a multiple of the period keeps to it through gaps & unprocessed records
+    /// Returns the number of accounts removed
+    pub(super) fn gc_if_due(&mut self, policy: &GcPolicy) -> Result<usize, Box<dyn Error>> {
+        if policy.inactive_for == 0
+            || self.last_seq.saturating_sub(self.last_gc_seq) < policy.inactive_for
+        {
+            return Ok(0);
+        }
+        self.last_gc_seq = self.last_seq;
+        self.gc_accounts(policy)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::account::Account;
+    use crate::amount::{Available, Held};
+    use crate::payments_engine::config::{
+        ChannelRules, EngineConfig, GcPolicy, WithdrawnFundsDispute,
+    };
+    use crate::payments_engine::transactions::TxnErrors;
+    use crate::payments_engine::PaymentsEngine;
+    use crate::test::utils::_get_test_output_file;
+    use crate::test_support::{deposit, pure_txn, withdrawal, EngineFixture};
+    use crate::transaction::{Channel, PureTxn, RefTxn, SequencedTxn, Transaction};
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn tst_gc_accounts() {
+        let fixture = EngineFixture::new()
+            // Emptied & inactive, should be collected
+            .deposit(1, 1, 10.0)
+            .withdrawal(1, 2, 10.0)
+            // Has a balance
+            .deposit(2, 3, 5.0)
+            // Frozen
+            .deposit(3, 4, 5.0)
+            .dispute(3, 4)
+            .chargeback(3, 4)
+            // Emptied but recently active
+            .deposit(4, 5, 1.0)
+            .withdrawal(4, 6, 1.0)
+            .build();
+        assert!(fixture.results.iter().all(Result::is_ok));
+        let mut payments_engine = fixture.engine;
+
+        let archive_path = _get_test_output_file("tst_gc_accounts_archive.csv");
+        let _ = std::fs::remove_file(&archive_path);
+        let policy = GcPolicy {
+            inactive_for: 3,
+            archive_path: Some(archive_path.clone()),
+        };
+        assert_eq!(payments_engine.gc_accounts(&policy).unwrap(), 1);
+        let ids: Vec<u16> = payments_engine.accounts.iter().map(|a| a.id).collect();
+        assert_eq!(ids, vec![2, 3, 4]);
+        assert_eq!(
+            payments_engine.accounts.get(4).map(|a| a.id),
+            Some(4),
+            "Lookup should point at compacted accounts"
+        );
+        assert_eq!(payments_engine.accounts.get(1), None);
+
+        let archived = std::fs::read_to_string(&archive_path).unwrap();
+        assert_eq!(
+            archived,
+            "client,available,held,total,locked\n1,0.0000,0.0000,0.0000,false\n"
+        );
+
+        // Collected accounts are recreated by new deposits, duplicate ids are still rejected
+        assert!(payments_engine.process_txn(&deposit(1, 1, 1.0)).is_err());
+        assert!(payments_engine.process_txn(&deposit(1, 7, 1.0)).is_ok());
+        assert_eq!(
+            payments_engine.accounts[3],
+            Account::with_balances(1, Available::from_f64(1.0), Held::default())
+        );
+        // Txns of the collected account don't carry over to the recreated one
+        assert!(payments_engine.txn_map.get(&1).is_none());
+        assert_eq!(
+            payments_engine.process_txn(&Transaction::Dispute(RefTxn {
+                ref_id: 1,
+                acnt_id: 1,
+            })),
+            Err(TxnErrors::RefTxnNotDisputable)
+        );
+    }
+
+    #[test]
+    fn tst_gc_if_due() {
+        let mut payments_engine = PaymentsEngine::new();
+        let policy = GcPolicy {
+            inactive_for: 4,
+            archive_path: None,
+        };
+        // Input seqs skip every multiple of the period
+        let txns = [
+            (1, deposit(1, 1, 1.0)),
+            (2, withdrawal(1, 2, 1.0)),
+            (3, deposit(2, 3, 1.0)),
+            (5, deposit(2, 4, 1.0)),
+            (7, deposit(2, 5, 1.0)),
+        ];
+        let mut collected = 0;
+        for (seq, txn) in txns {
+            let s_txn = SequencedTxn { seq, txn };
+            payments_engine.process_sequenced_txn(&s_txn).unwrap();
+            collected += payments_engine.gc_if_due(&policy).unwrap();
+        }
+        assert_eq!(
+            collected, 0,
+            "Client 1 was active 4 seqs before the collection at seq 5"
+        );
+        assert_eq!(payments_engine.last_gc_seq, 5);
+        assert!(payments_engine.accounts.get(1).is_some());
+
+        let s_txn = SequencedTxn {
+            seq: 9,
+            txn: deposit(2, 6, 1.0),
+        };
+        payments_engine.process_sequenced_txn(&s_txn).unwrap();
+        assert_eq!(payments_engine.gc_if_due(&policy).unwrap(), 1);
+        assert!(payments_engine.accounts.get(1).is_none());
+        assert_eq!(payments_engine.gc_if_due(&policy).unwrap(), 0);
+    }
+
+    #[test]
+    fn tst_gc_keeps_open_txns() {
+        let config = EngineConfig {
+            withdrawn_funds_dispute: WithdrawnFundsDispute::CapAtAvailable,
+            channel_rules: BTreeMap::from([(
+                Channel::Ach,
+                ChannelRules {
+                    dispute_window: Some(100),
+                    ..ChannelRules::default()
+                },
+            )]),
+            ..EngineConfig::default()
+        };
+        let fixture = EngineFixture::with_config(config)
+            // Emptied while a dispute holding nothing is open
+            .deposit(1, 1, 3.0)
+            .withdrawal(1, 2, 3.0)
+            .dispute(1, 1)
+            // Emptied with nothing left to dispute
+            .deposit(2, 3, 1.0)
+            .withdrawal(2, 4, 1.0)
+            // Emptied inside the rail's dispute window
+            .txn(Transaction::Deposit(PureTxn {
+                channel: Some(Channel::Ach),
+                ..pure_txn(3, 5, 2.0)
+            }))
+            .withdrawal(3, 6, 2.0);
+        let fixture = (7..20)
+            .fold(fixture, |fixture, txn_id| fixture.deposit(9, txn_id, 1.0))
+            .build();
+        assert!(fixture.results.iter().all(Result::is_ok));
+        let mut payments_engine = fixture.engine;
+        let policy = GcPolicy {
+            inactive_for: 10,
+            archive_path: None,
+        };
+        assert_eq!(payments_engine.gc_accounts(&policy).unwrap(), 1);
+        assert!(payments_engine.accounts.get(1).is_some());
+        assert!(payments_engine.accounts.get(2).is_none());
+        assert!(payments_engine.accounts.get(3).is_some());
+
+        // Once the rail's window closes the account goes too
+        for txn_id in 20..120 {
+            payments_engine
+                .process_txn(&deposit(9, txn_id, 1.0))
+                .unwrap();
+        }
+        assert_eq!(payments_engine.gc_accounts(&policy).unwrap(), 1);
+        assert!(payments_engine.accounts.get(3).is_none());
+    }
+}
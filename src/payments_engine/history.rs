@@ -0,0 +1,146 @@
+use super::stats::AccountActivity;
+use super::PaymentsEngine;
+use crate::transaction::{SequencedTxn, Transaction};
+use std::collections::HashMap;
+
+impl PaymentsEngine {
+    /// Page of a client's accepted txns in the order they were processed, disputes & admin txns included
+    /// Skips the first `offset` txns & returns at most `limit`, empty for clients without txns
+    pub fn account_history(&self, acnt_id: u16, offset: usize, limit: usize) -> Vec<&SequencedTxn> {
+        self.account_txns
+            .get(&acnt_id)
+            .map_or(&[][..], Vec::as_slice)
+            .iter()
+            .skip(offset)
+            .take(limit)
+            .map(|txn_key| &self.processed_txns[*txn_key])
+            .collect()
+    }
+
+    /// Number of accepted txns in a client's history, for paging through it
+    pub fn account_history_len(&self, acnt_id: u16) -> usize {
+        self.account_txns.get(&acnt_id).map_or(0, Vec::len)
+    }
+
+    /// Account sequence number of a client's latest accepted txn, 0 before its first
+    /// Each accepted txn of a client is numbered one past the one before, so consumers can spot gaps & reorder
+    pub fn account_seq(&self, acnt_id: u16) -> u64 {
+        self.account_seq_base.get(&acnt_id).copied().unwrap_or(0)
+            + self.account_history_len(acnt_id) as u64
+    }
+
+    /// Account sequence number of the txn at `offset` in a client's history
+    pub fn account_seq_at(&self, acnt_id: u16, offset: usize) -> u64 {
+        self.account_seq_base.get(&acnt_id).copied().unwrap_or(0) + offset as u64 + 1
+    }
+
+    /// Carries on numbering a client's txns from a logged account sequence number, so the next txn accepted
+    /// for the client gets `acnt_seq` unless the client's txns are already numbered past it
+    pub(super) fn observe_account_seq(&mut self, acnt_id: u16, acnt_seq: u64) {
+        let next = self.account_seq(acnt_id) + 1;
+        if acnt_seq > next {
+            *self.account_seq_base.entry(acnt_id).or_default() += acnt_seq - next;
+        }
+    }
+
+    /// Accepted txns in processing order, each with its account sequence number
+    pub fn numbered_txns(&self) -> impl Iterator<Item = (&SequencedTxn, Option<u64>)> {
+        let mut offsets: HashMap<u16, usize> = HashMap::new();
+        self.processed_txns.iter().map(move |s_txn| {
+            let acnt_id = s_txn.txn.acnt_id();
+            let offset = offsets.entry(acnt_id).or_default();
+            let acnt_seq = self.account_seq_at(acnt_id, *offset);
+            *offset += 1;
+            (s_txn, Some(acnt_seq))
+        })
+    }
+
+    /// Dispute & chargeback counts & latest txn id of a client's history
+    pub fn account_activity(&self, acnt_id: u16) -> AccountActivity {
+        let mut activity = AccountActivity::default();
+        let txn_keys = self
+            .account_txns
+            .get(&acnt_id)
+            .map_or(&[][..], Vec::as_slice);
+        for txn_key in txn_keys {
+            match &self.processed_txns[*txn_key].txn {
+                Transaction::Deposit(p_txn) | Transaction::Withdrawal(p_txn) => {
+                    activity.last_txn_id = Some(p_txn.txn_id)
+                }
+                Transaction::Dispute(_) => activity.disputes += 1,
+                Transaction::Chargeback(_) => activity.chargebacks += 1,
+                Transaction::Resolve(_)
+                | Transaction::Admin(_)
+                | Transaction::Refund(_)
+                | Transaction::Release(_) => {}
+            }
+        }
+        activity
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::payments_engine::PaymentsEngine;
+    use crate::test_support::deposit;
+    use crate::transaction::{RefTxn, SequencedTxn, Transaction};
+
+    #[test]
+    fn tst_account_history() {
+        let mut payments_engine = PaymentsEngine::new();
+        for txn_id in 1..=5 {
+            assert!(payments_engine
+                .process_txn(&deposit((txn_id % 2) as u16, txn_id, 1.0))
+                .is_ok());
+        }
+        let dispute = Transaction::Dispute(RefTxn {
+            ref_id: 3,
+            acnt_id: 1,
+        });
+        assert!(payments_engine.process_txn(&dispute).is_ok());
+        // Rejected txns aren't part of the history
+        assert!(payments_engine.process_txn(&deposit(1, 3, 1.0)).is_err());
+
+        assert_eq!(payments_engine.account_history_len(1), 4);
+        let seqs =
+            |page: Vec<&SequencedTxn>| -> Vec<u64> { page.iter().map(|s_txn| s_txn.seq).collect() };
+        assert_eq!(seqs(payments_engine.account_history(1, 0, 2)), vec![1, 3]);
+        assert_eq!(seqs(payments_engine.account_history(1, 2, 10)), vec![5, 6]);
+        assert!(payments_engine.account_history(1, 4, 10).is_empty());
+        assert!(payments_engine.account_history(9, 0, 10).is_empty());
+
+        // Txns rolled back leave the history as well
+        let savepoint = payments_engine.savepoint();
+        assert!(payments_engine.process_txn(&deposit(1, 7, 1.0)).is_ok());
+        assert_eq!(payments_engine.account_history_len(1), 5);
+        assert!(payments_engine.rollback_to(savepoint).is_ok());
+        assert_eq!(payments_engine.account_history_len(1), 4);
+    }
+
+    #[test]
+    fn tst_account_seqs() {
+        let mut payments_engine = PaymentsEngine::new();
+        for txn_id in 1..=3 {
+            assert!(payments_engine
+                .process_txn(&deposit((txn_id % 2) as u16, txn_id, 1.0))
+                .is_ok());
+        }
+        assert!(payments_engine.process_txn(&deposit(1, 3, 1.0)).is_err());
+        assert_eq!(payments_engine.account_seq(1), 2);
+        assert_eq!(payments_engine.account_seq(9), 0);
+        let acnt_seqs: Vec<(u64, Option<u64>)> = payments_engine
+            .numbered_txns()
+            .map(|(s_txn, acnt_seq)| (s_txn.seq, acnt_seq))
+            .collect();
+        assert_eq!(acnt_seqs, [(1, Some(1)), (2, Some(1)), (3, Some(2))]);
+
+        // Logged numbers carry on past the ones already used, never going back
+        payments_engine.observe_account_seq(1, 10);
+        payments_engine.observe_account_seq(0, 1);
+        assert!(payments_engine.process_txn(&deposit(1, 5, 1.0)).is_ok());
+        assert!(payments_engine.process_txn(&deposit(0, 6, 1.0)).is_ok());
+        assert_eq!(payments_engine.account_seq(1), 10);
+        assert_eq!(payments_engine.account_seq(0), 2);
+        assert_eq!(payments_engine.account_seq_at(1, 0), 8);
+    }
+}
@@ -0,0 +1,112 @@
+use super::config::IdHasher;
+use rustc_hash::{FxBuildHasher, FxHasher};
+use std::collections::hash_map::{DefaultHasher, RandomState};
+use std::collections::{HashMap, HashSet};
+use std::hash::{BuildHasher, Hasher};
+
+/// Lookup keyed by client or txn id, hashed the way the engine was configured
+pub(super) type IdMap<K, V> = HashMap<K, V, IdBuildHasher>;
+
+/// Set of client or txn ids, hashed the way the engine was configured
+pub(super) type IdSet<K> = HashSet<K, IdBuildHasher>;
+
+/// Builds the hasher an `IdHasher` names, every map built from one hashes the same way
+#[derive(Debug, Clone)]
+pub(super) enum IdBuildHasher {
+    Sip(RandomState),
+    Fx,
+}
+
+impl IdBuildHasher {
+    pub(super) fn new(choice: IdHasher) -> Self {
+        match choice {
+            IdHasher::Sip => IdBuildHasher::Sip(RandomState::new()),
+            IdHasher::Fx => IdBuildHasher::Fx,
+        }
+    }
+}
+
+impl Default for IdBuildHasher {
+    fn default() -> Self {
+        Self::new(IdHasher::default())
+    }
+}
+
+impl BuildHasher for IdBuildHasher {
+    type Hasher = IdHashState;
+
+    fn build_hasher(&self) -> IdHashState {
+        match self {
+            IdBuildHasher::Sip(state) => IdHashState::Sip(state.build_hasher()),
+            IdBuildHasher::Fx => IdHashState::Fx(FxBuildHasher.build_hasher()),
+        }
+    }
+}
+
+/// Hasher of either kind, ids are written as integers so each hasher takes its fast path for them
+#[derive(Clone)]
+pub(super) enum IdHashState {
+    Sip(DefaultHasher),
+    Fx(FxHasher),
+}
+
+impl Hasher for IdHashState {
+    fn write(&mut self, bytes: &[u8]) {
+        match self {
+            IdHashState::Sip(hasher) => hasher.write(bytes),
+            IdHashState::Fx(hasher) => hasher.write(bytes),
+        }
+    }
+
+    fn write_u16(&mut self, i: u16) {
+        match self {
+            IdHashState::Sip(hasher) => hasher.write_u16(i),
+            IdHashState::Fx(hasher) => hasher.write_u16(i),
+        }
+    }
+
+    fn write_u32(&mut self, i: u32) {
+        match self {
+            IdHashState::Sip(hasher) => hasher.write_u32(i),
+            IdHashState::Fx(hasher) => hasher.write_u32(i),
+        }
+    }
+
+    fn write_u64(&mut self, i: u64) {
+        match self {
+            IdHashState::Sip(hasher) => hasher.write_u64(i),
+            IdHashState::Fx(hasher) => hasher.write_u64(i),
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        match self {
+            IdHashState::Sip(hasher) => hasher.finish(),
+            IdHashState::Fx(hasher) => hasher.finish(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{IdBuildHasher, IdMap};
+    use crate::payments_engine::config::IdHasher;
+    use std::hash::BuildHasher;
+
+    #[test]
+    fn tst_id_hashers() {
+        for choice in [IdHasher::Sip, IdHasher::Fx] {
+            let mut map: IdMap<u32, u32> =
+                IdMap::with_capacity_and_hasher(1000, IdBuildHasher::new(choice));
+            for id in 0..1000 {
+                map.insert(id, id * 2);
+            }
+            assert_eq!(map.len(), 1000);
+            assert!((0..1000).all(|id| map.get(&id) == Some(&(id * 2))));
+        }
+
+        let fx = IdBuildHasher::new(IdHasher::Fx);
+        assert_eq!(fx.hash_one(7u16), fx.hash_one(7u16));
+        assert_ne!(fx.hash_one(7u32), fx.hash_one(8u32));
+    }
+}
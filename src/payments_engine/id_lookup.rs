@@ -0,0 +1,206 @@
+use super::config::IdIndex;
+use super::id_hash::{IdBuildHasher, IdMap};
+use std::hash::Hash;
+
+/// Slots a direct index grows by at once, so growing rarely copies the slots
+const INDEX_CHUNK: usize = 4096;
+/// A direct index switches to hashing once it would need more slots than this per stored id
+const MAX_SLOTS_PER_ID: usize = 4;
+
+/// Client or txn id usable as a position in a direct index
+pub(super) trait Id: Copy + Eq + Hash {
+    fn slot(self) -> usize;
+    fn from_slot(slot: usize) -> Self;
+}
+
+impl Id for u16 {
+    fn slot(self) -> usize {
+        self as usize
+    }
+
+    fn from_slot(slot: usize) -> Self {
+        slot as u16
+    }
+}
+
+impl Id for u32 {
+    fn slot(self) -> usize {
+        self as usize
+    }
+
+    fn from_slot(slot: usize) -> Self {
+        slot as u32
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Entries<K, V> {
+    /// Slot per id up to the largest one seen
+    Direct(Vec<Option<V>>),
+    Hashed(IdMap<K, V>),
+}
+
+/// Lookup keyed by client or txn id, either a `Vec` indexed by the id or a hash map
+/// A direct index skips hashing while ids are dense & turns into a hash map for good once they aren't
+#[derive(Debug, Clone)]
+pub(super) struct IdLookup<K, V> {
+    entries: Entries<K, V>,
+    len: usize,
+    /// Hashes the map a direct index turns into
+    hasher: IdBuildHasher,
+}
+
+impl<K: Id, V> Default for IdLookup<K, V> {
+    fn default() -> Self {
+        Self::with_capacity(IdIndex::default(), 0, IdBuildHasher::default())
+    }
+}
+
+impl<K: Id, V> IdLookup<K, V> {
+    /// Empty lookup with room for `capacity` ids
+    pub(super) fn with_capacity(index: IdIndex, capacity: usize, hasher: IdBuildHasher) -> Self {
+        let entries = match index {
+            IdIndex::Hashed => {
+                Entries::Hashed(IdMap::with_capacity_and_hasher(capacity, hasher.clone()))
+            }
+            IdIndex::Direct => Entries::Direct(Vec::with_capacity(capacity)),
+        };
+        Self {
+            entries,
+            len: 0,
+            hasher,
+        }
+    }
+
+    /// Empty lookup of the same kind & hasher, with room for `capacity` ids
+    pub(super) fn empty_like(&self, capacity: usize) -> Self {
+        let index = match self.entries {
+            Entries::Direct(_) => IdIndex::Direct,
+            Entries::Hashed(_) => IdIndex::Hashed,
+        };
+        Self::with_capacity(index, capacity, self.hasher.clone())
+    }
+
+    pub(super) fn len(&self) -> usize {
+        self.len
+    }
+
+    /// True while ids are looked up by position rather than hashed
+    pub(super) fn is_direct(&self) -> bool {
+        matches!(self.entries, Entries::Direct(_))
+    }
+
+    pub(super) fn get(&self, id: &K) -> Option<&V> {
+        match &self.entries {
+            Entries::Direct(slots) => slots.get(id.slot())?.as_ref(),
+            Entries::Hashed(map) => map.get(id),
+        }
+    }
+
+    pub(super) fn contains_key(&self, id: &K) -> bool {
+        self.get(id).is_some()
+    }
+
+    /// Stores the value of an id, returning the one it replaces
+    pub(super) fn insert(&mut self, id: K, value: V) -> Option<V> {
+        let replaced = match &mut self.entries {
+            Entries::Direct(slots) => {
+                let slot = id.slot();
+                if slot >= slots.len() {
+                    let needed = (slot + 1).next_multiple_of(INDEX_CHUNK);
+                    if needed > INDEX_CHUNK.max((self.len + 1) * MAX_SLOTS_PER_ID) {
+                        self.switch_to_hashed();
+                        return self.insert(id, value);
+                    }
+                    slots.resize_with(needed, || None);
+                }
+                slots[slot].replace(value)
+            }
+            Entries::Hashed(map) => map.insert(id, value),
+        };
+        if replaced.is_none() {
+            self.len += 1;
+        }
+        replaced
+    }
+
+    pub(super) fn remove(&mut self, id: &K) -> Option<V> {
+        let removed = match &mut self.entries {
+            Entries::Direct(slots) => slots.get_mut(id.slot())?.take(),
+            Entries::Hashed(map) => map.remove(id),
+        };
+        if removed.is_some() {
+            self.len -= 1;
+        }
+        removed
+    }
+
+    /// Stores many ids at once, reserving room for all of them before the first insert
+    pub(super) fn extend(&mut self, entries: impl ExactSizeIterator<Item = (K, V)>) {
+        match &mut self.entries {
+            Entries::Direct(slots) => slots.reserve(entries.len().saturating_sub(slots.len())),
+            Entries::Hashed(map) => map.reserve(entries.len()),
+        }
+        for (id, value) in entries {
+            self.insert(id, value);
+        }
+    }
+
+    /// Moves every id into a hash map, for ids too sparse to index directly
+    fn switch_to_hashed(&mut self) {
+        if let Entries::Direct(slots) = &mut self.entries {
+            let mut map = IdMap::with_capacity_and_hasher(self.len, self.hasher.clone());
+            map.extend(
+                slots
+                    .drain(..)
+                    .enumerate()
+                    .filter_map(|(slot, value)| Some((K::from_slot(slot), value?))),
+            );
+            self.entries = Entries::Hashed(map);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{IdLookup, INDEX_CHUNK};
+    use crate::payments_engine::config::IdIndex;
+    use crate::payments_engine::id_hash::IdBuildHasher;
+
+    #[test]
+    fn tst_id_lookup() {
+        for index in [IdIndex::Hashed, IdIndex::Direct] {
+            let mut lookup: IdLookup<u32, usize> =
+                IdLookup::with_capacity(index, 16, IdBuildHasher::default());
+            lookup.extend((1..101).map(|id| (id, id as usize * 2)));
+            assert_eq!(lookup.len(), 100);
+            assert_eq!(lookup.is_direct(), index == IdIndex::Direct);
+            assert_eq!(lookup.insert(7, 0), Some(14));
+            assert_eq!(lookup.remove(&8), Some(16));
+            assert_eq!(lookup.remove(&8), None);
+            assert_eq!(lookup.len(), 99);
+            assert!(!lookup.contains_key(&0));
+            assert!(!lookup.contains_key(&u32::MAX));
+
+            // A far away id makes a direct index hash, keeping what it held
+            lookup.insert(u32::MAX, 1);
+            assert!(!lookup.is_direct());
+            assert_eq!(lookup.len(), 100);
+            assert_eq!(lookup.get(&7), Some(&0));
+            assert_eq!(lookup.get(&100), Some(&200));
+            assert_eq!(lookup.get(&u32::MAX), Some(&1));
+        }
+
+        // Ids a couple apart stay direct, ids further apart are hashed
+        let spread = |step: usize| {
+            let mut lookup = IdLookup::with_capacity(IdIndex::Direct, 0, IdBuildHasher::default());
+            for id in (0..INDEX_CHUNK as u32 * 4).step_by(step) {
+                lookup.insert(id, ());
+            }
+            lookup
+        };
+        assert!(spread(2).is_direct());
+        assert!(spread(2).empty_like(0).is_direct());
+        assert!(!spread(8).is_direct());
+    }
+}
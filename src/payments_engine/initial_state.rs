@@ -0,0 +1,192 @@
+use super::account_store::AccountStore;
+use super::live_snapshot::AccountSnapshot;
+use super::PaymentsEngine;
+use crate::account::Account;
+use crate::amount::{Available, Held};
+use csv::{ReaderBuilder, StringRecord, Trim};
+use std::collections::HashMap;
+use std::io::{self, ErrorKind};
+use std::str::FromStr;
+
+/// What to do when the initial state file holds more than one row for a client
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DuplicateClientPolicy {
+    /// Refuse the file, naming the client & both rows
+    #[default]
+    Error,
+    /// Merge the rows into one account, adding balances & keeping any lock
+    Sum,
+}
+
+impl FromStr for DuplicateClientPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "error" => Ok(DuplicateClientPolicy::Error),
+            "sum" => Ok(DuplicateClientPolicy::Sum),
+            _ => Err(format!("Unknown duplicate client policy '{}'", s)),
+        }
+    }
+}
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, msg)
+}
+
+/// Reads an account from a row, `locked` stands in for a chargeback lock when the split columns are absent
+fn read_account(
+    record: &StringRecord,
+    column: &impl Fn(&str) -> Option<usize>,
+    line: u64,
+) -> Result<Account, io::Error> {
+    let malformed = || invalid_data(format!("Malformed account on line {}", line));
+    let field = |name: &str| column(name).and_then(|i| record.get(i));
+    let flag = |name: &str| -> Result<Option<bool>, io::Error> {
+        field(name)
+            .map(|value| value.parse().map_err(|_| malformed()))
+            .transpose()
+    };
+    let locked_by_chargeback = match flag("locked_by_chargeback")? {
+        Some(locked) => locked,
+        None => flag("locked")?.unwrap_or(false),
+    };
+    let id = field("client")
+        .and_then(|id| id.parse().ok())
+        .ok_or_else(malformed)?;
+    let available = field("available")
+        .and_then(|amount| amount.parse().ok())
+        .map(Available::from_f64)
+        .ok_or_else(malformed)?;
+    let held = field("held")
+        .and_then(|amount| amount.parse().ok())
+        .map(Held::from_f64)
+        .ok_or_else(malformed)?;
+    let admin_hold = flag("admin_hold")?.unwrap_or(false);
+    Ok(Account::with_balances(id, available, held).with_locks(locked_by_chargeback, admin_hold))
+}
+
+/// Reads accounts from a csv in the engine's output format, `total` is derived & ignored
+/// Clients appearing on more than one row are consolidated according to `policy`
+pub fn read_initial_state(
+    file_path: &str,
+    policy: DuplicateClientPolicy,
+) -> Result<AccountStore, io::Error> {
+    let mut rdr = ReaderBuilder::new().trim(Trim::All).from_path(file_path)?;
+    let headers = rdr.headers()?.clone();
+    let column = |name: &str| headers.iter().position(|h| h == name);
+    for required in ["client", "available", "held"] {
+        if column(required).is_none() {
+            return Err(invalid_data(format!(
+                "Missing {} column in {}",
+                required, file_path
+            )));
+        }
+    }
+
+    let mut accounts: Vec<Account> = vec![];
+    // Position of each client's account & the line it was first seen on
+    let mut seen: HashMap<u16, (usize, u64)> = HashMap::new();
+    for result in rdr.records() {
+        let record = result.map_err(io::Error::other)?;
+        let line = record.position().map_or(0, |pos| pos.line());
+        let acnt = read_account(&record, &column, line)?;
+        match (seen.get(&acnt.id), policy) {
+            (None, _) => {
+                seen.insert(acnt.id, (accounts.len(), line));
+                accounts.push(acnt);
+            }
+            (Some((_, first_line)), DuplicateClientPolicy::Error) => {
+                return Err(invalid_data(format!(
+                    "Client {} appears on lines {} & {} of {}",
+                    acnt.id, first_line, line, file_path
+                )));
+            }
+            (Some((indx, _)), DuplicateClientPolicy::Sum) => {
+                accounts[*indx].merge(&acnt).map_err(|_| {
+                    invalid_data(format!(
+                        "Client {}'s balances overflow when summed on line {} of {}",
+                        acnt.id, line, file_path
+                    ))
+                })?;
+            }
+        }
+    }
+    // Consolidation above leaves one row per client, so this can't fail
+    AccountStore::from_accounts(accounts)
+        .map_err(|acnt_id| invalid_data(format!("Client {} appears twice", acnt_id)))
+}
+
+impl PaymentsEngine {
+    /// Replaces account state with the accounts of an initial state file before processing
+    pub fn import_initial_state(
+        &mut self,
+        file_path: &str,
+        policy: DuplicateClientPolicy,
+    ) -> Result<(), io::Error> {
+        let accounts = read_initial_state(file_path, policy)?;
+        self.restore_accounts(AccountSnapshot::new(0, accounts));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_initial_state, DuplicateClientPolicy};
+    use crate::cli_io::{parse_cli_args, CliCommand};
+    use crate::payments_engine::PaymentsEngine;
+    use crate::test::utils::{_get_test_input_file, _get_test_output_file};
+
+    #[test]
+    fn tst_initial_state_duplicates() {
+        let f_state = _get_test_output_file("tst_initial_state_duplicates_state.csv");
+        std::fs::write(
+            &f_state,
+            "client,available,held,total,locked\n\
+             1,1.5,0.5,2.0,false\n\
+             2,3.0,0.0,3.0,false\n\
+             1,2.0,1.0,3.0,true\n",
+        )
+        .unwrap();
+
+        let err = read_initial_state(&f_state, DuplicateClientPolicy::Error).unwrap_err();
+        assert!(err.to_string().contains("Client 1 appears on lines 2 & 4"));
+
+        let accounts = read_initial_state(&f_state, DuplicateClientPolicy::Sum).unwrap();
+        assert_eq!(accounts.len(), 2);
+        let merged = accounts.get(1).unwrap();
+        assert_eq!(
+            (merged.available().to_f64(), merged.held().to_f64()),
+            (3.5, 1.5)
+        );
+        assert!(merged.locked_by_chargeback);
+
+        // Processing on top of the imported state never adds a second row for a client
+        let f_output = _get_test_output_file("tst_initial_state_duplicates.csv");
+        let args: Vec<String> = [
+            _get_test_input_file("simple.csv").as_str(),
+            "--initial-state",
+            &f_state,
+            "--on-duplicate-client",
+            "sum",
+            "--output",
+            &f_output,
+        ]
+        .iter()
+        .map(|a| a.to_string())
+        .collect();
+        let cli_options = match parse_cli_args(&args) {
+            Ok(CliCommand::Process(cli_options)) => cli_options,
+            _ => panic!("Should parse as process command"),
+        };
+        let mut payments_engine = PaymentsEngine::new();
+        assert!(payments_engine.streaming_execute(&cli_options).is_ok());
+        let output = std::fs::read_to_string(&f_output).unwrap();
+        let clients: Vec<&str> = output
+            .lines()
+            .skip(1)
+            .map(|line| line.split(',').next().unwrap())
+            .collect();
+        assert_eq!(clients, vec!["1", "2"]);
+    }
+}
@@ -0,0 +1,218 @@
+use super::approvals::PendingWithdrawal;
+use super::config::SafetyLimits;
+use super::dedupe_window::DedupeWindow;
+use super::stats::ClientStats;
+use super::txn_arena::TxnKey;
+use super::PaymentsEngine;
+use crate::account::Account;
+use crate::transaction::SequencedTxn;
+use std::error::Error;
+use std::fmt;
+use std::mem::size_of;
+
+/// Bytes a hash map entry costs beyond its key & value, covers control bytes & spare capacity
+pub(super) const MAP_ENTRY_OVERHEAD: usize = 16;
+
+/// Which safety limit a run passed & the value it reached
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LimitExceeded {
+    Accounts(usize),
+    ProcessedTxns(usize),
+    MemoryBytes(usize),
+}
+
+impl fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LimitExceeded::Accounts(count) => {
+                write!(f, "Account limit exceeded with {} accounts", count)
+            }
+            LimitExceeded::ProcessedTxns(count) => {
+                write!(f, "Processed txn limit exceeded with {} txns", count)
+            }
+            LimitExceeded::MemoryBytes(bytes) => {
+                write!(f, "Memory limit exceeded with an estimated {} bytes", bytes)
+            }
+        }
+    }
+}
+
+impl Error for LimitExceeded {}
+
+/// Bytes `len` entries of a hash map or set take
+pub(super) fn map_bytes<K, V>(len: usize) -> usize {
+    len * (size_of::<K>() + size_of::<V>() + MAP_ENTRY_OVERHEAD)
+}
+
+impl PaymentsEngine {
+    /// Number of accepted txns stored, the state growing fastest with the input
+    pub fn processed_txns_len(&self) -> usize {
+        self.processed_txns.len()
+    }
+
+    /// Rough size of the state growing with the input, accounts, stored txns, & the collections kept beside them
+    /// Heap bytes of memos, dispute histories, & case notes are counted as they're stored, so this stays cheap
+    /// enough to check per record
+    pub fn estimated_memory_bytes(&self) -> usize {
+        let acnt_entry = size_of::<u16>() + size_of::<usize>() + MAP_ENTRY_OVERHEAD;
+        let by_txn = map_bytes::<u32, TxnKey>(self.txn_map.len())
+            + map_bytes::<u32, ()>(self.withdrawal_ids.len())
+            + map_bytes::<u32, ()>(self.denied_withdrawals.len())
+            + map_bytes::<u32, f64>(self.held_amounts.len())
+            + map_bytes::<u32, f64>(self.refunded.len())
+            + map_bytes::<u32, PendingWithdrawal>(self.pending_withdrawals.len())
+            + map_bytes::<u32, Vec<(u64, String)>>(self.case_notes.len())
+            + self.case_note_bytes;
+        let by_client = map_bytes::<u16, Vec<TxnKey>>(self.account_txns.len())
+            + self.processed_txns.len() * size_of::<TxnKey>()
+            + map_bytes::<u16, u64>(self.last_activity.len())
+            + map_bytes::<u16, u64>(self.account_seq_base.len())
+            + map_bytes::<u16, usize>(self.open_disputes.len())
+            + map_bytes::<u16, ()>(self.negative_clients.len())
+            + map_bytes::<u16, ClientStats>(self.client_stats.len());
+        self.accounts.len() * (size_of::<Account>() + acnt_entry)
+            + self.processed_txns.memory_bytes()
+            + by_txn
+            + by_client
+            + self.quarantined.capacity() * size_of::<SequencedTxn>()
+            + self
+                .dedupe_window
+                .as_ref()
+                .map_or(0, DedupeWindow::memory_bytes)
+    }
+
+    /// Errors with the first configured limit the engine has grown past
+    pub fn check_limits(&self) -> Result<(), LimitExceeded> {
+        let SafetyLimits {
+            max_accounts,
+            max_processed_txns,
+            max_memory_bytes,
+        } = self.config.limits;
+        if max_accounts.is_some_and(|max| self.accounts.len() > max) {
+            return Err(LimitExceeded::Accounts(self.accounts.len()));
+        }
+        if max_processed_txns.is_some_and(|max| self.processed_txns.len() > max) {
+            return Err(LimitExceeded::ProcessedTxns(self.processed_txns.len()));
+        }
+        if let Some(max) = max_memory_bytes {
+            let bytes = self.estimated_memory_bytes();
+            if bytes > max {
+                return Err(LimitExceeded::MemoryBytes(bytes));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LimitExceeded;
+    use crate::cli_io::{parse_cli_args, CliCommand};
+    use crate::payments_engine::config::{EngineConfig, SafetyLimits};
+    use crate::payments_engine::stream_process::StreamOptions;
+    use crate::payments_engine::PaymentsEngine;
+    use crate::test::utils::_get_test_output_file;
+    use crate::test_support::pure_txn;
+    use crate::transaction::{PureTxn, Transaction};
+
+    const DATA: &str = "type,client,tx,amount\n\
+                        deposit,1,1,1.0\n\
+                        deposit,2,2,1.0\n\
+                        deposit,3,3,1.0\n\
+                        deposit,4,4,1.0\n";
+
+    fn engine_with(limits: SafetyLimits) -> PaymentsEngine {
+        PaymentsEngine::with_config(EngineConfig {
+            limits,
+            ..EngineConfig::default()
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn tst_safety_limits() {
+        let mut payments_engine = engine_with(SafetyLimits {
+            max_processed_txns: Some(1),
+            ..SafetyLimits::default()
+        });
+        let err = payments_engine
+            .stream_process_reader(DATA.as_bytes(), true, &StreamOptions::default())
+            .unwrap_err();
+        assert_eq!(
+            err.into_inner()
+                .unwrap()
+                .downcast::<LimitExceeded>()
+                .ok()
+                .map(|e| *e),
+            Some(LimitExceeded::ProcessedTxns(2))
+        );
+        assert_eq!(
+            payments_engine.accounts.len(),
+            2,
+            "Should stop right after the limit"
+        );
+
+        let mut payments_engine = engine_with(SafetyLimits {
+            max_memory_bytes: Some(1),
+            ..SafetyLimits::default()
+        });
+        assert!(payments_engine.check_limits().is_ok());
+        assert!(payments_engine
+            .stream_process_reader(DATA.as_bytes(), true, &StreamOptions::default())
+            .is_err());
+        assert_eq!(payments_engine.accounts.len(), 1);
+
+        let f_input = _get_test_output_file("tst_safety_limits.csv");
+        let f_output = _get_test_output_file("tst_safety_limits_accounts.csv");
+        std::fs::write(&f_input, DATA).unwrap();
+        let args: Vec<String> = [
+            f_input.as_str(),
+            "--output",
+            &f_output,
+            "--max-accounts",
+            "2",
+        ]
+        .iter()
+        .map(|a| a.to_string())
+        .collect();
+        let cli_options = match parse_cli_args(&args) {
+            Ok(CliCommand::Process(cli_options)) => cli_options,
+            _ => panic!("Should parse as process command"),
+        };
+        let mut payments_engine =
+            PaymentsEngine::with_config(cli_options.engine_config.clone()).unwrap();
+        assert!(payments_engine.streaming_execute(&cli_options).is_err());
+        assert_eq!(
+            std::fs::read_to_string(&f_output).unwrap().lines().count(),
+            4,
+            "Accounts up to the limit should be written as a checkpoint"
+        );
+    }
+
+    #[test]
+    fn tst_memory_estimate() {
+        let deposit = |txn_id: u32, memo: Option<String>| {
+            Transaction::Deposit(PureTxn {
+                memo,
+                ..pure_txn(1, txn_id, 1.0)
+            })
+        };
+        let mut plain = PaymentsEngine::new();
+        let mut with_memo = PaymentsEngine::new();
+        let memo = "x".repeat(4096);
+        assert!(plain.process_txn(&deposit(1, None)).is_ok());
+        assert!(with_memo
+            .process_txn(&deposit(1, Some(memo.clone())))
+            .is_ok());
+        assert!(
+            with_memo.estimated_memory_bytes() >= plain.estimated_memory_bytes() + memo.len(),
+            "Memos should count toward the estimate"
+        );
+
+        // Collections kept beside the txns count too
+        let before = plain.estimated_memory_bytes();
+        plain.denied_withdrawals.insert(7);
+        plain.negative_clients.insert(1);
+        assert!(plain.estimated_memory_bytes() > before);
+    }
+}
@@ -0,0 +1,488 @@
+use super::priority_lane::PriorityRecord;
+use super::signals::OperatorSignals;
+use super::stream_process::StreamOptions;
+use super::PaymentsEngine;
+use crate::cli_io::{CliOptions, ListenOptions};
+use crate::diagnostics::{log, Level};
+use std::error::Error;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::thread;
+
+/// Writes current account state to the configured output, answered with `ok`
+pub const FLUSH_MESSAGE: &str = "!flush";
+/// Stops listening & writes run outputs, answered with `ok`
+pub const SHUTDOWN_MESSAGE: &str = "!shutdown";
+/// Answered with a one line state summary
+pub const STATUS_MESSAGE: &str = "!status";
+
+/// Longest line a connection may send, a longer one fails the connection rather than growing the buffer
+pub const MAX_LINE_BYTES: usize = 64 * 1024;
+/// Sockets are only reachable by the user the engine runs as
+const SOCKET_MODE: u32 = 0o600;
+/// Priority connections read at once, each takes a thread
+pub const MAX_PRIORITY_CONNECTIONS: usize = 16;
+/// Answer to a priority connection over the limit, sent before closing it
+const BUSY_REPLY: &[u8] = b"busy\n";
+/// Mode of the directory a socket is bound in, other users can't reach anything inside it
+const STAGING_DIR_MODE: u32 = 0o700;
+
+/// How a connection ended
+enum ConnectionEnd {
+    Closed,
+    Shutdown,
+    /// A safety limit was passed, nothing more is applied
+    Stopped(io::Error),
+}
+
+/// Removes a socket left behind by an earlier run, anything other than a socket at the path is left alone
+fn remove_stale_socket(socket_path: &str) -> Result<(), io::Error> {
+    if let Ok(metadata) = fs::symlink_metadata(socket_path) {
+        if !metadata.file_type().is_socket() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} exists & isn't a socket", socket_path),
+            ));
+        }
+        fs::remove_file(socket_path)?;
+    }
+    Ok(())
+}
+
+/// Binds the socket with owner only permissions, replacing one left behind by an earlier run
+/// The socket is bound inside a fresh owner only directory, restricted, then renamed into place, so other users
+/// can't connect between the bind & the restriction
+fn bind_socket(socket_path: &str) -> Result<UnixListener, io::Error> {
+    remove_stale_socket(socket_path)?;
+    let staging_dir = format!("{}.tmp", socket_path);
+    if fs::symlink_metadata(&staging_dir).is_ok_and(|metadata| metadata.is_dir()) {
+        // Left behind by a run which stopped while binding
+        fs::remove_dir_all(&staging_dir)?;
+    }
+    fs::DirBuilder::new()
+        .mode(STAGING_DIR_MODE)
+        .create(&staging_dir)?;
+    let staging_path = format!("{}/socket", staging_dir);
+    let bound = UnixListener::bind(&staging_path).and_then(|listener| {
+        fs::set_permissions(&staging_path, fs::Permissions::from_mode(SOCKET_MODE))?;
+        fs::rename(&staging_path, socket_path)?;
+        Ok(listener)
+    });
+    let _ = fs::remove_dir_all(&staging_dir);
+    bound
+}
+
+/// Reads a line into `line` as `BufRead::read_line` does, but fails once it passes `MAX_LINE_BYTES`
+fn read_capped_line<R: BufRead>(reader: &mut R, line: &mut String) -> Result<usize, io::Error> {
+    let mut bytes = Vec::new();
+    let read = reader
+        .by_ref()
+        .take(MAX_LINE_BYTES as u64 + 1)
+        .read_until(b'\n', &mut bytes)?;
+    if read > MAX_LINE_BYTES {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Line longer than {} bytes", MAX_LINE_BYTES),
+        ));
+    }
+    let text = String::from_utf8(bytes)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Line isn't valid utf-8"))?;
+    line.push_str(&text);
+    Ok(read)
+}
+
+/// Counts a priority connection as open until dropped
+struct OpenConnection(Arc<AtomicUsize>);
+
+impl Drop for OpenConnection {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Binds the priority lane's socket & reads each connection on a thread of its own, a line per record
+/// At most `MAX_PRIORITY_CONNECTIONS` are read at once, later ones are answered `busy` & closed
+/// The accepting thread lives as long as the process, once the lane closes its socket can't be reached
+pub(super) fn serve_priority_socket(
+    socket_path: &str,
+) -> Result<Receiver<PriorityRecord>, io::Error> {
+    let listener = bind_socket(socket_path)?;
+    let (tx, rx) = mpsc::channel();
+    let open = Arc::new(AtomicUsize::new(0));
+    thread::spawn(move || {
+        for mut stream in listener.incoming().flatten() {
+            if open.fetch_add(1, Ordering::Relaxed) >= MAX_PRIORITY_CONNECTIONS {
+                open.fetch_sub(1, Ordering::Relaxed);
+                let _ = stream.write_all(BUSY_REPLY);
+                continue;
+            }
+            let tx = tx.clone();
+            let connection = OpenConnection(Arc::clone(&open));
+            thread::spawn(move || {
+                let _connection = connection;
+                read_priority_connection(stream, tx)
+            });
+        }
+    });
+    Ok(rx)
+}
+
+/// Passes a connection's records to the engine until it closes, an optional `type` header line is skipped
+fn read_priority_connection(
+    stream: UnixStream,
+    tx: Sender<PriorityRecord>,
+) -> Result<(), io::Error> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    loop {
+        let mut line = String::new();
+        if read_capped_line(&mut reader, &mut line)? == 0 {
+            break;
+        }
+        let line = line.trim_end_matches(['\n', '\r']).to_string();
+        let record = line.trim();
+        if record.is_empty() || record.starts_with("type") {
+            continue;
+        }
+        let reply = Box::new(stream.try_clone()?);
+        if tx.send(PriorityRecord { line, reply }).is_err() {
+            // The engine stopped taking priority records
+            break;
+        }
+    }
+    Ok(())
+}
+
+impl PaymentsEngine {
+    /// Applies newline delimited records sent over a unix domain socket as they arrive
+    /// Connections are served one at a time, a co-located ingestion daemon is expected to be the only writer
+    /// Lines starting with `!` are control messages, an optional `type` header line is skipped
+    /// Runs until a shutdown message or a passed safety limit, then writes accounts & reports
+    /// SIGUSR1 & SIGUSR2 are handled as when tailing, but only once the next record arrives
+    pub fn listen_execute(&mut self, listen_options: &ListenOptions) -> Result<(), Box<dyn Error>> {
+        let cli_input = &listen_options.cli_options;
+        self.configure_sinks(cli_input)?;
+        self.load_starting_state(cli_input)?;
+        let options = StreamOptions {
+            gc_policy: cli_input.gc_policy.as_ref(),
+            fixed_width: cli_input.fixed_width.as_ref(),
+            transforms: &cli_input.transforms,
+            precision: cli_input.precision,
+            ..StreamOptions::default()
+        };
+
+        let signals = OperatorSignals::register()?;
+        self.open_priority_lane(cli_input)?;
+        let listener = bind_socket(&listen_options.socket_path)?;
+        let mut lines_read = 0;
+        let mut stopped = None;
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    log(
+                        Level::Warn,
+                        format_args!("Failed to accept connection: {}", e),
+                    );
+                    continue;
+                }
+            };
+            match self.serve_connection(stream, cli_input, &options, &signals, &mut lines_read) {
+                Ok(ConnectionEnd::Closed) => {}
+                Ok(ConnectionEnd::Shutdown) => break,
+                Ok(ConnectionEnd::Stopped(e)) => {
+                    stopped = Some(e);
+                    break;
+                }
+                // A dropped connection only loses what it hadn't sent yet
+                Err(e) => log(Level::Warn, format_args!("Connection failed: {}", e)),
+            }
+        }
+        let _ = fs::remove_file(&listen_options.socket_path);
+        self.close_priority_lane(&options);
+
+        self.write_run_outputs(cli_input);
+        self.check_conservation()?;
+        match stopped {
+            Some(e) => Err(e.into()),
+            None => Ok(()),
+        }
+    }
+
+    /// Applies a connection's records until it closes, asks for a shutdown, or a safety limit is passed
+    /// Errors if the connection fails
+    fn serve_connection(
+        &mut self,
+        stream: UnixStream,
+        cli_input: &CliOptions,
+        options: &StreamOptions,
+        signals: &OperatorSignals,
+        lines_read: &mut u64,
+    ) -> Result<ConnectionEnd, io::Error> {
+        let mut reply = stream.try_clone()?;
+        let mut reader = BufReader::new(stream);
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if read_capped_line(&mut reader, &mut line)? == 0 {
+                return Ok(ConnectionEnd::Closed);
+            }
+            *lines_read += 1;
+            self.handle_operator_signals(signals, cli_input)?;
+            self.drain_priority_lane(options);
+            let record = line.trim();
+            match record {
+                FLUSH_MESSAGE => {
+                    self.write_accounts(cli_input);
+                    reply.write_all(b"ok\n")?;
+                }
+                STATUS_MESSAGE => {
+                    reply.write_all(format!("{}\n", self.state_summary()).as_bytes())?;
+                }
+                SHUTDOWN_MESSAGE => {
+                    reply.write_all(b"ok\n")?;
+                    return Ok(ConnectionEnd::Shutdown);
+                }
+                _ if record.starts_with('!') => {
+                    reply.write_all(format!("unknown control message {}\n", record).as_bytes())?;
+                }
+                _ if record.is_empty() || record.starts_with("type") => {}
+                _ => {
+                    let options = StreamOptions {
+                        line_offset: *lines_read - 1,
+                        ..*options
+                    };
+                    // Reading from memory can't fail, only a passed safety limit stops the record
+                    if let Err(e) = self.stream_process_reader(line.as_bytes(), false, &options) {
+                        return Ok(ConnectionEnd::Stopped(e));
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cli_io::{parse_cli_args, CliCommand};
+    use crate::payments_engine::PaymentsEngine;
+    use crate::test::utils::_get_test_output_file;
+    use std::io::{BufRead, BufReader, Write};
+    use std::os::unix::net::UnixStream;
+    use std::time::Duration;
+
+    /// Connects once the listener has bound the socket
+    fn connect(socket_path: &str) -> UnixStream {
+        for _ in 0..100 {
+            if let Ok(stream) = UnixStream::connect(socket_path) {
+                return stream;
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        panic!("Listener never bound {}", socket_path);
+    }
+
+    /// Sends a control message & returns the reply
+    fn control(stream: &mut UnixStream, message: &str) -> String {
+        writeln!(stream, "{}", message).unwrap();
+        let mut reply = String::new();
+        BufReader::new(stream.try_clone().unwrap())
+            .read_line(&mut reply)
+            .unwrap();
+        reply
+    }
+
+    #[test]
+    fn tst_listen_unix() {
+        let socket_path = _get_test_output_file("tst_listen_unix.sock");
+        let f_output = _get_test_output_file("tst_listen_unix.csv");
+        let f_dead_letter = _get_test_output_file("tst_listen_unix_dlq.jsonl");
+        let _ = std::fs::remove_file(&f_dead_letter);
+        let args: Vec<String> = [
+            "--listen-unix",
+            socket_path.as_str(),
+            "--output",
+            &f_output,
+            "--dead-letter",
+            &f_dead_letter,
+        ]
+        .iter()
+        .map(|a| a.to_string())
+        .collect();
+        let listen_options = match parse_cli_args(&args) {
+            Ok(CliCommand::Listen(listen_options)) => listen_options,
+            _ => panic!("Should parse as listen command"),
+        };
+        let listener = std::thread::spawn(move || {
+            let mut payments_engine = PaymentsEngine::new();
+            assert!(payments_engine.listen_execute(&listen_options).is_ok());
+            payments_engine
+        });
+
+        let mut stream = connect(&socket_path);
+        write!(
+            stream,
+            "type, client, tx, amount\ndeposit, 1, 1, 5.0\ndeposit, 2, 2, 3.0\n"
+        )
+        .unwrap();
+        assert_eq!(control(&mut stream, "!flush"), "ok\n");
+        assert_eq!(
+            std::fs::read_to_string(&f_output).unwrap(),
+            "client,available,held,total,locked\n\
+             1,5.0000,0.0000,5.0000,false\n\
+             2,3.0000,0.0000,3.0000,false\n"
+        );
+        assert!(control(&mut stream, "!status").starts_with("last_seq=2 accounts=2 "));
+        assert!(control(&mut stream, "!rewind").starts_with("unknown control message"));
+        drop(stream);
+
+        // Later connections carry on with the same accounts
+        let mut stream = connect(&socket_path);
+        write!(stream, "withdrawal, 1, 3, 2.0\nwithdrawal, 2, 4, 9.0\n").unwrap();
+        assert_eq!(control(&mut stream, "!shutdown"), "ok\n");
+        let payments_engine = listener.join().unwrap();
+        assert_eq!(
+            payments_engine
+                .accounts
+                .get(1)
+                .unwrap()
+                .available()
+                .to_f64(),
+            3.0
+        );
+        assert!(!std::path::Path::new(&socket_path).exists());
+        assert_eq!(
+            std::fs::read_to_string(&f_output).unwrap(),
+            "client,available,held,total,locked\n\
+             1,3.0000,0.0000,3.0000,false\n\
+             2,3.0000,0.0000,3.0000,false\n"
+        );
+        // Dead letters point at the line of the whole session
+        let dead_letters = std::fs::read_to_string(&f_dead_letter).unwrap();
+        assert!(dead_letters.contains("\"line\":8"));
+    }
+
+    #[test]
+    fn tst_listen_priority_lane() {
+        let socket_path = _get_test_output_file("tst_listen_priority_bulk.sock");
+        let priority_path = _get_test_output_file("tst_listen_priority.sock");
+        let args: Vec<String> = [
+            "--listen-unix",
+            socket_path.as_str(),
+            "--priority-unix",
+            priority_path.as_str(),
+            "--output",
+            &_get_test_output_file("tst_listen_priority_lane.csv"),
+        ]
+        .iter()
+        .map(|a| a.to_string())
+        .collect();
+        let listen_options = match parse_cli_args(&args) {
+            Ok(CliCommand::Listen(listen_options)) => listen_options,
+            _ => panic!("Should parse as listen command"),
+        };
+        let listener = std::thread::spawn(move || {
+            let mut payments_engine = PaymentsEngine::new();
+            assert!(payments_engine.listen_execute(&listen_options).is_ok());
+            payments_engine
+        });
+
+        let mut bulk = connect(&socket_path);
+        assert!(control(&mut bulk, "deposit, 1, 1, 5.0\n!status").starts_with("last_seq=1 "));
+        let mut priority = connect(&priority_path);
+        writeln!(priority, "type, client, tx, amount\nhold, 1, 100,").unwrap();
+        // Priority records are applied as the next bulk line arrives, ahead of it
+        for _ in 0..100 {
+            if control(&mut bulk, "!status").starts_with("last_seq=2 ") {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        writeln!(bulk, "withdrawal, 1, 2, 1.0").unwrap();
+        let mut reply = String::new();
+        BufReader::new(priority.try_clone().unwrap())
+            .read_line(&mut reply)
+            .unwrap();
+        assert_eq!(reply, "ok\n");
+        assert_eq!(control(&mut bulk, "!shutdown"), "ok\n");
+        let payments_engine = listener.join().unwrap();
+        let acnt = payments_engine.accounts.get(1).unwrap();
+        assert!(acnt.admin_hold);
+        assert_eq!(acnt.available().to_f64(), 5.0);
+        assert!(!std::path::Path::new(&priority_path).exists());
+    }
+
+    #[test]
+    fn tst_listen_socket_limits() {
+        use std::os::unix::fs::PermissionsExt;
+        let socket_path = _get_test_output_file("tst_listen_socket_limits.sock");
+        let args: Vec<String> = [
+            "--listen-unix",
+            socket_path.as_str(),
+            "--output",
+            &_get_test_output_file("tst_listen_socket_limits.csv"),
+        ]
+        .iter()
+        .map(|a| a.to_string())
+        .collect();
+        let listen_options = match parse_cli_args(&args) {
+            Ok(CliCommand::Listen(listen_options)) => listen_options,
+            _ => panic!("Should parse as listen command"),
+        };
+        let listener = std::thread::spawn(move || {
+            let mut payments_engine = PaymentsEngine::new();
+            assert!(payments_engine.listen_execute(&listen_options).is_ok());
+            payments_engine
+        });
+
+        let mut stream = connect(&socket_path);
+        let mode = std::fs::metadata(&socket_path)
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert!(!std::path::Path::new(&format!("{}.tmp", socket_path)).exists());
+        // An overlong line drops the connection without applying anything of it
+        let overlong = format!("deposit, 1, 1, 5.0{}\n", " ".repeat(super::MAX_LINE_BYTES));
+        let _ = stream.write_all(overlong.as_bytes());
+        let mut reply = String::new();
+        let _ = BufReader::new(stream).read_line(&mut reply);
+        assert_eq!(reply, "");
+
+        let mut stream = connect(&socket_path);
+        assert!(control(&mut stream, "!status").starts_with("last_seq=0 accounts=0 "));
+        assert_eq!(control(&mut stream, "!shutdown"), "ok\n");
+        listener.join().unwrap();
+    }
+
+    #[test]
+    fn tst_priority_connection_cap() {
+        let priority_path = _get_test_output_file("tst_priority_connection_cap.sock");
+        let records = super::serve_priority_socket(&priority_path).unwrap();
+        let open: Vec<UnixStream> = (0..super::MAX_PRIORITY_CONNECTIONS)
+            .map(|_| connect(&priority_path))
+            .collect();
+        let mut reply = String::new();
+        BufReader::new(connect(&priority_path))
+            .read_line(&mut reply)
+            .unwrap();
+        assert_eq!(reply, "busy\n");
+
+        // Closed connections make room for new ones
+        drop(open);
+        for _ in 0..100 {
+            let mut stream = connect(&priority_path);
+            if writeln!(stream, "hold, 1, 100,").is_ok() {
+                if let Ok(record) = records.recv_timeout(Duration::from_millis(50)) {
+                    assert_eq!(record.line, "hold, 1, 100,");
+                    return;
+                }
+            }
+        }
+        panic!("Connections were never accepted again");
+    }
+}
@@ -0,0 +1,150 @@
+use super::account_store::AccountStore;
+use super::PaymentsEngine;
+use crate::account::Account;
+use std::sync::{Arc, RwLock};
+
+/// Consistent copy of every account as of a sequence number
+#[derive(Debug, Default)]
+pub struct AccountSnapshot {
+    /// Sequence number of the last transaction applied before the copy was taken
+    pub seq: u64,
+    pub accounts: AccountStore,
+}
+
+impl AccountSnapshot {
+    pub(super) fn new(seq: u64, accounts: AccountStore) -> Self {
+        Self { seq, accounts }
+    }
+
+    pub(super) fn into_parts(self) -> (u64, AccountStore) {
+        (self.seq, self.accounts)
+    }
+
+    pub fn get(&self, acnt_id: u16) -> Option<&Account> {
+        self.accounts.get(acnt_id)
+    }
+}
+
+/// Cloneable handle other threads read the latest published snapshot through
+/// The lock is only held to swap or clone the pointer, never while a snapshot is built or read
+#[derive(Debug, Clone)]
+pub struct SnapshotReader {
+    latest: Arc<RwLock<Arc<AccountSnapshot>>>,
+}
+
+impl SnapshotReader {
+    /// Latest published snapshot, stays valid & unchanged while processing continues
+    pub fn latest(&self) -> Arc<AccountSnapshot> {
+        match self.latest.read() {
+            Ok(latest) => latest.clone(),
+            Err(poisoned) => poisoned.into_inner().clone(),
+        }
+    }
+}
+
+/// Processing side of snapshots, publishes a new copy once per epoch of transactions
+#[derive(Debug)]
+pub(super) struct SnapshotPublisher {
+    reader: SnapshotReader,
+    /// Transactions between published snapshots
+    every: u64,
+}
+
+impl PaymentsEngine {
+    /// Starts publishing account snapshots every `every` transactions & returns a reader for them
+    /// A snapshot of the current state is published straight away
+    pub fn enable_snapshots(&mut self, every: u64) -> SnapshotReader {
+        let reader = SnapshotReader {
+            latest: Arc::new(RwLock::new(Arc::new(AccountSnapshot::default()))),
+        };
+        self.snapshot_publisher = Some(SnapshotPublisher {
+            reader: reader.clone(),
+            every: every.max(1),
+        });
+        self.publish_snapshot();
+        reader
+    }
+
+    /// Copy of account state as of the last processed txn
+    pub fn snapshot(&self) -> AccountSnapshot {
+        AccountSnapshot::new(self.last_seq, self.accounts.clone())
+    }
+
+    /// Copies account state & swaps it in as the latest snapshot, readers holding older ones are unaffected
+    pub fn publish_snapshot(&self) {
+        let publisher = match &self.snapshot_publisher {
+            Some(publisher) => publisher,
+            None => return,
+        };
+        let snapshot = Arc::new(self.snapshot());
+        match publisher.reader.latest.write() {
+            Ok(mut latest) => *latest = snapshot,
+            Err(poisoned) => *poisoned.into_inner() = snapshot,
+        }
+    }
+
+    /// Publishes a snapshot when a sequence number closes an epoch
+    pub(super) fn publish_snapshot_on_epoch(&self, seq: u64) {
+        if let Some(publisher) = &self.snapshot_publisher {
+            if seq.is_multiple_of(publisher.every) {
+                self.publish_snapshot();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::payments_engine::PaymentsEngine;
+    use crate::test_support::deposit;
+
+    use std::sync::mpsc;
+    use std::thread;
+
+    #[test]
+    fn tst_live_snapshots() {
+        let mut payments_engine = PaymentsEngine::new();
+        let _ = payments_engine.process_txn(&deposit(1, 1, 1.0));
+        let reader = payments_engine.enable_snapshots(2);
+        let first = reader.latest();
+        assert_eq!(first.seq, 1);
+        assert_eq!(first.get(1).unwrap().available().to_f64(), 1.0);
+
+        let _ = payments_engine.process_txn(&deposit(1, 2, 1.0));
+        assert_eq!(reader.latest().seq, 2, "Epoch end should publish");
+        let _ = payments_engine.process_txn(&deposit(2, 3, 1.0));
+        assert_eq!(reader.latest().seq, 2, "Mid epoch should not publish");
+        assert!(reader.latest().get(2).is_none());
+        assert_eq!(
+            first.get(1).unwrap().available().to_f64(),
+            1.0,
+            "Old snapshots are unchanged"
+        );
+
+        // Reader on another thread sees balances consistent with a single sequence number
+        let (done_tx, done_rx) = mpsc::channel();
+        let thread_reader = reader.clone();
+        let handle = thread::spawn(move || {
+            let mut reads = 0;
+            loop {
+                let snapshot = thread_reader.latest();
+                let total: f64 = snapshot
+                    .accounts
+                    .iter()
+                    .map(|a| a.available().to_f64())
+                    .sum();
+                assert_eq!(total, snapshot.seq as f64);
+                reads += 1;
+                if done_rx.try_recv().is_ok() {
+                    return reads;
+                }
+            }
+        });
+        for txn_id in 4..2000 {
+            let _ = payments_engine.process_txn(&deposit((txn_id % 7) as u16, txn_id, 1.0));
+        }
+        done_tx.send(()).unwrap();
+        assert!(handle.join().unwrap() > 0);
+        assert_eq!(reader.latest().seq, 1998);
+    }
+}
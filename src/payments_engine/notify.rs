@@ -0,0 +1,162 @@
+use super::PaymentsEngine;
+use crate::alerts::evaluate_rules;
+use crate::transaction::{SequencedTxn, Transaction};
+use crate::webhook::HighSeverityEvent;
+
+impl PaymentsEngine {
+    /// Events external systems should hear about, given a txn which was just applied
+    /// Clients with a negative balance are remembered, so one is only reported as it crosses into negative
+    fn high_severity_events(&mut self, txn: &Transaction) -> Vec<HighSeverityEvent> {
+        let mut events = vec![];
+        let acnt = match self.accounts.get(txn.acnt_id()) {
+            Some(acnt) => acnt,
+            None => return events,
+        };
+
+        if let Transaction::Chargeback(ref_txn) = txn {
+            if let Some(txn_key) = self.stored_txn_key(ref_txn.ref_id) {
+                if let Transaction::Deposit(p_txn) | Transaction::Withdrawal(p_txn) =
+                    &self.processed_txns[txn_key].txn
+                {
+                    events.push(HighSeverityEvent::ChargebackProcessed {
+                        client: acnt.id,
+                        tx: ref_txn.ref_id,
+                        amount: p_txn.amount.to_f64(),
+                    });
+                }
+            }
+            if acnt.locked_by_chargeback {
+                events.push(HighSeverityEvent::AccountFrozen {
+                    client: acnt.id,
+                    tx: ref_txn.ref_id,
+                });
+            }
+        }
+
+        let negative = acnt.available().minor_units() < 0 || acnt.total_minor_units() < 0;
+        match negative {
+            true if self.negative_clients.insert(acnt.id) => {
+                events.push(HighSeverityEvent::NegativeBalance {
+                    client: acnt.id,
+                    tx: txn.id(),
+                    available: acnt.available().to_f64(),
+                    total: acnt.get_total(),
+                });
+            }
+            true => {}
+            false => {
+                self.negative_clients.remove(&acnt.id);
+            }
+        }
+        events
+    }
+
+    /// Sends high severity events for an applied txn to the webhook sink if one is configured
+    pub(super) fn notify_high_severity(&mut self, txn: &Transaction) {
+        if self.webhook_sink.is_none() {
+            return;
+        }
+        let events = self.high_severity_events(txn);
+        if let Some(sink) = &self.webhook_sink {
+            for event in events {
+                // Undeliverable events are dead lettered by the sink, failing that there is nowhere left to report to
+                let _ = sink.notify(&event);
+            }
+        }
+    }
+
+    /// Evaluates alert rules against the account a txn just changed & emits any newly triggered alerts
+    pub(super) fn notify_balance_alerts(&mut self, s_txn: &SequencedTxn) {
+        let sink = match &self.alert_sink {
+            Some(sink) => sink,
+            None => return,
+        };
+        let acnt = match self.accounts.get(s_txn.txn.acnt_id()) {
+            Some(acnt) => acnt,
+            None => return,
+        };
+        for alert in evaluate_rules(sink.rules(), &mut self.active_alerts, acnt, s_txn) {
+            // Alert webhooks dead letter undeliverable alerts, other failures have nowhere left to report to
+            let _ = sink.emit(&alert);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::payments_engine::config::EngineConfig;
+    use crate::payments_engine::PaymentsEngine;
+    use crate::test_support::{deposit, withdrawal, EngineFixture};
+    use crate::transaction::{RefTxn, Transaction};
+    use crate::webhook::HighSeverityEvent;
+
+    #[test]
+    fn tst_high_severity_events() {
+        let mut payments_engine = PaymentsEngine::new();
+        let deposit = deposit(1, 1, 10.0);
+        let withdrawal = withdrawal(1, 2, 4.0);
+        let ref_txn = RefTxn {
+            ref_id: 1,
+            acnt_id: 1,
+        };
+        let dispute = Transaction::Dispute(ref_txn.clone());
+        let chargeback = Transaction::Chargeback(ref_txn);
+
+        for txn in [&deposit, &withdrawal] {
+            payments_engine.process_txn(txn).unwrap();
+            assert!(payments_engine.high_severity_events(txn).is_empty());
+        }
+
+        payments_engine.process_txn(&dispute).unwrap();
+        assert_eq!(
+            payments_engine.high_severity_events(&dispute),
+            vec![HighSeverityEvent::NegativeBalance {
+                client: 1,
+                tx: 1,
+                available: -4.0,
+                total: 6.0
+            }],
+            "Disputing withdrawn funds should push available negative"
+        );
+
+        payments_engine.process_txn(&chargeback).unwrap();
+        assert_eq!(
+            payments_engine.high_severity_events(&chargeback),
+            vec![
+                HighSeverityEvent::ChargebackProcessed {
+                    client: 1,
+                    tx: 1,
+                    amount: 10.0
+                },
+                HighSeverityEvent::AccountFrozen { client: 1, tx: 1 },
+            ],
+            "An account already negative shouldn't be reported again"
+        );
+    }
+
+    #[test]
+    fn tst_negative_balance_crossings() {
+        let config = EngineConfig {
+            overdraft_limit: 5.0,
+            ..EngineConfig::default()
+        };
+        let mut payments_engine = EngineFixture::with_config(config).build().engine;
+        let mut negative_events = |txn: Transaction| {
+            payments_engine.process_txn(&txn).unwrap();
+            payments_engine
+                .high_severity_events(&txn)
+                .iter()
+                .filter(|event| matches!(event, HighSeverityEvent::NegativeBalance { .. }))
+                .count()
+        };
+        assert_eq!(negative_events(deposit(1, 1, 1.0)), 0);
+        assert_eq!(negative_events(withdrawal(1, 2, 2.0)), 1);
+        assert_eq!(negative_events(withdrawal(1, 3, 1.0)), 0);
+        assert_eq!(negative_events(deposit(1, 4, 5.0)), 0);
+        assert_eq!(
+            negative_events(withdrawal(1, 5, 4.0)),
+            1,
+            "Recovered accounts are reported again once they go negative"
+        );
+    }
+}
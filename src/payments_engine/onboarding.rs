@@ -0,0 +1,200 @@
+//! Account open records, read before any txn so clients start with known status, metadata, & balance
+//! ```text
+//! client,status,kyc,tags,opening_balance
+//! 1,active,true,vip;merchant,100.0
+//! 2,held,false,,
+//! ```
+//! `status` is `active` or `held`, `tags` are separated by `;`, & a left out status, tags, or opening balance
+//! mean active, none, & 0
+
+use super::PaymentsEngine;
+use crate::account::Account;
+use crate::amount::{Available, Held};
+use csv::{ReaderBuilder, Trim};
+use std::collections::HashMap;
+use std::io::{self, ErrorKind};
+use std::str::FromStr;
+
+/// Status an onboarded account opens with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OpeningStatus {
+    #[default]
+    Active,
+    /// Opens under a manual hold, as if a `hold` record had been applied
+    Held,
+}
+
+impl FromStr for OpeningStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "" | "active" => Ok(OpeningStatus::Active),
+            "held" => Ok(OpeningStatus::Held),
+            _ => Err(format!("Unknown opening status '{}'", s)),
+        }
+    }
+}
+
+/// Metadata pre-registered for a client, kept for the rest of the run
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ClientProfile {
+    /// Withdrawals of clients who haven't passed KYC are rejected
+    pub kyc_verified: bool,
+    pub tags: Vec<String>,
+}
+
+/// One row of an onboarding file
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccountOpen {
+    pub client: u16,
+    pub status: OpeningStatus,
+    pub profile: ClientProfile,
+    pub opening_balance: f64,
+}
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, msg)
+}
+
+/// Reads account open records, a client may only be onboarded once per file
+pub fn read_onboarding(file_path: &str) -> Result<Vec<AccountOpen>, io::Error> {
+    let mut rdr = ReaderBuilder::new().trim(Trim::All).from_path(file_path)?;
+    let headers = rdr.headers()?.clone();
+    let column = |name: &str| headers.iter().position(|h| h == name);
+    for required in ["client", "kyc"] {
+        if column(required).is_none() {
+            return Err(invalid_data(format!(
+                "Missing {} column in {}",
+                required, file_path
+            )));
+        }
+    }
+
+    let mut opens = vec![];
+    let mut seen: HashMap<u16, u64> = HashMap::new();
+    for result in rdr.records() {
+        let record = result.map_err(io::Error::other)?;
+        let line = record.position().map_or(0, |pos| pos.line());
+        let malformed = || invalid_data(format!("Malformed account open on line {}", line));
+        let field = |name: &str| column(name).and_then(|i| record.get(i)).unwrap_or("");
+        let open = AccountOpen {
+            client: field("client").parse().map_err(|_| malformed())?,
+            status: field("status").parse().map_err(|_| malformed())?,
+            profile: ClientProfile {
+                kyc_verified: field("kyc").parse().map_err(|_| malformed())?,
+                tags: field("tags")
+                    .split(';')
+                    .map(str::trim)
+                    .filter(|tag| !tag.is_empty())
+                    .map(String::from)
+                    .collect(),
+            },
+            opening_balance: match field("opening_balance") {
+                "" => 0.0,
+                amount => amount.parse().map_err(|_| malformed())?,
+            },
+        };
+        if let Some(first_line) = seen.insert(open.client, line) {
+            return Err(invalid_data(format!(
+                "Client {} is onboarded on lines {} & {} of {}",
+                open.client, first_line, line, file_path
+            )));
+        }
+        opens.push(open);
+    }
+    Ok(opens)
+}
+
+impl PaymentsEngine {
+    /// Opens an account per record & keeps the client's profile
+    /// Clients with an account already, e.g. restored from a snapshot, keep their balances but take the
+    /// record's profile & a held status
+    pub fn onboard_clients(&mut self, opens: Vec<AccountOpen>) {
+        for open in opens {
+            let held = open.status == OpeningStatus::Held;
+            match self.accounts.key(open.client) {
+                Some(acnt_key) => self.accounts[acnt_key].admin_hold |= held,
+                None => {
+                    self.accounts.insert(
+                        Account::with_balances(
+                            open.client,
+                            Available::from_f64(open.opening_balance),
+                            Held::default(),
+                        )
+                        .with_locks(false, held),
+                    );
+                }
+            }
+            self.client_profiles.insert(open.client, open.profile);
+        }
+    }
+
+    /// Reads an onboarding file & opens its accounts
+    pub fn import_onboarding(&mut self, file_path: &str) -> Result<(), io::Error> {
+        let opens = read_onboarding(file_path)?;
+        self.onboard_clients(opens);
+        Ok(())
+    }
+
+    /// Profile pre-registered for a client, None if the client wasn't onboarded
+    pub fn client_profile(&self, acnt_id: u16) -> Option<&ClientProfile> {
+        self.client_profiles.get(&acnt_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_onboarding, OpeningStatus};
+    use crate::payments_engine::config::EngineConfig;
+    use crate::payments_engine::transactions::TxnErrors;
+    use crate::payments_engine::PaymentsEngine;
+    use crate::test::utils::_get_test_output_file;
+    use crate::test_support::{deposit, withdrawal};
+
+    #[test]
+    fn tst_onboarding() {
+        let f_onboarding = _get_test_output_file("tst_onboarding.csv");
+        std::fs::write(
+            &f_onboarding,
+            "client,status,kyc,tags,opening_balance\n\
+             1,active,true,vip; merchant,100.0\n\
+             2,held,false,,\n\
+             3,,false,,5.0\n",
+        )
+        .unwrap();
+        let opens = read_onboarding(&f_onboarding).unwrap();
+        assert_eq!(opens[0].profile.tags, ["vip", "merchant"]);
+        assert_eq!(opens[1].status, OpeningStatus::Held);
+
+        let config = EngineConfig {
+            require_onboarding: true,
+            ..EngineConfig::default()
+        };
+        let mut payments_engine = PaymentsEngine::with_config(config).unwrap();
+        payments_engine.onboard_clients(opens);
+        assert_eq!(payments_engine.account(1).unwrap().available(), 100.0);
+        assert!(payments_engine.account(2).unwrap().admin_hold());
+        assert!(payments_engine.client_profile(1).unwrap().kyc_verified);
+
+        assert!(payments_engine.process_txn(&withdrawal(1, 1, 10.0)).is_ok());
+        assert_eq!(
+            payments_engine.process_txn(&withdrawal(3, 2, 1.0)),
+            Err(TxnErrors::KycNotVerified)
+        );
+        assert_eq!(
+            payments_engine.process_txn(&deposit(4, 3, 1.0)),
+            Err(TxnErrors::ClientNotOnboarded)
+        );
+        assert!(payments_engine.process_txn(&deposit(3, 4, 1.0)).is_ok());
+        assert!(payments_engine.account(4).is_none());
+
+        std::fs::write(&f_onboarding, "client,kyc\n1,true\n1,false\n").unwrap();
+        let err = read_onboarding(&f_onboarding).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("Client 1 is onboarded on lines 2 & 3"));
+        std::fs::write(&f_onboarding, "client,status\n1,active\n").unwrap();
+        assert!(read_onboarding(&f_onboarding).is_err());
+    }
+}
@@ -0,0 +1,401 @@
+//! Reference implementation of the standard processing rules, written as plainly as possible
+//! Runs next to the engine to catch divergences, e.g. after performance work on the engine
+//! Only models the default rules, extras like approvals or risk scoring aren't known to it
+
+use super::pipeline::{Stage, TxnOutcome};
+use super::transactions::TxnErrors;
+use super::PaymentsEngine;
+use crate::account::Account;
+use crate::amount::{Amount, TxnAmount};
+use crate::transaction::{AdminAction, DisputeState, SequencedTxn, Transaction};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+/// A deposit or withdrawal as the oracle remembers it, amounts in minor units
+#[derive(Debug)]
+struct OracleTxn {
+    acnt_id: u16,
+    amount: i64,
+    state: DisputeState,
+    deposit: bool,
+    refunded: i64,
+    /// Held funds partial releases returned to available during the open dispute
+    released: i64,
+}
+
+fn txn_amount(minor: i64) -> TxnAmount {
+    TxnAmount::new(Amount::from_minor_units(minor))
+}
+
+/// Accounts & txns kept in plain maps, every rule applied in one place
+#[derive(Debug, Default)]
+pub struct Oracle {
+    accounts: HashMap<u16, Account>,
+    txns: HashMap<u32, OracleTxn>,
+}
+
+impl Oracle {
+    /// Starts from existing accounts, e.g. ones restored before processing
+    pub fn with_accounts(accounts: &[Account]) -> Self {
+        Self {
+            accounts: accounts
+                .iter()
+                .map(|acnt| (acnt.id, acnt.clone()))
+                .collect(),
+            txns: HashMap::new(),
+        }
+    }
+
+    pub fn account(&self, acnt_id: u16) -> Option<&Account> {
+        self.accounts.get(&acnt_id)
+    }
+
+    /// Applies a txn, returns false if the rules reject it
+    pub fn apply(&mut self, txn: &Transaction) -> bool {
+        match txn {
+            Transaction::Deposit(p_txn) => {
+                if self.txns.contains_key(&p_txn.txn_id) {
+                    return false;
+                }
+                let acnt = self
+                    .accounts
+                    .entry(p_txn.acnt_id)
+                    .or_insert(Account::new(p_txn.acnt_id));
+                if acnt.is_locked() {
+                    return false;
+                }
+                if acnt.credit(p_txn.amount).is_err() {
+                    return false;
+                }
+                self.remember(p_txn.txn_id, p_txn.acnt_id, p_txn.amount, true);
+                true
+            }
+            Transaction::Withdrawal(p_txn) => {
+                if self.txns.contains_key(&p_txn.txn_id) {
+                    return false;
+                }
+                let acnt = match self.accounts.get_mut(&p_txn.acnt_id) {
+                    Some(acnt) => acnt,
+                    None => return false,
+                };
+                if acnt.is_locked() || acnt.available().minor_units() < p_txn.amount.minor_units() {
+                    return false;
+                }
+                if acnt.debit(p_txn.amount).is_err() {
+                    return false;
+                }
+                self.remember(p_txn.txn_id, p_txn.acnt_id, p_txn.amount, false);
+                true
+            }
+            Transaction::Dispute(ref_txn)
+            | Transaction::Resolve(ref_txn)
+            | Transaction::Chargeback(ref_txn) => {
+                let next = match txn {
+                    Transaction::Dispute(_) => DisputeState::Disputed,
+                    Transaction::Resolve(_) => DisputeState::Resolved,
+                    _ => DisputeState::ChargedBack,
+                };
+                let acnt = match self.accounts.get_mut(&ref_txn.acnt_id) {
+                    Some(acnt) if !acnt.is_locked() => acnt,
+                    _ => return false,
+                };
+                let ref_txn = match self.txns.get_mut(&ref_txn.ref_id) {
+                    Some(oracle_txn)
+                        if oracle_txn.acnt_id == ref_txn.acnt_id
+                            && oracle_txn.state.can_transition(next) =>
+                    {
+                        oracle_txn
+                    }
+                    _ => return false,
+                };
+                // Refunded funds went back to the payer & can't be disputed again
+                let disputed = txn_amount(ref_txn.amount - ref_txn.refunded - ref_txn.released);
+                let moved = match next {
+                    DisputeState::Disputed => acnt.hold(disputed),
+                    DisputeState::Resolved => acnt.release(disputed),
+                    _ => acnt.settle_held(disputed),
+                };
+                if moved.is_err() {
+                    return false;
+                }
+                if next == DisputeState::ChargedBack {
+                    acnt.locked_by_chargeback = true;
+                }
+                ref_txn.state = next;
+                ref_txn.released = 0;
+                true
+            }
+            Transaction::Refund(refund_txn) => {
+                let acnt = match self.accounts.get_mut(&refund_txn.acnt_id) {
+                    Some(acnt) if !acnt.is_locked() => acnt,
+                    _ => return false,
+                };
+                let deposit = match self.txns.get_mut(&refund_txn.ref_id) {
+                    Some(oracle_txn)
+                        if oracle_txn.deposit
+                            && oracle_txn.acnt_id == refund_txn.acnt_id
+                            && matches!(
+                                oracle_txn.state,
+                                DisputeState::Undisputed | DisputeState::Resolved
+                            ) =>
+                    {
+                        oracle_txn
+                    }
+                    _ => return false,
+                };
+                let refund = refund_txn.amount.minor_units();
+                if deposit.refunded + refund > deposit.amount
+                    || acnt.available().minor_units() < refund
+                {
+                    return false;
+                }
+                if acnt.debit(refund_txn.amount).is_err() {
+                    return false;
+                }
+                deposit.refunded += refund;
+                true
+            }
+            Transaction::Release(release_txn) => {
+                let acnt = match self.accounts.get_mut(&release_txn.acnt_id) {
+                    Some(acnt) if !acnt.is_locked() => acnt,
+                    _ => return false,
+                };
+                let disputed_txn = match self.txns.get_mut(&release_txn.ref_id) {
+                    Some(oracle_txn)
+                        if oracle_txn.acnt_id == release_txn.acnt_id
+                            && oracle_txn.state == DisputeState::Disputed =>
+                    {
+                        oracle_txn
+                    }
+                    _ => return false,
+                };
+                let held = disputed_txn.amount - disputed_txn.refunded - disputed_txn.released;
+                let released = release_txn.amount.minor_units();
+                if released <= 0 || released >= held {
+                    return false;
+                }
+                if acnt.release(release_txn.amount).is_err() {
+                    return false;
+                }
+                disputed_txn.released += released;
+                true
+            }
+            Transaction::Admin(admin_txn) => match self.accounts.get_mut(&admin_txn.acnt_id) {
+                Some(acnt) => match admin_txn.action {
+                    AdminAction::SetHold => {
+                        acnt.admin_hold = true;
+                        true
+                    }
+                    AdminAction::ClearHold => {
+                        acnt.admin_hold = false;
+                        true
+                    }
+                    // Nothing is ever waiting on approval under the standard rules
+                    AdminAction::Approve | AdminAction::Deny => false,
+                },
+                None => false,
+            },
+        }
+    }
+
+    fn remember(&mut self, txn_id: u32, acnt_id: u16, amount: TxnAmount, deposit: bool) {
+        self.txns.insert(
+            txn_id,
+            OracleTxn {
+                acnt_id,
+                amount: amount.minor_units(),
+                state: DisputeState::Undisputed,
+                deposit,
+                refunded: 0,
+                released: 0,
+            },
+        );
+    }
+}
+
+/// A txn the engine & the oracle disagreed on, by outcome or by the client's account afterwards
+#[derive(Debug, Clone, PartialEq)]
+pub struct Divergence {
+    pub seq: u64,
+    pub txn: Transaction,
+    pub engine_applied: bool,
+    pub oracle_applied: bool,
+    pub engine_account: Option<Account>,
+    pub oracle_account: Option<Account>,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let account = |acnt: &Option<Account>| match acnt {
+            Some(acnt) => acnt.get_extended_display_str(),
+            None => "none".to_string(),
+        };
+        write!(
+            f,
+            "Seq {} {:?}: engine applied {} with account {}, oracle applied {} with account {}",
+            self.seq,
+            self.txn,
+            self.engine_applied,
+            account(&self.engine_account),
+            self.oracle_applied,
+            account(&self.oracle_account)
+        )
+    }
+}
+
+/// Divergences found so far, shared with the stage comparing the engine to the oracle
+#[derive(Debug, Clone, Default)]
+pub struct OracleReport {
+    divergences: Arc<Mutex<Vec<Divergence>>>,
+}
+
+impl OracleReport {
+    pub fn divergences(&self) -> Vec<Divergence> {
+        match self.divergences.lock() {
+            Ok(divergences) => divergences.clone(),
+            Err(poisoned) => poisoned.into_inner().clone(),
+        }
+    }
+}
+
+/// Replays every txn the engine ran on the oracle, comparing outcomes & the touched account
+#[derive(Debug)]
+struct OracleStage {
+    oracle: Oracle,
+    report: OracleReport,
+}
+
+impl Stage for OracleStage {
+    fn name(&self) -> &str {
+        "oracle"
+    }
+
+    fn after(&mut self, engine: &mut PaymentsEngine, s_txn: &SequencedTxn, outcome: &TxnOutcome) {
+        // Sequencing isn't part of the business rules, txns which never reached them are skipped
+        if matches!(
+            outcome,
+            TxnOutcome::Dropped | TxnOutcome::Rejected(TxnErrors::OutOfSequence)
+        ) {
+            return;
+        }
+        let engine_applied = *outcome == TxnOutcome::Applied;
+        let oracle_applied = self.oracle.apply(&s_txn.txn);
+        let acnt_id = s_txn.txn.acnt_id();
+        let engine_account = engine.accounts.get(acnt_id);
+        let oracle_account = self.oracle.account(acnt_id);
+        if engine_applied == oracle_applied && engine_account == oracle_account {
+            return;
+        }
+        let divergence = Divergence {
+            seq: s_txn.seq,
+            txn: s_txn.txn.clone(),
+            engine_applied,
+            oracle_applied,
+            engine_account: engine_account.cloned(),
+            oracle_account: oracle_account.cloned(),
+        };
+        if let Ok(mut divergences) = self.report.divergences.lock() {
+            divergences.push(divergence);
+        }
+    }
+}
+
+impl PaymentsEngine {
+    /// Checks every txn from now on against the oracle, which starts from the current accounts
+    /// Only meaningful while the engine runs the standard rules
+    pub fn enable_oracle_check(&mut self) -> OracleReport {
+        let report = OracleReport::default();
+        self.pipeline.push(Box::new(OracleStage {
+            oracle: Oracle::with_accounts(&self.accounts),
+            report: report.clone(),
+        }));
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Oracle, Stage};
+    use crate::amount::TxnAmount;
+    use crate::payments_engine::pipeline::TxnOutcome;
+    use crate::payments_engine::PaymentsEngine;
+    use crate::test_support::pure_txn;
+    use crate::transaction::{RefTxn, SequencedTxn, Transaction};
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    /// Adds a little to every deposit, the kind of slip a rewrite could make
+    #[derive(Debug)]
+    struct SkewDeposits;
+
+    impl Stage for SkewDeposits {
+        fn name(&self) -> &str {
+            "skew_deposits"
+        }
+
+        fn after(
+            &mut self,
+            engine: &mut PaymentsEngine,
+            s_txn: &SequencedTxn,
+            outcome: &TxnOutcome,
+        ) {
+            if let (Transaction::Deposit(p_txn), TxnOutcome::Applied) = (&s_txn.txn, outcome) {
+                if let Some(acnt_key) = engine.accounts.key(p_txn.acnt_id) {
+                    engine.accounts[acnt_key]
+                        .credit(TxnAmount::from_f64(0.0001))
+                        .unwrap();
+                }
+            }
+        }
+    }
+
+    fn random_txn(rng: &mut StdRng) -> Transaction {
+        let acnt_id = rng.gen_range(1..4);
+        let txn_id = rng.gen_range(1..40);
+        let ref_txn = RefTxn {
+            ref_id: txn_id,
+            acnt_id,
+        };
+        let p_txn = pure_txn(acnt_id, txn_id, rng.gen_range(1..100) as f64 / 4.0);
+        match rng.gen_range(0..5) {
+            0 => Transaction::Deposit(p_txn),
+            1 => Transaction::Withdrawal(p_txn),
+            2 => Transaction::Dispute(ref_txn),
+            3 => Transaction::Resolve(ref_txn),
+            _ => Transaction::Chargeback(ref_txn),
+        }
+    }
+
+    #[test]
+    fn tst_oracle_check() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let txns: Vec<Transaction> = (0..2000).map(|_| random_txn(&mut rng)).collect();
+
+        let mut payments_engine = PaymentsEngine::new();
+        let report = payments_engine.enable_oracle_check();
+        for txn in txns.iter() {
+            let _ = payments_engine.process_txn(txn);
+        }
+        assert_eq!(report.divergences(), vec![]);
+
+        let mut oracle = Oracle::default();
+        for txn in txns.iter() {
+            oracle.apply(txn);
+        }
+        for acnt in payments_engine.accounts.iter() {
+            assert_eq!(oracle.account(acnt.id), Some(acnt));
+        }
+
+        let mut skewed = PaymentsEngine::new();
+        let report = skewed.enable_oracle_check();
+        // After hooks run in reverse, so the skew lands before the oracle compares
+        skewed.pipeline_mut().push(Box::new(SkewDeposits));
+        for txn in txns.iter() {
+            let _ = skewed.process_txn(txn);
+        }
+        let divergences = report.divergences();
+        assert!(!divergences.is_empty());
+        assert!(matches!(divergences[0].txn, Transaction::Deposit(_)));
+    }
+}
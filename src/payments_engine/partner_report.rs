@@ -0,0 +1,207 @@
+//! Reject rates per partner & error kind, evidence for escalating data quality issues with partners
+//! Only streamed records naming their partner in a `partner` column are attributed, others are kept under None
+//! Rows which can't be read as records aren't counted, as their partner can't be told
+
+use super::PaymentsEngine;
+use crate::dead_letter::RecordError;
+use crate::diagnostics::{log, Level};
+use csv::Writer;
+use std::collections::BTreeMap;
+use std::error::Error;
+
+/// Reject rate above which a partner is escalated, unless the run sets another
+pub const DEFAULT_REJECT_THRESHOLD: f64 = 0.05;
+
+/// Records of one partner & why those which weren't applied were rejected
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PartnerStats {
+    pub records: u64,
+    pub rejected: u64,
+    /// Rejected records by error kind, e.g. `AccountLacksFunds` or `MalformedAmount`
+    pub rejects_by_error: BTreeMap<String, u64>,
+}
+
+impl PartnerStats {
+    /// Share of the partner's records which were rejected
+    pub fn reject_rate(&self) -> f64 {
+        rate(self.rejected, self.records)
+    }
+}
+
+fn rate(count: u64, records: u64) -> f64 {
+    match records {
+        0 => 0.0,
+        records => count as f64 / records as f64,
+    }
+}
+
+/// Name of the error a record was rejected with, without the offending text some errors hold
+fn error_kind(error: &RecordError) -> String {
+    let debug = match error {
+        RecordError::Malformed(_) => return "Malformed".to_string(),
+        RecordError::Input(e) => format!("{:?}", e),
+        RecordError::Rejected(e, _) => format!("{:?}", e),
+    };
+    match debug.split_once('(') {
+        Some((kind, _)) => kind.to_string(),
+        None => debug,
+    }
+}
+
+/// Partner stats by partner name, records without one are kept under None
+#[derive(Debug, Default)]
+pub(super) struct PartnerReport {
+    reject_threshold: f64,
+    partners: BTreeMap<Option<String>, PartnerStats>,
+}
+
+impl PaymentsEngine {
+    /// Starts counting streamed records & their rejects per partner
+    pub fn enable_partner_report(&mut self, reject_threshold: f64) {
+        self.partner_report = Some(PartnerReport {
+            reject_threshold,
+            partners: BTreeMap::new(),
+        });
+    }
+
+    /// True if streamed records' partners are read for the partner report
+    pub(super) fn wants_partners(&self) -> bool {
+        self.partner_report.is_some()
+    }
+
+    /// Counts a streamed record against its partner
+    pub(super) fn record_partner_outcome(
+        &mut self,
+        partner: Option<String>,
+        result: &Result<(), RecordError>,
+    ) {
+        let report = match &mut self.partner_report {
+            Some(report) => report,
+            None => return,
+        };
+        let stats = report.partners.entry(partner).or_default();
+        stats.records += 1;
+        if let Err(e) = result {
+            stats.rejected += 1;
+            *stats.rejects_by_error.entry(error_kind(e)).or_default() += 1;
+        }
+    }
+
+    /// Stats of a partner, None for records without one
+    pub fn partner_stats(&self, partner: Option<&str>) -> Option<&PartnerStats> {
+        self.partner_report
+            .as_ref()?
+            .partners
+            .get(&partner.map(str::to_string))
+    }
+
+    /// Partners whose reject rate is above the threshold, in name order
+    /// Records without a partner can't be escalated to anyone, they're only marked in the report
+    pub fn escalated_partners(&self) -> Vec<&str> {
+        let report = match &self.partner_report {
+            Some(report) => report,
+            None => return vec![],
+        };
+        report
+            .partners
+            .iter()
+            .filter(|(_, stats)| stats.reject_rate() > report.reject_threshold)
+            .filter_map(|(partner, _)| partner.as_deref())
+            .collect()
+    }
+
+    /// Writes a row of all rejects per partner followed by a row per error kind, in partner name order
+    /// Rows whose reject rate is above the threshold are marked escalate & each escalated partner is logged
+    pub fn output_partner_report_csv(&self, file_path: &str) -> Result<(), Box<dyn Error>> {
+        let mut wtr = Writer::from_path(file_path)?;
+        wtr.write_record([
+            "partner",
+            "error",
+            "records",
+            "rejected",
+            "reject_rate",
+            "escalate",
+        ])?;
+        let report = match &self.partner_report {
+            Some(report) => report,
+            None => {
+                wtr.flush()?;
+                return Ok(());
+            }
+        };
+        for (partner, stats) in &report.partners {
+            let partner = partner.as_deref().unwrap_or("");
+            let rows = std::iter::once(("", stats.rejected)).chain(
+                stats
+                    .rejects_by_error
+                    .iter()
+                    .map(|(kind, count)| (kind.as_str(), *count)),
+            );
+            for (kind, count) in rows {
+                let reject_rate = rate(count, stats.records);
+                wtr.write_record([
+                    partner,
+                    kind,
+                    &stats.records.to_string(),
+                    &count.to_string(),
+                    &format!("{:.4}", reject_rate),
+                    &(reject_rate > report.reject_threshold).to_string(),
+                ])?;
+            }
+        }
+        wtr.flush()?;
+        for partner in self.escalated_partners() {
+            let stats = &report.partners[&Some(partner.to_string())];
+            log(
+                Level::Warn,
+                format_args!(
+                    "Partner {} rejected {} of {} records, above the {:.4} threshold",
+                    partner, stats.rejected, stats.records, report.reject_threshold
+                ),
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::payments_engine::stream_process::StreamOptions;
+    use crate::payments_engine::PaymentsEngine;
+    use crate::test::utils::_get_test_output_file;
+
+    #[test]
+    fn tst_partner_report() {
+        let records = "type,client,tx,amount,partner\n\
+                       deposit,1,1,10.0,acme\n\
+                       withdrawal,1,2,50.0,acme\n\
+                       deposit,2,3,oops,acme\n\
+                       deposit,2,4,5.0,acme\n\
+                       deposit,3,5,1.0,globex\n\
+                       withdrawal,3,6,0.5,globex\n\
+                       withdrawal,4,7,1.0,\n";
+        let mut payments_engine = PaymentsEngine::new();
+        payments_engine.enable_partner_report(0.25);
+        assert!(payments_engine
+            .stream_process_reader(records.as_bytes(), true, &StreamOptions::default())
+            .is_ok());
+        let acme = payments_engine.partner_stats(Some("acme")).unwrap();
+        assert_eq!((acme.records, acme.rejected), (4, 2));
+        assert_eq!(acme.reject_rate(), 0.5);
+        assert_eq!(payments_engine.partner_stats(None).unwrap().rejected, 1);
+        assert_eq!(payments_engine.escalated_partners(), ["acme"]);
+
+        let f_report = _get_test_output_file("tst_partner_report.csv");
+        assert!(payments_engine.output_partner_report_csv(&f_report).is_ok());
+        assert_eq!(
+            std::fs::read_to_string(&f_report).unwrap(),
+            "partner,error,records,rejected,reject_rate,escalate\n\
+             ,,1,1,1.0000,true\n\
+             ,AccountDoesNotExist,1,1,1.0000,true\n\
+             acme,,4,2,0.5000,true\n\
+             acme,AccountLacksFunds,4,1,0.2500,false\n\
+             acme,MalformedAmount,4,1,0.2500,false\n\
+             globex,,2,0,0.0000,false\n"
+        );
+    }
+}
@@ -0,0 +1,427 @@
+use super::transactions::TxnErrors;
+use super::PaymentsEngine;
+use crate::transaction::SequencedTxn;
+use std::fmt;
+
+/// Where a txn ended up once the pipeline is done with it
+#[derive(Debug, PartialEq)]
+pub enum TxnOutcome {
+    /// Changed account state
+    Applied,
+    /// Stopped without being an error, e.g. a filtered client or an absorbed retry
+    Dropped,
+    Rejected(TxnErrors),
+}
+
+/// One step of processing a txn, stages run like middleware around the ones after them
+/// `before` runs in pipeline order until a stage decides the outcome, then `after` runs on
+/// every stage in reverse order so stages can react to the outcome
+/// Stages must not process txns on the engine they are given, the pipeline is detached while it runs
+pub trait Stage: fmt::Debug + Send {
+    /// Identifies the stage when inserting stages around it or replacing it
+    fn name(&self) -> &str;
+
+    /// Returns an outcome to stop the txn here, None hands it to the next stage
+    fn before(
+        &mut self,
+        _engine: &mut PaymentsEngine,
+        _s_txn: &SequencedTxn,
+    ) -> Option<TxnOutcome> {
+        None
+    }
+
+    fn after(
+        &mut self,
+        _engine: &mut PaymentsEngine,
+        _s_txn: &SequencedTxn,
+        _outcome: &TxnOutcome,
+    ) {
+    }
+}
+
+/// Rejects stale sequence numbers & txns account & txn state doesn't allow, drops records of clients the
+/// filter excludes
+#[derive(Debug)]
+pub struct ValidateStage;
+
+impl Stage for ValidateStage {
+    fn name(&self) -> &str {
+        "validate"
+    }
+
+    fn before(&mut self, engine: &mut PaymentsEngine, s_txn: &SequencedTxn) -> Option<TxnOutcome> {
+        if s_txn.seq <= engine.last_seq {
+            return Some(TxnOutcome::Rejected(TxnErrors::OutOfSequence));
+        }
+        engine.last_seq = s_txn.seq;
+        engine.sequencer.observe(s_txn.seq);
+        if let Some(filter) = &engine.config.client_filter {
+            if !filter.allows(s_txn.txn.acnt_id()) {
+                engine.filtered_records += 1;
+                return Some(TxnOutcome::Dropped);
+            }
+        }
+        // Retries the dedup stage drops aren't rejected for what changed since they were applied
+        if engine.is_windowed_retry(&s_txn.txn) {
+            return None;
+        }
+        match engine.check_txn_state(&s_txn.txn) {
+            Ok(()) => None,
+            Err(e) => {
+                engine.flag_rejection(&s_txn.txn, &e);
+                Some(TxnOutcome::Rejected(e))
+            }
+        }
+    }
+}
+
+/// Drops retries of records still inside the dedupe window, records enter it once applied
+/// Rejects other deposits & withdrawals reusing the id of a stored or registered txn
+#[derive(Debug)]
+pub struct DedupStage;
+
+impl Stage for DedupStage {
+    fn name(&self) -> &str {
+        "dedup"
+    }
+
+    fn before(&mut self, engine: &mut PaymentsEngine, s_txn: &SequencedTxn) -> Option<TxnOutcome> {
+        if engine.is_windowed_duplicate(&s_txn.txn) {
+            return Some(TxnOutcome::Dropped);
+        }
+        engine
+            .check_txn_id(&s_txn.txn)
+            .err()
+            .map(TxnOutcome::Rejected)
+    }
+
+    fn after(&mut self, engine: &mut PaymentsEngine, s_txn: &SequencedTxn, outcome: &TxnOutcome) {
+        if *outcome == TxnOutcome::Applied {
+            engine.window_applied(&s_txn.txn);
+        }
+    }
+}
+
+/// Quarantines txns of accounts under review or of anomalous amounts & scores the outcome of the rest
+#[derive(Debug)]
+pub struct RiskRulesStage;
+
+impl Stage for RiskRulesStage {
+    fn name(&self) -> &str {
+        "risk_rules"
+    }
+
+    fn before(&mut self, engine: &mut PaymentsEngine, s_txn: &SequencedTxn) -> Option<TxnOutcome> {
+        if engine.is_quarantined(&s_txn.txn) {
+            return Some(engine.quarantine(s_txn, TxnErrors::AccountUnderReview));
+        }
+        match engine.is_anomalous(&s_txn.txn) {
+            true => Some(engine.quarantine(s_txn, TxnErrors::AnomalousAmount)),
+            false => None,
+        }
+    }
+
+    fn after(&mut self, engine: &mut PaymentsEngine, s_txn: &SequencedTxn, outcome: &TxnOutcome) {
+        engine.update_risk_score(&s_txn.txn, outcome);
+        engine.record_amount(&s_txn.txn, outcome);
+    }
+}
+
+/// Applies the txn to account state, the stages before it validated it
+#[derive(Debug)]
+pub struct ApplyStage;
+
+impl Stage for ApplyStage {
+    fn name(&self) -> &str {
+        "apply"
+    }
+
+    fn before(&mut self, engine: &mut PaymentsEngine, s_txn: &SequencedTxn) -> Option<TxnOutcome> {
+        Some(match engine.apply_txn(&s_txn.txn) {
+            Ok(_) => TxnOutcome::Applied,
+            Err(e) => TxnOutcome::Rejected(e),
+        })
+    }
+}
+
+/// Sends webhook events & balance alerts for applied txns
+#[derive(Debug)]
+pub struct NotifyStage;
+
+impl Stage for NotifyStage {
+    fn name(&self) -> &str {
+        "notify"
+    }
+
+    fn after(&mut self, engine: &mut PaymentsEngine, s_txn: &SequencedTxn, outcome: &TxnOutcome) {
+        if *outcome == TxnOutcome::Applied {
+            engine.notify_high_severity(&s_txn.txn);
+            engine.notify_balance_alerts(s_txn);
+        }
+    }
+}
+
+/// Ordered stages every txn given to the engine passes through
+#[derive(Debug, Default)]
+pub struct Pipeline {
+    stages: Vec<Box<dyn Stage>>,
+}
+
+impl Pipeline {
+    /// Validate -> Dedup -> RiskRules -> Apply -> Notify
+    pub fn standard() -> Self {
+        Self {
+            stages: vec![
+                Box::new(ValidateStage),
+                Box::new(DedupStage),
+                Box::new(RiskRulesStage),
+                Box::new(ApplyStage),
+                Box::new(NotifyStage),
+            ],
+        }
+    }
+
+    pub fn stage_names(&self) -> Vec<&str> {
+        self.stages.iter().map(|stage| stage.name()).collect()
+    }
+
+    fn position(&self, name: &str) -> Option<usize> {
+        self.stages.iter().position(|stage| stage.name() == name)
+    }
+
+    /// Adds a stage at the end of the pipeline
+    pub fn push(&mut self, stage: Box<dyn Stage>) {
+        self.stages.push(stage);
+    }
+
+    /// Adds a stage in front of the named one, hands the stage back if no stage has that name
+    pub fn insert_before(
+        &mut self,
+        name: &str,
+        stage: Box<dyn Stage>,
+    ) -> Result<(), Box<dyn Stage>> {
+        match self.position(name) {
+            Some(indx) => {
+                self.stages.insert(indx, stage);
+                Ok(())
+            }
+            None => Err(stage),
+        }
+    }
+
+    /// Adds a stage behind the named one, hands the stage back if no stage has that name
+    pub fn insert_after(
+        &mut self,
+        name: &str,
+        stage: Box<dyn Stage>,
+    ) -> Result<(), Box<dyn Stage>> {
+        match self.position(name) {
+            Some(indx) => {
+                self.stages.insert(indx + 1, stage);
+                Ok(())
+            }
+            None => Err(stage),
+        }
+    }
+
+    /// Swaps the named stage for another, returning the one replaced
+    pub fn replace(
+        &mut self,
+        name: &str,
+        stage: Box<dyn Stage>,
+    ) -> Result<Box<dyn Stage>, Box<dyn Stage>> {
+        match self.position(name) {
+            Some(indx) => Ok(std::mem::replace(&mut self.stages[indx], stage)),
+            None => Err(stage),
+        }
+    }
+
+    /// Takes the named stage out of the pipeline
+    pub fn remove(&mut self, name: &str) -> Option<Box<dyn Stage>> {
+        self.position(name).map(|indx| self.stages.remove(indx))
+    }
+
+    /// Passes a txn through the stages, a txn no stage decided on is dropped
+    fn run(&mut self, engine: &mut PaymentsEngine, s_txn: &SequencedTxn) -> TxnOutcome {
+        let outcome = self
+            .stages
+            .iter_mut()
+            .find_map(|stage| stage.before(engine, s_txn))
+            .unwrap_or(TxnOutcome::Dropped);
+        for stage in self.stages.iter_mut().rev() {
+            stage.after(engine, s_txn, &outcome);
+        }
+        outcome
+    }
+}
+
+impl PaymentsEngine {
+    /// Stages txns pass through, for adding, replacing, or removing stages
+    pub fn pipeline_mut(&mut self) -> &mut Pipeline {
+        &mut self.pipeline
+    }
+
+    /// Runs a txn through the pipeline, detaching it so stages can borrow the engine
+    pub(super) fn run_pipeline(&mut self, s_txn: &SequencedTxn) -> TxnOutcome {
+        self.log_undo(s_txn);
+        let mut pipeline = std::mem::take(&mut self.pipeline);
+        let outcome = pipeline.run(self, s_txn);
+        self.pipeline = pipeline;
+        outcome
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Stage, TxnOutcome};
+    use crate::amount::TxnAmount;
+    use crate::payments_engine::transactions::TxnErrors;
+    use crate::payments_engine::PaymentsEngine;
+    use crate::test_support::{deposit, withdrawal};
+    use crate::transaction::{SequencedTxn, Transaction};
+    use std::sync::{Arc, Mutex};
+
+    /// Rejects deposits over a limit
+    #[derive(Debug)]
+    struct DepositLimit(TxnAmount);
+
+    impl Stage for DepositLimit {
+        fn name(&self) -> &str {
+            "deposit_limit"
+        }
+
+        fn before(
+            &mut self,
+            _engine: &mut PaymentsEngine,
+            s_txn: &SequencedTxn,
+        ) -> Option<TxnOutcome> {
+            match &s_txn.txn {
+                Transaction::Deposit(p_txn) if p_txn.amount > self.0 => {
+                    Some(TxnOutcome::Rejected(TxnErrors::AccountLacksFunds))
+                }
+                _ => None,
+            }
+        }
+    }
+
+    /// Keeps every outcome it sees
+    #[derive(Debug, Default)]
+    struct Audit(Arc<Mutex<Vec<String>>>);
+
+    impl Stage for Audit {
+        fn name(&self) -> &str {
+            "audit"
+        }
+
+        fn after(
+            &mut self,
+            _engine: &mut PaymentsEngine,
+            s_txn: &SequencedTxn,
+            outcome: &TxnOutcome,
+        ) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("{}:{:?}", s_txn.seq, outcome));
+        }
+    }
+
+    /// Keeps the seq of every txn reaching it
+    #[derive(Debug)]
+    struct Probe(&'static str, Arc<Mutex<Vec<u64>>>);
+
+    impl Stage for Probe {
+        fn name(&self) -> &str {
+            self.0
+        }
+
+        fn before(
+            &mut self,
+            _engine: &mut PaymentsEngine,
+            s_txn: &SequencedTxn,
+        ) -> Option<TxnOutcome> {
+            self.1.lock().unwrap().push(s_txn.seq);
+            None
+        }
+    }
+
+    #[test]
+    fn tst_validate_dedup_stages() {
+        let mut payments_engine = PaymentsEngine::new();
+        let (validated, deduped) = (Arc::default(), Arc::default());
+        let pipeline = payments_engine.pipeline_mut();
+        assert!(pipeline
+            .insert_after(
+                "validate",
+                Box::new(Probe("validated", Arc::clone(&validated)))
+            )
+            .is_ok());
+        assert!(pipeline
+            .insert_after("dedup", Box::new(Probe("deduped", Arc::clone(&deduped))))
+            .is_ok());
+
+        assert!(payments_engine.process_txn(&deposit(1, 1, 5.0)).is_ok());
+        let withdrawal = withdrawal(1, 2, 50.0);
+        assert_eq!(
+            payments_engine.process_txn(&withdrawal),
+            Err(TxnErrors::AccountLacksFunds)
+        );
+        assert_eq!(
+            payments_engine.process_txn(&deposit(1, 1, 5.0)),
+            Err(TxnErrors::TxnIdAlreadyExists)
+        );
+        // Validation stops the withdrawal before dedup, which stops the reused id before apply
+        assert_eq!(*validated.lock().unwrap(), vec![1, 3]);
+        assert_eq!(*deduped.lock().unwrap(), vec![1]);
+    }
+
+    #[test]
+    fn tst_custom_stages() {
+        let mut payments_engine = PaymentsEngine::new();
+        let audit = Audit::default();
+        let seen = audit.0.clone();
+        let pipeline = payments_engine.pipeline_mut();
+        assert!(pipeline
+            .insert_before("apply", Box::new(DepositLimit(TxnAmount::from_f64(100.0))))
+            .is_ok());
+        pipeline.push(Box::new(audit));
+        assert!(pipeline
+            .insert_after("missing", Box::new(DepositLimit(TxnAmount::from_f64(1.0))))
+            .is_err());
+        assert_eq!(
+            pipeline.stage_names(),
+            vec![
+                "validate",
+                "dedup",
+                "risk_rules",
+                "deposit_limit",
+                "apply",
+                "notify",
+                "audit"
+            ]
+        );
+
+        assert!(payments_engine.process_txn(&deposit(1, 1, 50.0)).is_ok());
+        assert_eq!(
+            payments_engine.process_txn(&deposit(1, 2, 500.0)),
+            Err(TxnErrors::AccountLacksFunds)
+        );
+        assert_eq!(payments_engine.accounts[0].available().to_f64(), 50.0);
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec!["1:Applied", "2:Rejected(AccountLacksFunds)"],
+            "After hooks should run even when an earlier stage stops the txn"
+        );
+
+        let removed = payments_engine.pipeline_mut().remove("deposit_limit");
+        assert!(removed.is_some());
+        assert!(payments_engine.process_txn(&deposit(1, 3, 500.0)).is_ok());
+        assert!(payments_engine.pipeline_mut().remove("apply").is_some());
+        assert_eq!(
+            payments_engine.process_txn(&deposit(1, 4, 1.0)),
+            Ok(()),
+            "Txns no stage decides on are dropped"
+        );
+        assert_eq!(payments_engine.accounts[0].available().to_f64(), 550.0);
+    }
+}
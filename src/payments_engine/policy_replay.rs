@@ -0,0 +1,140 @@
+//! What-if replays of a past run's txn log under altered policies, so risk can see what a policy change
+//! would have done before deploying it
+//! Only txns the run accepted are logged, so overrides can show txns being rejected but not newly accepted
+
+use super::PaymentsEngine;
+use crate::amount::format_amount;
+use crate::cli_io::{csv_writer, PolicyReplayOptions};
+use crate::diagnostics::{log, Level};
+use crate::diff::{diff_accounts, write_diff_report, AccountFields};
+use std::collections::BTreeMap;
+use std::error::Error;
+
+impl PaymentsEngine {
+    /// End state of every account, keyed by client, compared field by field between replays
+    pub fn account_end_states(&self) -> BTreeMap<u16, AccountFields> {
+        self.iter_accounts()
+            .map(|view| {
+                let fields = [
+                    ("available", format_amount(view.available())),
+                    ("held", format_amount(view.held())),
+                    ("total", format_amount(view.total())),
+                    ("locked", view.locked().to_string()),
+                    ("status", view.status().as_str().to_string()),
+                ]
+                .into_iter()
+                .map(|(field, value)| (field.to_string(), value))
+                .collect();
+                (view.client(), fields)
+            })
+            .collect()
+    }
+}
+
+/// Replays the txn log under the run's rules & under the overrides, writing how account end states differ
+pub fn policy_replay_execute(options: &PolicyReplayOptions) -> Result<(), Box<dyn Error>> {
+    let mut baseline = PaymentsEngine::with_config(options.engine_config.clone())?;
+    let baseline_rejected = baseline.load_txn_log(&options.txn_log)?;
+    if baseline_rejected > 0 {
+        log(
+            Level::Warn,
+            format_args!(
+                "{} logged records were rejected replaying without overrides, the log may have been written under other --rules",
+                baseline_rejected
+            ),
+        );
+    }
+    let mut what_if = PaymentsEngine::with_config(options.override_config.clone())?;
+    let what_if_rejected = what_if.load_txn_log(&options.txn_log)?;
+
+    let diffs = diff_accounts(
+        &baseline.account_end_states(),
+        &what_if.account_end_states(),
+    );
+    log(
+        Level::Info,
+        format_args!(
+            "{} of {} accounts end differently under the overrides, which reject {} more logged records",
+            diffs.len(),
+            baseline.accounts.len(),
+            what_if_rejected.saturating_sub(baseline_rejected)
+        ),
+    );
+    write_diff_report(csv_writer(&options.output)?, &diffs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::policy_replay_execute;
+    use crate::cli_io::{output_txn_log_csv, parse_cli_args, CliCommand};
+    use crate::payments_engine::stream_process::StreamOptions;
+    use crate::payments_engine::PaymentsEngine;
+    use crate::test::utils::_get_test_output_file;
+
+    #[test]
+    fn tst_policy_replay() {
+        let records = "type,client,tx,amount\n\
+                       deposit,1,1,10.0\n\
+                       dispute,1,1,\n\
+                       chargeback,1,1,\n\
+                       deposit,2,2,10.0\n\
+                       withdrawal,2,3,4.0\n\
+                       deposit,3,4,1.0\n";
+        let mut payments_engine = PaymentsEngine::new();
+        assert!(payments_engine
+            .stream_process_reader(records.as_bytes(), true, &StreamOptions::default())
+            .is_ok());
+        let f_log = _get_test_output_file("tst_policy_replay_log.csv");
+        assert!(output_txn_log_csv(payments_engine.numbered_txns(), &f_log).is_ok());
+
+        let f_report = _get_test_output_file("tst_policy_replay.csv");
+        let args: Vec<String> = [
+            "replay",
+            &f_log,
+            "--override",
+            "freeze_policy=never",
+            "--override",
+            "overdraft_limit=0",
+            "--override",
+            "approval_threshold=3",
+            "--output",
+            &f_report,
+        ]
+        .iter()
+        .map(|arg| arg.to_string())
+        .collect();
+        let options = match parse_cli_args(&args) {
+            Ok(CliCommand::PolicyReplay(options)) => options,
+            _ => panic!("Expected a policy replay command"),
+        };
+        assert!(policy_replay_execute(&options).is_ok());
+        // Client 2's withdrawal is parked for approval instead of applied
+        assert_eq!(
+            std::fs::read_to_string(&f_report).unwrap(),
+            "change,client,field,before,after,delta\n\
+             changed,1,locked,true,false,\n\
+             changed,1,status,charged_back,active,\n\
+             changed,2,held,0.0000,4.0000,4.0000\n\
+             changed,2,total,6.0000,10.0000,4.0000\n"
+        );
+
+        for bad in [
+            "dispute_window=30d",
+            "freeze_policy=sometimes",
+            "max_open_disputes=0",
+            "speed=fast",
+            "freeze_policy",
+        ] {
+            let args: Vec<String> = ["replay", "log.csv", "--override", bad]
+                .iter()
+                .map(|arg| arg.to_string())
+                .collect();
+            assert!(parse_cli_args(&args).is_err(), "{}", bad);
+        }
+        let args: Vec<String> = ["replay", "log.csv"]
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect();
+        assert!(parse_cli_args(&args).is_err());
+    }
+}
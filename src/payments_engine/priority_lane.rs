@@ -0,0 +1,186 @@
+//! A second ingestion lane for urgent operator records, applied ahead of the bulk input between its records
+//! so an unhold, approval, or manual resolve doesn't wait behind hours of a batch file
+//! Only records acting on existing accounts & txns are taken, deposits & withdrawals go through the bulk lane
+
+use super::stream_process::StreamOptions;
+use super::PaymentsEngine;
+use crate::cli_io::{CliOptions, RawInputTxn};
+use crate::dead_letter::{DeadLetter, RecordError};
+use crate::diagnostics::{log, Level};
+use csv::{ReaderBuilder, Trim};
+use std::io::{self, Write};
+use std::sync::mpsc::Receiver;
+
+/// Types of records the priority lane applies
+pub const PRIORITY_TYPES: [&str; 8] = [
+    "dispute",
+    "resolve",
+    "chargeback",
+    "release",
+    "hold",
+    "unhold",
+    "approve",
+    "deny",
+];
+
+/// A line sent over the priority lane & where to answer it
+/// Answered with `ok`, or the kind & error a dead letter of the record would hold
+pub struct PriorityRecord {
+    pub line: String,
+    pub reply: Box<dyn Write + Send>,
+}
+
+/// Priority records waiting to be applied, with the socket they arrive over when bound by the engine
+#[derive(Debug)]
+pub(super) struct PriorityLane {
+    records: Receiver<PriorityRecord>,
+    socket_path: Option<String>,
+}
+
+/// Reads a line of the priority lane as a record in the standard column order
+fn read_priority_record(line: &str) -> Result<RawInputTxn, RecordError> {
+    let mut rdr = ReaderBuilder::new()
+        .trim(Trim::All)
+        .has_headers(false)
+        .from_reader(line.as_bytes());
+    let record: RawInputTxn = match rdr.deserialize().next() {
+        Some(Ok(record)) => record,
+        Some(Err(e)) => return Err(RecordError::Malformed(e.to_string())),
+        None => return Err(RecordError::Malformed("Empty record".to_string())),
+    };
+    if !PRIORITY_TYPES.contains(&record.txn_type()) {
+        return Err(RecordError::Malformed(format!(
+            "{} records go through the bulk lane",
+            record.txn_type()
+        )));
+    }
+    Ok(record)
+}
+
+impl PaymentsEngine {
+    /// Takes priority records from the receiver, applied before the next bulk record is read
+    pub fn set_priority_lane(&mut self, records: Receiver<PriorityRecord>) {
+        self.priority_lane = Some(PriorityLane {
+            records,
+            socket_path: None,
+        });
+    }
+
+    /// Binds the socket of `--priority-unix`, if the options give one
+    /// Each connection is read on its own thread, a line per record
+    pub(super) fn open_priority_lane(&mut self, cli_input: &CliOptions) -> Result<(), io::Error> {
+        let socket_path = match &cli_input.priority_unix {
+            Some(socket_path) => socket_path,
+            None => return Ok(()),
+        };
+        #[cfg(all(unix, feature = "listen"))]
+        {
+            let records = super::listen::serve_priority_socket(socket_path)?;
+            self.priority_lane = Some(PriorityLane {
+                records,
+                socket_path: Some(socket_path.clone()),
+            });
+            Ok(())
+        }
+        #[cfg(not(all(unix, feature = "listen")))]
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!(
+                "Can't bind {}, the priority lane needs the listen feature",
+                socket_path
+            ),
+        ))
+    }
+
+    /// Applies every priority record waiting, answering each
+    /// Called between bulk records so a record is never half applied
+    pub(super) fn drain_priority_lane(&mut self, options: &StreamOptions) {
+        let records: Vec<PriorityRecord> = match &self.priority_lane {
+            Some(lane) => lane.records.try_iter().collect(),
+            None => return,
+        };
+        for mut record in records {
+            let line = record.line.trim();
+            let result =
+                read_priority_record(line).and_then(|raw| self.ingest_record(raw, options));
+            self.metrics.counter("txns.priority", 1);
+            let answer = match result {
+                Ok(()) => "ok\n".to_string(),
+                Err(e) => {
+                    let dead_letter = DeadLetter::new(0, line, &e);
+                    log(
+                        Level::Warn,
+                        format_args!(
+                            "Priority record '{}' not applied: {}",
+                            line, dead_letter.error
+                        ),
+                    );
+                    format!("{} {}\n", dead_letter.kind, dead_letter.error)
+                }
+            };
+            // A sender which went away only misses its answer
+            let _ = record.reply.write_all(answer.as_bytes());
+        }
+    }
+
+    /// Applies what is left in the priority lane & removes its socket
+    pub(super) fn close_priority_lane(&mut self, options: &StreamOptions) {
+        self.drain_priority_lane(options);
+        if let Some(PriorityLane {
+            socket_path: Some(socket_path),
+            ..
+        }) = self.priority_lane.take()
+        {
+            let _ = std::fs::remove_file(socket_path);
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::PriorityRecord;
+    use crate::payments_engine::stream_process::StreamOptions;
+    use crate::payments_engine::PaymentsEngine;
+    use crate::test_support::deposit;
+
+    use std::io::Read;
+    use std::os::unix::net::UnixStream;
+    use std::sync::mpsc;
+
+    #[test]
+    fn tst_priority_lane() {
+        let mut payments_engine = PaymentsEngine::new();
+        assert!(payments_engine.process_txn(&deposit(1, 1, 10.0)).is_ok());
+        let (tx, rx) = mpsc::channel();
+        payments_engine.set_priority_lane(rx);
+        let (reply, mut answers) = UnixStream::pair().unwrap();
+        for line in ["hold, 1, 100,", "deposit, 1, 101, 1.0", "resolve, 1, 1,"] {
+            tx.send(PriorityRecord {
+                line: line.to_string(),
+                reply: Box::new(reply.try_clone().unwrap()),
+            })
+            .unwrap();
+        }
+        drop(reply);
+
+        // The hold is applied before the bulk withdrawal, freezing the account
+        let records = "type,client,tx,amount\nwithdrawal,1,2,1.0\n";
+        assert!(payments_engine
+            .stream_process_reader(records.as_bytes(), true, &StreamOptions::default())
+            .is_ok());
+        let acnt = payments_engine.accounts.get(1).unwrap();
+        assert!(acnt.admin_hold);
+        assert_eq!(acnt.available().to_f64(), 10.0);
+
+        payments_engine.close_priority_lane(&StreamOptions::default());
+        drop(tx);
+        let mut replies = String::new();
+        answers.read_to_string(&mut replies).unwrap();
+        assert_eq!(
+            replies,
+            "ok\n\
+             malformed deposit records go through the bulk lane\n\
+             rejected AccountFrozen\n"
+        );
+    }
+}
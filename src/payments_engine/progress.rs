@@ -0,0 +1,65 @@
+//! Progress bar of the bytes of the input read, drawn to stderr
+//! Builds without the `progress` feature never draw it, so indicatif isn't pulled in
+
+#[cfg(feature = "progress")]
+use indicatif::{ProgressBar, ProgressStyle};
+#[cfg(feature = "progress")]
+use std::time::Instant;
+
+/// Records processed between progress bar refreshes
+#[cfg(feature = "progress")]
+const PROGRESS_INTERVAL: u64 = 1024;
+
+/// Progress of a streaming run, hidden unless asked for
+pub(super) struct InputProgress {
+    #[cfg(feature = "progress")]
+    bar: Option<ProgressBar>,
+    #[cfg(feature = "progress")]
+    start: Instant,
+}
+
+impl InputProgress {
+    /// Progress bar over `input_len` bytes when known, with a records per second readout
+    #[cfg_attr(not(feature = "progress"), allow(unused_variables))]
+    pub(super) fn new(show: bool, input_len: Option<u64>) -> Self {
+        #[cfg(feature = "progress")]
+        let bar = show.then(|| {
+            let bar = match input_len {
+                Some(total_bytes) => ProgressBar::new(total_bytes),
+                None => ProgressBar::no_length(),
+            };
+            if let Ok(style) = ProgressStyle::with_template(
+                "[{elapsed_precise}] {wide_bar} {bytes}/{total_bytes} {msg} ETA {eta}",
+            ) {
+                bar.set_style(style);
+            }
+            bar
+        });
+        Self {
+            #[cfg(feature = "progress")]
+            bar,
+            #[cfg(feature = "progress")]
+            start: Instant::now(),
+        }
+    }
+
+    /// Moves the bar to `bytes_read`, refreshed only every `PROGRESS_INTERVAL` records
+    #[cfg_attr(not(feature = "progress"), allow(unused_variables))]
+    pub(super) fn update(&self, records_read: u64, bytes_read: impl FnOnce() -> u64) {
+        #[cfg(feature = "progress")]
+        if let Some(bar) = &self.bar {
+            if records_read.is_multiple_of(PROGRESS_INTERVAL) {
+                bar.set_position(bytes_read());
+                let rate = records_read as f64 / self.start.elapsed().as_secs_f64().max(1e-9);
+                bar.set_message(format!("{:.0} records/s", rate));
+            }
+        }
+    }
+
+    pub(super) fn finish(self) {
+        #[cfg(feature = "progress")]
+        if let Some(bar) = self.bar {
+            bar.finish();
+        }
+    }
+}
@@ -0,0 +1,196 @@
+use super::stream_process::StreamOptions;
+use super::PaymentsEngine;
+use crate::cli_io::{RawInputTxn, ReplayOptions};
+use crate::dead_letter::{read_dead_letters, DeadLetter, RecordError};
+use csv::{ReaderBuilder, Trim, Writer};
+use std::collections::{BTreeMap, HashSet};
+use std::error::Error;
+
+impl PaymentsEngine {
+    /// Rebuilds state from the input the dead letters came from, passing over their lines,
+    /// then re-attempts each dead letter in input order & reports which now succeed
+    /// Records still failing go to the rerun's dead letter file, if it has one
+    pub fn replay_dlq_execute(&mut self, options: &ReplayOptions) -> Result<(), Box<dyn Error>> {
+        // Reruns append to the same file, only the latest attempt at a line counts
+        let dead_letters: BTreeMap<u64, DeadLetter> = read_dead_letters(&options.dead_letters)?
+            .into_iter()
+            .map(|dead_letter| (dead_letter.line, dead_letter))
+            .collect();
+        let cli_input = &options.cli_options;
+        self.configure_sinks(cli_input)?;
+        self.load_starting_state(cli_input)?;
+        let skip_lines: HashSet<u64> = dead_letters.keys().copied().collect();
+        self.process_input_file(cli_input, Some(&skip_lines))?;
+
+        let stream_options = StreamOptions {
+            gc_policy: cli_input.gc_policy.as_ref(),
+            fixed_width: cli_input.fixed_width.as_ref(),
+            transforms: &cli_input.transforms,
+            precision: cli_input.precision,
+            ..StreamOptions::default()
+        };
+        let mut wtr = Writer::from_path(&options.report)?;
+        wtr.write_record(["line", "record", "status", "error"])?;
+        for dead_letter in dead_letters.values() {
+            let result = self.replay_dead_letter(dead_letter, &stream_options);
+            let (status, error) = match &result {
+                Ok(_) => ("ok", String::new()),
+                Err(e) => ("failed", DeadLetter::new(dead_letter.line, "", e).error),
+            };
+            wtr.write_record([
+                dead_letter.line.to_string(),
+                dead_letter.record.clone(),
+                status.to_string(),
+                error,
+            ])?;
+            if let Err(e) = result {
+                self.dead_letter(dead_letter.line, &dead_letter.record, &e);
+            }
+        }
+        wtr.flush()?;
+
+        self.write_run_outputs(cli_input);
+        self.check_conservation()?;
+        Ok(())
+    }
+
+    /// Reads a dead letter's record the way its input was read & applies it
+    fn replay_dead_letter(
+        &mut self,
+        dead_letter: &DeadLetter,
+        options: &StreamOptions,
+    ) -> Result<(), RecordError> {
+        let record = match options.fixed_width {
+            Some(spec) => spec
+                .decode(&dead_letter.record)
+                .map_err(RecordError::Input)?,
+            None => {
+                let mut rdr = ReaderBuilder::new()
+                    .trim(Trim::All)
+                    .has_headers(false)
+                    .from_reader(dead_letter.record.as_bytes());
+                match rdr.deserialize::<RawInputTxn>().next() {
+                    Some(Ok(record)) => record,
+                    Some(Err(e)) => return Err(RecordError::Malformed(e.to_string())),
+                    None => return Err(RecordError::Malformed("Empty record".to_string())),
+                }
+            }
+        };
+        self.ingest_record(record, options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cli_io::{parse_cli_args, CliCommand};
+    use crate::dead_letter::read_dead_letters;
+    use crate::payments_engine::PaymentsEngine;
+    use crate::test::utils::_get_test_output_file;
+
+    fn to_args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn tst_replay_dlq() {
+        let f_input = _get_test_output_file("tst_replay_dlq.csv");
+        let f_dlq = _get_test_output_file("tst_replay_dlq.jsonl");
+        let f_retry_dlq = _get_test_output_file("tst_replay_dlq_retry.jsonl");
+        let f_report = _get_test_output_file("tst_replay_dlq_report.csv");
+        let f_output = _get_test_output_file("tst_replay_dlq_accounts.csv");
+        for f in [&f_dlq, &f_retry_dlq] {
+            let _ = std::fs::remove_file(f);
+        }
+        std::fs::write(
+            &f_input,
+            "type,client,tx,amount\n\
+             deposit,1,1,10.0\n\
+             withdrawal,1,2,50.0\n\
+             deposit,1,3,abc\n",
+        )
+        .unwrap();
+
+        let args = to_args(&[&f_input, "--output", &f_output, "--dead-letter", &f_dlq]);
+        let cli_options = match parse_cli_args(&args) {
+            Ok(CliCommand::Process(cli_options)) => cli_options,
+            _ => panic!("Should parse as process command"),
+        };
+        assert!(PaymentsEngine::new()
+            .streaming_execute(&cli_options)
+            .is_ok());
+        let dead_letters = read_dead_letters(&f_dlq).unwrap();
+        assert_eq!(dead_letters.len(), 2);
+        assert_eq!(
+            (dead_letters[0].line, dead_letters[0].record.as_str()),
+            (3, "withdrawal,1,2,50.0")
+        );
+        assert_eq!(
+            (dead_letters[0].kind.as_str(), dead_letters[0].seq),
+            ("rejected", Some(2))
+        );
+        assert_eq!(dead_letters[1].kind, "input");
+
+        // The partner resends the missing funds, the withdrawal can now go through
+        std::fs::write(
+            &f_input,
+            "type,client,tx,amount\n\
+             deposit,1,1,10.0\n\
+             withdrawal,1,2,50.0\n\
+             deposit,1,3,abc\n\
+             deposit,1,4,100.0\n",
+        )
+        .unwrap();
+        let args = to_args(&[
+            "replay-dlq",
+            &f_dlq,
+            &f_input,
+            "--output",
+            &f_output,
+            "--dead-letter",
+            &f_retry_dlq,
+            "--replay-report",
+            &f_report,
+        ]);
+        let replay_options = match parse_cli_args(&args) {
+            Ok(CliCommand::ReplayDlq(replay_options)) => replay_options,
+            _ => panic!("Should parse as replay-dlq command"),
+        };
+        let mut payments_engine = PaymentsEngine::new();
+        assert!(payments_engine.replay_dlq_execute(&replay_options).is_ok());
+        assert_eq!(payments_engine.accounts[0].available().to_f64(), 60.0);
+        assert_eq!(
+            std::fs::read_to_string(&f_report).unwrap(),
+            "line,record,status,error\n\
+             3,\"withdrawal,1,2,50.0\",ok,\n\
+             4,\"deposit,1,3,abc\",failed,\"MalformedAmount(\"\"abc\"\")\"\n"
+        );
+        let still_failing = read_dead_letters(&f_retry_dlq).unwrap();
+        assert_eq!(still_failing.len(), 1);
+        assert_eq!(still_failing[0].line, 4);
+
+        let args = to_args(&["replay-dlq", &f_dlq, &f_input, "--dead-letter", &f_dlq]);
+        assert!(parse_cli_args(&args).is_err());
+        let args = to_args(&["replay-dlq", &f_dlq, &f_input, "--txn-registry", "ids.bin"]);
+        assert!(parse_cli_args(&args).is_err());
+
+        // A dead letter file which can't be opened fails the run instead of losing its records
+        let args = to_args(&[
+            "replay-dlq",
+            &f_dlq,
+            &f_input,
+            "--output",
+            &f_output,
+            "--dead-letter",
+            "/nonexistent/dir/dlq.jsonl",
+            "--replay-report",
+            &f_report,
+        ]);
+        let replay_options = match parse_cli_args(&args) {
+            Ok(CliCommand::ReplayDlq(replay_options)) => replay_options,
+            _ => panic!("Should parse as replay-dlq command"),
+        };
+        assert!(PaymentsEngine::new()
+            .replay_dlq_execute(&replay_options)
+            .is_err());
+    }
+}
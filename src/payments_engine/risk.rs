@@ -0,0 +1,135 @@
+use super::pipeline::TxnOutcome;
+use super::transactions::TxnErrors;
+use super::PaymentsEngine;
+use crate::account::Account;
+use crate::cli_io::output_txn_log_csv;
+use crate::transaction::{SequencedTxn, Transaction};
+use std::error::Error;
+
+impl PaymentsEngine {
+    /// Accumulated risk score of an account, 0 if nothing risky happened to it
+    pub fn risk_score(&self, acnt_id: u16) -> f64 {
+        self.risk_scores.get(&acnt_id).copied().unwrap_or(0.0)
+    }
+
+    /// True if the account's risk score put it under review, its txns are quarantined until cleared
+    pub fn is_under_review(&self, acnt_id: u16) -> bool {
+        self.under_review.contains(&acnt_id)
+    }
+
+    /// True if the account is locked, under review, or flagged for review, the accounts of the compliance feed
+    pub fn is_restricted(&self, acnt_id: u16) -> bool {
+        self.accounts.get(acnt_id).is_some_and(Account::is_locked)
+            || self.is_under_review(acnt_id)
+            || self.is_flagged_for_review(acnt_id)
+    }
+
+    /// Txns held back because their account was under review or their amount was anomalous, in the order they arrived
+    pub fn quarantined_txns(&self) -> &[SequencedTxn] {
+        &self.quarantined
+    }
+
+    /// True if the txn may not be applied because its account is under review
+    /// Admin instructions are exempt so operators can still act on the account
+    pub(super) fn is_quarantined(&self, txn: &Transaction) -> bool {
+        !matches!(txn, Transaction::Admin(_)) && self.is_under_review(txn.acnt_id())
+    }
+
+    /// Holds back a txn of an account under review or of an anomalous amount instead of applying it
+    pub(super) fn quarantine(&mut self, s_txn: &SequencedTxn, e: TxnErrors) -> TxnOutcome {
+        self.quarantined.push(s_txn.clone());
+        TxnOutcome::Rejected(e)
+    }
+
+    /// Adds the weight of a risky outcome to the account's score, placing it under review at the threshold
+    pub(super) fn update_risk_score(&mut self, txn: &Transaction, outcome: &TxnOutcome) {
+        let risk = match &self.config.risk {
+            Some(risk) => risk,
+            None => return,
+        };
+        let weight = match (txn, outcome) {
+            (_, TxnOutcome::Rejected(TxnErrors::AccountUnderReview | TxnErrors::OutOfSequence)) => {
+                return
+            }
+            (_, TxnOutcome::Rejected(TxnErrors::AnomalousAmount)) => risk.anomaly,
+            (Transaction::Withdrawal(_), TxnOutcome::Rejected(_)) => risk.failed_withdrawal,
+            (Transaction::Dispute(_), TxnOutcome::Applied) => risk.dispute,
+            (Transaction::Chargeback(_), TxnOutcome::Applied) => risk.chargeback,
+            _ => return,
+        };
+        let acnt_id = txn.acnt_id();
+        let score = self.risk_scores.entry(acnt_id).or_default();
+        *score += weight;
+        if *score >= risk.review_threshold {
+            self.under_review.insert(acnt_id);
+        }
+    }
+
+    /// Ends a review & resets the account's risk score, quarantined txns stay quarantined
+    pub(super) fn clear_review(&mut self, acnt_id: u16) {
+        self.under_review.remove(&acnt_id);
+        self.risk_scores.remove(&acnt_id);
+    }
+
+    /// Writes quarantined txns to a csv in the txn log format
+    pub fn output_quarantine_csv(&self, file_path: &str) -> Result<(), Box<dyn Error>> {
+        output_txn_log_csv(
+            self.quarantined.iter().map(|s_txn| (s_txn, None)),
+            file_path,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::payments_engine::config::{EngineConfig, RiskConfig};
+    use crate::payments_engine::transactions::TxnErrors;
+    use crate::payments_engine::PaymentsEngine;
+    use crate::test_support::{deposit, withdrawal};
+    use crate::transaction::{AdminAction, AdminTxn, RefTxn, Transaction};
+
+    #[test]
+    fn tst_risk_score_review() {
+        let config = EngineConfig {
+            risk: Some(RiskConfig {
+                review_threshold: 3.0,
+                ..RiskConfig::default()
+            }),
+            ..EngineConfig::default()
+        };
+        let mut payments_engine = PaymentsEngine::with_config(config).unwrap();
+        let _ = payments_engine.process_txn(&deposit(1, 1, 5.0));
+        let _ = payments_engine.process_txn(&deposit(1, 2, 5.0));
+        let _ = payments_engine.process_txn(&withdrawal(1, 3, 50.0));
+        assert_eq!(payments_engine.risk_score(1), 1.0);
+        let dispute = Transaction::Dispute(RefTxn {
+            ref_id: 1,
+            acnt_id: 1,
+        });
+        assert!(payments_engine.process_txn(&dispute).is_ok());
+        assert!(payments_engine.is_under_review(1));
+
+        let deposit = deposit(1, 4, 1.0);
+        assert_eq!(
+            payments_engine.process_txn(&deposit),
+            Err(TxnErrors::AccountUnderReview)
+        );
+        assert_eq!(
+            payments_engine.simulate_txn(&deposit).map(|_| ()),
+            Err(TxnErrors::AccountUnderReview)
+        );
+        assert_eq!(payments_engine.quarantined_txns().len(), 1);
+        assert_eq!(payments_engine.quarantined_txns()[0].txn, deposit);
+        assert_eq!(payments_engine.accounts[0].available().to_f64(), 5.0);
+
+        let unhold = Transaction::Admin(AdminTxn {
+            instr_id: 5,
+            acnt_id: 1,
+            action: AdminAction::ClearHold,
+        });
+        assert!(payments_engine.process_txn(&unhold).is_ok());
+        assert!(!payments_engine.is_under_review(1));
+        assert_eq!(payments_engine.risk_score(1), 0.0);
+        assert!(payments_engine.process_txn(&deposit).is_ok());
+    }
+}
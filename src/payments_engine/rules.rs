@@ -0,0 +1,190 @@
+//! Business rules read from a TOML file, so policies can differ per market without code changes
+//! Every key is optional, left out keys keep the engine's current setting
+//! ```toml
+//! [chargeback]
+//! freeze_account = true
+//!
+//! [withdrawal]
+//! overdraft_limit = 0.0
+//!
+//! [dispute]
+//! disputable = "all"                  # all, deposits, or withdrawals
+//! withdrawn_funds = "allow-negative"  # allow-negative, cap, or flag
+//! max_open = 5
+//! flag_floods = false
+//! ```
+
+use super::config::{ChargebackPolicy, DisputableTxns, EngineConfig, WithdrawnFundsDispute};
+use serde::Deserialize;
+use std::io::{self, ErrorKind};
+use std::str::FromStr;
+
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+struct ChargebackRules {
+    freeze_account: Option<bool>,
+}
+
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+struct WithdrawalRules {
+    overdraft_limit: Option<f64>,
+}
+
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+struct DisputeRules {
+    disputable: Option<String>,
+    withdrawn_funds: Option<String>,
+    max_open: Option<usize>,
+    flag_floods: Option<bool>,
+}
+
+/// Processing rules of a rules file, applied over an engine config
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct RulesFile {
+    #[serde(default)]
+    chargeback: ChargebackRules,
+    #[serde(default)]
+    withdrawal: WithdrawalRules,
+    #[serde(default)]
+    dispute: DisputeRules,
+}
+
+fn invalid(file_path: &str, problem: String) -> io::Error {
+    io::Error::new(
+        ErrorKind::InvalidData,
+        format!("Rules file {}: {}", file_path, problem),
+    )
+}
+
+/// Parses a value with the same names the matching command line flag takes
+fn parse_named<T: FromStr<Err = String>>(value: &Option<String>) -> Result<Option<T>, String> {
+    value.as_deref().map(str::parse).transpose()
+}
+
+impl RulesFile {
+    /// Reads & validates a rules file, naming the file & the problem in errors
+    pub fn from_file(file_path: &str) -> Result<Self, io::Error> {
+        let contents = std::fs::read_to_string(file_path)?;
+        let rules: RulesFile =
+            toml::from_str(&contents).map_err(|e| invalid(file_path, e.message().to_string()))?;
+        rules
+            .validate()
+            .map_err(|problem| invalid(file_path, problem))?;
+        Ok(rules)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if let Some(limit) = self.withdrawal.overdraft_limit {
+            if !limit.is_finite() || limit < 0.0 {
+                return Err(format!(
+                    "withdrawal.overdraft_limit must be 0 or more, not {}",
+                    limit
+                ));
+            }
+        }
+        if self.dispute.max_open == Some(0) {
+            return Err("dispute.max_open must be at least 1".to_string());
+        }
+        parse_named::<DisputableTxns>(&self.dispute.disputable)?;
+        parse_named::<WithdrawnFundsDispute>(&self.dispute.withdrawn_funds)?;
+        Ok(())
+    }
+
+    /// Sets the config fields the file gives, others are left as they are
+    pub fn apply(&self, config: &mut EngineConfig) {
+        if let Some(freeze_account) = self.chargeback.freeze_account {
+            config.chargeback_policy = match freeze_account {
+                true => ChargebackPolicy::Freeze,
+                false => ChargebackPolicy::KeepOpen,
+            };
+        }
+        if let Some(limit) = self.withdrawal.overdraft_limit {
+            config.overdraft_limit = limit;
+        }
+        // Names were checked when the file was read
+        if let Ok(Some(disputable)) = parse_named(&self.dispute.disputable) {
+            config.disputable_txns = disputable;
+        }
+        if let Ok(Some(withdrawn_funds)) = parse_named(&self.dispute.withdrawn_funds) {
+            config.withdrawn_funds_dispute = withdrawn_funds;
+        }
+        if let Some(max_open) = self.dispute.max_open {
+            config.max_open_disputes = Some(max_open);
+        }
+        if let Some(flag_floods) = self.dispute.flag_floods {
+            config.flag_dispute_floods = flag_floods;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RulesFile;
+    use crate::payments_engine::config::{ChargebackPolicy, DisputableTxns, EngineConfig};
+    use crate::test::utils::_get_test_output_file;
+    use crate::test_support::EngineFixture;
+
+    fn rules_file(name: &str, contents: &str) -> String {
+        let file_path = _get_test_output_file(name);
+        std::fs::write(&file_path, contents).unwrap();
+        file_path
+    }
+
+    #[test]
+    fn tst_rules_file() {
+        // An empty file keeps the standard rules
+        let rules = RulesFile::from_file(&rules_file("tst_rules_empty.toml", "")).unwrap();
+        let mut config = EngineConfig::default();
+        rules.apply(&mut config);
+        assert!(config.uses_standard_rules());
+
+        let file_path = rules_file(
+            "tst_rules_file.toml",
+            "[chargeback]\nfreeze_account = false\n\n\
+             [withdrawal]\noverdraft_limit = 5.0\n\n\
+             [dispute]\ndisputable = \"deposits\"\nmax_open = 2\n",
+        );
+        RulesFile::from_file(&file_path).unwrap().apply(&mut config);
+        assert_eq!(config.chargeback_policy, ChargebackPolicy::KeepOpen);
+        assert_eq!(config.disputable_txns, DisputableTxns::Deposits);
+        assert_eq!(config.max_open_disputes, Some(2));
+
+        let fixture = EngineFixture::with_config(config)
+            .deposit(1, 1, 10.0)
+            .withdrawal(1, 2, 14.0)
+            .withdrawal(1, 3, 2.0)
+            .dispute(1, 1)
+            .chargeback(1, 1)
+            .deposit(1, 4, 1.0)
+            .build();
+        // Withdrawals may overdraw by the limit & chargebacks leave the account open
+        assert!(fixture.results[1].is_ok());
+        assert!(fixture.results[2].is_err());
+        assert!(fixture.results[5].is_ok());
+        let acnt = fixture.engine.accounts.get(1).unwrap();
+        assert!(!acnt.is_locked());
+        assert_eq!(acnt.available().to_f64(), -13.0);
+
+        for (name, contents) in [
+            ("tst_rules_unknown.toml", "[chargeback]\nfreeze = true\n"),
+            (
+                "tst_rules_type.toml",
+                "[withdrawal]\noverdraft_limit = \"lots\"\n",
+            ),
+            (
+                "tst_rules_name.toml",
+                "[dispute]\nwithdrawn_funds = \"never\"\n",
+            ),
+            (
+                "tst_rules_negative.toml",
+                "[withdrawal]\noverdraft_limit = -1.0\n",
+            ),
+        ] {
+            let e = RulesFile::from_file(&rules_file(name, contents)).unwrap_err();
+            assert!(e.to_string().contains(name), "{}", e);
+        }
+    }
+}
@@ -0,0 +1,469 @@
+use super::anomaly::AmountHistory;
+use super::approvals::PendingWithdrawal;
+use super::channels::ChannelStats;
+use super::conservation::FundsLedger;
+use super::dispute_cases::note_bytes;
+use super::stats::ClientStats;
+use super::timestamp_sanity::TimestampRejects;
+use super::txn_arena::TxnKey;
+use super::PaymentsEngine;
+use crate::account::Account;
+use crate::amount::TxnAmount;
+use crate::diagnostics::{log, Level};
+use crate::transaction::{Channel, DisputeHistory, SequencedTxn, Transaction};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::hash::{BuildHasher, Hash};
+
+/// Identifies a point engine state can be rolled back to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SavepointId(u64);
+
+#[derive(Debug, PartialEq)]
+pub enum SavepointError {
+    /// The savepoint was released, or discarded by rolling back to one taken before it
+    UnknownSavepoint,
+}
+
+impl fmt::Display for SavepointError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SavepointError::UnknownSavepoint => write!(f, "Savepoint no longer exists"),
+        }
+    }
+}
+
+impl std::error::Error for SavepointError {}
+
+/// State a txn may change, as it was before the txn ran through the pipeline
+/// A txn only ever touches its own account & the txn it refers to
+#[derive(Debug)]
+struct TxnUndo {
+    last_seq: u64,
+    filtered_records: u64,
+    recycled_txn_ids: u64,
+    processed_len: usize,
+    quarantined_len: usize,
+    acnt_id: u16,
+    account: Option<Account>,
+    open_disputes: Option<usize>,
+    flagged_for_review: bool,
+    risk_score: Option<f64>,
+    under_review: bool,
+    amount_history: Option<AmountHistory>,
+    last_activity: Option<u64>,
+    /// Id of a deposit or withdrawal, with whether it was already stored & registered
+    pure_txn: Option<(u32, bool, bool)>,
+    /// Txn a stored id referred to, replaced when the id is reused in a later epoch
+    replaced_txn: Option<(u32, TxnKey)>,
+    /// Dispute history of the txn a dispute, resolve, or chargeback refers to
+    ref_dispute: Option<(TxnKey, DisputeHistory)>,
+    /// Keyed by the referenced txn id
+    held_amount: Option<(u32, Option<TxnAmount>)>,
+    /// Keyed by the refunded deposit's txn id, or a reused id whose refunds are cleared
+    refunded: Option<(u32, Option<TxnAmount>)>,
+    /// Keyed by the withdrawal's txn id or the admin instruction id
+    pending: Option<(u32, Option<PendingWithdrawal>)>,
+    /// Whether the withdrawal was denied, keyed as `pending` is
+    denied: Option<(u32, bool)>,
+    funds_ledger: Option<FundsLedger>,
+    /// Whether the account was reported negative, so webhooks hear of it going negative again
+    negative: bool,
+    /// Indices of the alert rules the account matched, so they fire again
+    active_alerts: Vec<usize>,
+    cross_client_refs_len: usize,
+    timestamp_rejects: TimestampRejects,
+    client_stats: Option<ClientStats>,
+    /// Every channel's, a ref txn counts towards the channel of the txn it refers to
+    channel_stats: BTreeMap<Option<Channel>, ChannelStats>,
+}
+
+/// Savepoints currently open & the undo log they roll back through
+#[derive(Debug, Default)]
+pub(super) struct Savepoints {
+    next_id: u64,
+    /// Open savepoints & the undo log length when they were taken, oldest first
+    open: Vec<(SavepointId, usize)>,
+    undo_log: Vec<TxnUndo>,
+}
+
+fn restore_entry<K: Eq + Hash, V, S: BuildHasher>(
+    map: &mut HashMap<K, V, S>,
+    key: K,
+    value: Option<V>,
+) {
+    match value {
+        Some(value) => map.insert(key, value),
+        None => map.remove(&key),
+    };
+}
+
+fn restore_member<K: Eq + Hash, S: BuildHasher>(set: &mut HashSet<K, S>, key: K, member: bool) {
+    match member {
+        true => set.insert(key),
+        false => set.remove(&key),
+    };
+}
+
+impl PaymentsEngine {
+    /// Marks the current state so it can be returned to with `rollback_to`
+    /// Txns processed while any savepoint is open are logged so they can be undone
+    pub fn savepoint(&mut self) -> SavepointId {
+        let savepoints = &mut self.savepoints;
+        let id = SavepointId(savepoints.next_id);
+        savepoints.next_id += 1;
+        savepoints.open.push((id, savepoints.undo_log.len()));
+        id
+    }
+
+    /// Undoes every txn processed since the savepoint, which stays open to roll back to again
+    /// Savepoints taken after it are discarded
+    /// Balances, txn history, disputes, approvals, risk state, client & channel stats, the cross client audit
+    /// trail, timestamp reject counts, & which clients are negative or match alert rules are restored
+    /// Metrics, events, notifications & alerts already sent, the dedupe window, & the balance series, flow,
+    /// & partner reports are not, they record what was ingested
+    /// Probabilistic duplicate stores can't forget ids, rolled back txn ids stay duplicates there
+    pub fn rollback_to(&mut self, id: SavepointId) -> Result<(), SavepointError> {
+        let pos = self
+            .savepoints
+            .open
+            .iter()
+            .position(|(open_id, _)| *open_id == id)
+            .ok_or(SavepointError::UnknownSavepoint)?;
+        let log_len = self.savepoints.open[pos].1;
+        self.savepoints.open.truncate(pos + 1);
+        while self.savepoints.undo_log.len() > log_len {
+            if let Some(undo) = self.savepoints.undo_log.pop() {
+                self.undo_txn(undo);
+            }
+        }
+        Ok(())
+    }
+
+    /// Closes a savepoint & those taken after it, keeping every change made since
+    pub fn release_savepoint(&mut self, id: SavepointId) -> Result<(), SavepointError> {
+        let savepoints = &mut self.savepoints;
+        let pos = savepoints
+            .open
+            .iter()
+            .position(|(open_id, _)| *open_id == id)
+            .ok_or(SavepointError::UnknownSavepoint)?;
+        savepoints.open.truncate(pos);
+        // Entries are still needed to roll back to older savepoints while any are open
+        if savepoints.open.is_empty() {
+            savepoints.undo_log.clear();
+        }
+        Ok(())
+    }
+
+    /// Logs what a txn may change before it runs, only while a savepoint is open
+    pub(super) fn log_undo(&mut self, s_txn: &SequencedTxn) {
+        if self.savepoints.open.is_empty() {
+            return;
+        }
+        let acnt_id = s_txn.txn.acnt_id();
+        let mut undo = TxnUndo {
+            last_seq: self.last_seq,
+            filtered_records: self.filtered_records,
+            recycled_txn_ids: self.recycled_txn_ids,
+            processed_len: self.processed_txns.len(),
+            quarantined_len: self.quarantined.len(),
+            acnt_id,
+            account: self.accounts.get(acnt_id).cloned(),
+            open_disputes: self.open_disputes.get(&acnt_id).copied(),
+            flagged_for_review: self.flagged_for_review.contains(&acnt_id),
+            risk_score: self.risk_scores.get(&acnt_id).copied(),
+            under_review: self.under_review.contains(&acnt_id),
+            amount_history: self.amount_history.get(&acnt_id).copied(),
+            last_activity: self.last_activity.get(&acnt_id).copied(),
+            pure_txn: None,
+            replaced_txn: None,
+            ref_dispute: None,
+            held_amount: None,
+            refunded: None,
+            pending: None,
+            denied: None,
+            funds_ledger: self.funds_ledger,
+            negative: self.negative_clients.contains(&acnt_id),
+            active_alerts: self
+                .active_alerts
+                .iter()
+                .filter(|(client, _)| *client == acnt_id)
+                .map(|(_, rule)| *rule)
+                .collect(),
+            cross_client_refs_len: self.cross_client_refs.len(),
+            timestamp_rejects: self.timestamp_rejects,
+            client_stats: self.client_stats.get(&acnt_id).cloned(),
+            channel_stats: self.channel_stats.clone(),
+        };
+        match &s_txn.txn {
+            Transaction::Deposit(p_txn) | Transaction::Withdrawal(p_txn) => {
+                undo.pure_txn = Some((
+                    p_txn.txn_id,
+                    self.is_stored_txn_id(p_txn.txn_id),
+                    self.is_registered_txn(p_txn.txn_id),
+                ));
+                undo.replaced_txn = self
+                    .txn_map
+                    .get(&p_txn.txn_id)
+                    .map(|txn_key| (p_txn.txn_id, *txn_key));
+                undo.refunded = Some((p_txn.txn_id, self.refunded.get(&p_txn.txn_id).copied()));
+                undo.pending = Some((
+                    p_txn.txn_id,
+                    self.pending_withdrawals.get(&p_txn.txn_id).cloned(),
+                ));
+                undo.denied = Some((
+                    p_txn.txn_id,
+                    self.denied_withdrawals.contains(&p_txn.txn_id),
+                ));
+            }
+            Transaction::Dispute(ref_txn)
+            | Transaction::Resolve(ref_txn)
+            | Transaction::Chargeback(ref_txn) => {
+                if let Some(txn_key) = self.stored_txn_key(ref_txn.ref_id) {
+                    if let Transaction::Deposit(p_txn) | Transaction::Withdrawal(p_txn) =
+                        &self.processed_txns[txn_key].txn
+                    {
+                        undo.ref_dispute = Some((txn_key, p_txn.dispute.clone()));
+                    }
+                }
+                undo.held_amount = Some((
+                    ref_txn.ref_id,
+                    self.held_amounts.get(&ref_txn.ref_id).copied(),
+                ));
+            }
+            Transaction::Release(release_txn) => {
+                undo.held_amount = Some((
+                    release_txn.ref_id,
+                    self.held_amounts.get(&release_txn.ref_id).copied(),
+                ));
+            }
+            Transaction::Refund(refund_txn) => {
+                undo.refunded = Some((
+                    refund_txn.ref_id,
+                    self.refunded.get(&refund_txn.ref_id).copied(),
+                ));
+            }
+            Transaction::Admin(admin_txn) => {
+                undo.pending = Some((
+                    admin_txn.instr_id,
+                    self.pending_withdrawals.get(&admin_txn.instr_id).cloned(),
+                ));
+                undo.denied = Some((
+                    admin_txn.instr_id,
+                    self.denied_withdrawals.contains(&admin_txn.instr_id),
+                ));
+            }
+        }
+        self.savepoints.undo_log.push(undo);
+    }
+
+    fn undo_txn(&mut self, undo: TxnUndo) {
+        self.last_seq = undo.last_seq;
+        self.filtered_records = undo.filtered_records;
+        self.recycled_txn_ids = undo.recycled_txn_ids;
+        self.processed_txns.truncate(undo.processed_len);
+        if let Some(txn_keys) = self.account_txns.get_mut(&undo.acnt_id) {
+            while txn_keys
+                .last()
+                .is_some_and(|txn_key| !self.processed_txns.contains(*txn_key))
+            {
+                txn_keys.pop();
+            }
+        }
+        self.quarantined.truncate(undo.quarantined_len);
+        self.accounts.restore(undo.acnt_id, undo.account);
+        if undo.funds_ledger.is_some() {
+            self.funds_ledger = undo.funds_ledger;
+        }
+        restore_entry(&mut self.open_disputes, undo.acnt_id, undo.open_disputes);
+        restore_member(
+            &mut self.flagged_for_review,
+            undo.acnt_id,
+            undo.flagged_for_review,
+        );
+        restore_entry(&mut self.risk_scores, undo.acnt_id, undo.risk_score);
+        restore_member(&mut self.under_review, undo.acnt_id, undo.under_review);
+        restore_entry(&mut self.amount_history, undo.acnt_id, undo.amount_history);
+        restore_entry(&mut self.last_activity, undo.acnt_id, undo.last_activity);
+        restore_member(&mut self.negative_clients, undo.acnt_id, undo.negative);
+        self.active_alerts
+            .retain(|(client, _)| *client != undo.acnt_id);
+        self.active_alerts.extend(
+            undo.active_alerts
+                .into_iter()
+                .map(|rule| (undo.acnt_id, rule)),
+        );
+        self.cross_client_refs.truncate(undo.cross_client_refs_len);
+        self.timestamp_rejects = undo.timestamp_rejects;
+        restore_entry(&mut self.client_stats, undo.acnt_id, undo.client_stats);
+        self.channel_stats = undo.channel_stats;
+        if let Some((txn_id, stored, registered)) = undo.pure_txn {
+            if !stored {
+                self.txn_map.remove(&txn_id);
+                self.withdrawal_ids.remove(&txn_id);
+                if let Some(Err(e)) = self.dup_filter.as_mut().map(|f| f.remove(txn_id)) {
+                    log(
+                        Level::Error,
+                        format_args!(
+                            "Failed to remove txn {} from the duplicate store: {}",
+                            txn_id, e
+                        ),
+                    );
+                }
+            }
+            if let (false, Some(registry)) = (registered, &mut self.txn_registry) {
+                registry.remove(txn_id);
+            }
+        }
+        if let Some((txn_id, txn_key)) = undo.replaced_txn {
+            self.txn_map.insert(txn_id, txn_key);
+        }
+        if let Some((txn_key, dispute)) = undo.ref_dispute {
+            if let Transaction::Deposit(p_txn) | Transaction::Withdrawal(p_txn) =
+                &mut self.processed_txns[txn_key].txn
+            {
+                p_txn.dispute = dispute;
+            }
+        }
+        if let Some((ref_id, held)) = undo.held_amount {
+            restore_entry(&mut self.held_amounts, ref_id, held);
+            // Notes are taken after a record is applied, so those of rolled back records are newer
+            if let Some(notes) = self.case_notes.get_mut(&ref_id) {
+                let case_note_bytes = &mut self.case_note_bytes;
+                notes.retain(|(seq, note)| {
+                    let kept = *seq <= undo.last_seq;
+                    if !kept {
+                        *case_note_bytes -= note_bytes(note);
+                    }
+                    kept
+                });
+            }
+        }
+        if let Some((ref_id, refunded)) = undo.refunded {
+            restore_entry(&mut self.refunded, ref_id, refunded);
+        }
+        if let Some((txn_id, pending)) = undo.pending {
+            restore_entry(&mut self.pending_withdrawals, txn_id, pending);
+        }
+        if let Some((txn_id, denied)) = undo.denied {
+            restore_member(&mut self.denied_withdrawals, txn_id, denied);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SavepointError;
+    use crate::alerts::{AlertConfig, AlertSink};
+    use crate::payments_engine::PaymentsEngine;
+    use crate::test::utils::_get_test_output_file;
+    use crate::test_support::deposit;
+    use crate::transaction::{RefTxn, Transaction};
+    use crate::webhook::WebhookConfig;
+
+    #[test]
+    fn tst_savepoint_rollback() {
+        let mut payments_engine = PaymentsEngine::new();
+        payments_engine.process_txn(&deposit(1, 1, 10.0)).unwrap();
+        let before = payments_engine.snapshot();
+
+        let savepoint = payments_engine.savepoint();
+        payments_engine.process_txn(&deposit(1, 2, 5.0)).unwrap();
+        payments_engine.process_txn(&deposit(2, 3, 1.0)).unwrap();
+        let nested = payments_engine.savepoint();
+        let dispute = Transaction::Dispute(RefTxn {
+            ref_id: 1,
+            acnt_id: 1,
+        });
+        payments_engine.process_txn(&dispute).unwrap();
+        payments_engine
+            .process_txn(&Transaction::Chargeback(RefTxn {
+                ref_id: 1,
+                acnt_id: 1,
+            }))
+            .unwrap();
+        assert!(payments_engine.accounts[0].locked_by_chargeback);
+
+        payments_engine.rollback_to(nested).unwrap();
+        assert_eq!(payments_engine.accounts.len(), 2);
+        assert_eq!(
+            (
+                payments_engine.accounts[0].available().to_f64(),
+                payments_engine.accounts[0].held().to_f64()
+            ),
+            (15.0, 0.0)
+        );
+        assert!(!payments_engine.accounts[0].locked_by_chargeback);
+        assert!(
+            payments_engine.process_txn(&dispute).is_ok(),
+            "Rolled back disputes should be forgotten"
+        );
+
+        payments_engine.rollback_to(savepoint).unwrap();
+        assert_eq!(*payments_engine.accounts, *before.accounts);
+        assert_eq!(payments_engine.processed_txns.len(), 1);
+        assert_eq!(
+            payments_engine.rollback_to(nested),
+            Err(SavepointError::UnknownSavepoint)
+        );
+        assert!(
+            payments_engine.process_txn(&deposit(1, 2, 5.0)).is_ok(),
+            "Rolled back txn ids should be reusable"
+        );
+
+        payments_engine.release_savepoint(savepoint).unwrap();
+        payments_engine.process_txn(&deposit(1, 4, 1.0)).unwrap();
+        assert!(payments_engine.savepoints.undo_log.is_empty());
+    }
+
+    #[test]
+    fn tst_savepoint_rollback_tracking() {
+        let f_alerts = _get_test_output_file("tst_savepoint_rollback_tracking.jsonl");
+        let _ = std::fs::remove_file(&f_alerts);
+        let mut payments_engine = PaymentsEngine::new();
+        payments_engine.alert_sink = Some(AlertSink::new(
+            &AlertConfig {
+                rules: vec!["held>1".parse().unwrap()],
+                file: Some(f_alerts.clone()),
+                webhook_urls: vec![],
+                stderr: false,
+            },
+            &WebhookConfig::default(),
+        ));
+        payments_engine.process_txn(&deposit(1, 1, 10.0)).unwrap();
+        payments_engine.process_txn(&deposit(2, 2, 1.0)).unwrap();
+        let client_stats = payments_engine.client_stats(1).cloned();
+        let channel_stats = payments_engine.channel_stats(None).cloned();
+
+        // A dispute firing an alert, a reference to another client's txn, & a chargeback, all undone
+        let savepoint = payments_engine.savepoint();
+        let dispute = |acnt_id| Transaction::Dispute(RefTxn { ref_id: 1, acnt_id });
+        payments_engine.process_txn(&dispute(1)).unwrap();
+        assert!(payments_engine.process_txn(&dispute(2)).is_err());
+        assert_eq!(payments_engine.active_alerts.len(), 1);
+        assert_eq!(payments_engine.cross_client_refs().len(), 1);
+        let nested = payments_engine.savepoint();
+        payments_engine
+            .process_txn(&Transaction::Chargeback(RefTxn {
+                ref_id: 1,
+                acnt_id: 1,
+            }))
+            .unwrap();
+        assert!(payments_engine.active_alerts.is_empty());
+
+        // Undoing the chargeback holds the funds again, so the rule still matches without alerting anew
+        payments_engine.rollback_to(nested).unwrap();
+        assert_eq!(payments_engine.active_alerts.len(), 1);
+        payments_engine.rollback_to(savepoint).unwrap();
+        assert!(payments_engine.active_alerts.is_empty());
+        assert!(payments_engine.cross_client_refs().is_empty());
+        assert_eq!(payments_engine.client_stats(1).cloned(), client_stats);
+        assert_eq!(payments_engine.channel_stats(None).cloned(), channel_stats);
+
+        // The rule stopped matching with the rollback, so the dispute applied again alerts again
+        payments_engine.process_txn(&dispute(1)).unwrap();
+        let alerts = std::fs::read_to_string(&f_alerts).unwrap();
+        assert_eq!(alerts.lines().count(), 2);
+    }
+}
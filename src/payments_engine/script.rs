@@ -0,0 +1,331 @@
+//! Custom validation rules written in rhai, evaluated per txn so product can prototype rules without recompiling
+//! A script sees the txn as `txn` & the client's account as `account`, `()` for a client not seen yet
+//! Accounts of onboarded clients also carry their `kyc` flag & `tags`
+//! ```rhai
+//! // Caps card deposits at 500, & rejects withdrawals leaving less than 10 available
+//! if txn.type == "deposit" && txn.channel == "card" && txn.amount > 500.0 {
+//!     return #{ amount: 500.0 };
+//! }
+//! !(txn.type == "withdrawal" && account != () && account.available - txn.amount < 10.0)
+//! ```
+//! The script's value decides the txn's fate: `true` or `()` accepts it, `false` rejects it, & a map of
+//! fields to change, `amount` or `memo`, applies a modified copy in its place
+
+use super::pipeline::{Stage, TxnOutcome};
+use super::transactions::TxnErrors;
+use super::PaymentsEngine;
+use crate::amount::{Amount, AmountError, PrecisionPolicy, TxnAmount};
+use crate::cli_io::txn_record;
+use crate::diagnostics::{log, Level};
+use crate::transaction::{SequencedTxn, Transaction};
+use rhai::{Dynamic, Engine, Map, Scope, AST};
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::sync::Arc;
+
+/// Operations a script may take per txn, so a runaway loop rejects its txn instead of stalling the run
+const MAX_OPERATIONS: u64 = 100_000;
+
+/// A compiled script, cheap to clone as clones share the compiled form
+#[derive(Debug, Clone)]
+pub struct TxnScript {
+    engine: Arc<Engine>,
+    ast: Arc<AST>,
+    /// Applied to amounts the script sets, as the run applies it to amounts it reads
+    precision: PrecisionPolicy,
+}
+
+/// What a script decided for a txn
+#[derive(Debug, PartialEq)]
+enum Verdict {
+    Accept,
+    Reject,
+    Modify(Transaction),
+}
+
+fn txn_map(txn: &Transaction) -> Map {
+    let [type_str, _, _, _] = txn_record(txn);
+    let (amount, memo, channel) = match txn {
+        Transaction::Deposit(p_txn) | Transaction::Withdrawal(p_txn) => (
+            Dynamic::from_float(p_txn.amount.to_f64()),
+            p_txn.memo.clone().map_or(Dynamic::UNIT, Dynamic::from),
+            p_txn
+                .channel
+                .map_or(Dynamic::UNIT, |channel| channel.as_str().into()),
+        ),
+        Transaction::Refund(refund_txn) => (
+            Dynamic::from_float(refund_txn.amount.to_f64()),
+            Dynamic::UNIT,
+            Dynamic::UNIT,
+        ),
+        Transaction::Release(release_txn) => (
+            Dynamic::from_float(release_txn.amount.to_f64()),
+            Dynamic::UNIT,
+            Dynamic::UNIT,
+        ),
+        _ => (Dynamic::UNIT, Dynamic::UNIT, Dynamic::UNIT),
+    };
+    let mut map = Map::new();
+    map.insert("type".into(), type_str.into());
+    map.insert("client".into(), Dynamic::from_int(txn.acnt_id().into()));
+    map.insert("tx".into(), Dynamic::from_int(txn.id().into()));
+    map.insert("amount".into(), amount);
+    map.insert("memo".into(), memo);
+    map.insert("channel".into(), channel);
+    map
+}
+
+fn account_value(engine: &PaymentsEngine, acnt_id: u16) -> Dynamic {
+    let view = match engine.account(acnt_id) {
+        Some(view) => view,
+        None => return Dynamic::UNIT,
+    };
+    let mut map = Map::new();
+    map.insert("available".into(), Dynamic::from_float(view.available()));
+    map.insert("held".into(), Dynamic::from_float(view.held()));
+    map.insert("total".into(), Dynamic::from_float(view.total()));
+    map.insert("locked".into(), view.locked().into());
+    map.insert("status".into(), view.status().as_str().into());
+    if let Some(profile) = engine.client_profile(acnt_id) {
+        map.insert("kyc".into(), profile.kyc_verified.into());
+        let tags: rhai::Array = profile.tags.iter().cloned().map(Dynamic::from).collect();
+        map.insert("tags".into(), tags.into());
+    }
+    map.into()
+}
+
+/// Amount a script's map sets, digits past `PRECISION` places go the way the policy says
+fn script_amount(value: &Dynamic, policy: PrecisionPolicy) -> Result<TxnAmount, String> {
+    let float = value
+        .as_float()
+        .map_err(|_| format!("amount must be a float, not {}", value.type_name()))?;
+    match Amount::from_f64(float, policy) {
+        Ok(amount) => Ok(TxnAmount::new(amount)),
+        Err(AmountError::ExcessPrecision) => Err(format!("amount {} has too many places", float)),
+        Err(_) => Err(format!("amount {} is out of range", float)),
+    }
+}
+
+/// Copy of the txn with the fields a script's map changes, an error names what can't be changed
+fn modified(
+    txn: &Transaction,
+    changes: Map,
+    policy: PrecisionPolicy,
+) -> Result<Transaction, String> {
+    let mut txn = txn.clone();
+    for (field, value) in changes {
+        let type_name = value.type_name();
+        match (field.as_str(), &mut txn) {
+            ("amount", Transaction::Deposit(p_txn) | Transaction::Withdrawal(p_txn)) => {
+                p_txn.amount = script_amount(&value, policy)?
+            }
+            ("amount", Transaction::Refund(refund_txn)) => {
+                refund_txn.amount = script_amount(&value, policy)?
+            }
+            ("amount", Transaction::Release(release_txn)) => {
+                release_txn.amount = script_amount(&value, policy)?
+            }
+            ("memo", Transaction::Deposit(p_txn) | Transaction::Withdrawal(p_txn)) => {
+                p_txn.memo = match value.is_unit() {
+                    true => None,
+                    false => Some(
+                        value
+                            .into_string()
+                            .map_err(|_| format!("memo must be a string, not {}", type_name))?,
+                    ),
+                }
+            }
+            (field, _) => return Err(format!("{} can't be changed on this txn", field)),
+        }
+    }
+    Ok(txn)
+}
+
+impl TxnScript {
+    pub fn compile(source: &str) -> Result<Self, io::Error> {
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+        let ast = engine
+            .compile(source)
+            .map_err(|e| io::Error::new(ErrorKind::InvalidData, e.to_string()))?;
+        Ok(Self {
+            engine: Arc::new(engine),
+            ast: Arc::new(ast),
+            precision: PrecisionPolicy::default(),
+        })
+    }
+
+    /// Sets the policy for amounts the script sets, a run passes its `--precision`
+    pub fn with_precision(mut self, precision: PrecisionPolicy) -> Self {
+        self.precision = precision;
+        self
+    }
+
+    /// Compiles a script file, an error names the file
+    pub fn from_file(file_path: &str) -> Result<Self, io::Error> {
+        let source = fs::read_to_string(file_path)?;
+        Self::compile(&source)
+            .map_err(|e| io::Error::new(ErrorKind::InvalidData, format!("{}: {}", file_path, e)))
+    }
+
+    /// Runs the script for a txn, an error is the script failing or giving a value it can't
+    fn verdict(&self, engine: &PaymentsEngine, txn: &Transaction) -> Result<Verdict, String> {
+        let mut scope = Scope::new();
+        scope.push("txn", txn_map(txn));
+        scope.push("account", account_value(engine, txn.acnt_id()));
+        let value: Dynamic = self
+            .engine
+            .eval_ast_with_scope(&mut scope, &self.ast)
+            .map_err(|e| e.to_string())?;
+        if value.is_unit() {
+            return Ok(Verdict::Accept);
+        }
+        if let Ok(accept) = value.as_bool() {
+            return Ok(match accept {
+                true => Verdict::Accept,
+                false => Verdict::Reject,
+            });
+        }
+        let type_name = value.type_name();
+        match value.try_cast::<Map>() {
+            Some(changes) => Ok(Verdict::Modify(modified(txn, changes, self.precision)?)),
+            None => Err(format!(
+                "script gave {}, expected a bool, (), or a map",
+                type_name
+            )),
+        }
+    }
+}
+
+/// Runs the script ahead of applying each txn, a modified txn is applied here in place of the original
+impl Stage for TxnScript {
+    fn name(&self) -> &str {
+        "script"
+    }
+
+    fn before(&mut self, engine: &mut PaymentsEngine, s_txn: &SequencedTxn) -> Option<TxnOutcome> {
+        match self.verdict(engine, &s_txn.txn) {
+            Ok(Verdict::Accept) => None,
+            Ok(Verdict::Reject) => Some(TxnOutcome::Rejected(TxnErrors::RejectedByScript)),
+            Ok(Verdict::Modify(txn)) => Some(match engine.apply_txn(&txn) {
+                Ok(_) => TxnOutcome::Applied,
+                Err(e) => TxnOutcome::Rejected(e),
+            }),
+            Err(e) => {
+                log(
+                    Level::Warn,
+                    format_args!("Script failed on seq {}: {}", s_txn.seq, e),
+                );
+                Some(TxnOutcome::Rejected(TxnErrors::ScriptFailed))
+            }
+        }
+    }
+}
+
+impl PaymentsEngine {
+    /// Runs a script on every txn just before it's applied
+    pub fn set_script(&mut self, script: TxnScript) {
+        if let Err(script) = self.pipeline.insert_before("apply", Box::new(script)) {
+            self.pipeline.push(script);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TxnScript;
+    use crate::amount::{PrecisionPolicy, TxnAmount};
+    use crate::payments_engine::transactions::TxnErrors;
+    use crate::test_support::{deposit, pure_txn, withdrawal, EngineFixture};
+    use crate::transaction::{Channel, PureTxn, Transaction};
+
+    #[test]
+    fn tst_txn_script() {
+        let script = TxnScript::compile(
+            r#"
+            if txn.type == "deposit" && txn.channel == "card" && txn.amount > 500.0 {
+                return #{ amount: 500.0, memo: "capped" };
+            }
+            if txn.tx == 99 { return 1; }
+            if txn.tx == 98 { return #{ client: 2 }; }
+            !(txn.type == "withdrawal" && account != () && account.available - txn.amount < 10.0)
+            "#,
+        )
+        .unwrap();
+        let mut fixture = EngineFixture::new().deposit(1, 1, 20.0).build();
+        let engine = &mut fixture.engine;
+        engine.set_script(script);
+        assert_eq!(
+            engine.pipeline_mut().stage_names(),
+            [
+                "validate",
+                "dedup",
+                "risk_rules",
+                "script",
+                "apply",
+                "notify"
+            ]
+        );
+
+        assert!(engine
+            .process_txn(&Transaction::Deposit(PureTxn {
+                channel: Some(Channel::Card),
+                ..pure_txn(1, 2, 800.0)
+            }))
+            .is_ok());
+        assert_eq!(engine.account(1).unwrap().available(), 520.0);
+        let capped = engine.processed_txns.iter().last().unwrap();
+        assert!(matches!(&capped.txn, Transaction::Deposit(p_txn)
+            if p_txn.amount == TxnAmount::from_f64(500.0) && p_txn.memo.as_deref() == Some("capped")));
+        assert!(engine
+            .process_txn(&Transaction::Deposit(PureTxn {
+                channel: Some(Channel::Wire),
+                ..pure_txn(1, 3, 800.0)
+            }))
+            .is_ok());
+
+        assert_eq!(
+            engine.process_txn(&withdrawal(1, 4, 1315.0)),
+            Err(TxnErrors::RejectedByScript)
+        );
+        assert!(engine.process_txn(&withdrawal(1, 5, 1310.0)).is_ok());
+        assert_eq!(
+            engine.process_txn(&deposit(1, 99, 1.0)),
+            Err(TxnErrors::ScriptFailed)
+        );
+        assert_eq!(
+            engine.process_txn(&deposit(1, 98, 1.0)),
+            Err(TxnErrors::ScriptFailed)
+        );
+        assert_eq!(engine.account(1).unwrap().available(), 10.0);
+
+        assert!(TxnScript::compile("if {").is_err());
+        let mut looping = EngineFixture::new().build();
+        looping
+            .engine
+            .set_script(TxnScript::compile("loop {}").unwrap());
+        assert_eq!(
+            looping.engine.process_txn(&deposit(1, 1, 1.0)),
+            Err(TxnErrors::ScriptFailed)
+        );
+
+        // Amounts a script sets go through the run's precision policy, as amounts read do
+        let source = "if txn.tx == 2 { #{ amount: 1.23456 } }";
+        let mut truncating = EngineFixture::new().build();
+        truncating
+            .engine
+            .set_script(TxnScript::compile(source).unwrap());
+        assert!(truncating.engine.process_txn(&deposit(1, 2, 5.0)).is_ok());
+        assert_eq!(truncating.engine.account(1).unwrap().available(), 1.2345);
+        let mut rejecting = EngineFixture::new().build();
+        rejecting.engine.set_script(
+            TxnScript::compile(source)
+                .unwrap()
+                .with_precision(PrecisionPolicy::Reject),
+        );
+        assert_eq!(
+            rejecting.engine.process_txn(&deposit(1, 2, 5.0)),
+            Err(TxnErrors::ScriptFailed)
+        );
+    }
+}
@@ -0,0 +1,129 @@
+use super::savepoint::SavepointId;
+use super::transactions::TxnErrors;
+use super::PaymentsEngine;
+use crate::account::Account;
+use crate::transaction::Transaction;
+
+/// What applying a txn in a session did to its client's account
+#[derive(Debug, Clone, PartialEq)]
+pub struct SessionReceipt {
+    /// Sequence number the txn was processed under
+    pub seq: u64,
+    /// The client's account before the txn, None if it had none
+    pub balances_before: Option<Account>,
+    pub balances_after: Option<Account>,
+    /// Ok if the txn was applied or quietly dropped, e.g. as a retry, else why it was rejected
+    pub status: Result<(), TxnErrors>,
+}
+
+/// Txns applied through a session are undone together unless the session is committed
+/// Dropping a session without committing it aborts it
+#[derive(Debug)]
+pub struct Session<'a> {
+    engine: &'a mut PaymentsEngine,
+    savepoint: Option<SavepointId>,
+}
+
+impl Session<'_> {
+    /// Processes a txn, reporting the client's account before & after it
+    pub fn apply(&mut self, txn: &Transaction) -> SessionReceipt {
+        let acnt_id = txn.acnt_id();
+        let balances_before = self.engine.accounts.get(acnt_id).cloned();
+        let s_txn = self.engine.sequence_txn(txn.clone());
+        let status = self.engine.process_sequenced_txn(&s_txn);
+        SessionReceipt {
+            seq: s_txn.seq,
+            balances_before,
+            balances_after: self.engine.accounts.get(acnt_id).cloned(),
+            status,
+        }
+    }
+
+    /// Engine state including the session's uncommitted txns
+    pub fn engine(&self) -> &PaymentsEngine {
+        self.engine
+    }
+
+    /// Keeps every txn applied in the session
+    pub fn commit(mut self) {
+        if let Some(savepoint) = self.savepoint.take() {
+            // The session owns its savepoint, nothing else can release it
+            let _ = self.engine.release_savepoint(savepoint);
+        }
+    }
+
+    /// Undoes every txn applied in the session
+    pub fn abort(mut self) {
+        self.rollback();
+    }
+
+    fn rollback(&mut self) {
+        if let Some(savepoint) = self.savepoint.take() {
+            let _ = self.engine.rollback_to(savepoint);
+            let _ = self.engine.release_savepoint(savepoint);
+        }
+    }
+}
+
+impl Drop for Session<'_> {
+    fn drop(&mut self) {
+        self.rollback();
+    }
+}
+
+impl PaymentsEngine {
+    /// Starts a session, the engine can only be reached through it until it ends
+    /// Undoing a session has the limits of `rollback_to`
+    pub fn begin_session(&mut self) -> Session<'_> {
+        let savepoint = self.savepoint();
+        Session {
+            engine: self,
+            savepoint: Some(savepoint),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::payments_engine::{PaymentsEngine, TxnErrors};
+    use crate::test_support::{deposit, withdrawal};
+
+    #[test]
+    fn tst_session_receipts() {
+        let mut payments_engine = PaymentsEngine::new();
+        let mut session = payments_engine.begin_session();
+        let receipt = session.apply(&deposit(1, 1, 10.0));
+        assert_eq!(receipt.seq, 1);
+        assert_eq!(receipt.balances_before, None);
+        assert_eq!(
+            receipt.balances_after.map(|acnt| acnt.available().to_f64()),
+            Some(10.0)
+        );
+        assert_eq!(receipt.status, Ok(()));
+
+        let receipt = session.apply(&withdrawal(1, 2, 25.0));
+        assert_eq!(receipt.status, Err(TxnErrors::AccountLacksFunds));
+        assert_eq!(receipt.balances_before, receipt.balances_after);
+        session.commit();
+        assert_eq!(payments_engine.accounts[0].available().to_f64(), 10.0);
+
+        let mut session = payments_engine.begin_session();
+        session.apply(&withdrawal(1, 3, 4.0));
+        session.apply(&deposit(2, 4, 1.0));
+        assert_eq!(session.engine().accounts.len(), 2);
+        session.abort();
+        assert_eq!(payments_engine.accounts.len(), 1);
+        assert_eq!(payments_engine.accounts[0].available().to_f64(), 10.0);
+
+        {
+            let mut session = payments_engine.begin_session();
+            session.apply(&withdrawal(1, 3, 4.0));
+        }
+        assert_eq!(
+            payments_engine.accounts[0].available().to_f64(),
+            10.0,
+            "Dropped sessions should abort"
+        );
+        assert!(payments_engine.process_txn(&withdrawal(1, 3, 4.0)).is_ok());
+    }
+}
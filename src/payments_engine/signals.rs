@@ -0,0 +1,131 @@
+use super::PaymentsEngine;
+use crate::cli_io::CliOptions;
+use crate::diagnostics::{log, Level};
+use signal_hook::consts::{SIGUSR1, SIGUSR2};
+use signal_hook::SigId;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// How often a paused engine checks for the signal to resume
+const PAUSE_POLL: Duration = Duration::from_millis(100);
+
+/// Operator requests delivered as signals, acted on between records so state is never half applied
+/// SIGUSR1 logs a state summary, SIGUSR2 flushes outputs & pauses ingestion until the next SIGUSR2
+#[derive(Debug, Default)]
+pub(super) struct OperatorSignals {
+    summary: Arc<AtomicBool>,
+    pause: Arc<AtomicBool>,
+    registered: Vec<SigId>,
+}
+
+impl OperatorSignals {
+    /// Starts catching the signals, their default action would end the process
+    pub(super) fn register() -> Result<Self, io::Error> {
+        let mut signals = Self::default();
+        signals.registered = vec![
+            signal_hook::flag::register(SIGUSR1, Arc::clone(&signals.summary))?,
+            signal_hook::flag::register(SIGUSR2, Arc::clone(&signals.pause))?,
+        ];
+        Ok(signals)
+    }
+}
+
+impl Drop for OperatorSignals {
+    fn drop(&mut self) {
+        for sig_id in self.registered.drain(..) {
+            signal_hook::low_level::unregister(sig_id);
+        }
+    }
+}
+
+impl PaymentsEngine {
+    /// Acts on signals received since the last check, blocking while ingestion is paused
+    /// Accounts & the snapshot, when one is configured, are written before pausing so they can be inspected
+    pub(super) fn handle_operator_signals(
+        &mut self,
+        signals: &OperatorSignals,
+        cli_input: &CliOptions,
+    ) -> Result<(), io::Error> {
+        self.log_summary_if_asked(signals);
+        if !signals.pause.swap(false, Ordering::Relaxed) {
+            return Ok(());
+        }
+        self.write_accounts(cli_input);
+        self.save_snapshot_option(cli_input)?;
+        log(
+            Level::Info,
+            format_args!("Paused after seq {}, resume with SIGUSR2", self.last_seq),
+        );
+        while !signals.pause.swap(false, Ordering::Relaxed) {
+            self.log_summary_if_asked(signals);
+            thread::sleep(PAUSE_POLL);
+        }
+        log(Level::Info, format_args!("Resumed"));
+        Ok(())
+    }
+
+    fn log_summary_if_asked(&self, signals: &OperatorSignals) {
+        if signals.summary.swap(false, Ordering::Relaxed) {
+            log(Level::Info, format_args!("{}", self.state_summary()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::OperatorSignals;
+    use crate::cli_io::{parse_cli_args, CliCommand};
+    use crate::payments_engine::PaymentsEngine;
+    use crate::test::utils::_get_test_output_file;
+    use crate::test_support::deposit;
+
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn tst_operator_signals() {
+        let f_output = _get_test_output_file("tst_operator_signals.csv");
+        let _ = std::fs::remove_file(&f_output);
+        let args: Vec<String> = ["in.csv", "--output", &f_output]
+            .iter()
+            .map(|a| a.to_string())
+            .collect();
+        let cli_options = match parse_cli_args(&args) {
+            Ok(CliCommand::Process(cli_options)) => cli_options,
+            _ => panic!("Should parse as process command"),
+        };
+        let mut payments_engine = PaymentsEngine::new();
+        payments_engine.process_txn(&deposit(1, 1, 2.0)).unwrap();
+
+        // Signals are only flags until handled, nothing is written without a pause
+        let signals = OperatorSignals::default();
+        signals.summary.store(true, Ordering::Relaxed);
+        assert!(payments_engine
+            .handle_operator_signals(&signals, &cli_options)
+            .is_ok());
+        assert!(!signals.summary.load(Ordering::Relaxed));
+        assert!(!std::path::Path::new(&f_output).exists());
+
+        // A pause writes outputs & holds until the next pause signal
+        let pause = Arc::clone(&signals.pause);
+        pause.store(true, Ordering::Relaxed);
+        let resumer = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(200));
+            pause.store(true, Ordering::Relaxed);
+        });
+        assert!(payments_engine
+            .handle_operator_signals(&signals, &cli_options)
+            .is_ok());
+        resumer.join().unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&f_output).unwrap(),
+            "client,available,held,total,locked\n1,2.0000,0.0000,2.0000,false\n"
+        );
+        assert!(!signals.pause.load(Ordering::Relaxed));
+    }
+}
@@ -0,0 +1,262 @@
+use super::config::ChargebackPolicy;
+use super::transactions::{dispute_hold, overflowed, TxnErrors};
+use super::PaymentsEngine;
+use crate::account::Account;
+use crate::amount::{Amount, TxnAmount};
+use crate::transaction::{AdminAction, RefTxn, Transaction};
+
+/// Account state a transaction would leave behind if it were processed
+#[derive(Debug, Clone, PartialEq)]
+pub struct TxnReceipt {
+    pub acnt_id: u16,
+    pub available: f64,
+    pub held: f64,
+    pub total: f64,
+    pub locked: bool,
+}
+
+/// Projected account fields, finalized into a receipt once the txn is applied
+/// Funds are moved in minor units, overflowing where processing would
+struct Projection {
+    acnt_id: u16,
+    available: Amount,
+    held: Amount,
+    locked_by_chargeback: bool,
+    admin_hold: bool,
+}
+
+impl Projection {
+    fn from_account(acnt: &Account) -> Self {
+        Self {
+            acnt_id: acnt.id,
+            available: acnt.available().amount(),
+            held: acnt.held().amount(),
+            locked_by_chargeback: acnt.locked_by_chargeback,
+            admin_hold: acnt.admin_hold,
+        }
+    }
+
+    /// Moves an amount into available, or out of it when `credit` is false
+    fn shift_available(&mut self, amount: TxnAmount, credit: bool) -> Result<(), TxnErrors> {
+        self.available = shift(self.available, amount, credit)?;
+        Ok(())
+    }
+
+    /// Moves an amount into held, or out of it when `credit` is false
+    fn shift_held(&mut self, amount: TxnAmount, credit: bool) -> Result<(), TxnErrors> {
+        self.held = shift(self.held, amount, credit)?;
+        Ok(())
+    }
+
+    fn into_receipt(self) -> Result<TxnReceipt, TxnErrors> {
+        let total = self.available.checked_add(self.held).map_err(overflowed)?;
+        Ok(TxnReceipt {
+            acnt_id: self.acnt_id,
+            available: self.available.to_f64(),
+            held: self.held.to_f64(),
+            total: total.to_f64(),
+            locked: self.locked_by_chargeback || self.admin_hold,
+        })
+    }
+}
+
+/// Adds an amount to a projected balance, or subtracts it when `credit` is false
+fn shift(balance: Amount, amount: TxnAmount, credit: bool) -> Result<Amount, TxnErrors> {
+    match credit {
+        true => balance.checked_add(amount.amount()),
+        false => balance.checked_sub(amount.amount()),
+    }
+    .map_err(overflowed)
+}
+
+impl PaymentsEngine {
+    /// Computes the result of processing a transaction without changing engine state
+    /// Runs the validators processing runs, after the risk rules, so errors match
+    /// Duplicate ids are checked against stored ids & the registry, probabilistic duplicate stores aren't read
+    pub fn simulate_txn(&self, txn: &Transaction) -> Result<TxnReceipt, TxnErrors> {
+        if self.is_quarantined(txn) {
+            return Err(TxnErrors::AccountUnderReview);
+        }
+        if self.is_anomalous(txn) {
+            return Err(TxnErrors::AnomalousAmount);
+        }
+        self.validate_txn(txn)?;
+        let acnt = self.accounts.get(txn.acnt_id());
+
+        let projection = match txn {
+            Transaction::Deposit(p_txn) => {
+                let mut projection = match acnt {
+                    Some(acnt) => Projection::from_account(acnt),
+                    None => Projection {
+                        acnt_id: p_txn.acnt_id,
+                        available: Amount::default(),
+                        held: Amount::default(),
+                        locked_by_chargeback: false,
+                        admin_hold: false,
+                    },
+                };
+                projection.shift_available(p_txn.amount, true)?;
+                projection
+            }
+            Transaction::Withdrawal(p_txn) => {
+                let mut projection =
+                    Projection::from_account(acnt.ok_or(TxnErrors::AccountDoesNotExist)?);
+                projection.shift_available(p_txn.amount, false)?;
+                if self.needs_approval(p_txn.amount) {
+                    projection.shift_held(p_txn.amount, true)?;
+                }
+                projection
+            }
+            Transaction::Dispute(ref_txn)
+            | Transaction::Resolve(ref_txn)
+            | Transaction::Chargeback(ref_txn) => self.simulate_ref_txn(txn, ref_txn)?,
+            Transaction::Refund(refund_txn) => {
+                let (acnt_key, _) = self.check_refund(refund_txn)?;
+                let mut projection = Projection::from_account(&self.accounts[acnt_key]);
+                projection.shift_available(refund_txn.amount, false)?;
+                projection
+            }
+            Transaction::Release(release_txn) => {
+                let (acnt_key, _) = self.check_release(release_txn)?;
+                let mut projection = Projection::from_account(&self.accounts[acnt_key]);
+                projection.shift_held(release_txn.amount, false)?;
+                projection.shift_available(release_txn.amount, true)?;
+                projection
+            }
+            Transaction::Admin(admin_txn) => {
+                let mut projection =
+                    Projection::from_account(acnt.ok_or(TxnErrors::AccountDoesNotExist)?);
+                match admin_txn.action {
+                    AdminAction::SetHold => projection.admin_hold = true,
+                    AdminAction::ClearHold => projection.admin_hold = false,
+                    AdminAction::Approve | AdminAction::Deny => {
+                        let amount = self.pending_for(admin_txn)?.amount;
+                        projection.shift_held(amount, false)?;
+                        if admin_txn.action == AdminAction::Deny {
+                            projection.shift_available(amount, true)?;
+                        }
+                    }
+                }
+                projection
+            }
+        };
+        projection.into_receipt()
+    }
+
+    /// Projects the funds a validated dispute, resolve, or chargeback would move
+    fn simulate_ref_txn(
+        &self,
+        txn: &Transaction,
+        ref_txn: &RefTxn,
+    ) -> Result<Projection, TxnErrors> {
+        let (acnt_key, txn_key) = self.get_ref_txn_keys(ref_txn)?;
+        let acnt = &self.accounts[acnt_key];
+        let referenced = match &self.processed_txns[txn_key].txn {
+            Transaction::Withdrawal(p_txn) | Transaction::Deposit(p_txn) => p_txn,
+            _ => return Err(TxnErrors::RefTxnNotDisputable),
+        };
+        let held = self
+            .held_amounts
+            .get(&ref_txn.ref_id)
+            .copied()
+            .unwrap_or(referenced.amount);
+
+        let mut projection = Projection::from_account(acnt);
+        match txn {
+            Transaction::Dispute(_) => {
+                let policy = self.config.withdrawn_funds_dispute;
+                let disputable = self.disputable(ref_txn.ref_id, referenced.amount)?;
+                let hold = dispute_hold(policy, acnt.available(), disputable);
+                projection.shift_available(hold, false)?;
+                projection.shift_held(hold, true)?;
+            }
+            Transaction::Resolve(_) => {
+                projection.shift_held(held, false)?;
+                projection.shift_available(held, true)?;
+            }
+            _ => {
+                projection.shift_held(held, false)?;
+                if self.config.chargeback_policy == ChargebackPolicy::Freeze {
+                    projection.locked_by_chargeback = true;
+                }
+            }
+        }
+        Ok(projection)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TxnReceipt;
+    use crate::payments_engine::transactions::TxnErrors;
+    use crate::payments_engine::PaymentsEngine;
+    use crate::test_support::{deposit, withdrawal};
+    use crate::transaction::{AdminAction, AdminTxn, RefTxn, Transaction};
+
+    #[test]
+    fn tst_simulate_txn() {
+        let mut payments_engine = PaymentsEngine::new();
+        assert_eq!(
+            payments_engine.simulate_txn(&deposit(1, 1, 10.0)),
+            Ok(TxnReceipt {
+                acnt_id: 1,
+                available: 10.0,
+                held: 0.0,
+                total: 10.0,
+                locked: false,
+            })
+        );
+        assert!(
+            payments_engine.accounts.is_empty(),
+            "Simulating should not create accounts"
+        );
+
+        let _ = payments_engine.process_txn(&deposit(1, 1, 10.0));
+        assert_eq!(
+            payments_engine.simulate_txn(&deposit(1, 1, 10.0)),
+            Err(TxnErrors::TxnIdAlreadyExists)
+        );
+        assert_eq!(
+            payments_engine.simulate_txn(&withdrawal(1, 2, 11.0)),
+            Err(TxnErrors::AccountLacksFunds)
+        );
+        let receipt = payments_engine
+            .simulate_txn(&withdrawal(1, 2, 4.0))
+            .unwrap();
+        assert_eq!((receipt.available, receipt.total), (6.0, 6.0));
+
+        let dispute = RefTxn {
+            ref_id: 1,
+            acnt_id: 1,
+        };
+        let receipt = payments_engine
+            .simulate_txn(&Transaction::Dispute(dispute.clone()))
+            .unwrap();
+        assert_eq!((receipt.available, receipt.held), (0.0, 10.0));
+        assert_eq!(
+            payments_engine.simulate_txn(&Transaction::Chargeback(dispute.clone())),
+            Err(TxnErrors::TxnMustBeDisputed)
+        );
+
+        let _ = payments_engine.process_txn(&Transaction::Dispute(dispute.clone()));
+        let receipt = payments_engine
+            .simulate_txn(&Transaction::Chargeback(dispute.clone()))
+            .unwrap();
+        assert_eq!(
+            (receipt.held, receipt.total, receipt.locked),
+            (0.0, 0.0, true)
+        );
+        assert!(
+            !payments_engine.accounts[0].is_locked(),
+            "Simulating should not change account state"
+        );
+
+        let hold = Transaction::Admin(AdminTxn {
+            instr_id: 3,
+            acnt_id: 1,
+            action: AdminAction::SetHold,
+        });
+        assert!(payments_engine.simulate_txn(&hold).unwrap().locked);
+        assert_eq!(payments_engine.processed_txns.len(), 2);
+    }
+}
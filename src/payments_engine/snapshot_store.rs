@@ -0,0 +1,623 @@
+use super::account_store::AccountStore;
+use super::live_snapshot::AccountSnapshot;
+use super::PaymentsEngine;
+use crate::account::Account;
+use crate::amount::{Available, Held};
+use crate::diagnostics::{log, Level};
+use csv::{ReaderBuilder, Writer};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::thread;
+
+/// Index of a sharded snapshot, written last so a snapshot without one is incomplete
+const MANIFEST_FILE: &str = "manifest.json";
+/// Subdirectory of a snapshot holding the deltas written since it, in the order they apply
+const DELTAS_DIR: &str = "deltas";
+/// Suffix of the sibling directory a snapshot or delta is written to before it's renamed into place
+const STAGING_SUFFIX: &str = ".tmp";
+/// Suffix an existing snapshot is moved aside to while its replacement is renamed into place
+const REPLACED_SUFFIX: &str = ".replaced";
+
+/// One shard as recorded in the manifest
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ShardEntry {
+    pub file: String,
+    pub accounts: usize,
+    /// Hex sha256 of the shard file
+    pub sha256: String,
+}
+
+/// Describes every shard of a snapshot, accounts are placed in shard `client % shards.len()`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SnapshotManifest {
+    pub seq: u64,
+    pub accounts: usize,
+    pub shards: Vec<ShardEntry>,
+    /// Set on a delta, the seq of the snapshot or delta it applies on top of
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base_seq: Option<u64>,
+    /// Clients a delta drops, e.g. collected as inactive since its base
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub removed: Vec<u16>,
+}
+
+/// Accounts changed & removed between two snapshots, saved instead of a full snapshot when few accounts change
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SnapshotDelta {
+    pub base_seq: u64,
+    pub seq: u64,
+    /// Accounts created or changed since the base, as of `seq`
+    pub changed: Vec<Account>,
+    pub removed: Vec<u16>,
+}
+
+impl SnapshotDelta {
+    /// True if applying the delta changes nothing, not even the sequence number
+    pub fn is_empty(&self) -> bool {
+        self.changed.is_empty() && self.removed.is_empty() && self.seq == self.base_seq
+    }
+
+    /// Writes the delta as the next link of the chain under the snapshot in `dir`
+    pub fn save_sharded(&self, dir: &str, shards: usize) -> Result<SnapshotManifest, io::Error> {
+        let deltas = Path::new(dir).join(DELTAS_DIR);
+        fs::create_dir_all(&deltas)?;
+        let mut links = 0;
+        for entry in fs::read_dir(&deltas)? {
+            if !is_staging(&entry?.path()) {
+                links += 1;
+            }
+        }
+        let changed: Vec<&Account> = self.changed.iter().collect();
+        write_sharded(
+            &deltas.join(format!("delta-{:06}", links)),
+            &changed,
+            shards,
+            |manifest| {
+                manifest.base_seq = Some(self.base_seq);
+                manifest.seq = self.seq;
+                manifest.removed = self.removed.clone();
+            },
+        )
+    }
+}
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, msg)
+}
+
+/// `dir` with `suffix` appended to its last component, e.g. `snap` to `snap.tmp`
+fn sibling(dir: &Path, suffix: &str) -> Result<PathBuf, io::Error> {
+    let name = dir.file_name().ok_or_else(|| {
+        io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("{} doesn't name a snapshot directory", dir.display()),
+        )
+    })?;
+    let mut name = name.to_os_string();
+    name.push(suffix);
+    Ok(dir.with_file_name(name))
+}
+
+fn is_staging(path: &Path) -> bool {
+    path.file_name()
+        .is_some_and(|name| name.to_string_lossy().ends_with(STAGING_SUFFIX))
+}
+
+fn remove_stale(dir: &Path) -> Result<(), io::Error> {
+    match fs::remove_dir_all(dir) {
+        Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+fn sha256_hex(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Serializes accounts as csv, balances keep full precision so a load restores them exactly
+fn shard_bytes(accounts: &[&Account]) -> Result<Vec<u8>, io::Error> {
+    let mut wtr = Writer::from_writer(vec![]);
+    wtr.write_record([
+        "client",
+        "available",
+        "held",
+        "locked_by_chargeback",
+        "admin_hold",
+    ])?;
+    for acnt in accounts {
+        wtr.write_record([
+            acnt.id.to_string(),
+            acnt.available().to_f64().to_string(),
+            acnt.held().to_f64().to_string(),
+            acnt.locked_by_chargeback.to_string(),
+            acnt.admin_hold.to_string(),
+        ])?;
+    }
+    wtr.into_inner().map_err(|e| e.into_error())
+}
+
+/// Reads a shard, checking it against its manifest entry & that it only holds its own clients
+fn read_shard(
+    dir: &Path,
+    indx: usize,
+    shards: usize,
+    entry: &ShardEntry,
+) -> Result<Vec<Account>, io::Error> {
+    let bytes = fs::read(dir.join(&entry.file))?;
+    if sha256_hex(&bytes) != entry.sha256 {
+        return Err(invalid_data(format!(
+            "Shard {} fails its checksum",
+            entry.file
+        )));
+    }
+    let mut accounts = vec![];
+    for result in ReaderBuilder::new().from_reader(bytes.as_slice()).records() {
+        let record = result.map_err(io::Error::other)?;
+        let field = |i: usize| record.get(i).unwrap_or("");
+        let malformed = || invalid_data(format!("Malformed account in shard {}", entry.file));
+        let acnt = Account::with_balances(
+            field(0).parse().map_err(|_| malformed())?,
+            Available::from_f64(field(1).parse().map_err(|_| malformed())?),
+            Held::from_f64(field(2).parse().map_err(|_| malformed())?),
+        )
+        .with_locks(
+            field(3).parse().map_err(|_| malformed())?,
+            field(4).parse().map_err(|_| malformed())?,
+        );
+        if acnt.id as usize % shards != indx {
+            return Err(invalid_data(format!(
+                "Client {} doesn't belong in shard {}",
+                acnt.id, entry.file
+            )));
+        }
+        accounts.push(acnt);
+    }
+    if accounts.len() != entry.accounts {
+        return Err(invalid_data(format!(
+            "Shard {} holds {} accounts, the manifest expects {}",
+            entry.file,
+            accounts.len(),
+            entry.accounts
+        )));
+    }
+    Ok(accounts)
+}
+
+/// Writes accounts to `dir` as `shards` files in parallel, then the manifest indexing them
+/// `finish` fills in what the shards alone don't tell, e.g. the sequence number
+/// Everything is written to a sibling staging directory which replaces `dir` once the manifest is
+/// written, so a crash part way leaves whatever `dir` held before intact
+fn write_sharded(
+    dir: &Path,
+    accounts: &[&Account],
+    shards: usize,
+    finish: impl FnOnce(&mut SnapshotManifest),
+) -> Result<SnapshotManifest, io::Error> {
+    let shards = shards.max(1);
+    let (staging, replaced) = (
+        sibling(dir, STAGING_SUFFIX)?,
+        sibling(dir, REPLACED_SUFFIX)?,
+    );
+    remove_stale(&staging)?;
+    remove_stale(&replaced)?;
+    fs::create_dir_all(&staging)?;
+    let manifest = write_staged(&staging, accounts, shards, finish)?;
+
+    // A rename can't replace a directory with contents, so the old one is moved aside first
+    match fs::rename(dir, &replaced) {
+        Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    fs::rename(&staging, dir)?;
+    remove_stale(&replaced)?;
+    Ok(manifest)
+}
+
+/// Writes the shards then the manifest to the empty staging directory `dir`
+fn write_staged(
+    dir: &Path,
+    accounts: &[&Account],
+    shards: usize,
+    finish: impl FnOnce(&mut SnapshotManifest),
+) -> Result<SnapshotManifest, io::Error> {
+    let mut sharded: Vec<Vec<&Account>> = vec![vec![]; shards];
+    for acnt in accounts {
+        sharded[acnt.id as usize % shards].push(acnt);
+    }
+    let entries = thread::scope(|scope| {
+        let handles: Vec<_> = sharded
+            .iter()
+            .enumerate()
+            .map(|(indx, accounts)| {
+                scope.spawn(move || -> Result<ShardEntry, io::Error> {
+                    let file = format!("shard-{:04}.csv", indx);
+                    let bytes = shard_bytes(accounts)?;
+                    fs::write(dir.join(&file), &bytes)?;
+                    Ok(ShardEntry {
+                        file,
+                        accounts: accounts.len(),
+                        sha256: sha256_hex(&bytes),
+                    })
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| {
+                handle
+                    .join()
+                    .map_err(|_| io::Error::other("Shard writer panicked"))?
+            })
+            .collect::<Result<Vec<_>, io::Error>>()
+    })?;
+
+    let mut manifest = SnapshotManifest {
+        seq: 0,
+        accounts: accounts.len(),
+        shards: entries,
+        base_seq: None,
+        removed: vec![],
+    };
+    finish(&mut manifest);
+    fs::write(
+        dir.join(MANIFEST_FILE),
+        serde_json::to_vec_pretty(&manifest)?,
+    )?;
+    Ok(manifest)
+}
+
+fn read_manifest(dir: &Path) -> Result<SnapshotManifest, io::Error> {
+    Ok(serde_json::from_slice(&fs::read(dir.join(MANIFEST_FILE))?)?)
+}
+
+/// Reads the accounts of a manifest's shards in parallel
+/// Errors if a shard fails its checksum or count, or a client appears twice
+fn read_sharded(dir: &Path, manifest: &SnapshotManifest) -> Result<AccountStore, io::Error> {
+    let shards = manifest.shards.len();
+    let loaded = thread::scope(|scope| {
+        let handles: Vec<_> = manifest
+            .shards
+            .iter()
+            .enumerate()
+            .map(|(indx, entry)| scope.spawn(move || read_shard(dir, indx, shards, entry)))
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| {
+                handle
+                    .join()
+                    .map_err(|_| io::Error::other("Shard reader panicked"))?
+            })
+            .collect::<Result<Vec<_>, io::Error>>()
+    })?;
+
+    let accounts: Vec<Account> = loaded.into_iter().flatten().collect();
+    if accounts.len() != manifest.accounts {
+        return Err(invalid_data(format!(
+            "Snapshot holds {} accounts, the manifest expects {}",
+            accounts.len(),
+            manifest.accounts
+        )));
+    }
+    AccountStore::from_accounts(accounts)
+        .map_err(|acnt_id| invalid_data(format!("Client {} appears twice", acnt_id)))
+}
+
+/// Complete deltas saved under the snapshot in `dir`, in the order they apply
+/// A delta without a manifest was cut short while being written & never became part of the chain
+fn read_deltas(dir: &Path) -> Result<Vec<SnapshotDelta>, io::Error> {
+    let mut links: Vec<_> = match fs::read_dir(dir.join(DELTAS_DIR)) {
+        Ok(entries) => entries
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<_, io::Error>>()?,
+        Err(e) if e.kind() == ErrorKind::NotFound => vec![],
+        Err(e) => return Err(e),
+    };
+    links.retain(|link| !is_staging(link));
+    links.sort();
+    let mut deltas = vec![];
+    for link in links {
+        let manifest = match read_manifest(&link) {
+            Ok(manifest) => manifest,
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                log(
+                    Level::Warn,
+                    format_args!("Skipping incomplete snapshot delta {}", link.display()),
+                );
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        let base_seq = manifest.base_seq.ok_or_else(|| {
+            invalid_data(format!(
+                "{} is a full snapshot, not a delta",
+                link.display()
+            ))
+        })?;
+        let changed = read_sharded(&link, &manifest)?;
+        deltas.push(SnapshotDelta {
+            base_seq,
+            seq: manifest.seq,
+            changed: changed.iter().cloned().collect(),
+            removed: manifest.removed,
+        });
+    }
+    Ok(deltas)
+}
+
+impl AccountSnapshot {
+    /// Writes the snapshot to `dir` as `shards` files in parallel, then the manifest indexing them
+    /// The snapshot replaces `dir` whole, so deltas saved under an earlier one, which don't apply on
+    /// top of this one, are dropped with it
+    pub fn save_sharded(&self, dir: &str, shards: usize) -> Result<SnapshotManifest, io::Error> {
+        let accounts: Vec<&Account> = self.accounts.iter().collect();
+        write_sharded(Path::new(dir), &accounts, shards, |manifest| {
+            manifest.seq = self.seq
+        })
+    }
+
+    /// Reads a snapshot written by `save_sharded`, folding in the deltas saved under it since
+    /// Errors if the manifest is missing, a shard fails its checksum or count, a client appears twice,
+    /// or a delta doesn't apply on top of the ones before it
+    pub fn load_sharded(dir: &str) -> Result<Self, io::Error> {
+        let dir = Path::new(dir);
+        let manifest = read_manifest(dir)?;
+        if manifest.base_seq.is_some() {
+            return Err(invalid_data(format!(
+                "{} is a delta, not a full snapshot",
+                dir.display()
+            )));
+        }
+        let mut snapshot = Self::new(manifest.seq, read_sharded(dir, &manifest)?);
+        for delta in read_deltas(dir)? {
+            snapshot.apply_delta(delta)?;
+        }
+        Ok(snapshot)
+    }
+
+    /// Accounts changed & removed since `base`, a snapshot of the same engine taken earlier
+    pub fn delta_since(&self, base: &AccountSnapshot) -> SnapshotDelta {
+        let mut changed: Vec<Account> = self
+            .accounts
+            .iter()
+            .filter(|acnt| base.get(acnt.id) != Some(acnt))
+            .cloned()
+            .collect();
+        changed.sort_unstable_by_key(|acnt| acnt.id);
+        let mut removed: Vec<u16> = base
+            .accounts
+            .iter()
+            .filter(|acnt| self.get(acnt.id).is_none())
+            .map(|acnt| acnt.id)
+            .collect();
+        removed.sort_unstable();
+        SnapshotDelta {
+            base_seq: base.seq,
+            seq: self.seq,
+            changed,
+            removed,
+        }
+    }
+
+    /// Brings the snapshot forward by a delta taken on top of it
+    pub fn apply_delta(&mut self, delta: SnapshotDelta) -> Result<(), io::Error> {
+        if delta.base_seq != self.seq {
+            return Err(invalid_data(format!(
+                "Delta from seq {} doesn't apply on top of seq {}",
+                delta.base_seq, self.seq
+            )));
+        }
+        for acnt_id in delta.removed {
+            self.accounts.restore(acnt_id, None);
+        }
+        for acnt in delta.changed {
+            self.accounts.restore(acnt.id, Some(acnt));
+        }
+        self.seq = delta.seq;
+        Ok(())
+    }
+}
+
+/// Folds the deltas under the snapshot in `dir` into a new full snapshot, in as many shards as the old one
+/// unless `shards` is given. The new snapshot is written beside the old & swapped in once complete
+pub fn compact_snapshots(dir: &str, shards: Option<usize>) -> Result<SnapshotManifest, io::Error> {
+    let shards = match shards {
+        Some(shards) => shards,
+        None => read_manifest(Path::new(dir))?.shards.len(),
+    };
+    AccountSnapshot::load_sharded(dir)?.save_sharded(dir, shards)
+}
+
+/// Last snapshot saved to or restored from a directory, deltas saved there hold what changed since
+#[derive(Debug)]
+pub(super) struct SnapshotCheckpoint {
+    dir: String,
+    snapshot: AccountSnapshot,
+}
+
+impl PaymentsEngine {
+    /// Restores accounts from the snapshot in `dir`, with `deltas` ones saved there later only hold what changes from here
+    pub(super) fn restore_checkpoint(&mut self, dir: &str, deltas: bool) -> Result<(), io::Error> {
+        let snapshot = AccountSnapshot::load_sharded(dir)?;
+        if deltas {
+            self.snapshot_checkpoint = Some(SnapshotCheckpoint {
+                dir: dir.to_string(),
+                snapshot: AccountSnapshot::new(snapshot.seq, snapshot.accounts.clone()),
+            });
+        }
+        self.restore_accounts(snapshot);
+        Ok(())
+    }
+
+    /// Saves accounts to `dir`, as a delta on top of the checkpoint if `deltas` & the checkpoint is of `dir`
+    /// A full snapshot is saved the first time, or when the last checkpoint was of another directory
+    pub(super) fn save_checkpoint(
+        &mut self,
+        dir: &str,
+        shards: usize,
+        deltas: bool,
+    ) -> Result<(), io::Error> {
+        let snapshot = self.snapshot();
+        match &self.snapshot_checkpoint {
+            Some(checkpoint) if deltas && checkpoint.dir == dir => {
+                let delta = snapshot.delta_since(&checkpoint.snapshot);
+                if !delta.is_empty() {
+                    delta.save_sharded(dir, shards)?;
+                }
+            }
+            _ => {
+                snapshot.save_sharded(dir, shards)?;
+            }
+        }
+        // Without deltas nothing is diffed against the checkpoint, so there's no need to keep a copy
+        self.snapshot_checkpoint = match deltas {
+            true => Some(SnapshotCheckpoint {
+                dir: dir.to_string(),
+                snapshot,
+            }),
+            false => None,
+        };
+        Ok(())
+    }
+
+    /// Replaces account state with a snapshot's, later txns are sequenced after it
+    /// Txn history isn't part of a snapshot, disputes of txns from before it are rejected
+    pub fn restore_accounts(&mut self, snapshot: AccountSnapshot) {
+        let (seq, accounts) = snapshot.into_parts();
+        self.accounts.replace(accounts);
+        self.last_seq = self.last_seq.max(seq);
+        self.sequencer.observe(seq);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::compact_snapshots;
+    use crate::payments_engine::config::GcPolicy;
+    use crate::payments_engine::live_snapshot::AccountSnapshot;
+    use crate::payments_engine::PaymentsEngine;
+    use crate::test::utils::_get_test_output_file;
+    use crate::test_support::{deposit, withdrawal};
+
+    #[test]
+    fn tst_sharded_snapshot() {
+        let mut payments_engine = PaymentsEngine::new();
+        for txn_id in 0..50 {
+            let _ = payments_engine.process_txn(&deposit((txn_id % 13) as u16, txn_id, 0.1));
+        }
+        let dir = _get_test_output_file("tst_sharded_snapshot");
+        let manifest = payments_engine.snapshot().save_sharded(&dir, 4).unwrap();
+        assert_eq!(
+            (manifest.seq, manifest.accounts, manifest.shards.len()),
+            (50, 13, 4)
+        );
+
+        // A save cut short leaves only its staging directory behind, the last snapshot still loads
+        let staging = format!("{}.tmp", dir);
+        std::fs::create_dir_all(&staging).unwrap();
+        std::fs::write(format!("{}/shard-0000.csv", staging), "client\n").unwrap();
+        assert_eq!(AccountSnapshot::load_sharded(&dir).unwrap().seq, 50);
+        assert!(payments_engine.snapshot().save_sharded(&dir, 4).is_ok());
+        assert!(!std::path::Path::new(&staging).exists());
+        assert!(!std::path::Path::new(&format!("{}.replaced", dir)).exists());
+
+        let restored = AccountSnapshot::load_sharded(&dir).unwrap();
+        assert_eq!(restored.seq, 50);
+        assert_eq!(restored.accounts.len(), 13);
+        for acnt in payments_engine.accounts.iter() {
+            assert_eq!(
+                restored.get(acnt.id),
+                Some(acnt),
+                "Balances should restore exactly"
+            );
+        }
+
+        let mut recovered = PaymentsEngine::new();
+        recovered.restore_accounts(restored);
+        assert!(recovered.process_txn(&deposit(1, 50, 1.0)).is_ok());
+        assert_eq!(recovered.processed_txns[0].seq, 51);
+
+        // Tampering with any shard is caught on load
+        let shard = format!("{}/shard-0001.csv", dir);
+        let contents = std::fs::read_to_string(&shard).unwrap();
+        std::fs::write(&shard, contents.replacen("false", "true", 1)).unwrap();
+        assert!(AccountSnapshot::load_sharded(&dir).is_err());
+    }
+
+    #[test]
+    fn tst_snapshot_deltas() {
+        let mut payments_engine = PaymentsEngine::new();
+        for txn_id in 0..30 {
+            let _ = payments_engine.process_txn(&deposit((txn_id % 10) as u16, txn_id, 1.0));
+        }
+        let dir = _get_test_output_file("tst_snapshot_deltas");
+        let _ = std::fs::remove_dir_all(&dir);
+        assert!(payments_engine.save_checkpoint(&dir, 3, true).is_ok());
+
+        // Client 2 is emptied & collected, 4 changes, & 11 is new
+        let withdrawal = withdrawal(2, 30, 3.0);
+        assert!(payments_engine.process_txn(&withdrawal).is_ok());
+        assert!(payments_engine.process_txn(&deposit(4, 31, 2.5)).is_ok());
+        assert!(payments_engine.process_txn(&deposit(11, 32, 1.0)).is_ok());
+        let policy = GcPolicy {
+            inactive_for: 2,
+            archive_path: None,
+        };
+        assert_eq!(payments_engine.gc_accounts(&policy).unwrap(), 1);
+        let delta = payments_engine
+            .snapshot()
+            .delta_since(&AccountSnapshot::load_sharded(&dir).unwrap());
+        assert_eq!((delta.base_seq, delta.seq), (30, 33));
+        assert_eq!(
+            delta.changed.iter().map(|acnt| acnt.id).collect::<Vec<_>>(),
+            [4, 11]
+        );
+        assert_eq!(delta.removed, [2]);
+        assert!(payments_engine.save_checkpoint(&dir, 3, true).is_ok());
+        // Saving again without changes doesn't add a link
+        assert!(payments_engine.save_checkpoint(&dir, 3, true).is_ok());
+        assert!(payments_engine.process_txn(&deposit(5, 33, 1.0)).is_ok());
+        assert!(payments_engine.save_checkpoint(&dir, 3, true).is_ok());
+        let links = std::fs::read_dir(format!("{}/deltas", dir))
+            .unwrap()
+            .count();
+        assert_eq!(links, 2);
+
+        let loaded = AccountSnapshot::load_sharded(&dir).unwrap();
+        assert_eq!(loaded.seq, 34);
+        assert_eq!(loaded.accounts.len(), payments_engine.accounts.len());
+        for acnt in payments_engine.accounts.iter() {
+            assert_eq!(loaded.get(acnt.id), Some(acnt));
+        }
+        assert!(loaded.get(2).is_none());
+
+        // An incomplete last link is skipped, a link which doesn't follow the chain is refused
+        std::fs::create_dir_all(format!("{}/deltas/delta-000002", dir)).unwrap();
+        assert_eq!(AccountSnapshot::load_sharded(&dir).unwrap().seq, 34);
+        let manifest = format!("{}/deltas/delta-000001/manifest.json", dir);
+        let contents = std::fs::read_to_string(&manifest).unwrap();
+        std::fs::write(
+            &manifest,
+            contents.replace("\"base_seq\": 33", "\"base_seq\": 32"),
+        )
+        .unwrap();
+        assert!(AccountSnapshot::load_sharded(&dir).is_err());
+        std::fs::write(&manifest, contents).unwrap();
+
+        let manifest = compact_snapshots(&dir, None).unwrap();
+        assert_eq!((manifest.seq, manifest.shards.len()), (34, 3));
+        assert!(manifest.base_seq.is_none());
+        assert!(!std::path::Path::new(&format!("{}/deltas", dir)).exists());
+        let compacted = AccountSnapshot::load_sharded(&dir).unwrap();
+        assert_eq!(compacted.accounts.len(), loaded.accounts.len());
+        for acnt in loaded.accounts.iter() {
+            assert_eq!(compacted.get(acnt.id), Some(acnt));
+        }
+    }
+}
@@ -0,0 +1,116 @@
+use super::PaymentsEngine;
+use crate::amount::format_minor_units;
+use sha2::{Digest, Sha256};
+use std::fmt;
+
+/// Headline figures of engine state & a hash of every balance, for operators checking a running engine
+/// Equal hashes mean equal balances, e.g. between a primary & a replica fed the same records
+#[derive(Debug, Clone, PartialEq)]
+pub struct StateSummary {
+    pub last_seq: u64,
+    pub accounts: usize,
+    pub locked_accounts: usize,
+    pub processed_txns: usize,
+    /// Streamed records rejected for timestamps too far ahead of or behind the stream's time
+    pub future_timestamps: u64,
+    pub stale_timestamps: u64,
+    /// Sums in minor units so they match the rounded output columns exactly
+    pub available: i64,
+    pub held: i64,
+    /// Sha256 of the accounts in client order, at output precision
+    pub state_hash: String,
+}
+
+impl fmt::Display for StateSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "last_seq={} accounts={} locked={} txns={} future_timestamps={} stale_timestamps={} available={} held={} state_hash={}",
+            self.last_seq,
+            self.accounts,
+            self.locked_accounts,
+            self.processed_txns,
+            self.future_timestamps,
+            self.stale_timestamps,
+            format_minor_units(self.available),
+            format_minor_units(self.held),
+            self.state_hash
+        )
+    }
+}
+
+impl PaymentsEngine {
+    /// Summarizes current state, hashing every account so it's linear in the number of accounts
+    pub fn state_summary(&self) -> StateSummary {
+        let mut accounts: Vec<_> = self.accounts.iter().collect();
+        accounts.sort_by_key(|acnt| acnt.id);
+        let mut hasher = Sha256::new();
+        for acnt in &accounts {
+            hasher.update(
+                format!(
+                    "{},{},{},{}\n",
+                    acnt.id,
+                    acnt.available().minor_units(),
+                    acnt.held().minor_units(),
+                    acnt.is_locked()
+                )
+                .as_bytes(),
+            );
+        }
+        StateSummary {
+            last_seq: self.last_seq,
+            accounts: accounts.len(),
+            locked_accounts: accounts.iter().filter(|acnt| acnt.is_locked()).count(),
+            processed_txns: self.processed_txns.len(),
+            future_timestamps: self.timestamp_rejects.future,
+            stale_timestamps: self.timestamp_rejects.stale,
+            available: accounts
+                .iter()
+                .map(|acnt| acnt.available().minor_units())
+                .sum(),
+            held: accounts.iter().map(|acnt| acnt.held().minor_units()).sum(),
+            state_hash: hasher
+                .finalize()
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::EngineFixture;
+
+    #[test]
+    fn tst_state_summary() {
+        let build = |first: u16, second: u16| {
+            EngineFixture::new()
+                .deposit(first, 1, 10.0)
+                .deposit(second, 2, 2.5)
+                .dispute(second, 2)
+                .build()
+                .engine
+        };
+        let summary = build(1, 2).state_summary();
+        assert_eq!(summary.accounts, 2);
+        assert_eq!(summary.processed_txns, 3);
+        assert_eq!(
+            summary.to_string(),
+            format!(
+                "last_seq=3 accounts=2 locked=0 txns=3 future_timestamps=0 stale_timestamps=0 available=10.0000 held=2.5000 state_hash={}",
+                summary.state_hash
+            )
+        );
+        // Same balances hash the same whatever order accounts were created in
+        let swapped = EngineFixture::new()
+            .deposit(2, 2, 2.5)
+            .deposit(1, 1, 10.0)
+            .dispute(2, 2)
+            .build()
+            .engine
+            .state_summary();
+        assert_eq!(swapped.state_hash, summary.state_hash);
+        assert_ne!(build(2, 1).state_summary().state_hash, summary.state_hash);
+    }
+}
@@ -0,0 +1,237 @@
+use super::transactions::TxnErrors;
+use super::PaymentsEngine;
+use crate::cli_io::txn_record;
+use crate::diagnostics::{log, Level};
+use crate::transaction::SequencedTxn;
+use csv::Writer;
+use std::error::Error;
+use std::time::Duration;
+
+/// Processing counters & timers for a single client
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ClientStats {
+    /// Transactions addressed to the client, accepted or not
+    pub txns: u64,
+    /// Transactions addressed to the client which were rejected
+    pub rejects: u64,
+    /// Sequence number of the latest transaction addressed to the client
+    pub last_seq: u64,
+    /// Time spent applying the client's transactions
+    pub processing_time: Duration,
+}
+
+/// What a client's accepted txns in this run add up to, reported by the v2 output columns
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AccountActivity {
+    pub disputes: u64,
+    pub chargebacks: u64,
+    /// Id of the latest deposit or withdrawal
+    pub last_txn_id: Option<u32>,
+}
+
+impl PaymentsEngine {
+    /// Processing stats for a client, None if no transaction has been addressed to it
+    pub fn client_stats(&self, acnt_id: u16) -> Option<&ClientStats> {
+        self.client_stats.get(&acnt_id)
+    }
+
+    pub(super) fn record_client_stats(
+        &mut self,
+        s_txn: &SequencedTxn,
+        result: &Result<(), TxnErrors>,
+        elapsed: Duration,
+    ) {
+        let stats = self.client_stats.entry(s_txn.txn.acnt_id()).or_default();
+        stats.txns += 1;
+        if result.is_err() {
+            stats.rejects += 1;
+        }
+        stats.last_seq = stats.last_seq.max(s_txn.seq);
+        stats.processing_time += elapsed;
+    }
+
+    /// Number of records which took longer than the latency budget to apply
+    pub fn slow_records(&self) -> u64 {
+        self.slow_records
+    }
+
+    /// Logs & counts a record which took longer to apply than the latency budget
+    pub(super) fn check_latency_budget(
+        &mut self,
+        s_txn: &SequencedTxn,
+        result: &Result<(), TxnErrors>,
+        elapsed: Duration,
+    ) {
+        let budget = match self.latency_budget {
+            Some(budget) if elapsed > budget => budget,
+            _ => return,
+        };
+        self.slow_records += 1;
+        self.metrics.counter("txns.slow", 1);
+        let [type_str, client, tx, _] = txn_record(&s_txn.txn);
+        log(
+            Level::Warn,
+            format_args!(
+                "Slow record seq {}: {} of client {} tx {} took {}us, over the {}us budget, {}",
+                s_txn.seq,
+                type_str,
+                client,
+                tx,
+                elapsed.as_micros(),
+                budget.as_micros(),
+                match result {
+                    Ok(_) => "applied".to_string(),
+                    Err(e) => format!("rejected with {:?}", e),
+                }
+            ),
+        );
+    }
+
+    /// Reports a processed txn's outcome & apply time to the metrics sink
+    /// The account count gauge is only sent when it changed
+    pub(super) fn report_txn_metrics(
+        &self,
+        result: &Result<(), TxnErrors>,
+        elapsed: Duration,
+        accounts_before: usize,
+    ) {
+        match result {
+            Ok(_) => self.metrics.counter("txns.accepted", 1),
+            Err(_) => self.metrics.counter("txns.rejected", 1),
+        }
+        self.metrics
+            .histogram("txn.apply_us", elapsed.as_secs_f64() * 1e6);
+        if self.accounts.len() != accounts_before {
+            self.metrics.gauge("accounts", self.accounts.len() as f64);
+        }
+    }
+
+    /// Writes client stats to a csv, clients with the most processing time first
+    pub fn output_client_stats_csv(&self, file_path: &str) -> Result<(), Box<dyn Error>> {
+        let mut clients: Vec<(&u16, &ClientStats)> = self.client_stats.iter().collect();
+        clients.sort_by(|a, b| {
+            b.1.processing_time
+                .cmp(&a.1.processing_time)
+                .then(a.0.cmp(b.0))
+        });
+
+        let mut wtr = Writer::from_path(file_path)?;
+        wtr.write_record(["client", "txns", "rejects", "last_seq", "processing_us"])?;
+        for (acnt_id, stats) in clients {
+            wtr.write_record([
+                acnt_id.to_string(),
+                stats.txns.to_string(),
+                stats.rejects.to_string(),
+                stats.last_seq.to_string(),
+                stats.processing_time.as_micros().to_string(),
+            ])?;
+        }
+        wtr.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::metrics::MetricsSink;
+    use crate::payments_engine::PaymentsEngine;
+    use crate::test::utils::_get_test_output_file;
+    use crate::test_support::{deposit, withdrawal};
+
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    /// Keeps the names of metrics it receives for inspection
+    #[derive(Debug, Default)]
+    struct RecordingMetrics {
+        names: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl MetricsSink for RecordingMetrics {
+        fn counter(&self, name: &str, value: u64) {
+            self.names
+                .lock()
+                .unwrap()
+                .push(format!("{}+{}", name, value));
+        }
+        fn gauge(&self, name: &str, value: f64) {
+            self.names
+                .lock()
+                .unwrap()
+                .push(format!("{}={}", name, value));
+        }
+        fn histogram(&self, name: &str, _value: f64) {
+            self.names.lock().unwrap().push(name.to_string());
+        }
+    }
+
+    #[test]
+    fn tst_client_stats() {
+        let mut payments_engine = PaymentsEngine::new();
+        let deposit = deposit(1, 1, 5.0);
+        assert!(payments_engine.process_txn(&deposit).is_ok());
+        assert!(payments_engine.process_txn(&withdrawal(2, 2, 1.0)).is_err());
+        assert!(payments_engine
+            .process_txn(&withdrawal(1, 3, 10.0))
+            .is_err());
+        assert!(payments_engine.process_txn(&withdrawal(1, 4, 1.0)).is_ok());
+
+        let stats = payments_engine.client_stats(1).unwrap();
+        assert_eq!(stats.txns, 3);
+        assert_eq!(stats.rejects, 1);
+        assert_eq!(stats.last_seq, 4);
+        let stats = payments_engine.client_stats(2).unwrap();
+        assert_eq!((stats.txns, stats.rejects, stats.last_seq), (1, 1, 2));
+        assert!(payments_engine.client_stats(3).is_none());
+
+        let f = _get_test_output_file("tst_client_stats.csv");
+        assert!(payments_engine.output_client_stats_csv(&f).is_ok());
+        let mut rdr = csv::Reader::from_path(&f).unwrap();
+        assert_eq!(
+            rdr.headers().unwrap(),
+            vec!["client", "txns", "rejects", "last_seq", "processing_us"]
+        );
+        assert_eq!(rdr.records().count(), 2);
+    }
+
+    #[test]
+    fn tst_report_txn_metrics() {
+        let metrics = RecordingMetrics::default();
+        let names = metrics.names.clone();
+        let mut payments_engine = PaymentsEngine::new();
+        payments_engine.set_metrics_sink(Box::new(metrics));
+        let deposit = deposit(1, 1, 5.0);
+        let _ = payments_engine.process_txn(&deposit);
+        let _ = payments_engine.process_txn(&withdrawal(1, 2, 10.0));
+        assert_eq!(
+            *names.lock().unwrap(),
+            vec![
+                "txns.accepted+1",
+                "txn.apply_us",
+                "accounts=1",
+                "txns.rejected+1",
+                "txn.apply_us"
+            ]
+        );
+    }
+
+    #[test]
+    fn tst_latency_budget() {
+        let metrics = RecordingMetrics::default();
+        let names = metrics.names.clone();
+        let mut payments_engine = PaymentsEngine::new();
+        payments_engine.set_metrics_sink(Box::new(metrics));
+        let _ = payments_engine.process_txn(&withdrawal(1, 1, 1.0));
+        assert_eq!(payments_engine.slow_records(), 0);
+
+        // Any measurable time is over a zero budget
+        payments_engine.set_latency_budget(Duration::ZERO);
+        let _ = payments_engine.process_txn(&withdrawal(1, 2, 1.0));
+        assert_eq!(payments_engine.slow_records(), 1);
+        assert!(names.lock().unwrap().contains(&"txns.slow+1".to_string()));
+
+        payments_engine.set_latency_budget(Duration::from_secs(60));
+        let _ = payments_engine.process_txn(&withdrawal(1, 3, 1.0));
+        assert_eq!(payments_engine.slow_records(), 1);
+    }
+}
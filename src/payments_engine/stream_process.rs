@@ -1,86 +1,598 @@
+use super::config::GcPolicy;
+use super::progress::InputProgress;
 use super::PaymentsEngine;
+use crate::account::Account;
+use crate::alerts::AlertSink;
+use crate::amount::PrecisionPolicy;
 use crate::cli_io::RawInputTxn;
-use crate::cli_io::{output_accounts, parse_cli, CliOptions};
-use csv::{ReaderBuilder, Trim};
-use std::io::{self};
+use crate::cli_io::{
+    output_accounts, output_accounts_checksums, output_txn_log_csv, AccountColumns, CliOptions,
+    FixedWidthSpec, HeaderOptions, OutputSchema, OutputSink,
+};
+use crate::clock::ClockSource;
+use crate::dead_letter::{DeadLetter, DeadLetterSink, RecordError};
+use crate::diagnostics::{log, Level};
+use crate::encoding::{DecodingReader, InputEncoding};
+use crate::metrics::StatsdMetrics;
+use crate::shadow::shadow_execute;
+use crate::transaction::Transaction;
+use crate::transform::IngestTransform;
+use crate::webhook::WebhookSink;
+use csv::{ReaderBuilder, StringRecord, Terminator, Trim, WriterBuilder};
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, IsTerminal};
+
+/// Per run behavior of the streaming loop, the defaults stream csv with none of the optional behavior
+#[derive(Debug, Default, Clone, Copy)]
+pub struct StreamOptions<'a> {
+    /// Inactive zero balance accounts are collected periodically when set
+    pub gc_policy: Option<&'a GcPolicy>,
+    /// Draw a progress bar of bytes read to stderr
+    pub progress: bool,
+    /// Bytes the input is expected to hold, lets the progress bar show an ETA
+    pub input_len: Option<u64>,
+    /// Records are fixed width lines laid out by this spec instead of csv
+    pub fixed_width: Option<&'a FixedWidthSpec>,
+    /// Lines of the input read before this reader started, so dead letters point at file lines
+    pub line_offset: u64,
+    /// File lines to pass over, e.g. dead letters being replayed separately
+    pub skip_lines: Option<&'a HashSet<u64>>,
+    /// Encoding input files are in, detected from their first bytes when unset
+    pub encoding: Option<InputEncoding>,
+    /// Rewrites applied to records before they are converted
+    pub transforms: &'a [IngestTransform],
+    /// Aliases & schema checks of the header row, when the input has one
+    pub header: Option<&'a HeaderOptions>,
+    /// What happens to amounts with more places than `PRECISION`
+    pub precision: PrecisionPolicy,
+}
+
+/// Record text as a csv line, as it would appear in a headerless input
+fn row_text(row: &StringRecord) -> String {
+    let mut wtr = WriterBuilder::new()
+        .terminator(Terminator::Any(b'\n'))
+        .from_writer(vec![]);
+    if wtr.write_record(row).is_err() {
+        return row.iter().collect::<Vec<_>>().join(",");
+    }
+    let text = wtr.into_inner().unwrap_or_default();
+    String::from_utf8_lossy(&text).trim_end().to_string()
+}
 
 impl PaymentsEngine {
+    /// Converts, sequences, & applies a single input record, counting it against its partner
+    /// Errors with why the record wasn't applied, so the caller can dead letter it & continue
+    pub(super) fn ingest_record(
+        &mut self,
+        record: RawInputTxn,
+        options: &StreamOptions,
+    ) -> Result<(), RecordError> {
+        let partner = match self.wants_partners() {
+            true => record.partner().map(str::to_string),
+            false => None,
+        };
+        let result = self.apply_record(record, options);
+        self.record_partner_outcome(partner, &result);
+        if let Some(policy) = options.gc_policy {
+            let _ = self.gc_if_due(policy);
+        }
+        result
+    }
+
+    /// Converts, sequences, & applies a single input record
+    fn apply_record(
+        &mut self,
+        mut record: RawInputTxn,
+        options: &StreamOptions,
+    ) -> Result<(), RecordError> {
+        record
+            .apply_transforms(options.transforms)
+            .map_err(RecordError::Input)?;
+        let note = record.memo().map(str::to_string);
+        let timestamp = match self.wants_timestamps() {
+            true => record.timestamp().map_err(RecordError::Input)?,
+            false => None,
+        };
+        let txn = record
+            .convert_with_precision(options.precision)
+            .map_err(RecordError::Input)?;
+        if let Some(timestamp) = timestamp {
+            if let Err(e) = self.check_timestamp(timestamp) {
+                let s_txn = self.sequence_txn(txn);
+                self.reject_timestamp(&s_txn, &e);
+                return Err(RecordError::Rejected(e, s_txn.seq));
+            }
+            self.clock.observe(timestamp);
+        }
+        let s_txn = self.sequence_txn(txn);
+        let result = self
+            .process_sequenced_txn(&s_txn)
+            .map_err(|e| RecordError::Rejected(e, s_txn.seq));
+        self.record_flow(timestamp, &s_txn.txn, result.is_ok());
+        if let (
+            Ok(_),
+            Some(note),
+            Transaction::Dispute(ref_txn)
+            | Transaction::Resolve(ref_txn)
+            | Transaction::Chargeback(ref_txn),
+        ) = (&result, note, &s_txn.txn)
+        {
+            self.note_dispute_case(ref_txn.ref_id, note);
+        }
+        result
+    }
+
+    /// Restores accounts from the snapshot or initial state file run options point at, if any, then opens
+    /// onboarded accounts & adds the stage running the `--script` given
+    /// The conservation check starts after, so restored balances are its opening balance
+    pub(super) fn load_starting_state(&mut self, cli_input: &CliOptions) -> Result<(), io::Error> {
+        #[cfg(feature = "scripting")]
+        if let Some(file_path) = &cli_input.script {
+            self.set_script(
+                super::script::TxnScript::from_file(file_path)?.with_precision(cli_input.precision),
+            );
+        }
+        if let Some(dir) = &cli_input.restore_snapshot {
+            self.restore_checkpoint(dir, cli_input.snapshot_deltas)?;
+        }
+        if let Some(file_path) = &cli_input.initial_state {
+            self.import_initial_state(file_path, cli_input.on_duplicate_client)?;
+        }
+        if let Some(file_path) = &cli_input.onboarding {
+            self.import_onboarding(file_path)?;
+        }
+        if cli_input.assert_conservation {
+            self.enable_conservation_check();
+        }
+        Ok(())
+    }
+
+    /// Saves a sharded snapshot of accounts, or a delta of it, if run options ask for one
+    pub(super) fn save_snapshot_option(&mut self, cli_input: &CliOptions) -> Result<(), io::Error> {
+        if let Some(dir) = &cli_input.save_snapshot {
+            self.save_checkpoint(dir, cli_input.snapshot_shards, cli_input.snapshot_deltas)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the pseudonym of each account & the client it stands for if run options ask for it
+    pub(super) fn save_anonymize_map(&self, cli_input: &CliOptions) -> Result<(), Box<dyn Error>> {
+        if let (Some(file_path), Some(pseudonyms)) = (
+            &cli_input.anonymize_map,
+            IngestTransform::pseudonyms(&cli_input.transforms),
+        ) {
+            pseudonyms.output_map_csv(self.iter_accounts().map(|view| view.client()), file_path)?;
+        }
+        Ok(())
+    }
+
+    /// Sets up sinks run options ask for
+    /// Errors if a sink can't be set up, e.g. a statsd address which can't be reached
+    pub(super) fn configure_sinks(&mut self, cli_input: &CliOptions) -> Result<(), io::Error> {
+        if !cli_input.webhook.urls.is_empty() {
+            self.webhook_sink = Some(WebhookSink::new(cli_input.webhook.clone()));
+        }
+        if let Some(addr) = &cli_input.statsd {
+            let metrics = StatsdMetrics::new(addr, &cli_input.statsd_prefix)?;
+            self.set_metrics_sink(Box::new(metrics));
+        }
+        if let Some(budget) = cli_input.latency_budget {
+            self.set_latency_budget(budget);
+        }
+        if !cli_input.alerts.rules.is_empty() {
+            self.alert_sink = Some(AlertSink::new(&cli_input.alerts, &cli_input.webhook));
+        }
+        if let Some(dead_letter) = &cli_input.dead_letter {
+            self.dead_letter_sink = Some(DeadLetterSink::new(dead_letter)?);
+        }
+        if cli_input.clock != ClockSource::Wall {
+            self.set_clock(cli_input.clock.clock());
+        }
+        if cli_input.flow_report.is_some() {
+            self.enable_flow_report(cli_input.flow_bucket);
+        }
+        if cli_input.partner_report.is_some() {
+            self.enable_partner_report(cli_input.partner_reject_threshold);
+        }
+        if let Some(balances_series) = &cli_input.balances_series {
+            if self
+                .enable_balance_series(balances_series, cli_input.series_interval)
+                .is_err()
+            {
+                // Error logging and follow up
+            }
+        }
+        Ok(())
+    }
+
+    /// Columns of the account output the options ask for
+    pub(super) fn account_columns(&self, cli_input: &CliOptions) -> AccountColumns {
+        let activity = match cli_input.output_schema {
+            OutputSchema::V1 => None,
+            OutputSchema::V2 => Some(
+                self.accounts
+                    .iter()
+                    .map(|acnt| (acnt.id, self.account_activity(acnt.id)))
+                    .collect(),
+            ),
+        };
+        let chargeback_txns = match cli_input.only_locked {
+            true => Some(
+                self.accounts
+                    .iter()
+                    .filter_map(|acnt| Some((acnt.id, self.locking_chargeback(acnt.id)?)))
+                    .collect(),
+            ),
+            false => None,
+        };
+        AccountColumns {
+            lock_reasons: cli_input.extended_output,
+            activity,
+            chargeback_txns,
+        }
+    }
+
+    /// Accounts the run's account outputs hold, `--only-locked` keeps those the compliance feed wants
+    pub(super) fn output_account_list(&self, cli_input: &CliOptions) -> Cow<'_, [Account]> {
+        match cli_input.only_locked {
+            true => Cow::Owned(
+                self.accounts
+                    .iter()
+                    .filter(|acnt| self.is_restricted(acnt.id))
+                    .cloned()
+                    .collect(),
+            ),
+            false => Cow::Borrowed(&self.accounts),
+        }
+    }
+
+    /// Writes accounts to every configured output, with checksum sidecars when asked for
+    pub(super) fn write_accounts(&self, cli_input: &CliOptions) {
+        self.write_accounts_to(cli_input, &cli_input.output);
+    }
+
+    /// Writes accounts to some of the configured outputs, with checksum sidecars when asked for
+    pub(super) fn write_accounts_to(&self, cli_input: &CliOptions, outputs: &[OutputSink]) {
+        let columns = self.account_columns(cli_input);
+        let accounts = self.output_account_list(cli_input);
+        output_accounts(&accounts, outputs, &columns, &cli_input.csv_dialect);
+        if !cli_input.checksum {
+            return;
+        }
+        if let Err(e) = output_accounts_checksums(&accounts, outputs) {
+            log(
+                Level::Error,
+                format_args!("Failed to write accounts checksum: {}", e),
+            );
+        }
+    }
+
     /// Returns error in the event that file cannot be read
     /// Else mutates the payments engine state
     /// Records with correct data format but fail logically given business logic are ignored
-    /// Improper csv format or corrupted records are skipped
-    #[allow(clippy::single_match)]
+    /// Improper csv format or corrupted records are skipped, fixed width inputs are read per the options
     fn stream_process_csv(
         &mut self,
         in_file_path: &str,
         has_header: bool,
+        options: &StreamOptions,
     ) -> Result<(), io::Error> {
+        let file = File::open(in_file_path)?;
+        let options = StreamOptions {
+            input_len: Some(file.metadata()?.len()),
+            ..*options
+        };
+        let reader = DecodingReader::new(file, options.encoding).map_err(|e| {
+            io::Error::new(e.kind(), format!("Can't decode {}: {}", in_file_path, e))
+        })?;
+        self.stream_process_reader(reader, has_header, &options)
+    }
+
+    /// Streams csv records from any reader, e.g. an in memory buffer or a network stream an embedder accepted
+    /// Errors if the reader fails or a safety limit is passed, improper csv format or corrupted records are skipped
+    pub fn stream_process_reader<R: io::Read>(
+        &mut self,
+        reader: R,
+        has_header: bool,
+        options: &StreamOptions,
+    ) -> Result<(), io::Error> {
+        if let Some(spec) = options.fixed_width {
+            return self.stream_process_fixed_width(reader, spec, options);
+        }
         let mut rdr = ReaderBuilder::new()
             .trim(Trim::All)
             .has_headers(has_header)
-            .from_path(in_file_path)?;
+            .from_reader(reader);
+        let headers = match has_header {
+            true => {
+                let headers = rdr.headers().map_err(io::Error::other)?;
+                match options.header {
+                    Some(header) => Some(header.apply(headers)?),
+                    None => Some(headers.clone()),
+                }
+            }
+            false => None,
+        };
+        let progress = InputProgress::new(options.progress, options.input_len);
+        let mut records_read: u64 = 0;
 
-        for result in rdr.deserialize() {
-            if result.is_err() {
+        let mut records = rdr.records();
+        while let Some(result) = records.next() {
+            self.drain_priority_lane(options);
+            records_read += 1;
+            progress.update(records_read, || records.reader().position().byte());
+            let row = match result {
+                Ok(row) => row,
+                Err(e) if e.is_io_error() => return Err(io::Error::other(e)),
+                Err(e) => {
+                    let line = e.position().map_or(0, |pos| pos.line());
+                    self.dead_letter(
+                        options.line_offset + line,
+                        "",
+                        &RecordError::Malformed(e.to_string()),
+                    );
+                    continue;
+                }
+            };
+            let line = options.line_offset + row.position().map_or(0, |pos| pos.line());
+            if options.skip_lines.is_some_and(|skip| skip.contains(&line)) {
                 continue;
             }
-            let record: RawInputTxn = result?;
-            let txn = record.convert_to_txn();
-            // Assume individual invalid records can be ignored, continue process file
-            if txn.is_err() {
-                // Record error logging & fanout
+            let result = match row.deserialize::<RawInputTxn>(headers.as_ref()) {
+                Ok(record) => self.ingest_record(record, options),
+                Err(e) => Err(RecordError::Malformed(e.to_string())),
+            };
+            if let Err(e) = result {
+                self.dead_letter(line, &row_text(&row), &e);
+            }
+            self.check_limits().map_err(io::Error::other)?;
+        }
+
+        progress.finish();
+        Ok(())
+    }
+
+    /// Streams fixed width records a line at a time, blank lines are skipped
+    fn stream_process_fixed_width<R: io::Read>(
+        &mut self,
+        reader: R,
+        spec: &FixedWidthSpec,
+        options: &StreamOptions,
+    ) -> Result<(), io::Error> {
+        let mut rdr = BufReader::new(reader);
+        let progress = InputProgress::new(options.progress, options.input_len);
+        let mut bytes_read: u64 = 0;
+        let mut line_number = options.line_offset;
+        let mut line = String::new();
+        loop {
+            line.clear();
+            let read = rdr.read_line(&mut line)?;
+            if read == 0 {
+                break;
+            }
+            self.drain_priority_lane(options);
+            bytes_read += read as u64;
+            line_number += 1;
+            progress.update(line_number, || bytes_read);
+            let record = line.trim_end_matches(['\r', '\n']);
+            if record.trim().is_empty()
+                || options
+                    .skip_lines
+                    .is_some_and(|skip| skip.contains(&line_number))
+            {
                 continue;
             }
-            match self.process_txn(&txn.unwrap()) {
-                Ok(_) => {
-                    // Record success logging & fanout
-                }
-                Err(_) => {
-                    // Record error logging & fanout
-                }
+            let result = match spec.decode(record) {
+                Ok(raw_txn) => self.ingest_record(raw_txn, options),
+                Err(e) => Err(RecordError::Input(e)),
+            };
+            if let Err(e) = result {
+                self.dead_letter(line_number, record, &e);
             }
+            self.check_limits().map_err(io::Error::other)?;
         }
 
+        progress.finish();
         Ok(())
     }
 
-    /// Executes Payments Engine given a cli input
-    /// Won't execute if cli fails parsing
-    /// Else will output stream data if input file is valid
-    pub fn streaming_execute_cli(&mut self) {
-        // Using guard pattern to avoid nested match
-        let cli_res = parse_cli();
-        if cli_res.is_err() {
-            // TODO custom parsing error message
-            return;
+    /// Applies the booked entries of a camt document, the whole document is read before any are applied
+    #[cfg(feature = "iso20022")]
+    fn stream_process_iso20022(
+        &mut self,
+        in_file_path: &str,
+        options: &StreamOptions,
+    ) -> Result<(), io::Error> {
+        let mut xml = String::new();
+        DecodingReader::new(File::open(in_file_path)?, options.encoding)
+            .and_then(|mut reader| io::Read::read_to_string(&mut reader, &mut xml))
+            .map_err(|e| {
+                io::Error::new(e.kind(), format!("Can't decode {}: {}", in_file_path, e))
+            })?;
+        for entry in crate::iso20022::decode_entries(&xml)? {
+            self.drain_priority_lane(options);
+            let result = match entry.record {
+                Ok(raw_txn) => self.ingest_record(raw_txn, options),
+                Err(e) => Err(RecordError::Input(e)),
+            };
+            if let Err(e) = result {
+                self.dead_letter(entry.line, &entry.source, &e);
+            }
+            self.check_limits().map_err(io::Error::other)?;
         }
-        let cli_options = cli_res.unwrap();
-
-        self.streaming_execute(&cli_options);
+        Ok(())
     }
 
-    /// Executes Payments Engine given a cli input string
-    /// If a failure occurs mid stream will output all valid records up until that point
-    #[allow(clippy::single_match)]
-    fn streaming_execute(&mut self, cli_input: &CliOptions) {
-        match self.stream_process_csv(&cli_input.input_file, true) {
-            Ok(_) => {
-                // Success logging and follow up
+    /// Keeps a record that wasn't applied in the dead letter file, if one is configured
+    pub(super) fn dead_letter(&mut self, line: u64, record: &str, error: &RecordError) {
+        if let Some(sink) = &mut self.dead_letter_sink {
+            if sink.append(&DeadLetter::new(line, record, error)).is_err() {
+                // Error logging and follow up
             }
-            Err(_) => {
+        }
+    }
+
+    /// Executes Payments Engine given parsed cli options
+    /// If a failure occurs mid stream, e.g. a safety limit is passed, will output all valid records
+    /// up until that point as a checkpoint & then return the failure
+    /// With an oracle or conservation check, errors once outputs are written if the check failed
+    pub fn streaming_execute(&mut self, cli_input: &CliOptions) -> Result<(), io::Error> {
+        self.configure_sinks(cli_input)?;
+        self.load_starting_state(cli_input)?;
+        let oracle = cli_input.oracle_check.then(|| self.enable_oracle_check());
+        self.open_priority_lane(cli_input)?;
+        let result = self.process_input_file(cli_input, None);
+        self.close_priority_lane(&StreamOptions {
+            transforms: &cli_input.transforms,
+            precision: cli_input.precision,
+            ..StreamOptions::default()
+        });
+        self.write_run_outputs(cli_input);
+        result?;
+        self.check_conservation().map_err(io::Error::other)?;
+        let divergences = oracle
+            .map(|report| report.divergences())
+            .unwrap_or_default();
+        for divergence in divergences.iter() {
+            log(Level::Warn, divergence);
+        }
+        match divergences.len() {
+            0 => Ok(()),
+            count => Err(io::Error::other(format!(
+                "Engine diverged from the oracle on {} txns",
+                count
+            ))),
+        }
+    }
+
+    /// Streams the input file, passing over any lines in `skip_lines`
+    pub(super) fn process_input_file(
+        &mut self,
+        cli_input: &CliOptions,
+        skip_lines: Option<&HashSet<u64>>,
+    ) -> Result<(), io::Error> {
+        // Progress is only drawn for interactive runs which aren't writing results to the terminal
+        let options = StreamOptions {
+            gc_policy: cli_input.gc_policy.as_ref(),
+            progress: cli_input.progress
+                && io::stdout().is_terminal()
+                && !cli_input.output.iter().any(OutputSink::is_stdout),
+            input_len: None,
+            fixed_width: cli_input.fixed_width.as_ref(),
+            line_offset: 0,
+            skip_lines,
+            encoding: cli_input.encoding,
+            transforms: &cli_input.transforms,
+            header: Some(&cli_input.header),
+            precision: cli_input.precision,
+        };
+        #[cfg(feature = "iso20022")]
+        if cli_input.iso20022 {
+            return self.stream_process_iso20022(&cli_input.input_file, &options);
+        }
+        // Fixed width files come without a header
+        let has_header = cli_input.fixed_width.is_none();
+        self.stream_process_csv(&cli_input.input_file, has_header, &options)
+    }
+
+    /// Collects inactive accounts if asked to, then writes accounts & every report the options ask for
+    pub(super) fn write_run_outputs(&mut self, cli_input: &CliOptions) {
+        if let Some(policy) = &cli_input.gc_policy {
+            if self.gc_accounts(policy).is_err() {
                 // Error logging and follow up
             }
         }
 
-        output_accounts(&self.accounts, &cli_input.output);
+        self.write_accounts(cli_input);
+        if self.save_anonymize_map(cli_input).is_err() {
+            // Error logging and follow up
+        }
+        if self.finish_balance_series().is_err() {
+            // Error logging and follow up
+        }
+        if let Some(txn_log) = &cli_input.txn_log {
+            if output_txn_log_csv(self.numbered_txns(), txn_log).is_err() {
+                // Error logging and follow up
+            }
+        }
+        if let Some(client_stats) = &cli_input.client_stats {
+            if self.output_client_stats_csv(client_stats).is_err() {
+                // Error logging and follow up
+            }
+        }
+        if let Some(channel_report) = &cli_input.channel_report {
+            if self.output_channel_report_csv(channel_report).is_err() {
+                // Error logging and follow up
+            }
+        }
+        if let Some(pending_report) = &cli_input.pending_report {
+            if self.output_pending_withdrawals_csv(pending_report).is_err() {
+                // Error logging and follow up
+            }
+        }
+        if let Some(quarantine) = &cli_input.quarantine {
+            if self.output_quarantine_csv(quarantine).is_err() {
+                // Error logging and follow up
+            }
+        }
+        if let Some(audit_log) = &cli_input.audit_log {
+            if self.output_audit_log_csv(audit_log).is_err() {
+                // Error logging and follow up
+            }
+        }
+        self.summarize_audit();
+        if let Some(dispute_aging) = &cli_input.dispute_aging {
+            if self.output_dispute_aging_csv(dispute_aging).is_err() {
+                // Error logging and follow up
+            }
+        }
+        if let Some(held_breakdown) = &cli_input.held_breakdown {
+            if self.output_held_breakdown_csv(held_breakdown).is_err() {
+                // Error logging and follow up
+            }
+        }
+        if let Some(dispute_cases) = &cli_input.dispute_cases {
+            if self.output_dispute_cases_csv(dispute_cases).is_err() {
+                // Error logging and follow up
+            }
+        }
+        if let Some(flow_report) = &cli_input.flow_report {
+            if self.output_flow_report_csv(flow_report).is_err() {
+                // Error logging and follow up
+            }
+        }
+        if let Some(partner_report) = &cli_input.partner_report {
+            if self.output_partner_report_csv(partner_report).is_err() {
+                // Error logging and follow up
+            }
+        }
+        if let Some(shadow) = &cli_input.shadow {
+            if shadow_execute(&self.accounts, shadow).is_err() {
+                // Error logging and follow up
+            }
+        }
+        if self.save_txn_registry().is_err() {
+            // Error logging and follow up
+        }
+        if self.save_snapshot_option(cli_input).is_err() {
+            // Error logging and follow up
+        }
     }
 }
 
 #[cfg(test)]
 pub mod tests {
+    use super::StreamOptions;
     use crate::account::Account;
+    use crate::amount::{Available, Held};
+    use crate::cli_io::FixedWidthSpec;
+    use crate::payments_engine::config::{ClientFilter, DedupeWindowConfig, EngineConfig};
     use crate::payments_engine::PaymentsEngine;
-    use crate::test::utils::_get_test_input_file;
+    use crate::test::utils::{_get_test_input_file, _get_test_output_file};
+    use std::collections::HashSet;
     use std::io::{self};
     use std::path::PathBuf;
 
@@ -90,9 +602,9 @@ pub mod tests {
     ) -> Result<(), io::Error> {
         let mut f_input = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
         f_input.push(format!("src/test/inputs/{}.csv", file_root));
-        let f_input = _get_test_input_file(&format!("{}", file_root));
+        let f_input = _get_test_input_file(file_root);
 
-        payments_engine.stream_process_csv(f_input.as_str(), true)
+        payments_engine.stream_process_csv(f_input.as_str(), true, &StreamOptions::default())
     }
 
     #[test]
@@ -100,31 +612,152 @@ pub mod tests {
         let mut payments_engine = PaymentsEngine::new();
         let res = stream_execute_on_tst_file("simple.csv", &mut payments_engine);
         assert!(res.is_ok(), "Error free is the way to be");
-        let expected = vec![Account {
-            id: 1,
-            available: 10.0,
-            held: 0.0,
-            frozen: false,
-        }];
+        let expected = vec![Account::with_balances(
+            1,
+            Available::from_f64(10.0),
+            Held::default(),
+        )];
         assert_eq!(expected, payments_engine.accounts);
 
         let mut payments_engine = PaymentsEngine::new();
         let res = stream_execute_on_tst_file("broke_middle.csv", &mut payments_engine);
         assert!(res.is_ok(), "Error free is the way to be");
         let expected = vec![
-            Account {
-                id: 1,
-                available: 1.0,
-                held: 0.0,
-                frozen: false,
-            },
-            Account {
-                id: 3,
-                available: 3.0,
-                held: 0.0,
-                frozen: false,
-            },
+            Account::with_balances(1, Available::from_f64(1.0), Held::default()),
+            Account::with_balances(3, Available::from_f64(3.0), Held::default()),
         ];
         assert_eq!(expected, payments_engine.accounts);
     }
+
+    #[test]
+    fn tst_stream_process_reader() {
+        let mut payments_engine = PaymentsEngine::new();
+        let data = "type, client, tx, amount\n\
+                    deposit, 1, 1, 4.0\n\
+                    deposit, 2, x, 1.0\n\
+                    withdrawal, 1, 2, 1.5\n";
+        let res =
+            payments_engine.stream_process_reader(data.as_bytes(), true, &StreamOptions::default());
+        assert!(res.is_ok());
+        assert_eq!(payments_engine.accounts.len(), 1);
+        assert_eq!(payments_engine.accounts[0].available().to_f64(), 2.5);
+    }
+
+    #[test]
+    fn tst_stream_process_fixed_width() {
+        let spec =
+            FixedWidthSpec::from_file(&_get_test_input_file("fixed_width_spec.csv")).unwrap();
+        let options = StreamOptions {
+            fixed_width: Some(&spec),
+            ..StreamOptions::default()
+        };
+        let mut payments_engine = PaymentsEngine::new();
+        let f_input = _get_test_input_file("fixed_width.txt");
+        let res = payments_engine.stream_process_csv(f_input.as_str(), false, &options);
+        assert!(res.is_ok());
+        let balances: Vec<(u16, f64, f64)> = payments_engine
+            .accounts
+            .iter()
+            .map(|a| (a.id, a.available().to_f64(), a.held().to_f64()))
+            .collect();
+        assert_eq!(
+            balances,
+            vec![(1, 6.25, 0.0), (2, 0.0, 2.0)],
+            "Malformed client ids should be skipped"
+        );
+    }
+
+    #[test]
+    fn tst_stream_process_csv_progress() {
+        let mut payments_engine = PaymentsEngine::new();
+        let f_input = _get_test_input_file("transactions.csv");
+        let options = StreamOptions {
+            progress: true,
+            ..StreamOptions::default()
+        };
+        let res = payments_engine.stream_process_csv(f_input.as_str(), true, &options);
+        assert!(
+            res.is_ok(),
+            "Progress reporting should not affect processing"
+        );
+        assert_eq!(payments_engine.accounts.len(), 2);
+    }
+
+    #[test]
+    fn tst_stream_process_encodings() {
+        let text = "type, client, tx, amount\ndeposit, 1, 1, 10.0\nwithdrawal, 1, 2, 2.5\n";
+        let f_input = _get_test_output_file("tst_stream_process_encodings.csv");
+        let mut utf16 = vec![0xff, 0xfe];
+        utf16.extend(text.encode_utf16().flat_map(u16::to_le_bytes));
+        std::fs::write(&f_input, &utf16).unwrap();
+        let mut payments_engine = PaymentsEngine::new();
+        let res = payments_engine.stream_process_csv(&f_input, true, &StreamOptions::default());
+        assert!(res.is_ok());
+        assert_eq!(payments_engine.accounts[0].available().to_f64(), 7.5);
+
+        let mut utf8 = vec![0xef, 0xbb, 0xbf];
+        utf8.extend(text.as_bytes());
+        std::fs::write(&f_input, &utf8).unwrap();
+        let mut payments_engine = PaymentsEngine::new();
+        let res = payments_engine.stream_process_csv(&f_input, true, &StreamOptions::default());
+        assert!(res.is_ok(), "The BOM shouldn't end up in the type header");
+        assert_eq!(payments_engine.accounts[0].available().to_f64(), 7.5);
+
+        // Binary input fails loudly rather than dead lettering every row
+        std::fs::write(&f_input, b"type\0\0\0, client, tx, amount\n").unwrap();
+        let err = PaymentsEngine::new()
+            .stream_process_csv(&f_input, true, &StreamOptions::default())
+            .unwrap_err();
+        assert!(err.to_string().contains("--encoding"));
+    }
+
+    #[test]
+    fn tst_stream_process_dedupe_window() {
+        let f_input = _get_test_output_file("tst_stream_process_dedupe_window.csv");
+        std::fs::write(
+            &f_input,
+            "type, client, tx, amount\n\
+             deposit, 1, 1, 5.0\n\
+             deposit, 1, 1, 5.0\n\
+             dispute, 1, 1,\n\
+             deposit, 1, 1, 5.0\n\
+             dispute, 1, 1,\n",
+        )
+        .unwrap();
+        let config = EngineConfig {
+            dedupe_window: Some(DedupeWindowConfig {
+                max_ids_per_client: 8,
+                max_age: None,
+            }),
+            ..EngineConfig::default()
+        };
+        let mut payments_engine = PaymentsEngine::with_config(config).unwrap();
+        let res = payments_engine.stream_process_csv(&f_input, true, &StreamOptions::default());
+        assert!(res.is_ok());
+        assert_eq!(payments_engine.duplicates_absorbed(), 2);
+        assert_eq!(payments_engine.accounts[0].held().to_f64(), 5.0);
+        assert_eq!(
+            payments_engine.client_stats(1).unwrap().rejects,
+            1,
+            "Only the repeated dispute, which isn't windowed, should be rejected"
+        );
+    }
+
+    #[test]
+    fn tst_stream_process_client_filter() {
+        let config = EngineConfig {
+            client_filter: Some(ClientFilter {
+                only: Some(HashSet::from([1, 2])),
+                skip: HashSet::from([2]),
+            }),
+            ..EngineConfig::default()
+        };
+        let mut payments_engine = PaymentsEngine::with_config(config).unwrap();
+        let f_input = _get_test_input_file("transactions.csv");
+        let res = payments_engine.stream_process_csv(&f_input, true, &StreamOptions::default());
+        assert!(res.is_ok());
+        assert_eq!(payments_engine.accounts.len(), 1);
+        assert_eq!(payments_engine.accounts[0].id, 1);
+        assert_eq!(payments_engine.filtered_records(), 2);
+    }
 }
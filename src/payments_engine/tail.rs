@@ -0,0 +1,282 @@
+#[cfg(unix)]
+use super::signals::OperatorSignals;
+use super::stream_process::StreamOptions;
+use super::PaymentsEngine;
+use crate::cli_io::{OutputSink, TailOptions};
+use crate::shadow::shadow_execute;
+use notify::{RecursiveMode, Watcher};
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+/// Longest wait between checks of the file, covers file systems which don't deliver change events
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Reads the complete lines appended to a file since the last read
+/// A trailing line without a newline is held back until the writer finishes it
+#[derive(Debug)]
+pub struct LogTailer {
+    path: String,
+    offset: u64,
+    pending: Vec<u8>,
+    skip_header: bool,
+    header_skipped: bool,
+    /// Complete lines read so far, header included
+    lines_read: u64,
+    /// File lines before the first line of the last batch returned
+    batch_offset: u64,
+}
+
+impl LogTailer {
+    pub fn new(path: &str, has_header: bool) -> Self {
+        Self {
+            path: path.to_string(),
+            offset: 0,
+            pending: vec![],
+            skip_header: has_header,
+            header_skipped: false,
+            lines_read: 0,
+            batch_offset: 0,
+        }
+    }
+
+    /// Returns newly completed lines, empty if nothing complete was appended
+    /// A file shorter than what was already read is treated as rotated & read from the start
+    pub fn read_complete_lines(&mut self) -> Result<Vec<u8>, io::Error> {
+        let mut file = File::open(&self.path)?;
+        let len = file.metadata()?.len();
+        if len < self.offset {
+            self.offset = 0;
+            self.pending.clear();
+            self.header_skipped = false;
+            self.lines_read = 0;
+        }
+        file.seek(SeekFrom::Start(self.offset))?;
+        let read = file.read_to_end(&mut self.pending)?;
+        self.offset += read as u64;
+
+        let complete_len = match self.pending.iter().rposition(|b| *b == b'\n') {
+            Some(indx) => indx + 1,
+            None => return Ok(vec![]),
+        };
+        let rest = self.pending.split_off(complete_len);
+        let mut lines = std::mem::replace(&mut self.pending, rest);
+        self.batch_offset = self.lines_read;
+        self.lines_read += lines.iter().filter(|b| **b == b'\n').count() as u64;
+
+        if self.skip_header && !self.header_skipped {
+            self.header_skipped = true;
+            self.batch_offset += 1;
+            let header_len = lines.iter().position(|b| *b == b'\n').unwrap_or(0) + 1;
+            lines.drain(..header_len);
+        }
+        Ok(lines)
+    }
+
+    /// File lines before the last batch, so line numbers within a batch can be made file line numbers
+    pub fn batch_offset(&self) -> u64 {
+        self.batch_offset
+    }
+}
+
+impl PaymentsEngine {
+    /// Follows an append only transaction file, applying records as another process writes them
+    /// Wakes up on file change notifications, falling back to polling
+    /// File outputs are rewritten after every batch of applied records, stdout is only written on exit
+    /// Runs until the file has been idle for the configured period, if one is set, or a safety limit is passed
+    /// On unix SIGUSR1 logs a state summary & SIGUSR2 pauses or resumes following the file
+    pub fn tail_execute(&mut self, tail_options: &TailOptions) -> Result<(), Box<dyn Error>> {
+        let cli_input = &tail_options.cli_options;
+        self.configure_sinks(cli_input)?;
+        self.load_starting_state(cli_input)?;
+        let options = StreamOptions {
+            gc_policy: cli_input.gc_policy.as_ref(),
+            fixed_width: cli_input.fixed_width.as_ref(),
+            transforms: &cli_input.transforms,
+            precision: cli_input.precision,
+            ..StreamOptions::default()
+        };
+
+        let (tx, rx) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(move |event| {
+            let _ = tx.send(event);
+        })?;
+        watcher.watch(
+            Path::new(&cli_input.input_file),
+            RecursiveMode::NonRecursive,
+        )?;
+
+        #[cfg(unix)]
+        let signals = OperatorSignals::register()?;
+        let file_outputs: Vec<OutputSink> = cli_input
+            .output
+            .iter()
+            .filter(|sink| !sink.is_stdout())
+            .cloned()
+            .collect();
+        self.open_priority_lane(cli_input)?;
+        let mut tailer = LogTailer::new(&cli_input.input_file, cli_input.fixed_width.is_none());
+        let mut last_activity = Instant::now();
+        let mut stopped = None;
+        loop {
+            #[cfg(unix)]
+            self.handle_operator_signals(&signals, cli_input)?;
+            // Priority records don't wait for the bulk file to grow
+            self.drain_priority_lane(&options);
+            let lines = tailer.read_complete_lines()?;
+            if !lines.is_empty() {
+                let options = StreamOptions {
+                    line_offset: tailer.batch_offset(),
+                    ..options
+                };
+                // Reading from memory can't fail, only a passed safety limit stops the batch
+                if let Err(e) = self.stream_process_reader(lines.as_slice(), false, &options) {
+                    stopped = Some(e);
+                    break;
+                }
+                last_activity = Instant::now();
+                self.write_accounts_to(cli_input, &file_outputs);
+            }
+            if let Some(idle_exit) = tail_options.idle_exit {
+                if last_activity.elapsed() >= idle_exit {
+                    break;
+                }
+            }
+            // Any event or a timeout means it's time to check the file again
+            let _ = rx.recv_timeout(POLL_INTERVAL);
+        }
+        self.close_priority_lane(&options);
+
+        self.write_accounts(cli_input);
+        self.save_anonymize_map(cli_input)?;
+        self.finish_balance_series()?;
+        if let Some(client_stats) = &cli_input.client_stats {
+            self.output_client_stats_csv(client_stats)?;
+        }
+        if let Some(pending_report) = &cli_input.pending_report {
+            self.output_pending_withdrawals_csv(pending_report)?;
+        }
+        if let Some(quarantine) = &cli_input.quarantine {
+            self.output_quarantine_csv(quarantine)?;
+        }
+        if let Some(dispute_aging) = &cli_input.dispute_aging {
+            self.output_dispute_aging_csv(dispute_aging)?;
+        }
+        if let Some(held_breakdown) = &cli_input.held_breakdown {
+            self.output_held_breakdown_csv(held_breakdown)?;
+        }
+        if let Some(dispute_cases) = &cli_input.dispute_cases {
+            self.output_dispute_cases_csv(dispute_cases)?;
+        }
+        if let Some(flow_report) = &cli_input.flow_report {
+            self.output_flow_report_csv(flow_report)?;
+        }
+        if let Some(partner_report) = &cli_input.partner_report {
+            self.output_partner_report_csv(partner_report)?;
+        }
+        if let Some(shadow) = &cli_input.shadow {
+            shadow_execute(&self.accounts, shadow)?;
+        }
+        self.save_txn_registry()?;
+        self.save_snapshot_option(cli_input)?;
+        self.check_conservation()?;
+        match stopped {
+            Some(e) => Err(e.into()),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LogTailer;
+    use crate::cli_io::{parse_cli_args, CliCommand, OutputMethod, OutputSink};
+    use crate::payments_engine::PaymentsEngine;
+    use crate::test::utils::_get_test_output_file;
+    use std::fs::OpenOptions;
+    use std::io::Write;
+    use std::thread;
+    use std::time::Duration;
+
+    fn append(path: &str, contents: &str) {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .unwrap();
+        file.write_all(contents.as_bytes()).unwrap();
+    }
+
+    #[test]
+    fn tst_log_tailer() {
+        let f = _get_test_output_file("tst_log_tailer.csv");
+        let _ = std::fs::remove_file(&f);
+        append(&f, "type, client, tx, amount\ndeposit, 1, 1, 1.0\ndepo");
+        let mut tailer = LogTailer::new(&f, true);
+        assert_eq!(
+            tailer.read_complete_lines().unwrap(),
+            b"deposit, 1, 1, 1.0\n"
+        );
+        assert!(
+            tailer.read_complete_lines().unwrap().is_empty(),
+            "Partial lines should be held back"
+        );
+
+        append(&f, "sit, 1, 2, 2.0\n");
+        assert_eq!(
+            tailer.read_complete_lines().unwrap(),
+            b"deposit, 1, 2, 2.0\n"
+        );
+
+        // Rotated files are read from the start
+        std::fs::write(&f, "type, client, tx, amount\ndeposit, 2, 3, 3.0\n").unwrap();
+        assert_eq!(
+            tailer.read_complete_lines().unwrap(),
+            b"deposit, 2, 3, 3.0\n"
+        );
+    }
+
+    #[test]
+    fn tst_tail_execute() {
+        let f_input = _get_test_output_file("tst_tail_execute.csv");
+        let f_output = _get_test_output_file("tst_tail_execute_accounts.csv");
+        std::fs::write(&f_input, "type, client, tx, amount\ndeposit, 1, 1, 1.0\n").unwrap();
+
+        let args: Vec<String> = ["tail", &f_input, "--output", &f_output]
+            .iter()
+            .map(|a| a.to_string())
+            .collect();
+        let mut tail_options = match parse_cli_args(&args) {
+            Ok(CliCommand::Tail(tail_options)) => tail_options,
+            _ => panic!("Should parse as tail command"),
+        };
+        assert_eq!(
+            tail_options.cli_options.output,
+            [OutputSink::csv(OutputMethod::Csv(f_output.clone()))]
+        );
+        tail_options.idle_exit = Some(Duration::from_millis(1500));
+
+        let writer_input = f_input.clone();
+        let writer = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(200));
+            append(&writer_input, "deposit, 1, 2, 2.0\nwithdrawal, 1, 3, ");
+            thread::sleep(Duration::from_millis(200));
+            append(&writer_input, "0.5\n");
+        });
+
+        let mut payments_engine = PaymentsEngine::new();
+        assert!(payments_engine.tail_execute(&tail_options).is_ok());
+        writer.join().unwrap();
+
+        assert_eq!(payments_engine.accounts.len(), 1);
+        assert_eq!(payments_engine.accounts[0].available().to_f64(), 2.5);
+        let output = std::fs::read_to_string(&f_output).unwrap();
+        assert_eq!(
+            output,
+            "client,available,held,total,locked\n1,2.5000,0.0000,2.5000,false\n"
+        );
+    }
+}
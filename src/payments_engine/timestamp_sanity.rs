@@ -0,0 +1,149 @@
+//! Rejects streamed records whose timestamps are too far from the stream's current time
+//! The current time is the engine's clock, the latest record timestamp under `--clock records`
+//! Records without a timestamp aren't checked, nor is anything before a record clock has seen a timestamp
+
+use super::transactions::TxnErrors;
+use super::PaymentsEngine;
+use crate::transaction::SequencedTxn;
+
+/// Streamed records rejected by the timestamp sanity bounds
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct TimestampRejects {
+    /// Dated too far ahead of the current time
+    pub future: u64,
+    /// Dated too far behind the current time
+    pub stale: u64,
+}
+
+impl PaymentsEngine {
+    /// Counts of records rejected for their timestamps
+    pub fn timestamp_rejects(&self) -> TimestampRejects {
+        self.timestamp_rejects
+    }
+
+    /// Checks a record's timestamp against the sanity bounds, before the clock sees it
+    pub(super) fn check_timestamp(&self, timestamp: i64) -> Result<(), TxnErrors> {
+        let sanity = match &self.config.timestamp_sanity {
+            Some(sanity) => sanity,
+            None => return Ok(()),
+        };
+        let now = self.now();
+        // A record clock reads 0 until the first timestamp sets the stream's time
+        if self.clock.follows_records() && now == 0 {
+            return Ok(());
+        }
+        if sanity
+            .max_future_secs
+            .is_some_and(|max_future| timestamp > now.saturating_add(max_future))
+        {
+            return Err(TxnErrors::FutureTimestamp);
+        }
+        if sanity
+            .max_age_secs
+            .is_some_and(|max_age| timestamp < now.saturating_sub(max_age))
+        {
+            return Err(TxnErrors::StaleTimestamp);
+        }
+        Ok(())
+    }
+
+    /// Counts a record rejected for its timestamp & quarantines it if the run asks to
+    pub(super) fn reject_timestamp(&mut self, s_txn: &SequencedTxn, e: &TxnErrors) {
+        match e {
+            TxnErrors::FutureTimestamp => self.timestamp_rejects.future += 1,
+            _ => self.timestamp_rejects.stale += 1,
+        }
+        if self
+            .config
+            .timestamp_sanity
+            .is_some_and(|sanity| sanity.quarantine)
+        {
+            self.quarantined.push(s_txn.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TimestampRejects;
+    use crate::clock::{ManualClock, RecordClock};
+    use crate::payments_engine::config::{EngineConfig, TimestampSanity};
+    use crate::payments_engine::stream_process::StreamOptions;
+    use crate::payments_engine::PaymentsEngine;
+
+    fn sanity_engine(quarantine: bool) -> PaymentsEngine {
+        let config = EngineConfig {
+            timestamp_sanity: Some(TimestampSanity {
+                max_future_secs: Some(60),
+                max_age_secs: Some(3600),
+                quarantine,
+            }),
+            ..EngineConfig::default()
+        };
+        PaymentsEngine::with_config(config).unwrap()
+    }
+
+    #[test]
+    fn tst_timestamp_sanity() {
+        // Against the wall clock a service would run on
+        let mut payments_engine = sanity_engine(false);
+        payments_engine.set_clock(Box::new(ManualClock::new(1_714_571_100)));
+        let records = "type,client,tx,amount,timestamp\n\
+                       deposit,1,1,10.0,2024-05-01T13:45:00Z\n\
+                       deposit,1,2,1.0,2024-05-01T13:47:00Z\n\
+                       deposit,1,3,1.0,2024-05-01T11:00:00Z\n\
+                       deposit,1,4,1.0,\n";
+        assert!(payments_engine
+            .stream_process_reader(records.as_bytes(), true, &StreamOptions::default())
+            .is_ok());
+        assert_eq!(
+            payments_engine
+                .accounts
+                .get(1)
+                .unwrap()
+                .available()
+                .to_f64(),
+            11.0
+        );
+        assert_eq!(
+            payments_engine.timestamp_rejects(),
+            TimestampRejects {
+                future: 1,
+                stale: 1
+            }
+        );
+        assert!(payments_engine.quarantined_txns().is_empty());
+        assert!(payments_engine
+            .state_summary()
+            .to_string()
+            .contains("future_timestamps=1 stale_timestamps=1"));
+
+        // Against the latest timestamp, a skewed record doesn't move the stream's time
+        let mut payments_engine = sanity_engine(true);
+        payments_engine.set_clock(Box::new(RecordClock::default()));
+        let records = "type,client,tx,amount,timestamp\n\
+                       deposit,1,1,10.0,1714571100\n\
+                       deposit,1,2,1.0,1914571100\n\
+                       deposit,1,3,1.0,1714571130\n\
+                       deposit,1,4,1.0,1714560000\n";
+        assert!(payments_engine
+            .stream_process_reader(records.as_bytes(), true, &StreamOptions::default())
+            .is_ok());
+        assert_eq!(payments_engine.now(), 1_714_571_130);
+        let quarantined: Vec<u64> = payments_engine
+            .quarantined_txns()
+            .iter()
+            .map(|s_txn| s_txn.seq)
+            .collect();
+        assert_eq!(quarantined, [2, 4]);
+        assert_eq!(
+            payments_engine
+                .accounts
+                .get(1)
+                .unwrap()
+                .available()
+                .to_f64(),
+            11.0
+        );
+    }
+}
@@ -1,168 +1,850 @@
+use super::account_store::AcntKey;
+use super::config::{ChargebackPolicy, WithdrawnFundsDispute};
+use super::conservation::FundsFlow;
+use super::id_hash::IdMap;
+use super::pipeline::TxnOutcome;
+use super::txn_arena::TxnKey;
 use super::PaymentsEngine;
 use crate::account::Account;
-use crate::transaction::{PureTxn, RefTxn, Transaction};
+use crate::amount::{Amount, AmountError, Available, TxnAmount, MINOR_UNITS};
+use crate::diagnostics::{log, Level};
+use crate::transaction::{
+    AdminAction, AdminTxn, DisputeHistory, DisputeState, PureTxn, RefTxn, RefundTxn, ReleaseTxn,
+    SequencedTxn, Transaction,
+};
+use std::time::Instant;
 
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone)]
 pub enum TxnErrors {
     AccountDoesNotExist,
     AccountFrozen,
     AccountLacksFunds,
+    AccountUnderReview,
+    /// Deposit or withdrawal far above the amounts its client usually moves
+    AnomalousAmount,
+    /// Txn which would take a balance past the range of minor units
+    BalanceOverflow,
+    ChannelLimitExceeded,
+    /// Deposit opening an account for a client no onboarding record opened, under `--require-onboarding`
+    ClientNotOnboarded,
+    DisputeWindowClosed,
+    DuplicateCheckFailed,
+    /// Streamed record dated further ahead of the stream's current time than `--max-future-skew` allows
+    FutureTimestamp,
+    /// Withdrawal by an onboarded client who hasn't passed KYC
+    KycNotVerified,
+    OutOfSequence,
+    /// Release of nothing, or of all a dispute holds or more, which takes a resolve
+    PartialReleaseOutOfRange,
+    RefTxnNotDisputable,
+    /// Refund of anything other than a deposit
+    RefTxnNotRefundable,
+    /// Dispute, resolve, or chargeback of a txn which belongs to another client
+    RefTxnOfOtherClient,
+    /// Refund larger than what is left of the deposit after earlier refunds
+    RefundExceedsDeposit,
+    /// A `--script` rule rejected the txn
+    RejectedByScript,
+    /// Streamed record dated further behind the stream's current time than `--max-timestamp-age` allows
+    StaleTimestamp,
+    /// Withdrawal or dispute which would leave a reserve account's available funds below its floor
+    ReserveFloorBreached,
+    /// A `--script` rule failed or gave a value it can't, the txn is rejected as it wasn't vetted
+    ScriptFailed,
+    TxnAlreadyChargedBack,
     TxnAlreadyDisputed,
     TxnIdAlreadyExists,
     TxnIdDoesNotExist,
     TxnMustBeDisputed,
+    TooManyOpenDisputes,
+    TxnNotPendingApproval,
+    TxnPendingApproval,
+}
+
+/// Rejects a txn whose fund movement overflowed, the account was left as it was
+pub(super) fn overflowed(_: AmountError) -> TxnErrors {
+    TxnErrors::BalanceOverflow
+}
+
+/// Amount a dispute moves from available to held under the withdrawn funds policy
+pub(super) fn dispute_hold(
+    policy: WithdrawnFundsDispute,
+    available: Available,
+    amount: TxnAmount,
+) -> TxnAmount {
+    match policy {
+        WithdrawnFundsDispute::CapAtAvailable if available.amount() < amount.amount() => {
+            TxnAmount::new(available.amount().max(Amount::default()))
+        }
+        _ => amount,
+    }
+}
+
+/// Checks a dispute, resolve, or chargeback may move a txn's dispute state to `next`
+fn check_dispute_transition(history: &DisputeHistory, next: DisputeState) -> Result<(), TxnErrors> {
+    let state = history.state();
+    if state.can_transition(next) {
+        return Ok(());
+    }
+    Err(match state {
+        DisputeState::ChargedBack => TxnErrors::TxnAlreadyChargedBack,
+        DisputeState::Disputed => TxnErrors::TxnAlreadyDisputed,
+        DisputeState::Undisputed | DisputeState::Resolved => TxnErrors::TxnMustBeDisputed,
+    })
 }
 
 impl PaymentsEngine {
-    /// Takes input withdrawl txn and applies it if valid, else returns an error message
-    fn process_deposit(&mut self, p_txn: &PureTxn) -> Result<(), TxnErrors> {
-        if self.txn_map.get(&p_txn.txn_id).is_some() {
-            return Err(TxnErrors::TxnIdAlreadyExists);
+    /// Checks a pure txn id against the probabilistic duplicate store, if one is configured
+    /// `check_txn_id` checks it first so a store which can't be read is rejected as such, not as a duplicate
+    fn check_duplicate_store(&self, txn_id: u32) -> Result<(), TxnErrors> {
+        let filter = match &self.dup_filter {
+            Some(filter) => filter,
+            None => return Ok(()),
+        };
+        match filter.contains(txn_id) {
+            Ok(true) => Err(TxnErrors::TxnIdAlreadyExists),
+            Ok(false) => Ok(()),
+            Err(_) => Err(TxnErrors::DuplicateCheckFailed),
         }
-        if let Some(acnt_indx) = self.acnt_map.get(&p_txn.acnt_id) {
-            if self.accounts[*acnt_indx].frozen {
-                return Err(TxnErrors::AccountFrozen);
+    }
+
+    /// True if a pure txn id was already accepted, by the registry or the stored ids
+    fn is_known_txn_id(&self, txn_id: u32) -> bool {
+        self.is_registered_txn(txn_id) || self.is_live_txn_id(txn_id)
+    }
+
+    /// True if a stored txn id is still taken, ids stored in an earlier id epoch may be reused
+    pub(super) fn is_live_txn_id(&self, txn_id: u32) -> bool {
+        self.is_stored_txn_id(txn_id) && !self.is_recyclable_txn_id(txn_id)
+    }
+
+    /// True if a stored txn id was stored in an earlier id epoch than the current record's & may be reused
+    /// Ids of txns still under dispute or pending approval stay in use
+    pub(super) fn is_recyclable_txn_id(&self, txn_id: u32) -> bool {
+        let (epoch, txn_key) = match (self.config.id_epoch, self.txn_map.get(&txn_id)) {
+            (Some(epoch), Some(txn_key)) => (epoch, *txn_key),
+            _ => return false,
+        };
+        let s_txn = &self.processed_txns[txn_key];
+        let in_use = match &s_txn.txn {
+            Transaction::Deposit(p_txn) | Transaction::Withdrawal(p_txn) => {
+                p_txn.dispute.is_disputed() || self.is_pending_approval(txn_id)
             }
-            self.accounts[*acnt_indx].available += p_txn.amount;
-            self.processed_txns
-                .push(Transaction::Deposit(p_txn.clone()));
-            self.txn_map
-                .insert(p_txn.txn_id, self.processed_txns.len() - 1);
-        } else {
-            let new_account = Account {
-                id: p_txn.acnt_id,
-                available: p_txn.amount,
-                held: 0.0,
-                frozen: false,
-            };
-            self.acnt_map.insert(new_account.id, self.accounts.len());
-            self.accounts.push(new_account);
-            self.processed_txns
-                .push(Transaction::Deposit(p_txn.clone()));
-            self.txn_map
-                .insert(p_txn.txn_id, self.processed_txns.len() - 1);
+            _ => false,
+        };
+        !in_use && s_txn.seq / epoch < self.last_seq / epoch
+    }
+
+    /// True if the txn map or the compacted withdrawal ids hold the id, or the duplicate store in their place
+    /// A duplicate store which can't be read counts the id as stored, so it never lets a duplicate through
+    pub(super) fn is_stored_txn_id(&self, txn_id: u32) -> bool {
+        match &self.dup_filter {
+            Some(filter) => filter.contains(txn_id).unwrap_or(true),
+            None => self.txn_map.contains_key(&txn_id) || self.withdrawal_ids.contains(&txn_id),
         }
+    }
+
+    /// Key of the stored txn with this id, from the txn map or the duplicate store holding ids in its place
+    fn lookup_txn_key(&self, txn_id: u32) -> Result<Option<TxnKey>, TxnErrors> {
+        match &self.dup_filter {
+            Some(filter) => filter
+                .txn_key(txn_id)
+                .map_err(|_| TxnErrors::DuplicateCheckFailed),
+            None => Ok(self.txn_map.get(&txn_id).copied()),
+        }
+    }
+
+    /// Key of the stored txn with this id, a duplicate store which can't be read is logged & finds nothing
+    pub(super) fn stored_txn_key(&self, txn_id: u32) -> Option<TxnKey> {
+        self.lookup_txn_key(txn_id).unwrap_or_else(|_| {
+            log(
+                Level::Error,
+                format_args!("Failed to read txn {} from the duplicate store", txn_id),
+            );
+            None
+        })
+    }
 
+    /// Stores an accepted pure txn so it can be referenced & checked for duplicates
+    /// Should be called before account balances are mutated as writing to the duplicate filter may fail
+    fn record_pure_txn(&mut self, txn_id: u32, txn: Transaction) -> Result<(), TxnErrors> {
+        if self.is_recyclable_txn_id(txn_id) {
+            // The id now refers to the new txn, refunds or a denial of the old one don't carry over
+            self.refunded.remove(&txn_id);
+            self.denied_withdrawals.remove(&txn_id);
+            self.recycled_txn_ids += 1;
+            self.metrics.counter("txns.recycled_ids", 1);
+        }
+        let compacted =
+            self.config.compact_withdrawals && matches!(txn, Transaction::Withdrawal(_));
+        if let Some(filter) = &mut self.dup_filter {
+            // Stored ahead of its txn so a failed write leaves nothing behind, compacted txns have no key
+            let txn_key = (!compacted).then(|| self.processed_txns.next_key());
+            filter
+                .insert(txn_id, txn_key)
+                .map_err(|_| TxnErrors::DuplicateCheckFailed)?;
+        }
+        self.register_txn(txn_id);
+        if compacted {
+            // A duplicate filter already remembers the id
+            if self.dup_filter.is_none() {
+                self.withdrawal_ids.insert(txn_id);
+            }
+            return Ok(());
+        }
+        let txn_key = self.push_processed(txn);
+        if self.dup_filter.is_some() {
+            // The duplicate store keeps the key, ids aren't held in memory
+            return Ok(());
+        }
+        let was_direct = self.txn_map.is_direct();
+        self.txn_map.insert(txn_id, txn_key);
+        if was_direct && !self.txn_map.is_direct() {
+            log(
+                Level::Info,
+                format_args!(
+                    "Txn ids are too sparse to index directly from txn {}, hashing them instead",
+                    txn_id
+                ),
+            );
+        }
         Ok(())
     }
 
-    /// Takes input withdrawl txn and applies it if valid, else returns an error message
-    fn process_withdrawl(&mut self, p_txn: &PureTxn) -> Result<(), TxnErrors> {
-        if self.txn_map.get(&p_txn.txn_id).is_some() {
+    /// Stores an accepted txn with the sequence number it is being processed under, returns its key
+    fn push_processed(&mut self, txn: Transaction) -> TxnKey {
+        let acnt_id = txn.acnt_id();
+        let txn_key = self.processed_txns.push(SequencedTxn {
+            seq: self.last_seq,
+            txn,
+        });
+        self.account_txns.entry(acnt_id).or_default().push(txn_key);
+        self.last_activity.insert(acnt_id, self.last_seq);
+        txn_key
+    }
+
+    /// Checks a deposit could be applied, without changing any state
+    /// Duplicate ids are checked against stored ids & the registry
+    pub fn validate_deposit(&self, p_txn: &PureTxn) -> Result<(), TxnErrors> {
+        if self.is_known_txn_id(p_txn.txn_id) {
             return Err(TxnErrors::TxnIdAlreadyExists);
         }
-        if let Some(ii) = self.acnt_map.get(&p_txn.acnt_id) {
-            if self.accounts[*ii].available < p_txn.amount {
-                return Err(TxnErrors::AccountLacksFunds);
+        self.check_deposit(p_txn)
+    }
+
+    /// Checks a deposit against account state, its id is left to `check_txn_id`
+    fn check_deposit(&self, p_txn: &PureTxn) -> Result<(), TxnErrors> {
+        self.check_channel_limit(p_txn)?;
+        // A deposit which can't cover its own fee would leave the client owing
+        if p_txn.amount < self.channel_fee(p_txn) {
+            return Err(TxnErrors::AccountLacksFunds);
+        }
+        match self.accounts.get(p_txn.acnt_id) {
+            Some(acnt) if acnt.is_locked() => return Err(TxnErrors::AccountFrozen),
+            Some(acnt) => {
+                self.deposited(acnt, p_txn)?;
+            }
+            None if self.config.require_onboarding => return Err(TxnErrors::ClientNotOnboarded),
+            None => {}
+        }
+        Ok(())
+    }
+
+    /// Account as a deposit leaves it, credited its amount & debited its fee
+    /// Errors if either movement overflows, before anything is applied
+    fn deposited(&self, acnt: &Account, p_txn: &PureTxn) -> Result<Account, TxnErrors> {
+        let mut acnt = acnt.clone();
+        acnt.credit(p_txn.amount).map_err(overflowed)?;
+        acnt.debit(self.channel_fee(p_txn)).map_err(overflowed)?;
+        Ok(acnt)
+    }
+
+    /// Applies a deposit the validate & dedup stages accepted
+    fn process_deposit(&mut self, p_txn: &PureTxn) -> Result<(), TxnErrors> {
+        let fee = self.channel_fee(p_txn);
+        let acnt_key = self.accounts.key(p_txn.acnt_id);
+        let deposited = match acnt_key {
+            Some(acnt_key) => self.deposited(&self.accounts[acnt_key], p_txn)?,
+            None => self.deposited(&Account::new(p_txn.acnt_id), p_txn)?,
+        };
+        self.record_pure_txn(p_txn.txn_id, Transaction::Deposit(p_txn.clone()))?;
+        match acnt_key {
+            Some(acnt_key) => self.accounts[acnt_key] = deposited,
+            None => {
+                self.accounts.insert(deposited);
             }
-            if self.accounts[*ii].frozen {
-                return Err(TxnErrors::AccountFrozen);
+        }
+        self.record_funds_flow(FundsFlow::Deposit, p_txn.amount);
+        self.record_funds_flow(FundsFlow::Fee, fee);
+        Ok(())
+    }
+
+    /// Checks a withdrawal could be applied, without changing any state
+    /// Duplicate ids are checked as `validate_deposit` checks them
+    pub fn validate_withdrawal(&self, p_txn: &PureTxn) -> Result<(), TxnErrors> {
+        if self.is_known_txn_id(p_txn.txn_id) {
+            return Err(TxnErrors::TxnIdAlreadyExists);
+        }
+        self.check_withdrawal(p_txn)
+    }
+
+    /// Checks a withdrawal against account state, its id is left to `check_txn_id`
+    fn check_withdrawal(&self, p_txn: &PureTxn) -> Result<(), TxnErrors> {
+        self.check_channel_limit(p_txn)?;
+        let acnt = self
+            .accounts
+            .get(p_txn.acnt_id)
+            .ok_or(TxnErrors::AccountDoesNotExist)?;
+        // Compared in minor units, summed wider than an i64 so huge amounts & limits can't overflow the check
+        let overdraft = (self.config.overdraft_limit * MINOR_UNITS as f64).round() as i128;
+        let within_reach = acnt.available().minor_units() as i128 + overdraft;
+        let taken =
+            p_txn.amount.minor_units() as i128 + self.channel_fee(p_txn).minor_units() as i128;
+        if within_reach < taken {
+            return Err(TxnErrors::AccountLacksFunds);
+        }
+        let withdrawn = self.withdrawn(acnt, p_txn)?;
+        if acnt.is_locked() {
+            return Err(TxnErrors::AccountFrozen);
+        }
+        if self
+            .client_profile(p_txn.acnt_id)
+            .is_some_and(|profile| !profile.kyc_verified)
+        {
+            return Err(TxnErrors::KycNotVerified);
+        }
+        self.check_reserve_floor(p_txn.acnt_id, withdrawn.available())
+    }
+
+    /// Account as a withdrawal leaves it, debited its fee & its amount, or holding the amount while the
+    /// withdrawal waits on approval. Errors if either movement overflows, before anything is applied
+    fn withdrawn(&self, acnt: &Account, p_txn: &PureTxn) -> Result<Account, TxnErrors> {
+        let mut acnt = acnt.clone();
+        acnt.debit(self.channel_fee(p_txn)).map_err(overflowed)?;
+        match self.needs_approval(p_txn.amount) {
+            true => acnt.hold(p_txn.amount),
+            false => acnt.debit(p_txn.amount),
+        }
+        .map_err(overflowed)?;
+        Ok(acnt)
+    }
+
+    /// Checks a reserve account's available funds stay at or above its floor, other accounts have none
+    fn check_reserve_floor(&self, acnt_id: u16, available: Available) -> Result<(), TxnErrors> {
+        match self.config.reserve_floors.get(&acnt_id) {
+            Some(floor) if available < Available::from_f64(*floor) => {
+                Err(TxnErrors::ReserveFloorBreached)
             }
-            self.accounts[*ii].available -= p_txn.amount;
-            self.processed_txns
-                .push(Transaction::Withdrawal(p_txn.clone()));
-            self.txn_map
-                .insert(p_txn.txn_id, self.processed_txns.len() - 1);
+            _ => Ok(()),
+        }
+    }
+
+    /// Flags the account of a txn validation rejected for review when the rejection calls for it,
+    /// a dispute flood if configured or an attempt to breach a reserve floor, so it reaches the compliance feed
+    pub(super) fn flag_rejection(&mut self, txn: &Transaction, e: &TxnErrors) {
+        let flagged = match e {
+            TxnErrors::TooManyOpenDisputes => self.config.flag_dispute_floods,
+            TxnErrors::ReserveFloorBreached => true,
+            _ => false,
+        };
+        if flagged {
+            self.flagged_for_review.insert(txn.acnt_id());
+        }
+    }
+
+    /// Applies a withdrawal the validate & dedup stages accepted
+    fn process_withdrawl(&mut self, p_txn: &PureTxn) -> Result<(), TxnErrors> {
+        let ii = self
+            .accounts
+            .key(p_txn.acnt_id)
+            .ok_or(TxnErrors::AccountDoesNotExist)?;
+        let fee = self.channel_fee(p_txn);
+        // The fee & the amount are both checked before either is taken, so neither is taken alone
+        let withdrawn = self.withdrawn(&self.accounts[ii], p_txn)?;
+        self.record_pure_txn(p_txn.txn_id, Transaction::Withdrawal(p_txn.clone()))?;
+        self.accounts[ii] = withdrawn;
+        self.record_funds_flow(FundsFlow::Fee, fee);
+        if self.needs_approval(p_txn.amount) {
+            self.park_withdrawal(p_txn);
         } else {
-            return Err(TxnErrors::AccountDoesNotExist);
+            self.record_funds_flow(FundsFlow::Withdrawal, p_txn.amount);
         }
         Ok(())
     }
 
-    // Returns Account & Transaction Indices or error string
-    fn get_ref_txn_indicies(&self, ref_txn: &RefTxn) -> Result<(usize, usize), TxnErrors> {
-        let acnt_indx = self.acnt_map.get(&ref_txn.acnt_id);
-        if acnt_indx.is_none() {
+    // Returns Account & Transaction keys or error string
+    pub(super) fn get_ref_txn_keys(
+        &self,
+        ref_txn: &RefTxn,
+    ) -> Result<(AcntKey, TxnKey), TxnErrors> {
+        let acnt_key = self.accounts.key(ref_txn.acnt_id);
+        if acnt_key.is_none() {
             return Err(TxnErrors::AccountDoesNotExist);
         }
-        let acnt_indx = *acnt_indx.unwrap();
-        if self.accounts[acnt_indx].frozen {
+        let acnt_key = acnt_key.unwrap();
+        if self.accounts[acnt_key].is_locked() {
             return Err(TxnErrors::AccountFrozen);
         }
 
-        let txn_indx = self.txn_map.get(&ref_txn.ref_id);
-        if txn_indx.is_none() {
-            return Err(TxnErrors::TxnIdDoesNotExist);
+        let txn_key = match self.lookup_txn_key(ref_txn.ref_id)? {
+            Some(txn_key) => txn_key,
+            // Compacted withdrawals & txns of collected accounts are known without their txn, so only as not disputable
+            None if self.is_stored_txn_id(ref_txn.ref_id) => {
+                return Err(TxnErrors::RefTxnNotDisputable)
+            }
+            None => return Err(TxnErrors::TxnIdDoesNotExist),
         };
-        Ok((acnt_indx, *txn_indx.unwrap()))
+        if self.processed_txns[txn_key].txn.acnt_id() != ref_txn.acnt_id {
+            return Err(TxnErrors::RefTxnOfOtherClient);
+        }
+        if self.is_pending_approval(ref_txn.ref_id) {
+            return Err(TxnErrors::TxnPendingApproval);
+        }
+        Ok((acnt_key, txn_key))
     }
 
-    /// Takes input dispute txn and applies it if valid, else returns an error message
-    fn process_dispute(&mut self, ref_txn: &RefTxn) -> Result<(), TxnErrors> {
-        let (acnt_indx, txn_indx) = self.get_ref_txn_indicies(ref_txn)?;
+    /// Keys of a validated ref txn's account & the txn it refers to
+    fn ref_keys(&self, acnt_id: u16, ref_id: u32) -> Result<(AcntKey, TxnKey), TxnErrors> {
+        let acnt_key = self
+            .accounts
+            .key(acnt_id)
+            .ok_or(TxnErrors::AccountDoesNotExist)?;
+        let txn_key = self
+            .lookup_txn_key(ref_id)?
+            .ok_or(TxnErrors::TxnIdDoesNotExist)?;
+        Ok((acnt_key, txn_key))
+    }
 
-        match &mut self.processed_txns[txn_indx] {
+    /// Checks a dispute may be opened, returns the keys of its account & the disputed txn
+    fn check_dispute(&self, ref_txn: &RefTxn) -> Result<(AcntKey, TxnKey), TxnErrors> {
+        let (acnt_key, txn_key) = self.get_ref_txn_keys(ref_txn)?;
+        if !self
+            .config
+            .disputable_txns
+            .allows(&self.processed_txns[txn_key].txn)
+            || self.denied_withdrawals.contains(&ref_txn.ref_id)
+        {
+            return Err(TxnErrors::RefTxnNotDisputable);
+        }
+        self.check_dispute_window(txn_key)?;
+        let amount = match &self.processed_txns[txn_key].txn {
             // Assumption can only have referential transactions on withdrawals & deposits
             Transaction::Withdrawal(disputed_txn) | Transaction::Deposit(disputed_txn) => {
-                if disputed_txn.disputed {
-                    return Err(TxnErrors::TxnAlreadyDisputed);
-                }
+                check_dispute_transition(&disputed_txn.dispute, DisputeState::Disputed)?;
+                disputed_txn.amount
+            }
+            _ => return Err(TxnErrors::RefTxnNotDisputable),
+        };
+        let open_disputes = self.open_disputes.get(&ref_txn.acnt_id).copied();
+        if let Some(max_open_disputes) = self.config.max_open_disputes {
+            if open_disputes.unwrap_or(0) >= max_open_disputes {
+                return Err(TxnErrors::TooManyOpenDisputes);
+            }
+        }
+        if self.config.reserve_floors.contains_key(&ref_txn.acnt_id) {
+            let mut held = self.accounts[acnt_key].clone();
+            let disputable = self.disputable(ref_txn.ref_id, amount)?;
+            let hold = dispute_hold(
+                self.config.withdrawn_funds_dispute,
+                held.available(),
+                disputable,
+            );
+            held.hold(hold).map_err(overflowed)?;
+            self.check_reserve_floor(ref_txn.acnt_id, held.available())?;
+        }
+        Ok((acnt_key, txn_key))
+    }
 
-                self.accounts[acnt_indx].available -= disputed_txn.amount;
-                self.accounts[acnt_indx].held += disputed_txn.amount;
+    /// Part of a txn's amount a dispute can hold, refunded funds already went back to the payer
+    pub(super) fn disputable(
+        &self,
+        ref_id: u32,
+        amount: TxnAmount,
+    ) -> Result<TxnAmount, TxnErrors> {
+        match self.refunded.get(&ref_id) {
+            Some(refunded) => amount.checked_sub(*refunded).map_err(overflowed),
+            None => Ok(amount),
+        }
+    }
+
+    /// Checks a dispute could be applied, without changing any state
+    /// A dispute flood isn't flagged for review, only processing flags it
+    pub fn validate_dispute(&self, ref_txn: &RefTxn) -> Result<(), TxnErrors> {
+        self.check_dispute(ref_txn).map(|_| ())
+    }
 
-                disputed_txn.disputed = true;
-                self.processed_txns
-                    .push(Transaction::Dispute(ref_txn.clone()))
+    /// Applies a dispute the validate stage accepted
+    fn process_dispute(&mut self, ref_txn: &RefTxn) -> Result<(), TxnErrors> {
+        let (acnt_key, txn_key) = self.ref_keys(ref_txn.acnt_id, ref_txn.ref_id)?;
+
+        let amount = match &self.processed_txns[txn_key].txn {
+            Transaction::Withdrawal(disputed_txn) | Transaction::Deposit(disputed_txn) => {
+                disputed_txn.amount
+            }
+            _ => return Err(TxnErrors::RefTxnNotDisputable),
+        };
+        let disputable = self.disputable(ref_txn.ref_id, amount)?;
+        match &mut self.processed_txns[txn_key].txn {
+            Transaction::Withdrawal(disputed_txn) | Transaction::Deposit(disputed_txn) => {
+                let acnt = &mut self.accounts[acnt_key];
+                let policy = self.config.withdrawn_funds_dispute;
+                let available = acnt.available();
+                let hold = dispute_hold(policy, available, disputable);
+                acnt.hold(hold).map_err(overflowed)?;
+
+                *self.open_disputes.entry(ref_txn.acnt_id).or_default() += 1;
+                if hold != disputed_txn.amount {
+                    self.held_amounts.insert(ref_txn.ref_id, hold);
+                }
+                if available.amount() < disputable.amount()
+                    && policy == WithdrawnFundsDispute::FlagForReview
+                {
+                    self.flagged_for_review.insert(acnt.id);
+                }
+
+                disputed_txn
+                    .dispute
+                    .record(DisputeState::Disputed, self.last_seq);
+                self.push_processed(Transaction::Dispute(ref_txn.clone()));
             }
-            _ => panic!("Only indices of PureTxns should be given from get_ref_txn_indicies()"),
+            _ => return Err(TxnErrors::RefTxnNotDisputable),
         }
         Ok(())
     }
 
-    /// Takes input resolve txn and applies it if valid, else returns an error message
-    fn process_resolve(&mut self, ref_txn: &RefTxn) -> Result<(), TxnErrors> {
-        let (acnt_indx, txn_indx) = self.get_ref_txn_indicies(ref_txn)?;
-        match &mut self.processed_txns[txn_indx] {
+    /// Decrements an account's open dispute count once a dispute is resolved or charged back
+    fn close_dispute(open_disputes: &mut IdMap<u16, usize>, acnt_id: u16) {
+        if let Some(open_disputes) = open_disputes.get_mut(&acnt_id) {
+            *open_disputes = open_disputes.saturating_sub(1);
+        }
+    }
+
+    /// Checks a resolve or chargeback may close a txn's dispute, returns the keys of its account & the txn
+    fn check_dispute_close(
+        &self,
+        ref_txn: &RefTxn,
+        next: DisputeState,
+    ) -> Result<(AcntKey, TxnKey), TxnErrors> {
+        let (acnt_key, txn_key) = self.get_ref_txn_keys(ref_txn)?;
+        match &self.processed_txns[txn_key].txn {
             // Assumption can only have referential transactions on withdrawals & deposits
             Transaction::Withdrawal(disputed_txn) | Transaction::Deposit(disputed_txn) => {
-                if !disputed_txn.disputed {
-                    return Err(TxnErrors::TxnMustBeDisputed);
-                }
-                self.accounts[acnt_indx].held -= disputed_txn.amount;
-                self.accounts[acnt_indx].available += disputed_txn.amount;
+                check_dispute_transition(&disputed_txn.dispute, next)?;
+                Ok((acnt_key, txn_key))
+            }
+            _ => Err(TxnErrors::RefTxnNotDisputable),
+        }
+    }
 
-                disputed_txn.disputed = false;
-                self.processed_txns
-                    .push(Transaction::Resolve(ref_txn.clone()))
+    /// Checks a resolve could be applied, without changing any state
+    pub fn validate_resolve(&self, ref_txn: &RefTxn) -> Result<(), TxnErrors> {
+        self.check_dispute_close(ref_txn, DisputeState::Resolved)
+            .map(|_| ())
+    }
+
+    /// Applies a resolve the validate stage accepted
+    fn process_resolve(&mut self, ref_txn: &RefTxn) -> Result<(), TxnErrors> {
+        let (acnt_key, txn_key) = self.ref_keys(ref_txn.acnt_id, ref_txn.ref_id)?;
+        match &mut self.processed_txns[txn_key].txn {
+            Transaction::Withdrawal(disputed_txn) | Transaction::Deposit(disputed_txn) => {
+                let hold = self
+                    .held_amounts
+                    .get(&ref_txn.ref_id)
+                    .copied()
+                    .unwrap_or(disputed_txn.amount);
+                self.accounts[acnt_key].release(hold).map_err(overflowed)?;
+                self.held_amounts.remove(&ref_txn.ref_id);
+                Self::close_dispute(&mut self.open_disputes, ref_txn.acnt_id);
+
+                disputed_txn
+                    .dispute
+                    .record(DisputeState::Resolved, self.last_seq);
+                self.push_processed(Transaction::Resolve(ref_txn.clone()));
             }
-            _ => panic!("Only indices of PureTxns should be given from get_ref_txn_indicies()"),
+            _ => return Err(TxnErrors::RefTxnNotDisputable),
         }
         Ok(())
     }
 
-    /// Takes input chargeback txn and applies it if valid, else returns an error message
+    /// Checks a chargeback could be applied, without changing any state
+    pub fn validate_chargeback(&self, ref_txn: &RefTxn) -> Result<(), TxnErrors> {
+        self.check_dispute_close(ref_txn, DisputeState::ChargedBack)
+            .map(|_| ())
+    }
+
+    /// Applies a chargeback the validate stage accepted
     fn process_chargeback(&mut self, ref_txn: &RefTxn) -> Result<(), TxnErrors> {
-        let (acnt_indx, txn_indx) = self.get_ref_txn_indicies(ref_txn)?;
-        // Assumption can only have referential transactions on withdrawals & deposits
-        match &mut self.processed_txns[txn_indx] {
+        let (acnt_key, txn_key) = self.ref_keys(ref_txn.acnt_id, ref_txn.ref_id)?;
+        match &mut self.processed_txns[txn_key].txn {
             Transaction::Withdrawal(disputed_txn) | Transaction::Deposit(disputed_txn) => {
-                if !disputed_txn.disputed {
-                    return Err(TxnErrors::TxnMustBeDisputed);
+                let hold = self
+                    .held_amounts
+                    .get(&ref_txn.ref_id)
+                    .copied()
+                    .unwrap_or(disputed_txn.amount);
+                self.accounts[acnt_key]
+                    .settle_held(hold)
+                    .map_err(overflowed)?;
+                self.held_amounts.remove(&ref_txn.ref_id);
+                Self::close_dispute(&mut self.open_disputes, ref_txn.acnt_id);
+                if self.config.chargeback_policy == ChargebackPolicy::Freeze {
+                    self.accounts[acnt_key].locked_by_chargeback = true;
                 }
-                self.accounts[acnt_indx].held -= disputed_txn.amount;
-                self.accounts[acnt_indx].frozen = true;
 
-                disputed_txn.disputed = false;
+                disputed_txn
+                    .dispute
+                    .record(DisputeState::ChargedBack, self.last_seq);
+                self.record_funds_flow(FundsFlow::Chargeback, hold);
 
-                self.processed_txns
-                    .push(Transaction::Chargeback(ref_txn.clone()))
+                self.push_processed(Transaction::Chargeback(ref_txn.clone()));
             }
-            _ => panic!("Only indices of PureTxns should be given from get_ref_txn_indicies()"),
+            _ => return Err(TxnErrors::RefTxnNotDisputable),
         }
         Ok(())
     }
 
+    /// Checks a release against its disputed txn, returns the account key & the amount held before it
+    pub(super) fn check_release(
+        &self,
+        release_txn: &ReleaseTxn,
+    ) -> Result<(AcntKey, TxnAmount), TxnErrors> {
+        let ref_txn = RefTxn {
+            ref_id: release_txn.ref_id,
+            acnt_id: release_txn.acnt_id,
+        };
+        // Resolving checks the txn is disputed, which a release needs too
+        let (acnt_key, txn_key) = self.check_dispute_close(&ref_txn, DisputeState::Resolved)?;
+        let hold = match &self.processed_txns[txn_key].txn {
+            Transaction::Withdrawal(disputed_txn) | Transaction::Deposit(disputed_txn) => self
+                .held_amounts
+                .get(&release_txn.ref_id)
+                .copied()
+                .unwrap_or(disputed_txn.amount),
+            _ => return Err(TxnErrors::RefTxnNotDisputable),
+        };
+        if release_txn.amount <= TxnAmount::default() || release_txn.amount >= hold {
+            return Err(TxnErrors::PartialReleaseOutOfRange);
+        }
+        Ok((acnt_key, hold))
+    }
+
+    /// Checks a release could be applied, without changing any state
+    pub fn validate_release(&self, release_txn: &ReleaseTxn) -> Result<(), TxnErrors> {
+        self.check_release(release_txn).map(|_| ())
+    }
+
+    /// Returns part of the disputed txn's held funds to available, for a release the validate stage accepted
+    fn process_release(&mut self, release_txn: &ReleaseTxn) -> Result<(), TxnErrors> {
+        let (acnt_key, txn_key) = self.ref_keys(release_txn.acnt_id, release_txn.ref_id)?;
+        let hold = match &self.processed_txns[txn_key].txn {
+            Transaction::Withdrawal(disputed_txn) | Transaction::Deposit(disputed_txn) => self
+                .held_amounts
+                .get(&release_txn.ref_id)
+                .copied()
+                .unwrap_or(disputed_txn.amount),
+            _ => return Err(TxnErrors::RefTxnNotDisputable),
+        };
+        let left = hold.checked_sub(release_txn.amount).map_err(overflowed)?;
+        self.accounts[acnt_key]
+            .release(release_txn.amount)
+            .map_err(overflowed)?;
+        self.held_amounts.insert(release_txn.ref_id, left);
+        self.push_processed(Transaction::Release(release_txn.clone()));
+        Ok(())
+    }
+
+    /// Checks a refund against its deposit, returns the account key & the amount refunded so far
+    pub(super) fn check_refund(
+        &self,
+        refund_txn: &RefundTxn,
+    ) -> Result<(AcntKey, TxnAmount), TxnErrors> {
+        let ref_txn = RefTxn {
+            ref_id: refund_txn.ref_id,
+            acnt_id: refund_txn.acnt_id,
+        };
+        let (acnt_key, txn_key) = self.get_ref_txn_keys(&ref_txn).map_err(|e| match e {
+            // Compacted withdrawals are reported as not disputable
+            TxnErrors::RefTxnNotDisputable => TxnErrors::RefTxnNotRefundable,
+            e => e,
+        })?;
+        let deposit = match &self.processed_txns[txn_key].txn {
+            Transaction::Deposit(p_txn) => p_txn,
+            _ => return Err(TxnErrors::RefTxnNotRefundable),
+        };
+        match deposit.dispute.state() {
+            DisputeState::Disputed => return Err(TxnErrors::TxnAlreadyDisputed),
+            DisputeState::ChargedBack => return Err(TxnErrors::TxnAlreadyChargedBack),
+            DisputeState::Undisputed | DisputeState::Resolved => {}
+        }
+        let refunded = self
+            .refunded
+            .get(&refund_txn.ref_id)
+            .copied()
+            .unwrap_or_default();
+        let total = refunded
+            .checked_add(refund_txn.amount)
+            .map_err(|_| TxnErrors::RefundExceedsDeposit)?;
+        if total > deposit.amount {
+            return Err(TxnErrors::RefundExceedsDeposit);
+        }
+        if self.accounts[acnt_key].available().amount() < refund_txn.amount.amount() {
+            return Err(TxnErrors::AccountLacksFunds);
+        }
+        Ok((acnt_key, refunded))
+    }
+
+    /// Checks a refund could be applied, without changing any state
+    pub fn validate_refund(&self, refund_txn: &RefundTxn) -> Result<(), TxnErrors> {
+        self.check_refund(refund_txn).map(|_| ())
+    }
+
+    /// Applies a refund the validate stage accepted
+    fn process_refund(&mut self, refund_txn: &RefundTxn) -> Result<(), TxnErrors> {
+        let (acnt_key, _) = self.ref_keys(refund_txn.acnt_id, refund_txn.ref_id)?;
+        let refunded = self
+            .refunded
+            .get(&refund_txn.ref_id)
+            .copied()
+            .unwrap_or_default()
+            .checked_add(refund_txn.amount)
+            .map_err(overflowed)?;
+        self.accounts[acnt_key]
+            .debit(refund_txn.amount)
+            .map_err(overflowed)?;
+        self.record_funds_flow(FundsFlow::Refund, refund_txn.amount);
+        self.refunded.insert(refund_txn.ref_id, refunded);
+        self.push_processed(Transaction::Refund(refund_txn.clone()));
+        Ok(())
+    }
+
+    /// Checks an admin instruction could be applied, without changing any state
+    /// Approvals & denials need a withdrawal of the client pending approval
+    pub fn validate_admin(&self, admin_txn: &AdminTxn) -> Result<(), TxnErrors> {
+        if self.accounts.key(admin_txn.acnt_id).is_none() {
+            return Err(TxnErrors::AccountDoesNotExist);
+        }
+        if let AdminAction::Approve | AdminAction::Deny = admin_txn.action {
+            self.pending_for(admin_txn)?;
+        }
+        Ok(())
+    }
+
+    /// Applies an admin instruction the validate stage accepted
+    /// Admin instructions apply to locked accounts so holds can be cleared
+    fn process_admin(&mut self, admin_txn: &AdminTxn) -> Result<(), TxnErrors> {
+        let acnt_key = self
+            .accounts
+            .key(admin_txn.acnt_id)
+            .ok_or(TxnErrors::AccountDoesNotExist)?;
+        match admin_txn.action {
+            AdminAction::SetHold => self.accounts[acnt_key].admin_hold = true,
+            AdminAction::ClearHold => {
+                self.accounts[acnt_key].admin_hold = false;
+                self.clear_review(admin_txn.acnt_id);
+            }
+            AdminAction::Approve | AdminAction::Deny => {
+                self.decide_withdrawal(acnt_key, admin_txn)?
+            }
+        }
+        self.push_processed(Transaction::Admin(admin_txn.clone()));
+        Ok(())
+    }
+
+    /// Checks a txn against account & txn state as applying it would, without changing any state
+    /// Pipeline stages aren't run, so sequencing, the dedupe window, & risk rules aren't checked, `simulate_txn` checks those too
+    pub fn validate_txn(&self, txn: &Transaction) -> Result<(), TxnErrors> {
+        self.check_txn_id(txn)?;
+        self.check_txn_state(txn)
+    }
+
+    /// Checks a deposit or withdrawal's id isn't taken by a stored or registered txn
+    /// Other records reuse the id of the txn they refer to, so pass
+    pub(super) fn check_txn_id(&self, txn: &Transaction) -> Result<(), TxnErrors> {
+        let txn_id = match txn {
+            Transaction::Deposit(p_txn) | Transaction::Withdrawal(p_txn) => p_txn.txn_id,
+            _ => return Ok(()),
+        };
+        // The store is read first so one which can't be read is rejected as such, not as a duplicate
+        self.check_duplicate_store(txn_id)?;
+        match self.is_known_txn_id(txn_id) {
+            true => Err(TxnErrors::TxnIdAlreadyExists),
+            false => Ok(()),
+        }
+    }
+
+    /// Checks a txn against account & txn state as `validate_txn` does, leaving its id to `check_txn_id`
+    pub(super) fn check_txn_state(&self, txn: &Transaction) -> Result<(), TxnErrors> {
+        match txn {
+            Transaction::Deposit(p_txn) => self.check_deposit(p_txn),
+            Transaction::Withdrawal(p_txn) => self.check_withdrawal(p_txn),
+            Transaction::Dispute(ref_txn) => self.validate_dispute(ref_txn),
+            Transaction::Resolve(ref_txn) => self.validate_resolve(ref_txn),
+            Transaction::Chargeback(ref_txn) => self.validate_chargeback(ref_txn),
+            Transaction::Admin(admin_txn) => self.validate_admin(admin_txn),
+            Transaction::Refund(refund_txn) => self.validate_refund(refund_txn),
+            Transaction::Release(release_txn) => self.validate_release(release_txn),
+        }
+    }
+
+    /// Assigns the next sequence number to a transaction as it is ingested
+    pub fn sequence_txn(&mut self, txn: Transaction) -> SequencedTxn {
+        self.sequencer.assign(txn)
+    }
+
+    /// Processes a transaction which was sequenced at ingest by running it through the pipeline
+    /// Errors without applying if its sequence number isn't greater than every one before it
+    /// Txns a stage dropped, like filtered clients or absorbed retries, count as processed
+    pub fn process_sequenced_txn(&mut self, s_txn: &SequencedTxn) -> Result<(), TxnErrors> {
+        let accounts_before = self.accounts.len();
+        let locked_before = self.locked_before_events(s_txn.txn.acnt_id());
+        self.close_balance_interval(s_txn.seq);
+        let start = Instant::now();
+        let outcome = self.run_pipeline(s_txn);
+        let elapsed = start.elapsed();
+        let result = match outcome {
+            TxnOutcome::Applied => Ok(()),
+            TxnOutcome::Rejected(TxnErrors::OutOfSequence) => return Err(TxnErrors::OutOfSequence),
+            TxnOutcome::Rejected(e) => Err(e),
+            TxnOutcome::Dropped => {
+                self.publish_snapshot_on_epoch(s_txn.seq);
+                return Ok(());
+            }
+        };
+        self.record_client_stats(s_txn, &result, elapsed);
+        self.check_latency_budget(s_txn, &result, elapsed);
+        self.record_channel_stats(s_txn, &result);
+        self.report_txn_metrics(&result, elapsed, accounts_before);
+        self.send_events(s_txn, &result, accounts_before, locked_before);
+        if result == Err(TxnErrors::RefTxnOfOtherClient) {
+            self.audit_cross_client_ref(s_txn);
+        }
+        if result.is_ok() {
+            self.touch_balance_series(s_txn.txn.acnt_id());
+        }
+        self.publish_snapshot_on_epoch(s_txn.seq);
+        result
+    }
+
     /// Base level transactions processing function.  Updates account state with transaction info
     /// Returns success or error depending on transaction details & account state
     /// Logging of fails should be handled by outside functionality
     pub fn process_txn(&mut self, txn: &Transaction) -> Result<(), TxnErrors> {
+        let s_txn = self.sequence_txn(txn.clone());
+        self.process_sequenced_txn(&s_txn)
+    }
+
+    /// Changes account & txn state by a txn the validate & dedup stages accepted
+    /// Errors only if a fund movement overflows or the duplicate store can't be written
+    pub(super) fn apply_txn(&mut self, txn: &Transaction) -> Result<(), TxnErrors> {
         match txn {
             Transaction::Deposit(p_txn) => self.process_deposit(p_txn),
             Transaction::Withdrawal(p_txn) => self.process_withdrawl(p_txn),
             Transaction::Dispute(ref_txn) => self.process_dispute(ref_txn),
             Transaction::Resolve(ref_txn) => self.process_resolve(ref_txn),
             Transaction::Chargeback(ref_txn) => self.process_chargeback(ref_txn),
+            Transaction::Admin(admin_txn) => self.process_admin(admin_txn),
+            Transaction::Refund(refund_txn) => self.process_refund(refund_txn),
+            Transaction::Release(release_txn) => self.process_release(release_txn),
         }
     }
 }
@@ -171,79 +853,61 @@ impl PaymentsEngine {
 pub mod tests {
     use super::TxnErrors;
     use crate::account::Account;
+    use crate::amount::{Available, Held, TxnAmount};
+    use crate::payments_engine::config::{
+        DisputableTxns, DuplicateCheck, EngineConfig, WithdrawnFundsDispute,
+    };
     use crate::payments_engine::PaymentsEngine;
+    use crate::test::utils::_get_test_output_file;
+    use crate::test_support::{deposit, pure_txn, EngineFixture};
     use crate::transaction::Transaction;
-    use crate::transaction::{PureTxn, RefTxn};
+    use crate::transaction::{
+        AdminAction, AdminTxn, DisputeState, PureTxn, RefTxn, RefundTxn, ReleaseTxn, SequencedTxn,
+    };
+    use std::collections::BTreeMap;
 
     fn init_test_objects() -> (PaymentsEngine, PureTxn) {
         let payments_engine = PaymentsEngine::new();
-        let txn = PureTxn {
-            txn_id: 1,
-            acnt_id: 1,
-            amount: 10.0,
-            disputed: false,
-        };
+        let txn = pure_txn(1, 1, 10.0);
         (payments_engine, txn)
     }
 
     #[test]
     fn tst_process_deposit() {
         let (mut payments_engine, txn) = init_test_objects();
-        let res = payments_engine.process_deposit(&txn);
+        let res = payments_engine.process_txn(&Transaction::Deposit(txn.clone()));
         assert!(res.is_ok(), "Should pass if account doesn't exist");
         assert_eq!(payments_engine.accounts.len(), 1);
-        assert_eq!(payments_engine.acnt_map.len(), 1);
         assert_eq!(payments_engine.processed_txns.len(), 1);
         assert_eq!(payments_engine.txn_map.len(), 1);
         assert_eq!(
             payments_engine.accounts[0],
-            Account {
-                id: 1,
-                available: 10.0,
-                held: 0.0,
-                frozen: false
-            },
+            Account::with_balances(1, Available::from_f64(10.0), Held::default()),
             "Should get initial values from deposit"
         );
 
-        let res = payments_engine.process_deposit(&txn);
+        let res = payments_engine.process_txn(&Transaction::Deposit(txn.clone()));
         match res {
             Ok(_) => panic!("Should be invalid deposit due to TxnIdAlreadyExists"),
 
             Err(e) => assert_eq!(e, TxnErrors::TxnIdAlreadyExists, "Invalid error type"),
         }
 
-        let txn = PureTxn {
-            txn_id: 2,
-            acnt_id: 1,
-            amount: 10.0,
-            disputed: false,
-        };
-        let res = payments_engine.process_deposit(&txn);
+        let txn = pure_txn(1, 2, 10.0);
+        let res = payments_engine.process_txn(&Transaction::Deposit(txn.clone()));
         assert!(res.is_ok(), "Should pass if account already exists");
         assert_eq!(payments_engine.accounts.len(), 1);
-        assert_eq!(payments_engine.acnt_map.len(), 1);
         assert_eq!(payments_engine.processed_txns.len(), 2);
         assert_eq!(payments_engine.txn_map.len(), 2);
         assert_eq!(
             payments_engine.accounts[0],
-            Account {
-                id: 1,
-                available: 20.0,
-                held: 0.0,
-                frozen: false
-            },
+            Account::with_balances(1, Available::from_f64(20.0), Held::default()),
             "Should add to account 1"
         );
 
-        payments_engine.accounts[0].frozen = true;
-        let txn = PureTxn {
-            txn_id: 3,
-            acnt_id: 1,
-            amount: 10.0,
-            disputed: true,
-        };
-        let res = payments_engine.process_deposit(&txn);
+        payments_engine.accounts[0].locked_by_chargeback = true;
+        let txn = pure_txn(1, 3, 10.0);
+        let res = payments_engine.process_txn(&Transaction::Deposit(txn.clone()));
         match res {
             Ok(_) => {
                 panic!("Should be invalid deposit due to AccountFrozen")
@@ -252,16 +916,31 @@ pub mod tests {
         }
     }
 
+    #[test]
+    fn tst_balance_overflow() {
+        let (mut payments_engine, mut txn) = init_test_objects();
+        txn.amount = TxnAmount::from_f64(500_000_000_000_000.0);
+        assert!(payments_engine
+            .process_txn(&Transaction::Deposit(txn.clone()))
+            .is_ok());
+        txn.txn_id = 2;
+        assert_eq!(
+            payments_engine.process_txn(&Transaction::Deposit(txn.clone())),
+            Err(TxnErrors::BalanceOverflow)
+        );
+        // The overflowing deposit is rejected whole, not recorded or clamped
+        assert_eq!(payments_engine.txn_map.len(), 1);
+        assert_eq!(
+            payments_engine.accounts[0].available(),
+            Available::from_f64(500_000_000_000_000.0)
+        );
+    }
+
     #[test]
     fn tst_process_withdrawl() {
         let mut payments_engine = PaymentsEngine::new();
-        let mut txn = PureTxn {
-            txn_id: 1,
-            acnt_id: 1,
-            amount: 10.0,
-            disputed: false,
-        };
-        let res = payments_engine.process_withdrawl(&txn);
+        let mut txn = pure_txn(1, 1, 10.0);
+        let res = payments_engine.process_txn(&Transaction::Withdrawal(txn.clone()));
 
         match res {
             Ok(_) => panic!("Should err since account dne"),
@@ -269,9 +948,9 @@ pub mod tests {
             Err(e) => assert_eq!(e, TxnErrors::AccountDoesNotExist, "Invalid error type"),
         }
 
-        let _ = payments_engine.process_deposit(&txn);
+        let _ = payments_engine.process_txn(&Transaction::Deposit(txn.clone()));
 
-        let res = payments_engine.process_withdrawl(&txn);
+        let res = payments_engine.process_txn(&Transaction::Withdrawal(txn.clone()));
         match res {
             Ok(_) => panic!("Should err since account TxnIdAlreadyExists"),
 
@@ -279,16 +958,16 @@ pub mod tests {
         }
 
         txn.txn_id = 2;
-        txn.amount = 20.0;
-        let res = payments_engine.process_withdrawl(&txn);
+        txn.amount = TxnAmount::from_f64(20.0);
+        let res = payments_engine.process_txn(&Transaction::Withdrawal(txn.clone()));
         match res {
             Ok(_) => panic!("Should err since account AccountLacksFunds"),
 
             Err(e) => assert_eq!(e, TxnErrors::AccountLacksFunds, "Invalid error type"),
         }
 
-        txn.amount = 5.0;
-        let res = payments_engine.process_withdrawl(&txn);
+        txn.amount = TxnAmount::from_f64(5.0);
+        let res = payments_engine.process_txn(&Transaction::Withdrawal(txn.clone()));
         assert!(res.is_ok(), "Should be valid withdrawl");
         assert_eq!(
             5.0,
@@ -296,10 +975,10 @@ pub mod tests {
             "Should equal 5 'deposit amount - withdrawl' amount"
         );
 
-        payments_engine.accounts[0].frozen = true;
+        payments_engine.accounts[0].locked_by_chargeback = true;
         txn.txn_id = 3;
-        txn.amount = 1.0;
-        let res = payments_engine.process_deposit(&txn);
+        txn.amount = TxnAmount::from_f64(1.0);
+        let res = payments_engine.process_txn(&Transaction::Deposit(txn.clone()));
         match res {
             Ok(_) => panic!("Should err since account AccountFrozen"),
             Err(e) => assert_eq!(e, TxnErrors::AccountFrozen, "Invalid error type"),
@@ -307,62 +986,61 @@ pub mod tests {
     }
 
     #[test]
-    fn tst_get_ref_txn_indicies() {
+    fn tst_get_ref_txn_keys() {
         let mut payments_engine = PaymentsEngine::new();
-        let txn = PureTxn {
-            txn_id: 1,
-            acnt_id: 1,
-            amount: 10.0,
-            disputed: false,
-        };
-        let _ = payments_engine.process_deposit(&txn);
+        let txn = pure_txn(1, 1, 10.0);
+        let _ = payments_engine.process_txn(&Transaction::Deposit(txn.clone()));
 
         let mut ref_txn = RefTxn {
             ref_id: 1,
             acnt_id: 2,
         };
-        let res = payments_engine.get_ref_txn_indicies(&ref_txn);
+        let res = payments_engine.get_ref_txn_keys(&ref_txn);
         match res {
             Ok(_) => panic!("Should err since account dne"),
             Err(e) => assert_eq!(e, TxnErrors::AccountDoesNotExist, "Invalid error type"),
         }
 
         ref_txn.acnt_id = 1;
-        payments_engine.accounts[0].frozen = true;
-        let res = payments_engine.get_ref_txn_indicies(&ref_txn);
+        payments_engine.accounts[0].locked_by_chargeback = true;
+        let res = payments_engine.get_ref_txn_keys(&ref_txn);
         match res {
             Ok(_) => panic!("Should err since AccountFrozen"),
             Err(e) => assert_eq!(e, TxnErrors::AccountFrozen, "Invalid error type"),
         }
 
         ref_txn.ref_id = 3;
-        payments_engine.accounts[0].frozen = false;
-        let res = payments_engine.get_ref_txn_indicies(&ref_txn);
+        payments_engine.accounts[0].locked_by_chargeback = false;
+        let res = payments_engine.get_ref_txn_keys(&ref_txn);
         match res {
             Ok(_) => panic!("Should err since TxnIdDoesNotExist"),
             Err(e) => assert_eq!(e, TxnErrors::TxnIdDoesNotExist, "Invalid error type"),
         }
 
         ref_txn.ref_id = 1;
-        let res = payments_engine.get_ref_txn_indicies(&ref_txn);
+        let res = payments_engine.get_ref_txn_keys(&ref_txn);
         assert!(res.is_ok(), "Should be valid RefTxn");
+        let (acnt_key, txn_key) = res.unwrap();
         assert_eq!(
-            (0, 0),
-            res.unwrap(),
-            "Should be point to acnt & txn indices"
+            (
+                payments_engine.accounts[acnt_key].id,
+                &payments_engine.processed_txns[txn_key]
+            ),
+            (1, &payments_engine.processed_txns[0]),
+            "Should be point to acnt & txn"
         );
     }
 
     #[test]
     fn tst_process_dispute_txn() {
         let (mut payments_engine, mut txn) = init_test_objects();
-        let _ = payments_engine.process_deposit(&txn);
+        let _ = payments_engine.process_txn(&Transaction::Deposit(txn.clone()));
 
         let ref_txn = RefTxn {
             ref_id: 1,
             acnt_id: 1,
         };
-        let res = payments_engine.process_dispute(&ref_txn);
+        let res = payments_engine.process_txn(&Transaction::Dispute(ref_txn.clone()));
         assert!(res.is_ok(), "Should be valid RefTxn");
         assert_eq!(
             payments_engine.processed_txns.len(),
@@ -374,8 +1052,8 @@ pub mod tests {
             1,
             "Should not add to txn lookup"
         );
-        txn.disputed = true;
-        match payments_engine.processed_txns[0].clone() {
+        txn.dispute.record(DisputeState::Disputed, 2);
+        match payments_engine.processed_txns[0].txn.clone() {
             Transaction::Deposit(processed_txn) => {
                 assert_eq!(processed_txn, txn, "Transaction should be disputed")
             }
@@ -383,16 +1061,11 @@ pub mod tests {
         }
         assert_eq!(
             payments_engine.accounts[0],
-            Account {
-                id: 1,
-                available: 0.0,
-                held: 10.0,
-                frozen: false
-            },
+            Account::with_balances(1, Available::default(), Held::from_f64(10.0)),
             "Account should be unfrozen & funds in held"
         );
 
-        let res = payments_engine.process_dispute(&ref_txn);
+        let res = payments_engine.process_txn(&Transaction::Dispute(ref_txn.clone()));
         match res {
             Ok(_) => panic!("Should err since TxnAlreadyDisputed"),
             Err(e) => assert_eq!(e, TxnErrors::TxnAlreadyDisputed, "Invalid error type"),
@@ -403,22 +1076,22 @@ pub mod tests {
     fn tst_process_resolve_txn() {
         let (mut payments_engine, mut txn) = init_test_objects();
 
-        let _ = payments_engine.process_deposit(&txn);
+        let _ = payments_engine.process_txn(&Transaction::Deposit(txn.clone()));
 
         let ref_txn = RefTxn {
             ref_id: 1,
             acnt_id: 1,
         };
-        let res = payments_engine.process_resolve(&ref_txn);
+        let res = payments_engine.process_txn(&Transaction::Resolve(ref_txn.clone()));
         match res {
             Ok(_) => panic!("Should err since TxnMustBeDisputed"),
             Err(e) => assert_eq!(e, TxnErrors::TxnMustBeDisputed, "Invalid error type"),
         }
 
-        let _ = payments_engine.process_dispute(&ref_txn);
+        let _ = payments_engine.process_txn(&Transaction::Dispute(ref_txn.clone()));
 
         // Testing successful run
-        let res = payments_engine.process_resolve(&ref_txn);
+        let res = payments_engine.process_txn(&Transaction::Resolve(ref_txn.clone()));
         assert!(res.is_ok(), "Should be valid RefTxn");
         assert_eq!(
             payments_engine.processed_txns.len(),
@@ -430,21 +1103,17 @@ pub mod tests {
             1,
             "RefTxns should not add to txn lookup"
         );
-        txn.disputed = false;
-        match payments_engine.processed_txns[0].clone() {
+        txn.dispute.record(DisputeState::Disputed, 3);
+        txn.dispute.record(DisputeState::Resolved, 4);
+        match payments_engine.processed_txns[0].txn.clone() {
             Transaction::Deposit(processed_txn) => {
-                assert_eq!(processed_txn, txn, "Transaction should be not be disputed")
+                assert_eq!(processed_txn, txn, "Transaction should be resolved")
             }
             _ => panic!("Transaction order should not have changed"),
         }
         assert_eq!(
             payments_engine.accounts[0],
-            Account {
-                id: 1,
-                available: 10.0,
-                held: 0.0,
-                frozen: false
-            },
+            Account::with_balances(1, Available::from_f64(10.0), Held::default()),
             "Account should be undisputed & funds in available"
         );
     }
@@ -453,22 +1122,22 @@ pub mod tests {
     fn tst_process_chargeback_txn() {
         let (mut payments_engine, mut txn) = init_test_objects();
 
-        let _ = payments_engine.process_deposit(&txn);
+        let _ = payments_engine.process_txn(&Transaction::Deposit(txn.clone()));
 
         let ref_txn = RefTxn {
             ref_id: 1,
             acnt_id: 1,
         };
-        let res = payments_engine.process_chargeback(&ref_txn);
+        let res = payments_engine.process_txn(&Transaction::Chargeback(ref_txn.clone()));
         match res {
             Ok(_) => panic!("Should err since TxnMustBeDisputed"),
             Err(e) => assert_eq!(e, TxnErrors::TxnMustBeDisputed, "Invalid error type"),
         }
 
-        let _ = payments_engine.process_dispute(&ref_txn);
+        let _ = payments_engine.process_txn(&Transaction::Dispute(ref_txn.clone()));
 
         // Testing successful run
-        let res = payments_engine.process_chargeback(&ref_txn);
+        let res = payments_engine.process_txn(&Transaction::Chargeback(ref_txn.clone()));
         assert!(res.is_ok(), "Should be valid RefTxn");
         assert_eq!(
             payments_engine.processed_txns.len(),
@@ -480,22 +1149,761 @@ pub mod tests {
             1,
             "RefTxns should not add to txn lookup"
         );
-        txn.disputed = false;
-        match payments_engine.processed_txns[0].clone() {
+        txn.dispute.record(DisputeState::Disputed, 3);
+        txn.dispute.record(DisputeState::ChargedBack, 4);
+        match payments_engine.processed_txns[0].txn.clone() {
             Transaction::Deposit(processed_txn) => {
-                assert_eq!(processed_txn, txn, "Transaction should be not be disputed")
+                assert_eq!(processed_txn, txn, "Transaction should be charged back")
             }
             _ => panic!("Transaction order should not have changed"),
         }
         assert_eq!(
             payments_engine.accounts[0],
-            Account {
-                id: 1,
-                available: 0.0,
-                held: 0.0,
-                frozen: true
-            },
+            Account::new(1).with_locks(true, false),
             "Account should be frozen, no longer disputed, & funds charged back"
-        )
+        );
+
+        // The lock rejects these first, lift it to reach the dispute state checks
+        payments_engine.accounts[0].locked_by_chargeback = false;
+        for res in [
+            payments_engine.process_txn(&Transaction::Resolve(ref_txn.clone())),
+            payments_engine.process_txn(&Transaction::Dispute(ref_txn.clone())),
+        ] {
+            assert_eq!(res, Err(TxnErrors::TxnAlreadyChargedBack));
+        }
+    }
+
+    #[test]
+    fn tst_probabilistic_duplicate_check() {
+        let store_path = _get_test_output_file("tst_probabilistic_duplicate_check.bin");
+        let config = EngineConfig {
+            duplicate_check: DuplicateCheck::Probabilistic {
+                expected_txns: 100,
+                false_positive_rate: 0.01,
+                store_path,
+            },
+            ..EngineConfig::default()
+        };
+        let mut payments_engine = PaymentsEngine::with_config(config).unwrap();
+        let mut txn = pure_txn(1, 1, 10.0);
+        assert!(payments_engine
+            .process_txn(&Transaction::Deposit(txn.clone()))
+            .is_ok());
+        assert_eq!(
+            payments_engine.process_txn(&Transaction::Deposit(txn.clone())),
+            Err(TxnErrors::TxnIdAlreadyExists)
+        );
+        assert_eq!(
+            payments_engine.process_txn(&Transaction::Withdrawal(txn.clone())),
+            Err(TxnErrors::TxnIdAlreadyExists)
+        );
+
+        txn.txn_id = 2;
+        txn.amount = TxnAmount::from_f64(4.0);
+        assert!(payments_engine
+            .process_txn(&Transaction::Withdrawal(txn.clone()))
+            .is_ok());
+        assert_eq!(payments_engine.accounts[0].available().to_f64(), 6.0);
+        assert_eq!(
+            payments_engine.txn_map.len(),
+            0,
+            "Ids should only be kept in the store"
+        );
+        assert_eq!(
+            payments_engine.validate_deposit(&txn),
+            Err(TxnErrors::TxnIdAlreadyExists)
+        );
+
+        // Pure txns are still referenceable through the store
+        let dispute = RefTxn {
+            ref_id: 1,
+            acnt_id: 1,
+        };
+        assert!(payments_engine
+            .process_txn(&Transaction::Dispute(dispute.clone()))
+            .is_ok());
+        assert_eq!(payments_engine.accounts[0].held().to_f64(), 10.0);
+
+        // Rolled back ids leave the store & can be accepted again
+        let savepoint = payments_engine.savepoint();
+        txn.txn_id = 3;
+        assert!(payments_engine
+            .process_txn(&Transaction::Deposit(txn.clone()))
+            .is_ok());
+        assert!(payments_engine.rollback_to(savepoint).is_ok());
+        assert!(payments_engine
+            .process_txn(&Transaction::Deposit(txn.clone()))
+            .is_ok());
+        let dispute = RefTxn {
+            ref_id: 3,
+            acnt_id: 1,
+        };
+        assert!(payments_engine
+            .process_txn(&Transaction::Dispute(dispute.clone()))
+            .is_ok());
+    }
+
+    #[test]
+    fn tst_process_sequenced_txn() {
+        let (mut payments_engine, txn) = init_test_objects();
+        let s_txn = payments_engine.sequence_txn(Transaction::Deposit(txn.clone()));
+        assert_eq!(s_txn.seq, 1);
+        assert!(payments_engine.process_sequenced_txn(&s_txn).is_ok());
+        assert_eq!(payments_engine.processed_txns[0], s_txn);
+
+        let replayed = Transaction::Deposit(PureTxn { txn_id: 2, ..txn });
+        let res = payments_engine.process_sequenced_txn(&SequencedTxn {
+            seq: 1,
+            txn: replayed.clone(),
+        });
+        assert_eq!(res, Err(TxnErrors::OutOfSequence));
+        assert_eq!(payments_engine.processed_txns.len(), 1);
+
+        assert!(payments_engine.process_txn(&replayed).is_ok());
+        assert_eq!(
+            payments_engine.processed_txns[1].seq, 2,
+            "Unsequenced txns should continue the sequence"
+        );
+    }
+
+    #[test]
+    fn tst_process_admin() {
+        let (mut payments_engine, txn) = init_test_objects();
+        let hold = AdminTxn {
+            instr_id: 1,
+            acnt_id: 1,
+            action: AdminAction::SetHold,
+        };
+        assert_eq!(
+            payments_engine.process_txn(&Transaction::Admin(hold.clone())),
+            Err(TxnErrors::AccountDoesNotExist)
+        );
+
+        let _ = payments_engine.process_txn(&Transaction::Deposit(txn.clone()));
+        assert!(payments_engine
+            .process_txn(&Transaction::Admin(hold.clone()))
+            .is_ok());
+        assert!(payments_engine.accounts[0].admin_hold);
+        assert!(payments_engine.accounts[0].is_locked());
+        let deposit = PureTxn { txn_id: 2, ..txn };
+        assert_eq!(
+            payments_engine.process_txn(&Transaction::Deposit(deposit.clone())),
+            Err(TxnErrors::AccountFrozen),
+            "Held accounts should reject transactions"
+        );
+
+        let unhold = AdminTxn {
+            action: AdminAction::ClearHold,
+            ..hold
+        };
+        assert!(payments_engine
+            .process_txn(&Transaction::Admin(unhold.clone()))
+            .is_ok());
+        assert!(!payments_engine.accounts[0].is_locked());
+        assert!(payments_engine
+            .process_txn(&Transaction::Deposit(deposit.clone()))
+            .is_ok());
+
+        payments_engine.accounts[0].locked_by_chargeback = true;
+        assert!(payments_engine
+            .process_txn(&Transaction::Admin(unhold.clone()))
+            .is_ok());
+        assert!(
+            payments_engine.accounts[0].is_locked(),
+            "Clearing a hold should not lift a chargeback lock"
+        );
+    }
+
+    #[test]
+    fn tst_withdrawn_funds_dispute() {
+        let dispute = RefTxn {
+            ref_id: 1,
+            acnt_id: 1,
+        };
+        let withdrawn_engine = |policy: WithdrawnFundsDispute| {
+            let config = EngineConfig {
+                withdrawn_funds_dispute: policy,
+                ..EngineConfig::default()
+            };
+            let mut payments_engine = PaymentsEngine::with_config(config).unwrap();
+            let (_, txn) = init_test_objects();
+            let withdrawal = PureTxn {
+                txn_id: 2,
+                amount: TxnAmount::from_f64(3.0),
+                ..txn.clone()
+            };
+            let _ = payments_engine.process_txn(&Transaction::Deposit(txn.clone()));
+            let _ = payments_engine.process_txn(&Transaction::Withdrawal(withdrawal.clone()));
+            payments_engine
+        };
+
+        let mut payments_engine = withdrawn_engine(WithdrawnFundsDispute::AllowNegative);
+        assert!(payments_engine
+            .process_txn(&Transaction::Dispute(dispute.clone()))
+            .is_ok());
+        assert_eq!(payments_engine.accounts[0].available().to_f64(), -3.0);
+        assert_eq!(payments_engine.accounts[0].held().to_f64(), 10.0);
+        assert!(!payments_engine.is_flagged_for_review(1));
+
+        let mut payments_engine = withdrawn_engine(WithdrawnFundsDispute::CapAtAvailable);
+        assert!(payments_engine
+            .process_txn(&Transaction::Dispute(dispute.clone()))
+            .is_ok());
+        assert_eq!(payments_engine.accounts[0].available().to_f64(), 0.0);
+        assert_eq!(payments_engine.accounts[0].held().to_f64(), 7.0);
+        assert!(payments_engine
+            .process_txn(&Transaction::Resolve(dispute.clone()))
+            .is_ok());
+        assert_eq!(payments_engine.accounts[0].available().to_f64(), 7.0);
+        assert_eq!(payments_engine.accounts[0].held().to_f64(), 0.0);
+        assert!(payments_engine
+            .process_txn(&Transaction::Dispute(dispute.clone()))
+            .is_ok());
+        assert!(payments_engine
+            .process_txn(&Transaction::Chargeback(dispute.clone()))
+            .is_ok());
+        assert_eq!(
+            payments_engine.accounts[0].held().to_f64(),
+            0.0,
+            "Chargebacks should only release what was held"
+        );
+
+        let mut payments_engine = withdrawn_engine(WithdrawnFundsDispute::FlagForReview);
+        assert!(payments_engine
+            .process_txn(&Transaction::Dispute(dispute.clone()))
+            .is_ok());
+        assert_eq!(payments_engine.accounts[0].available().to_f64(), -3.0);
+        assert!(payments_engine.is_flagged_for_review(1));
+    }
+
+    #[test]
+    fn tst_max_open_disputes() {
+        let config = EngineConfig {
+            max_open_disputes: Some(2),
+            flag_dispute_floods: true,
+            ..EngineConfig::default()
+        };
+        let mut payments_engine = PaymentsEngine::with_config(config).unwrap();
+        let (_, txn) = init_test_objects();
+        let disputes: Vec<RefTxn> = (1..=3)
+            .map(|txn_id| {
+                let _ = payments_engine.process_txn(&Transaction::Deposit(PureTxn {
+                    txn_id,
+                    ..txn.clone()
+                }));
+                RefTxn {
+                    ref_id: txn_id,
+                    acnt_id: 1,
+                }
+            })
+            .collect();
+
+        assert!(payments_engine
+            .process_txn(&Transaction::Dispute(disputes[0].clone()))
+            .is_ok());
+        assert!(payments_engine
+            .process_txn(&Transaction::Dispute(disputes[1].clone()))
+            .is_ok());
+        assert_eq!(
+            payments_engine.process_txn(&Transaction::Dispute(disputes[2].clone())),
+            Err(TxnErrors::TooManyOpenDisputes)
+        );
+        assert!(payments_engine.is_flagged_for_review(1));
+        assert_eq!(payments_engine.accounts[0].held().to_f64(), 20.0);
+
+        assert!(payments_engine
+            .process_txn(&Transaction::Resolve(disputes[0].clone()))
+            .is_ok());
+        assert!(
+            payments_engine
+                .process_txn(&Transaction::Dispute(disputes[2].clone()))
+                .is_ok(),
+            "Resolving should free up a dispute slot"
+        );
+    }
+
+    #[test]
+    fn tst_reserve_floor() {
+        let config = EngineConfig {
+            reserve_floors: BTreeMap::from([(1, 100.0)]),
+            ..EngineConfig::default()
+        };
+        let fixture = EngineFixture::with_config(config)
+            .deposit(1, 1, 150.0)
+            .deposit(1, 2, 40.0)
+            .withdrawal(1, 3, 60.0)
+            .withdrawal(1, 4, 90.0)
+            .deposit(2, 5, 10.0)
+            .withdrawal(2, 6, 10.0)
+            .dispute(1, 2)
+            .build();
+        assert_eq!(
+            fixture.results,
+            [
+                Ok(()),
+                Ok(()),
+                Ok(()),
+                Err(TxnErrors::ReserveFloorBreached),
+                Ok(()),
+                Ok(()),
+                Err(TxnErrors::ReserveFloorBreached),
+            ]
+        );
+        let mut engine = fixture.engine;
+        assert_eq!(engine.accounts.get(1).unwrap().available().to_f64(), 130.0);
+        // Breach attempts put the reserve account in the compliance feed, others have no floor
+        assert!(engine.is_restricted(1));
+        assert!(!engine.is_restricted(2));
+
+        let dispute = RefTxn {
+            ref_id: 2,
+            acnt_id: 1,
+        };
+        assert!(engine.process_txn(&deposit(1, 7, 40.0)).is_ok());
+        assert!(engine.validate_dispute(&dispute).is_ok());
+    }
+
+    #[test]
+    fn tst_validate_txns() {
+        let config = EngineConfig {
+            max_open_disputes: Some(1),
+            flag_dispute_floods: true,
+            withdrawal_approval_threshold: Some(50.0),
+            ..EngineConfig::default()
+        };
+        let mut engine = EngineFixture::with_config(config)
+            .deposit(1, 1, 100.0)
+            .deposit(1, 2, 5.0)
+            .withdrawal(1, 3, 60.0)
+            .dispute(1, 2)
+            .deposit(2, 4, 1.0)
+            .admin(2, 5, AdminAction::SetHold)
+            .build()
+            .engine;
+        let ref_txn = |acnt_id, ref_id| RefTxn { ref_id, acnt_id };
+        let admin = |acnt_id, instr_id, action| {
+            Transaction::Admin(AdminTxn {
+                acnt_id,
+                instr_id,
+                action,
+            })
+        };
+        let refund = |acnt_id, ref_id, amount| {
+            Transaction::Refund(RefundTxn {
+                ref_id,
+                acnt_id,
+                amount: TxnAmount::from_f64(amount),
+            })
+        };
+        let cases = [
+            (
+                Transaction::Deposit(pure_txn(1, 1, 1.0)),
+                Some(TxnErrors::TxnIdAlreadyExists),
+            ),
+            (
+                Transaction::Deposit(pure_txn(2, 9, 1.0)),
+                Some(TxnErrors::AccountFrozen),
+            ),
+            (Transaction::Deposit(pure_txn(3, 9, 1.0)), None),
+            (
+                Transaction::Withdrawal(pure_txn(3, 9, 1.0)),
+                Some(TxnErrors::AccountDoesNotExist),
+            ),
+            (
+                Transaction::Withdrawal(pure_txn(1, 9, 41.0)),
+                Some(TxnErrors::AccountLacksFunds),
+            ),
+            (Transaction::Withdrawal(pure_txn(1, 9, 40.0)), None),
+            (
+                Transaction::Dispute(ref_txn(1, 1)),
+                Some(TxnErrors::TooManyOpenDisputes),
+            ),
+            (
+                Transaction::Dispute(ref_txn(1, 8)),
+                Some(TxnErrors::TxnIdDoesNotExist),
+            ),
+            (
+                Transaction::Dispute(ref_txn(2, 1)),
+                Some(TxnErrors::AccountFrozen),
+            ),
+            (
+                Transaction::Resolve(ref_txn(1, 1)),
+                Some(TxnErrors::TxnMustBeDisputed),
+            ),
+            (Transaction::Resolve(ref_txn(1, 2)), None),
+            (Transaction::Chargeback(ref_txn(1, 2)), None),
+            (
+                Transaction::Chargeback(ref_txn(1, 3)),
+                Some(TxnErrors::TxnPendingApproval),
+            ),
+            (refund(1, 2, 1.0), Some(TxnErrors::TxnAlreadyDisputed)),
+            (refund(1, 1, 101.0), Some(TxnErrors::RefundExceedsDeposit)),
+            (refund(1, 1, 40.0), None),
+            (
+                admin(1, 9, AdminAction::Approve),
+                Some(TxnErrors::TxnNotPendingApproval),
+            ),
+            (admin(1, 3, AdminAction::Deny), None),
+            (
+                admin(3, 9, AdminAction::SetHold),
+                Some(TxnErrors::AccountDoesNotExist),
+            ),
+        ];
+        for (txn, expected) in cases {
+            assert_eq!(engine.validate_txn(&txn).err(), expected, "{:?}", txn);
+            // Validating has no effect, processing then fails or succeeds the same way
+            let savepoint = engine.savepoint();
+            assert_eq!(engine.process_txn(&txn).err(), expected, "{:?}", txn);
+            assert!(engine.rollback_to(savepoint).is_ok());
+        }
+        // Only processing a dispute flood flags it
+        assert!(!engine.is_flagged_for_review(1));
+        let _ = engine.process_txn(&Transaction::Dispute(ref_txn(1, 1)));
+        assert!(engine.is_flagged_for_review(1));
+    }
+
+    #[test]
+    fn tst_disputable_txns() {
+        let config = EngineConfig {
+            disputable_txns: DisputableTxns::Deposits,
+            ..EngineConfig::default()
+        };
+        let mut payments_engine = PaymentsEngine::with_config(config).unwrap();
+        let (_, txn) = init_test_objects();
+        let withdrawal = PureTxn {
+            txn_id: 2,
+            amount: TxnAmount::from_f64(3.0),
+            ..txn.clone()
+        };
+        assert!(payments_engine
+            .process_txn(&Transaction::Deposit(txn.clone()))
+            .is_ok());
+        assert!(payments_engine
+            .process_txn(&Transaction::Withdrawal(withdrawal.clone()))
+            .is_ok());
+        let dispute = |ref_id| RefTxn { ref_id, acnt_id: 1 };
+        assert_eq!(
+            payments_engine.process_txn(&Transaction::Dispute(dispute(2))),
+            Err(TxnErrors::RefTxnNotDisputable)
+        );
+        assert_eq!(payments_engine.accounts[0].held().to_f64(), 0.0);
+        assert!(payments_engine
+            .process_txn(&Transaction::Dispute(dispute(1)))
+            .is_ok());
+
+        // A txn id pointing at a ref txn is rejected rather than trusted
+        let txn_key = payments_engine.push_processed(Transaction::Dispute(dispute(1)));
+        payments_engine.txn_map.insert(99, txn_key);
+        assert_eq!(
+            payments_engine.process_txn(&Transaction::Dispute(dispute(99))),
+            Err(TxnErrors::RefTxnNotDisputable)
+        );
+        assert_eq!(
+            payments_engine.process_txn(&Transaction::Chargeback(dispute(99))),
+            Err(TxnErrors::RefTxnNotDisputable)
+        );
+    }
+
+    #[test]
+    fn tst_compact_withdrawals() {
+        let config = EngineConfig {
+            compact_withdrawals: true,
+            ..EngineConfig::default()
+        };
+        assert!(PaymentsEngine::with_config(config.clone()).is_err());
+        let config = EngineConfig {
+            disputable_txns: DisputableTxns::Deposits,
+            ..config
+        };
+        let mut payments_engine = PaymentsEngine::with_config(config).unwrap();
+        let (_, txn) = init_test_objects();
+        let withdrawal = PureTxn {
+            txn_id: 2,
+            amount: TxnAmount::from_f64(3.0),
+            ..txn.clone()
+        };
+        assert!(payments_engine
+            .process_txn(&Transaction::Deposit(txn.clone()))
+            .is_ok());
+        assert!(payments_engine
+            .process_txn(&Transaction::Withdrawal(withdrawal.clone()))
+            .is_ok());
+        assert_eq!(payments_engine.processed_txns_len(), 1);
+        assert_eq!(
+            payments_engine.accounts[0].available().to_f64(),
+            txn.amount.to_f64() - 3.0
+        );
+
+        // The id alone still rejects duplicates & disputes
+        assert_eq!(
+            payments_engine.process_txn(&Transaction::Withdrawal(withdrawal.clone())),
+            Err(TxnErrors::TxnIdAlreadyExists)
+        );
+        assert_eq!(
+            payments_engine.process_txn(&Transaction::Deposit(PureTxn {
+                txn_id: 2,
+                ..txn.clone()
+            })),
+            Err(TxnErrors::TxnIdAlreadyExists)
+        );
+        assert_eq!(
+            payments_engine.process_txn(&Transaction::Dispute(RefTxn {
+                ref_id: 2,
+                acnt_id: 1
+            })),
+            Err(TxnErrors::RefTxnNotDisputable)
+        );
+
+        // Rolled back withdrawals leave no id behind
+        let savepoint = payments_engine.savepoint();
+        let withdrawal = PureTxn {
+            txn_id: 3,
+            ..withdrawal
+        };
+        assert!(payments_engine
+            .process_txn(&Transaction::Withdrawal(withdrawal.clone()))
+            .is_ok());
+        assert!(payments_engine.rollback_to(savepoint).is_ok());
+        assert!(payments_engine
+            .process_txn(&Transaction::Withdrawal(withdrawal.clone()))
+            .is_ok());
+    }
+
+    #[test]
+    fn tst_refunds() {
+        let fixture = EngineFixture::new()
+            .deposit(1, 1, 10.0)
+            .deposit(1, 2, 20.0)
+            .deposit(1, 4, 10.0)
+            .withdrawal(1, 3, 2.0)
+            .refund(1, 1, 4.0)
+            .refund(1, 1, 6.0001)
+            .refund(1, 3, 1.0)
+            .refund(2, 1, 1.0)
+            .refund(1, 1, 6.0)
+            .dispute(1, 2)
+            .refund(1, 2, 1.0)
+            .resolve(1, 2)
+            .refund(1, 2, 5.0)
+            .dispute(1, 2)
+            .build();
+        let errors: Vec<_> = fixture
+            .results
+            .iter()
+            .filter_map(|result| result.clone().err())
+            .collect();
+        assert_eq!(
+            errors,
+            [
+                TxnErrors::RefundExceedsDeposit,
+                TxnErrors::RefTxnNotRefundable,
+                TxnErrors::AccountDoesNotExist,
+                TxnErrors::TxnAlreadyDisputed,
+            ]
+        );
+        assert_eq!(fixture.engine.accounts.to_vec(), fixture.expected);
+        // Only what is left of the deposit after refunds is held
+        let acnt = fixture.engine.accounts.get(1).unwrap();
+        assert_eq!(acnt.held().to_f64(), 15.0);
+        assert_eq!(acnt.available().to_f64(), 8.0);
+
+        let mut payments_engine = fixture.engine;
+        let refund = |ref_id, amount| {
+            Transaction::Refund(RefundTxn {
+                ref_id,
+                acnt_id: 1,
+                amount: TxnAmount::from_f64(amount),
+            })
+        };
+        assert_eq!(
+            payments_engine.simulate_txn(&refund(1, 0.0001)),
+            Err(TxnErrors::RefundExceedsDeposit)
+        );
+        let resolve = Transaction::Resolve(RefTxn {
+            ref_id: 2,
+            acnt_id: 1,
+        });
+        assert!(payments_engine.process_txn(&resolve).is_ok());
+        let savepoint = payments_engine.savepoint();
+        assert!(payments_engine.process_txn(&refund(2, 15.0)).is_ok());
+        assert!(payments_engine.rollback_to(savepoint).is_ok());
+        assert!(payments_engine.process_txn(&refund(2, 15.0)).is_ok());
+        assert_eq!(
+            payments_engine
+                .accounts
+                .get(1)
+                .unwrap()
+                .available()
+                .to_f64(),
+            8.0
+        );
+    }
+
+    #[test]
+    fn tst_partial_releases() {
+        let fixture = EngineFixture::new()
+            .deposit(1, 1, 10.0)
+            .deposit(1, 2, 20.0)
+            .dispute(1, 1)
+            .release(1, 1, 4.0)
+            .release(1, 1, 6.0)
+            .release(1, 1, 0.0)
+            .release(1, 2, 1.0)
+            .release(2, 1, 1.0)
+            .release(1, 1, 2.5)
+            .resolve(1, 1)
+            .dispute(1, 1)
+            .release(1, 1, 1.0)
+            .build();
+        let errors: Vec<_> = fixture
+            .results
+            .iter()
+            .filter_map(|result| result.clone().err())
+            .collect();
+        assert_eq!(
+            errors,
+            [
+                TxnErrors::PartialReleaseOutOfRange,
+                TxnErrors::PartialReleaseOutOfRange,
+                TxnErrors::TxnMustBeDisputed,
+                TxnErrors::AccountDoesNotExist,
+            ]
+        );
+        assert_eq!(fixture.engine.accounts.to_vec(), fixture.expected);
+        // The resolve returned what was left held, the new dispute holds the whole deposit again
+        let acnt = fixture.engine.accounts.get(1).unwrap();
+        assert_eq!(
+            (acnt.available().to_f64(), acnt.held().to_f64()),
+            (21.0, 9.0)
+        );
+
+        let mut payments_engine = fixture.engine;
+        let release = |amount| {
+            Transaction::Release(ReleaseTxn {
+                ref_id: 1,
+                acnt_id: 1,
+                amount: TxnAmount::from_f64(amount),
+            })
+        };
+        assert_eq!(
+            payments_engine.simulate_txn(&release(9.0)),
+            Err(TxnErrors::PartialReleaseOutOfRange)
+        );
+        let savepoint = payments_engine.savepoint();
+        assert!(payments_engine.process_txn(&release(5.0)).is_ok());
+        assert!(payments_engine.rollback_to(savepoint).is_ok());
+        let chargeback = Transaction::Chargeback(RefTxn {
+            ref_id: 1,
+            acnt_id: 1,
+        });
+        assert!(payments_engine.process_txn(&chargeback).is_ok());
+        let acnt = payments_engine.accounts.get(1).unwrap();
+        assert_eq!(
+            (acnt.available().to_f64(), acnt.held().to_f64()),
+            (21.0, 0.0)
+        );
+    }
+
+    #[test]
+    fn tst_split_amounts_add_up_exactly() {
+        // 0.1 + 0.2 isn't 0.3 in f64, in minor units the parts add up to the whole
+        let fixture = EngineFixture::new()
+            .deposit(1, 1, 0.3)
+            .deposit(1, 2, 0.3)
+            .refund(1, 1, 0.1)
+            .refund(1, 1, 0.2)
+            .refund(1, 1, 0.0001)
+            .dispute(1, 2)
+            .release(1, 2, 0.1)
+            .release(1, 2, 0.1)
+            .release(1, 2, 0.1)
+            .build();
+        let errors: Vec<_> = fixture
+            .results
+            .iter()
+            .filter_map(|result| result.clone().err())
+            .collect();
+        assert_eq!(
+            errors,
+            [
+                TxnErrors::RefundExceedsDeposit,
+                TxnErrors::PartialReleaseOutOfRange,
+            ]
+        );
+        let acnt = fixture.engine.accounts.get(1).unwrap();
+        // The last release would have emptied the hold, which only a resolve does
+        assert_eq!(acnt.available(), Available::from_f64(0.2));
+        assert_eq!(acnt.held(), Held::from_f64(0.1));
+    }
+
+    #[test]
+    fn tst_id_epochs() {
+        let config = EngineConfig {
+            id_epoch: Some(10),
+            ..EngineConfig::default()
+        };
+        let mut payments_engine = PaymentsEngine::with_config(config).unwrap();
+        let (_, txn) = init_test_objects();
+        let deposit = |txn_id, amount| {
+            Transaction::Deposit(PureTxn {
+                txn_id,
+                amount: TxnAmount::from_f64(amount),
+                ..txn.clone()
+            })
+        };
+        let dispute = |ref_id| Transaction::Dispute(RefTxn { ref_id, acnt_id: 1 });
+        let mut process =
+            |seq, txn| payments_engine.process_sequenced_txn(&SequencedTxn { seq, txn });
+        assert!(process(1, deposit(1, 10.0)).is_ok());
+        assert_eq!(
+            process(2, deposit(1, 10.0)),
+            Err(TxnErrors::TxnIdAlreadyExists)
+        );
+        assert!(process(3, deposit(2, 5.0)).is_ok());
+        assert!(process(4, dispute(2)).is_ok());
+        assert!(process(5, deposit(3, 7.0)).is_ok());
+
+        // Ids of the last epoch are reused unless their txn is still disputed
+        assert!(process(11, deposit(1, 3.0)).is_ok());
+        assert_eq!(
+            process(12, deposit(2, 5.0)),
+            Err(TxnErrors::TxnIdAlreadyExists)
+        );
+        assert_eq!(
+            process(13, deposit(1, 3.0)),
+            Err(TxnErrors::TxnIdAlreadyExists)
+        );
+        // The reused id refers to the new txn
+        assert!(process(14, dispute(1)).is_ok());
+        assert_eq!(payments_engine.recycled_txn_ids(), 1);
+        assert_eq!(payments_engine.accounts[0].held().to_f64(), 8.0);
+
+        // Rolling back a reuse points the id at the old txn again
+        let savepoint = payments_engine.savepoint();
+        assert!(payments_engine
+            .process_sequenced_txn(&SequencedTxn {
+                seq: 21,
+                txn: deposit(3, 2.0),
+            })
+            .is_ok());
+        assert_eq!(payments_engine.recycled_txn_ids(), 2);
+        assert!(payments_engine.rollback_to(savepoint).is_ok());
+        assert_eq!(payments_engine.recycled_txn_ids(), 1);
+        assert!(payments_engine
+            .process_sequenced_txn(&SequencedTxn {
+                seq: 22,
+                txn: dispute(3),
+            })
+            .is_ok());
+        assert_eq!(payments_engine.accounts[0].held().to_f64(), 15.0);
+        assert_eq!(payments_engine.accounts[0].available().to_f64(), 10.0);
+
+        // Stores which don't know when an id was seen can't tell epochs apart
+        let config = EngineConfig {
+            id_epoch: Some(10),
+            txn_registry: Some(_get_test_output_file("tst_id_epochs_registry.txt")),
+            ..EngineConfig::default()
+        };
+        assert!(PaymentsEngine::with_config(config).is_err());
     }
 }
@@ -0,0 +1,431 @@
+//! Trimming of `--txn-log` files, records before a sequence number are folded into an opening state written
+//! ahead of the kept records, so the log replays to the same state from the trim point on
+//! ```text
+//! # opening_state,seq=41,accounts=2,sha256=<hex>
+//! # 1,10.0000,0.0000,false,false
+//! # 2,0.0000,5.0000,true,false
+//! seq,type,client,tx,amount,...
+//! ```
+//! Opening accounts are `client,available,held,locked_by_chargeback,admin_hold` lines, or are saved as a
+//! sharded snapshot the header names with `snapshot=<dir>`, either way `sha256` covers the account lines
+//! so a replay fails instead of starting from an altered opening state
+
+use super::account_store::AccountStore;
+use super::live_snapshot::AccountSnapshot;
+use super::PaymentsEngine;
+use crate::account::Account;
+use crate::amount::{format_amount, Available, Held};
+use crate::cli_io::{RawInputTxn, TrimLogOptions};
+use crate::transaction::{AdminAction, SequencedTxn, Transaction};
+use csv::{ReaderBuilder, StringRecord, Trim, Writer};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, ErrorKind, Write};
+
+const HEADER_PREFIX: &str = "# opening_state,";
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, msg)
+}
+
+/// Where a trimmed log's opening accounts are kept
+#[derive(Debug, Clone, PartialEq)]
+pub enum OpeningAccounts {
+    Embedded(Vec<Account>),
+    /// Directory of a sharded snapshot
+    Snapshot(String),
+}
+
+/// State a trimmed log starts from
+#[derive(Debug, Clone, PartialEq)]
+pub struct OpeningState {
+    /// Sequence number of the last record folded into the state
+    pub seq: u64,
+    pub accounts: OpeningAccounts,
+    /// Hex sha256 of the account lines, ordered by client
+    pub sha256: String,
+}
+
+/// An opening account line, amounts are written at output precision so the digest is stable
+fn account_line(acnt: &Account) -> String {
+    format!(
+        "{},{},{},{},{}",
+        acnt.id,
+        format_amount(acnt.available().to_f64()),
+        format_amount(acnt.held().to_f64()),
+        acnt.locked_by_chargeback,
+        acnt.admin_hold
+    )
+}
+
+fn parse_account_line(line: &str) -> Option<Account> {
+    let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+    match fields[..] {
+        [id, available, held, locked_by_chargeback, admin_hold] => Some(
+            Account::with_balances(
+                id.parse().ok()?,
+                Available::from_f64(available.parse().ok()?),
+                Held::from_f64(held.parse().ok()?),
+            )
+            .with_locks(locked_by_chargeback.parse().ok()?, admin_hold.parse().ok()?),
+        ),
+        _ => None,
+    }
+}
+
+/// Digest of account lines in client order
+fn accounts_digest<'a>(accounts: impl Iterator<Item = &'a Account>) -> String {
+    let mut accounts: Vec<&Account> = accounts.collect();
+    accounts.sort_unstable_by_key(|acnt| acnt.id);
+    let mut hasher = Sha256::new();
+    for acnt in accounts {
+        hasher.update(account_line(acnt));
+        hasher.update(b"\n");
+    }
+    hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Reads the opening state ahead of a log's records, None for a log which was never trimmed
+pub(super) fn read_opening_state(file_path: &str) -> Result<Option<OpeningState>, io::Error> {
+    let mut lines = BufReader::new(File::open(file_path)?).lines();
+    let header = match lines.next().transpose()? {
+        Some(line) if line.starts_with(HEADER_PREFIX) => line,
+        _ => return Ok(None),
+    };
+    let invalid = || invalid_data(format!("Malformed opening state header in {}", file_path));
+    let fields: HashMap<&str, &str> = header[HEADER_PREFIX.len()..]
+        .split(',')
+        .map(|field| field.split_once('=').ok_or_else(invalid))
+        .collect::<Result<_, _>>()?;
+    let field = |name: &str| fields.get(name).copied().ok_or_else(invalid);
+    let seq: u64 = field("seq")?.parse().map_err(|_| invalid())?;
+    let sha256 = field("sha256")?.to_string();
+    let accounts = match fields.get("snapshot") {
+        Some(dir) => OpeningAccounts::Snapshot(dir.to_string()),
+        None => {
+            let count: usize = field("accounts")?.parse().map_err(|_| invalid())?;
+            let mut accounts = Vec::with_capacity(count);
+            for (indx, line) in lines.take(count).enumerate() {
+                let line = line?;
+                let acnt = line
+                    .strip_prefix("# ")
+                    .and_then(parse_account_line)
+                    .ok_or_else(|| {
+                        invalid_data(format!(
+                            "Malformed opening account on line {} of {}",
+                            indx + 2,
+                            file_path
+                        ))
+                    })?;
+                accounts.push(acnt);
+            }
+            if accounts.len() != count {
+                return Err(invalid_data(format!(
+                    "{} holds {} of its {} opening accounts",
+                    file_path,
+                    accounts.len(),
+                    count
+                )));
+            }
+            OpeningAccounts::Embedded(accounts)
+        }
+    };
+    Ok(Some(OpeningState {
+        seq,
+        accounts,
+        sha256,
+    }))
+}
+
+/// Writes the opening state lines a trimmed log starts with
+fn write_opening_state<W: Write>(out: &mut W, opening: &OpeningState) -> Result<(), io::Error> {
+    match &opening.accounts {
+        OpeningAccounts::Embedded(accounts) => {
+            writeln!(
+                out,
+                "{}seq={},accounts={},sha256={}",
+                HEADER_PREFIX,
+                opening.seq,
+                accounts.len(),
+                opening.sha256
+            )?;
+            for acnt in accounts {
+                writeln!(out, "# {}", account_line(acnt))?;
+            }
+        }
+        OpeningAccounts::Snapshot(dir) => writeln!(
+            out,
+            "{}seq={},sha256={},snapshot={}",
+            HEADER_PREFIX, opening.seq, opening.sha256, dir
+        )?,
+    }
+    Ok(())
+}
+
+/// Id of the txn a record refers to, None for records which don't refer to one
+fn referenced_txn(txn: &Transaction) -> Option<u32> {
+    match txn {
+        Transaction::Dispute(ref_txn)
+        | Transaction::Resolve(ref_txn)
+        | Transaction::Chargeback(ref_txn) => Some(ref_txn.ref_id),
+        Transaction::Refund(refund_txn) => Some(refund_txn.ref_id),
+        Transaction::Release(release_txn) => Some(release_txn.ref_id),
+        Transaction::Admin(admin_txn)
+            if matches!(admin_txn.action, AdminAction::Approve | AdminAction::Deny) =>
+        {
+            Some(admin_txn.instr_id)
+        }
+        _ => None,
+    }
+}
+
+/// Reads a logged record, erroring with its line
+pub(super) fn logged_txn(
+    file_path: &str,
+    headers: &StringRecord,
+    seq_indx: usize,
+    record: &StringRecord,
+) -> Result<SequencedTxn, io::Error> {
+    let line = record.position().map_or(0, |pos| pos.line());
+    let invalid =
+        |problem: String| invalid_data(format!("{} line {}: {}", file_path, line, problem));
+    let seq: u64 = record[seq_indx]
+        .parse()
+        .map_err(|_| invalid(format!("Invalid seq '{}'", &record[seq_indx])))?;
+    let raw: RawInputTxn = record
+        .deserialize(Some(headers))
+        .map_err(|e| invalid(e.to_string()))?;
+    let txn = raw
+        .convert_to_txn()
+        .map_err(|e| invalid(format!("{:?}", e)))?;
+    Ok(SequencedTxn { seq, txn })
+}
+
+/// Csv reader of a log's records, passing over the opening state lines of a trimmed log
+pub(super) fn txn_log_reader(file_path: &str) -> Result<csv::Reader<File>, io::Error> {
+    ReaderBuilder::new()
+        .trim(Trim::All)
+        .comment(Some(b'#'))
+        .from_path(file_path)
+        .map_err(io::Error::other)
+}
+
+/// Column index of a log's seq column
+pub(super) fn seq_column(file_path: &str, headers: &StringRecord) -> Result<usize, io::Error> {
+    headers.iter().position(|h| h == "seq").ok_or_else(|| {
+        invalid_data(format!(
+            "{} has no seq column, is it a --txn-log file?",
+            file_path
+        ))
+    })
+}
+
+impl PaymentsEngine {
+    /// Starts from a trimmed log's opening state, erroring if its accounts don't match the header's digest
+    pub(super) fn restore_opening_state(
+        &mut self,
+        opening: &OpeningState,
+    ) -> Result<(), io::Error> {
+        let accounts = match &opening.accounts {
+            OpeningAccounts::Embedded(accounts) => AccountStore::from_accounts(accounts.clone())
+                .map_err(|acnt_id| {
+                    invalid_data(format!("Client {} opens the log twice", acnt_id))
+                })?,
+            OpeningAccounts::Snapshot(dir) => AccountSnapshot::load_sharded(dir)?.accounts,
+        };
+        if accounts_digest(accounts.iter()) != opening.sha256 {
+            return Err(invalid_data(
+                "Opening state doesn't match its sha256, it was altered since the log was trimmed"
+                    .to_string(),
+            ));
+        }
+        self.restore_accounts(AccountSnapshot::new(opening.seq, accounts));
+        Ok(())
+    }
+}
+
+/// Counts of a trim
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TrimSummary {
+    /// Records folded into the opening state
+    pub trimmed: usize,
+    pub kept: usize,
+    pub accounts: usize,
+}
+
+/// Folds the log's records before `options.before` into an opening state & writes it ahead of the rest
+/// Refuses to trim a deposit or withdrawal which a kept record refers to, or records which don't replay
+/// under the rules given, as the trimmed log would no longer replay to the same state
+pub fn trim_txn_log(options: &TrimLogOptions) -> Result<TrimSummary, Box<dyn Error>> {
+    let file_path = options.txn_log.as_str();
+    let mut engine = PaymentsEngine::with_config(options.engine_config.clone())?;
+    if let Some(opening) = read_opening_state(file_path)? {
+        engine.restore_opening_state(&opening)?;
+    }
+    let mut rdr = txn_log_reader(file_path)?;
+    let headers = rdr.headers()?.clone();
+    let seq_indx = seq_column(file_path, &headers)?;
+
+    let mut trimmed_txns: HashMap<u32, u64> = HashMap::new();
+    let mut kept: Vec<StringRecord> = vec![];
+    let mut trimmed = 0;
+    for record in rdr.records() {
+        let record = record?;
+        let s_txn = logged_txn(file_path, &headers, seq_indx, &record)?;
+        if s_txn.seq >= options.before {
+            if let Some(seq) = referenced_txn(&s_txn.txn).and_then(|id| trimmed_txns.get(&id)) {
+                return Err(Box::new(invalid_data(format!(
+                    "Txn {} at seq {} is referenced by seq {}, trim at or before seq {}",
+                    s_txn.txn.id(),
+                    seq,
+                    s_txn.seq,
+                    seq
+                ))));
+            }
+            kept.push(record);
+            continue;
+        }
+        if let Transaction::Deposit(p_txn) | Transaction::Withdrawal(p_txn) = &s_txn.txn {
+            trimmed_txns.insert(p_txn.txn_id, s_txn.seq);
+        }
+        if let Err(e) = engine.process_sequenced_txn(&s_txn) {
+            return Err(Box::new(invalid_data(format!(
+                "Logged seq {} was rejected on replay with {:?}, pass the --rules the log was written under",
+                s_txn.seq, e
+            ))));
+        }
+        trimmed += 1;
+    }
+    if trimmed == 0 {
+        return Err(Box::new(invalid_data(format!(
+            "{} has no records before seq {} to trim",
+            file_path, options.before
+        ))));
+    }
+
+    let accounts: Vec<Account> = engine
+        .iter_accounts()
+        .map(|view| view.to_account())
+        .collect();
+    let sha256 = accounts_digest(accounts.iter());
+    let summary = TrimSummary {
+        trimmed,
+        kept: kept.len(),
+        accounts: accounts.len(),
+    };
+    let opening = OpeningState {
+        seq: engine.last_seq,
+        accounts: match &options.snapshot_dir {
+            Some(dir) => {
+                engine
+                    .snapshot()
+                    .save_sharded(dir, options.snapshot_shards)?;
+                OpeningAccounts::Snapshot(dir.clone())
+            }
+            None => OpeningAccounts::Embedded(accounts),
+        },
+        sha256,
+    };
+
+    // Written aside & renamed over the log, so a failed trim leaves the log as it was
+    let output = options.output.as_deref().unwrap_or(file_path);
+    let staging = format!("{}.trimming", output);
+    let mut file = io::BufWriter::new(File::create(&staging)?);
+    write_opening_state(&mut file, &opening)?;
+    let mut wtr = Writer::from_writer(file);
+    wtr.write_record(&headers)?;
+    for record in &kept {
+        wtr.write_record(record)?;
+    }
+    wtr.flush()?;
+    drop(wtr);
+    fs::rename(&staging, output)?;
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::trim_txn_log;
+    use crate::cli_io::{output_txn_log_csv, parse_cli_args, CliCommand, TrimLogOptions};
+    use crate::payments_engine::stream_process::StreamOptions;
+    use crate::payments_engine::PaymentsEngine;
+    use crate::test::utils::_get_test_output_file;
+
+    fn trim_options(args: &[&str]) -> TrimLogOptions {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        match parse_cli_args(&args) {
+            Ok(CliCommand::TrimLog(options)) => options,
+            _ => panic!("Expected a trim-log command"),
+        }
+    }
+
+    #[test]
+    fn tst_trim_log() {
+        let records = "type,client,tx,amount\n\
+                       deposit,1,1,10.0\n\
+                       deposit,2,2,5.0\n\
+                       dispute,2,2,\n\
+                       chargeback,2,2,\n\
+                       withdrawal,1,3,4.0\n\
+                       deposit,1,4,1.5\n\
+                       dispute,1,3,\n\
+                       resolve,1,3,\n";
+        let mut engine = PaymentsEngine::new();
+        assert!(engine
+            .stream_process_reader(records.as_bytes(), true, &StreamOptions::default())
+            .is_ok());
+        let f_log = _get_test_output_file("tst_trim_log.csv");
+        assert!(output_txn_log_csv(engine.numbered_txns(), &f_log).is_ok());
+        let expected = engine.account_end_states();
+
+        // Seq 7 disputes the withdrawal at seq 5
+        let e = trim_txn_log(&trim_options(&["trim-log", &f_log, "--before", "6"])).unwrap_err();
+        assert!(e.to_string().contains("trim at or before seq 5"), "{}", e);
+        let summary = trim_txn_log(&trim_options(&["trim-log", &f_log, "--before", "5"])).unwrap();
+        assert_eq!((summary.trimmed, summary.kept, summary.accounts), (4, 4, 2));
+
+        let contents = std::fs::read_to_string(&f_log).unwrap();
+        assert!(contents.starts_with("# opening_state,seq=4,accounts=2,sha256="));
+        assert!(contents.contains("# 2,0.0000,0.0000,true,false\n"));
+        let mut replayed = PaymentsEngine::new();
+        assert_eq!(replayed.load_txn_log(&f_log).unwrap(), 0);
+        assert_eq!(replayed.account_end_states(), expected);
+
+        // Trimming a trimmed log folds its opening state in, here into a referenced snapshot
+        let f_snapshot = _get_test_output_file("tst_trim_log_snapshot");
+        let f_retrimmed = _get_test_output_file("tst_trim_log_retrimmed.csv");
+        let options = trim_options(&[
+            "trim-log",
+            &f_log,
+            "--before",
+            "9",
+            "--snapshot-dir",
+            &f_snapshot,
+            "--output",
+            &f_retrimmed,
+        ]);
+        assert!(trim_txn_log(&options).is_ok());
+        let mut replayed = PaymentsEngine::new();
+        assert_eq!(replayed.load_txn_log(&f_retrimmed).unwrap(), 0);
+        assert_eq!(replayed.account_end_states(), expected);
+
+        let tampered = contents.replace(
+            "# 2,0.0000,0.0000,true,false",
+            "# 2,9.0000,0.0000,false,false",
+        );
+        std::fs::write(&f_log, tampered).unwrap();
+        assert!(PaymentsEngine::new().load_txn_log(&f_log).is_err());
+        let e = trim_txn_log(&trim_options(&["trim-log", &f_retrimmed, "--before", "3"]));
+        assert!(e
+            .unwrap_err()
+            .to_string()
+            .contains("no records before seq 3"));
+        assert!(parse_cli_args(&["trim-log".to_string(), f_log]).is_err());
+    }
+}
@@ -0,0 +1,282 @@
+use crate::transaction::{DisputeTransition, SequencedTxn, Transaction};
+use std::mem::size_of;
+use std::ops::{Index, IndexMut};
+
+/// Txns held by each block, a block is allocated once & never grows past this
+const BLOCK_LEN: usize = 1 << 16;
+/// Transitions a dispute history allocates room for on its first push, resolves & chargebacks fit after it
+const DISPUTE_HISTORY_CAPACITY: usize = 4;
+
+/// Heap bytes a txn owns, or for a dispute the history it grows on the disputed txn
+/// Only what can't change once the txn is stored is counted, so truncating gives back what pushing added
+fn heap_bytes(txn: &Transaction) -> usize {
+    match txn {
+        Transaction::Deposit(p_txn) | Transaction::Withdrawal(p_txn) => {
+            p_txn.memo.as_ref().map_or(0, String::capacity)
+        }
+        Transaction::Dispute(_) => DISPUTE_HISTORY_CAPACITY * size_of::<DisputeTransition>(),
+        _ => 0,
+    }
+}
+
+/// Handle to a txn stored in a `TxnArena`, only the arena hands these out
+/// A handle stays valid until a rollback truncates its txn away, handles to truncated txns panic on use
+/// instead of reaching whichever txn was pushed into their slot since
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct TxnKey {
+    indx: usize,
+    generation: u32,
+}
+
+impl TxnKey {
+    /// Index & generation, for stores which keep keys outside of memory
+    pub(super) fn into_parts(self) -> (usize, u32) {
+        (self.indx, self.generation)
+    }
+
+    /// Rebuilds a key from `into_parts`, it is checked against the arena like any other key
+    pub(super) fn from_parts(indx: usize, generation: u32) -> Self {
+        Self { indx, generation }
+    }
+}
+
+/// A stored txn with the arena generation it was pushed in
+#[derive(Debug)]
+struct Slot {
+    generation: u32,
+    s_txn: SequencedTxn,
+}
+
+/// Storage for processed txns, referenced by key or by position
+/// Txns are only appended, except that rolling back truncates the newest ones
+/// Txns are stored in fixed size blocks so growing never moves or copies a full block
+#[derive(Debug)]
+pub(super) struct TxnArena {
+    blocks: Vec<Vec<Slot>>,
+    len: usize,
+    /// Small runs start with a block sized to the hint, so they don't pay for a full one
+    first_block_capacity: usize,
+    /// Bumped by every truncation, so slots refilled afterwards don't match keys to the txns truncated away
+    generation: u32,
+    /// Heap bytes of the stored txns, kept as they're pushed & truncated
+    heap_bytes: usize,
+}
+
+impl Default for TxnArena {
+    fn default() -> Self {
+        Self::with_capacity(0)
+    }
+}
+
+impl TxnArena {
+    /// Reserves room for the block list of `expected_txns`, blocks themselves are allocated as they fill
+    pub(super) fn with_capacity(expected_txns: usize) -> Self {
+        Self {
+            blocks: Vec::with_capacity(expected_txns.div_ceil(BLOCK_LEN)),
+            len: 0,
+            first_block_capacity: expected_txns.min(BLOCK_LEN),
+            generation: 0,
+            heap_bytes: 0,
+        }
+    }
+
+    /// Stores a txn & returns the key it can be referenced by
+    pub(super) fn push(&mut self, s_txn: SequencedTxn) -> TxnKey {
+        let indx = self.len();
+        self.heap_bytes += heap_bytes(&s_txn.txn);
+        let s_txn = Slot {
+            generation: self.generation,
+            s_txn,
+        };
+        match self.blocks.last_mut() {
+            Some(block) if block.len() < BLOCK_LEN => block.push(s_txn),
+            _ => {
+                let capacity = match self.blocks.is_empty() {
+                    true => self.first_block_capacity,
+                    false => BLOCK_LEN,
+                };
+                let mut block = Vec::with_capacity(capacity);
+                block.push(s_txn);
+                self.blocks.push(block);
+            }
+        }
+        self.len += 1;
+        TxnKey {
+            indx,
+            generation: self.generation,
+        }
+    }
+
+    pub(super) fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Key the next pushed txn will get, so it can be stored before the txn is
+    pub(super) fn next_key(&self) -> TxnKey {
+        TxnKey {
+            indx: self.len,
+            generation: self.generation,
+        }
+    }
+
+    fn slot(&self, indx: usize) -> &Slot {
+        &self.blocks[indx / BLOCK_LEN][indx % BLOCK_LEN]
+    }
+
+    fn slot_mut(&mut self, indx: usize) -> &mut Slot {
+        &mut self.blocks[indx / BLOCK_LEN][indx % BLOCK_LEN]
+    }
+
+    /// False for keys to txns dropped by `truncate`, even once their slot holds another txn
+    pub(super) fn contains(&self, key: TxnKey) -> bool {
+        key.indx < self.len && self.slot(key.indx).generation == key.generation
+    }
+
+    fn check_key(&self, key: TxnKey) -> usize {
+        assert!(
+            self.contains(key),
+            "Txn key used after its txn was rolled back"
+        );
+        key.indx
+    }
+
+    /// Drops txns stored after the first `len`, keys to them no longer reach a txn
+    pub(super) fn truncate(&mut self, len: usize) {
+        if len >= self.len {
+            return;
+        }
+        self.generation = self.generation.wrapping_add(1);
+        let truncated: usize = (len..self.len)
+            .map(|indx| heap_bytes(&self.slot(indx).s_txn.txn))
+            .sum();
+        self.heap_bytes -= truncated;
+        let blocks = len.div_ceil(BLOCK_LEN);
+        self.blocks.truncate(blocks);
+        if let Some(block) = self.blocks.last_mut() {
+            block.truncate(len - (blocks - 1) * BLOCK_LEN);
+        }
+        self.len = len;
+    }
+
+    /// Bytes of the allocated blocks & of what the stored txns own on the heap
+    pub(super) fn memory_bytes(&self) -> usize {
+        let slots: usize = self.blocks.iter().map(Vec::capacity).sum();
+        slots * size_of::<Slot>() + self.heap_bytes
+    }
+
+    /// Txns in the order they were stored
+    pub(super) fn iter(&self) -> impl Iterator<Item = &SequencedTxn> {
+        self.blocks.iter().flatten().map(|slot| &slot.s_txn)
+    }
+}
+
+impl Index<usize> for TxnArena {
+    type Output = SequencedTxn;
+
+    fn index(&self, indx: usize) -> &SequencedTxn {
+        &self.slot(indx).s_txn
+    }
+}
+
+impl IndexMut<usize> for TxnArena {
+    fn index_mut(&mut self, indx: usize) -> &mut SequencedTxn {
+        &mut self.slot_mut(indx).s_txn
+    }
+}
+
+impl Index<TxnKey> for TxnArena {
+    type Output = SequencedTxn;
+
+    fn index(&self, key: TxnKey) -> &SequencedTxn {
+        &self[self.check_key(key)]
+    }
+}
+
+impl IndexMut<TxnKey> for TxnArena {
+    fn index_mut(&mut self, key: TxnKey) -> &mut SequencedTxn {
+        let indx = self.check_key(key);
+        &mut self[indx]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TxnArena, TxnKey, BLOCK_LEN};
+    use crate::test_support::pure_txn;
+    use crate::transaction::{PureTxn, RefTxn, SequencedTxn, Transaction};
+
+    fn s_txn(seq: u64) -> SequencedTxn {
+        SequencedTxn {
+            seq,
+            txn: Transaction::Dispute(RefTxn {
+                ref_id: 1,
+                acnt_id: 1,
+            }),
+        }
+    }
+
+    #[test]
+    fn tst_txn_arena() {
+        let mut arena = TxnArena::with_capacity(BLOCK_LEN + 1);
+        let keys: Vec<TxnKey> = (0..(BLOCK_LEN + 2) as u64)
+            .map(|seq| arena.push(s_txn(seq)))
+            .collect();
+        assert!(keys.iter().enumerate().all(|(indx, key)| key.indx == indx));
+        assert_eq!(arena.len(), BLOCK_LEN + 2);
+        assert_eq!(arena.blocks.len(), 2);
+        let first_block = arena.blocks[0].as_ptr();
+
+        arena[BLOCK_LEN + 1].seq = 7;
+        arena.push(s_txn(0));
+        assert_eq!(arena[BLOCK_LEN].seq, BLOCK_LEN as u64);
+        assert_eq!(arena[BLOCK_LEN + 1].seq, 7);
+        assert_eq!(
+            arena.blocks[0].as_ptr(),
+            first_block,
+            "Full blocks should never move"
+        );
+        assert!(arena.iter().map(|t| t.seq).take(3).eq([0, 1, 2]));
+
+        arena.truncate(BLOCK_LEN);
+        assert_eq!((arena.len(), arena.blocks.len()), (BLOCK_LEN, 1));
+        let refilled = arena.push(s_txn(9));
+        assert_eq!(refilled.indx, BLOCK_LEN);
+        // Keys to txns truncated away don't reach the txn refilling their slot, kept ones still work
+        assert!(arena.contains(refilled) && !arena.contains(keys[BLOCK_LEN]));
+        assert!(arena.contains(keys[BLOCK_LEN - 1]));
+        assert_eq!(arena[keys[1]].seq, 1);
+        assert_eq!(arena[refilled].seq, 9);
+    }
+
+    #[test]
+    fn tst_txn_arena_memory_bytes() {
+        let mut arena = TxnArena::with_capacity(4);
+        let empty = arena.memory_bytes();
+        arena.push(s_txn(1));
+        let slots = arena.memory_bytes();
+        assert!(slots > empty);
+        let memo = "invoice 1234".repeat(100);
+        arena.push(SequencedTxn {
+            seq: 2,
+            txn: Transaction::Deposit(PureTxn {
+                memo: Some(memo.clone()),
+                ..pure_txn(1, 2, 1.0)
+            }),
+        });
+        assert!(arena.memory_bytes() >= slots + memo.len());
+        // Truncating gives back the memo, the block stays allocated
+        arena.truncate(1);
+        assert_eq!(arena.memory_bytes(), slots);
+    }
+
+    #[test]
+    #[should_panic(expected = "Txn key used after its txn was rolled back")]
+    fn tst_txn_arena_stale_key() {
+        let mut arena = TxnArena::default();
+        arena.push(s_txn(1));
+        let stale = arena.push(s_txn(2));
+        arena.truncate(1);
+        arena.push(s_txn(3));
+        let _ = &arena[stale];
+    }
+}
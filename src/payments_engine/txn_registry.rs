@@ -0,0 +1,345 @@
+use super::PaymentsEngine;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, ErrorKind, Read, Write};
+
+/// Identifies registry files & their layout version
+const MAGIC: &[u8; 4] = b"TXR1";
+/// Chunks holding more ids than this switch from a sorted list to a bitmap
+const ARRAY_MAX: usize = 4096;
+/// Words in a bitmap chunk, one bit per low 16 bit value
+const BITMAP_WORDS: usize = 1024;
+/// Most ids a chunk can hold, one per low 16 bit value
+const CHUNK_MAX: usize = 1 << 16;
+
+/// Low 16 bits of the ids sharing a chunk, stored whichever way is smaller
+#[derive(Debug, Clone, PartialEq)]
+enum Chunk {
+    Sorted(Vec<u16>),
+    Bitmap(Box<[u64; BITMAP_WORDS]>),
+}
+
+impl Chunk {
+    fn contains(&self, low: u16) -> bool {
+        match self {
+            Chunk::Sorted(lows) => lows.binary_search(&low).is_ok(),
+            Chunk::Bitmap(words) => words[low as usize / 64] & (1 << (low % 64)) != 0,
+        }
+    }
+
+    /// Returns false if the value was already present
+    fn insert(&mut self, low: u16) -> bool {
+        match self {
+            Chunk::Sorted(lows) => {
+                let pos = match lows.binary_search(&low) {
+                    Ok(_) => return false,
+                    Err(pos) => pos,
+                };
+                lows.insert(pos, low);
+                if lows.len() > ARRAY_MAX {
+                    let mut words = Box::new([0_u64; BITMAP_WORDS]);
+                    for low in lows.iter() {
+                        words[*low as usize / 64] |= 1 << (low % 64);
+                    }
+                    *self = Chunk::Bitmap(words);
+                }
+                true
+            }
+            Chunk::Bitmap(words) => {
+                let (word, bit) = (low as usize / 64, 1 << (low % 64));
+                let is_new = words[word] & bit == 0;
+                words[word] |= bit;
+                is_new
+            }
+        }
+    }
+
+    /// Returns false if the value wasn't present, bitmaps stay bitmaps as ids are rarely removed
+    fn remove(&mut self, low: u16) -> bool {
+        match self {
+            Chunk::Sorted(lows) => match lows.binary_search(&low) {
+                Ok(pos) => {
+                    lows.remove(pos);
+                    true
+                }
+                Err(_) => false,
+            },
+            Chunk::Bitmap(words) => {
+                let (word, bit) = (low as usize / 64, 1 << (low % 64));
+                let was_present = words[word] & bit != 0;
+                words[word] &= !bit;
+                was_present
+            }
+        }
+    }
+}
+
+/// Compact set of every txn id accepted across runs, persisted between them
+/// Ids are split into chunks by their high 16 bits, so dense id ranges cost about a bit per id
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct TxnIdRegistry {
+    chunks: BTreeMap<u16, Chunk>,
+    len: usize,
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, msg.to_string())
+}
+
+impl TxnIdRegistry {
+    pub fn contains(&self, txn_id: u32) -> bool {
+        self.chunks
+            .get(&((txn_id >> 16) as u16))
+            .is_some_and(|chunk| chunk.contains(txn_id as u16))
+    }
+
+    pub fn insert(&mut self, txn_id: u32) {
+        let chunk = self
+            .chunks
+            .entry((txn_id >> 16) as u16)
+            .or_insert_with(|| Chunk::Sorted(vec![]));
+        if chunk.insert(txn_id as u16) {
+            self.len += 1;
+        }
+    }
+
+    pub fn remove(&mut self, txn_id: u32) {
+        if let Some(chunk) = self.chunks.get_mut(&((txn_id >> 16) as u16)) {
+            if chunk.remove(txn_id as u16) {
+                self.len -= 1;
+            }
+        }
+    }
+
+    /// Number of ids registered
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Reads a registry written by `save`, a missing file is an empty registry
+    /// Headers are checked before anything is allocated for them & chunks must be as `save` writes them,
+    /// sorted lists strictly ascending & bitmaps holding their count, so a corrupt file can't mislead lookups
+    pub fn load(file_path: &str) -> Result<Self, io::Error> {
+        let file = match File::open(file_path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e),
+        };
+        let mut rdr = BufReader::new(file);
+        let mut magic = [0_u8; 4];
+        rdr.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid_data("Not a txn id registry file"));
+        }
+
+        let mut registry = Self::default();
+        let mut header = [0_u8; 7];
+        loop {
+            match rdr.read_exact(&mut header) {
+                Ok(_) => {}
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(registry),
+                Err(e) => return Err(e),
+            }
+            let high = u16::from_le_bytes([header[0], header[1]]);
+            let count = u32::from_le_bytes([header[3], header[4], header[5], header[6]]) as usize;
+            if count > CHUNK_MAX {
+                return Err(invalid_data(
+                    "Txn id registry chunk holds more ids than it can",
+                ));
+            }
+            let chunk = match header[2] {
+                0 => {
+                    let mut buf = vec![0_u8; count * 2];
+                    rdr.read_exact(&mut buf)?;
+                    let lows: Vec<u16> = buf
+                        .chunks_exact(2)
+                        .map(|b| u16::from_le_bytes([b[0], b[1]]))
+                        .collect();
+                    if lows.windows(2).any(|pair| pair[0] >= pair[1]) {
+                        return Err(invalid_data(
+                            "Txn id registry chunk isn't sorted or repeats an id",
+                        ));
+                    }
+                    Chunk::Sorted(lows)
+                }
+                1 => {
+                    let mut words = Box::new([0_u64; BITMAP_WORDS]);
+                    let mut buf = [0_u8; 8];
+                    for word in words.iter_mut() {
+                        rdr.read_exact(&mut buf)?;
+                        *word = u64::from_le_bytes(buf);
+                    }
+                    let ones: usize = words.iter().map(|w| w.count_ones() as usize).sum();
+                    if ones != count {
+                        return Err(invalid_data(
+                            "Txn id registry bitmap doesn't hold its count of ids",
+                        ));
+                    }
+                    Chunk::Bitmap(words)
+                }
+                _ => return Err(invalid_data("Unknown txn id registry chunk kind")),
+            };
+            if registry.chunks.insert(high, chunk).is_some() {
+                return Err(invalid_data("Txn id registry repeats a chunk"));
+            }
+            registry.len += count;
+        }
+    }
+
+    /// Writes the registry next to `file_path` then renames it over, so a crash keeps the old copy
+    pub fn save(&self, file_path: &str) -> Result<(), io::Error> {
+        let tmp_path = format!("{}.tmp", file_path);
+        let mut wtr = BufWriter::new(File::create(&tmp_path)?);
+        wtr.write_all(MAGIC)?;
+        for (high, chunk) in self.chunks.iter() {
+            wtr.write_all(&high.to_le_bytes())?;
+            match chunk {
+                Chunk::Sorted(lows) => {
+                    wtr.write_all(&[0])?;
+                    wtr.write_all(&(lows.len() as u32).to_le_bytes())?;
+                    for low in lows {
+                        wtr.write_all(&low.to_le_bytes())?;
+                    }
+                }
+                Chunk::Bitmap(words) => {
+                    let count: u32 = words.iter().map(|w| w.count_ones()).sum();
+                    wtr.write_all(&[1])?;
+                    wtr.write_all(&count.to_le_bytes())?;
+                    for word in words.iter() {
+                        wtr.write_all(&word.to_le_bytes())?;
+                    }
+                }
+            }
+        }
+        wtr.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        fs::rename(tmp_path, file_path)
+    }
+}
+
+impl PaymentsEngine {
+    /// True if this or a previous run registered the txn id
+    pub(super) fn is_registered_txn(&self, txn_id: u32) -> bool {
+        self.txn_registry
+            .as_ref()
+            .is_some_and(|registry| registry.contains(txn_id))
+    }
+
+    /// Adds an accepted txn id to the registry when one is configured
+    pub(super) fn register_txn(&mut self, txn_id: u32) {
+        if let Some(registry) = &mut self.txn_registry {
+            registry.insert(txn_id);
+        }
+    }
+
+    /// Persists the txn id registry to its configured file so later runs reject replays
+    pub fn save_txn_registry(&self) -> Result<(), io::Error> {
+        match (&self.txn_registry, &self.config.txn_registry) {
+            (Some(registry), Some(file_path)) => registry.save(file_path),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TxnIdRegistry;
+    use crate::payments_engine::config::EngineConfig;
+    use crate::payments_engine::transactions::TxnErrors;
+    use crate::payments_engine::PaymentsEngine;
+    use crate::test::utils::_get_test_output_file;
+    use crate::test_support::deposit;
+
+    #[test]
+    fn tst_txn_id_registry_round_trip() {
+        let mut registry = TxnIdRegistry::default();
+        // Dense range forces a bitmap chunk, the scattered ids stay sorted lists
+        for txn_id in 0..10_000 {
+            registry.insert(txn_id);
+        }
+        for txn_id in [70_000, u32::MAX, 70_000] {
+            registry.insert(txn_id);
+        }
+        assert_eq!(registry.len(), 10_002);
+        assert!(registry.contains(9_999) && registry.contains(u32::MAX));
+        assert!(!registry.contains(10_000) && !registry.contains(70_001));
+
+        let f = _get_test_output_file("tst_txn_id_registry.bin");
+        registry.save(&f).unwrap();
+        let restored = TxnIdRegistry::load(&f).unwrap();
+        assert_eq!(restored, registry);
+        assert!(
+            std::fs::metadata(&f).unwrap().len() < 10_000,
+            "Dense ids should be stored as a bitmap"
+        );
+
+        let missing = _get_test_output_file("tst_txn_id_registry_missing.bin");
+        let _ = std::fs::remove_file(&missing);
+        assert!(TxnIdRegistry::load(&missing).unwrap().is_empty());
+    }
+
+    #[test]
+    fn tst_txn_id_registry_rejects_corrupt_files() {
+        let f = _get_test_output_file("tst_txn_id_registry_corrupt.bin");
+        let chunk = |kind: u8, count: u32, body: &[u8]| {
+            let mut bytes = b"TXR1".to_vec();
+            bytes.extend_from_slice(&1_u16.to_le_bytes());
+            bytes.push(kind);
+            bytes.extend_from_slice(&count.to_le_bytes());
+            bytes.extend_from_slice(body);
+            bytes
+        };
+        let lows =
+            |lows: &[u16]| -> Vec<u8> { lows.iter().flat_map(|l| l.to_le_bytes()).collect() };
+        let corrupt = [
+            // A header claiming more ids than a chunk holds isn't allocated for
+            chunk(0, u32::MAX, &[]),
+            chunk(0, 3, &lows(&[1, 5, 3])),
+            chunk(0, 2, &lows(&[4, 4])),
+            chunk(1, 5, &[0; 8 * 1024]),
+        ];
+        for bytes in corrupt {
+            std::fs::write(&f, bytes).unwrap();
+            assert!(TxnIdRegistry::load(&f).is_err());
+        }
+        let mut repeated = chunk(0, 1, &lows(&[2]));
+        repeated.extend_from_slice(&chunk(0, 1, &lows(&[3]))[4..]);
+        std::fs::write(&f, repeated).unwrap();
+        assert!(TxnIdRegistry::load(&f).is_err());
+
+        std::fs::write(&f, chunk(0, 3, &lows(&[1, 3, 5]))).unwrap();
+        let registry = TxnIdRegistry::load(&f).unwrap();
+        assert_eq!(registry.len(), 3);
+        assert!(registry.contains((1 << 16) + 3));
+    }
+
+    #[test]
+    fn tst_txn_registry_rejects_replays() {
+        let f = _get_test_output_file("tst_txn_registry_rejects_replays.bin");
+        let _ = std::fs::remove_file(&f);
+        let config = EngineConfig {
+            txn_registry: Some(f.clone()),
+            ..EngineConfig::default()
+        };
+
+        let mut first_run = PaymentsEngine::with_config(config.clone()).unwrap();
+        assert!(first_run.process_txn(&deposit(1, 1, 1.0)).is_ok());
+        assert!(first_run.process_txn(&deposit(1, 2, 1.0)).is_ok());
+        first_run.save_txn_registry().unwrap();
+
+        let mut second_run = PaymentsEngine::with_config(config).unwrap();
+        assert_eq!(
+            second_run.process_txn(&deposit(1, 1, 1.0)),
+            Err(TxnErrors::TxnIdAlreadyExists)
+        );
+        assert_eq!(
+            second_run.simulate_txn(&deposit(1, 2, 1.0)).map(|_| ()),
+            Err(TxnErrors::TxnIdAlreadyExists)
+        );
+        assert!(second_run.process_txn(&deposit(1, 3, 1.0)).is_ok());
+        assert!(second_run.accounts[0].available().to_f64() == 1.0);
+    }
+}
@@ -0,0 +1,292 @@
+//! Reconciles the deposits & withdrawals of a run's txn log against an external bank statement
+//! Both sides are keyed by reference, our memo against the statement's reference column, & amounts are
+//! compared in minor units with withdrawals counting as debits
+
+use crate::amount::{format_minor_units, parse_minor_units};
+use crate::cli_io::{csv_writer, ReconcileOptions};
+use crate::diagnostics::{log, Level};
+use csv::{ReaderBuilder, StringRecord, Trim, Writer};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::io::{self, ErrorKind};
+
+/// Net amount per reference in minor units, with the rows skipped for having no reference
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ReferencedAmounts {
+    pub amounts: BTreeMap<String, i64>,
+    pub unreferenced: u64,
+}
+
+impl ReferencedAmounts {
+    fn add(&mut self, reference: &str, minor: i64) {
+        match reference.is_empty() {
+            true => self.unreferenced += 1,
+            false => *self.amounts.entry(reference.to_string()).or_default() += minor,
+        }
+    }
+}
+
+/// Bucket a reference is reconciled into
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ReconcileStatus {
+    Matched,
+    /// On the bank statement but not in our log
+    MissingOnOurs,
+    /// In our log but not on the bank statement
+    MissingOnTheirs,
+    AmountMismatch,
+}
+
+impl ReconcileStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ReconcileStatus::Matched => "matched",
+            ReconcileStatus::MissingOnOurs => "missing_on_ours",
+            ReconcileStatus::MissingOnTheirs => "missing_on_theirs",
+            ReconcileStatus::AmountMismatch => "amount_mismatch",
+        }
+    }
+}
+
+/// How one reference reconciled, amounts in minor units
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReconcileRow {
+    pub status: ReconcileStatus,
+    pub reference: String,
+    pub ours: Option<i64>,
+    pub theirs: Option<i64>,
+}
+
+fn missing_column(file_path: &str, column: &str) -> io::Error {
+    io::Error::new(
+        ErrorKind::InvalidData,
+        format!("Missing {} column in {}", column, file_path),
+    )
+}
+
+fn column_index(file_path: &str, headers: &StringRecord, column: &str) -> Result<usize, io::Error> {
+    headers
+        .iter()
+        .position(|h| h == column)
+        .ok_or_else(|| missing_column(file_path, column))
+}
+
+fn parse_amount(file_path: &str, row: usize, value: &str) -> Result<i64, io::Error> {
+    parse_minor_units(value).ok_or_else(|| {
+        io::Error::new(
+            ErrorKind::InvalidData,
+            format!("Invalid amount '{}' on row {} of {}", value, row, file_path),
+        )
+    })
+}
+
+/// Net amount per memo of a txn log's deposits & withdrawals, withdrawals negative
+/// Trimmed logs are read past their opening state, only the kept records are reconciled
+pub fn read_txn_log_amounts(file_path: &str) -> Result<ReferencedAmounts, Box<dyn Error>> {
+    let mut rdr = ReaderBuilder::new()
+        .trim(Trim::All)
+        .comment(Some(b'#'))
+        .from_path(file_path)?;
+    let headers = rdr.headers()?.clone();
+    let type_indx = column_index(file_path, &headers, "type")?;
+    let amount_indx = column_index(file_path, &headers, "amount")?;
+    let memo_indx = column_index(file_path, &headers, "memo")?;
+
+    let mut ours = ReferencedAmounts::default();
+    for (row, result) in rdr.records().enumerate() {
+        let record = result?;
+        let sign = match &record[type_indx] {
+            "deposit" => 1,
+            "withdrawal" => -1,
+            _ => continue,
+        };
+        let minor = parse_amount(file_path, row + 1, &record[amount_indx])?;
+        ours.add(&record[memo_indx], sign * minor);
+    }
+    Ok(ours)
+}
+
+/// Net amount per reference of a bank statement, credits positive & debits negative
+pub fn read_bank_amounts(
+    file_path: &str,
+    reference_column: &str,
+    amount_column: &str,
+) -> Result<ReferencedAmounts, Box<dyn Error>> {
+    let mut rdr = ReaderBuilder::new().trim(Trim::All).from_path(file_path)?;
+    let headers = rdr.headers()?.clone();
+    let reference_indx = column_index(file_path, &headers, reference_column)?;
+    let amount_indx = column_index(file_path, &headers, amount_column)?;
+
+    let mut theirs = ReferencedAmounts::default();
+    for (row, result) in rdr.records().enumerate() {
+        let record = result?;
+        let minor = parse_amount(file_path, row + 1, &record[amount_indx])?;
+        theirs.add(&record[reference_indx], minor);
+    }
+    Ok(theirs)
+}
+
+/// Buckets every reference either side has, ordered by reference
+pub fn reconcile(
+    ours: &BTreeMap<String, i64>,
+    theirs: &BTreeMap<String, i64>,
+) -> Vec<ReconcileRow> {
+    let mut references: Vec<&String> = ours.keys().chain(theirs.keys()).collect();
+    references.sort();
+    references.dedup();
+
+    references
+        .into_iter()
+        .map(|reference| {
+            let ours = ours.get(reference).copied();
+            let theirs = theirs.get(reference).copied();
+            let status = match (ours, theirs) {
+                (Some(o), Some(t)) if o == t => ReconcileStatus::Matched,
+                (Some(_), Some(_)) => ReconcileStatus::AmountMismatch,
+                (Some(_), None) => ReconcileStatus::MissingOnTheirs,
+                (None, _) => ReconcileStatus::MissingOnOurs,
+            };
+            ReconcileRow {
+                status,
+                reference: reference.clone(),
+                ours,
+                theirs,
+            }
+        })
+        .collect()
+}
+
+/// Writes a reconciliation report of the txn log against the bank statement
+pub fn reconcile_execute(options: &ReconcileOptions) -> Result<(), Box<dyn Error>> {
+    let ours = read_txn_log_amounts(&options.txn_log)?;
+    let theirs = read_bank_amounts(
+        &options.bank_statement,
+        &options.reference_column,
+        &options.amount_column,
+    )?;
+    if ours.unreferenced + theirs.unreferenced > 0 {
+        log(
+            Level::Warn,
+            format_args!(
+                "Skipped {} txn log & {} bank statement rows without a reference",
+                ours.unreferenced, theirs.unreferenced
+            ),
+        );
+    }
+    let rows = reconcile(&ours.amounts, &theirs.amounts);
+    let mut counts: BTreeMap<ReconcileStatus, usize> = BTreeMap::new();
+    for row in rows.iter() {
+        *counts.entry(row.status).or_default() += 1;
+    }
+    let count = |status| counts.get(&status).copied().unwrap_or_default();
+    log(
+        Level::Info,
+        format_args!(
+            "Reconciled {} references: matched={} missing_on_ours={} missing_on_theirs={} amount_mismatch={}",
+            rows.len(),
+            count(ReconcileStatus::Matched),
+            count(ReconcileStatus::MissingOnOurs),
+            count(ReconcileStatus::MissingOnTheirs),
+            count(ReconcileStatus::AmountMismatch)
+        ),
+    );
+    write_reconcile_report(csv_writer(&options.output)?, &rows)
+}
+
+/// Writes one `status,reference,ours,theirs,difference` row per reference, difference being theirs less ours
+pub fn write_reconcile_report<W: io::Write>(
+    mut wtr: Writer<W>,
+    rows: &[ReconcileRow],
+) -> Result<(), Box<dyn Error>> {
+    wtr.write_record(["status", "reference", "ours", "theirs", "difference"])?;
+    let amount = |minor: Option<i64>| minor.map_or(String::new(), format_minor_units);
+    for row in rows.iter() {
+        let difference = match (row.ours, row.theirs) {
+            (Some(o), Some(t)) => Some(t - o),
+            _ => None,
+        };
+        wtr.write_record([
+            row.status.as_str(),
+            &row.reference,
+            &amount(row.ours),
+            &amount(row.theirs),
+            &amount(difference),
+        ])?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_bank_amounts, read_txn_log_amounts, reconcile, write_reconcile_report};
+    use super::{ReconcileRow, ReconcileStatus};
+    use crate::test::utils::_get_test_output_file;
+    use csv::Writer;
+
+    #[test]
+    fn tst_reconcile() {
+        let f_log = _get_test_output_file("tst_reconcile_txn_log.csv");
+        let f_bank = _get_test_output_file("tst_reconcile_bank.csv");
+        std::fs::write(
+            &f_log,
+            "seq,type,client,tx,amount,dispute_state,dispute_history,memo,channel,acnt_seq\n\
+             1,deposit,1,1,10.0000,none,,INV-1,,1\n\
+             2,deposit,2,2,5.0000,none,,INV-2,,1\n\
+             3,withdrawal,1,3,2.5000,none,,PAY-3,,2\n\
+             4,dispute,1,1,,,,,,3\n\
+             5,deposit,2,4,1.0000,none,,,,2\n\
+             6,deposit,1,5,3.0000,none,,INV-5,,4\n\
+             7,deposit,1,6,1.0000,none,,INV-5,,5\n",
+        )
+        .unwrap();
+        std::fs::write(
+            &f_bank,
+            "Date,Ref,Value\n\
+             2024-05-01,INV-1,10.00\n\
+             2024-05-01,INV-2,4.50\n\
+             2024-05-02,PAY-3,-2.5\n\
+             2024-05-02,INV-5,4\n\
+             2024-05-03,FEE-9,-0.25\n\
+             2024-05-03,,1.00\n",
+        )
+        .unwrap();
+
+        let ours = read_txn_log_amounts(&f_log).unwrap();
+        assert_eq!(ours.unreferenced, 1);
+        assert!(read_bank_amounts(&f_bank, "reference", "amount").is_err());
+        let theirs = read_bank_amounts(&f_bank, "Ref", "Value").unwrap();
+        assert_eq!(theirs.unreferenced, 1);
+
+        let rows = reconcile(&ours.amounts, &theirs.amounts);
+        let statuses: Vec<(&str, ReconcileStatus)> = rows
+            .iter()
+            .map(|row| (row.reference.as_str(), row.status))
+            .collect();
+        assert_eq!(
+            statuses,
+            [
+                ("FEE-9", ReconcileStatus::MissingOnOurs),
+                ("INV-1", ReconcileStatus::Matched),
+                ("INV-2", ReconcileStatus::AmountMismatch),
+                ("INV-5", ReconcileStatus::Matched),
+                ("PAY-3", ReconcileStatus::Matched),
+            ]
+        );
+
+        let mut out = vec![];
+        let missing = ReconcileRow {
+            status: ReconcileStatus::MissingOnTheirs,
+            reference: "INV-7".to_string(),
+            ours: Some(12_500),
+            theirs: None,
+        };
+        write_reconcile_report(Writer::from_writer(&mut out), &[rows[2].clone(), missing]).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "status,reference,ours,theirs,difference\n\
+             amount_mismatch,INV-2,5.0000,4.5000,-0.5000\n\
+             missing_on_theirs,INV-7,1.2500,,\n"
+        );
+    }
+}
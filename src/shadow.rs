@@ -0,0 +1,304 @@
+//! Comparison of final balances against a system of record over http, to verify a migration before cut-over
+//! For each sampled client the ledger gets `GET <url>` with `{client}` replaced by the client id & answers with
+//! `{"available": 1.5, "held": 0.0, "total": 1.5, "locked": false}`, `locked` may be left out
+//! A 404 means the ledger doesn't know the client
+
+use crate::account::Account;
+use crate::amount::{format_amount, format_wide_minor_units, to_minor_units};
+use crate::diagnostics::{log, Level};
+use crate::http;
+use csv::Writer;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::time::Duration;
+
+/// Longest wait for the ledger to answer about a single client
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Where balances of the system of record are fetched from & how many clients are checked
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShadowConfig {
+    /// Url of a client's balances, `{client}` is replaced with the client id
+    pub url: String,
+    /// Clients checked, spread evenly over client ids, every client when there are fewer
+    pub sample: usize,
+    /// Discrepancies are written to this csv as well as summarized on stderr
+    pub report_path: Option<String>,
+}
+
+impl Default for ShadowConfig {
+    fn default() -> Self {
+        Self {
+            url: String::new(),
+            sample: 100,
+            report_path: None,
+        }
+    }
+}
+
+/// Balances as the ledger reports them
+#[derive(Debug, Deserialize)]
+struct LedgerBalances {
+    available: f64,
+    held: f64,
+    total: f64,
+    #[serde(default)]
+    locked: Option<bool>,
+}
+
+/// A field of a sampled client the ledger disagrees on
+/// `field` is `missing` when the ledger doesn't know the client & `error` when it couldn't be asked
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Discrepancy {
+    pub client: u16,
+    pub field: &'static str,
+    pub engine: String,
+    pub ledger: String,
+}
+
+/// Up to `sample` accounts spread evenly over client ids, so repeated runs check the same clients
+pub fn sample_accounts(accounts: &[Account], sample: usize) -> Vec<&Account> {
+    let mut sorted: Vec<&Account> = accounts.iter().collect();
+    sorted.sort_by_key(|acnt| acnt.id);
+    if sample >= sorted.len() {
+        return sorted;
+    }
+    let step = sorted.len() as f64 / sample as f64;
+    (0..sample)
+        .map(|ii| sorted[(ii as f64 * step) as usize])
+        .collect()
+}
+
+/// Fields of an account the ledger's balances differ on, amounts are compared at output precision
+fn field_discrepancies(acnt: &Account, ledger: &LedgerBalances) -> Vec<Discrepancy> {
+    let amounts = [
+        ("available", acnt.available().to_f64(), ledger.available),
+        ("held", acnt.held().to_f64(), ledger.held),
+        ("total", acnt.get_total(), ledger.total),
+    ];
+    let mut discrepancies: Vec<Discrepancy> = amounts
+        .into_iter()
+        .filter(|(_, engine, ledger)| to_minor_units(*engine) != to_minor_units(*ledger))
+        .map(|(field, engine, ledger)| Discrepancy {
+            client: acnt.id,
+            field,
+            engine: format_amount(engine),
+            ledger: format_amount(ledger),
+        })
+        .collect();
+    if let Some(locked) = ledger.locked.filter(|locked| *locked != acnt.is_locked()) {
+        discrepancies.push(Discrepancy {
+            client: acnt.id,
+            field: "locked",
+            engine: acnt.is_locked().to_string(),
+            ledger: locked.to_string(),
+        });
+    }
+    discrepancies
+}
+
+/// Asks the ledger about one account, a failed request is a discrepancy too
+fn check_account(acnt: &Account, url: &str) -> Vec<Discrepancy> {
+    let error = |ledger: String| {
+        vec![Discrepancy {
+            client: acnt.id,
+            field: "error",
+            engine: String::new(),
+            ledger,
+        }]
+    };
+    let url = url.replace("{client}", &acnt.id.to_string());
+    let response = match http::get(&url, REQUEST_TIMEOUT) {
+        Ok(response) => response,
+        Err(e) => return error(e.to_string()),
+    };
+    match response.status {
+        200 => match serde_json::from_str::<LedgerBalances>(&response.body) {
+            Ok(ledger) => field_discrepancies(acnt, &ledger),
+            Err(e) => error(format!("Unreadable balances: {}", e)),
+        },
+        404 => vec![Discrepancy {
+            client: acnt.id,
+            field: "missing",
+            engine: format_wide_minor_units(acnt.total_minor_units()),
+            ledger: String::new(),
+        }],
+        status => error(format!("Status {}", status)),
+    }
+}
+
+/// Fetches balances of a sample of accounts from the ledger & returns where they differ
+pub fn shadow_compare(accounts: &[Account], config: &ShadowConfig) -> Vec<Discrepancy> {
+    sample_accounts(accounts, config.sample)
+        .into_iter()
+        .flat_map(|acnt| check_account(acnt, &config.url))
+        .collect()
+}
+
+/// Writes discrepancies to a csv report
+pub fn output_shadow_report_csv(
+    discrepancies: &[Discrepancy],
+    file_path: &str,
+) -> Result<(), Box<dyn Error>> {
+    let mut wtr = Writer::from_path(file_path)?;
+    for discrepancy in discrepancies {
+        wtr.serialize(discrepancy)?;
+    }
+    // Serializing nothing leaves the file without a header
+    if discrepancies.is_empty() {
+        wtr.write_record(["client", "field", "engine", "ledger"])?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+/// Compares accounts with the ledger after a run, summarizing the result on stderr & writing the report
+pub fn shadow_execute(accounts: &[Account], config: &ShadowConfig) -> Result<(), Box<dyn Error>> {
+    let discrepancies = shadow_compare(accounts, config);
+    let mut clients: Vec<u16> = discrepancies.iter().map(|d| d.client).collect();
+    clients.dedup();
+    let sampled = config.sample.min(accounts.len());
+    match clients.len() {
+        0 => log(
+            Level::Info,
+            format_args!("All {} sampled clients match the ledger", sampled),
+        ),
+        count => log(
+            Level::Warn,
+            format_args!(
+                "{} of {} sampled clients differ from the ledger",
+                count, sampled
+            ),
+        ),
+    }
+    if let Some(report_path) = &config.report_path {
+        output_shadow_report_csv(&discrepancies, report_path)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        output_shadow_report_csv, sample_accounts, shadow_compare, Discrepancy, ShadowConfig,
+    };
+    use crate::account::Account;
+    use crate::amount::{Available, Held};
+    use crate::cli_io::{parse_cli_args, CliCommand};
+    use crate::test::utils::_get_test_output_file;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    fn account(id: u16, available: f64) -> Account {
+        Account::with_balances(id, Available::from_f64(available), Held::default())
+    }
+
+    #[test]
+    fn tst_sample_accounts() {
+        let accounts: Vec<Account> = (1..=10).rev().map(|id| account(id, 0.0)).collect();
+        let ids = |sample| -> Vec<u16> {
+            sample_accounts(&accounts, sample)
+                .iter()
+                .map(|acnt| acnt.id)
+                .collect()
+        };
+        assert_eq!(ids(4), vec![1, 3, 6, 8]);
+        assert_eq!(ids(20), (1..=10).collect::<Vec<u16>>());
+    }
+
+    #[test]
+    fn tst_shadow_compare() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!(
+            "http://{}/balances/{{client}}",
+            listener.local_addr().unwrap()
+        );
+        // The ledger agrees on client 1, disagrees on client 2, & doesn't know client 3
+        let server = thread::spawn(move || {
+            for _ in 0..4 {
+                let (stream, _) = listener.accept().unwrap();
+                let mut rdr = BufReader::new(stream);
+                let mut request_line = String::new();
+                rdr.read_line(&mut request_line).unwrap();
+                let (status, body) = match request_line.split_whitespace().nth(1).unwrap() {
+                    "/balances/1" => (200, r#"{"available":1.5,"held":0,"total":1.5}"#),
+                    "/balances/2" => (
+                        200,
+                        r#"{"available":2.0,"held":0,"total":2.0,"locked":true}"#,
+                    ),
+                    "/balances/3" => (404, ""),
+                    _ => (200, "not json"),
+                };
+                let response = format!(
+                    "HTTP/1.1 {} X\r\nContent-Length: {}\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+                rdr.get_mut().write_all(response.as_bytes()).unwrap();
+            }
+        });
+        let accounts = vec![
+            account(1, 1.5),
+            account(2, 2.5),
+            account(3, 1.0),
+            account(4, 1.0),
+        ];
+        let config = ShadowConfig {
+            url,
+            ..ShadowConfig::default()
+        };
+        let discrepancies = shadow_compare(&accounts, &config);
+        server.join().unwrap();
+        let discrepancy = |client, field, engine: &str, ledger: &str| Discrepancy {
+            client,
+            field,
+            engine: engine.to_string(),
+            ledger: ledger.to_string(),
+        };
+        assert_eq!(
+            discrepancies[..4],
+            [
+                discrepancy(2, "available", "2.5000", "2.0000"),
+                discrepancy(2, "total", "2.5000", "2.0000"),
+                discrepancy(2, "locked", "false", "true"),
+                discrepancy(3, "missing", "1.0000", ""),
+            ]
+        );
+        assert_eq!(discrepancies[4].client, 4);
+        assert_eq!(discrepancies[4].field, "error");
+        assert_eq!(discrepancies.len(), 5);
+
+        let f_report = _get_test_output_file("tst_shadow_compare.csv");
+        assert!(output_shadow_report_csv(&discrepancies[..2], &f_report).is_ok());
+        assert_eq!(
+            std::fs::read_to_string(&f_report).unwrap(),
+            "client,field,engine,ledger\n2,available,2.5000,2.0000\n2,total,2.5000,2.0000\n"
+        );
+    }
+
+    #[test]
+    fn tst_parse_shadow_args() {
+        let args = |args: &[&str]| -> Vec<String> { args.iter().map(|a| a.to_string()).collect() };
+        match parse_cli_args(&args(&[
+            "in.csv",
+            "--shadow-url",
+            "http://ledger/balances/{client}",
+            "--shadow-sample",
+            "5",
+        ])) {
+            Ok(CliCommand::Process(cli_options)) => assert_eq!(
+                cli_options.shadow,
+                Some(ShadowConfig {
+                    url: "http://ledger/balances/{client}".to_string(),
+                    sample: 5,
+                    report_path: None,
+                })
+            ),
+            _ => panic!("Should parse as process command"),
+        }
+        assert!(parse_cli_args(&args(&["in.csv", "--shadow-url", "http://ledger/"])).is_err());
+        assert!(parse_cli_args(&args(&["in.csv", "--shadow-report", "r.csv"])).is_err());
+    }
+}
@@ -0,0 +1,175 @@
+//! Long running generate & process loop reporting throughput & memory, to catch memory growth before release
+//! Allocation stats are only gathered when the binary installs `CountingAllocator` as its global allocator
+//! Soaking generates its records so needs the `gen` feature, the allocation stats don't
+
+#[cfg(any(test, feature = "gen"))]
+use crate::{
+    cli_io::{csv_writer, SoakOptions},
+    generator::{seeded_rng, ScenarioStream},
+    payments_engine::PaymentsEngine,
+};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicU64, Ordering};
+#[cfg(any(test, feature = "gen"))]
+use std::{error::Error, time::Instant};
+
+/// Records generated & processed between clock checks
+#[cfg(any(test, feature = "gen"))]
+const SOAK_BATCH: usize = 10_000;
+
+static ALLOCATED_BYTES: AtomicU64 = AtomicU64::new(0);
+static FREED_BYTES: AtomicU64 = AtomicU64::new(0);
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+
+/// System allocator which counts what passes through it
+pub struct CountingAllocator;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATED_BYTES.fetch_add(layout.size() as u64, Ordering::Relaxed);
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        FREED_BYTES.fetch_add(layout.size() as u64, Ordering::Relaxed);
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATED_BYTES.fetch_add(new_size as u64, Ordering::Relaxed);
+        FREED_BYTES.fetch_add(layout.size() as u64, Ordering::Relaxed);
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+/// Bytes currently allocated & allocations made so far, None unless `CountingAllocator` is installed
+pub fn allocation_stats() -> Option<(u64, u64)> {
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    if allocations == 0 {
+        return None;
+    }
+    let live = ALLOCATED_BYTES
+        .load(Ordering::Relaxed)
+        .saturating_sub(FREED_BYTES.load(Ordering::Relaxed));
+    Some((live, allocations))
+}
+
+/// Resident set size of this process, None where `/proc` isn't available
+pub fn resident_set_bytes() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let kb = status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmRSS:"))?
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse::<u64>()
+        .ok()?;
+    Some(kb * 1024)
+}
+
+#[cfg(any(test, feature = "gen"))]
+fn optional(value: Option<u64>) -> String {
+    value.map_or(String::new(), |value| value.to_string())
+}
+
+/// Generates & processes records until the duration is up or the generator runs out of txn ids
+/// Writes a report row every interval & a last one when stopping
+#[cfg(any(test, feature = "gen"))]
+pub fn soak_execute(options: &SoakOptions) -> Result<(), Box<dyn Error>> {
+    let mut rng = seeded_rng(options.seed);
+    let mut stream = ScenarioStream::new(options.scenario, options.clients);
+    let mut payments_engine = PaymentsEngine::new();
+    let mut wtr = csv_writer(&options.output)?;
+    wtr.write_record([
+        "elapsed_secs",
+        "records",
+        "records_per_sec",
+        "rss_bytes",
+        "live_alloc_bytes",
+        "allocations",
+        "accounts",
+        "processed_txns",
+        "estimated_memory_bytes",
+    ])?;
+    wtr.flush()?;
+
+    let start = Instant::now();
+    let mut last_report = start;
+    let mut records: u64 = 0;
+    let mut records_at_last_report: u64 = 0;
+    loop {
+        let finished =
+            start.elapsed() >= options.duration || (stream.txn_ids_left() as usize) < SOAK_BATCH;
+        if finished || last_report.elapsed() >= options.report_every {
+            let interval = last_report.elapsed().as_secs_f64().max(1e-9);
+            let rate = (records - records_at_last_report) as f64 / interval;
+            let alloc_stats = allocation_stats();
+            wtr.write_record([
+                format!("{:.1}", start.elapsed().as_secs_f64()),
+                records.to_string(),
+                format!("{:.0}", rate),
+                optional(resident_set_bytes()),
+                optional(alloc_stats.map(|(live, _)| live)),
+                optional(alloc_stats.map(|(_, allocations)| allocations)),
+                payments_engine.accounts.len().to_string(),
+                payments_engine.processed_txns_len().to_string(),
+                payments_engine.estimated_memory_bytes().to_string(),
+            ])?;
+            // Rows are flushed as they're written so a soak can be watched live
+            wtr.flush()?;
+            last_report = Instant::now();
+            records_at_last_report = records;
+        }
+        if finished {
+            break;
+        }
+        for txn in stream.next_batch(SOAK_BATCH, &mut rng) {
+            // Generated streams contain records the business rules reject
+            let _ = payments_engine.process_txn(&txn);
+        }
+        records += SOAK_BATCH as u64;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{resident_set_bytes, soak_execute};
+    use crate::cli_io::{OutputMethod, SoakOptions};
+    use crate::generator::Scenario;
+    use crate::test::utils::_get_test_output_file;
+    use std::time::Duration;
+
+    #[test]
+    fn tst_soak_execute() {
+        let f_output = _get_test_output_file("tst_soak_execute.csv");
+        let options = SoakOptions {
+            scenario: Scenario::Steady,
+            clients: 50,
+            duration: Duration::from_millis(300),
+            report_every: Duration::from_millis(100),
+            seed: Some(7),
+            output: OutputMethod::Csv(f_output.clone()),
+        };
+        assert!(soak_execute(&options).is_ok());
+        let report = std::fs::read_to_string(&f_output).unwrap();
+        let rows: Vec<Vec<&str>> = report
+            .lines()
+            .skip(1)
+            .map(|line| line.split(',').collect())
+            .collect();
+        assert!(rows.len() >= 2, "Expected a first & a final row");
+        let last = rows.last().unwrap();
+        assert!(last[1].parse::<u64>().unwrap() > 0);
+        assert!(
+            last[7].parse::<u64>().unwrap() > 0,
+            "Accepted txns are stored"
+        );
+        if cfg!(target_os = "linux") {
+            assert!(resident_set_bytes().is_some());
+        }
+    }
+}
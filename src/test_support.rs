@@ -0,0 +1,179 @@
+//! Fluent setup of engines in a known state for tests, ours & those of crates embedding the engine
+//! Built with the `test_support` feature, e.g. as a dev dependency feature downstream
+
+use crate::account::Account;
+use crate::amount::TxnAmount;
+use crate::payments_engine::config::EngineConfig;
+use crate::payments_engine::oracle::Oracle;
+use crate::payments_engine::{PaymentsEngine, TxnErrors};
+use crate::transaction::{
+    AdminAction, AdminTxn, DisputeHistory, PureTxn, RefTxn, RefundTxn, ReleaseTxn, Transaction,
+};
+
+/// Txns to run through a fresh engine, in the order they are added
+#[derive(Debug, Default)]
+pub struct EngineFixture {
+    config: EngineConfig,
+    txns: Vec<Transaction>,
+}
+
+/// Engine a fixture was built into
+#[derive(Debug)]
+pub struct Fixture {
+    pub engine: PaymentsEngine,
+    /// Accounts the standard rules lead to, in the order they were created
+    /// Only describes the engine when its config `uses_standard_rules`
+    pub expected: Vec<Account>,
+    /// Outcome of each fixture txn, in the order they were added
+    pub results: Vec<Result<(), TxnErrors>>,
+}
+
+/// Deposit or withdrawal body without a memo or rail, set those with struct update syntax, e.g.
+/// `PureTxn { channel: Some(Channel::Ach), ..pure_txn(1, 1, 5.0) }`
+pub fn pure_txn(acnt_id: u16, txn_id: u32, amount: f64) -> PureTxn {
+    PureTxn {
+        txn_id,
+        acnt_id,
+        amount: TxnAmount::from_f64(amount),
+        dispute: DisputeHistory::default(),
+        memo: None,
+        channel: None,
+    }
+}
+
+/// Deposit for tests processing txns one at a time, the fixture's `deposit` adds one to a fixture
+pub fn deposit(acnt_id: u16, txn_id: u32, amount: f64) -> Transaction {
+    Transaction::Deposit(pure_txn(acnt_id, txn_id, amount))
+}
+
+pub fn withdrawal(acnt_id: u16, txn_id: u32, amount: f64) -> Transaction {
+    Transaction::Withdrawal(pure_txn(acnt_id, txn_id, amount))
+}
+
+impl EngineFixture {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Builds the engine with non default behavior
+    pub fn with_config(config: EngineConfig) -> Self {
+        Self {
+            config,
+            txns: vec![],
+        }
+    }
+
+    /// Adds any txn, for ones the shorthands don't cover, e.g. deposits with a memo
+    pub fn txn(mut self, txn: Transaction) -> Self {
+        self.txns.push(txn);
+        self
+    }
+
+    pub fn deposit(self, acnt_id: u16, txn_id: u32, amount: f64) -> Self {
+        self.txn(deposit(acnt_id, txn_id, amount))
+    }
+
+    pub fn withdrawal(self, acnt_id: u16, txn_id: u32, amount: f64) -> Self {
+        self.txn(withdrawal(acnt_id, txn_id, amount))
+    }
+
+    pub fn dispute(self, acnt_id: u16, ref_id: u32) -> Self {
+        self.txn(Transaction::Dispute(RefTxn { ref_id, acnt_id }))
+    }
+
+    pub fn resolve(self, acnt_id: u16, ref_id: u32) -> Self {
+        self.txn(Transaction::Resolve(RefTxn { ref_id, acnt_id }))
+    }
+
+    pub fn chargeback(self, acnt_id: u16, ref_id: u32) -> Self {
+        self.txn(Transaction::Chargeback(RefTxn { ref_id, acnt_id }))
+    }
+
+    pub fn refund(self, acnt_id: u16, ref_id: u32, amount: f64) -> Self {
+        self.txn(Transaction::Refund(RefundTxn {
+            ref_id,
+            acnt_id,
+            amount: TxnAmount::from_f64(amount),
+        }))
+    }
+
+    pub fn release(self, acnt_id: u16, ref_id: u32, amount: f64) -> Self {
+        self.txn(Transaction::Release(ReleaseTxn {
+            ref_id,
+            acnt_id,
+            amount: TxnAmount::from_f64(amount),
+        }))
+    }
+
+    pub fn admin(self, acnt_id: u16, instr_id: u32, action: AdminAction) -> Self {
+        self.txn(Transaction::Admin(AdminTxn {
+            instr_id,
+            acnt_id,
+            action,
+        }))
+    }
+
+    /// Processes every txn on a new engine & works out the expected accounts alongside
+    /// Panics if the config can't create an engine, fixtures shouldn't point at missing resources
+    pub fn build(self) -> Fixture {
+        let mut engine = match PaymentsEngine::with_config(self.config) {
+            Ok(engine) => engine,
+            Err(e) => panic!("Fixture config can't create an engine: {}", e),
+        };
+        let mut oracle = Oracle::default();
+        let mut created = vec![];
+        let mut results = vec![];
+        for txn in &self.txns {
+            results.push(engine.process_txn(txn));
+            let acnt_id = txn.acnt_id();
+            let existed = oracle.account(acnt_id).is_some();
+            oracle.apply(txn);
+            if !existed && oracle.account(acnt_id).is_some() {
+                created.push(acnt_id);
+            }
+        }
+        let expected = created
+            .iter()
+            .filter_map(|acnt_id| oracle.account(*acnt_id).cloned())
+            .collect();
+        Fixture {
+            engine,
+            expected,
+            results,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::EngineFixture;
+    use crate::payments_engine::TxnErrors;
+    use crate::transaction::AdminAction;
+
+    #[test]
+    fn tst_engine_fixture() {
+        let fixture = EngineFixture::new()
+            .deposit(1, 1, 10.0)
+            .deposit(2, 2, 5.0)
+            .withdrawal(2, 3, 7.5)
+            .dispute(1, 1)
+            .resolve(1, 1)
+            .dispute(2, 2)
+            .chargeback(2, 2)
+            .admin(1, 4, AdminAction::SetHold)
+            .build();
+        assert_eq!(fixture.results[2], Err(TxnErrors::AccountLacksFunds));
+        assert_eq!(
+            fixture
+                .results
+                .iter()
+                .filter(|result| result.is_err())
+                .count(),
+            1
+        );
+        assert_eq!(fixture.engine.accounts.to_vec(), fixture.expected);
+        let acnt = fixture.engine.accounts.get(2).unwrap();
+        assert!(acnt.locked_by_chargeback);
+        assert_eq!(acnt.get_total(), 0.0);
+    }
+}
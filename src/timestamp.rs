@@ -0,0 +1,176 @@
+//! Record timestamps, read as unix seconds or RFC 3339 & kept as unix seconds in UTC
+//! Calendar math follows the proleptic Gregorian calendar, so no date library is needed
+
+const SECS_PER_DAY: i64 = 86_400;
+
+/// Days since 1970-01-01 of a calendar date
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month_from_march = (month as i64 + 9) % 12;
+    let day_of_year = (153 * month_from_march + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Calendar date of a number of days since 1970-01-01
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_from_march + 2) / 5 + 1) as u32;
+    let month = if month_from_march < 10 {
+        month_from_march + 3
+    } else {
+        month_from_march - 9
+    } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Parses a fixed width run of digits
+fn digits(text: &str, range: std::ops::Range<usize>) -> Option<u32> {
+    let field = text.get(range)?;
+    match field.bytes().all(|b| b.is_ascii_digit()) {
+        true => field.parse().ok(),
+        false => None,
+    }
+}
+
+/// Parses unix seconds, e.g. `1714571100`, or an RFC 3339 date time, e.g. `2024-05-01T13:45:00Z`
+/// RFC 3339 times may use a space for the `T`, a fraction of a second, which is dropped, & a `+hh:mm` offset
+pub fn parse_timestamp(text: &str) -> Option<i64> {
+    let text = text.trim();
+    if let Ok(secs) = text.parse::<i64>() {
+        return Some(secs);
+    }
+    let (year, month, day) = (
+        digits(text, 0..4)?,
+        digits(text, 5..7)?,
+        digits(text, 8..10)?,
+    );
+    let (hour, minute, second) = (
+        digits(text, 11..13)?,
+        digits(text, 14..16)?,
+        digits(text, 17..19)?,
+    );
+    let separators = [(4, b'-'), (7, b'-'), (13, b':'), (16, b':')];
+    let bytes = text.as_bytes();
+    if separators.iter().any(|(indx, sep)| bytes[*indx] != *sep)
+        || !matches!(bytes[10], b'T' | b't' | b' ')
+        || !(1..=12).contains(&month)
+        || day == 0
+        || day > days_in_month(year as i64, month)
+        || hour > 23
+        || minute > 59
+        || second > 60
+    {
+        return None;
+    }
+    let mut zone = &text[19..];
+    if let Some(fraction) = zone.strip_prefix('.') {
+        let frac_len = fraction.bytes().take_while(u8::is_ascii_digit).count();
+        if frac_len == 0 {
+            return None;
+        }
+        zone = &fraction[frac_len..];
+    }
+    let offset = match zone {
+        "Z" | "z" => 0,
+        _ => {
+            let sign = match zone.as_bytes().first()? {
+                b'+' => 1,
+                b'-' => -1,
+                _ => return None,
+            };
+            if zone.len() != 6 || zone.as_bytes()[3] != b':' {
+                return None;
+            }
+            let (hours, minutes) = (digits(zone, 1..3)?, digits(zone, 4..6)?);
+            if hours > 23 || minutes > 59 {
+                return None;
+            }
+            sign * (hours as i64 * 3600 + minutes as i64 * 60)
+        }
+    };
+    let days = days_from_civil(year as i64, month, day);
+    // A leap second is read as the first second of the next minute
+    Some(days * SECS_PER_DAY + hour as i64 * 3600 + minute as i64 * 60 + second as i64 - offset)
+}
+
+/// Formats unix seconds as an RFC 3339 date time in UTC, e.g. `2024-05-01T13:45:00Z`
+pub fn format_timestamp(secs: i64) -> String {
+    let secs_of_day = secs.rem_euclid(SECS_PER_DAY);
+    format!(
+        "{}T{:02}:{:02}:{:02}Z",
+        format_date(secs),
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60
+    )
+}
+
+/// Formats the UTC date of unix seconds, e.g. `2024-05-01`
+pub fn format_date(secs: i64) -> String {
+    let (year, month, day) = civil_from_days(secs.div_euclid(SECS_PER_DAY));
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_date, format_timestamp, parse_timestamp};
+
+    #[test]
+    fn tst_parse_timestamp() {
+        assert_eq!(parse_timestamp("1714571100"), Some(1714571100));
+        assert_eq!(parse_timestamp("2024-05-01T13:45:00Z"), Some(1714571100));
+        assert_eq!(
+            parse_timestamp(" 2024-05-01 13:45:00.250z "),
+            Some(1714571100)
+        );
+        assert_eq!(
+            parse_timestamp("2024-05-01T15:45:00+02:00"),
+            Some(1714571100)
+        );
+        assert_eq!(
+            parse_timestamp("2024-05-01T08:45:00-05:00"),
+            Some(1714571100)
+        );
+        assert_eq!(parse_timestamp("1970-01-01T00:00:00Z"), Some(0));
+        assert_eq!(parse_timestamp("1969-12-31T23:59:59Z"), Some(-1));
+        assert_eq!(parse_timestamp("2024-02-29T00:00:00Z"), Some(1709164800));
+        for invalid in [
+            "",
+            "yesterday",
+            "2024-05-01",
+            "2024-05-01T13:45:00",
+            "2023-02-29T00:00:00Z",
+            "2024-13-01T00:00:00Z",
+            "2024-05-01T24:00:00Z",
+            "2024-05-01T13:45:00.Z",
+            "2024-05-01T13:45:00+0200",
+            "2024/05/01T13:45:00Z",
+        ] {
+            assert_eq!(parse_timestamp(invalid), None, "{}", invalid);
+        }
+
+        assert_eq!(format_timestamp(1714571100), "2024-05-01T13:45:00Z");
+        assert_eq!(format_timestamp(-1), "1969-12-31T23:59:59Z");
+        assert_eq!(format_date(1709164800), "2024-02-29");
+        assert_eq!(format_date(951782400), "2000-02-29");
+    }
+}
@@ -1,3 +1,5 @@
+use crate::amount::TxnAmount;
+
 /// Financial transactions which can affect an accounts held & available amounts
 #[derive(Debug, Clone, PartialEq)]
 pub enum Transaction {
@@ -6,6 +8,37 @@ pub enum Transaction {
     Dispute(RefTxn),
     Resolve(RefTxn),
     Chargeback(RefTxn),
+    Admin(AdminTxn),
+    Refund(RefundTxn),
+    Release(ReleaseTxn),
+}
+
+impl Transaction {
+    /// Account the transaction is addressed to
+    pub fn acnt_id(&self) -> u16 {
+        match self {
+            Transaction::Deposit(p_txn) | Transaction::Withdrawal(p_txn) => p_txn.acnt_id,
+            Transaction::Dispute(ref_txn)
+            | Transaction::Resolve(ref_txn)
+            | Transaction::Chargeback(ref_txn) => ref_txn.acnt_id,
+            Transaction::Admin(admin_txn) => admin_txn.acnt_id,
+            Transaction::Refund(refund_txn) => refund_txn.acnt_id,
+            Transaction::Release(release_txn) => release_txn.acnt_id,
+        }
+    }
+
+    /// Id given in the tx column, a referenced id for disputes, resolves, chargebacks, refunds, & releases
+    pub fn id(&self) -> u32 {
+        match self {
+            Transaction::Deposit(p_txn) | Transaction::Withdrawal(p_txn) => p_txn.txn_id,
+            Transaction::Dispute(ref_txn)
+            | Transaction::Resolve(ref_txn)
+            | Transaction::Chargeback(ref_txn) => ref_txn.ref_id,
+            Transaction::Admin(admin_txn) => admin_txn.instr_id,
+            Transaction::Refund(refund_txn) => refund_txn.ref_id,
+            Transaction::Release(release_txn) => release_txn.ref_id,
+        }
+    }
 }
 
 /// A transaction which adds or removes an amount
@@ -13,8 +46,122 @@ pub enum Transaction {
 pub struct PureTxn {
     pub txn_id: u32,
     pub acnt_id: u16,
-    pub amount: f64,
-    pub disputed: bool,
+    pub amount: TxnAmount,
+    pub dispute: DisputeHistory,
+    /// Free text passed through from input, e.g. an invoice number, has no effect on processing
+    pub memo: Option<String>,
+    /// Payment rail the funds moved over, rules configured for it apply when set
+    pub channel: Option<Channel>,
+}
+
+/// Payment rails deposits & withdrawals can arrive over
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Channel {
+    Card,
+    Ach,
+    Wire,
+}
+
+impl Channel {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Channel::Card => "card",
+            Channel::Ach => "ach",
+            Channel::Wire => "wire",
+        }
+    }
+}
+
+impl std::str::FromStr for Channel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "card" => Ok(Channel::Card),
+            "ach" => Ok(Channel::Ach),
+            "wire" => Ok(Channel::Wire),
+            _ => Err(format!("Unknown channel '{}'", s)),
+        }
+    }
+}
+
+/// Where a pure transaction is in the dispute lifecycle
+/// Undisputed -> Disputed -> Resolved | ChargedBack, resolved txns may be disputed again
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum DisputeState {
+    #[default]
+    Undisputed,
+    Disputed,
+    Resolved,
+    ChargedBack,
+}
+
+impl DisputeState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DisputeState::Undisputed => "undisputed",
+            DisputeState::Disputed => "disputed",
+            DisputeState::Resolved => "resolved",
+            DisputeState::ChargedBack => "chargedback",
+        }
+    }
+
+    /// True if the lifecycle allows moving from this state to `next`
+    pub fn can_transition(&self, next: DisputeState) -> bool {
+        matches!(
+            (self, next),
+            (
+                DisputeState::Undisputed | DisputeState::Resolved,
+                DisputeState::Disputed
+            ) | (
+                DisputeState::Disputed,
+                DisputeState::Resolved | DisputeState::ChargedBack
+            )
+        )
+    }
+}
+
+/// A dispute state a txn entered & the sequence number of the record which moved it there
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DisputeTransition {
+    pub state: DisputeState,
+    pub seq: u64,
+}
+
+/// Every dispute state change of a pure txn, oldest first
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct DisputeHistory {
+    transitions: Vec<DisputeTransition>,
+}
+
+impl DisputeHistory {
+    pub fn state(&self) -> DisputeState {
+        self.transitions
+            .last()
+            .map_or(DisputeState::Undisputed, |t| t.state)
+    }
+
+    pub fn is_disputed(&self) -> bool {
+        self.state() == DisputeState::Disputed
+    }
+
+    pub fn transitions(&self) -> &[DisputeTransition] {
+        &self.transitions
+    }
+
+    /// Records a move to `state`, callers check the move is allowed with `can_transition`
+    pub fn record(&mut self, state: DisputeState, seq: u64) {
+        self.transitions.push(DisputeTransition { state, seq });
+    }
+
+    /// Transitions as `state@seq` separated by spaces, empty for undisputed txns
+    pub fn to_export_str(&self) -> String {
+        self.transitions
+            .iter()
+            .map(|t| format!("{}@{}", t.state.as_str(), t.seq))
+            .collect::<Vec<String>>()
+            .join(" ")
+    }
 }
 
 /// A transaction which references another transaction
@@ -25,3 +172,126 @@ pub struct RefTxn {
     /// Account Id this transaction should affect, should align with the reference transaction
     pub acnt_id: u16,
 }
+
+/// Return of part or all of a deposit to its payer, taken from the account's available funds
+#[derive(Debug, Clone, PartialEq)]
+pub struct RefundTxn {
+    /// Deposit being refunded, refunds don't get ids of their own
+    pub ref_id: u32,
+    pub acnt_id: u16,
+    pub amount: TxnAmount,
+}
+
+/// Return of part of a disputed txn's held funds to available, the rest stays disputed
+/// Processors often settle disputes partially, the dispute is closed later by a resolve or chargeback
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReleaseTxn {
+    /// Disputed txn whose held funds are released
+    pub ref_id: u32,
+    pub acnt_id: u16,
+    pub amount: TxnAmount,
+}
+
+/// Manual operations on an account
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AdminAction {
+    /// Place a manual hold, locking the account
+    SetHold,
+    /// Clear a manual hold, a chargeback lock is unaffected
+    ClearHold,
+    /// Release a withdrawal pending approval, its held funds leave the account
+    Approve,
+    /// Reject a withdrawal pending approval, its held funds return to available
+    Deny,
+}
+
+/// An operator instruction against an account
+/// Holds don't move funds, approvals & denials settle the held funds of a withdrawal pending approval
+#[derive(Debug, Clone, PartialEq)]
+pub struct AdminTxn {
+    /// Identifier of the instruction, not checked against transaction ids
+    /// For approvals & denials it is the id of the withdrawal being decided
+    pub instr_id: u32,
+    pub acnt_id: u16,
+    pub action: AdminAction,
+}
+
+/// A transaction tagged with the order it was ingested in
+/// Stored with processed transactions so replays can prove global ordering was preserved
+#[derive(Debug, Clone, PartialEq)]
+pub struct SequencedTxn {
+    /// Monotonically increasing over everything ingested by an engine, starts at 1
+    pub seq: u64,
+    pub txn: Transaction,
+}
+
+/// Hands out sequence numbers to transactions at ingest
+#[derive(Debug, Default)]
+pub struct Sequencer {
+    last_seq: u64,
+}
+
+impl Sequencer {
+    pub fn assign(&mut self, txn: Transaction) -> SequencedTxn {
+        self.last_seq += 1;
+        SequencedTxn {
+            seq: self.last_seq,
+            txn,
+        }
+    }
+
+    /// Advances past a sequence number assigned elsewhere so later assignments stay monotonic
+    pub fn observe(&mut self, seq: u64) {
+        self.last_seq = self.last_seq.max(seq);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DisputeHistory, DisputeState, RefTxn, Sequencer, Transaction};
+
+    #[test]
+    fn tst_sequencer_assign() {
+        let mut sequencer = Sequencer::default();
+        let txn = Transaction::Dispute(RefTxn {
+            ref_id: 1,
+            acnt_id: 1,
+        });
+        assert_eq!(sequencer.assign(txn.clone()).seq, 1);
+        let sequenced = sequencer.assign(txn.clone());
+        assert_eq!(sequenced.seq, 2);
+        assert_eq!(sequenced.txn, txn);
+
+        sequencer.observe(10);
+        sequencer.observe(5);
+        assert_eq!(sequencer.assign(txn).seq, 11);
+    }
+
+    #[test]
+    fn tst_dispute_state_transitions() {
+        let mut history = DisputeHistory::default();
+        assert_eq!(history.state(), DisputeState::Undisputed);
+        assert!(!history.state().can_transition(DisputeState::Resolved));
+        assert!(history.state().can_transition(DisputeState::Disputed));
+        history.record(DisputeState::Disputed, 2);
+        assert!(history.is_disputed());
+        history.record(DisputeState::Resolved, 4);
+        assert!(history.state().can_transition(DisputeState::Disputed));
+        history.record(DisputeState::Disputed, 5);
+        history.record(DisputeState::ChargedBack, 9);
+        for next in [
+            DisputeState::Disputed,
+            DisputeState::Resolved,
+            DisputeState::ChargedBack,
+        ] {
+            assert!(
+                !history.state().can_transition(next),
+                "Charged back txns are final"
+            );
+        }
+        assert_eq!(
+            history.to_export_str(),
+            "disputed@2 resolved@4 disputed@5 chargedback@9"
+        );
+    }
+}
@@ -0,0 +1,364 @@
+//! Rewrites of input records before they are converted to txns, so partner quirks don't need pre-processing
+//! Transforms are written as short expressions:
+//! - `amount*<factor>` or `amount/<divisor>` scales deposit & withdrawal amounts, e.g. `amount/100` for cents
+//! - `client=<lookup.csv>` replaces client ids through a csv of `from,to` rows
+//!
+//! `--anonymize` adds pseudonymizing transforms after those, so outputs can be shared with vendors
+//! Pseudonyms are not encryption: the permutation only spans 65536 ids & a few known `pseudonym,client`
+//! pairs are enough to recover every other one, so shared outputs are pseudonymised, not anonymous
+
+use csv::Writer;
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::{self, ErrorKind};
+
+/// Feistel rounds of the client id permutation
+const PSEUDONYM_ROUNDS: u64 = 8;
+
+/// Environment variable the `--anonymize` secret is read from when no `--anonymize-key-file` is given
+pub const ANONYMIZE_KEY_VAR: &str = "TOYPAYMENTENGINE_ANONYMIZE_KEY";
+
+/// Keyed permutation of client ids, every client gets a distinct pseudonym & the key reverses it
+/// An 8 round Feistel network over a 64 bit key, it hides ids from casual reading but is not a cipher
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClientPseudonyms {
+    key: u64,
+}
+
+/// Mixes a 64 bit value, the finalizer of splitmix64
+fn mix(value: u64) -> u64 {
+    let mut z = value.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+impl ClientPseudonyms {
+    /// Derives the permutation from a secret, FNV-1a keeps it the same across builds & platforms
+    pub fn new(secret: &str) -> Self {
+        let key = secret.bytes().fold(0xcbf2_9ce4_8422_2325, |hash: u64, b| {
+            (hash ^ b as u64).wrapping_mul(0x0100_0000_01b3)
+        });
+        Self { key }
+    }
+
+    /// Derives the permutation from the secret in `key_file`, or else in `ANONYMIZE_KEY_VAR`
+    /// Keeping it out of the arguments keeps it out of process listings & shell history
+    pub fn from_key_source(key_file: Option<&str>) -> Result<Self, io::Error> {
+        let secret = match key_file {
+            Some(file_path) => std::fs::read_to_string(file_path)?,
+            None => std::env::var(ANONYMIZE_KEY_VAR).map_err(|_| {
+                invalid_input(format!(
+                    "--anonymize requires --anonymize-key-file or {}",
+                    ANONYMIZE_KEY_VAR
+                ))
+            })?,
+        };
+        let secret = secret.trim_end_matches(['\r', '\n']);
+        if secret.is_empty() {
+            return Err(invalid_input("--anonymize secret is empty".to_string()));
+        }
+        Ok(Self::new(secret))
+    }
+
+    fn round(&self, round: u64, half: u8) -> u8 {
+        mix(self.key ^ (round << 8) ^ half as u64) as u8
+    }
+
+    pub fn pseudonym(&self, client: u16) -> u16 {
+        let [mut left, mut right] = client.to_be_bytes();
+        for round in 0..PSEUDONYM_ROUNDS {
+            (left, right) = (right, left ^ self.round(round, right));
+        }
+        u16::from_be_bytes([left, right])
+    }
+
+    /// Client a pseudonym stands for
+    pub fn client(&self, pseudonym: u16) -> u16 {
+        let [mut left, mut right] = pseudonym.to_be_bytes();
+        for round in (0..PSEUDONYM_ROUNDS).rev() {
+            (left, right) = (right ^ self.round(round, left), left);
+        }
+        u16::from_be_bytes([left, right])
+    }
+
+    /// Writes a `pseudonym,client` row per pseudonym, so shared outputs can be read back
+    pub fn output_map_csv(
+        &self,
+        pseudonyms: impl Iterator<Item = u16>,
+        file_path: &str,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut wtr = Writer::from_path(file_path)?;
+        wtr.write_record(["pseudonym", "client"])?;
+        for pseudonym in pseudonyms {
+            wtr.write_record([pseudonym.to_string(), self.client(pseudonym).to_string()])?;
+        }
+        wtr.flush()?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum IngestTransform {
+    /// Multiplies amounts by the factor
+    ScaleAmount(f64),
+    /// Partner client ids & the ids they stand for, records of clients missing from it are rejected
+    RemapClients(HashMap<u16, u16>),
+    /// Replaces client ids by their pseudonyms
+    Pseudonymize(ClientPseudonyms),
+    /// Drops deposit & withdrawal amount places past the count
+    TruncateAmount(usize),
+}
+
+fn invalid_input(msg: String) -> io::Error {
+    io::Error::new(ErrorKind::InvalidInput, msg)
+}
+
+fn parse_factor(expr: &str, value: &str) -> Result<f64, io::Error> {
+    match value.trim().parse::<f64>() {
+        Ok(factor) if factor.is_finite() && factor != 0.0 => Ok(factor),
+        _ => Err(invalid_input(format!(
+            "Transform '{}' needs a non zero number",
+            expr
+        ))),
+    }
+}
+
+/// Reads a `from,to` csv of client ids, the header is required so the direction can't be mixed up
+fn read_client_lookup(file_path: &str) -> Result<HashMap<u16, u16>, io::Error> {
+    let mut rdr = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_path(file_path)?;
+    if rdr.headers()?.iter().collect::<Vec<_>>() != ["from", "to"] {
+        return Err(invalid_input(format!(
+            "Client lookup {} needs a from,to header",
+            file_path
+        )));
+    }
+    let mut lookup = HashMap::new();
+    for result in rdr.deserialize::<(u16, u16)>() {
+        let (from, to) = result.map_err(|e| invalid_input(format!("{} in {}", e, file_path)))?;
+        if lookup.insert(from, to).is_some() {
+            return Err(invalid_input(format!(
+                "Client {} is mapped twice in {}",
+                from, file_path
+            )));
+        }
+    }
+    Ok(lookup)
+}
+
+impl IngestTransform {
+    /// Pseudonyms the transforms replace client ids by, if any
+    pub fn pseudonyms(transforms: &[IngestTransform]) -> Option<&ClientPseudonyms> {
+        transforms.iter().find_map(|transform| match transform {
+            IngestTransform::Pseudonymize(pseudonyms) => Some(pseudonyms),
+            _ => None,
+        })
+    }
+
+    /// Parses a transform expression, reading any lookup file it names
+    pub fn parse(expr: &str) -> Result<Self, io::Error> {
+        let expr = expr.trim();
+        if let Some(factor) = expr.strip_prefix("amount*") {
+            return Ok(IngestTransform::ScaleAmount(parse_factor(expr, factor)?));
+        }
+        if let Some(divisor) = expr.strip_prefix("amount/") {
+            return Ok(IngestTransform::ScaleAmount(
+                1.0 / parse_factor(expr, divisor)?,
+            ));
+        }
+        if let Some(file_path) = expr.strip_prefix("client=") {
+            return Ok(IngestTransform::RemapClients(read_client_lookup(
+                file_path.trim(),
+            )?));
+        }
+        Err(invalid_input(format!(
+            "Unknown transform '{}', expected amount*<n>, amount/<n>, or client=<lookup.csv>",
+            expr
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ClientPseudonyms, IngestTransform, ANONYMIZE_KEY_VAR};
+    use crate::cli_io::{parse_cli_args, CliCommand};
+    use crate::payments_engine::PaymentsEngine;
+    use crate::test::utils::_get_test_output_file;
+
+    #[test]
+    fn tst_parse_transforms() {
+        assert_eq!(
+            IngestTransform::parse("amount*100").unwrap(),
+            IngestTransform::ScaleAmount(100.0)
+        );
+        assert_eq!(
+            IngestTransform::parse(" amount/4 ").unwrap(),
+            IngestTransform::ScaleAmount(0.25)
+        );
+        assert!(IngestTransform::parse("amount/0").is_err());
+        assert!(IngestTransform::parse("amount+1").is_err());
+
+        let f_lookup = _get_test_output_file("tst_parse_transforms.csv");
+        std::fs::write(&f_lookup, "from,to\n7001,1\n7002,2\n").unwrap();
+        match IngestTransform::parse(&format!("client={}", f_lookup)).unwrap() {
+            IngestTransform::RemapClients(lookup) => {
+                assert_eq!(lookup.get(&7002), Some(&2));
+                assert_eq!(lookup.len(), 2);
+            }
+            other => panic!("Expected a client remap, got {:?}", other),
+        }
+        std::fs::write(&f_lookup, "from,to\n7001,1\n7001,2\n").unwrap();
+        assert!(IngestTransform::parse(&format!("client={}", f_lookup)).is_err());
+    }
+
+    #[test]
+    fn tst_ingest_transforms() {
+        let f_lookup = _get_test_output_file("tst_ingest_transforms_lookup.csv");
+        std::fs::write(&f_lookup, "from,to\n7001,1\n7002,2\n").unwrap();
+        let f_input = _get_test_output_file("tst_ingest_transforms_input.csv");
+        std::fs::write(
+            &f_input,
+            "type, client, tx, amount\n\
+             deposit, 7001, 1, 1250\n\
+             deposit, 7002, 2, 99\n\
+             withdrawal, 7001, 3, 250\n\
+             deposit, 7003, 4, 100\n\
+             dispute, 7002, 2,\n",
+        )
+        .unwrap();
+        let f_output = _get_test_output_file("tst_ingest_transforms.csv");
+        let f_dead_letter = _get_test_output_file("tst_ingest_transforms_dlq.jsonl");
+        let _ = std::fs::remove_file(&f_dead_letter);
+        let client_transform = format!("client={}", f_lookup);
+        let args: Vec<String> = [
+            f_input.as_str(),
+            "--transform",
+            "amount/100",
+            "--transform",
+            &client_transform,
+            "--dead-letter",
+            &f_dead_letter,
+            "--output",
+            &f_output,
+        ]
+        .iter()
+        .map(|a| a.to_string())
+        .collect();
+        let cli_options = match parse_cli_args(&args) {
+            Ok(CliCommand::Process(cli_options)) => cli_options,
+            _ => panic!("Should parse as process command"),
+        };
+        let mut payments_engine = PaymentsEngine::new();
+        assert!(payments_engine.streaming_execute(&cli_options).is_ok());
+        assert_eq!(
+            std::fs::read_to_string(&f_output).unwrap(),
+            "client,available,held,total,locked\n\
+             1,10.0000,0.0000,10.0000,false\n\
+             2,0.0000,0.9900,0.9900,false\n"
+        );
+        let dead_letters = std::fs::read_to_string(&f_dead_letter).unwrap();
+        assert_eq!(dead_letters.lines().count(), 1);
+        assert!(dead_letters.contains("UnmappedClient(7003)"));
+    }
+
+    #[test]
+    fn tst_anonymize() {
+        let pseudonyms = ClientPseudonyms::new("vendor-2024");
+        let mut seen = vec![false; 1 << 16];
+        for client in 0..=u16::MAX {
+            let pseudonym = pseudonyms.pseudonym(client);
+            assert!(!seen[pseudonym as usize], "Pseudonyms must not collide");
+            seen[pseudonym as usize] = true;
+            assert_eq!(pseudonyms.client(pseudonym), client);
+        }
+        assert_ne!(
+            ClientPseudonyms::new("other").pseudonym(1),
+            pseudonyms.pseudonym(1)
+        );
+
+        let f_input = _get_test_output_file("tst_anonymize_input.csv");
+        std::fs::write(
+            &f_input,
+            "type, client, tx, amount\n\
+             deposit, 1, 1, 12.3456\n\
+             deposit, 2, 2, 0.29\n\
+             withdrawal, 1, 3, 2.999\n",
+        )
+        .unwrap();
+        let f_key = _get_test_output_file("tst_anonymize.key");
+        std::fs::write(&f_key, "vendor-2024\n").unwrap();
+        let f_output = _get_test_output_file("tst_anonymize.csv");
+        let f_map = _get_test_output_file("tst_anonymize_map.csv");
+        let args: Vec<String> = [
+            f_input.as_str(),
+            "--anonymize",
+            "--anonymize-key-file",
+            &f_key,
+            "--anonymize-decimals",
+            "1",
+            "--anonymize-map",
+            &f_map,
+            "--output",
+            &f_output,
+        ]
+        .iter()
+        .map(|a| a.to_string())
+        .collect();
+        let cli_options = match parse_cli_args(&args) {
+            Ok(CliCommand::Process(cli_options)) => cli_options,
+            _ => panic!("Should parse as process command"),
+        };
+        let mut payments_engine = PaymentsEngine::new();
+        assert!(payments_engine.streaming_execute(&cli_options).is_ok());
+        assert!(payments_engine.account(1).is_none());
+        let first = payments_engine.account(pseudonyms.pseudonym(1)).unwrap();
+        assert_eq!(first.available(), 9.4);
+        let second = payments_engine.account(pseudonyms.pseudonym(2)).unwrap();
+        assert_eq!(second.available(), 0.2);
+
+        let mut expected_map = [(pseudonyms.pseudonym(1), 1), (pseudonyms.pseudonym(2), 2)];
+        expected_map.sort();
+        let expected_map: String = expected_map
+            .iter()
+            .map(|(pseudonym, client)| format!("{},{}\n", pseudonym, client))
+            .collect();
+        assert_eq!(
+            std::fs::read_to_string(&f_map).unwrap(),
+            format!("pseudonym,client\n{}", expected_map)
+        );
+
+        // Only this test sets the variable
+        std::env::set_var(ANONYMIZE_KEY_VAR, "vendor-2024");
+        assert_eq!(ClientPseudonyms::from_key_source(None).unwrap(), pseudonyms);
+        std::env::remove_var(ANONYMIZE_KEY_VAR);
+        assert!(ClientPseudonyms::from_key_source(None).is_err());
+        let f_empty_key = _get_test_output_file("tst_anonymize_empty.key");
+        std::fs::write(&f_empty_key, "\n").unwrap();
+        assert!(ClientPseudonyms::from_key_source(Some(&f_empty_key)).is_err());
+
+        for args in [
+            vec!["input.csv", "--anonymize-map", "map.csv"],
+            vec!["input.csv", "--anonymize-decimals", "2"],
+            vec!["input.csv", "--anonymize-key-file", &f_key],
+            vec![
+                "input.csv",
+                "--anonymize",
+                "--anonymize-key-file",
+                "missing.key",
+            ],
+            vec![
+                "input.csv",
+                "--anonymize",
+                "--anonymize-key-file",
+                &f_key,
+                "--dead-letter",
+                "dlq.jsonl",
+            ],
+        ] {
+            let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+            assert!(parse_cli_args(&args).is_err(), "{:?}", args);
+        }
+    }
+}
@@ -0,0 +1,315 @@
+use crate::diagnostics::{log, Level};
+use crate::http::post_json;
+use serde::Serialize;
+use serde_json::value::RawValue;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::sync::mpsc::{sync_channel, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// Account events important enough to push to external systems as they happen
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum HighSeverityEvent {
+    AccountFrozen {
+        client: u16,
+        tx: u32,
+    },
+    ChargebackProcessed {
+        client: u16,
+        tx: u32,
+        amount: f64,
+    },
+    NegativeBalance {
+        client: u16,
+        tx: u32,
+        available: f64,
+        total: f64,
+    },
+    /// A dispute, resolve, or chargeback named a txn of another client & was rejected
+    CrossClientReference {
+        client: u16,
+        tx: u32,
+        owner: u16,
+    },
+}
+
+/// Where & how webhook notifications are delivered
+#[derive(Debug, Clone, PartialEq)]
+pub struct WebhookConfig {
+    /// Every event is posted to each url
+    pub urls: Vec<String>,
+    /// Attempts after the first failed one
+    pub max_retries: u32,
+    /// Delay before the first retry, doubled on each following retry
+    pub backoff_ms: u64,
+    /// Events which could not be delivered are appended here as json lines
+    pub dead_letter_path: String,
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        Self {
+            urls: vec![],
+            max_retries: 3,
+            backoff_ms: 100,
+            dead_letter_path: "webhook_dead_letter.jsonl".to_string(),
+        }
+    }
+}
+
+/// Events waiting for delivery, once it is full further events are dead lettered instead of holding up processing
+const QUEUE_LEN: usize = 1024;
+
+/// Record written to the dead letter file
+#[derive(Serialize)]
+struct DeadLetter<'a> {
+    url: &'a str,
+    error: String,
+    payload: &'a RawValue,
+}
+
+/// Posts events to the configured urls, retrying with backoff, & dead letters those it can't deliver
+#[derive(Debug)]
+struct Courier {
+    config: WebhookConfig,
+    timeout: Duration,
+}
+
+impl Courier {
+    /// Delivers an event to every url, failed deliveries go to the dead letter file
+    fn send(&self, payload: &RawValue) {
+        for url in self.config.urls.iter() {
+            if let Err(error) = self.deliver(url, payload.get()) {
+                self.dead_letter(url, error, payload);
+            }
+        }
+    }
+
+    fn deliver(&self, url: &str, payload: &str) -> Result<(), String> {
+        let mut backoff = Duration::from_millis(self.config.backoff_ms);
+        let mut attempt = 0;
+        loop {
+            let error = match post_json(url, payload, self.timeout) {
+                Ok(status) if (200..300).contains(&status) => return Ok(()),
+                Ok(status) => format!("Received status {}", status),
+                Err(e) => e.to_string(),
+            };
+            if attempt >= self.config.max_retries {
+                return Err(error);
+            }
+            attempt += 1;
+            thread::sleep(backoff);
+            backoff *= 2;
+        }
+    }
+
+    /// Failing to dead letter leaves nowhere but the log to report the event to
+    fn dead_letter(&self, url: &str, error: String, payload: &RawValue) {
+        let record = DeadLetter {
+            url,
+            error,
+            payload,
+        };
+        let written = serde_json::to_string(&record)
+            .map_err(io::Error::from)
+            .and_then(|line| {
+                let mut file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&self.config.dead_letter_path)?;
+                writeln!(file, "{}", line)
+            });
+        if let Err(e) = written {
+            log(
+                Level::Error,
+                format_args!(
+                    "Failed to dead letter webhook event {}: {}",
+                    payload.get(),
+                    e
+                ),
+            );
+        }
+    }
+}
+
+/// Posts high severity events to configured urls from a background thread, retrying with backoff
+/// Events are queued so slow or failing urls never hold up processing, dropping the sink waits for the
+/// queued events to be delivered
+#[derive(Debug)]
+pub struct WebhookSink {
+    queue: Option<SyncSender<Box<RawValue>>>,
+    worker: Option<JoinHandle<()>>,
+    courier: Arc<Courier>,
+}
+
+impl WebhookSink {
+    pub fn new(config: WebhookConfig) -> Self {
+        let courier = Arc::new(Courier {
+            config,
+            timeout: Duration::from_secs(5),
+        });
+        let (queue, events) = sync_channel::<Box<RawValue>>(QUEUE_LEN);
+        let worker_courier = Arc::clone(&courier);
+        let worker = thread::spawn(move || {
+            for payload in events {
+                worker_courier.send(&payload);
+            }
+        });
+        Self {
+            queue: Some(queue),
+            worker: Some(worker),
+            courier,
+        }
+    }
+
+    /// Queues an event for delivery to every url, events which don't fit in the queue are dead lettered
+    pub fn notify<T: Serialize>(&self, event: &T) -> Result<(), io::Error> {
+        let payload = serde_json::value::to_raw_value(event)?;
+        let queue = self
+            .queue
+            .as_ref()
+            .expect("Queue is open until the sink is dropped");
+        if let Err(TrySendError::Full(payload) | TrySendError::Disconnected(payload)) =
+            queue.try_send(payload)
+        {
+            for url in self.courier.config.urls.iter() {
+                let error = "Delivery queue full".to_string();
+                self.courier.dead_letter(url, error, &payload);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for WebhookSink {
+    fn drop(&mut self) {
+        // Closing the queue ends the worker once it has delivered what was queued
+        self.queue.take();
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{HighSeverityEvent, WebhookConfig, WebhookSink};
+    use crate::http::tests::serve_responses;
+    use crate::test::utils::_get_test_output_file;
+    use std::io::Write;
+    use std::net::TcpListener;
+    use std::time::{Duration, Instant};
+
+    fn webhook_config(urls: Vec<String>, dead_letter_path: String) -> WebhookConfig {
+        WebhookConfig {
+            urls,
+            max_retries: 2,
+            backoff_ms: 1,
+            dead_letter_path,
+        }
+    }
+
+    #[test]
+    fn tst_notify_retries() {
+        let (url, handle) = serve_responses(vec![500, 200]);
+        let dead_letter_path = _get_test_output_file("tst_notify_retries.jsonl");
+        let _ = std::fs::remove_file(&dead_letter_path);
+        let sink = WebhookSink::new(webhook_config(vec![url], dead_letter_path.clone()));
+        let event = HighSeverityEvent::AccountFrozen { client: 1, tx: 2 };
+        assert!(sink.notify(&event).is_ok());
+        drop(sink);
+
+        let bodies = handle.join().unwrap();
+        assert_eq!(bodies.len(), 2, "Should retry after failed status");
+        assert_eq!(bodies[1], r#"{"event":"account_frozen","client":1,"tx":2}"#);
+        assert!(
+            std::fs::metadata(&dead_letter_path).is_err(),
+            "Delivered events should not be dead lettered"
+        );
+    }
+
+    #[test]
+    fn tst_notify_dead_letter() {
+        // Bind then drop a listener to get a port nothing listens on
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let url = format!("http://127.0.0.1:{}/hook", port);
+        let dead_letter_path = _get_test_output_file("tst_notify_dead_letter.jsonl");
+        let _ = std::fs::remove_file(&dead_letter_path);
+        let sink = WebhookSink::new(webhook_config(vec![url.clone()], dead_letter_path.clone()));
+        let event = HighSeverityEvent::ChargebackProcessed {
+            client: 1,
+            tx: 2,
+            amount: 5.0,
+        };
+        assert!(sink.notify(&event).is_ok());
+        drop(sink);
+
+        let dead_letters = std::fs::read_to_string(&dead_letter_path).unwrap();
+        let record: serde_json::Value = serde_json::from_str(dead_letters.trim()).unwrap();
+        assert_eq!(record["url"], url.as_str());
+        assert_eq!(record["payload"]["event"], "chargeback_processed");
+        assert_eq!(record["payload"]["amount"], 5.0);
+    }
+
+    #[test]
+    fn tst_notify_in_background() {
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let url = format!("http://127.0.0.1:{}/hook", port);
+        let dead_letter_path = _get_test_output_file("tst_notify_in_background.jsonl");
+        let _ = std::fs::remove_file(&dead_letter_path);
+        let mut config = webhook_config(vec![url], dead_letter_path.clone());
+        config.backoff_ms = 200;
+        let sink = WebhookSink::new(config);
+
+        // Retries back off for 600ms, none of it on the caller's thread
+        let start = Instant::now();
+        for tx in 0..3 {
+            let event = HighSeverityEvent::AccountFrozen { client: 1, tx };
+            assert!(sink.notify(&event).is_ok());
+        }
+        assert!(start.elapsed() < Duration::from_millis(200));
+
+        drop(sink);
+        let dead_letters = std::fs::read_to_string(&dead_letter_path).unwrap();
+        assert_eq!(
+            dead_letters.lines().count(),
+            3,
+            "Dropping the sink should deliver what was queued"
+        );
+    }
+
+    #[test]
+    fn tst_notify_endless_response() {
+        // An endpoint streaming a status line which never ends fails each attempt instead of stalling delivery
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let handle = std::thread::spawn(move || {
+            for _ in 0..3 {
+                let (mut stream, _) = listener.accept().unwrap();
+                let _ = write!(stream, "HTTP/1.1 200 {}", "x".repeat(64 * 1024));
+            }
+        });
+        let dead_letter_path = _get_test_output_file("tst_notify_endless_response.jsonl");
+        let _ = std::fs::remove_file(&dead_letter_path);
+        let sink = WebhookSink::new(webhook_config(vec![url], dead_letter_path.clone()));
+        let event = HighSeverityEvent::AccountFrozen { client: 1, tx: 2 };
+        assert!(sink.notify(&event).is_ok());
+        drop(sink);
+        handle.join().unwrap();
+
+        let dead_letters = std::fs::read_to_string(&dead_letter_path).unwrap();
+        let record: serde_json::Value = serde_json::from_str(dead_letters.trim()).unwrap();
+        assert!(record["error"].as_str().unwrap().contains("longer than"));
+    }
+}